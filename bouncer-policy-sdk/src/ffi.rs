@@ -0,0 +1,313 @@
+//! The C ABI between Bouncer and plugin libraries
+//!
+//! Plugins don't use this module directly: [`export_policies!`](crate::export_policies)
+//! builds the declaration, and Bouncer reads it. `abi_version` is always the
+//! first field of [`PluginDeclaration`], so it can be checked before anything
+//! else in the declaration is trusted.
+
+use crate::{Decision, Policy, PolicyFactory, Request};
+use std::any::Any;
+use std::ffi::c_void;
+use std::mem::ManuallyDrop;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Version of the C ABI, bumped whenever a type in this module changes
+pub const ABI_VERSION: u32 = 1;
+
+/// Name of the symbol holding a plugin's [`PluginDeclaration`]
+pub const DECLARATION_SYMBOL: &[u8] = b"bouncer_plugin_declaration\0";
+
+/// Borrowed bytes, valid for the duration of a call or, in a declaration, for
+/// as long as the library is loaded
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfiStr {
+    pub ptr: *const u8,
+    pub len: usize,
+}
+
+impl FfiStr {
+    pub const fn new(s: &'static str) -> Self {
+        Self {
+            ptr: s.as_ptr(),
+            len: s.len(),
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self {
+            ptr: bytes.as_ptr(),
+            len: bytes.len(),
+        }
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be null or point to `len` bytes that outlive `'a`.
+    pub unsafe fn as_bytes<'a>(self) -> &'a [u8] {
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(self.ptr, self.len)
+        }
+    }
+}
+
+/// Bytes the plugin allocated and handed to Bouncer, which releases them with
+/// [`PluginDeclaration::free`], so they're freed by the allocator that made them
+#[repr(C)]
+#[derive(Debug)]
+pub struct FfiBuffer {
+    pub ptr: *mut u8,
+    pub len: usize,
+    pub capacity: usize,
+}
+
+impl FfiBuffer {
+    pub fn new(bytes: Vec<u8>) -> Self {
+        let mut bytes = ManuallyDrop::new(bytes);
+        Self {
+            ptr: bytes.as_mut_ptr(),
+            len: bytes.len(),
+            capacity: bytes.capacity(),
+        }
+    }
+
+    pub const fn empty() -> Self {
+        Self {
+            ptr: ptr::null_mut(),
+            len: 0,
+            capacity: 0,
+        }
+    }
+
+    /// # Safety
+    ///
+    /// The buffer must be empty or come from [`FfiBuffer::new`] and not have
+    /// been freed.
+    pub unsafe fn as_bytes(&self) -> &[u8] {
+        if self.ptr.is_null() {
+            &[]
+        } else {
+            std::slice::from_raw_parts(self.ptr, self.len)
+        }
+    }
+}
+
+/// A policy a plugin provides
+#[repr(C)]
+pub struct PolicyEntry {
+    /// Provider ID, as `@provider/category/name/version`
+    pub id: FfiStr,
+    /// How many request body bytes `process` is passed
+    pub inspect_body_bytes: usize,
+    /// Check a JSON config, returning an empty buffer if it's valid and the
+    /// error otherwise
+    pub validate: extern "C" fn(config: FfiStr) -> FfiBuffer,
+    /// Create a policy from a JSON config. On failure this returns null and
+    /// writes the error to `error`
+    pub create: extern "C" fn(config: FfiStr, error: *mut FfiBuffer) -> *mut c_void,
+    /// Decide on a JSON [`Request`] and the start of its body, returning a
+    /// JSON [`Decision`]
+    pub process: extern "C" fn(policy: *const c_void, request: FfiStr, body: FfiStr) -> FfiBuffer,
+    /// Drop a policy returned by `create`
+    pub destroy: extern "C" fn(policy: *mut c_void),
+}
+
+impl PolicyEntry {
+    pub const fn of<F: PolicyFactory>() -> Self {
+        Self {
+            id: FfiStr::new(F::ID),
+            inspect_body_bytes: F::INSPECT_BODY_BYTES,
+            validate: validate::<F>,
+            create: create::<F>,
+            process: process::<F>,
+            destroy: destroy::<F>,
+        }
+    }
+}
+
+/// Everything a plugin exports, under [`DECLARATION_SYMBOL`]
+#[repr(C)]
+pub struct PluginDeclaration {
+    pub abi_version: u32,
+    /// Version of this crate the plugin was built with, for error messages
+    pub sdk_version: FfiStr,
+    pub policies: *const PolicyEntry,
+    pub policy_count: usize,
+    /// Release a buffer returned by one of the plugin's functions
+    pub free: extern "C" fn(buffer: FfiBuffer),
+}
+
+// Declarations only point to static, immutable data
+unsafe impl Sync for PluginDeclaration {}
+unsafe impl Sync for PolicyEntry {}
+
+impl PluginDeclaration {
+    pub const fn new(policies: &'static [PolicyEntry]) -> Self {
+        Self {
+            abi_version: ABI_VERSION,
+            sdk_version: FfiStr::new(env!("CARGO_PKG_VERSION")),
+            policies: policies.as_ptr(),
+            policy_count: policies.len(),
+            free,
+        }
+    }
+}
+
+// Panics must not unwind into Bouncer, so they're turned into errors
+fn guard<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|panic| Err(format!("plugin panicked: {}", panic_message(&*panic))))
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error")
+}
+
+fn parse_config(config: FfiStr) -> Result<serde_json::Value, String> {
+    serde_json::from_slice(unsafe { config.as_bytes() })
+        .map_err(|e| format!("Failed to parse config: {}", e))
+}
+
+extern "C" fn validate<F: PolicyFactory>(config: FfiStr) -> FfiBuffer {
+    match guard(|| F::validate(&parse_config(config)?)) {
+        Ok(()) => FfiBuffer::empty(),
+        Err(e) => FfiBuffer::new(e.into_bytes()),
+    }
+}
+
+extern "C" fn create<F: PolicyFactory>(config: FfiStr, error: *mut FfiBuffer) -> *mut c_void {
+    match guard(|| F::create(parse_config(config)?)) {
+        Ok(policy) => Box::into_raw(Box::new(policy)) as *mut c_void,
+        Err(e) => {
+            if !error.is_null() {
+                unsafe { error.write(FfiBuffer::new(e.into_bytes())) };
+            }
+            ptr::null_mut()
+        }
+    }
+}
+
+extern "C" fn process<F: PolicyFactory>(
+    policy: *const c_void,
+    request: FfiStr,
+    body: FfiStr,
+) -> FfiBuffer {
+    let decision = guard(|| {
+        let policy = unsafe { &*(policy as *const F::Policy) };
+        let mut request: Request = serde_json::from_slice(unsafe { request.as_bytes() })
+            .map_err(|e| format!("Failed to parse request: {}", e))?;
+        request.body = unsafe { body.as_bytes() }.to_vec();
+        Ok(policy.process(&request))
+    })
+    .unwrap_or_else(Decision::error);
+    FfiBuffer::new(serde_json::to_vec(&decision).unwrap_or_default())
+}
+
+extern "C" fn destroy<F: PolicyFactory>(policy: *mut c_void) {
+    if !policy.is_null() {
+        drop(unsafe { Box::from_raw(policy as *mut F::Policy) });
+    }
+}
+
+extern "C" fn free(buffer: FfiBuffer) {
+    if !buffer.ptr.is_null() {
+        drop(unsafe { Vec::from_raw_parts(buffer.ptr, buffer.len, buffer.capacity) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Identity;
+
+    struct RolePolicy(String);
+
+    impl Policy for RolePolicy {
+        fn process(&self, request: &Request) -> Decision {
+            if request.body == b"panic" {
+                panic!("boom");
+            }
+            Decision::authenticate(Identity {
+                role: self.0.clone(),
+                ..Identity::default()
+            })
+        }
+    }
+
+    struct RolePolicyFactory;
+
+    impl PolicyFactory for RolePolicyFactory {
+        type Policy = RolePolicy;
+        const ID: &'static str = "@test/authentication/role/v1";
+        const INSPECT_BODY_BYTES: usize = 16;
+
+        fn create(config: serde_json::Value) -> Result<RolePolicy, String> {
+            let role = config["role"].as_str().ok_or("role is required")?;
+            Ok(RolePolicy(role.to_string()))
+        }
+    }
+
+    static POLICIES: &[PolicyEntry] = &[PolicyEntry::of::<RolePolicyFactory>()];
+    static DECLARATION: PluginDeclaration = PluginDeclaration::new(POLICIES);
+
+    fn take(buffer: FfiBuffer) -> String {
+        let text = String::from_utf8(unsafe { buffer.as_bytes() }.to_vec()).unwrap();
+        (DECLARATION.free)(buffer);
+        text
+    }
+
+    #[test]
+    fn test_declaration() {
+        assert_eq!(DECLARATION.abi_version, ABI_VERSION);
+        assert_eq!(DECLARATION.policy_count, 1);
+        let entry = unsafe { &*DECLARATION.policies };
+        assert_eq!(
+            unsafe { entry.id.as_bytes() },
+            b"@test/authentication/role/v1"
+        );
+        assert_eq!(entry.inspect_body_bytes, 16);
+
+        assert_eq!(take((entry.validate)(FfiStr::new(r#"{"role": "a"}"#))), "");
+        assert_eq!(
+            take((entry.validate)(FfiStr::new("{}"))),
+            "role is required"
+        );
+        assert!(take((entry.validate)(FfiStr::new("not json"))).starts_with("Failed to parse"));
+    }
+
+    #[test]
+    fn test_create_and_process() {
+        let entry = &POLICIES[0];
+        let mut error = FfiBuffer::empty();
+        assert!((entry.create)(FfiStr::new("{}"), &mut error).is_null());
+        assert_eq!(take(error), "role is required");
+
+        let policy = (entry.create)(FfiStr::new(r#"{"role": "admin"}"#), ptr::null_mut());
+        assert!(!policy.is_null());
+        let request = FfiStr::new(r#"{"method": "GET", "uri": "/"}"#);
+
+        let decision = take((entry.process)(policy, request, FfiStr::new("")));
+        let decision: Decision = serde_json::from_str(&decision).unwrap();
+        assert_eq!(
+            decision,
+            Decision::authenticate(Identity {
+                role: "admin".to_string(),
+                ..Identity::default()
+            })
+        );
+
+        // Panics come back as errors instead of unwinding across the boundary
+        let decision = take((entry.process)(policy, request, FfiStr::new("panic")));
+        let decision: Decision = serde_json::from_str(&decision).unwrap();
+        assert_eq!(decision, Decision::error("plugin panicked: boom"));
+
+        (entry.destroy)(policy);
+    }
+}
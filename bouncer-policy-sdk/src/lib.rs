@@ -0,0 +1,229 @@
+//! Stable interface for Bouncer policy plugins
+//!
+//! Plugins are `cdylib` crates that depend on this crate instead of `bouncer`
+//! itself. Only the `#[repr(C)]` types in [`ffi`] cross the library boundary,
+//! and requests, configs and decisions are passed between them as JSON, so a
+//! plugin keeps loading across Bouncer releases and compiler versions until
+//! [`ffi::ABI_VERSION`] changes.
+//!
+//! ```rust
+//! use bouncer_policy_sdk::{export_policies, Decision, Policy, PolicyFactory, Request};
+//!
+//! pub struct HeaderPolicy {
+//!     header: String,
+//! }
+//!
+//! impl Policy for HeaderPolicy {
+//!     fn process(&self, request: &Request) -> Decision {
+//!         match request.header(&self.header) {
+//!             Some(_) => Decision::allow(),
+//!             None => Decision::deny(401, "Missing header"),
+//!         }
+//!     }
+//! }
+//!
+//! pub struct HeaderPolicyFactory;
+//!
+//! impl PolicyFactory for HeaderPolicyFactory {
+//!     type Policy = HeaderPolicy;
+//!     const ID: &'static str = "@acme/authentication/header/v1";
+//!
+//!     fn create(config: serde_json::Value) -> Result<HeaderPolicy, String> {
+//!         let header = config["header"].as_str().ok_or("header is required")?;
+//!         Ok(HeaderPolicy { header: header.to_string() })
+//!     }
+//! }
+//!
+//! export_policies!(HeaderPolicyFactory);
+//! ```
+
+pub mod ffi;
+
+use serde::{Deserialize, Serialize};
+
+pub use serde_json;
+
+/// Who a request was authenticated as
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    pub role: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// A request as plugins see it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Request {
+    pub method: String,
+    /// Path and query
+    pub uri: String,
+    /// Header names are lowercase. Headers that aren't valid UTF-8 are left out
+    #[serde(default)]
+    pub headers: Vec<(String, String)>,
+    /// The client IP, resolved through the trusted proxies
+    #[serde(default)]
+    pub client_ip: Option<String>,
+    /// The identity an earlier authentication policy resolved the request to
+    #[serde(default)]
+    pub identity: Option<Identity>,
+    /// Start of the body, at most [`PolicyFactory::INSPECT_BODY_BYTES`] long
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// The first value of a header, matched case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What a policy decided about a request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "decision", rename_all = "snake_case")]
+pub enum Decision {
+    /// Pass the request on to the next policy, with changes
+    Continue {
+        #[serde(default)]
+        set_headers: Vec<(String, String)>,
+        #[serde(default)]
+        remove_headers: Vec<String>,
+        /// Make this the request's identity, as authentication policies do
+        #[serde(default)]
+        identity: Option<Identity>,
+    },
+    /// Respond to the client instead of the upstream
+    Terminate {
+        status: u16,
+        #[serde(default)]
+        headers: Vec<(String, String)>,
+        #[serde(default)]
+        body: String,
+    },
+    /// The policy couldn't decide, e.g. because a backend is down. Bouncer
+    /// handles this according to the policy's `failure_mode`
+    Error { message: String },
+}
+
+impl Decision {
+    /// Pass the request on unchanged
+    pub fn allow() -> Self {
+        Self::Continue {
+            set_headers: Vec::new(),
+            remove_headers: Vec::new(),
+            identity: None,
+        }
+    }
+
+    /// Pass the request on as `identity`
+    pub fn authenticate(identity: Identity) -> Self {
+        Self::Continue {
+            set_headers: Vec::new(),
+            remove_headers: Vec::new(),
+            identity: Some(identity),
+        }
+    }
+
+    /// Respond with `status` and a `{"error": message}` body
+    pub fn deny(status: u16, message: &str) -> Self {
+        Self::Terminate {
+            status,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: serde_json::json!({ "error": message }).to_string(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::Error {
+            message: message.into(),
+        }
+    }
+}
+
+/// A configured policy
+///
+/// `process` runs on Bouncer's async runtime, so it must not block for long.
+pub trait Policy: Send + Sync + 'static {
+    fn process(&self, request: &Request) -> Decision;
+}
+
+/// Creates a policy from its parameters in the Bouncer config
+pub trait PolicyFactory {
+    type Policy: Policy;
+
+    /// Provider ID the policy is configured with, as
+    /// `@provider/category/name/version`
+    const ID: &'static str;
+
+    /// How many request body bytes `process` sees, or 0 to see none
+    const INSPECT_BODY_BYTES: usize = 0;
+
+    fn create(config: serde_json::Value) -> Result<Self::Policy, String>;
+
+    /// Check the parameters without creating the policy, e.g. for
+    /// `bouncer validate`. Defaults to creating and dropping a policy
+    fn validate(config: &serde_json::Value) -> Result<(), String> {
+        Self::create(config.clone()).map(drop)
+    }
+}
+
+/// Export policy factories from a plugin library
+///
+/// Use it once per plugin, listing every factory the plugin provides.
+#[macro_export]
+macro_rules! export_policies {
+    ($($factory:ty),+ $(,)?) => {
+        #[doc(hidden)]
+        #[no_mangle]
+        #[allow(non_upper_case_globals)]
+        pub static bouncer_plugin_declaration: $crate::ffi::PluginDeclaration = {
+            static POLICIES: &[$crate::ffi::PolicyEntry] =
+                &[$($crate::ffi::PolicyEntry::of::<$factory>()),+];
+            $crate::ffi::PluginDeclaration::new(POLICIES)
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_format() {
+        // The JSON format is part of the ABI, so it mustn't change by accident
+        assert_eq!(
+            serde_json::to_value(Decision::deny(403, "Forbidden")).unwrap(),
+            serde_json::json!({
+                "decision": "terminate",
+                "status": 403,
+                "headers": [["content-type", "application/json"]],
+                "body": "{\"error\":\"Forbidden\"}",
+            })
+        );
+        let decision: Decision =
+            serde_json::from_str(r#"{"decision": "continue", "identity": {"role": "admin"}}"#)
+                .unwrap();
+        assert_eq!(
+            decision,
+            Decision::authenticate(Identity {
+                role: "admin".to_string(),
+                ..Identity::default()
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_header() {
+        let request: Request =
+            serde_json::from_str(r#"{"method": "GET", "uri": "/", "headers": [["x-key", "1"]]}"#)
+                .unwrap();
+        assert_eq!(request.header("X-Key"), Some("1"));
+        assert_eq!(request.header("x-other"), None);
+        assert!(request.identity.is_none());
+    }
+}
@@ -0,0 +1,474 @@
+use super::store::{create_acme_store, AcmeStore};
+use super::AcmeListener;
+use crate::config::{AcmeChallenge, AcmeConfig, DatabasesConfig};
+use axum::body::Body;
+use axum::extract::Path;
+use axum::http::{Response, StatusCode};
+use axum_server::tls_rustls::RustlsConfig;
+use instant_acme::{
+    Account, AccountCredentials, AuthorizationStatus, ChallengeType, Identifier, NewAccount,
+    NewOrder, Order, OrderStatus,
+};
+use rcgen::{CertificateParams, CustomExtension, DistinguishedName, KeyPair};
+use rustls::crypto::aws_lc_rs::sign::any_supported_type;
+use rustls::pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+// ALPN protocol the CA offers when it checks a tls-alpn-01 challenge
+const ACME_TLS_ALPN: &[u8] = b"acme-tls/1";
+
+// How often the store is checked for a certificate renewed by another
+// replica, and whether it's time to renew. Also the wait after a failure
+const CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+// Serves the current certificate, or a challenge certificate to the CA
+#[derive(Debug, Default)]
+struct CertResolver {
+    current: RwLock<Option<Arc<CertifiedKey>>>,
+    // tls-alpn-01 certificates of pending challenges, by domain
+    challenges: RwLock<HashMap<String, Arc<CertifiedKey>>>,
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        let challenge = client_hello
+            .alpn()
+            .is_some_and(|mut protocols| protocols.any(|protocol| protocol == ACME_TLS_ALPN));
+        if challenge {
+            let domain = client_hello.server_name()?;
+            return self.challenges.read().unwrap().get(domain).cloned();
+        }
+        self.current.read().unwrap().clone()
+    }
+}
+
+// A short, stable name for a value, to keep state for different domains or
+// CAs apart in the store
+fn short_hash(value: &str) -> String {
+    Sha256::digest(value.as_bytes())[..4]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// Tokens are base64url, which keeps them safe to use as names in the store
+fn is_valid_token(token: &str) -> bool {
+    !token.is_empty()
+        && token
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+fn http_challenge_name(token: &str) -> String {
+    format!("http-01-{}", token)
+}
+
+fn acme_error(e: instant_acme::Error) -> String {
+    format!("ACME: {}", e)
+}
+
+// Parse a certificate chain followed by its private key, as stored, into the
+// key served to clients and the Unix time the certificate expires at
+fn parse_certificate(pem: &str) -> Result<(Arc<CertifiedKey>, i64), String> {
+    let certs = rustls_pemfile::certs(&mut pem.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Invalid stored certificate: {}", e))?;
+    let key = rustls_pemfile::private_key(&mut pem.as_bytes())
+        .map_err(|e| format!("Invalid stored private key: {}", e))?
+        .ok_or("No private key in the stored certificate")?;
+    let leaf = certs
+        .first()
+        .ok_or("No certificate in the stored certificate")?;
+    let (_, parsed) = x509_parser::parse_x509_certificate(leaf.as_ref())
+        .map_err(|e| format!("Invalid stored certificate: {}", e))?;
+    let expires_at = parsed.validity().not_after.timestamp();
+
+    let signing_key =
+        any_supported_type(&key).map_err(|e| format!("Unsupported private key: {}", e))?;
+    Ok((Arc::new(CertifiedKey::new(certs, signing_key)), expires_at))
+}
+
+// The self-signed certificate the CA expects for a tls-alpn-01 challenge
+fn alpn_challenge_certificate(domain: &str, digest: &[u8]) -> Result<Arc<CertifiedKey>, String> {
+    let key_pair = KeyPair::generate().map_err(|e| e.to_string())?;
+    let mut params = CertificateParams::new(vec![domain.to_string()]).map_err(|e| e.to_string())?;
+    params.custom_extensions = vec![CustomExtension::new_acme_identifier(digest)];
+    let cert = params.self_signed(&key_pair).map_err(|e| e.to_string())?;
+
+    let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key_pair.serialize_der()));
+    let signing_key = any_supported_type(&key).map_err(|e| e.to_string())?;
+    Ok(Arc::new(CertifiedKey::new(
+        vec![cert.der().clone()],
+        signing_key,
+    )))
+}
+
+struct Manager {
+    config: AcmeConfig,
+    store: Arc<dyn AcmeStore>,
+    resolver: Arc<CertResolver>,
+    // Key authorizations of pending http-01 challenges, by token
+    http_challenges: RwLock<HashMap<String, String>>,
+    account_name: String,
+    certificate_name: String,
+}
+
+impl Manager {
+    async fn load_stored(&self) -> Result<Option<(Arc<CertifiedKey>, i64)>, String> {
+        let stored = self
+            .store
+            .get(&self.certificate_name)
+            .await
+            .map_err(|e| e.to_string())?;
+        stored.as_deref().map(parse_certificate).transpose()
+    }
+
+    // The key authorization for an http-01 token. Challenges of other
+    // replicas sharing the store are answered too, since the CA's request
+    // can reach any of them
+    async fn key_authorization(&self, token: &str) -> Option<String> {
+        if !is_valid_token(token) {
+            return None;
+        }
+        let pending = self.http_challenges.read().unwrap().get(token).cloned();
+        match pending {
+            Some(key_authorization) => Some(key_authorization),
+            None => self
+                .store
+                .get(&http_challenge_name(token))
+                .await
+                .ok()
+                .flatten(),
+        }
+    }
+
+    async fn account(&self) -> Result<Account, String> {
+        let stored = self
+            .store
+            .get(&self.account_name)
+            .await
+            .map_err(|e| e.to_string())?;
+        if let Some(json) = stored {
+            let credentials: AccountCredentials = serde_json::from_str(&json)
+                .map_err(|e| format!("Invalid stored ACME account: {}", e))?;
+            return Account::from_credentials(credentials)
+                .await
+                .map_err(acme_error);
+        }
+
+        let contact: Vec<String> = self
+            .config
+            .contact
+            .iter()
+            .map(|email| format!("mailto:{}", email))
+            .collect();
+        let contact: Vec<&str> = contact.iter().map(String::as_str).collect();
+        let (account, credentials) = Account::create(
+            &NewAccount {
+                contact: &contact,
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            &self.config.directory_url,
+            None,
+        )
+        .await
+        .map_err(acme_error)?;
+
+        let json = serde_json::to_string(&credentials).map_err(|e| e.to_string())?;
+        self.store
+            .put(&self.account_name, &json)
+            .await
+            .map_err(|e| e.to_string())?;
+        tracing::info!("Created ACME account at {}", self.config.directory_url);
+        Ok(account)
+    }
+
+    // Set up a challenge for every pending authorization of the order, and
+    // wait for the CA to validate them. The http-01 tokens set up are added
+    // to `tokens`, so they can be removed whatever happens
+    async fn authorize(&self, order: &mut Order, tokens: &mut Vec<String>) -> Result<(), String> {
+        let challenge_type = match self.config.challenge {
+            AcmeChallenge::Http01 => ChallengeType::Http01,
+            AcmeChallenge::TlsAlpn01 => ChallengeType::TlsAlpn01,
+        };
+
+        let authorizations = order.authorizations().await.map_err(acme_error)?;
+        let mut ready = Vec::new();
+        for authorization in &authorizations {
+            match authorization.status {
+                AuthorizationStatus::Pending => {}
+                AuthorizationStatus::Valid => continue,
+                status => {
+                    return Err(format!(
+                        "Authorization for {:?} is {:?}",
+                        authorization.identifier, status
+                    ))
+                }
+            }
+
+            let Identifier::Dns(domain) = &authorization.identifier;
+            let challenge = authorization
+                .challenges
+                .iter()
+                .find(|challenge| challenge.r#type == challenge_type)
+                .ok_or_else(|| {
+                    format!(
+                        "The CA offers no {:?} challenge for {}",
+                        challenge_type, domain
+                    )
+                })?;
+            let key_authorization = order.key_authorization(challenge);
+
+            match self.config.challenge {
+                AcmeChallenge::Http01 => {
+                    if !is_valid_token(&challenge.token) {
+                        return Err(format!(
+                            "Invalid challenge token from the CA for {}",
+                            domain
+                        ));
+                    }
+                    tokens.push(challenge.token.clone());
+                    self.http_challenges.write().unwrap().insert(
+                        challenge.token.clone(),
+                        key_authorization.as_str().to_string(),
+                    );
+                    self.store
+                        .put(
+                            &http_challenge_name(&challenge.token),
+                            key_authorization.as_str(),
+                        )
+                        .await
+                        .map_err(|e| e.to_string())?;
+                }
+                AcmeChallenge::TlsAlpn01 => {
+                    let certificate =
+                        alpn_challenge_certificate(domain, key_authorization.digest().as_ref())?;
+                    self.resolver
+                        .challenges
+                        .write()
+                        .unwrap()
+                        .insert(domain.clone(), certificate);
+                }
+            }
+            ready.push(challenge.url.clone());
+        }
+
+        for url in &ready {
+            order.set_challenge_ready(url).await.map_err(acme_error)?;
+        }
+
+        let mut delay = Duration::from_millis(500);
+        for _ in 0..10 {
+            tokio::time::sleep(delay).await;
+            let state = order.refresh().await.map_err(acme_error)?;
+            match state.status {
+                OrderStatus::Ready | OrderStatus::Valid => return Ok(()),
+                OrderStatus::Invalid => {
+                    return Err("The CA couldn't validate the challenges".to_string())
+                }
+                _ => delay = (delay * 2).min(Duration::from_secs(10)),
+            }
+        }
+        Err("Timed out waiting for the CA to validate the challenges".to_string())
+    }
+
+    async fn clear_challenges(&self, tokens: &[String]) {
+        self.resolver.challenges.write().unwrap().clear();
+        for token in tokens {
+            self.http_challenges.write().unwrap().remove(token);
+            if let Err(e) = self.store.delete(&http_challenge_name(token)).await {
+                tracing::warn!("Failed to remove ACME challenge {}: {}", token, e);
+            }
+        }
+    }
+
+    // Order a certificate for the configured domains, returning its chain
+    // followed by its private key
+    async fn obtain(&self) -> Result<String, String> {
+        let account = self.account().await?;
+        let identifiers: Vec<Identifier> = self
+            .config
+            .domains
+            .iter()
+            .map(|domain| Identifier::Dns(domain.clone()))
+            .collect();
+        let mut order = account
+            .new_order(&NewOrder {
+                identifiers: &identifiers,
+            })
+            .await
+            .map_err(acme_error)?;
+
+        let mut tokens = Vec::new();
+        let authorized = self.authorize(&mut order, &mut tokens).await;
+        self.clear_challenges(&tokens).await;
+        authorized?;
+
+        let key_pair = KeyPair::generate().map_err(|e| e.to_string())?;
+        let mut params =
+            CertificateParams::new(self.config.domains.clone()).map_err(|e| e.to_string())?;
+        params.distinguished_name = DistinguishedName::new();
+        let csr = params
+            .serialize_request(&key_pair)
+            .map_err(|e| e.to_string())?;
+        order.finalize(csr.der()).await.map_err(acme_error)?;
+
+        for _ in 0..10 {
+            if let Some(chain) = order.certificate().await.map_err(acme_error)? {
+                return Ok(format!("{}{}", chain, key_pair.serialize_pem()));
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+        Err("Timed out waiting for the CA to issue the certificate".to_string())
+    }
+
+    // Pick up certificates renewed by other replicas, and renew the
+    // certificate once it's due
+    async fn run(self: Arc<Self>, mut expires_at: Option<i64>) {
+        let domains = self.config.domains.join(", ");
+        loop {
+            match self.load_stored().await {
+                Ok(Some((certificate, expiry))) if Some(expiry) != expires_at => {
+                    *self.resolver.current.write().unwrap() = Some(certificate);
+                    expires_at = Some(expiry);
+                    tracing::info!("Loaded the stored certificate for {}", domains);
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to load the stored certificate: {}", e),
+            }
+
+            let renew_before = self.config.renew_before_days as i64 * SECONDS_PER_DAY;
+            let now = crate::admin::now_secs();
+            let due = match expires_at {
+                Some(expiry) => expiry - renew_before <= now,
+                None => true,
+            };
+            if due {
+                tracing::info!("Obtaining a certificate for {}", domains);
+                let obtained = match self.obtain().await {
+                    Ok(pem) => parse_certificate(&pem).map(|parsed| (pem, parsed)),
+                    Err(e) => Err(e),
+                };
+                match obtained {
+                    Ok((pem, (certificate, expiry))) => {
+                        *self.resolver.current.write().unwrap() = Some(certificate);
+                        expires_at = Some(expiry);
+                        tracing::info!("Obtained a certificate for {}", domains);
+                        if let Err(e) = self.store.put(&self.certificate_name, &pem).await {
+                            tracing::error!("Failed to store the certificate: {}", e);
+                        }
+                    }
+                    Err(e) => tracing::error!(
+                        "Failed to obtain a certificate for {}, retrying in {:?}: {}",
+                        domains,
+                        CHECK_INTERVAL,
+                        e
+                    ),
+                }
+            }
+
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+    }
+}
+
+pub(super) async fn start(
+    acme: &AcmeConfig,
+    databases: &DatabasesConfig,
+    http2: bool,
+    https_port: u16,
+) -> Result<AcmeListener, String> {
+    let store = create_acme_store(&acme.storage, databases)
+        .await
+        .map_err(|e| format!("server.acme.storage: {}", e))?;
+
+    let mut domains = acme.domains.clone();
+    domains.sort();
+    let manager = Arc::new(Manager {
+        config: acme.clone(),
+        store,
+        resolver: Arc::new(CertResolver::default()),
+        http_challenges: RwLock::new(HashMap::new()),
+        account_name: format!("account-{}.json", short_hash(&acme.directory_url)),
+        certificate_name: format!(
+            "certificate-{}.pem",
+            short_hash(&format!("{} {}", acme.directory_url, domains.join(",")))
+        ),
+    });
+
+    // Serve the stored certificate from the first connection on
+    let expires_at = match manager.load_stored().await? {
+        Some((certificate, expiry)) => {
+            *manager.resolver.current.write().unwrap() = Some(certificate);
+            Some(expiry)
+        }
+        None => None,
+    };
+
+    let mut tls = crate::tls::server_config_builder()
+        .with_no_client_auth()
+        .with_cert_resolver(manager.resolver.clone());
+    tls.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    tls.alpn_protocols.push(ACME_TLS_ALPN.to_vec());
+
+    let http = match acme.challenge {
+        AcmeChallenge::Http01 => {
+            let manager = Arc::clone(&manager);
+            Some(crate::tls::redirect_router(https_port).route(
+                "/.well-known/acme-challenge/{token}",
+                axum::routing::get(move |Path(token): Path<String>| async move {
+                    match manager.key_authorization(&token).await {
+                        Some(key_authorization) => Response::new(Body::from(key_authorization)),
+                        None => Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Body::from("Not Found"))
+                            .unwrap(),
+                    }
+                }),
+            ))
+        }
+        AcmeChallenge::TlsAlpn01 => None,
+    };
+
+    tokio::spawn(Arc::clone(&manager).run(expires_at));
+    Ok(AcmeListener {
+        tls: RustlsConfig::from_config(Arc::new(tls)),
+        http,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored_certificate() {
+        let key_pair = KeyPair::generate().unwrap();
+        let params = CertificateParams::new(vec!["api.example.com".to_string()]).unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+        let pem = format!("{}{}", cert.pem(), key_pair.serialize_pem());
+
+        let (certificate, expires_at) = parse_certificate(&pem).unwrap();
+        assert_eq!(certificate.cert.len(), 1);
+        // rcgen's default validity ends in 4096
+        assert!(expires_at > 4_000_000_000);
+
+        assert!(parse_certificate(&cert.pem()).is_err());
+        assert!(is_valid_token(
+            "LoqXcYV8q5ONbJQxbmR7SCTNo3tiAXDfowyjxAjEuX0"
+        ));
+        assert!(!is_valid_token("../account.json"));
+    }
+}
@@ -0,0 +1,7 @@
+-- Account, certificates and pending http-01 challenges of server.acme
+CREATE TABLE IF NOT EXISTS bouncer_acme_objects (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    -- Unix timestamp in seconds
+    updated_at BIGINT NOT NULL
+);
@@ -0,0 +1,63 @@
+#[cfg(feature = "acme")]
+mod manager;
+pub mod store;
+
+use crate::config::{AcmeChallenge, DatabasesConfig, ServerConfig};
+use axum::Router;
+use axum_server::tls_rustls::RustlsConfig;
+
+/// What the listeners need to serve certificates from `server.acme`
+pub struct AcmeListener {
+    /// TLS config of the HTTPS listener, whose certificate is swapped when
+    /// it's renewed
+    pub tls: RustlsConfig,
+    /// Router of the plain HTTP listener on `http_port`, with `http-01`
+    pub http: Option<Router>,
+}
+
+/// Check `server.acme` without contacting the CA
+pub fn validate(server: &ServerConfig) -> Result<(), String> {
+    let Some(acme) = &server.acme else {
+        return Ok(());
+    };
+    if server.tls.is_some() {
+        return Err("server.acme and server.tls can't both be set".to_string());
+    }
+    if acme.domains.is_empty() {
+        return Err("server.acme.domains must name at least one domain".to_string());
+    }
+    // Wildcards can only be validated with dns-01
+    if let Some(domain) = acme.domains.iter().find(|domain| domain.contains('*')) {
+        return Err(format!(
+            "server.acme.domains: wildcard '{}' isn't supported by http-01 or tls-alpn-01",
+            domain
+        ));
+    }
+    if acme.challenge == AcmeChallenge::Http01 && acme.http_port == server.port {
+        return Err("server.acme.http_port must differ from server.port".to_string());
+    }
+    Ok(())
+}
+
+/// Start obtaining and renewing certificates for `server.acme`
+///
+/// A certificate in the store is served straight away. Otherwise the first
+/// one is obtained in the background once the listeners are up, since the CA
+/// checks the challenges through them, and handshakes fail until then.
+#[cfg(feature = "acme")]
+pub async fn start(
+    server: &ServerConfig,
+    databases: &DatabasesConfig,
+) -> Result<AcmeListener, String> {
+    validate(server)?;
+    let acme = server.acme.as_ref().ok_or("server.acme is not set")?;
+    manager::start(acme, databases, server.http2, server.port).await
+}
+
+#[cfg(not(feature = "acme"))]
+pub async fn start(
+    _server: &ServerConfig,
+    _databases: &DatabasesConfig,
+) -> Result<AcmeListener, String> {
+    Err("ACME support is not enabled. Rebuild with the 'acme' feature.".to_string())
+}
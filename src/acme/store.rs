@@ -0,0 +1,264 @@
+use crate::config::{AcmeStorageConfig, DatabasesConfig};
+use crate::database::migrations::Migration;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Schema for the PostgreSQL ACME store
+///
+/// Versions share the table of the other Bouncer migrations, so they start at
+/// 500 to stay clear of the token, RBAC, metering, consent and plan ones.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[Migration {
+    version: 500,
+    name: "create_acme_objects",
+    sql: include_str!("migrations/postgres/0500_create_acme_objects.sql"),
+}];
+
+/// Where the ACME account, certificates and pending challenges are kept
+///
+/// Names are made of ASCII letters, digits, `.`, `-` and `_`.
+#[async_trait]
+pub trait AcmeStore: Send + Sync + 'static {
+    async fn get(&self, name: &str) -> Result<Option<String>, DatabaseError>;
+
+    async fn put(&self, name: &str, value: &str) -> Result<(), DatabaseError>;
+
+    async fn delete(&self, name: &str) -> Result<(), DatabaseError>;
+}
+
+/// One file per object in a local directory
+///
+/// Files are only readable by the user Bouncer runs as, since they hold
+/// private keys.
+pub struct DirectoryAcmeStore {
+    path: PathBuf,
+}
+
+impl DirectoryAcmeStore {
+    pub fn new(path: &str) -> Result<Self, DatabaseError> {
+        std::fs::create_dir_all(path).map_err(|e| {
+            DatabaseError::ConfigurationError(format!("Failed to create {}: {}", path, e))
+        })?;
+        Ok(Self { path: path.into() })
+    }
+}
+
+#[async_trait]
+impl AcmeStore for DirectoryAcmeStore {
+    async fn get(&self, name: &str) -> Result<Option<String>, DatabaseError> {
+        match tokio::fs::read_to_string(self.path.join(name)).await {
+            Ok(value) => Ok(Some(value)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(DatabaseError::QueryError(e.to_string())),
+        }
+    }
+
+    async fn put(&self, name: &str, value: &str) -> Result<(), DatabaseError> {
+        // Written next to the file and renamed over it, so readers never see
+        // half of it
+        let path = self.path.join(name);
+        let temporary = self.path.join(format!(".{}.tmp", name));
+        let mut options = tokio::fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        let write = async {
+            use tokio::io::AsyncWriteExt;
+            let mut file = options.open(&temporary).await?;
+            file.write_all(value.as_bytes()).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&temporary, &path).await
+        };
+        write
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), DatabaseError> {
+        match tokio::fs::remove_file(self.path.join(name)).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(DatabaseError::QueryError(e.to_string()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Objects stored as Redis strings at `{prefix}acme:{name}`
+#[cfg(feature = "redis")]
+pub struct RedisAcmeStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisAcmeStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}acme:{}", self.key_prefix, name)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl AcmeStore for RedisAcmeStore {
+    async fn get(&self, name: &str) -> Result<Option<String>, DatabaseError> {
+        redis::cmd("GET")
+            .arg(self.key(name))
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn put(&self, name: &str, value: &str) -> Result<(), DatabaseError> {
+        redis::cmd("SET")
+            .arg(self.key(name))
+            .arg(value)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), DatabaseError> {
+        redis::cmd("DEL")
+            .arg(self.key(name))
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+}
+
+/// Objects stored in the `bouncer_acme_objects` PostgreSQL table
+///
+/// Everything is read from the primary, since a challenge written by one
+/// replica has to be visible to the others straight away.
+#[cfg(feature = "postgres")]
+pub struct PostgresAcmeStore {
+    pool: Arc<sqlx::Pool<sqlx::Postgres>>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresAcmeStore {
+    pub fn new(pool: Arc<sqlx::Pool<sqlx::Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl AcmeStore for PostgresAcmeStore {
+    async fn get(&self, name: &str) -> Result<Option<String>, DatabaseError> {
+        sqlx::query_scalar::<_, String>("SELECT value FROM bouncer_acme_objects WHERE name = $1")
+            .bind(name)
+            .fetch_optional(&*self.pool)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn put(&self, name: &str, value: &str) -> Result<(), DatabaseError> {
+        sqlx::query(
+            "INSERT INTO bouncer_acme_objects (name, value, updated_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value, updated_at = EXCLUDED.updated_at",
+        )
+        .bind(name)
+        .bind(value)
+        .bind(crate::admin::now_secs())
+        .execute(&*self.pool)
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), DatabaseError> {
+        sqlx::query("DELETE FROM bouncer_acme_objects WHERE name = $1")
+            .bind(name)
+            .execute(&*self.pool)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Create the store selected in `server.acme.storage`
+pub async fn create_acme_store(
+    storage: &AcmeStorageConfig,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn AcmeStore>, DatabaseError> {
+    match storage {
+        AcmeStorageConfig::Directory { path } => Ok(Arc::new(DirectoryAcmeStore::new(path)?)),
+        AcmeStorageConfig::Redis { key_prefix } => {
+            crate::database::validate_database_config(databases, "redis")?;
+            create_redis_store(key_prefix, databases).await
+        }
+        AcmeStorageConfig::Postgres { run_migrations } => {
+            crate::database::validate_database_config(databases, "postgres")?;
+            create_postgres_store(*run_migrations, databases).await
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    key_prefix: &str,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn AcmeStore>, DatabaseError> {
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisAcmeStore::new(&client, key_prefix.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _key_prefix: &str,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn AcmeStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_store(
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn AcmeStore>, DatabaseError> {
+    let postgres_config = databases.postgres.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("PostgreSQL configuration is required".to_string())
+    })?;
+    let pool = crate::database::get_postgres_client(postgres_config).await?;
+
+    if run_migrations {
+        crate::database::migrations::run_postgres_migrations(&pool, POSTGRES_MIGRATIONS).await?;
+    }
+
+    Ok(Arc::new(PostgresAcmeStore::new(pool)))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_store(
+    _run_migrations: bool,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn AcmeStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string(),
+    ))
+}
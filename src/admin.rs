@@ -0,0 +1,54 @@
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+
+/// Error response of the admin routes, as `{"error": message}`
+pub fn json_error(status: StatusCode, message: impl Into<String>) -> axum::response::Response {
+    (status, Json(serde_json::json!({ "error": message.into() }))).into_response()
+}
+
+/// Whether the request is authorized with `Authorization: Bearer <admin_token>`
+pub fn is_admin(headers: &HeaderMap, admin_token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|token| secrets_match(token, admin_token))
+}
+
+/// Compare a secret in constant time, so response times don't reveal how much
+/// of a guess was right
+pub fn secrets_match(given: &str, secret: &str) -> bool {
+    given.as_bytes().ct_eq(secret.as_bytes()).into()
+}
+
+/// The current Unix time in seconds
+pub fn now_secs() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_admin() {
+        let headers = |value: &str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(header::AUTHORIZATION, value.parse().unwrap());
+            headers
+        };
+        assert!(is_admin(&headers("Bearer secret"), "secret"));
+        assert!(!is_admin(&headers("Bearer secre"), "secret"));
+        assert!(!is_admin(&headers("Bearer secret2"), "secret"));
+        assert!(!is_admin(&headers("Basic secret"), "secret"));
+        assert!(!is_admin(&HeaderMap::new(), "secret"));
+    }
+}
@@ -0,0 +1,296 @@
+use lru::LruCache;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+
+pub mod store;
+pub use store::{shared_cache_store, CacheError, CacheStore};
+
+/// Size limits for an in-memory cache
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct CacheLimits {
+    /// Maximum number of entries kept in the cache
+    #[serde(default = "default_max_entries")]
+    pub max_entries: usize,
+    /// Optional limit on the total estimated size of all entries, in bytes
+    #[serde(default)]
+    pub max_bytes: Option<usize>,
+}
+
+impl Default for CacheLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: default_max_entries(),
+            max_bytes: None,
+        }
+    }
+}
+
+fn default_max_entries() -> usize {
+    10_000
+}
+
+/// Point-in-time metrics for a cache
+#[derive(Serialize, Debug, Clone)]
+pub struct CacheStats {
+    pub name: String,
+    pub entries: usize,
+    pub bytes: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+}
+
+/// Anything that can report cache metrics
+pub trait CacheMetrics: Send + Sync {
+    fn stats(&self) -> CacheStats;
+}
+
+// Every live cache, so metrics can be reported from one place
+static CACHES: Lazy<Mutex<Vec<Weak<dyn CacheMetrics>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Metrics for every cache that is still alive
+pub fn all_stats() -> Vec<CacheStats> {
+    let mut caches = CACHES.lock().unwrap();
+    caches.retain(|cache| cache.strong_count() > 0);
+    caches
+        .iter()
+        .filter_map(|cache| cache.upgrade())
+        .map(|cache| cache.stats())
+        .collect()
+}
+
+type Weigher<K, V> = Box<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+struct CacheState<K: Hash + Eq, V> {
+    entries: LruCache<K, V>,
+    bytes: usize,
+}
+
+/// Thread-safe LRU cache bounded by entry count and, optionally, total size
+///
+/// All in-process caches should be built on this type so Bouncer's memory use
+/// stays predictable. Entry sizes are estimated with a weigher function, which
+/// defaults to the shallow size of the key and value.
+pub struct BoundedCache<K: Hash + Eq, V> {
+    name: String,
+    limits: CacheLimits,
+    weigher: Weigher<K, V>,
+    state: Mutex<CacheState<K, V>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl<K, V> BoundedCache<K, V>
+where
+    K: Hash + Eq + Send + 'static,
+    V: Clone + Send + 'static,
+{
+    /// Create a cache and register it for metrics reporting
+    pub fn new(name: impl Into<String>, limits: CacheLimits) -> Arc<Self> {
+        Self::with_weigher(name, limits, |_, _| {
+            std::mem::size_of::<K>() + std::mem::size_of::<V>()
+        })
+    }
+
+    /// Create a cache that estimates entry sizes with a custom weigher
+    pub fn with_weigher<F>(name: impl Into<String>, limits: CacheLimits, weigher: F) -> Arc<Self>
+    where
+        F: Fn(&K, &V) -> usize + Send + Sync + 'static,
+    {
+        let cache = Arc::new(Self {
+            name: name.into(),
+            limits,
+            weigher: Box::new(weigher),
+            state: Mutex::new(CacheState {
+                entries: LruCache::unbounded(),
+                bytes: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: AtomicU64::new(0),
+        });
+
+        let metrics: Arc<dyn CacheMetrics> = cache.clone();
+        CACHES.lock().unwrap().push(Arc::downgrade(&metrics));
+
+        cache
+    }
+
+    pub fn get(&self, key: &K) -> Option<V> {
+        let value = self.state.lock().unwrap().entries.get(key).cloned();
+
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        value
+    }
+
+    /// Insert an entry, evicting the least recently used entries to stay within limits
+    ///
+    /// Entries larger than `max_bytes` on their own are not cached.
+    pub fn insert(&self, key: K, value: V) {
+        let weight = (self.weigher)(&key, &value);
+        if self.limits.max_bytes.is_some_and(|max| weight > max) {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap();
+        if let Some(old) = state.entries.pop(&key) {
+            state.bytes -= (self.weigher)(&key, &old);
+        }
+        state.entries.put(key, value);
+        state.bytes += weight;
+
+        self.evict(&mut state);
+    }
+
+    /// Atomically replace an entry with a value computed from the current one
+    ///
+    /// This is used for counters and other read-modify-write state, where a
+    /// separate `get` and `insert` would race.
+    pub fn update<F>(&self, key: K, f: F) -> V
+    where
+        F: FnOnce(Option<&V>) -> V,
+    {
+        let mut state = self.state.lock().unwrap();
+        let old = state.entries.pop(&key);
+        if let Some(old) = &old {
+            state.bytes -= (self.weigher)(&key, old);
+        }
+
+        let value = f(old.as_ref());
+        state.bytes += (self.weigher)(&key, &value);
+        state.entries.put(key, value.clone());
+
+        self.evict(&mut state);
+        value
+    }
+
+    // Evict least recently used entries until the cache is within its limits
+    fn evict(&self, state: &mut CacheState<K, V>) {
+        while state.entries.len() > self.limits.max_entries
+            || self.limits.max_bytes.is_some_and(|max| state.bytes > max)
+        {
+            match state.entries.pop_lru() {
+                Some((key, value)) => {
+                    state.bytes -= (self.weigher)(&key, &value);
+                    self.evictions.fetch_add(1, Ordering::Relaxed);
+                }
+                None => break,
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &K) -> Option<V> {
+        let mut state = self.state.lock().unwrap();
+        let value = state.entries.pop(key)?;
+        state.bytes -= (self.weigher)(key, &value);
+        Some(value)
+    }
+
+    /// Remove the entries `keep` returns false for, returning how many were removed
+    pub fn retain<F>(&self, mut keep: F) -> usize
+    where
+        K: Clone,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut state = self.state.lock().unwrap();
+        let removed: Vec<K> = state
+            .entries
+            .iter()
+            .filter(|(key, value)| !keep(key, value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &removed {
+            if let Some(value) = state.entries.pop(key) {
+                state.bytes -= (self.weigher)(key, &value);
+            }
+        }
+        removed.len()
+    }
+
+    pub fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.bytes = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<K, V> CacheMetrics for BoundedCache<K, V>
+where
+    K: Hash + Eq + Send,
+    V: Send,
+{
+    fn stats(&self) -> CacheStats {
+        let state = self.state.lock().unwrap();
+        CacheStats {
+            name: self.name.clone(),
+            entries: state.entries.len(),
+            bytes: state.bytes,
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bounded_cache() {
+        let cache = BoundedCache::with_weigher(
+            "test",
+            CacheLimits {
+                max_entries: 2,
+                max_bytes: Some(10),
+            },
+            |_: &&str, value: &String| value.len(),
+        );
+
+        cache.insert("a", "1234".to_string());
+        cache.insert("b", "1234".to_string());
+        assert_eq!(cache.get(&"a"), Some("1234".to_string()));
+
+        // Over the entry limit: "b" is least recently used
+        cache.insert("c", "12".to_string());
+        assert_eq!(cache.get(&"b"), None);
+
+        // Over the byte limit: "a" is now least recently used
+        cache.insert("d", "12345".to_string());
+        assert_eq!(cache.get(&"a"), None);
+        assert_eq!(cache.len(), 2);
+
+        // Entries larger than the byte limit are never cached
+        cache.insert("e", "12345678901".to_string());
+        assert_eq!(cache.get(&"e"), None);
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 3);
+        assert_eq!(stats.evictions, 2);
+        assert_eq!(stats.bytes, 7);
+
+        assert_eq!(cache.retain(|key, _| *key != "c"), 1);
+        assert_eq!(cache.get(&"c"), None);
+        assert_eq!(cache.get(&"d"), Some("12345".to_string()));
+        assert_eq!(cache.stats().bytes, 5);
+    }
+}
@@ -0,0 +1,425 @@
+use super::{BoundedCache, CacheLimits};
+use crate::config::{CacheBackend, CacheConfig, DatabasesConfig, RedisConfig};
+use async_trait::async_trait;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Error type for cache store operations
+#[derive(Debug)]
+pub enum CacheError {
+    /// Error related to cache configuration
+    ConfigurationError(String),
+    /// Error reported by the cache backend
+    BackendError(String),
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConfigurationError(msg) => write!(f, "Cache configuration error: {}", msg),
+            Self::BackendError(msg) => write!(f, "Cache backend error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
+
+/// Key/value store with expiry shared by caches in policies
+///
+/// Idempotency keys, cached responses, nonces and negative authentication results
+/// are all stored through this trait, so the backend can be chosen in config.
+#[async_trait]
+pub trait CacheStore: Send + Sync + 'static {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError>;
+
+    /// Store a value. Without a TTL the value is kept until evicted or deleted
+    async fn set(&self, key: &str, value: Vec<u8>, ttl: Option<Duration>)
+        -> Result<(), CacheError>;
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError>;
+
+    /// Store a value unless the key already has one, returning whether it was
+    /// stored. Unlike `get` followed by `set`, concurrent calls can't both win
+    async fn add(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, CacheError>;
+}
+
+/// In-process store built on [`BoundedCache`]
+#[allow(clippy::type_complexity)]
+pub struct MemoryCacheStore {
+    cache: Arc<BoundedCache<String, (Vec<u8>, Option<Instant>)>>,
+}
+
+impl MemoryCacheStore {
+    pub fn new(limits: CacheLimits) -> Self {
+        Self {
+            cache: BoundedCache::with_weigher(
+                "cache_store",
+                limits,
+                |key: &String, entry: &(Vec<u8>, Option<Instant>)| key.len() + entry.0.len(),
+            ),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheStore for MemoryCacheStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let key = key.to_string();
+        match self.cache.get(&key) {
+            Some((_, Some(expires_at))) if expires_at <= Instant::now() => {
+                self.cache.remove(&key);
+                Ok(None)
+            }
+            Some((value, _)) => Ok(Some(value)),
+            None => Ok(None),
+        }
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
+        let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+        self.cache.insert(key.to_string(), (value, expires_at));
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        self.cache.remove(&key.to_string());
+        Ok(())
+    }
+
+    async fn add(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, CacheError> {
+        let now = Instant::now();
+        let expires_at = ttl.map(|ttl| now + ttl);
+        let mut added = false;
+        self.cache.update(key.to_string(), |current| match current {
+            Some(entry) if entry.1.is_none_or(|expires_at| expires_at > now) => entry.clone(),
+            _ => {
+                added = true;
+                (value, expires_at)
+            }
+        });
+        Ok(added)
+    }
+}
+
+/// Store backed by the configured Redis database
+#[cfg(feature = "redis")]
+pub struct RedisCacheStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCacheStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, CacheError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| CacheError::BackendError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl CacheStore for RedisCacheStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        redis::cmd("GET")
+            .arg(format!("{}{}", self.key_prefix, key))
+            .query_async::<_, Option<Vec<u8>>>(&mut self.connection.clone())
+            .await
+            .map_err(|e| CacheError::BackendError(e.to_string()))
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(format!("{}{}", self.key_prefix, key)).arg(value);
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+        }
+
+        cmd.query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| CacheError::BackendError(e.to_string()))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        redis::cmd("DEL")
+            .arg(format!("{}{}", self.key_prefix, key))
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| CacheError::BackendError(e.to_string()))
+    }
+
+    async fn add(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, CacheError> {
+        let mut cmd = redis::cmd("SET");
+        cmd.arg(format!("{}{}", self.key_prefix, key))
+            .arg(value)
+            .arg("NX");
+        if let Some(ttl) = ttl {
+            cmd.arg("PX").arg(ttl.as_millis().max(1) as u64);
+        }
+
+        cmd.query_async::<_, Option<String>>(&mut self.connection.clone())
+            .await
+            .map(|reply| reply.is_some())
+            .map_err(|e| CacheError::BackendError(e.to_string()))
+    }
+}
+
+/// Store backed by a memcached server
+#[cfg(feature = "memcached")]
+pub struct MemcachedCacheStore {
+    client: Arc<memcache::Client>,
+    key_prefix: String,
+}
+
+#[cfg(feature = "memcached")]
+impl MemcachedCacheStore {
+    pub fn new(url: &str, key_prefix: String) -> Result<Self, CacheError> {
+        let client =
+            memcache::Client::connect(url).map_err(|e| CacheError::BackendError(e.to_string()))?;
+
+        Ok(Self {
+            client: Arc::new(client),
+            key_prefix,
+        })
+    }
+
+    // The memcache client is blocking, so run operations off the async runtime
+    async fn run<T, F>(&self, operation: F) -> Result<T, CacheError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&memcache::Client) -> Result<T, memcache::MemcacheError> + Send + 'static,
+    {
+        let client = Arc::clone(&self.client);
+        tokio::task::spawn_blocking(move || operation(&client))
+            .await
+            .map_err(|e| CacheError::BackendError(e.to_string()))?
+            .map_err(|e| CacheError::BackendError(e.to_string()))
+    }
+}
+
+#[cfg(feature = "memcached")]
+#[async_trait]
+impl CacheStore for MemcachedCacheStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, CacheError> {
+        let key = format!("{}{}", self.key_prefix, key);
+        self.run(move |client| client.get::<Vec<u8>>(&key)).await
+    }
+
+    async fn set(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<(), CacheError> {
+        let key = format!("{}{}", self.key_prefix, key);
+        // memcached treats 0 as "never expire"
+        let expiration = ttl.map(|ttl| ttl.as_secs().max(1) as u32).unwrap_or(0);
+        self.run(move |client| client.set(&key, value.as_slice(), expiration))
+            .await
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), CacheError> {
+        let key = format!("{}{}", self.key_prefix, key);
+        self.run(move |client| client.delete(&key).map(|_| ()))
+            .await
+    }
+
+    async fn add(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> Result<bool, CacheError> {
+        use memcache::{CommandError, MemcacheError};
+
+        let key = format!("{}{}", self.key_prefix, key);
+        let expiration = ttl.map(|ttl| ttl.as_secs().max(1) as u32).unwrap_or(0);
+        // Only the binary protocol, memcache's default, reports keys that
+        // exist. With `?protocol=ascii` every add looks stored
+        self.run(
+            move |client| match client.add(&key, value.as_slice(), expiration) {
+                Ok(()) => Ok(true),
+                Err(MemcacheError::CommandError(CommandError::KeyExists)) => Ok(false),
+                Err(e) => Err(e),
+            },
+        )
+        .await
+    }
+}
+
+/// Create the cache store selected in config
+pub async fn create_cache_store(
+    config: &CacheConfig,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn CacheStore>, CacheError> {
+    match config.backend {
+        CacheBackend::Memory => Ok(Arc::new(MemoryCacheStore::new(config.limits.clone()))),
+        CacheBackend::Redis => {
+            let redis_config = databases.redis.as_ref().ok_or_else(|| {
+                CacheError::ConfigurationError(
+                    "The redis cache backend requires a databases.redis section".to_string(),
+                )
+            })?;
+            create_redis_store(config, redis_config).await
+        }
+        CacheBackend::Memcached => {
+            let url = config.memcached_url.as_ref().ok_or_else(|| {
+                CacheError::ConfigurationError(
+                    "The memcached cache backend requires cache.memcached_url".to_string(),
+                )
+            })?;
+            create_memcached_store(config, url.expose())
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    config: &CacheConfig,
+    redis_config: &RedisConfig,
+) -> Result<Arc<dyn CacheStore>, CacheError> {
+    let client = crate::database::get_redis_client(redis_config)
+        .await
+        .map_err(|e| CacheError::BackendError(e.to_string()))?;
+    let store = RedisCacheStore::new(&client, config.key_prefix.clone()).await?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _config: &CacheConfig,
+    _redis_config: &RedisConfig,
+) -> Result<Arc<dyn CacheStore>, CacheError> {
+    Err(CacheError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(feature = "memcached")]
+fn create_memcached_store(
+    config: &CacheConfig,
+    url: &str,
+) -> Result<Arc<dyn CacheStore>, CacheError> {
+    let store = MemcachedCacheStore::new(url, config.key_prefix.clone())?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "memcached"))]
+fn create_memcached_store(
+    _config: &CacheConfig,
+    _url: &str,
+) -> Result<Arc<dyn CacheStore>, CacheError> {
+    Err(CacheError::ConfigurationError(
+        "Memcached support is not enabled. Rebuild with the 'memcached' feature.".to_string(),
+    ))
+}
+
+// Store shared by every policy, created on first use from the global config
+static SHARED_STORE: tokio::sync::OnceCell<Arc<dyn CacheStore>> =
+    tokio::sync::OnceCell::const_new();
+
+/// Get the cache store configured in the global config's `cache` section
+pub async fn shared_cache_store() -> Result<Arc<dyn CacheStore>, CacheError> {
+    SHARED_STORE
+        .get_or_try_init(|| async {
+            let config = crate::GLOBAL_CONFIG.get().ok_or_else(|| {
+                CacheError::ConfigurationError("Global configuration not initialized".to_string())
+            })?;
+            create_cache_store(&config.cache, &config.databases).await
+        })
+        .await
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn memory_store(max_entries: usize, max_bytes: Option<usize>) -> MemoryCacheStore {
+        MemoryCacheStore::new(CacheLimits {
+            max_entries,
+            max_bytes,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = memory_store(100, None);
+        store.set("a", b"1".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), Some(b"1".to_vec()));
+        store.delete("a").await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+
+        // Entries expire after their TTL
+        let ttl = Some(Duration::from_millis(50));
+        store.set("b", b"2".to_vec(), ttl).await.unwrap();
+        assert_eq!(store.get("b").await.unwrap(), Some(b"2".to_vec()));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert_eq!(store.get("b").await.unwrap(), None);
+
+        // `add` only replaces entries that are missing or expired
+        assert!(store.add("c", b"3".to_vec(), ttl).await.unwrap());
+        assert!(!store.add("c", b"4".to_vec(), ttl).await.unwrap());
+        assert_eq!(store.get("c").await.unwrap(), Some(b"3".to_vec()));
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        assert!(store.add("c", b"5".to_vec(), None).await.unwrap());
+        assert_eq!(store.get("c").await.unwrap(), Some(b"5".to_vec()));
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_eviction() {
+        // Over the entry limit, the least recently used entry goes
+        let store = memory_store(2, None);
+        store.set("a", b"1".to_vec(), None).await.unwrap();
+        store.set("b", b"2".to_vec(), None).await.unwrap();
+        store.get("a").await.unwrap();
+        store.set("c", b"3".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("b").await.unwrap(), None);
+        assert_eq!(store.get("a").await.unwrap(), Some(b"1".to_vec()));
+        assert_eq!(store.get("c").await.unwrap(), Some(b"3".to_vec()));
+
+        // Keys and values count towards the byte limit
+        let store = memory_store(100, Some(9));
+        store.set("a", b"1234".to_vec(), None).await.unwrap();
+        store.set("b", b"1234".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("a").await.unwrap(), None);
+        assert_eq!(store.get("b").await.unwrap(), Some(b"1234".to_vec()));
+
+        // Values too large for the store aren't kept
+        store.set("c", b"123456789".to_vec(), None).await.unwrap();
+        assert_eq!(store.get("c").await.unwrap(), None);
+        assert_eq!(store.get("b").await.unwrap(), Some(b"1234".to_vec()));
+    }
+}
@@ -0,0 +1,498 @@
+use super::{
+    AcmeConfig, BodySizeRouteConfig, CacheConfig, Config, DatabasesConfig, FallbackConfig,
+    FanoutConfig, MeteringConfig, MongoConfig, MySqlConfig, PipelineStep, PluginsConfig,
+    PolicyConfig, PostgresConfig, PreauthorizeConfig, RedisConfig, ResponseCacheConfig,
+    ResponseHeadersConfig, RewriteRuleConfig, RouteLabelConfig, ServerConfig, StagingConfig,
+    TlsConfig, UpstreamConfig, UpstreamTlsConfig, WarmUpConfig, WebhookConfig,
+};
+use crate::policy::condition::ApplyIf;
+use crate::policy::denial::DenialBodies;
+use crate::policy::failure::FailureMode;
+use crate::policy::logging::LogLevel;
+use crate::policy::schedule::Timestamp;
+use crate::policy::traits::PolicyFactory;
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Builder for constructing a [`Config`] in code
+///
+/// This is mostly useful in tests and when embedding Bouncer, where authoring
+/// YAML strings is awkward. Policies are added with their typed config structs.
+///
+/// # Example
+///
+/// ```rust
+/// use bouncer::config::Config;
+/// use bouncer::policy::providers::bouncer::authentication::bearer::v1::{
+///     BearerAuthConfig, BearerAuthPolicyFactory,
+/// };
+///
+/// let config = Config::builder()
+///     .port(8000)
+///     .destination_address("http://localhost:3000")
+///     .policy::<BearerAuthPolicyFactory>(BearerAuthConfig {
+///         token: Some("secret".into()),
+///         ..Default::default()
+///     })
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(config.policies.len(), 1);
+/// ```
+#[derive(Default)]
+pub struct ConfigBuilder {
+    bouncer_version: Option<String>,
+    server: ServerConfig,
+    databases: DatabasesConfig,
+    plugins: PluginsConfig,
+    cache: CacheConfig,
+    labels: Vec<RouteLabelConfig>,
+    bypass: Vec<String>,
+    webhooks: Vec<WebhookConfig>,
+    staging: Option<StagingConfig>,
+    metering: Option<MeteringConfig>,
+    fanout: Option<FanoutConfig>,
+    policy_log_level: Option<LogLevel>,
+    policies: Vec<PolicyConfig>,
+    errors: Vec<String>,
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the compatible Bouncer version. Defaults to the current minor version (e.g. `0.1.*`)
+    pub fn bouncer_version(mut self, version: impl Into<String>) -> Self {
+        self.bouncer_version = Some(version.into());
+        self
+    }
+
+    /// Replace the whole server section
+    pub fn server(mut self, server: ServerConfig) -> Self {
+        self.server = server;
+        self
+    }
+
+    pub fn bind_address(mut self, bind_address: impl Into<String>) -> Self {
+        self.server.bind_address = bind_address.into();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.server.port = port;
+        self
+    }
+
+    /// Set the upstream that requests are forwarded to after the policy chain
+    pub fn destination_address(mut self, destination_address: impl Into<String>) -> Self {
+        self.server.destination_address = Some(destination_address.into());
+        self
+    }
+
+    /// Run consecutive read-only policies concurrently
+    pub fn parallel_policies(mut self, parallel: bool) -> Self {
+        self.server.parallel_policies = parallel;
+        self
+    }
+
+    /// Send clients only the error code and request ID of denials
+    pub fn terse_denials(mut self) -> Self {
+        self.server.denial_bodies = DenialBodies::Terse;
+        self
+    }
+
+    /// Send denials with the `error` code in `locale` with this message template
+    pub fn denial_message(
+        mut self,
+        locale: impl Into<String>,
+        error: impl Into<String>,
+        template: impl Into<String>,
+    ) -> Self {
+        self.server
+            .denial_messages
+            .locales
+            .entry(locale.into())
+            .or_default()
+            .insert(error.into(), template.into());
+        self
+    }
+
+    /// Send denial messages in `locale` to clients that accept none of the others
+    pub fn fallback_locale(mut self, locale: impl Into<String>) -> Self {
+        self.server.denial_messages.fallback_locale = Some(locale.into());
+        self
+    }
+
+    /// Give every request this long across the policy chain and the upstream call
+    pub fn request_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.server.request_timeout_ms = Some(timeout_ms);
+        self
+    }
+
+    /// Reject request bodies larger than this many bytes
+    pub fn max_body_size(mut self, bytes: usize) -> Self {
+        self.server.max_body_size = Some(bytes);
+        self
+    }
+
+    /// Allow bodies of up to `bytes` on routes matching `route`, instead of `max_body_size`
+    pub fn max_body_size_route(mut self, route: impl Into<String>, bytes: usize) -> Self {
+        self.server.max_body_size_routes.push(BodySizeRouteConfig {
+            route: route.into(),
+            max_body_size: bytes,
+        });
+        self
+    }
+
+    /// Track active sessions for the admin API
+    pub fn track_sessions(mut self, track: bool) -> Self {
+        self.server.track_sessions = track;
+        self
+    }
+
+    /// Terminate HTTPS with this certificate
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.server.tls = Some(tls);
+        self
+    }
+
+    /// Terminate HTTPS with certificates obtained from an ACME CA
+    pub fn acme(mut self, acme: AcmeConfig) -> Self {
+        self.server.acme = Some(acme);
+        self
+    }
+
+    /// Serve repeated `GET` and `HEAD` requests from cached upstream responses
+    pub fn response_cache(mut self, cache: ResponseCacheConfig) -> Self {
+        self.server.response_cache = Some(cache);
+        self
+    }
+
+    /// Set the responses Bouncer answers itself, without a destination or for static routes
+    pub fn fallback(mut self, fallback: FallbackConfig) -> Self {
+        self.server.fallback = fallback;
+        self
+    }
+
+    /// Serve the batch pre-authorization endpoint
+    pub fn preauthorize(mut self, preauthorize: PreauthorizeConfig) -> Self {
+        self.server.preauthorize = Some(preauthorize);
+        self
+    }
+
+    /// Accept HTTP/2 from clients (on by default)
+    pub fn http2(mut self, enabled: bool) -> Self {
+        self.server.http2 = enabled;
+        self
+    }
+
+    /// Speak HTTP/2 to plain HTTP upstreams without negotiating it first
+    pub fn upstream_http2_prior_knowledge(mut self, enabled: bool) -> Self {
+        self.server.upstream_http2_prior_knowledge = enabled;
+        self
+    }
+
+    /// Set the client certificate and trusted CAs for HTTPS upstreams
+    pub fn upstream_tls(mut self, upstream_tls: UpstreamTlsConfig) -> Self {
+        self.server.upstream_tls = Some(upstream_tls);
+        self
+    }
+
+    /// Set the timeouts and retries for requests to the upstream
+    pub fn upstream(mut self, upstream: UpstreamConfig) -> Self {
+        self.server.upstream = upstream;
+        self
+    }
+
+    /// Set the headers removed from, set on or added to upstream responses
+    pub fn response_headers(mut self, response_headers: ResponseHeadersConfig) -> Self {
+        self.server.response_headers = response_headers;
+        self
+    }
+
+    /// Append a rule rewriting the path of forwarded requests
+    pub fn rewrite(mut self, rule: RewriteRuleConfig) -> Self {
+        self.server.rewrite.push(rule);
+        self
+    }
+
+    /// Warm up policies and upstream connections before accepting traffic
+    pub fn warm_up(mut self, warm_up: WarmUpConfig) -> Self {
+        self.server.warm_up = Some(warm_up);
+        self
+    }
+
+    /// Set the order of the ingress pipeline's steps
+    pub fn pipeline(mut self, steps: Vec<PipelineStep>) -> Self {
+        self.server.pipeline = steps;
+        self
+    }
+
+    /// Believe the `X-Forwarded-*` headers of proxies at these addresses or CIDR ranges
+    pub fn trusted_proxies(mut self, networks: Vec<String>) -> Self {
+        self.server.trusted_proxies = networks;
+        self
+    }
+
+    /// Replace the whole databases section
+    pub fn databases(mut self, databases: DatabasesConfig) -> Self {
+        self.databases = databases;
+        self
+    }
+
+    pub fn redis(mut self, redis: RedisConfig) -> Self {
+        self.databases.redis = Some(redis);
+        self
+    }
+
+    pub fn postgres(mut self, postgres: PostgresConfig) -> Self {
+        self.databases.postgres = Some(postgres);
+        self
+    }
+
+    pub fn mysql(mut self, mysql: MySqlConfig) -> Self {
+        self.databases.mysql = Some(mysql);
+        self
+    }
+
+    pub fn mongo(mut self, mongo: MongoConfig) -> Self {
+        self.databases.mongo = Some(mongo);
+        self
+    }
+
+    /// Configure loading of dynamic policy plugins
+    pub fn plugins(mut self, plugins: PluginsConfig) -> Self {
+        self.plugins = plugins;
+        self
+    }
+
+    /// Select the cache store backend shared by policies
+    pub fn cache(mut self, cache: CacheConfig) -> Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Label requests matching a path pattern. The first matching label wins
+    pub fn label(mut self, label: RouteLabelConfig) -> Self {
+        self.labels.push(label);
+        self
+    }
+
+    /// Skip the policy chain for requests matching a route pattern, e.g. `GET /health`
+    pub fn bypass(mut self, pattern: impl Into<String>) -> Self {
+        self.bypass.push(pattern.into());
+        self
+    }
+
+    /// Send decision events to a webhook
+    pub fn webhook(mut self, webhook: WebhookConfig) -> Self {
+        self.webhooks.push(webhook);
+        self
+    }
+
+    /// Send a share of the traffic through the policies of a second config
+    pub fn staging(mut self, staging: StagingConfig) -> Self {
+        self.staging = Some(staging);
+        self
+    }
+
+    /// Write per-identity usage records
+    pub fn metering(mut self, metering: MeteringConfig) -> Self {
+        self.metering = Some(metering);
+        self
+    }
+
+    /// Deliver events published by internal services to consumer webhooks
+    pub fn fanout(mut self, fanout: FanoutConfig) -> Self {
+        self.fanout = Some(fanout);
+        self
+    }
+
+    /// Append a policy to the chain using its typed config
+    ///
+    /// The config is validated with the factory's `validate_config`; any error is
+    /// reported when calling [`ConfigBuilder::build`].
+    pub fn policy<F>(mut self, config: F::Config) -> Self
+    where
+        F: PolicyFactory,
+        F::Config: Serialize,
+    {
+        let provider = F::policy_id();
+
+        if let Err(e) = F::validate_config(&config) {
+            self.errors
+                .push(format!("Invalid config for policy {}: {}", provider, e));
+            return self;
+        }
+
+        // The policy reads its secrets back from the parameters
+        match super::secret::exposed(|| serde_json::to_value(&config)) {
+            Ok(parameters) => self.policies.push(PolicyConfig {
+                id: provider.to_string(),
+                provider: provider.to_string(),
+                parameters,
+                effective_from: None,
+                effective_until: None,
+                log_level: None,
+                apply_if: None,
+                failure_mode: FailureMode::Closed,
+                group: None,
+            }),
+            Err(e) => self.errors.push(format!(
+                "Failed to serialize config for policy {}: {}",
+                provider, e
+            )),
+        }
+
+        self
+    }
+
+    /// Append a policy by provider ID with raw parameters, e.g. for plugin policies
+    pub fn policy_value(
+        mut self,
+        provider: impl Into<String>,
+        parameters: serde_json::Value,
+    ) -> Self {
+        let provider = provider.into();
+        self.policies.push(PolicyConfig {
+            id: provider.clone(),
+            provider,
+            parameters,
+            effective_from: None,
+            effective_until: None,
+            log_level: None,
+            apply_if: None,
+            failure_mode: FailureMode::Closed,
+            group: None,
+        });
+        self
+    }
+
+    /// Only run the last added policy between `from` and `until`, in Unix seconds
+    pub fn effective_between(mut self, from: Option<i64>, until: Option<i64>) -> Self {
+        if let Some(policy) = self.policies.last_mut() {
+            policy.effective_from = from.map(Timestamp);
+            policy.effective_until = until.map(Timestamp);
+        }
+        self
+    }
+
+    /// Set the most verbose level the last added policy logs at
+    pub fn log_level(mut self, level: LogLevel) -> Self {
+        if let Some(policy) = self.policies.last_mut() {
+            policy.log_level = Some(level);
+        }
+        self
+    }
+
+    /// Only run the last added policy on requests matching `condition`
+    pub fn apply_if(mut self, condition: ApplyIf) -> Self {
+        if let Some(policy) = self.policies.last_mut() {
+            policy.apply_if = Some(condition);
+        }
+        self
+    }
+
+    /// Set whether requests continue when the last added policy fails
+    pub fn failure_mode(mut self, mode: FailureMode) -> Self {
+        if let Some(policy) = self.policies.last_mut() {
+            policy.failure_mode = mode;
+        }
+        self
+    }
+
+    /// Run the last added policy concurrently with the policies next to it in `group`
+    pub fn group(mut self, group: impl Into<String>) -> Self {
+        if let Some(policy) = self.policies.last_mut() {
+            policy.group = Some(group.into());
+        }
+        self
+    }
+
+    /// Set the most verbose level policies log at, unless they set their own
+    pub fn policy_log_level(mut self, level: LogLevel) -> Self {
+        self.policy_log_level = Some(level);
+        self
+    }
+
+    pub fn build(self) -> Result<Config, String> {
+        if !self.errors.is_empty() {
+            return Err(self.errors.join("; "));
+        }
+
+        let bouncer_version = self.bouncer_version.unwrap_or_else(|| {
+            let (major_minor, _patch) = crate::VERSION.rsplit_once('.').unwrap_or(("0.1", ""));
+            format!("{}.*", major_minor)
+        });
+        super::validate_version(&bouncer_version, crate::VERSION)?;
+
+        Ok(Config {
+            server: self.server,
+            policies: self.policies,
+            databases: self.databases,
+            plugins: self.plugins,
+            cache: self.cache,
+            labels: self.labels,
+            bypass: self.bypass,
+            webhooks: self.webhooks,
+            staging: self.staging,
+            metering: self.metering,
+            fanout: self.fanout,
+            policy_log_level: self.policy_log_level,
+            bouncer_version,
+            policy_configs: HashMap::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::providers::bouncer::authentication::bearer::v1::{
+        BearerAuthConfig, BearerAuthPolicyFactory,
+    };
+    use crate::policy::providers::bouncer::authorization::rbac::v1::{
+        RbacConfig, RbacPolicyFactory,
+    };
+
+    #[test]
+    fn test_builder() {
+        let config = Config::builder()
+            .port(9000)
+            .destination_address("http://localhost:3000")
+            .policy::<RbacPolicyFactory>(RbacConfig {
+                route_roles: HashMap::from([("/api/*".to_string(), vec!["admin".to_string()])]),
+            })
+            .build()
+            .unwrap();
+
+        assert_eq!(config.full_bind_address(), "127.0.0.1:9000");
+        assert_eq!(
+            config.policies[0].provider,
+            "@bouncer/authorization/rbac/v1"
+        );
+        assert_eq!(
+            config.policies[0].parameters["route_roles"]["/api/*"][0],
+            "admin"
+        );
+
+        // Invalid policy configs are reported at build time
+        assert!(Config::builder()
+            .policy::<RbacPolicyFactory>(RbacConfig {
+                route_roles: HashMap::new(),
+            })
+            .build()
+            .is_err());
+
+        // Secrets are passed on to the policy, not masked
+        let config = Config::builder()
+            .policy::<BearerAuthPolicyFactory>(BearerAuthConfig {
+                token: Some("secret".into()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+        assert_eq!(config.policies[0].parameters["token"], "secret");
+    }
+}
@@ -0,0 +1,195 @@
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rand::RngCore;
+use std::env;
+
+/// Base64 encoded 32-byte key
+pub const KEY_ENV: &str = "BOUNCER_CONFIG_KEY";
+/// Path to a file holding the base64 encoded key
+pub const KEY_FILE_ENV: &str = "BOUNCER_CONFIG_KEY_FILE";
+/// Base64 encoded key, encrypted with AWS KMS
+pub const KMS_KEY_ENV: &str = "BOUNCER_CONFIG_KEY_KMS";
+
+// Any string in a config file can be written as `ENC[AES256_GCM,<base64>]`,
+// where the payload is a 12-byte nonce followed by the AES-256-GCM ciphertext
+const PREFIX: &str = "ENC[AES256_GCM,";
+const NONCE_LEN: usize = 12;
+
+/// Whether a config value is encrypted
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with("ENC[") && value.ends_with(']')
+}
+
+/// Generate a new base64 encoded key
+pub fn generate_key() -> String {
+    let mut key = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut key);
+    STANDARD.encode(key)
+}
+
+/// Encrypt a value for use in a config file
+pub fn encrypt_value(key: &[u8; 32], plaintext: &str) -> String {
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut nonce);
+
+    let ciphertext = Aes256Gcm::new(key.into())
+        .encrypt(Nonce::from_slice(&nonce), plaintext.as_bytes())
+        .expect("AES-GCM encryption of an in-memory value cannot fail");
+
+    format!(
+        "{}{}]",
+        PREFIX,
+        STANDARD.encode([&nonce[..], &ciphertext].concat())
+    )
+}
+
+/// Decrypt an `ENC[...]` value
+pub fn decrypt_value(key: &[u8; 32], value: &str) -> Result<String, String> {
+    let payload = value
+        .strip_prefix(PREFIX)
+        .and_then(|value| value.strip_suffix(']'))
+        .ok_or_else(|| "Encrypted values must look like ENC[AES256_GCM,...]".to_string())?;
+
+    let payload = STANDARD
+        .decode(payload)
+        .map_err(|e| format!("Invalid encrypted value: {}", e))?;
+    if payload.len() < NONCE_LEN {
+        return Err("Invalid encrypted value: too short".to_string());
+    }
+
+    let (nonce, ciphertext) = payload.split_at(NONCE_LEN);
+    let plaintext = Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| "Failed to decrypt value: wrong key or corrupted value".to_string())?;
+
+    String::from_utf8(plaintext).map_err(|_| "Decrypted value is not valid UTF-8".to_string())
+}
+
+fn decode_key(key: &str) -> Result<[u8; 32], String> {
+    STANDARD
+        .decode(key.trim())
+        .ok()
+        .and_then(|key| key.try_into().ok())
+        .ok_or_else(|| "Config key must be a base64 encoded 32-byte key".to_string())
+}
+
+/// Load the config key from the environment, a key file, or AWS KMS
+pub fn load_key() -> Result<[u8; 32], String> {
+    if let Ok(key) = env::var(KEY_ENV) {
+        return decode_key(&key);
+    }
+
+    if let Ok(path) = env::var(KEY_FILE_ENV) {
+        let key = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read config key file {}: {}", path, e))?;
+        return decode_key(&key);
+    }
+
+    if let Ok(ciphertext) = env::var(KMS_KEY_ENV) {
+        return decode_key(&decrypt_kms_key(&ciphertext)?);
+    }
+
+    Err(format!(
+        "Config contains encrypted values, but none of {}, {} or {} is set",
+        KEY_ENV, KEY_FILE_ENV, KMS_KEY_ENV
+    ))
+}
+
+#[cfg(feature = "aws-kms")]
+fn decrypt_kms_key(ciphertext: &str) -> Result<String, String> {
+    let ciphertext = STANDARD
+        .decode(ciphertext.trim())
+        .map_err(|e| format!("Invalid {}: {}", KMS_KEY_ENV, e))?;
+
+    // Configs are loaded synchronously, possibly from inside a runtime, so the
+    // KMS call runs on its own thread and runtime
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        runtime.block_on(async {
+            let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+            let output = aws_sdk_kms::Client::new(&config)
+                .decrypt()
+                .ciphertext_blob(aws_sdk_kms::primitives::Blob::new(ciphertext))
+                .send()
+                .await
+                .map_err(|e| format!("Failed to decrypt config key with KMS: {}", e))?;
+
+            output
+                .plaintext
+                .map(|plaintext| STANDARD.encode(plaintext.into_inner()))
+                .ok_or_else(|| "KMS returned no plaintext for the config key".to_string())
+        })
+    })
+    .join()
+    .map_err(|_| "Config key decryption panicked".to_string())?
+}
+
+#[cfg(not(feature = "aws-kms"))]
+fn decrypt_kms_key(_ciphertext: &str) -> Result<String, String> {
+    Err("AWS KMS support is not enabled. Rebuild with the 'aws-kms' feature.".to_string())
+}
+
+/// Decrypt every encrypted string in a parsed config, loading the key only if
+/// there is something to decrypt
+pub(crate) fn decrypt_yaml_values(value: &mut serde_yaml::Value) -> Result<(), String> {
+    fn walk(
+        value: &mut serde_yaml::Value,
+        key: &mut Option<[u8; 32]>,
+        path: &str,
+    ) -> Result<(), String> {
+        match value {
+            serde_yaml::Value::String(s) if is_encrypted(s) => {
+                let key = match *key {
+                    Some(key) => key,
+                    None => *key.insert(load_key()?),
+                };
+                *s = decrypt_value(&key, s).map_err(|e| format!("{}: {}", path, e))?;
+            }
+            serde_yaml::Value::Mapping(map) => {
+                for (name, v) in map.iter_mut() {
+                    let name = name.as_str().unwrap_or("?");
+                    walk(v, key, &format!("{}.{}", path, name))?;
+                }
+            }
+            serde_yaml::Value::Sequence(seq) => {
+                for (index, v) in seq.iter_mut().enumerate() {
+                    walk(v, key, &format!("{}[{}]", path, index))?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    walk(value, &mut None, "config")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_values() {
+        let key = [3u8; 32];
+        let encrypted = encrypt_value(&key, "postgres://user:secret@db/app");
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(
+            decrypt_value(&key, &encrypted).unwrap(),
+            "postgres://user:secret@db/app"
+        );
+
+        // The wrong key is rejected rather than producing garbage
+        assert!(decrypt_value(&[4u8; 32], &encrypted).is_err());
+
+        let mut yaml: serde_yaml::Value =
+            serde_yaml::from_str(&format!("databases:\n  url: \"{}\"\n", encrypted)).unwrap();
+        std::env::set_var(KEY_ENV, STANDARD.encode(key));
+        decrypt_yaml_values(&mut yaml).unwrap();
+        assert_eq!(yaml["databases"]["url"], "postgres://user:secret@db/app");
+    }
+}
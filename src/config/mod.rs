@@ -0,0 +1,1621 @@
+use crate::policy::condition::ApplyIf;
+use crate::policy::denial::DenialBodies;
+use crate::policy::failure::FailureMode;
+use crate::policy::logging::LogLevel;
+use crate::policy::schedule::{Schedule, Timestamp};
+use once_cell::sync::OnceCell;
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+use std::fmt;
+use std::{collections::HashMap, env, fs, path::Path};
+
+pub mod builder;
+pub mod encryption;
+pub mod presets;
+pub mod secret;
+pub use builder::ConfigBuilder;
+pub use secret::SecretString;
+
+// Custom deserializer for strings that might contain environment variable references
+fn deserialize_env_var<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct StringVisitor;
+
+    impl Visitor<'_> for StringVisitor {
+        type Value = String;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a string or an environment variable reference")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            if let Some(env_var) = value.strip_prefix("ENV.") {
+                match env::var(env_var) {
+                    Ok(val) => Ok(val),
+                    Err(_) => {
+                        // Return the original value if the environment variable isn't set
+                        // This allows for fallback behavior
+                        Ok(value.to_string())
+                    }
+                }
+            } else {
+                Ok(value.to_string())
+            }
+        }
+    }
+
+    deserializer.deserialize_str(StringVisitor)
+}
+
+// Custom deserializer for optional strings that might contain environment variable references
+fn deserialize_optional_env_var<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    // First deserialize to an Option<String>
+    Option::<String>::deserialize(deserializer).map(|opt_string| {
+        opt_string.map(|s| {
+            // Process environment variables if present
+            if let Some(env_var) = s.strip_prefix("ENV.") {
+                env::var(env_var).unwrap_or(s)
+            } else {
+                s
+            }
+        })
+    })
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum DatabaseType {
+    Redis,
+    Postgres,
+    Mysql,
+    Mongo,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RedisConfig {
+    pub connection_url: SecretString,
+    #[serde(default)]
+    pub password: Option<SecretString>,
+    pub database: Option<u16>,
+    pub timeout: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct PostgresConfig {
+    pub connection_url: SecretString,
+    #[serde(deserialize_with = "deserialize_optional_env_var", default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<SecretString>,
+    #[serde(deserialize_with = "deserialize_optional_env_var", default)]
+    pub database: Option<String>,
+    pub connection_pool_size: Option<u32>,
+    pub ssl: Option<bool>,
+    /// Prepared statements cached per connection (defaults to 100)
+    pub statement_cache_capacity: Option<usize>,
+    /// Log queries that take longer than this many milliseconds
+    pub slow_query_ms: Option<u64>,
+    /// Read replica connection URLs. Authentication lookups are served from
+    /// healthy replicas, falling back to the primary
+    #[serde(default)]
+    pub replicas: Vec<SecretString>,
+    /// Replicas lagging further behind than this stop serving reads (default 30)
+    pub max_replica_lag_secs: Option<u64>,
+    /// Seconds between replica lag checks (default 10)
+    pub replica_check_interval_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MySqlConfig {
+    pub connection_url: SecretString,
+    #[serde(deserialize_with = "deserialize_optional_env_var", default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<SecretString>,
+    #[serde(deserialize_with = "deserialize_optional_env_var", default)]
+    pub database: Option<String>,
+    pub connection_pool_size: Option<u32>,
+    pub ssl: Option<bool>,
+    /// Prepared statements cached per connection (defaults to 100)
+    pub statement_cache_capacity: Option<usize>,
+    /// Log queries that take longer than this many milliseconds
+    pub slow_query_ms: Option<u64>,
+    /// Read replica connection URLs. Authentication lookups are served from
+    /// healthy replicas, falling back to the primary
+    #[serde(default)]
+    pub replicas: Vec<SecretString>,
+    /// Replicas lagging further behind than this stop serving reads (default 30)
+    pub max_replica_lag_secs: Option<u64>,
+    /// Seconds between replica lag checks (default 10)
+    pub replica_check_interval_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MongoConfig {
+    pub connection_uri: SecretString,
+    #[serde(deserialize_with = "deserialize_env_var")]
+    pub database: String,
+    pub options: Option<HashMap<String, serde_json::Value>>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DatabasesConfig {
+    pub redis: Option<RedisConfig>,
+    pub postgres: Option<PostgresConfig>,
+    pub mysql: Option<MySqlConfig>,
+    pub mongo: Option<MongoConfig>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PolicyConfig {
+    pub id: String,
+    pub provider: String,
+    pub parameters: serde_json::Value,
+    /// The policy only runs from this time on
+    #[serde(default)]
+    pub effective_from: Option<Timestamp>,
+    /// The policy stops running at this time
+    #[serde(default)]
+    pub effective_until: Option<Timestamp>,
+    /// Most verbose level the policy logs at, instead of `policy_log_level`
+    #[serde(default)]
+    pub log_level: Option<LogLevel>,
+    /// Only run the policy on matching requests
+    #[serde(default)]
+    pub apply_if: Option<ApplyIf>,
+    /// Whether requests continue when the policy fails, e.g. because its
+    /// database is down
+    #[serde(default)]
+    pub failure_mode: FailureMode,
+    /// Run the policy concurrently with the policies next to it in the same group
+    #[serde(default)]
+    pub group: Option<String>,
+}
+
+impl PolicyConfig {
+    pub fn schedule(&self) -> Schedule {
+        Schedule {
+            from: self.effective_from,
+            until: self.effective_until,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheBackend {
+    #[default]
+    Memory,
+    Redis,
+    Memcached,
+}
+
+/// Backend for the cache store shared by policies
+#[derive(Deserialize, Debug, Clone)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub backend: CacheBackend,
+    /// Size limits for the memory backend
+    #[serde(default)]
+    pub limits: crate::cache::CacheLimits,
+    /// Prefix for keys written to Redis or memcached
+    #[serde(default = "default_cache_key_prefix")]
+    pub key_prefix: String,
+    /// Server URL for the memcached backend, e.g. `memcache://127.0.0.1:11211`
+    #[serde(default)]
+    pub memcached_url: Option<SecretString>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            backend: CacheBackend::default(),
+            limits: crate::cache::CacheLimits::default(),
+            key_prefix: default_cache_key_prefix(),
+            memcached_url: None,
+        }
+    }
+}
+
+fn default_cache_key_prefix() -> String {
+    "bouncer:cache:".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct PluginsConfig {
+    /// Directory containing plugin libraries and their `manifest.yaml`
+    #[serde(default = "default_plugins_directory")]
+    pub directory: String,
+    /// Plugin names that may be loaded. When empty, every plugin in the manifest is allowed
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// Plugin names that must never be loaded
+    #[serde(default)]
+    pub deny: Vec<String>,
+    /// Base64-encoded Ed25519 public keys trusted to sign plugins
+    #[serde(default)]
+    pub trusted_keys: Vec<String>,
+    /// Refuse to load plugins without a valid signature
+    #[serde(default)]
+    pub require_signature: bool,
+    /// Watch the manifest and rebuild the policy chain when plugins are updated
+    #[serde(default)]
+    pub hot_reload: bool,
+    /// How often to check the manifest for changes when `hot_reload` is enabled
+    #[serde(default = "default_plugin_reload_interval")]
+    pub reload_interval_secs: u64,
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        Self {
+            directory: default_plugins_directory(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+            trusted_keys: Vec::new(),
+            require_signature: false,
+            hot_reload: false,
+            reload_interval_secs: default_plugin_reload_interval(),
+        }
+    }
+}
+
+fn default_plugins_directory() -> String {
+    "plugins".to_string()
+}
+
+fn default_plugin_reload_interval() -> u64 {
+    5
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    #[serde(default)]
+    pub policies: Vec<PolicyConfig>,
+    #[serde(default)]
+    pub databases: DatabasesConfig,
+    #[serde(default)]
+    pub plugins: PluginsConfig,
+    #[serde(default)]
+    pub cache: CacheConfig,
+    #[serde(default)]
+    pub labels: Vec<RouteLabelConfig>,
+    /// Route patterns that skip the policy chain, such as health checks and static assets
+    #[serde(default)]
+    pub bypass: Vec<String>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+    #[serde(default)]
+    pub staging: Option<StagingConfig>,
+    /// Per-identity usage records for billing
+    #[serde(default)]
+    pub metering: Option<MeteringConfig>,
+    /// Receive internal events and deliver them to consumer webhooks
+    #[serde(default)]
+    pub fanout: Option<FanoutConfig>,
+    /// Most verbose level policies log at, unless they set their own `log_level`
+    #[serde(default)]
+    pub policy_log_level: Option<LogLevel>,
+    // Specify bouncer version compatibility (required)
+    pub bouncer_version: String,
+    // This will catch all other fields that don't match the above
+    #[serde(flatten)]
+    pub policy_configs: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ServerConfig {
+    #[serde(default = "default_bind_address")]
+    #[serde(deserialize_with = "deserialize_env_var")]
+    pub bind_address: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    /// Optional destination address to forward requests to after middleware processing.
+    /// Can be a full URL like "http://api.example.com" or a local address like "http://localhost:3000"
+    #[serde(default)]
+    #[serde(deserialize_with = "deserialize_optional_env_var")]
+    pub destination_address: Option<String>,
+    #[serde(default)]
+    pub protected_headers: ProtectedHeadersConfig,
+    /// Token required by the policy reload routes under `/_admin/policies` and
+    /// the session routes under `/_admin/sessions`, which are disabled without it
+    #[serde(default)]
+    pub admin_token: Option<SecretString>,
+    /// Run consecutive read-only policies, such as RBAC and rate limits,
+    /// concurrently instead of one after another
+    #[serde(default)]
+    pub parallel_policies: bool,
+    /// Time allowed for each request, across the policy chain and the upstream
+    /// call. Requests that run out get a 504
+    #[serde(default)]
+    pub request_timeout_ms: Option<u64>,
+    /// Let clients set a shorter deadline with `X-Request-Timeout` or `grpc-timeout`
+    #[serde(default = "default_client_timeouts")]
+    pub client_timeouts: bool,
+    /// Largest request body accepted, in bytes. Larger requests get a 413
+    /// before any policy runs. Unlimited if unset
+    #[serde(default)]
+    pub max_body_size: Option<usize>,
+    /// Routes with their own `max_body_size`, such as upload endpoints. The
+    /// first matching entry wins
+    #[serde(default)]
+    pub max_body_size_routes: Vec<BodySizeRouteConfig>,
+    /// Most bytes of a request body read for policies that inspect it. The rest
+    /// of the body is streamed to the upstream without being buffered
+    #[serde(default = "default_max_body_inspection_bytes")]
+    pub max_body_inspection_bytes: usize,
+    /// Track requests that passed the policy chain until their response has
+    /// streamed, so they can be listed and terminated under `/_admin/sessions`
+    #[serde(default = "default_track_sessions")]
+    pub track_sessions: bool,
+    /// Terminate HTTPS on the listener instead of serving plain HTTP
+    #[serde(default)]
+    pub tls: Option<TlsConfig>,
+    /// Terminate HTTPS with certificates obtained and renewed from an ACME
+    /// CA such as Let's Encrypt, instead of the files of `tls`
+    #[serde(default)]
+    pub acme: Option<AcmeConfig>,
+    /// Client certificate and trusted CAs for HTTPS upstreams
+    #[serde(default)]
+    pub upstream_tls: Option<UpstreamTlsConfig>,
+    /// Upstreams to fail over to, in order, when `destination_address` is unhealthy
+    #[serde(default)]
+    pub failover_addresses: Vec<String>,
+    /// Probe the destination and failover addresses, and stop forwarding to
+    /// those that fail
+    #[serde(default)]
+    pub health_check: Option<HealthCheckConfig>,
+    /// Accept HTTP/2 from clients, negotiated with ALPN over TLS or with prior
+    /// knowledge over plain HTTP. Without it the listener only speaks HTTP/1.1
+    #[serde(default = "default_http2")]
+    pub http2: bool,
+    /// Speak HTTP/2 to plain HTTP upstreams without negotiating it first.
+    /// HTTPS upstreams negotiate HTTP/2 with ALPN either way
+    #[serde(default)]
+    pub upstream_http2_prior_knowledge: bool,
+    /// Timeouts and retries for requests to the upstream
+    #[serde(default)]
+    pub upstream: UpstreamConfig,
+    /// IP addresses or CIDR ranges of proxies in front of Bouncer, whose
+    /// `X-Forwarded-*` headers are believed
+    #[serde(default)]
+    pub trusted_proxies: Vec<String>,
+    /// Headers removed from, set on or added to upstream responses
+    #[serde(default)]
+    pub response_headers: ResponseHeadersConfig,
+    /// Changes to the path of requests before they're forwarded, applied in order
+    #[serde(default)]
+    pub rewrite: Vec<RewriteRuleConfig>,
+    /// Warm up policies and upstream connections before accepting traffic
+    #[serde(default)]
+    pub warm_up: Option<WarmUpConfig>,
+    /// Order of the steps requests go through, with built-in steps inserted.
+    /// Empty means `[bypass, chain, proxy]`
+    #[serde(default)]
+    pub pipeline: Vec<PipelineStep>,
+    /// Most bytes of a request body held in memory by the `decompress` and
+    /// `buffer` pipeline steps. Larger bodies get a 413
+    #[serde(default = "default_max_buffered_body_bytes")]
+    pub max_buffered_body_bytes: usize,
+    /// Serve repeated `GET` and `HEAD` requests from cached upstream responses
+    #[serde(default)]
+    pub response_cache: Option<ResponseCacheConfig>,
+    /// Responses Bouncer answers itself, without a destination or for static routes
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+    /// Let clients ask which of a list of requests the policy chain would allow
+    #[serde(default)]
+    pub preauthorize: Option<PreauthorizeConfig>,
+    /// How much of why a policy rejected a request is sent to the client
+    #[serde(default)]
+    pub denial_bodies: DenialBodies,
+    /// Denial messages in the languages clients ask for with `Accept-Language`
+    #[serde(default)]
+    pub denial_messages: DenialMessagesConfig,
+}
+
+/// Responses Bouncer answers itself instead of forwarding
+#[derive(Deserialize, Debug, Clone)]
+pub struct FallbackConfig {
+    /// Status of responses to requests when no destination is configured
+    #[serde(default = "default_fallback_status")]
+    pub status: u16,
+    /// Body of responses to requests when no destination is configured
+    #[serde(default = "default_fallback_body")]
+    pub body: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Routes answered with a static response, such as `/robots.txt`, after the
+    /// policy chain and instead of forwarding. The first matching entry wins
+    #[serde(default)]
+    pub routes: Vec<StaticRouteConfig>,
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            status: default_fallback_status(),
+            body: default_fallback_body(),
+            headers: HashMap::new(),
+            routes: Vec::new(),
+        }
+    }
+}
+
+fn default_fallback_status() -> u16 {
+    200
+}
+
+fn default_fallback_body() -> String {
+    "Hello from Bouncer!".to_string()
+}
+
+/// A static response for the requests matching a route pattern
+#[derive(Deserialize, Debug, Clone)]
+pub struct StaticRouteConfig {
+    /// Route pattern, optionally starting with methods, e.g. `GET /robots.txt`
+    pub route: String,
+    #[serde(default = "default_fallback_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// Caching upstream responses in the shared `cache` store
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResponseCacheConfig {
+    /// Route patterns whose responses are cached. Empty means every route
+    #[serde(default)]
+    pub routes: Vec<String>,
+    /// Request headers whose values are part of the cache key. Responses
+    /// varying on other headers aren't cached
+    #[serde(default = "default_response_cache_vary_headers")]
+    pub vary_headers: Vec<String>,
+    /// How long responses without `max-age` or `s-maxage` are fresh. Without
+    /// it, they aren't cached
+    #[serde(default)]
+    pub default_ttl_secs: Option<u64>,
+    /// How long stale responses with an `ETag` or `Last-Modified` are kept to
+    /// be revalidated with the upstream
+    #[serde(default = "default_response_cache_revalidate_secs")]
+    pub revalidate_secs: u64,
+    /// Larger responses, and those of unknown length, aren't cached
+    #[serde(default = "default_response_cache_max_entry_bytes")]
+    pub max_entry_bytes: usize,
+}
+
+fn default_response_cache_vary_headers() -> Vec<String> {
+    vec!["accept".to_string(), "accept-encoding".to_string()]
+}
+
+fn default_response_cache_revalidate_secs() -> u64 {
+    300
+}
+
+fn default_response_cache_max_entry_bytes() -> usize {
+    1024 * 1024
+}
+
+/// A `max_body_size` for the requests matching a route pattern
+#[derive(Deserialize, Debug, Clone)]
+pub struct BodySizeRouteConfig {
+    /// Route pattern, optionally starting with methods, e.g. `POST /uploads/*`
+    pub route: String,
+    pub max_body_size: usize,
+}
+
+/// How requests are sent to the upstream
+#[derive(Deserialize, Debug, Clone)]
+pub struct UpstreamConfig {
+    /// Time allowed to open a connection
+    #[serde(default)]
+    pub connect_timeout_ms: Option<u64>,
+    /// Time allowed for each attempt, until the response headers arrive. The
+    /// body can take longer
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Times an idempotent request is retried after a connection failure,
+    /// timeout or one of the `retry_on` statuses
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default = "default_upstream_retry_on")]
+    pub retry_on: Vec<u16>,
+    /// Wait before the first retry, doubled for each one after
+    #[serde(default = "default_upstream_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    /// Most idle connections kept open to each upstream host. Unlimited if unset
+    #[serde(default)]
+    pub pool_max_idle_per_host: Option<usize>,
+    /// How long an idle connection is kept open for reuse. 0 keeps idle
+    /// connections until the upstream closes them
+    #[serde(default = "default_upstream_pool_idle_timeout_ms")]
+    pub pool_idle_timeout_ms: u64,
+    /// Interval of TCP keepalive probes on upstream connections. Off if unset
+    #[serde(default)]
+    pub tcp_keepalive_secs: Option<u64>,
+    /// Send small writes without waiting to fill a packet (no Nagle's algorithm)
+    #[serde(default = "default_upstream_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: None,
+            timeout_ms: None,
+            retries: 0,
+            retry_on: default_upstream_retry_on(),
+            retry_backoff_ms: default_upstream_retry_backoff_ms(),
+            pool_max_idle_per_host: None,
+            pool_idle_timeout_ms: default_upstream_pool_idle_timeout_ms(),
+            tcp_keepalive_secs: None,
+            tcp_nodelay: default_upstream_tcp_nodelay(),
+        }
+    }
+}
+
+fn default_upstream_retry_on() -> Vec<u16> {
+    vec![502, 503, 504]
+}
+
+fn default_upstream_retry_backoff_ms() -> u64 {
+    100
+}
+
+fn default_upstream_pool_idle_timeout_ms() -> u64 {
+    90_000
+}
+
+fn default_upstream_tcp_nodelay() -> bool {
+    true
+}
+
+fn default_http2() -> bool {
+    true
+}
+
+/// Active health checks of the configured upstreams
+#[derive(Deserialize, Debug, Clone)]
+pub struct HealthCheckConfig {
+    #[serde(default = "default_health_check_path")]
+    pub path: String,
+    /// Status a healthy upstream answers with
+    #[serde(default = "default_health_check_status")]
+    pub expected_status: u16,
+    #[serde(default = "default_health_check_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_health_check_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Consecutive failed checks before an upstream is marked unhealthy. One
+    /// passing check marks it healthy again
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
+}
+
+fn default_health_check_path() -> String {
+    "/health".to_string()
+}
+
+fn default_health_check_status() -> u16 {
+    200
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    10
+}
+
+fn default_health_check_timeout_ms() -> u64 {
+    2000
+}
+
+fn default_unhealthy_threshold() -> u32 {
+    2
+}
+
+/// Certificate for terminating HTTPS
+#[derive(Deserialize, Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM file with the certificate chain, leaf first
+    pub cert_path: String,
+    /// PEM file with the private key
+    pub key_path: String,
+    /// PEM file with the CAs that client certificates must be issued by.
+    /// When set, clients must present a certificate
+    #[serde(default)]
+    pub client_ca_path: Option<String>,
+    /// Ask clients for a certificate without checking who issued it, for
+    /// `client_cert` policies to pin by fingerprint. Clients may still connect
+    /// without one. The listener accepts any certificate, so only routes with
+    /// a `client_cert` policy are protected
+    #[serde(default)]
+    pub pinned_client_certs: bool,
+    /// How often the files are checked for changes, so renewed certificates
+    /// are picked up without a restart
+    #[serde(default = "default_tls_reload_interval_secs")]
+    pub reload_interval_secs: u64,
+    /// Also listen for plain HTTP and redirect it to HTTPS, and tell browsers
+    /// to only use HTTPS from then on
+    #[serde(default)]
+    pub redirect_http: Option<RedirectHttpConfig>,
+}
+
+/// The plain HTTP listener of `tls.redirect_http`
+#[derive(Deserialize, Debug, Clone)]
+pub struct RedirectHttpConfig {
+    /// Port the plain HTTP listener binds, on the same address as HTTPS
+    #[serde(default = "default_redirect_http_port")]
+    pub port: u16,
+    /// `max-age` of the `Strict-Transport-Security` header set on HTTPS
+    /// responses. 0 leaves the header out
+    #[serde(default = "default_hsts_max_age_secs")]
+    pub hsts_max_age_secs: u64,
+    /// Apply HSTS to subdomains too
+    #[serde(default)]
+    pub hsts_include_subdomains: bool,
+}
+
+/// Certificates obtained from an ACME CA
+#[derive(Deserialize, Debug, Clone)]
+pub struct AcmeConfig {
+    /// Names the certificate is issued for
+    pub domains: Vec<String>,
+    /// Email addresses the CA can reach about the certificate
+    #[serde(default)]
+    pub contact: Vec<String>,
+    /// Directory of the CA. Let's Encrypt's production directory by default
+    #[serde(default = "default_acme_directory_url")]
+    pub directory_url: String,
+    /// How the CA checks that the domains point to Bouncer
+    #[serde(default)]
+    pub challenge: AcmeChallenge,
+    /// Port of the plain HTTP listener that answers `http-01` challenges and
+    /// redirects everything else to HTTPS
+    #[serde(default = "default_redirect_http_port")]
+    pub http_port: u16,
+    /// Renew the certificate once it expires in fewer days than this
+    #[serde(default = "default_acme_renew_before_days")]
+    pub renew_before_days: u64,
+    /// Where the account, certificate and pending challenges are kept
+    pub storage: AcmeStorageConfig,
+}
+
+/// ACME challenge used to prove control of the domains
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AcmeChallenge {
+    /// A token served over plain HTTP on port 80
+    #[serde(rename = "http-01")]
+    Http01,
+    /// A certificate served over TLS on port 443, so no plain HTTP listener
+    /// is needed
+    #[default]
+    #[serde(rename = "tls-alpn-01")]
+    TlsAlpn01,
+}
+
+/// Where ACME state is kept. Redis and PostgreSQL let replicas share it
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AcmeStorageConfig {
+    /// Files in a local directory, created if it doesn't exist
+    Directory { path: String },
+    /// Keys under `{key_prefix}acme:` in `databases.redis`
+    Redis {
+        #[serde(default = "default_acme_key_prefix")]
+        key_prefix: String,
+    },
+    /// The `bouncer_acme_objects` table in `databases.postgres`
+    Postgres {
+        /// Create the table on startup
+        #[serde(default = "default_run_migrations")]
+        run_migrations: bool,
+    },
+}
+
+fn default_acme_directory_url() -> String {
+    "https://acme-v02.api.letsencrypt.org/directory".to_string()
+}
+
+fn default_acme_renew_before_days() -> u64 {
+    30
+}
+
+fn default_acme_key_prefix() -> String {
+    "bouncer:".to_string()
+}
+
+/// How Bouncer connects to HTTPS upstreams
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct UpstreamTlsConfig {
+    /// PEM file with the certificate chain presented to upstreams that
+    /// require client certificates. Requires `client_key`
+    #[serde(default)]
+    pub client_cert: Option<String>,
+    /// PEM file with the PKCS#8 private key of `client_cert`
+    #[serde(default)]
+    pub client_key: Option<String>,
+    /// PEM file with CAs trusted for upstream certificates, on top of the
+    /// system's
+    #[serde(default)]
+    pub ca_bundle: Option<String>,
+    /// Accept any upstream certificate. Only for testing
+    #[serde(default)]
+    pub insecure_skip_verify: bool,
+}
+
+fn default_tls_reload_interval_secs() -> u64 {
+    30
+}
+
+fn default_redirect_http_port() -> u16 {
+    80
+}
+
+fn default_hsts_max_age_secs() -> u64 {
+    // One year, as recommended for HSTS preload lists
+    31_536_000
+}
+
+fn default_track_sessions() -> bool {
+    true
+}
+
+fn default_client_timeouts() -> bool {
+    true
+}
+
+fn default_max_body_inspection_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_max_buffered_body_bytes() -> usize {
+    10 * 1024 * 1024
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: default_bind_address(),
+            port: default_port(),
+            destination_address: None,
+            protected_headers: ProtectedHeadersConfig::default(),
+            admin_token: None,
+            parallel_policies: false,
+            request_timeout_ms: None,
+            client_timeouts: default_client_timeouts(),
+            max_body_size: None,
+            max_body_size_routes: Vec::new(),
+            max_body_inspection_bytes: default_max_body_inspection_bytes(),
+            track_sessions: default_track_sessions(),
+            tls: None,
+            acme: None,
+            upstream_tls: None,
+            failover_addresses: Vec::new(),
+            health_check: None,
+            http2: default_http2(),
+            upstream_http2_prior_knowledge: false,
+            upstream: UpstreamConfig::default(),
+            trusted_proxies: Vec::new(),
+            response_headers: ResponseHeadersConfig::default(),
+            rewrite: Vec::new(),
+            warm_up: None,
+            pipeline: Vec::new(),
+            max_buffered_body_bytes: default_max_buffered_body_bytes(),
+            response_cache: None,
+            fallback: FallbackConfig::default(),
+            preauthorize: None,
+            denial_bodies: DenialBodies::default(),
+            denial_messages: DenialMessagesConfig::default(),
+        }
+    }
+}
+
+/// When traffic waits for the warm-up to finish
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WarmUpMode {
+    /// The listener is bound once the warm-up is done
+    #[default]
+    BeforeListening,
+    /// The listener is bound right away, but `/readyz` fails until the warm-up
+    /// is done
+    BeforeReady,
+}
+
+/// Localized messages of denials, for deployments whose users don't all read
+/// English
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DenialMessagesConfig {
+    /// Locale of clients that accept none of `locales`, e.g. `en`. Without it
+    /// they get each policy's own message
+    #[serde(default)]
+    pub fallback_locale: Option<String>,
+    /// Message templates by locale, e.g. `de` or `pt-BR`, and error code, e.g.
+    /// `rate_limited`. `{{ retry_after }}` and the denial's other fields are
+    /// replaced by their values
+    #[serde(default)]
+    pub locales: HashMap<String, HashMap<String, String>>,
+}
+
+/// The batch pre-authorization endpoint, for UIs to hide actions the caller
+/// isn't allowed to take
+#[derive(Deserialize, Debug, Clone)]
+pub struct PreauthorizeConfig {
+    #[serde(default = "default_preauthorize_path")]
+    pub path: String,
+    /// Most actions checked in one request
+    #[serde(default = "default_preauthorize_max_actions")]
+    pub max_actions: usize,
+}
+
+impl Default for PreauthorizeConfig {
+    fn default() -> Self {
+        Self {
+            path: default_preauthorize_path(),
+            max_actions: default_preauthorize_max_actions(),
+        }
+    }
+}
+
+fn default_preauthorize_path() -> String {
+    "/_bouncer/authorize".to_string()
+}
+
+fn default_preauthorize_max_actions() -> usize {
+    50
+}
+
+/// Warming up policies and upstream connections before accepting traffic
+#[derive(Deserialize, Debug, Clone)]
+pub struct WarmUpConfig {
+    #[serde(default)]
+    pub mode: WarmUpMode,
+    /// Time allowed for the warm-up, after which traffic is accepted anyway
+    #[serde(default = "default_warm_up_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_warm_up_timeout_secs() -> u64 {
+    30
+}
+
+/// A step of the ingress pipeline
+///
+/// `bypass`, `chain` and `proxy` must each appear once, in that order, with
+/// `proxy` last. The built-in steps can appear at most once each: before
+/// `bypass` they apply to every request, after it only to requests that go
+/// through the chain, and after `chain` only to requests that passed it.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineStep {
+    /// Skip the steps up to `proxy` for routes in `bypass`
+    Bypass,
+    /// Run the policy chain
+    Chain,
+    /// Forward the request to the upstream
+    Proxy,
+    /// Collapse repeated slashes and resolve `.` and `..` segments in the path
+    Normalize,
+    /// Decode gzip and deflate request bodies
+    Decompress,
+    /// Read the whole request body before continuing
+    Buffer,
+}
+
+/// A change to the path of forwarded requests
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RewriteRuleConfig {
+    /// Remove a leading path, e.g. `/api/v1` turns `/api/v1/users` into
+    /// `/users`. Paths that don't start with it are left alone
+    StripPrefix(String),
+    /// Put a path in front, e.g. `/internal`
+    AddPrefix(String),
+    /// Replace the first match of a regex. `$1` or `${name}` in the
+    /// replacement insert capture groups
+    Regex {
+        pattern: String,
+        replacement: String,
+    },
+}
+
+/// Changes to the headers of upstream responses, applied in this order
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ResponseHeadersConfig {
+    /// Headers removed, e.g. `Server` or `X-Powered-By`
+    #[serde(default)]
+    pub remove: Vec<String>,
+    /// Headers set, replacing any the upstream sent
+    #[serde(default)]
+    pub set: HashMap<String, String>,
+    /// Headers added alongside any the upstream sent
+    #[serde(default)]
+    pub add: HashMap<String, String>,
+}
+
+/// Headers that clients can't set and only policies can
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct ProtectedHeadersConfig {
+    /// Protected header prefixes, in addition to `x-bouncer-`
+    #[serde(default)]
+    pub prefixes: Vec<String>,
+    /// Protected headers that are forwarded to the destination. All others are
+    /// removed once the policy chain has run
+    #[serde(default)]
+    pub forward: Vec<String>,
+}
+
+/// Stable labels for requests matching a path pattern
+///
+/// `{name}` in `path` matches one segment and `{*name}` matches the rest of the path.
+/// Any other pattern accepted by [`crate::policy::matcher::RouteMatcher`] works too.
+#[derive(Deserialize, Debug, Clone)]
+pub struct RouteLabelConfig {
+    pub path: String,
+    /// Methods the labels apply to. All methods if empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    pub name: String,
+    #[serde(default)]
+    pub service: Option<String>,
+    #[serde(default)]
+    pub team: Option<String>,
+}
+
+/// A webhook receiving batches of decision events
+#[derive(Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Event kinds to send. All kinds if empty
+    #[serde(default)]
+    pub events: Vec<crate::events::DecisionEventKind>,
+    /// Key the payloads are signed with
+    pub signing_key: crate::signing::KeyConfig,
+    /// Send a batch once it holds this many events
+    #[serde(default = "default_webhook_batch_size")]
+    pub batch_size: usize,
+    /// Send a partial batch after this many seconds
+    #[serde(default = "default_webhook_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+    /// Events waiting to be sent before new ones are dropped
+    #[serde(default = "default_webhook_queue_size")]
+    pub queue_size: usize,
+}
+
+/// Webhook delivery of events published by internal services
+#[derive(Deserialize, Debug, Clone)]
+pub struct FanoutConfig {
+    /// Token internal services publish events with
+    pub token: SecretString,
+    /// Key the deliveries are signed with
+    pub signing_key: crate::signing::KeyConfig,
+    pub consumers: Vec<FanoutConsumerConfig>,
+    /// Attempts at each delivery before it's dead-lettered
+    #[serde(default = "default_fanout_max_attempts")]
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled for each one after
+    #[serde(default = "default_fanout_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    /// Time each consumer has to respond
+    #[serde(default = "default_fanout_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Events waiting for each consumer before new ones are rejected
+    #[serde(default = "default_webhook_queue_size")]
+    pub queue_size: usize,
+    /// Dead letters kept before the oldest are dropped
+    #[serde(default = "default_fanout_dead_letter_limit")]
+    pub dead_letter_limit: usize,
+}
+
+/// A webhook receiving published events
+#[derive(Deserialize, Debug, Clone)]
+pub struct FanoutConsumerConfig {
+    pub url: String,
+    /// Event types to deliver, e.g. `order.created`, or `order.*` for every
+    /// type starting with `order.`. All types if empty
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+fn default_fanout_max_attempts() -> u32 {
+    5
+}
+
+fn default_fanout_retry_backoff_ms() -> u64 {
+    1000
+}
+
+fn default_fanout_timeout_ms() -> u64 {
+    10_000
+}
+
+fn default_fanout_dead_letter_limit() -> usize {
+    1000
+}
+
+/// A second config whose policy chain receives a share of the traffic
+#[derive(Deserialize, Debug, Clone)]
+pub struct StagingConfig {
+    /// Config file with the staged policies. Only its policies are used
+    pub config: String,
+    /// Percentage of requests sent through the staged chain
+    #[serde(default)]
+    pub percent: u8,
+    /// Token required by the staging admin routes, which are disabled without it
+    #[serde(default)]
+    pub admin_token: Option<SecretString>,
+}
+
+/// Periodic usage records, written for every identity that made requests
+#[derive(Deserialize, Debug, Clone)]
+pub struct MeteringConfig {
+    /// Length of each usage period, after which its records are written
+    #[serde(default = "default_metering_interval_secs")]
+    pub interval_secs: u64,
+    pub sink: UsageSinkConfig,
+}
+
+/// Where usage records are written
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum UsageSinkConfig {
+    /// The `bouncer_usage_records` table in `databases.postgres`
+    Postgres {
+        /// Create the table on startup
+        #[serde(default = "default_run_migrations")]
+        run_migrations: bool,
+    },
+    /// One JSON Lines object per period, using the default AWS credential chain
+    S3 {
+        bucket: String,
+        /// Prefix for object keys, e.g. `usage/`
+        #[serde(default)]
+        prefix: String,
+        region: Option<String>,
+    },
+    /// One JSON message per record, keyed by identity
+    Kafka {
+        /// Comma-separated `host:port` list
+        #[serde(deserialize_with = "deserialize_env_var")]
+        brokers: String,
+        topic: String,
+    },
+}
+
+fn default_metering_interval_secs() -> u64 {
+    60
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+fn default_webhook_batch_size() -> usize {
+    100
+}
+
+fn default_webhook_flush_interval_secs() -> u64 {
+    5
+}
+
+fn default_webhook_queue_size() -> usize {
+    10_000
+}
+
+fn default_bind_address() -> String {
+    "127.0.0.1".to_string()
+}
+
+fn default_port() -> u16 {
+    8080
+}
+
+impl Config {
+    /// Start building a config programmatically instead of loading a YAML file
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::new()
+    }
+
+    // Generate policy configs from the flattened map
+    pub fn process_policy_configs(&mut self) -> Result<(), String> {
+        for (key, value) in self.policy_configs.iter() {
+            // Skip entries that don't look like policy identifiers
+            if !key.starts_with('@') {
+                continue;
+            }
+
+            // Scheduling keys sit next to the policy's own parameters
+            let mut parameters = value.clone();
+            let mut take_timestamp = |name: &str| -> Result<Option<Timestamp>, String> {
+                match parameters.as_object_mut().and_then(|map| map.remove(name)) {
+                    Some(value) => serde_json::from_value(value)
+                        .map(Some)
+                        .map_err(|e| format!("Policy {}: invalid {}: {}", key, name, e)),
+                    None => Ok(None),
+                }
+            };
+            let effective_from = take_timestamp("effective_from")?;
+            let effective_until = take_timestamp("effective_until")?;
+            let log_level = match parameters
+                .as_object_mut()
+                .and_then(|map| map.remove("log_level"))
+            {
+                Some(value) => Some(
+                    serde_json::from_value(value)
+                        .map_err(|e| format!("Policy {}: invalid log_level: {}", key, e))?,
+                ),
+                None => None,
+            };
+            let apply_if = match parameters
+                .as_object_mut()
+                .and_then(|map| map.remove("apply_if"))
+            {
+                Some(value) => Some(
+                    serde_json::from_value(value)
+                        .map_err(|e| format!("Policy {}: invalid apply_if: {}", key, e))?,
+                ),
+                None => None,
+            };
+            let failure_mode = match parameters
+                .as_object_mut()
+                .and_then(|map| map.remove("failure_mode"))
+            {
+                Some(value) => serde_json::from_value(value)
+                    .map_err(|e| format!("Policy {}: invalid failure_mode: {}", key, e))?,
+                None => FailureMode::default(),
+            };
+            let group = match parameters
+                .as_object_mut()
+                .and_then(|map| map.remove("group"))
+            {
+                Some(value) => Some(
+                    serde_json::from_value(value)
+                        .map_err(|e| format!("Policy {}: invalid group: {}", key, e))?,
+                ),
+                None => None,
+            };
+
+            self.policies.push(PolicyConfig {
+                id: key.clone(),
+                provider: key.clone(), // The provider is the same as the key in this new format
+                parameters,
+                effective_from,
+                effective_until,
+                log_level,
+                apply_if,
+                failure_mode,
+                group,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Construct the bind address string with port
+    pub fn full_bind_address(&self) -> String {
+        format!("{}:{}", self.server.bind_address, self.server.port)
+    }
+}
+
+// Function to process environment variables in serde_json::Value
+fn process_env_vars(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) => {
+            if let Some(env_var) = s.strip_prefix("ENV.") {
+                if let Ok(val) = env::var(env_var) {
+                    *s = val;
+                }
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (_, v) in map.iter_mut() {
+                process_env_vars(v);
+            }
+        }
+        serde_json::Value::Array(arr) => {
+            for v in arr.iter_mut() {
+                process_env_vars(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config, String> {
+    let mut configs = load_configs(path)?;
+    if configs.len() != 1 {
+        return Err(format!(
+            "Config file defines {} instances; use load_configs to load all of them",
+            configs.len()
+        ));
+    }
+
+    Ok(configs.remove(0))
+}
+
+/// Load the instance of a config file bound to `bind_address`, or its only one
+pub fn load_instance<P: AsRef<Path>>(path: P, bind_address: &str) -> Result<Config, String> {
+    select_instance(load_configs(path)?, bind_address)
+}
+
+fn select_instance(mut configs: Vec<Config>, bind_address: &str) -> Result<Config, String> {
+    if configs.len() == 1 {
+        return Ok(configs.remove(0));
+    }
+    configs
+        .into_iter()
+        .find(|config| config.full_bind_address() == bind_address)
+        .ok_or_else(|| format!("No instance is bound to {}", bind_address))
+}
+
+/// Load every gateway instance described by a config file
+///
+/// A config file either describes a single gateway, or contains a top-level
+/// `instances:` list where each entry has its own `server` section and policies.
+/// `bouncer_version`, `databases` and `cache` are set once at the top level and
+/// shared by all instances.
+///
+/// The profile from [`set_profile`] or `BOUNCER_PROFILE` is merged over the
+/// rest of the file, if the file has a `profiles:` section.
+pub fn load_configs<P: AsRef<Path>>(path: P) -> Result<Vec<Config>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed to read file: {}", e))?;
+    parse_configs(&content, active_profile().as_deref())
+}
+
+// Profile selected with `--profile`, which takes precedence over BOUNCER_PROFILE
+static PROFILE: OnceCell<String> = OnceCell::new();
+
+/// Select the profile applied to every config file loaded from now on
+///
+/// Only the first call has an effect. Without it, the profile is read from
+/// the `BOUNCER_PROFILE` environment variable.
+pub fn set_profile(profile: &str) {
+    let _ = PROFILE.set(profile.to_string());
+}
+
+/// The profile applied to config files, if one is selected
+pub fn active_profile() -> Option<String> {
+    PROFILE
+        .get()
+        .cloned()
+        .or_else(|| env::var("BOUNCER_PROFILE").ok())
+        .filter(|profile| !profile.is_empty())
+}
+
+// Merge `overlay` into `base`. Mappings are merged key by key, and anything
+// else, lists included, replaces the value in `base`
+fn merge_yaml(base: &mut serde_yaml::Value, overlay: serde_yaml::Value) {
+    match (base, overlay) {
+        (serde_yaml::Value::Mapping(base), serde_yaml::Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_yaml(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+// Take the `profiles:` section out of the file and merge the selected profile
+// over the rest. Files without profiles are used as they are, whatever the
+// selected profile
+fn apply_profile(map: &mut serde_yaml::Mapping, profile: Option<&str>) -> Result<(), String> {
+    let profiles = match map.remove("profiles") {
+        None => return Ok(()),
+        Some(serde_yaml::Value::Mapping(profiles)) => profiles,
+        Some(_) => return Err("'profiles' must map profile names to config overlays".to_string()),
+    };
+    let Some(profile) = profile else {
+        return Ok(());
+    };
+
+    let overlay = match profiles.get(profile) {
+        Some(overlay @ serde_yaml::Value::Mapping(_)) => overlay.clone(),
+        Some(serde_yaml::Value::Null) => return Ok(()),
+        Some(_) => return Err(format!("Profile '{}' must be a YAML mapping", profile)),
+        None => {
+            let names: Vec<&str> = profiles.keys().filter_map(|key| key.as_str()).collect();
+            return Err(format!(
+                "Unknown profile '{}'. The config defines: {}",
+                profile,
+                names.join(", ")
+            ));
+        }
+    };
+
+    let mut merged = serde_yaml::Value::Mapping(std::mem::take(map));
+    merge_yaml(&mut merged, overlay);
+    if let serde_yaml::Value::Mapping(merged) = merged {
+        *map = merged;
+    }
+    Ok(())
+}
+
+// Top-level fields that are shared by every entry of an `instances:` list
+const SHARED_INSTANCE_FIELDS: [&str; 3] = ["bouncer_version", "databases", "cache"];
+
+fn parse_configs(content: &str, profile: Option<&str>) -> Result<Vec<Config>, String> {
+    // First parse to Value to allow processing environment variables
+    let mut yaml_value: serde_yaml::Value =
+        serde_yaml::from_str(content).map_err(|e| format!("Failed to parse YAML: {}", e))?;
+
+    // Process environment variables in the parsed YAML
+    process_yaml_env_vars(&mut yaml_value);
+
+    // Decrypt ENC[...] values, including any that came from environment variables
+    encryption::decrypt_yaml_values(&mut yaml_value)?;
+
+    let mut yaml_map = match yaml_value {
+        serde_yaml::Value::Mapping(map) => map,
+        _ => return Err("Config file must contain a YAML mapping".to_string()),
+    };
+
+    apply_profile(&mut yaml_map, profile)?;
+
+    // Check if bouncer_version field is present
+    if !yaml_map.contains_key("bouncer_version") {
+        return Err("Missing required field 'bouncer_version'. Please specify a compatible version (e.g., '0.1.*')".to_string());
+    }
+
+    let instances = match yaml_map.remove("instances") {
+        // Classic layout: the whole file describes a single gateway
+        None => return Ok(vec![parse_instance(yaml_map)?]),
+        Some(serde_yaml::Value::Sequence(instances)) => instances,
+        Some(_) => return Err("'instances' must be a list of gateway definitions".to_string()),
+    };
+
+    if instances.is_empty() {
+        return Err("'instances' must contain at least one gateway definition".to_string());
+    }
+
+    // Everything else belongs to a specific instance
+    for key in yaml_map.keys() {
+        let key = key.as_str().unwrap_or("<non-string key>");
+        if !SHARED_INSTANCE_FIELDS.contains(&key) {
+            return Err(format!(
+                "Unexpected top-level field '{}' alongside 'instances'. Move it into an instance definition",
+                key
+            ));
+        }
+    }
+
+    instances
+        .into_iter()
+        .enumerate()
+        .map(|(index, instance)| {
+            let mut instance_map = match instance {
+                serde_yaml::Value::Mapping(map) => map,
+                _ => return Err(format!("Instance {} must be a YAML mapping", index)),
+            };
+
+            for field in SHARED_INSTANCE_FIELDS {
+                if instance_map.contains_key(field) {
+                    return Err(format!(
+                        "Instance {}: '{}' must be set at the top level and is shared by all instances",
+                        index, field
+                    ));
+                }
+            }
+
+            for (key, value) in yaml_map.iter() {
+                instance_map.insert(key.clone(), value.clone());
+            }
+
+            parse_instance(instance_map).map_err(|e| format!("Instance {}: {}", index, e))
+        })
+        .collect()
+}
+
+// Parse a single gateway definition into a Config
+fn parse_instance(map: serde_yaml::Mapping) -> Result<Config, String> {
+    let mut config: Config = serde_yaml::from_value(serde_yaml::Value::Mapping(map))
+        .map_err(|e| format!("Failed to parse YAML into Config: {}", e))?;
+
+    // Process environment variables in policy configs
+    for (_, value) in config.policy_configs.iter_mut() {
+        process_env_vars(value);
+    }
+
+    // Process the policy configs to generate the policies array
+    config.process_policy_configs()?;
+
+    Ok(config)
+}
+
+// Process environment variables in YAML values
+fn process_yaml_env_vars(value: &mut serde_yaml::Value) {
+    match value {
+        serde_yaml::Value::String(s) => {
+            if let Some(env_var) = s.strip_prefix("ENV.") {
+                if let Ok(val) = env::var(env_var) {
+                    *s = val;
+                }
+            }
+        }
+        serde_yaml::Value::Mapping(map) => {
+            for (_, v) in map.iter_mut() {
+                process_yaml_env_vars(v);
+            }
+        }
+        serde_yaml::Value::Sequence(seq) => {
+            for v in seq.iter_mut() {
+                process_yaml_env_vars(v);
+            }
+        }
+        _ => {}
+    }
+}
+
+// Check if the specified version in the config is compatible with the current version
+pub fn validate_version(config_version: &str, current_version: &str) -> Result<(), String> {
+    // Parse the current version
+    let current_parts: Vec<&str> = current_version.split('.').collect();
+    if current_parts.len() != 3 {
+        return Err(format!(
+            "Invalid current version format: {}",
+            current_version
+        ));
+    }
+
+    // Parse the config version, which may contain wildcards
+    let config_parts: Vec<&str> = config_version.split('.').collect();
+    if config_parts.len() != 3 {
+        return Err(format!("Invalid config version format: {}", config_version));
+    }
+
+    // Validate major version - must be explicitly specified
+    if config_parts[0] == "*" {
+        return Err(
+            "Wildcard major version is not allowed. Use a specific major version number."
+                .to_string(),
+        );
+    }
+
+    // Check if major versions match
+    if config_parts[0] != current_parts[0] {
+        return Err(format!(
+            "Major version mismatch: config requires {}, but current is {}",
+            config_parts[0], current_parts[0]
+        ));
+    }
+
+    // Check minor version if not wildcard
+    if config_parts[1] != "*" && config_parts[1] != current_parts[1] {
+        return Err(format!(
+            "Minor version mismatch: config requires {}.{}.*, but current is {}.{}.{}",
+            config_parts[0], config_parts[1], current_parts[0], current_parts[1], current_parts[2]
+        ));
+    }
+
+    // Check patch version if not wildcard
+    if config_parts[2] != "*" && config_parts[2] != current_parts[2] {
+        return Err(format!(
+            "Patch version mismatch: config requires {}.{}.{}, but current is {}.{}.{}",
+            config_parts[0],
+            config_parts[1],
+            config_parts[2],
+            current_parts[0],
+            current_parts[1],
+            current_parts[2]
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_validation() {
+        // Valid versions
+        assert!(validate_version("0.1.0", "0.1.0").is_ok());
+        assert!(validate_version("0.1.*", "0.1.0").is_ok());
+        assert!(validate_version("0.1.*", "0.1.5").is_ok());
+        assert!(validate_version("0.*.*", "0.1.0").is_ok());
+        assert!(validate_version("0.*.*", "0.2.0").is_ok());
+
+        // Invalid versions
+        assert!(validate_version("1.0.0", "0.1.0").is_err()); // Major version mismatch
+        assert!(validate_version("0.2.0", "0.1.0").is_err()); // Minor version mismatch
+        assert!(validate_version("0.1.1", "0.1.0").is_err()); // Patch version mismatch
+        assert!(validate_version("*.1.0", "0.1.0").is_err()); // Wildcard major not allowed
+    }
+
+    #[test]
+    fn test_multiple_instances() {
+        let configs = parse_configs(
+            r#"
+bouncer_version: 0.1.*
+instances:
+  - server:
+      port: 8001
+  - server:
+      port: 8002
+    "@bouncer/authentication/bearer/v1":
+      token: foo
+"#,
+            None,
+        )
+        .unwrap();
+
+        assert_eq!(configs.len(), 2);
+        assert_eq!(configs[0].server.port, 8001);
+        assert!(configs[0].policies.is_empty());
+        assert_eq!(configs[1].server.port, 8002);
+        assert_eq!(configs[1].policies.len(), 1);
+        assert_eq!(configs[1].bouncer_version, "0.1.*");
+
+        let bind_address = configs[1].full_bind_address();
+        let selected = select_instance(configs.clone(), &bind_address).unwrap();
+        assert_eq!(selected.server.port, 8002);
+        assert!(select_instance(configs, "127.0.0.1:9000").is_err());
+
+        // Per-instance fields are not allowed at the top level
+        assert!(parse_configs(
+            r#"
+bouncer_version: 0.1.*
+server:
+  port: 8000
+instances:
+  - server:
+      port: 8001
+"#,
+            None,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_profiles() {
+        let content = r#"
+bouncer_version: 0.1.*
+server:
+  port: 8000
+  destination_address: http://localhost:3000
+"@bouncer/authentication/bearer/v1":
+  token: dev-token
+profiles:
+  dev:
+  prod:
+    server:
+      destination_address: http://api.internal
+    "@bouncer/authentication/bearer/v1":
+      token: prod-token
+"#;
+
+        let base = parse_configs(content, None).unwrap().remove(0);
+        assert_eq!(
+            base.server.destination_address.as_deref(),
+            Some("http://localhost:3000")
+        );
+
+        let prod = parse_configs(content, Some("prod")).unwrap().remove(0);
+        assert_eq!(prod.server.port, 8000);
+        assert_eq!(
+            prod.server.destination_address.as_deref(),
+            Some("http://api.internal")
+        );
+        assert_eq!(prod.policies[0].parameters["token"], "prod-token");
+
+        let dev = parse_configs(content, Some("dev")).unwrap().remove(0);
+        assert_eq!(dev.policies[0].parameters["token"], "dev-token");
+
+        assert!(parse_configs(content, Some("staging")).is_err());
+    }
+}
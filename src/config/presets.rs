@@ -0,0 +1,251 @@
+use crate::policy::providers::bouncer::authentication::{bearer, jwt};
+use crate::policy::providers::bouncer::authorization::rbac;
+use crate::policy::providers::bouncer::traffic::rate_limit;
+use crate::policy::traits::PolicyFactory;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Starting point for a config written by `bouncer init`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    /// Static bearer token authentication and a per-IP rate limit
+    ApiGateway,
+    /// Access tokens from an identity provider, checked as JWTs, with RBAC
+    OauthProxy,
+    /// Only a per-IP rate limit
+    RateLimitOnly,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "api-gateway" => Ok(Self::ApiGateway),
+            "oauth-proxy" => Ok(Self::OauthProxy),
+            "rate-limit-only" => Ok(Self::RateLimitOnly),
+            other => Err(format!(
+                "Unknown preset '{}', expected 'api-gateway', 'oauth-proxy' or 'rate-limit-only'",
+                other
+            )),
+        }
+    }
+}
+
+// A policy of a preset, with the comment written above it
+struct PresetPolicy {
+    id: &'static str,
+    provider: &'static str,
+    comment: &'static str,
+    parameters: Map<String, Value>,
+}
+
+// Round trip the placeholders through the policy's config struct, so every
+// field it has is written out with its default, and placeholders that no
+// longer fit the struct fail here instead of in the generated file
+fn policy<F>(
+    id: &'static str,
+    comment: &'static str,
+    placeholders: Value,
+) -> Result<PresetPolicy, String>
+where
+    F: PolicyFactory,
+    F::Config: Serialize,
+{
+    let provider = F::policy_id();
+    let config: F::Config = serde_json::from_value(placeholders.clone())
+        .map_err(|e| format!("Preset parameters for {} are invalid: {}", provider, e))?;
+    match serde_json::to_value(&config) {
+        Ok(Value::Object(mut parameters)) => {
+            // Secrets come back masked, and `ENV.` references as the value
+            // they were read as, so string placeholders are written as given
+            if let Value::Object(placeholders) = placeholders {
+                for (name, placeholder) in placeholders {
+                    if placeholder.is_string() {
+                        parameters.insert(name, placeholder);
+                    }
+                }
+            }
+            Ok(PresetPolicy {
+                id,
+                provider,
+                comment,
+                parameters,
+            })
+        }
+        Ok(_) => Err(format!("Config of {} is not a map", provider)),
+        Err(e) => Err(format!("Failed to serialize config of {}: {}", provider, e)),
+    }
+}
+
+fn rate_limit() -> Result<PresetPolicy, String> {
+    policy::<rate_limit::v1::RateLimitPolicyFactory>(
+        "rate-limit",
+        "Allow each client IP 100 requests a minute. With `store: redis`, replicas\n\
+         share their counters through `databases.redis`",
+        json!({ "requests": 100, "window_secs": 60 }),
+    )
+}
+
+impl Preset {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::ApiGateway => "api-gateway",
+            Self::OauthProxy => "oauth-proxy",
+            Self::RateLimitOnly => "rate-limit-only",
+        }
+    }
+
+    fn policies(self) -> Result<Vec<PresetPolicy>, String> {
+        match self {
+            Self::ApiGateway => Ok(vec![
+                policy::<bearer::v1::BearerAuthPolicyFactory>(
+                    "auth",
+                    "Requests must carry `Authorization: Bearer <token>`. Set `db_provider`\n\
+                     and `token_validation_query` to look tokens up in a database instead",
+                    json!({ "token": "ENV.API_TOKEN", "realm": "api" }),
+                )?,
+                rate_limit()?,
+            ]),
+            Self::OauthProxy => Ok(vec![
+                policy::<jwt::v1::JwtAuthPolicyFactory>(
+                    "auth",
+                    "Accept access tokens signed by your identity provider. The role is\n\
+                     read from `role_claim` and the owner from `owner_claim`",
+                    json!({
+                        "algorithm": "RS256",
+                        "public_key": "ENV.OAUTH_PUBLIC_KEY",
+                        "issuer": "ENV.OAUTH_ISSUER",
+                        "audience": "ENV.OAUTH_AUDIENCE",
+                        "realm": "api",
+                    }),
+                )?,
+                policy::<rbac::v1::RbacPolicyFactory>(
+                    "rbac",
+                    "Roles allowed on each route. Routes that match no pattern are denied",
+                    json!({
+                        "route_roles": {
+                            "/api/admin/**": ["admin"],
+                            "/api/**": ["user", "admin"],
+                        },
+                    }),
+                )?,
+            ]),
+            Self::RateLimitOnly => Ok(vec![rate_limit()?]),
+        }
+    }
+}
+
+// Write a `key: value` entry, indented. Unset options are written commented
+// out, so the file shows everything the policy can do
+fn write_parameter(out: &mut String, indent: &str, key: &str, value: &Value) -> Result<(), String> {
+    if value.is_null() {
+        let _ = writeln!(out, "{}# {}:", indent, key);
+        return Ok(());
+    }
+    let mut entry = Map::new();
+    entry.insert(key.to_string(), value.clone());
+    let yaml = serde_yaml::to_string(&entry).map_err(|e| e.to_string())?;
+    for line in yaml.lines() {
+        let _ = writeln!(out, "{}{}", indent, line);
+    }
+    Ok(())
+}
+
+/// Write a commented starter config for `preset`
+///
+/// Policy parameters come from the policies' own config structs, so the file
+/// lists every option with its default, and values to fill in are `ENV.`
+/// references.
+pub fn render(preset: Preset) -> Result<String, String> {
+    let (major_minor, _patch) = crate::VERSION.rsplit_once('.').unwrap_or(("0.1", ""));
+    let mut out = String::new();
+
+    let _ = writeln!(
+        out,
+        "# Generated by `bouncer init --preset {}`",
+        preset.name()
+    );
+    let _ = writeln!(
+        out,
+        "# Values written as ENV.NAME are read from the environment variable NAME."
+    );
+    let _ = writeln!(
+        out,
+        "# Check the file with `bouncer --config <file> validate` after editing it."
+    );
+    let _ = writeln!(out, "bouncer_version: \"{}.*\"", major_minor);
+    out.push_str(
+        "
+server:
+  bind_address: 0.0.0.0
+  port: 8000
+  # Where requests that pass the policy chain are forwarded
+  destination_address: ENV.DESTINATION_URL
+  # Token for the admin routes under /_admin, which are disabled without it
+  # admin_token: ENV.BOUNCER_ADMIN_TOKEN
+
+# Routes that skip the policy chain
+bypass:
+  - GET,HEAD /health
+
+# Policies run in this order
+policies:
+",
+    );
+
+    for (index, policy) in preset.policies()?.iter().enumerate() {
+        if index > 0 {
+            out.push('\n');
+        }
+        for line in policy.comment.lines() {
+            let _ = writeln!(out, "  # {}", line.trim());
+        }
+        let _ = writeln!(out, "  - id: {}", policy.id);
+        let _ = writeln!(out, "    provider: \"{}\"", policy.provider);
+        let _ = writeln!(out, "    parameters:");
+        for (key, value) in &policy.parameters {
+            write_parameter(&mut out, "      ", key, value)?;
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only used to check that the generated `public_key` is accepted
+    const TEST_PUBLIC_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAmgNJkID72TjqDOjw2OTQ
+nopKiR3WSpHOpHR/nBSQjH0Pg2N0pjCfIqQr4ehazPS1BFRnnbLbRrXcxVI8Kj9X
+gzjbPkdixGKqgJ6ZKxol2CKRfWe/pubEcoX5bp8C9Syt0PPE+oRh5Pw6wV0gyOGH
+6bAXtDoa+7bGr3XVPCr7nQuwCkYZQEIJH7uk6IOtvnm4dJxGbMmGPtKZ97oF7NzA
+uU5LO7vJDZYEgY9PzxVVSfH8YEd3sBtWr2CYEapu4Btj3sjT5yG9fhXlghfJGfUp
+Oy5L9E/z69ypWyZtWIC+lDg9UsogPEdaCHl6z8OOEuKWJdaMOSX7/yyKrKZCJ83g
+awIDAQAB
+-----END PUBLIC KEY-----";
+
+    #[test]
+    fn test_presets_are_valid() {
+        std::env::set_var("OAUTH_PUBLIC_KEY", TEST_PUBLIC_KEY);
+
+        for preset in [
+            Preset::ApiGateway,
+            Preset::OauthProxy,
+            Preset::RateLimitOnly,
+        ] {
+            let rendered = render(preset).unwrap();
+            let configs = super::super::parse_configs(&rendered, None).unwrap();
+            let registry = crate::server::create_registry(&configs[0]);
+            let errors = crate::server::validate_config(&configs[0], &registry);
+            assert!(errors.is_empty(), "{}: {:?}", preset.name(), errors);
+        }
+
+        let rendered = render(Preset::ApiGateway).unwrap();
+        assert!(rendered.contains("      token: ENV.API_TOKEN\n"));
+        assert!(rendered.contains("      # db_provider:\n"));
+    }
+}
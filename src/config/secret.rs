@@ -0,0 +1,145 @@
+use crate::diagnostics::{redact_url, MASK};
+use serde::de::{self, Deserializer, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use std::cell::Cell;
+use std::{env, fmt};
+
+/// A token, salt, password, key or connection URL from the config
+///
+/// Debug, Display and serialized output never show the value: secrets are
+/// masked, and URLs only lose their credentials so they can still be told
+/// apart in logs. Call [`SecretString::expose`] where the value itself is
+/// needed. Like other config strings, `ENV.NAME` is read from the environment.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// The secret itself, for handing to whatever needs it
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// What is shown in place of the value
+    pub fn redacted(&self) -> String {
+        if self.0.is_empty() {
+            String::new()
+        } else if self.0.contains("://") {
+            redact_url(&self.0)
+        } else {
+            MASK.to_string()
+        }
+    }
+}
+
+impl From<String> for SecretString {
+    fn from(value: String) -> Self {
+        Self(value)
+    }
+}
+
+impl From<&str> for SecretString {
+    fn from(value: &str) -> Self {
+        Self(value.to_string())
+    }
+}
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.redacted())
+    }
+}
+
+impl fmt::Display for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.redacted())
+    }
+}
+
+thread_local! {
+    static EXPOSED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` with secrets serialized as their value instead of masked, for
+/// configs that are handed on rather than shown, e.g. policy parameters
+pub fn exposed<T>(f: impl FnOnce() -> T) -> T {
+    struct Reset(bool);
+
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            EXPOSED.with(|exposed| exposed.set(self.0));
+        }
+    }
+
+    let _reset = Reset(EXPOSED.with(|exposed| exposed.replace(true)));
+    f()
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match EXPOSED.with(Cell::get) {
+            true => serializer.serialize_str(&self.0),
+            false => serializer.serialize_str(&self.redacted()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for SecretString {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SecretVisitor;
+
+        impl Visitor<'_> for SecretVisitor {
+            type Value = SecretString;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a string or an environment variable reference")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+                // Unset variables fall back to the reference itself, like other config strings
+                let value = match value.strip_prefix("ENV.") {
+                    Some(env_var) => env::var(env_var).unwrap_or_else(|_| value.to_string()),
+                    None => value.to_string(),
+                };
+                Ok(SecretString(value))
+            }
+        }
+
+        deserializer.deserialize_str(SecretVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_string_is_redacted() {
+        let password = SecretString::from("hunter2");
+        let url = SecretString::from("postgres://bouncer:hunter2@db:5432/app");
+
+        assert_eq!(password.expose(), "hunter2");
+        assert_eq!(format!("{:?}", password), format!("{:?}", MASK));
+        assert_eq!(url.to_string(), format!("postgres://{}@db:5432/app", MASK));
+        assert_eq!(
+            serde_json::to_value(Some(&password)).unwrap(),
+            serde_json::json!(MASK)
+        );
+        assert!(!format!("{:?}", Some(url)).contains("hunter2"));
+        assert_eq!(
+            exposed(|| serde_json::to_value(&password)).unwrap(),
+            serde_json::json!("hunter2")
+        );
+        assert_eq!(
+            serde_json::to_value(&password).unwrap(),
+            serde_json::json!(MASK)
+        );
+    }
+}
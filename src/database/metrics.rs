@@ -0,0 +1,142 @@
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+/// Point-in-time metrics for a database query
+#[derive(Serialize, Debug, Clone)]
+pub struct QueryStats {
+    pub name: String,
+    pub calls: u64,
+    pub errors: u64,
+    pub slow: u64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+}
+
+// Every live query, so metrics can be reported from one place
+static QUERIES: Lazy<Mutex<Vec<Weak<QueryMetrics>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Metrics for every query that is still in use
+pub fn all_query_stats() -> Vec<QueryStats> {
+    let mut queries = QUERIES.lock().unwrap();
+    queries.retain(|query| query.strong_count() > 0);
+    queries
+        .iter()
+        .filter_map(|query| query.upgrade())
+        .map(|query| query.stats())
+        .collect()
+}
+
+/// Latency and error counters for one query, with optional slow query logging
+pub struct QueryMetrics {
+    name: String,
+    slow_threshold: Option<Duration>,
+    calls: AtomicU64,
+    errors: AtomicU64,
+    slow: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl QueryMetrics {
+    /// Create metrics for a query and register them for reporting
+    pub fn new(name: impl Into<String>, slow_threshold: Option<Duration>) -> Arc<Self> {
+        let metrics = Arc::new(Self {
+            name: name.into(),
+            slow_threshold,
+            calls: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            slow: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        });
+
+        QUERIES.lock().unwrap().push(Arc::downgrade(&metrics));
+        metrics
+    }
+
+    /// Run a query and record how long it took and whether it failed
+    pub async fn observe<T, E, F>(&self, query: F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let started = Instant::now();
+        let result = query.await;
+        self.record(started.elapsed(), result.is_ok());
+        result
+    }
+
+    pub fn record(&self, elapsed: Duration, ok: bool) {
+        let micros = elapsed.as_micros() as u64;
+        self.calls.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        if !ok {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if self
+            .slow_threshold
+            .is_some_and(|threshold| elapsed > threshold)
+        {
+            self.slow.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!("Slow query '{}' took {}ms", self.name, elapsed.as_millis());
+        }
+    }
+
+    pub fn stats(&self) -> QueryStats {
+        let calls = self.calls.load(Ordering::Relaxed);
+        let total_micros = self.total_micros.load(Ordering::Relaxed);
+        QueryStats {
+            name: self.name.clone(),
+            calls,
+            errors: self.errors.load(Ordering::Relaxed),
+            slow: self.slow.load(Ordering::Relaxed),
+            avg_ms: if calls == 0 {
+                0.0
+            } else {
+                total_micros as f64 / calls as f64 / 1000.0
+            },
+            max_ms: self.max_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_query_metrics() {
+        let metrics = QueryMetrics::new("test_lookup", Some(Duration::from_millis(20)));
+
+        let ok = metrics.observe(async { Ok::<_, String>(1) }).await;
+        assert_eq!(ok, Ok(1));
+        let failed = metrics.observe(async { Err::<(), _>("down") }).await;
+        assert_eq!(failed, Err("down"));
+        metrics
+            .observe(async {
+                tokio::time::sleep(Duration::from_millis(30)).await;
+                Ok::<_, String>(())
+            })
+            .await
+            .unwrap();
+
+        let exported = |name: &str| {
+            all_query_stats()
+                .into_iter()
+                .find(|stats| stats.name == name)
+        };
+        let stats = exported("test_lookup").unwrap();
+        assert_eq!((stats.calls, stats.errors, stats.slow), (3, 1, 1));
+        assert!(stats.max_ms >= 30.0);
+        assert!(stats.avg_ms >= 10.0 && stats.avg_ms <= stats.max_ms);
+
+        // Queries no longer in use aren't reported
+        drop(metrics);
+        assert!(exported("test_lookup").is_none());
+    }
+}
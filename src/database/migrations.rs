@@ -0,0 +1,101 @@
+#[cfg(feature = "postgres")]
+use super::DatabaseError;
+
+/// A schema change shipped with Bouncer
+///
+/// Migrations are embedded in the binary with `include_str!` and applied in
+/// version order. Versions must be unique within a set of migrations and must
+/// never be changed once released.
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+/// Apply every migration that hasn't been applied to the database yet
+///
+/// Applied versions are recorded in `bouncer_schema_migrations`. An advisory
+/// lock makes replicas starting at the same time apply each migration once.
+#[cfg(feature = "postgres")]
+pub async fn run_postgres_migrations(
+    pool: &sqlx::Pool<sqlx::Postgres>,
+    migrations: &[Migration],
+) -> Result<(), DatabaseError> {
+    use sqlx::Executor;
+
+    // Arbitrary, but fixed, advisory lock key for Bouncer migrations
+    const LOCK_KEY: i64 = 0x0062_6f75_6e63_6572;
+    const MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS bouncer_schema_migrations (
+        version BIGINT PRIMARY KEY,
+        name TEXT NOT NULL,
+        applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+    )";
+
+    let query_error = |e: sqlx::Error| DatabaseError::QueryError(e.to_string());
+    let mut conn = pool
+        .acquire()
+        .await
+        .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+    sqlx::query("SELECT pg_advisory_lock($1)")
+        .bind(LOCK_KEY)
+        .execute(&mut *conn)
+        .await
+        .map_err(query_error)?;
+
+    let result = async {
+        (&mut *conn)
+            .execute(MIGRATIONS_TABLE)
+            .await
+            .map_err(query_error)?;
+
+        let applied: Vec<i64> = sqlx::query_scalar("SELECT version FROM bouncer_schema_migrations")
+            .fetch_all(&mut *conn)
+            .await
+            .map_err(query_error)?;
+
+        let mut pending: Vec<&Migration> = migrations
+            .iter()
+            .filter(|migration| !applied.contains(&migration.version))
+            .collect();
+        pending.sort_by_key(|migration| migration.version);
+
+        for migration in pending {
+            tracing::info!(
+                "Applying migration {} ({})",
+                migration.version,
+                migration.name
+            );
+
+            // Each migration and its record are committed together
+            let mut tx = sqlx::Connection::begin(&mut *conn)
+                .await
+                .map_err(query_error)?;
+            (&mut *tx).execute(migration.sql).await.map_err(|e| {
+                DatabaseError::QueryError(format!(
+                    "Migration {} ({}) failed: {}",
+                    migration.version, migration.name, e
+                ))
+            })?;
+            sqlx::query("INSERT INTO bouncer_schema_migrations (version, name) VALUES ($1, $2)")
+                .bind(migration.version)
+                .bind(migration.name)
+                .execute(&mut *tx)
+                .await
+                .map_err(query_error)?;
+            tx.commit().await.map_err(query_error)?;
+        }
+
+        Ok(())
+    }
+    .await;
+
+    sqlx::query("SELECT pg_advisory_unlock($1)")
+        .bind(LOCK_KEY)
+        .execute(&mut *conn)
+        .await
+        .map_err(query_error)?;
+
+    result
+}
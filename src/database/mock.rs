@@ -0,0 +1,59 @@
+use crate::policy::providers::bouncer::authentication::identity::Identity;
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+
+// Installed by `bouncer simulate --mock-db` before the policy chain is built
+static MOCK_DATABASES: OnceCell<MockDatabases> = OnceCell::new();
+
+/// Canned data that database-backed lookups use instead of real databases
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct MockDatabases {
+    /// Bearer token lookups, keyed by the plain token
+    #[serde(default)]
+    pub tokens: HashMap<String, MockIdentity>,
+    /// JWT IDs on the revocation list
+    #[serde(default)]
+    pub revoked_jtis: HashSet<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct MockIdentity {
+    pub role: String,
+    #[serde(default)]
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+    #[serde(default)]
+    pub expires_at: Option<i64>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl From<&MockIdentity> for Identity {
+    fn from(mock: &MockIdentity) -> Self {
+        Identity {
+            role: mock.role.clone(),
+            owner: mock.owner.clone(),
+            scopes: mock.scopes.clone(),
+            expires_at: mock.expires_at,
+            enabled: mock.enabled,
+        }
+    }
+}
+
+/// Use mock data instead of real databases for the rest of the process
+pub fn install(mock: MockDatabases) -> Result<(), String> {
+    MOCK_DATABASES
+        .set(mock)
+        .map_err(|_| "Mock databases are already installed".to_string())
+}
+
+/// The installed mock data, if databases are mocked
+pub fn get() -> Option<&'static MockDatabases> {
+    MOCK_DATABASES.get()
+}
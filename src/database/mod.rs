@@ -2,6 +2,12 @@ use crate::config::{DatabasesConfig, MongoConfig, MySqlConfig, PostgresConfig, R
 use std::sync::Arc;
 
 pub mod errors;
+pub mod metrics;
+pub mod migrations;
+pub mod mock;
+#[cfg(feature = "sqlx")]
+pub mod replicas;
+pub mod sql;
 pub use errors::DatabaseError;
 
 // Helper functions for getting database clients
@@ -18,13 +24,22 @@ pub async fn get_postgres_client(
     }
 
     tracing::debug!(
-        "Connecting to PostgreSQL database with URL pattern: {}...",
-        config.connection_url.split('@').nth(1).unwrap_or("")
+        "Connecting to PostgreSQL database at {}",
+        config.connection_url
     );
 
+    let mut options = config
+        .connection_url
+        .expose()
+        .parse::<sqlx::postgres::PgConnectOptions>()
+        .map_err(|e| DatabaseError::ConfigurationError(e.to_string()))?;
+    if let Some(capacity) = config.statement_cache_capacity {
+        options = options.statement_cache_capacity(capacity);
+    }
+
     let pool = sqlx::postgres::PgPoolOptions::new()
         .max_connections(config.connection_pool_size.unwrap_or(5))
-        .connect(&config.connection_url)
+        .connect_with(options)
         .await
         .map_err(|e| {
             tracing::error!("Failed to connect to PostgreSQL: {}", e);
@@ -41,6 +56,43 @@ pub async fn get_postgres_client(
     Ok(Arc::new(pool))
 }
 
+#[cfg(feature = "postgres")]
+/// Get the PostgreSQL primary and read replica pools from configuration
+///
+/// Replica lag is checked in the background for as long as the pools are used.
+pub async fn get_postgres_pools(
+    config: &PostgresConfig,
+) -> Result<Arc<replicas::SqlPools<sqlx::Postgres>>, DatabaseError> {
+    const LAG_QUERY: &str =
+        "SELECT COALESCE(EXTRACT(EPOCH FROM now() - pg_last_xact_replay_timestamp()), 0)::float8";
+
+    let primary = get_postgres_client(config).await?;
+    let mut replica_pools = Vec::new();
+    for url in &config.replicas {
+        let replica_config = PostgresConfig {
+            connection_url: url.clone(),
+            ..config.clone()
+        };
+        replica_pools.push((
+            url.expose().to_string(),
+            get_postgres_client(&replica_config).await?,
+        ));
+    }
+
+    let pools = Arc::new(replicas::SqlPools::new(primary, replica_pools));
+    pools.spawn_lag_checks(
+        replica_check_interval(config.replica_check_interval_secs),
+        max_replica_lag(config.max_replica_lag_secs),
+        |pool| async move {
+            sqlx::query_scalar::<_, f64>(LAG_QUERY)
+                .fetch_one(&*pool)
+                .await
+        },
+    );
+
+    Ok(pools)
+}
+
 #[cfg(not(feature = "postgres"))]
 /// Get a PostgreSQL database client when feature is not enabled
 pub async fn get_postgres_client(_config: &PostgresConfig) -> Result<Arc<()>, DatabaseError> {
@@ -60,14 +112,20 @@ pub async fn get_mysql_client(
         ));
     }
 
-    tracing::debug!(
-        "Connecting to MySQL database with URL pattern: {}...",
-        config.connection_url.split('@').nth(1).unwrap_or("")
-    );
+    tracing::debug!("Connecting to MySQL database at {}", config.connection_url);
+
+    let mut options = config
+        .connection_url
+        .expose()
+        .parse::<sqlx::mysql::MySqlConnectOptions>()
+        .map_err(|e| DatabaseError::ConfigurationError(e.to_string()))?;
+    if let Some(capacity) = config.statement_cache_capacity {
+        options = options.statement_cache_capacity(capacity);
+    }
 
     let pool = sqlx::mysql::MySqlPoolOptions::new()
         .max_connections(config.connection_pool_size.unwrap_or(5))
-        .connect(&config.connection_url)
+        .connect_with(options)
         .await
         .map_err(|e| {
             tracing::error!("Failed to connect to MySQL: {}", e);
@@ -84,6 +142,58 @@ pub async fn get_mysql_client(
     Ok(Arc::new(pool))
 }
 
+#[cfg(feature = "mysql")]
+/// Get the MySQL primary and read replica pools from configuration
+///
+/// Replica lag is checked in the background for as long as the pools are used.
+pub async fn get_mysql_pools(
+    config: &MySqlConfig,
+) -> Result<Arc<replicas::SqlPools<sqlx::MySql>>, DatabaseError> {
+    // Delay between commit on the source and apply on this replica, in seconds
+    const LAG_QUERY: &str = "SELECT CAST(COALESCE(MAX(TIMESTAMPDIFF(MICROSECOND, \
+        LAST_APPLIED_TRANSACTION_ORIGINAL_COMMIT_TIMESTAMP, \
+        LAST_APPLIED_TRANSACTION_END_APPLY_TIMESTAMP)), 0) / 1000000 AS DOUBLE) \
+        FROM performance_schema.replication_applier_status_by_worker";
+
+    let primary = get_mysql_client(config).await?;
+    let mut replica_pools = Vec::new();
+    for url in &config.replicas {
+        let replica_config = MySqlConfig {
+            connection_url: url.clone(),
+            ..config.clone()
+        };
+        replica_pools.push((
+            url.expose().to_string(),
+            get_mysql_client(&replica_config).await?,
+        ));
+    }
+
+    let pools = Arc::new(replicas::SqlPools::new(primary, replica_pools));
+    pools.spawn_lag_checks(
+        replica_check_interval(config.replica_check_interval_secs),
+        max_replica_lag(config.max_replica_lag_secs),
+        |pool| async move {
+            sqlx::query_scalar::<_, f64>(LAG_QUERY)
+                .fetch_one(&*pool)
+                .await
+        },
+    );
+
+    Ok(pools)
+}
+
+#[cfg(feature = "sqlx")]
+fn replica_check_interval(secs: Option<u64>) -> std::time::Duration {
+    secs.map(std::time::Duration::from_secs)
+        .unwrap_or(replicas::DEFAULT_REPLICA_CHECK_INTERVAL)
+}
+
+#[cfg(feature = "sqlx")]
+fn max_replica_lag(secs: Option<u64>) -> std::time::Duration {
+    secs.map(std::time::Duration::from_secs)
+        .unwrap_or(replicas::DEFAULT_MAX_REPLICA_LAG)
+}
+
 #[cfg(not(feature = "mysql"))]
 /// Get a MySQL database client when feature is not enabled
 pub async fn get_mysql_client(_config: &MySqlConfig) -> Result<Arc<()>, DatabaseError> {
@@ -101,7 +211,7 @@ pub async fn get_redis_client(config: &RedisConfig) -> Result<Arc<redis::Client>
         ));
     }
 
-    let client = redis::Client::open(&config.connection_url[..])
+    let client = redis::Client::open(config.connection_url.expose())
         .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
 
     // Test the connection
@@ -135,7 +245,7 @@ pub async fn get_mongo_client(config: &MongoConfig) -> Result<Arc<mongodb::Clien
         ));
     }
 
-    let client_options = mongodb::options::ClientOptions::parse(&config.connection_uri)
+    let client_options = mongodb::options::ClientOptions::parse(config.connection_uri.expose())
         .await
         .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
 
@@ -172,11 +282,12 @@ pub fn validate_database_config(
                 ));
             }
 
-            #[cfg(not(feature = "postgres"))]
-            return Err(DatabaseError::ConfigurationError(
-                "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature."
-                    .to_string(),
-            ));
+            if cfg!(not(feature = "postgres")) {
+                return Err(DatabaseError::ConfigurationError(
+                    "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature."
+                        .to_string(),
+                ));
+            }
         }
         "mysql" => {
             if config.mysql.is_none() {
@@ -185,10 +296,11 @@ pub fn validate_database_config(
                 ));
             }
 
-            #[cfg(not(feature = "mysql"))]
-            return Err(DatabaseError::ConfigurationError(
-                "MySQL support is not enabled. Rebuild with the 'mysql' feature.".to_string(),
-            ));
+            if cfg!(not(feature = "mysql")) {
+                return Err(DatabaseError::ConfigurationError(
+                    "MySQL support is not enabled. Rebuild with the 'mysql' feature.".to_string(),
+                ));
+            }
         }
         "redis" => {
             if config.redis.is_none() {
@@ -197,10 +309,11 @@ pub fn validate_database_config(
                 ));
             }
 
-            #[cfg(not(feature = "redis"))]
-            return Err(DatabaseError::ConfigurationError(
-                "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
-            ));
+            if cfg!(not(feature = "redis")) {
+                return Err(DatabaseError::ConfigurationError(
+                    "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+                ));
+            }
         }
         "mongo" => {
             if config.mongo.is_none() {
@@ -209,10 +322,11 @@ pub fn validate_database_config(
                 ));
             }
 
-            #[cfg(not(feature = "mongo"))]
-            return Err(DatabaseError::ConfigurationError(
-                "MongoDB support is not enabled. Rebuild with the 'mongo' feature.".to_string(),
-            ));
+            if cfg!(not(feature = "mongo")) {
+                return Err(DatabaseError::ConfigurationError(
+                    "MongoDB support is not enabled. Rebuild with the 'mongo' feature.".to_string(),
+                ));
+            }
         }
         _ => {
             return Err(DatabaseError::ConfigurationError(format!(
@@ -0,0 +1,314 @@
+use crate::diagnostics::redact_url;
+use serde::Serialize;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Default lag after which a replica stops serving reads
+pub const DEFAULT_MAX_REPLICA_LAG: Duration = Duration::from_secs(30);
+
+/// Default interval between replica lag checks
+pub const DEFAULT_REPLICA_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Health of a read replica as of its last lag check
+#[derive(Serialize, Debug, Clone)]
+pub struct ReplicaStatus {
+    pub url: String,
+    pub healthy: bool,
+    pub lag_secs: Option<f64>,
+    pub error: Option<String>,
+}
+
+struct Replica<DB: sqlx::Database> {
+    url: String,
+    pool: Arc<sqlx::Pool<DB>>,
+    healthy: AtomicBool,
+    status: Mutex<ReplicaStatus>,
+}
+
+/// A primary connection pool with optional read replicas
+///
+/// Writes always go to the primary. Reads are spread over healthy replicas and
+/// fail over to the primary when no replica is healthy or a replica query fails.
+pub struct SqlPools<DB: sqlx::Database> {
+    primary: Arc<sqlx::Pool<DB>>,
+    replicas: Vec<Replica<DB>>,
+    next: AtomicUsize,
+}
+
+impl<DB: sqlx::Database> SqlPools<DB> {
+    pub fn new(primary: Arc<sqlx::Pool<DB>>, replicas: Vec<(String, Arc<sqlx::Pool<DB>>)>) -> Self {
+        let replicas = replicas
+            .into_iter()
+            .map(|(url, pool)| Replica {
+                status: Mutex::new(ReplicaStatus {
+                    url: redact_url(&url),
+                    healthy: true,
+                    lag_secs: None,
+                    error: None,
+                }),
+                url: redact_url(&url),
+                pool,
+                healthy: AtomicBool::new(true),
+            })
+            .collect();
+
+        Self {
+            primary,
+            replicas,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Pool for writes and reads that must see the latest data
+    pub fn primary(&self) -> Arc<sqlx::Pool<DB>> {
+        self.primary.clone()
+    }
+
+    // Pick the next healthy replica, round-robin
+    fn replica(&self) -> Option<&Replica<DB>> {
+        let count = self.replicas.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        (0..count)
+            .map(|offset| &self.replicas[(start + offset) % count])
+            .find(|replica| replica.healthy.load(Ordering::Relaxed))
+    }
+
+    /// Run a read on a healthy replica, falling back to the primary
+    ///
+    /// A replica whose query fails is marked unhealthy until its next
+    /// successful lag check.
+    pub async fn read<T, E, F, Fut>(&self, query: F) -> Result<T, E>
+    where
+        E: std::fmt::Display,
+        F: Fn(Arc<sqlx::Pool<DB>>) -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        if let Some(replica) = self.replica() {
+            match query(replica.pool.clone()).await {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    tracing::warn!(
+                        "Read from replica {} failed, using primary: {}",
+                        replica.url,
+                        e
+                    );
+                    replica.healthy.store(false, Ordering::Relaxed);
+                }
+            }
+        }
+
+        query(self.primary.clone()).await
+    }
+
+    /// Health of every replica as of its last check
+    pub fn replica_status(&self) -> Vec<ReplicaStatus> {
+        self.replicas
+            .iter()
+            .map(|replica| replica.status.lock().unwrap().clone())
+            .collect()
+    }
+
+    /// Periodically measure replica lag, taking replicas that fall behind by
+    /// more than `max_lag` (or can't be reached) out of rotation
+    ///
+    /// `lag` runs the database-specific lag query and returns seconds behind.
+    pub fn spawn_lag_checks<F, Fut>(self: &Arc<Self>, interval: Duration, max_lag: Duration, lag: F)
+    where
+        F: Fn(Arc<sqlx::Pool<DB>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<f64, sqlx::Error>> + Send,
+    {
+        if self.replicas.is_empty() {
+            return;
+        }
+
+        // Hold a weak reference so the task stops once the pools are dropped
+        let pools = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(pools) = pools.upgrade() else {
+                    break;
+                };
+
+                for replica in &pools.replicas {
+                    let (healthy, lag_secs, error) = match lag(replica.pool.clone()).await {
+                        Ok(lag_secs) if lag_secs <= max_lag.as_secs_f64() => {
+                            (true, Some(lag_secs), None)
+                        }
+                        Ok(lag_secs) => (
+                            false,
+                            Some(lag_secs),
+                            Some(format!("lagging {:.1}s behind the primary", lag_secs)),
+                        ),
+                        Err(e) => (false, None, Some(e.to_string())),
+                    };
+
+                    let was_healthy = replica.healthy.swap(healthy, Ordering::Relaxed);
+                    match (&error, was_healthy) {
+                        (Some(error), true) => tracing::warn!(
+                            "Replica {} removed from rotation: {}",
+                            replica.url,
+                            error
+                        ),
+                        (None, false) => {
+                            tracing::info!("Replica {} returned to rotation", replica.url)
+                        }
+                        _ => {}
+                    }
+
+                    *replica.status.lock().unwrap() = ReplicaStatus {
+                        url: replica.url.clone(),
+                        healthy,
+                        lag_secs,
+                        error,
+                    };
+                }
+            }
+        });
+    }
+}
+
+#[cfg(all(test, feature = "postgres"))]
+mod tests {
+    use super::*;
+    use sqlx::postgres::{PgPool, PgPoolOptions};
+
+    // Pools that never connect, told apart by identity
+    fn pool(name: &str) -> Arc<PgPool> {
+        let url = format!("postgres://localhost/{}", name);
+        Arc::new(PgPoolOptions::new().connect_lazy(&url).unwrap())
+    }
+
+    // Which pool a read ran on
+    async fn read_from(pools: &SqlPools<sqlx::Postgres>) -> Arc<PgPool> {
+        pools
+            .read(|pool| async move { Ok::<_, String>(pool) })
+            .await
+            .unwrap()
+    }
+
+    // Each replica's lag, or `None` when it can't be reached
+    type Lags = Arc<Mutex<Vec<(Arc<PgPool>, Option<f64>)>>>;
+
+    // Run lag checks every few milliseconds, each replica reporting the lag
+    // set for it in `lags`, or failing when it's `None`
+    fn check_lag(
+        pools: &Arc<SqlPools<sqlx::Postgres>>,
+        lags: Vec<(Arc<PgPool>, Option<f64>)>,
+    ) -> Lags {
+        let lags: Lags = Arc::new(Mutex::new(lags));
+        let checked = Arc::clone(&lags);
+        pools.spawn_lag_checks(
+            Duration::from_millis(5),
+            DEFAULT_MAX_REPLICA_LAG,
+            move |pool| {
+                let lag = checked
+                    .lock()
+                    .unwrap()
+                    .iter()
+                    .find(|(replica, _)| Arc::ptr_eq(replica, &pool))
+                    .and_then(|(_, lag)| *lag);
+                async move { lag.ok_or(sqlx::Error::PoolTimedOut) }
+            },
+        );
+        lags
+    }
+
+    async fn wait_for(pools: &SqlPools<sqlx::Postgres>, healthy: &[bool]) {
+        for _ in 0..200 {
+            let status = pools.replica_status();
+            if status
+                .iter()
+                .map(|status| status.healthy)
+                .eq(healthy.iter().copied())
+            {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        panic!(
+            "replicas never became {:?}: {:?}",
+            healthy,
+            pools.replica_status()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lagging_replica() {
+        let (primary, lagging, current) = (pool("primary"), pool("lagging"), pool("current"));
+        let pools = Arc::new(SqlPools::new(
+            primary,
+            vec![
+                ("postgres://lagging".to_string(), lagging.clone()),
+                ("postgres://current".to_string(), current.clone()),
+            ],
+        ));
+        let lags = check_lag(
+            &pools,
+            vec![(lagging.clone(), Some(60.0)), (current.clone(), Some(1.0))],
+        );
+
+        wait_for(&pools, &[false, true]).await;
+        let status = pools.replica_status();
+        assert_eq!(status[0].lag_secs, Some(60.0));
+        assert_eq!(
+            status[0].error.as_deref(),
+            Some("lagging 60.0s behind the primary")
+        );
+
+        // Reads skip the lagging replica
+        for _ in 0..4 {
+            assert!(Arc::ptr_eq(&read_from(&pools).await, &current));
+        }
+
+        // and return to it once it catches up
+        lags.lock().unwrap()[0].1 = Some(2.0);
+        wait_for(&pools, &[true, true]).await;
+        let mut used = Vec::new();
+        for _ in 0..2 {
+            used.push(read_from(&pools).await);
+        }
+        assert!(used.iter().any(|pool| Arc::ptr_eq(pool, &lagging)));
+    }
+
+    #[tokio::test]
+    async fn test_replicas_down() {
+        let (primary, first, second) = (pool("primary"), pool("first"), pool("second"));
+        let pools = Arc::new(SqlPools::new(
+            primary.clone(),
+            vec![
+                ("postgres://first".to_string(), first.clone()),
+                ("postgres://second".to_string(), second.clone()),
+            ],
+        ));
+
+        // A replica whose read fails is taken out of rotation, and the read
+        // is answered by the primary
+        let failing = first.clone();
+        let result = pools
+            .read(|pool| {
+                let failing = Arc::ptr_eq(&pool, &failing);
+                async move {
+                    match failing {
+                        true => Err("connection reset".to_string()),
+                        false => Ok(pool),
+                    }
+                }
+            })
+            .await
+            .unwrap();
+        assert!(Arc::ptr_eq(&result, &primary));
+        assert!(Arc::ptr_eq(&read_from(&pools).await, &second));
+
+        // With every replica unreachable, reads go to the primary
+        check_lag(&pools, vec![(first, None), (second, None)]);
+        wait_for(&pools, &[false, false]).await;
+        assert!(pools.replica_status()[1].error.is_some());
+        for _ in 0..3 {
+            assert!(Arc::ptr_eq(&read_from(&pools).await, &primary));
+        }
+    }
+}
@@ -0,0 +1,103 @@
+/// Placeholder syntax used by a SQL database
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaceholderStyle {
+    /// `?`, used by MySQL
+    QuestionMark,
+    /// `$1`, `$2`, ..., used by PostgreSQL
+    Numbered,
+}
+
+/// A SQL query with `:name` parameters rewritten into positional placeholders
+///
+/// Each occurrence of a named parameter becomes its own placeholder, so
+/// `params` lists the parameter names in the order values must be bound.
+/// Text inside quotes and PostgreSQL `::type` casts are left untouched.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NamedQuery {
+    pub sql: String,
+    pub params: Vec<String>,
+}
+
+impl NamedQuery {
+    pub fn parse(query: &str, style: PlaceholderStyle) -> Self {
+        let mut sql = String::with_capacity(query.len());
+        let mut params = Vec::new();
+        let mut quote: Option<char> = None;
+        let mut chars = query.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            if let Some(q) = quote {
+                if c == q {
+                    quote = None;
+                }
+                sql.push(c);
+                continue;
+            }
+
+            match c {
+                '\'' | '"' | '`' => {
+                    quote = Some(c);
+                    sql.push(c);
+                }
+                ':' if chars.peek() == Some(&':') => {
+                    // Type cast, e.g. `$1::text`
+                    sql.push(c);
+                    sql.push(chars.next().unwrap());
+                }
+                ':' if chars
+                    .peek()
+                    .is_some_and(|next| next.is_ascii_alphabetic() || *next == '_') =>
+                {
+                    let mut name = String::new();
+                    while let Some(&next) = chars.peek() {
+                        if !(next.is_ascii_alphanumeric() || next == '_') {
+                            break;
+                        }
+                        name.push(next);
+                        chars.next();
+                    }
+
+                    params.push(name);
+                    match style {
+                        PlaceholderStyle::QuestionMark => sql.push('?'),
+                        PlaceholderStyle::Numbered => sql.push_str(&format!("${}", params.len())),
+                    }
+                }
+                _ => sql.push(c),
+            }
+        }
+
+        Self { sql, params }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_query() {
+        let query = NamedQuery::parse(
+            "SELECT role, ':skip' FROM tokens WHERE hash = :token_sha256 OR id = :token::text",
+            PlaceholderStyle::Numbered,
+        );
+        assert_eq!(
+            query.sql,
+            "SELECT role, ':skip' FROM tokens WHERE hash = $1 OR id = $2::text"
+        );
+        assert_eq!(query.params, vec!["token_sha256", "token"]);
+
+        let query = NamedQuery::parse(
+            "SELECT role FROM tokens WHERE token = :token",
+            PlaceholderStyle::QuestionMark,
+        );
+        assert_eq!(query.sql, "SELECT role FROM tokens WHERE token = ?");
+
+        // Positional queries pass through unchanged
+        let query = NamedQuery::parse(
+            "SELECT role FROM t WHERE token = ?",
+            PlaceholderStyle::QuestionMark,
+        );
+        assert!(query.params.is_empty());
+    }
+}
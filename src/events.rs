@@ -0,0 +1,345 @@
+use crate::admin::now_secs;
+use crate::cache::{BoundedCache, CacheLimits};
+use crate::config::WebhookConfig;
+use crate::signing::{create_signer, Signer};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Header carrying the webhook signature, as `t=<timestamp>,v1=<hex signature>`
+pub const SIGNATURE_HEADER: &str = "x-bouncer-signature";
+
+/// Kinds of policy decisions that can be sent to webhooks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionEventKind {
+    /// An authentication policy rejected the request
+    AuthFailure,
+    /// A request was rejected with 429 Too Many Requests
+    RateLimited,
+    /// A policy in the `waf` category blocked the request
+    WafBlock,
+    /// Any other policy rejected the request
+    Denied,
+}
+
+impl DecisionEventKind {
+    /// Classify a rejection by the category of the policy and the response status
+    pub fn classify(category: &str, status: u16) -> Self {
+        match (category, status) {
+            ("authentication", _) => Self::AuthFailure,
+            (_, 429) => Self::RateLimited,
+            ("waf", _) => Self::WafBlock,
+            _ => Self::Denied,
+        }
+    }
+}
+
+/// A request a policy rejected
+#[derive(Debug, Clone, Serialize)]
+pub struct DecisionEvent {
+    #[serde(rename = "type")]
+    pub kind: DecisionEventKind,
+    /// ID of the policy that rejected the request
+    pub policy: String,
+    pub status: u16,
+    pub method: String,
+    pub path: String,
+    pub route: Option<String>,
+    pub client_ip: Option<String>,
+    /// Unix timestamp in seconds
+    pub timestamp: u64,
+}
+
+impl DecisionEvent {
+    pub fn new(
+        kind: DecisionEventKind,
+        policy: String,
+        status: u16,
+        method: String,
+        path: String,
+    ) -> Self {
+        Self {
+            kind,
+            policy,
+            status,
+            method,
+            path,
+            route: None,
+            client_ip: None,
+            timestamp: now_secs() as u64,
+        }
+    }
+}
+
+/// Denials kept for each identity
+const DENIALS_PER_OWNER: usize = 20;
+
+/// The most recent requests rejected for each identity on this replica, shown
+/// to users by the developer portal
+pub struct RecentDenials {
+    denials: Arc<BoundedCache<String, VecDeque<DecisionEvent>>>,
+}
+
+impl Default for RecentDenials {
+    fn default() -> Self {
+        Self {
+            denials: BoundedCache::new("recent_denials", CacheLimits::default()),
+        }
+    }
+}
+
+/// The log of recent denials of the current instance
+pub fn recent_denials() -> Arc<RecentDenials> {
+    crate::instance::current().recent_denials.clone()
+}
+
+impl RecentDenials {
+    /// Record a request rejected after it was authenticated as `owner`
+    pub fn record(&self, owner: &str, event: DecisionEvent) {
+        self.denials.update(owner.to_string(), |current| {
+            let mut denials = current.cloned().unwrap_or_default();
+            if denials.len() == DENIALS_PER_OWNER {
+                denials.pop_back();
+            }
+            denials.push_front(event);
+            denials
+        });
+    }
+
+    /// Recent denials for `owner`, newest first
+    pub fn list(&self, owner: &str) -> Vec<DecisionEvent> {
+        self.denials
+            .get(&owner.to_string())
+            .map(Vec::from)
+            .unwrap_or_default()
+    }
+}
+
+struct WebhookSink {
+    config: WebhookConfig,
+    sender: mpsc::Sender<DecisionEvent>,
+}
+
+/// Sends decision events to the configured webhooks
+///
+/// Events are queued and sent in batches by a background task per webhook, so
+/// emitting never waits on the network. Events are dropped, with a warning, if
+/// a webhook's queue is full.
+#[derive(Default)]
+pub struct EventEmitter {
+    sinks: Vec<WebhookSink>,
+}
+
+impl EventEmitter {
+    /// Create the signers and start a delivery task for every webhook
+    pub async fn new(webhooks: &[WebhookConfig]) -> Result<Self, String> {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|e| format!("Failed to create webhook HTTP client: {}", e))?;
+
+        let mut sinks = Vec::new();
+        for config in webhooks {
+            let signer = create_signer(&config.signing_key)
+                .await
+                .map_err(|e| format!("Webhook {}: {}", config.url, e))?;
+            let (sender, receiver) = mpsc::channel(config.queue_size);
+            tokio::spawn(deliver(config.clone(), client.clone(), signer, receiver));
+            sinks.push(WebhookSink {
+                config: config.clone(),
+                sender,
+            });
+        }
+
+        Ok(Self { sinks })
+    }
+
+    /// Whether any webhook is configured
+    pub fn is_enabled(&self) -> bool {
+        !self.sinks.is_empty()
+    }
+
+    /// Queue an event for every webhook subscribed to its kind
+    pub fn emit(&self, event: DecisionEvent) {
+        for sink in &self.sinks {
+            if !sink.config.events.is_empty() && !sink.config.events.contains(&event.kind) {
+                continue;
+            }
+            if sink.sender.try_send(event.clone()).is_err() {
+                tracing::warn!(
+                    "Webhook queue for {} is full, dropping decision event",
+                    sink.config.url
+                );
+            }
+        }
+    }
+}
+
+/// Sign a webhook payload sent at `timestamp`, returning the signature header value
+///
+/// The signed message is `<timestamp>.<body>`, so receivers can reject old
+/// payloads being replayed.
+pub async fn sign_payload(
+    signer: &dyn Signer,
+    timestamp: u64,
+    body: &[u8],
+) -> Result<String, String> {
+    let message = [format!("{}.", timestamp).as_bytes(), body].concat();
+    let signature = signer.sign(&message).await.map_err(|e| e.to_string())?;
+    let hex: String = signature.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("t={},v1={}", timestamp, hex))
+}
+
+// Collect events into batches and post them until the emitter is dropped
+async fn deliver(
+    config: WebhookConfig,
+    client: reqwest::Client,
+    signer: Arc<dyn Signer>,
+    mut receiver: mpsc::Receiver<DecisionEvent>,
+) {
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.flush_interval_secs));
+    let mut batch = Vec::with_capacity(config.batch_size);
+
+    loop {
+        tokio::select! {
+            event = receiver.recv() => match event {
+                Some(event) => {
+                    batch.push(event);
+                    if batch.len() < config.batch_size {
+                        continue;
+                    }
+                }
+                None => {
+                    // Send what's left before stopping
+                    if !batch.is_empty() {
+                        send_batch(&config, &client, signer.as_ref(), &batch).await;
+                    }
+                    break;
+                }
+            },
+            _ = ticker.tick() => {
+                if batch.is_empty() {
+                    continue;
+                }
+            }
+        }
+
+        send_batch(&config, &client, signer.as_ref(), &batch).await;
+        batch.clear();
+    }
+}
+
+async fn send_batch(
+    config: &WebhookConfig,
+    client: &reqwest::Client,
+    signer: &dyn Signer,
+    batch: &[DecisionEvent],
+) {
+    let body = match serde_json::to_vec(batch) {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::error!("Failed to serialize decision events: {}", e);
+            return;
+        }
+    };
+
+    let signature = match sign_payload(signer, now_secs() as u64, &body).await {
+        Ok(signature) => signature,
+        Err(e) => {
+            tracing::warn!(
+                "Failed to sign webhook payload for {}, dropping {} events: {}",
+                config.url,
+                batch.len(),
+                e
+            );
+            return;
+        }
+    };
+
+    let result = client
+        .post(&config.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    if let Err(e) = result {
+        tracing::warn!(
+            "Failed to deliver {} decision events to {}: {}",
+            batch.len(),
+            config.url,
+            e
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{KeyConfig, SigningAlgorithm};
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    #[tokio::test]
+    async fn test_sign_payload() {
+        let signer = create_signer(&KeyConfig::Local {
+            algorithm: SigningAlgorithm::HmacSha256,
+            key: "secret".into(),
+        })
+        .await
+        .unwrap();
+        let signature = sign_payload(signer.as_ref(), 1700000000, b"[]")
+            .await
+            .unwrap();
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+        mac.update(b"1700000000.[]");
+        let hex: String = mac
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(signature, format!("t=1700000000,v1={}", hex));
+
+        assert_eq!(
+            DecisionEventKind::classify("authentication", 401),
+            DecisionEventKind::AuthFailure
+        );
+        assert_eq!(
+            DecisionEventKind::classify("traffic", 429),
+            DecisionEventKind::RateLimited
+        );
+        assert_eq!(
+            DecisionEventKind::classify("authorization", 403),
+            DecisionEventKind::Denied
+        );
+    }
+
+    #[test]
+    fn test_recent_denials() {
+        let denials = RecentDenials::default();
+        for status in 0..25 {
+            let event = DecisionEvent::new(
+                DecisionEventKind::Denied,
+                "@bouncer/authorization/rbac/v1".to_string(),
+                400 + status,
+                "GET".to_string(),
+                "/orders".to_string(),
+            );
+            denials.record("alice", event);
+        }
+
+        // Newest first, and only the last ones are kept
+        let listed = denials.list("alice");
+        assert_eq!(listed.len(), DENIALS_PER_OWNER);
+        assert_eq!(listed[0].status, 424);
+        assert_eq!(listed[DENIALS_PER_OWNER - 1].status, 405);
+        assert!(denials.list("bob").is_empty());
+    }
+}
@@ -0,0 +1,442 @@
+use crate::admin::{is_admin, json_error, now_secs};
+use crate::config::{FanoutConfig, FanoutConsumerConfig};
+use crate::events::{sign_payload, SIGNATURE_HEADER};
+use crate::signing::{create_signer, Signer};
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Header carrying the ID of the delivered event, the same on every retry
+pub const EVENT_ID_HEADER: &str = "x-bouncer-event-id";
+/// Header carrying the type of the delivered event
+pub const EVENT_TYPE_HEADER: &str = "x-bouncer-event-type";
+/// Header carrying which attempt at the delivery this is, starting at 1
+pub const ATTEMPT_HEADER: &str = "x-bouncer-delivery-attempt";
+
+/// An event published by an internal service, as delivered to consumers
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// Unix timestamp in seconds of when it was published
+    pub timestamp: u64,
+    pub data: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct PublishRequest {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    data: Value,
+}
+
+/// A delivery that failed every attempt, kept so it can be inspected and retried
+#[derive(Debug, Clone, Serialize)]
+pub struct DeadLetter {
+    pub id: u64,
+    /// URL of the consumer it was for
+    pub consumer: String,
+    pub event: Event,
+    pub attempts: u32,
+    /// Why the last attempt failed
+    pub error: String,
+    /// Unix timestamp in seconds
+    pub failed_at: u64,
+}
+
+// The most recent dead letters, oldest first
+struct DeadLetters {
+    letters: Mutex<VecDeque<DeadLetter>>,
+    next_id: AtomicU64,
+    limit: usize,
+}
+
+impl DeadLetters {
+    fn push(&self, consumer: &str, event: Event, attempts: u32, error: String) {
+        tracing::warn!(
+            "Dead-lettering event {} for {} after {} attempts: {}",
+            event.id,
+            consumer,
+            attempts,
+            error
+        );
+        let letter = DeadLetter {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed) + 1,
+            consumer: consumer.to_string(),
+            event,
+            attempts,
+            error,
+            failed_at: now_secs() as u64,
+        };
+
+        let mut letters = self.letters.lock().unwrap();
+        if letters.len() >= self.limit {
+            if let Some(dropped) = letters.pop_front() {
+                tracing::warn!(
+                    "Dead letter limit reached, dropping event {} for {}",
+                    dropped.event.id,
+                    dropped.consumer
+                );
+            }
+        }
+        letters.push_back(letter);
+    }
+
+    fn list(&self) -> Vec<DeadLetter> {
+        self.letters.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn remove(&self, id: u64) -> Option<DeadLetter> {
+        let mut letters = self.letters.lock().unwrap();
+        let index = letters.iter().position(|letter| letter.id == id)?;
+        letters.remove(index)
+    }
+}
+
+// Whether a consumer subscribed to events of this type
+fn subscribed(patterns: &[String], kind: &str) -> bool {
+    patterns.is_empty()
+        || patterns
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => kind.starts_with(prefix),
+                None => pattern == kind,
+            })
+}
+
+struct Consumer {
+    config: FanoutConsumerConfig,
+    sender: mpsc::Sender<Event>,
+}
+
+/// Delivers events published by internal services to the consumer webhooks
+/// subscribed to them
+///
+/// Each consumer has a queue and a background task delivering its events one
+/// at a time, in the order they were published. Failed deliveries are retried
+/// with exponential backoff, and dead-lettered once `max_attempts` fail.
+pub struct Fanout {
+    config: FanoutConfig,
+    consumers: Vec<Consumer>,
+    dead_letters: Arc<DeadLetters>,
+}
+
+impl Fanout {
+    /// Create the signer and start a delivery task for every consumer
+    pub async fn new(config: &FanoutConfig) -> Result<Self, String> {
+        if config.consumers.is_empty() {
+            return Err("fanout: at least one consumer must be configured".to_string());
+        }
+        if config.max_attempts == 0 {
+            return Err("fanout.max_attempts must be greater than 0".to_string());
+        }
+        if config.queue_size == 0 {
+            return Err("fanout.queue_size must be greater than 0".to_string());
+        }
+        for consumer in &config.consumers {
+            let url = reqwest::Url::parse(&consumer.url)
+                .map_err(|e| format!("fanout: invalid consumer URL '{}': {}", consumer.url, e))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(format!(
+                    "fanout: consumer URL '{}' must be http or https",
+                    consumer.url
+                ));
+            }
+        }
+
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .map_err(|e| format!("Failed to create fanout HTTP client: {}", e))?;
+        let signer = create_signer(&config.signing_key)
+            .await
+            .map_err(|e| format!("fanout: {}", e))?;
+        let dead_letters = Arc::new(DeadLetters {
+            letters: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(0),
+            limit: config.dead_letter_limit,
+        });
+
+        let consumers = config
+            .consumers
+            .iter()
+            .map(|consumer| {
+                let (sender, receiver) = mpsc::channel(config.queue_size);
+                tokio::spawn(deliver(
+                    Delivery {
+                        url: consumer.url.clone(),
+                        max_attempts: config.max_attempts,
+                        retry_backoff: Duration::from_millis(config.retry_backoff_ms),
+                        client: client.clone(),
+                        signer: signer.clone(),
+                        dead_letters: dead_letters.clone(),
+                    },
+                    receiver,
+                ));
+                Consumer {
+                    config: consumer.clone(),
+                    sender,
+                }
+            })
+            .collect();
+
+        Ok(Self {
+            config: config.clone(),
+            consumers,
+            dead_letters,
+        })
+    }
+
+    /// Queue an event for every consumer subscribed to its type, returning it
+    /// and how many consumers it was queued for
+    ///
+    /// A consumer whose queue is full gets the event dead-lettered instead, so
+    /// it can be retried once the consumer catches up.
+    pub fn publish(&self, kind: String, data: Value) -> (Event, usize) {
+        let event = Event {
+            id: format!("evt_{:032x}", rand::random::<u128>()),
+            kind,
+            timestamp: now_secs() as u64,
+            data,
+        };
+
+        let mut queued = 0;
+        for consumer in &self.consumers {
+            if !subscribed(&consumer.config.events, &event.kind) {
+                continue;
+            }
+            match consumer.sender.try_send(event.clone()) {
+                Ok(()) => queued += 1,
+                Err(_) => self.dead_letters.push(
+                    &consumer.config.url,
+                    event.clone(),
+                    0,
+                    "Delivery queue is full".to_string(),
+                ),
+            }
+        }
+        (event, queued)
+    }
+
+    /// Queue a dead letter for delivery again, removing it from the dead letters
+    pub fn retry(&self, id: u64) -> Result<(), String> {
+        let letter = self
+            .dead_letters
+            .remove(id)
+            .ok_or_else(|| format!("Dead letter {} not found", id))?;
+        let Some(consumer) = self
+            .consumers
+            .iter()
+            .find(|consumer| consumer.config.url == letter.consumer)
+        else {
+            return Err(format!(
+                "Consumer {} is no longer configured",
+                letter.consumer
+            ));
+        };
+
+        consumer.sender.try_send(letter.event).map_err(|e| {
+            let event = match e {
+                mpsc::error::TrySendError::Full(event)
+                | mpsc::error::TrySendError::Closed(event) => event,
+            };
+            self.dead_letters.push(
+                &letter.consumer,
+                event,
+                letter.attempts,
+                "Delivery queue is full".to_string(),
+            );
+            format!("Delivery queue for {} is full", letter.consumer)
+        })
+    }
+}
+
+// What a consumer's delivery task needs
+struct Delivery {
+    url: String,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    client: reqwest::Client,
+    signer: Arc<dyn Signer>,
+    dead_letters: Arc<DeadLetters>,
+}
+
+// Deliver a consumer's events in order until the fanout is dropped
+async fn deliver(delivery: Delivery, mut receiver: mpsc::Receiver<Event>) {
+    while let Some(event) = receiver.recv().await {
+        let mut backoff = delivery.retry_backoff;
+        let mut attempt = 1;
+        loop {
+            let error = match send(&delivery, &event, attempt).await {
+                Ok(()) => break,
+                Err(error) => error,
+            };
+            if attempt >= delivery.max_attempts {
+                delivery
+                    .dead_letters
+                    .push(&delivery.url, event, attempt, error);
+                break;
+            }
+
+            tracing::debug!(
+                "Delivery of event {} to {} failed, retrying in {:?}: {}",
+                event.id,
+                delivery.url,
+                backoff,
+                error
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+}
+
+async fn send(delivery: &Delivery, event: &Event, attempt: u32) -> Result<(), String> {
+    let body = serde_json::to_vec(event).map_err(|e| e.to_string())?;
+    // Signed anew each attempt, so receivers checking the timestamp accept retries
+    let signature = sign_payload(delivery.signer.as_ref(), now_secs() as u64, &body).await?;
+
+    delivery
+        .client
+        .post(&delivery.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, signature)
+        .header(EVENT_ID_HEADER, &event.id)
+        .header(EVENT_TYPE_HEADER, &event.kind)
+        .header(ATTEMPT_HEADER, attempt.to_string())
+        .body(body)
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .map(|_| ())
+        .map_err(|e| e.to_string())
+}
+
+async fn publish(
+    State(fanout): State<Arc<Fanout>>,
+    headers: HeaderMap,
+    Json(request): Json<PublishRequest>,
+) -> axum::response::Response {
+    if !is_admin(&headers, fanout.config.token.expose()) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid token");
+    }
+    if request.kind.is_empty() {
+        return json_error(StatusCode::BAD_REQUEST, "Event type is required");
+    }
+
+    let (event, consumers) = fanout.publish(request.kind, request.data);
+    (
+        StatusCode::ACCEPTED,
+        Json(serde_json::json!({ "id": event.id, "consumers": consumers })),
+    )
+        .into_response()
+}
+
+async fn list_dead_letters(
+    State(fanout): State<Arc<Fanout>>,
+    headers: HeaderMap,
+) -> axum::response::Response {
+    if !is_admin(&headers, fanout.config.token.expose()) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid token");
+    }
+    Json(fanout.dead_letters.list()).into_response()
+}
+
+async fn retry_dead_letter(
+    State(fanout): State<Arc<Fanout>>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> axum::response::Response {
+    if !is_admin(&headers, fanout.config.token.expose()) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid token");
+    }
+    match fanout.retry(id) {
+        Ok(()) => StatusCode::ACCEPTED.into_response(),
+        Err(e) => json_error(StatusCode::CONFLICT, e),
+    }
+}
+
+async fn delete_dead_letter(
+    State(fanout): State<Arc<Fanout>>,
+    headers: HeaderMap,
+    Path(id): Path<u64>,
+) -> axum::response::Response {
+    if !is_admin(&headers, fanout.config.token.expose()) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid token");
+    }
+    match fanout.dead_letters.remove(id) {
+        Some(_) => StatusCode::NO_CONTENT.into_response(),
+        None => json_error(
+            StatusCode::NOT_FOUND,
+            format!("Dead letter {} not found", id),
+        ),
+    }
+}
+
+/// Routes under `/_admin/fanout` to publish events and manage dead letters
+pub fn router(fanout: Arc<Fanout>) -> Router {
+    Router::new()
+        .route("/_admin/fanout/events", post(publish))
+        .route("/_admin/fanout/dead_letters", get(list_dead_letters))
+        .route(
+            "/_admin/fanout/dead_letters/{id}",
+            delete(delete_dead_letter),
+        )
+        .route(
+            "/_admin/fanout/dead_letters/{id}/retry",
+            post(retry_dead_letter),
+        )
+        .with_state(fanout)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscriptions_and_dead_letters() {
+        let patterns = vec!["order.*".to_string(), "user.deleted".to_string()];
+        assert!(subscribed(&patterns, "order.created"));
+        assert!(subscribed(&patterns, "user.deleted"));
+        assert!(!subscribed(&patterns, "user.created"));
+        assert!(subscribed(&[], "user.created"));
+
+        let dead_letters = DeadLetters {
+            letters: Mutex::new(VecDeque::new()),
+            next_id: AtomicU64::new(0),
+            limit: 2,
+        };
+        let event = |id: &str| Event {
+            id: id.to_string(),
+            kind: "order.created".to_string(),
+            timestamp: 0,
+            data: Value::Null,
+        };
+        for id in ["evt_1", "evt_2", "evt_3"] {
+            dead_letters.push("http://consumer", event(id), 5, "503".to_string());
+        }
+
+        let ids: Vec<_> = dead_letters
+            .list()
+            .into_iter()
+            .map(|letter| letter.event.id)
+            .collect();
+        assert_eq!(ids, ["evt_2", "evt_3"]);
+        assert_eq!(dead_letters.remove(3).unwrap().event.id, "evt_3");
+        assert!(dead_letters.remove(3).is_none());
+    }
+}
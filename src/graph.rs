@@ -0,0 +1,297 @@
+use crate::config::Config;
+use crate::diagnostics::redact_url;
+use crate::GLOBAL_CONFIG;
+use std::fmt::Write;
+use std::str::FromStr;
+
+/// Output format for `bouncer graph`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "dot" => Ok(Self::Dot),
+            "mermaid" => Ok(Self::Mermaid),
+            other => Err(format!(
+                "Unknown graph format '{}', expected 'dot' or 'mermaid'",
+                other
+            )),
+        }
+    }
+}
+
+/// How requests flow through one gateway instance
+///
+/// Built from the same policy chain and routes as the running server, so the
+/// graph shows policies in the order they actually run.
+#[derive(Debug, Clone)]
+pub struct ChainGraph {
+    pub listener: String,
+    /// Labeled routes, or the catch-all route when there are no labels
+    pub routes: Vec<String>,
+    /// Admin routes registered by policies
+    pub admin_routes: Vec<String>,
+    /// Policy IDs in chain order
+    pub policies: Vec<String>,
+    pub upstream: String,
+}
+
+impl ChainGraph {
+    /// Build the policy chain for a config and describe it
+    ///
+    /// Policies are created as they would be at startup, so databases they use
+    /// must be reachable.
+    pub async fn build(config: &Config) -> Result<Self, String> {
+        let registry = crate::server::create_registry(config);
+        let (policy_chain, policy_router) = registry.build_policy_chain(&config.policies).await?;
+
+        let routes = if config.labels.is_empty() {
+            vec!["/{*path}".to_string()]
+        } else {
+            config
+                .labels
+                .iter()
+                .map(|label| {
+                    let methods = if label.methods.is_empty() {
+                        "*".to_string()
+                    } else {
+                        label.methods.join(",").to_uppercase()
+                    };
+                    format!("{} {}\n{}", methods, label.path, label.name)
+                })
+                .collect()
+        };
+
+        Ok(Self {
+            listener: config.full_bind_address(),
+            routes,
+            admin_routes: policy_router.paths().map(str::to_string).collect(),
+            policies: policy_chain
+                .iter()
+                .map(|policy| {
+                    format!(
+                        "@{}/{}/{}/{}",
+                        policy.provider(),
+                        policy.category(),
+                        policy.name(),
+                        policy.version()
+                    )
+                })
+                .collect(),
+            upstream: match &config.server.destination_address {
+                Some(destination) => redact_url(destination),
+                None => "no destination".to_string(),
+            },
+        })
+    }
+}
+
+/// Describe every instance of a config file
+pub async fn build_graphs(configs: &[Config]) -> Result<Vec<ChainGraph>, String> {
+    // Policies read shared settings such as databases from the global config
+    if let Some(config) = configs.first() {
+        let _ = GLOBAL_CONFIG.set(config.clone());
+    }
+
+    let mut graphs = Vec::new();
+    for config in configs {
+        graphs.push(ChainGraph::build(config).await?);
+    }
+    Ok(graphs)
+}
+
+/// Render graphs as a DOT or Mermaid document
+pub fn render(graphs: &[ChainGraph], format: GraphFormat) -> String {
+    match format {
+        GraphFormat::Dot => render_dot(graphs),
+        GraphFormat::Mermaid => render_mermaid(graphs),
+    }
+}
+
+// Node IDs are scoped by instance so several instances can share a document
+struct Nodes {
+    listener: String,
+    routes: Vec<String>,
+    admin_routes: Vec<String>,
+    policies: Vec<String>,
+    upstream: String,
+}
+
+impl Nodes {
+    fn new(instance: usize, graph: &ChainGraph) -> Self {
+        Self {
+            listener: format!("i{}_listener", instance),
+            routes: (0..graph.routes.len())
+                .map(|index| format!("i{}_route{}", instance, index))
+                .collect(),
+            admin_routes: (0..graph.admin_routes.len())
+                .map(|index| format!("i{}_admin{}", instance, index))
+                .collect(),
+            policies: (0..graph.policies.len())
+                .map(|index| format!("i{}_policy{}", instance, index))
+                .collect(),
+            upstream: format!("i{}_upstream", instance),
+        }
+    }
+
+    // Edges along the chain, from each route through every policy to the upstream
+    fn chain_edges(&self) -> Vec<(&str, &str)> {
+        let mut edges = Vec::new();
+        let entry = self.policies.first().unwrap_or(&self.upstream);
+        for route in &self.routes {
+            edges.push((self.listener.as_str(), route.as_str()));
+            edges.push((route.as_str(), entry.as_str()));
+        }
+        for pair in self.policies.windows(2) {
+            edges.push((pair[0].as_str(), pair[1].as_str()));
+        }
+        if let Some(last) = self.policies.last() {
+            edges.push((last.as_str(), self.upstream.as_str()));
+        }
+        edges
+    }
+}
+
+fn render_dot(graphs: &[ChainGraph]) -> String {
+    fn escape(label: &str) -> String {
+        label
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    }
+
+    let mut out = String::from("digraph bouncer {\n    rankdir=LR;\n    node [shape=box];\n");
+    for (instance, graph) in graphs.iter().enumerate() {
+        let nodes = Nodes::new(instance, graph);
+        let _ = writeln!(out, "    subgraph cluster_{} {{", instance);
+        let _ = writeln!(out, "        label=\"{}\";", escape(&graph.listener));
+        let _ = writeln!(
+            out,
+            "        {} [label=\"listener\\n{}\", shape=ellipse];",
+            nodes.listener,
+            escape(&graph.listener)
+        );
+        for (id, route) in nodes.routes.iter().zip(&graph.routes) {
+            let _ = writeln!(out, "        {} [label=\"{}\"];", id, escape(route));
+        }
+        for (id, route) in nodes.admin_routes.iter().zip(&graph.admin_routes) {
+            let _ = writeln!(
+                out,
+                "        {} [label=\"{}\", style=dashed];",
+                id,
+                escape(route)
+            );
+        }
+        for (position, (id, policy)) in nodes.policies.iter().zip(&graph.policies).enumerate() {
+            let _ = writeln!(
+                out,
+                "        {} [label=\"{}. {}\", style=rounded];",
+                id,
+                position + 1,
+                escape(policy)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "        {} [label=\"upstream\\n{}\", shape=ellipse];",
+            nodes.upstream,
+            escape(&graph.upstream)
+        );
+        for (from, to) in nodes.chain_edges() {
+            let _ = writeln!(out, "        {} -> {};", from, to);
+        }
+        for id in &nodes.admin_routes {
+            let _ = writeln!(out, "        {} -> {} [style=dashed];", nodes.listener, id);
+        }
+        out.push_str("    }\n");
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn render_mermaid(graphs: &[ChainGraph]) -> String {
+    fn escape(label: &str) -> String {
+        label.replace('"', "#quot;").replace('\n', "<br/>")
+    }
+
+    let mut out = String::from("flowchart LR\n");
+    for (instance, graph) in graphs.iter().enumerate() {
+        let nodes = Nodes::new(instance, graph);
+        let _ = writeln!(
+            out,
+            "    subgraph i{}[\"{}\"]",
+            instance,
+            escape(&graph.listener)
+        );
+        let _ = writeln!(
+            out,
+            "        {}([\"listener<br/>{}\"])",
+            nodes.listener,
+            escape(&graph.listener)
+        );
+        for (id, route) in nodes.routes.iter().zip(&graph.routes) {
+            let _ = writeln!(out, "        {}[\"{}\"]", id, escape(route));
+        }
+        for (id, route) in nodes.admin_routes.iter().zip(&graph.admin_routes) {
+            let _ = writeln!(out, "        {}[/\"{}\"/]", id, escape(route));
+        }
+        for (position, (id, policy)) in nodes.policies.iter().zip(&graph.policies).enumerate() {
+            let _ = writeln!(
+                out,
+                "        {}(\"{}. {}\")",
+                id,
+                position + 1,
+                escape(policy)
+            );
+        }
+        let _ = writeln!(
+            out,
+            "        {}([\"upstream<br/>{}\"])",
+            nodes.upstream,
+            escape(&graph.upstream)
+        );
+        for (from, to) in nodes.chain_edges() {
+            let _ = writeln!(out, "        {} --> {}", from, to);
+        }
+        for id in &nodes.admin_routes {
+            let _ = writeln!(out, "        {} -.-> {}", nodes.listener, id);
+        }
+        out.push_str("    end\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_chain_in_order() {
+        let graph = ChainGraph {
+            listener: "127.0.0.1:8080".to_string(),
+            routes: vec!["/{*path}".to_string()],
+            admin_routes: vec![],
+            policies: vec![
+                "@bouncer/authentication/bearer/v1".to_string(),
+                "@bouncer/authorization/rbac/v1".to_string(),
+            ],
+            upstream: "http://api:3000".to_string(),
+        };
+
+        let dot = render(std::slice::from_ref(&graph), GraphFormat::Dot);
+        assert!(dot.contains("i0_route0 -> i0_policy0;"));
+        assert!(dot.contains("i0_policy0 -> i0_policy1;"));
+        assert!(dot.contains("i0_policy1 -> i0_upstream;"));
+
+        let mermaid = render(&[graph], GraphFormat::Mermaid);
+        assert!(mermaid.starts_with("flowchart LR\n"));
+        assert!(mermaid.contains("i0_policy0(\"1. @bouncer/authentication/bearer/v1\")"));
+        assert!(mermaid.contains("i0_policy1 --> i0_upstream"));
+    }
+}
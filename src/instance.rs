@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::events::RecentDenials;
+use crate::policy::failure::Failures;
+use crate::policy::sessions::SessionRegistry;
+use once_cell::sync::Lazy;
+use std::future::Future;
+use std::sync::Arc;
+
+/// State kept apart for each gateway instance of a config file
+///
+/// Requests, policy construction and reloads run inside [`scope`] for their
+/// instance, so sessions, failure counts and recent denials of one instance
+/// aren't seen by the others.
+#[derive(Default)]
+pub struct Instance {
+    /// Distinguishes the instance in shared stores, when the config file has
+    /// several
+    pub name: Option<String>,
+    pub config: Option<Arc<Config>>,
+    pub sessions: Arc<SessionRegistry>,
+    pub failures: Arc<Failures>,
+    pub recent_denials: Arc<RecentDenials>,
+}
+
+impl Instance {
+    pub fn new(config: Config, name: Option<String>) -> Self {
+        Self {
+            name,
+            config: Some(Arc::new(config)),
+            ..Self::default()
+        }
+    }
+}
+
+tokio::task_local! {
+    static CURRENT: Arc<Instance>;
+}
+
+// Used outside of any instance, e.g. by policies created directly by library
+// users
+static DEFAULT: Lazy<Arc<Instance>> = Lazy::new(|| Arc::new(Instance::default()));
+
+/// Run `future` as part of `instance`
+pub async fn scope<F: Future>(instance: Arc<Instance>, future: F) -> F::Output {
+    CURRENT.scope(instance, future).await
+}
+
+/// The instance the current task runs for
+pub fn current() -> Arc<Instance> {
+    CURRENT
+        .try_with(Arc::clone)
+        .unwrap_or_else(|_| Arc::clone(&DEFAULT))
+}
+
+/// The config of the current instance, or else the global config
+pub fn config() -> Option<Arc<Config>> {
+    current()
+        .config
+        .clone()
+        .or_else(|| crate::GLOBAL_CONFIG.get().cloned().map(Arc::new))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_instances_are_separate() {
+        let first = Arc::new(Instance::default());
+        let second = Arc::new(Instance::default());
+        scope(Arc::clone(&first), async {
+            current()
+                .failures
+                .record("@test/failing/v1", Default::default());
+        })
+        .await;
+
+        let stats = |instance| scope(instance, async { current().failures.stats() });
+        assert_eq!(stats(first).await.len(), 1);
+        assert!(stats(second).await.is_empty());
+    }
+}
@@ -1,7 +1,20 @@
+pub mod acme;
+pub mod admin;
+pub mod cache;
 pub mod config;
 pub mod database;
+pub mod diagnostics;
+pub mod events;
+pub mod fanout;
+pub mod graph;
+pub mod instance;
+pub mod metering;
 pub mod policy;
 pub mod server;
+pub mod signing;
+pub mod simulate;
+pub mod tls;
+pub mod websocket_proxy;
 
 use once_cell::sync::Lazy;
 use once_cell::sync::OnceCell;
@@ -9,15 +22,16 @@ use policy::registry::PolicyRegistry;
 use std::sync::Mutex;
 
 // Re-export key components for convenience
-pub use policy::traits::{Policy, PolicyFactory, PolicyResult};
+pub use policy::traits::{Policy, PolicyFactory, PolicyResult, ResponsePolicyResult};
 
 // Simplified API for library users
-pub use server::start_server;
+pub use server::{build_router, start_server, start_servers};
 
 // The crate version from Cargo.toml
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 // Global registry for storing custom policy factories
+#[allow(clippy::type_complexity)]
 static CUSTOM_POLICIES: Lazy<Mutex<Vec<fn(&mut PolicyRegistry)>>> =
     Lazy::new(|| Mutex::new(Vec::new()));
 
@@ -40,28 +54,29 @@ pub static GLOBAL_CONFIG: OnceCell<config::Config> = OnceCell::new();
 /// }
 /// ```
 pub async fn start_with_config(config_path: &str) {
-    // Load configuration file
-    let config = match config::load_config(config_path) {
-        Ok(config) => config,
+    // Load configuration file, which may describe several gateway instances
+    let configs = match config::load_configs(config_path) {
+        Ok(configs) => configs,
         Err(e) => {
             eprintln!("Failed to load configuration: {}", e);
             std::process::exit(1);
         }
     };
 
-    // Check version compatibility
-    if let Err(e) = config::validate_version(&config.bouncer_version, VERSION) {
+    // Check version compatibility (shared by all instances)
+    let bouncer_version = &configs[0].bouncer_version;
+    if let Err(e) = config::validate_version(bouncer_version, VERSION) {
         eprintln!("Version compatibility error: {}", e);
         eprintln!(
             "Config version: {}, Bouncer version: {}",
-            config.bouncer_version, VERSION
+            bouncer_version, VERSION
         );
         eprintln!("Hint: Update your config file with a compatible 'bouncer_version' field.");
         std::process::exit(1);
     }
 
-    // Start the server with loaded configuration
-    server::start_server(config).await;
+    // Start a server for every instance with the loaded configuration
+    server::start_servers(configs).await;
 }
 
 /// Register a custom policy for use with Bouncer
@@ -82,6 +97,11 @@ pub async fn start_with_config(config_path: &str) {
 ///
 /// #[async_trait]
 /// impl Policy for MyCustomPolicy {
+///     fn provider(&self) -> &'static str { "mycustom" }
+///     fn category(&self) -> &'static str { "custom" }
+///     fn name(&self) -> &'static str { "policy" }
+///     fn version(&self) -> &'static str { "v1" }
+///
 ///     async fn process(&self, request: Request<Body>) -> PolicyResult {
 ///         // Implementation details...
 ///         PolicyResult::Continue(request)
@@ -1,22 +1,579 @@
+use bouncer::admin::now_secs;
+use bouncer::config::presets::{self, Preset};
+use bouncer::config::{self, encryption, Config, ConfigBuilder, PolicyConfig};
+use bouncer::database::migrations::Migration;
+use bouncer::database::mock::{self, MockDatabases};
+use bouncer::graph::{self, GraphFormat};
+use bouncer::policy::logging::PolicyLogFilter;
+use bouncer::policy::providers::bouncer::authentication::bearer::{
+    self,
+    store::create_token_store,
+    v1_managed::{
+        issue_token, rotate_token, BearerAuthManagedConfig, BearerAuthManagedPolicyFactory,
+    },
+};
+use bouncer::policy::providers::bouncer::authorization::{consent, entitlements, rbac};
+use bouncer::server::{create_registry, validate_config};
+use bouncer::simulate::{simulate, SimulatedRequest};
 use bouncer::start_with_config;
-use clap::Parser;
+use bouncer::PolicyFactory;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use std::io::{Read, Write};
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::prelude::*;
 
 #[derive(Parser)]
 struct Args {
-    #[clap(short, long)]
-    config: String,
+    #[clap(short, long, global = true)]
+    config: Option<String>,
+
+    /// Profile from the config's `profiles:` section to merge over the rest,
+    /// instead of the one in BOUNCER_PROFILE
+    #[clap(short, long, global = true)]
+    profile: Option<String>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the gateway. This is the default when no command is given
+    Run,
+    /// Check a config for mistakes without starting a server or connecting to databases
+    Validate,
+    /// List the policies a config runs, in chain order
+    Policies {
+        /// List every policy that can be used instead, including plugins
+        #[clap(long)]
+        available: bool,
+    },
+    /// Print the SQL schema of the PostgreSQL stores, for applying it by hand
+    Schema {
+        /// Only print the schema of this store
+        #[clap(long)]
+        store: Option<SchemaStore>,
+    },
+    /// Manage the tokens of a managed bearer policy
+    Token {
+        /// ID of the policy, when the config has several
+        #[clap(long)]
+        policy: Option<String>,
+        /// Index of the instance to use when the config has several
+        #[clap(long, default_value = "0")]
+        instance: usize,
+        #[clap(subcommand)]
+        command: TokenCommand,
+    },
+    /// Write a commented starter config
+    Init {
+        /// Policies to start with: api-gateway, oauth-proxy or rate-limit-only
+        #[clap(long, default_value = "api-gateway")]
+        preset: Preset,
+        /// File to write, which must not exist yet. Printed if omitted
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// Generate a key for encrypted config values
+    GenerateKey,
+    /// Encrypt a config value with the key from BOUNCER_CONFIG_KEY
+    Encrypt {
+        /// The value to encrypt. Read from stdin if omitted
+        value: Option<String>,
+    },
+    /// Print the listeners, routes, policy chains and upstreams of a config
+    Graph {
+        /// Output format: mermaid or dot
+        #[clap(long, default_value = "mermaid")]
+        format: GraphFormat,
+    },
+    /// Run a request through the policy chain without starting a server
+    Simulate {
+        #[clap(short = 'X', long, default_value = "GET")]
+        method: axum::http::Method,
+        /// Path with an optional query string
+        #[clap(long)]
+        path: String,
+        /// Request header as 'Name: value'. Can be repeated
+        #[clap(short = 'H', long = "header")]
+        headers: Vec<String>,
+        /// Request body
+        #[clap(short, long)]
+        data: Option<String>,
+        /// YAML file with token and revocation fixtures to use instead of databases
+        #[clap(long)]
+        mock_db: Option<String>,
+        /// Index of the instance to use when the config has several
+        #[clap(long, default_value = "0")]
+        instance: usize,
+    },
+}
+
+#[derive(Subcommand)]
+enum TokenCommand {
+    /// List every token
+    List {
+        /// Only list the tokens of this owner
+        #[clap(long)]
+        owner: Option<String>,
+    },
+    /// Issue a token. It's only ever printed here
+    Create {
+        #[clap(long)]
+        role: String,
+        #[clap(long)]
+        owner: Option<String>,
+        #[clap(long)]
+        expires_in_secs: Option<u64>,
+        /// Print only the token instead of the JSON with its metadata
+        #[clap(long)]
+        token_only: bool,
+        /// Do nothing if the owner already has a valid token with this role
+        #[clap(long, requires = "owner")]
+        if_missing: bool,
+    },
+    /// Replace a token with a new one that keeps its role, owner and expiry
+    Rotate {
+        id: String,
+        /// How long the old token stays valid, instead of the policy's grace period
+        #[clap(long)]
+        grace_period_secs: Option<u64>,
+    },
+    /// Revoke a token
+    Revoke { id: String },
+    /// Show the usage of every token
+    Usage,
+}
+
+/// A PostgreSQL store with a schema owned by Bouncer
+#[derive(Clone, Copy, ValueEnum)]
+enum SchemaStore {
+    Tokens,
+    Rbac,
+    Entitlements,
+    Consent,
+    Metering,
+    Acme,
+}
+
+impl SchemaStore {
+    fn migrations(self) -> Result<&'static [Migration], String> {
+        Ok(match self {
+            Self::Tokens => bearer::store::POSTGRES_MIGRATIONS,
+            Self::Rbac => rbac::store::POSTGRES_MIGRATIONS,
+            Self::Entitlements => entitlements::store::POSTGRES_MIGRATIONS,
+            Self::Consent => consent::store::POSTGRES_MIGRATIONS,
+            Self::Acme => bouncer::acme::store::POSTGRES_MIGRATIONS,
+            #[cfg(feature = "postgres")]
+            Self::Metering => bouncer::metering::postgres::POSTGRES_MIGRATIONS,
+            #[cfg(not(feature = "postgres"))]
+            Self::Metering => {
+                return Err(
+                    "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature."
+                        .to_string(),
+                )
+            }
+        })
+    }
+}
+
+// Load the config given with --config
+fn load_configs(config: Option<&str>) -> Result<Vec<Config>, String> {
+    let path = config.ok_or_else(|| "--config is required".to_string())?;
+    config::load_configs(path)
+}
+
+// Pick one instance of a config that may describe several
+fn select_instance(mut configs: Vec<Config>, instance: usize) -> Result<Config, String> {
+    if instance >= configs.len() {
+        return Err(format!(
+            "Config defines {} instances, there is no instance {}",
+            configs.len(),
+            instance
+        ));
+    }
+    Ok(configs.remove(instance))
+}
+
+// Find the managed bearer policy whose tokens are managed
+fn managed_policy<'a>(config: &'a Config, id: Option<&str>) -> Result<&'a PolicyConfig, String> {
+    let provider = BearerAuthManagedPolicyFactory::policy_id();
+    let managed: Vec<&PolicyConfig> = config
+        .policies
+        .iter()
+        .filter(|policy| policy.provider == provider)
+        .collect();
+
+    match (id, managed.as_slice()) {
+        (Some(id), _) => managed
+            .iter()
+            .copied()
+            .find(|policy| policy.id == id)
+            .ok_or_else(|| format!("Config has no {} policy with ID {}", provider, id)),
+        (None, [policy]) => Ok(*policy),
+        (None, []) => Err(format!("Config has no {} policy", provider)),
+        (None, _) => Err(format!(
+            "Config has several {} policies, pick one with --policy: {}",
+            provider,
+            managed
+                .iter()
+                .map(|policy| policy.id.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )),
+    }
+}
+
+// Manage tokens directly in the store of a managed bearer policy
+async fn run_token_command(
+    config: &Config,
+    policy: Option<&str>,
+    command: TokenCommand,
+) -> Result<(), String> {
+    let policy = managed_policy(config, policy)?;
+    let managed: BearerAuthManagedConfig = serde_json::from_value(policy.parameters.clone())
+        .map_err(|e| format!("Policy {}: {}", policy.id, e))?;
+    let store = create_token_store(
+        managed.store,
+        &managed.key_prefix,
+        managed.run_migrations,
+        &config.databases,
+    )
+    .await
+    .map_err(|e| e.to_string())?;
+
+    let output = match command {
+        TokenCommand::List { owner } => {
+            let mut tokens = store.list().await.map_err(|e| e.to_string())?;
+            if let Some(owner) = owner {
+                tokens.retain(|token| token.owner.as_ref() == Some(&owner));
+            }
+            serde_json::to_value(tokens)
+        }
+        TokenCommand::Create {
+            role,
+            owner,
+            expires_in_secs,
+            token_only,
+            if_missing,
+        } => {
+            if role.is_empty() {
+                return Err("--role must not be empty".to_string());
+            }
+            if if_missing {
+                // Rotated tokens are on their way out, so they don't count
+                let now = now_secs();
+                let existing = store.list().await.map_err(|e| e.to_string())?;
+                if let Some(token) = existing.iter().find(|token| {
+                    token.owner == owner
+                        && token.role == role
+                        && token.replaced_by.is_none()
+                        && token.expires_at.is_none_or(|at| at > now)
+                }) {
+                    eprintln!("Token {} already exists, not creating another", token.id);
+                    return Ok(());
+                }
+            }
+
+            let created = issue_token(
+                store.as_ref(),
+                managed.salt.expose(),
+                role,
+                owner,
+                expires_in_secs,
+            )
+            .await
+            .map_err(|e| e.to_string())?;
+            if token_only {
+                println!("{}", created.token);
+                return Ok(());
+            }
+            serde_json::to_value(created)
+        }
+        TokenCommand::Rotate {
+            id,
+            grace_period_secs,
+        } => {
+            let grace = grace_period_secs.unwrap_or(managed.rotation_grace_period_secs);
+            let created = rotate_token(store.as_ref(), managed.salt.expose(), &id, grace)
+                .await
+                .map_err(|e| e.to_string())?;
+            serde_json::to_value(created)
+        }
+        TokenCommand::Revoke { id } => {
+            if !store.revoke(&id).await.map_err(|e| e.to_string())? {
+                return Err("Token not found".to_string());
+            }
+            println!("Revoked {}", id);
+            return Ok(());
+        }
+        TokenCommand::Usage => {
+            serde_json::to_value(store.usage().await.map_err(|e| e.to_string())?)
+        }
+    };
+    let output = output.map_err(|e| e.to_string())?;
+    println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    Ok(())
+}
+
+// Run a helper subcommand and exit
+async fn run_command(command: Command, config: Option<&str>) -> Result<(), String> {
+    match command {
+        Command::Run => unreachable!("the server is started by main"),
+        Command::Validate => {
+            let configs = load_configs(config)?;
+            let mut problems = 0;
+            for (index, config) in configs.iter().enumerate() {
+                let errors = validate_config(config, &create_registry(config));
+                for error in &errors {
+                    match configs.len() {
+                        1 => eprintln!("{}", error),
+                        _ => eprintln!("instance {}: {}", index, error),
+                    }
+                }
+                problems += errors.len();
+            }
+            if problems > 0 {
+                return Err(format!("Found {} problem(s)", problems));
+            }
+            println!("Config is valid");
+        }
+        Command::Policies { available } => {
+            if available || config.is_none() {
+                let config = match config {
+                    Some(_) => load_configs(config)?.remove(0),
+                    None => ConfigBuilder::new().build()?,
+                };
+                for id in create_registry(&config).policy_ids() {
+                    println!("{}", id);
+                }
+                return Ok(());
+            }
+
+            let configs = load_configs(config)?;
+            for (index, config) in configs.iter().enumerate() {
+                if configs.len() > 1 {
+                    println!(
+                        "instance {} ({}:{})",
+                        index, config.server.bind_address, config.server.port
+                    );
+                }
+                for (position, policy) in config.policies.iter().enumerate() {
+                    println!("{}. {} {}", position + 1, policy.id, policy.provider);
+                }
+            }
+        }
+        Command::Schema { store } => {
+            let stores = match store {
+                Some(store) => vec![store],
+                None => SchemaStore::value_variants().to_vec(),
+            };
+            for store in stores {
+                for migration in store.migrations()? {
+                    println!("-- {} {}", migration.version, migration.name);
+                    println!("{}", migration.sql.trim_end());
+                    println!();
+                }
+            }
+        }
+        Command::Token {
+            policy,
+            instance,
+            command,
+        } => {
+            let config = select_instance(load_configs(config)?, instance)?;
+            run_token_command(&config, policy.as_deref(), command).await?;
+        }
+        Command::Init { preset, output } => {
+            let rendered = presets::render(preset)?;
+            match output {
+                Some(path) => {
+                    let mut file = std::fs::OpenOptions::new()
+                        .write(true)
+                        .create_new(true)
+                        .open(&path)
+                        .map_err(|e| format!("Failed to create {}: {}", path, e))?;
+                    file.write_all(rendered.as_bytes())
+                        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+                    eprintln!("Wrote {}", path);
+                }
+                None => print!("{}", rendered),
+            }
+        }
+        Command::GenerateKey => println!("{}", encryption::generate_key()),
+        Command::Encrypt { value } => {
+            let value = match value {
+                Some(value) => value,
+                None => {
+                    let mut value = String::new();
+                    std::io::stdin()
+                        .read_to_string(&mut value)
+                        .map_err(|e| e.to_string())?;
+                    value.trim_end_matches('\n').to_string()
+                }
+            };
+            println!(
+                "{}",
+                encryption::encrypt_value(&encryption::load_key()?, &value)
+            );
+        }
+        Command::Graph { format } => {
+            let configs = load_configs(config)?;
+            let graphs = graph::build_graphs(&configs).await?;
+            print!("{}", graph::render(&graphs, format));
+        }
+        Command::Simulate {
+            method,
+            path,
+            headers,
+            data,
+            mock_db,
+            instance,
+        } => {
+            let config = select_instance(load_configs(config)?, instance)?;
+
+            if let Some(mock_path) = mock_db {
+                let content = std::fs::read_to_string(&mock_path)
+                    .map_err(|e| format!("Failed to read {}: {}", mock_path, e))?;
+                let fixtures: MockDatabases = serde_yaml::from_str(&content)
+                    .map_err(|e| format!("Invalid mock database file {}: {}", mock_path, e))?;
+                mock::install(fixtures)?;
+            }
+
+            let mut request = SimulatedRequest::new(method, path);
+            for header in &headers {
+                request = request.header(header)?;
+            }
+            request.body = data.unwrap_or_default().into_bytes();
+
+            print!("{}", simulate(&config, request).await?);
+        }
+    }
+    Ok(())
 }
 
 #[tokio::main]
 async fn main() {
-    // Initialize tracing with DEBUG level
-    tracing_subscriber::fmt()
-        .with_max_level(tracing::Level::DEBUG)
-        .init();
-
     // Parse command line arguments
     let args = Args::parse();
+    if let Some(profile) = &args.profile {
+        config::set_profile(profile);
+    }
+
+    match args.command {
+        None | Some(Command::Run) => {}
+        Some(command) => {
+            if let Err(e) = run_command(command, args.config.as_deref()).await {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+    }
+
+    let Some(config) = args.config else {
+        Args::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "--config is required",
+            )
+            .exit();
+    };
+
+    // Initialize tracing with DEBUG level, and the configured levels inside policies
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::fmt::layer().with_filter(PolicyLogFilter::new(LevelFilter::DEBUG)),
+        )
+        .init();
 
     // Start the server with the config file
-    start_with_config(&args.config).await;
+    start_with_config(&config).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(policies: serde_json::Value) -> Config {
+        let mut config = ConfigBuilder::new().build().unwrap();
+        config.policies = serde_json::from_value(policies).unwrap();
+        config
+    }
+
+    fn managed(id: &str) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "provider": BearerAuthManagedPolicyFactory::policy_id(),
+            "parameters": {},
+        })
+    }
+
+    #[test]
+    fn test_args() {
+        Args::command().debug_assert();
+        let parse = |args: &[&str]| Args::try_parse_from([&["bouncer"], args].concat());
+
+        // The gateway runs without a command
+        assert!(parse(&["--config", "config.yaml"])
+            .unwrap()
+            .command
+            .is_none());
+        assert!(matches!(
+            parse(&["schema", "--store", "tokens"]).unwrap().command,
+            Some(Command::Schema {
+                store: Some(SchemaStore::Tokens)
+            })
+        ));
+        assert!(parse(&["token", "create", "--role", "admin"]).is_ok());
+        assert!(parse(&["token", "create", "--role", "admin", "--if-missing"]).is_err());
+    }
+
+    #[test]
+    fn test_select_instance() {
+        let configs = || vec![config(serde_json::json!([])), config(serde_json::json!([]))];
+        assert!(select_instance(configs(), 1).is_ok());
+        let error = select_instance(configs(), 2).err().unwrap();
+        assert_eq!(error, "Config defines 2 instances, there is no instance 2");
+    }
+
+    #[test]
+    fn test_managed_policy() {
+        let provider = BearerAuthManagedPolicyFactory::policy_id();
+        let none = config(serde_json::json!([{
+            "id": "mock",
+            "provider": "@bouncer/development/mock/v1",
+            "parameters": {},
+        }]));
+        assert_eq!(
+            managed_policy(&none, None).err().unwrap(),
+            format!("Config has no {} policy", provider)
+        );
+
+        let one = config(serde_json::json!([managed("tokens")]));
+        assert_eq!(managed_policy(&one, None).unwrap().id, "tokens");
+        assert!(managed_policy(&one, Some("other")).is_err());
+
+        // Several have to be told apart
+        let several = config(serde_json::json!([
+            managed("internal"),
+            managed("partners")
+        ]));
+        let error = managed_policy(&several, None).err().unwrap();
+        assert!(error.ends_with("pick one with --policy: internal, partners"));
+        assert_eq!(
+            managed_policy(&several, Some("partners")).unwrap().id,
+            "partners"
+        );
+    }
+
+    #[test]
+    fn test_schema() {
+        for store in SchemaStore::value_variants() {
+            if let Ok(migrations) = store.migrations() {
+                assert!(!migrations.is_empty());
+            }
+        }
+        assert!(SchemaStore::Tokens.migrations().is_ok());
+    }
 }
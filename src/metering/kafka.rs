@@ -0,0 +1,56 @@
+use super::{UsageRecord, UsageSink};
+use async_trait::async_trait;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::ClientConfig;
+use std::time::Duration;
+
+// How long a record may wait for room in the producer's queue
+const QUEUE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Publishes usage records to a Kafka topic as JSON, keyed by identity so
+/// each identity's records stay in order on one partition
+pub struct KafkaUsageSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaUsageSink {
+    pub fn new(brokers: &str, topic: &str) -> Result<Self, String> {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            // Retried sends mustn't duplicate records
+            .set("enable.idempotence", "true")
+            .create()
+            .map_err(|e| format!("Failed to create Kafka producer: {}", e))?;
+
+        Ok(Self {
+            producer,
+            topic: topic.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl UsageSink for KafkaUsageSink {
+    async fn write(&self, records: &[UsageRecord]) -> Result<(), String> {
+        let payloads = records
+            .iter()
+            .map(|record| serde_json::to_string(record).map(|payload| (record, payload)))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+
+        let sends = payloads.iter().map(|(record, payload)| {
+            self.producer.send(
+                FutureRecord::to(&self.topic)
+                    .key(&record.owner)
+                    .payload(payload),
+                QUEUE_TIMEOUT,
+            )
+        });
+        for result in futures::future::join_all(sends).await {
+            result.map_err(|(e, _)| e.to_string())?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,14 @@
+-- Usage of each identity per metering period, written by the usage meter.
+CREATE TABLE IF NOT EXISTS bouncer_usage_records (
+    instance TEXT NOT NULL,
+    owner TEXT NOT NULL,
+    period_start BIGINT NOT NULL,
+    period_end BIGINT NOT NULL,
+    requests BIGINT NOT NULL,
+    bytes_in BIGINT NOT NULL,
+    bytes_out BIGINT NOT NULL,
+    PRIMARY KEY (instance, owner, period_start)
+);
+
+CREATE INDEX IF NOT EXISTS bouncer_usage_records_period_start
+    ON bouncer_usage_records (period_start);
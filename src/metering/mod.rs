@@ -0,0 +1,378 @@
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+#[cfg(feature = "s3")]
+pub mod s3;
+
+use crate::admin::now_secs;
+use crate::config::{DatabasesConfig, MeteringConfig, UsageSinkConfig};
+use crate::policy::traits::buffered_body;
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::Request;
+use futures::StreamExt;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Usage of one identity over a metering period
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct UsageRecord {
+    /// The replica that counted the usage, so records from several replicas
+    /// for the same period don't collide
+    pub instance: String,
+    pub owner: String,
+    /// Unix timestamps in seconds
+    pub period_start: i64,
+    pub period_end: i64,
+    pub requests: u64,
+    /// Request body bytes received from the client
+    pub bytes_in: u64,
+    /// Response body bytes sent to the client
+    pub bytes_out: u64,
+}
+
+/// Where usage records are written at the end of each period
+#[async_trait]
+pub trait UsageSink: Send + Sync + 'static {
+    async fn write(&self, records: &[UsageRecord]) -> Result<(), String>;
+}
+
+// The host name, which is the pod name on Kubernetes, or else a random ID
+fn instance_id() -> String {
+    std::env::var("HOSTNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>()))
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counts {
+    requests: u64,
+    bytes_in: u64,
+    bytes_out: u64,
+}
+
+// Usage counted since `started`
+struct Period {
+    started: i64,
+    counts: HashMap<String, Counts>,
+}
+
+/// Counts requests and bytes per identity, and writes them to a sink as
+/// usage records once per period
+///
+/// Requests are identified by the `x-bouncer-owner` header set by
+/// authentication policies; anonymous requests aren't metered. If a write
+/// fails, its counts are kept and written with the next period's, so records
+/// can span more than one period but usage isn't lost while Bouncer runs.
+pub struct UsageMeter {
+    instance: String,
+    sink: Arc<dyn UsageSink>,
+    period: Mutex<Period>,
+}
+
+impl UsageMeter {
+    pub fn new(sink: Arc<dyn UsageSink>) -> Arc<Self> {
+        Arc::new(Self {
+            instance: instance_id(),
+            sink,
+            period: Mutex::new(Period {
+                started: now_secs(),
+                counts: HashMap::new(),
+            }),
+        })
+    }
+
+    /// Count a finished request
+    pub fn record(&self, owner: &str, bytes_in: u64, bytes_out: u64) {
+        let mut period = self.period.lock().unwrap();
+        let counts = period.counts.entry(owner.to_string()).or_default();
+        counts.requests += 1;
+        counts.bytes_in += bytes_in;
+        counts.bytes_out += bytes_out;
+    }
+
+    /// Start metering a request that passed the policy chain, if it has an identity
+    ///
+    /// The request body is counted as the upstream reads it, and the usage is
+    /// recorded once the returned guard's response body has been sent.
+    pub fn start(self: &Arc<Self>, request: Request<Body>) -> (Request<Body>, Option<Metered>) {
+        let owner = request
+            .headers()
+            .get("x-bouncer-owner")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let Some(owner) = owner else {
+            return (request, None);
+        };
+
+        // The forwarder sends a fully buffered body without reading it again
+        let buffered = buffered_body(&request)
+            .and_then(|body| body.complete_bytes())
+            .map(|bytes| bytes.len() as u64);
+        let bytes_in = Arc::new(AtomicU64::new(buffered.unwrap_or(0)));
+        let request = match buffered {
+            Some(_) => request,
+            None => {
+                let counter = bytes_in.clone();
+                request.map(|body| {
+                    Body::from_stream(body.into_data_stream().inspect(move |chunk| {
+                        if let Ok(chunk) = chunk {
+                            counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                        }
+                    }))
+                })
+            }
+        };
+
+        let metered = Metered {
+            meter: self.clone(),
+            owner,
+            bytes_in,
+            bytes_out: 0,
+        };
+        (request, Some(metered))
+    }
+
+    /// Write the usage counted so far as records for the period ending now
+    pub async fn flush(&self) {
+        let now = now_secs();
+        let (started, counts) = {
+            let mut period = self.period.lock().unwrap();
+            let started = std::mem::replace(&mut period.started, now);
+            (started, std::mem::take(&mut period.counts))
+        };
+        if counts.is_empty() {
+            return;
+        }
+
+        let records: Vec<UsageRecord> = counts
+            .iter()
+            .map(|(owner, counts)| UsageRecord {
+                instance: self.instance.clone(),
+                owner: owner.clone(),
+                period_start: started,
+                period_end: now,
+                requests: counts.requests,
+                bytes_in: counts.bytes_in,
+                bytes_out: counts.bytes_out,
+            })
+            .collect();
+
+        if let Err(e) = self.sink.write(&records).await {
+            tracing::error!("Failed to write {} usage records: {}", records.len(), e);
+            // Keep the usage for the next attempt
+            let mut period = self.period.lock().unwrap();
+            period.started = started;
+            for (owner, counts) in counts {
+                let current = period.counts.entry(owner).or_default();
+                current.requests += counts.requests;
+                current.bytes_in += counts.bytes_in;
+                current.bytes_out += counts.bytes_out;
+            }
+        }
+    }
+
+    /// Write usage records every `interval`
+    pub fn spawn_flush(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the meter is dropped
+        let meter = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, before anything was counted
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(meter) = meter.upgrade() else {
+                    break;
+                };
+                meter.flush().await;
+            }
+        });
+    }
+}
+
+/// A metered request, recorded when dropped
+pub struct Metered {
+    meter: Arc<UsageMeter>,
+    owner: String,
+    bytes_in: Arc<AtomicU64>,
+    bytes_out: u64,
+}
+
+impl Metered {
+    /// Count the response body as it's sent, and record the request once it
+    /// ends or the client goes away
+    pub fn track_body(self, body: Body) -> Body {
+        let state = (body.into_data_stream(), self);
+        let stream = futures::stream::unfold(state, |(mut stream, mut metered)| async move {
+            let chunk = stream.next().await?;
+            if let Ok(chunk) = &chunk {
+                metered.bytes_out += chunk.len() as u64;
+            }
+            Some((chunk, (stream, metered)))
+        });
+        Body::from_stream(stream)
+    }
+}
+
+impl Drop for Metered {
+    fn drop(&mut self) {
+        self.meter.record(
+            &self.owner,
+            self.bytes_in.load(Ordering::Relaxed),
+            self.bytes_out,
+        );
+    }
+}
+
+/// Create the meter for the metering config and start writing its records
+pub async fn create_meter(
+    config: &MeteringConfig,
+    databases: &DatabasesConfig,
+) -> Result<Arc<UsageMeter>, String> {
+    if config.interval_secs == 0 {
+        return Err("metering.interval_secs must be greater than 0".to_string());
+    }
+
+    let sink = match &config.sink {
+        UsageSinkConfig::Postgres { run_migrations } => {
+            create_postgres_sink(*run_migrations, databases).await?
+        }
+        UsageSinkConfig::S3 {
+            bucket,
+            prefix,
+            region,
+        } => create_s3_sink(bucket, prefix, region.as_deref()).await?,
+        UsageSinkConfig::Kafka { brokers, topic } => create_kafka_sink(brokers, topic)?,
+    };
+
+    let meter = UsageMeter::new(sink);
+    meter.spawn_flush(Duration::from_secs(config.interval_secs));
+    Ok(meter)
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_sink(
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn UsageSink>, String> {
+    crate::database::validate_database_config(databases, "postgres").map_err(|e| e.to_string())?;
+    let postgres_config = databases
+        .postgres
+        .as_ref()
+        .ok_or_else(|| "PostgreSQL configuration is required".to_string())?;
+    let pools = crate::database::get_postgres_pools(postgres_config)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if run_migrations {
+        crate::database::migrations::run_postgres_migrations(
+            &pools.primary(),
+            postgres::POSTGRES_MIGRATIONS,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+    }
+
+    Ok(Arc::new(postgres::PostgresUsageSink::new(pools.primary())))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_sink(
+    _run_migrations: bool,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn UsageSink>, String> {
+    Err("PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string())
+}
+
+#[cfg(feature = "s3")]
+async fn create_s3_sink(
+    bucket: &str,
+    prefix: &str,
+    region: Option<&str>,
+) -> Result<Arc<dyn UsageSink>, String> {
+    Ok(Arc::new(
+        s3::S3UsageSink::new(bucket.to_string(), prefix.to_string(), region).await,
+    ))
+}
+
+#[cfg(not(feature = "s3"))]
+async fn create_s3_sink(
+    _bucket: &str,
+    _prefix: &str,
+    _region: Option<&str>,
+) -> Result<Arc<dyn UsageSink>, String> {
+    Err("S3 support is not enabled. Rebuild with the 's3' feature.".to_string())
+}
+
+#[cfg(feature = "kafka")]
+fn create_kafka_sink(brokers: &str, topic: &str) -> Result<Arc<dyn UsageSink>, String> {
+    Ok(Arc::new(kafka::KafkaUsageSink::new(brokers, topic)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn create_kafka_sink(_brokers: &str, _topic: &str) -> Result<Arc<dyn UsageSink>, String> {
+    Err("Kafka support is not enabled. Rebuild with the 'kafka' feature.".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct MemorySink {
+        records: Mutex<Vec<UsageRecord>>,
+        fail: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl UsageSink for MemorySink {
+        async fn write(&self, records: &[UsageRecord]) -> Result<(), String> {
+            if self.fail.load(Ordering::Relaxed) {
+                return Err("unavailable".to_string());
+            }
+            self.records.lock().unwrap().extend_from_slice(records);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_meter_request() {
+        let sink = Arc::new(MemorySink::default());
+        let meter = UsageMeter::new(sink.clone());
+
+        let request = Request::post("/upload")
+            .header("x-bouncer-owner", "alice")
+            .body(Body::from("hello"))
+            .unwrap();
+        let (request, metered) = meter.start(request);
+        axum::body::to_bytes(request.into_body(), 1024)
+            .await
+            .unwrap();
+        let body = metered.unwrap().track_body(Body::from("hello world"));
+        axum::body::to_bytes(body, 1024).await.unwrap();
+
+        // Anonymous requests aren't metered
+        let (_, metered) = meter.start(Request::get("/").body(Body::empty()).unwrap());
+        assert!(metered.is_none());
+
+        // Usage survives a failed write
+        sink.fail.store(true, Ordering::Relaxed);
+        meter.flush().await;
+        sink.fail.store(false, Ordering::Relaxed);
+        meter.record("alice", 0, 4);
+        meter.flush().await;
+
+        let records = sink.records.lock().unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].owner, "alice");
+        assert_eq!(records[0].requests, 2);
+        assert_eq!(records[0].bytes_in, 5);
+        assert_eq!(records[0].bytes_out, 15);
+    }
+}
@@ -0,0 +1,58 @@
+use super::{UsageRecord, UsageSink};
+use crate::database::migrations::Migration;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Schema for the usage records table
+///
+/// Versions share the table of the other Bouncer migrations, so they start at
+/// 200 to stay clear of the token and RBAC stores'.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[Migration {
+    version: 200,
+    name: "create_usage_records",
+    sql: include_str!("migrations/postgres/0200_create_usage_records.sql"),
+}];
+
+/// Writes usage records to the `bouncer_usage_records` table
+///
+/// A record written again after a failed attempt replaces the earlier one, so
+/// retries never count usage twice.
+pub struct PostgresUsageSink {
+    pool: Arc<sqlx::Pool<sqlx::Postgres>>,
+}
+
+impl PostgresUsageSink {
+    pub fn new(pool: Arc<sqlx::Pool<sqlx::Postgres>>) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UsageSink for PostgresUsageSink {
+    async fn write(&self, records: &[UsageRecord]) -> Result<(), String> {
+        let mut tx = self.pool.begin().await.map_err(|e| e.to_string())?;
+
+        for record in records {
+            sqlx::query(
+                "INSERT INTO bouncer_usage_records \
+                 (instance, owner, period_start, period_end, requests, bytes_in, bytes_out) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7) \
+                 ON CONFLICT (instance, owner, period_start) DO UPDATE SET \
+                 period_end = EXCLUDED.period_end, requests = EXCLUDED.requests, \
+                 bytes_in = EXCLUDED.bytes_in, bytes_out = EXCLUDED.bytes_out",
+            )
+            .bind(&record.instance)
+            .bind(&record.owner)
+            .bind(record.period_start)
+            .bind(record.period_end)
+            .bind(record.requests as i64)
+            .bind(record.bytes_in as i64)
+            .bind(record.bytes_out as i64)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        }
+
+        tx.commit().await.map_err(|e| e.to_string())
+    }
+}
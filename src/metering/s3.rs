@@ -0,0 +1,60 @@
+use super::{UsageRecord, UsageSink};
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+
+/// Writes each period's usage records to S3 as one JSON Lines object
+///
+/// Objects are keyed `<prefix><period_start>-<instance>.jsonl`, so replicas
+/// never overwrite each other and a retried period replaces its earlier
+/// object. Credentials come from the default AWS chain.
+pub struct S3UsageSink {
+    client: aws_sdk_s3::Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3UsageSink {
+    pub async fn new(bucket: String, prefix: String, region: Option<&str>) -> Self {
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+        if let Some(region) = region {
+            loader = loader.region(aws_config::Region::new(region.to_string()));
+        }
+
+        Self {
+            client: aws_sdk_s3::Client::new(&loader.load().await),
+            bucket,
+            prefix,
+        }
+    }
+}
+
+#[async_trait]
+impl UsageSink for S3UsageSink {
+    async fn write(&self, records: &[UsageRecord]) -> Result<(), String> {
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+
+        let mut body = Vec::new();
+        for record in records {
+            serde_json::to_writer(&mut body, record).map_err(|e| e.to_string())?;
+            body.push(b'\n');
+        }
+
+        let key = format!(
+            "{}{}-{}.jsonl",
+            self.prefix, first.period_start, first.instance
+        );
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type("application/x-ndjson")
+            .body(ByteStream::from(body))
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
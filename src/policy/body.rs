@@ -0,0 +1,252 @@
+use crate::config::ServerConfig;
+use crate::policy::matcher::RouteMatcher;
+use crate::policy::traits::BufferedBody;
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::{header, Method, Request, Response, StatusCode};
+use futures::StreamExt;
+use std::error::Error;
+use std::fmt;
+
+/// Read up to `limit` bytes of the request's body into a [`BufferedBody`]
+///
+/// Does nothing if the body was already buffered. If the body can't be read,
+/// the error is returned and the request is lost, like when a client disconnects.
+pub async fn inspect_body(
+    request: Request<Body>,
+    limit: usize,
+) -> Result<Request<Body>, axum::Error> {
+    if limit == 0 || request.extensions().get::<BufferedBody>().is_some() {
+        return Ok(request);
+    }
+
+    let (mut parts, body) = request.into_parts();
+    let mut stream = body.into_data_stream();
+    let mut chunks = Vec::new();
+    let mut read = 0;
+    let mut complete = true;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk?;
+        read += chunk.len();
+        chunks.push(chunk);
+        // A body of exactly `limit` bytes is only cut off if more follows
+        if read > limit {
+            complete = false;
+            break;
+        }
+    }
+
+    // Join the chunks into one buffer that the body and every policy share
+    let prefix = match chunks.len() {
+        1 => chunks.pop().unwrap(),
+        _ => Bytes::from(chunks.concat()),
+    };
+
+    let body = if complete {
+        Body::from(prefix.clone())
+    } else {
+        // Replay what was read, then stream the rest as it arrives
+        let replay = futures::stream::once(futures::future::ready(Ok(prefix.clone())));
+        Body::from_stream(replay.chain(stream))
+    };
+    parts
+        .extensions
+        .insert(BufferedBody::new(prefix.slice(..read.min(limit)), complete));
+    Ok(Request::from_parts(parts, body))
+}
+
+/// The error a body fails with once it's longer than its [`BodyLimits`] allow
+#[derive(Debug)]
+pub struct BodyTooLarge;
+
+impl fmt::Display for BodyTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "request body too large")
+    }
+}
+
+impl Error for BodyTooLarge {}
+
+/// Whether `error`, or any error it was caused by, is [`BodyTooLarge`]
+pub fn is_too_large(error: &(dyn Error + 'static)) -> bool {
+    std::iter::successors(Some(error), |&error| error.source())
+        .any(|error| error.is::<BodyTooLarge>())
+}
+
+/// The 413 sent for bodies over their limit
+pub fn too_large_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(Body::from("Request body too large"))
+        .unwrap()
+}
+
+/// Request body size limits from `server.max_body_size` and `server.max_body_size_routes`
+#[derive(Debug, Clone, Default)]
+pub struct BodyLimits {
+    default: Option<usize>,
+    routes: Vec<(RouteMatcher, usize)>,
+}
+
+impl BodyLimits {
+    pub fn new(server: &ServerConfig) -> Result<Self, String> {
+        let routes = server
+            .max_body_size_routes
+            .iter()
+            .map(|route| {
+                RouteMatcher::parse(&route.route)
+                    .map(|matcher| (matcher, route.max_body_size))
+                    .map_err(|e| format!("server.max_body_size_routes: {}", e))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            default: server.max_body_size,
+            routes,
+        })
+    }
+
+    /// The limit for a request, from the first matching route or `max_body_size`
+    pub fn limit(&self, method: &Method, path: &str) -> Option<usize> {
+        self.routes
+            .iter()
+            .find(|(matcher, _)| matcher.matches(method, path))
+            .map(|(_, limit)| *limit)
+            .or(self.default)
+    }
+
+    /// Reject a request whose `Content-Length` is over its limit
+    ///
+    /// Bodies without a `Content-Length`, or longer than it, fail with
+    /// [`BodyTooLarge`] once they pass the limit, whoever is reading them.
+    #[allow(clippy::result_large_err)]
+    pub fn apply(&self, request: Request<Body>) -> Result<Request<Body>, Response<Body>> {
+        let Some(limit) = self.limit(request.method(), request.uri().path()) else {
+            return Ok(request);
+        };
+        let declared = request
+            .headers()
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok());
+        if declared.is_some_and(|declared| declared > limit as u64) {
+            return Err(too_large_response());
+        }
+
+        // Empty bodies are left as they are, so they can still be told apart
+        let (parts, body) = request.into_parts();
+        if body.is_end_stream() {
+            return Ok(Request::from_parts(parts, body));
+        }
+        let mut read = 0;
+        let limited = body.into_data_stream().map(move |chunk| {
+            let chunk = chunk?;
+            read += chunk.len();
+            match read > limit {
+                true => Err(axum::Error::new(BodyTooLarge)),
+                false => Ok(chunk),
+            }
+        });
+        Ok(Request::from_parts(parts, Body::from_stream(limited)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_inspect_body() {
+        let chunks = vec![Ok::<_, axum::Error>("hello "), Ok("wide "), Ok("world")];
+        let request = Request::post("/")
+            .body(Body::from_stream(futures::stream::iter(chunks)))
+            .unwrap();
+
+        let request = inspect_body(request, 8).await.unwrap();
+        let buffered = request.extensions().get::<BufferedBody>().unwrap().clone();
+        assert_eq!(buffered.bytes().as_ref(), b"hello wi");
+        assert!(!buffered.is_complete());
+
+        // Nothing is lost for the upstream
+        let body = axum::body::to_bytes(request.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"hello wide world");
+
+        let request = Request::post("/").body(Body::from("short")).unwrap();
+        let request = inspect_body(request, 8).await.unwrap();
+        assert!(request
+            .extensions()
+            .get::<BufferedBody>()
+            .unwrap()
+            .is_complete());
+    }
+
+    #[tokio::test]
+    async fn test_inspect_body_at_limit() {
+        let buffered =
+            |request: &Request<Body>| request.extensions().get::<BufferedBody>().unwrap().clone();
+
+        let request = Request::post("/").body(Body::from("12345678")).unwrap();
+        let request = inspect_body(request, 8).await.unwrap();
+        assert!(buffered(&request).is_complete());
+        assert_eq!(
+            buffered(&request).complete_bytes().unwrap().as_ref(),
+            b"12345678"
+        );
+
+        let chunks = vec![Ok::<_, axum::Error>("1234"), Ok("5678")];
+        let request = Request::post("/")
+            .body(Body::from_stream(futures::stream::iter(chunks)))
+            .unwrap();
+        let request = inspect_body(request, 8).await.unwrap();
+        assert!(buffered(&request).is_complete());
+
+        // One more frame after the limit, however short, makes it incomplete
+        let chunks = vec![Ok::<_, axum::Error>("12345678"), Ok("9")];
+        let request = Request::post("/")
+            .body(Body::from_stream(futures::stream::iter(chunks)))
+            .unwrap();
+        let request = inspect_body(request, 8).await.unwrap();
+        assert!(!buffered(&request).is_complete());
+        assert_eq!(buffered(&request).bytes().as_ref(), b"12345678");
+        let body = axum::body::to_bytes(request.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"123456789");
+    }
+
+    #[tokio::test]
+    async fn test_body_limits() {
+        let server = ServerConfig {
+            max_body_size: Some(8),
+            max_body_size_routes: vec![crate::config::BodySizeRouteConfig {
+                route: "POST /uploads/*".to_string(),
+                max_body_size: 1024,
+            }],
+            ..ServerConfig::default()
+        };
+        let limits = BodyLimits::new(&server).unwrap();
+        assert_eq!(limits.limit(&Method::POST, "/uploads/a.png"), Some(1024));
+        assert_eq!(limits.limit(&Method::PUT, "/uploads/a.png"), Some(8));
+
+        let declared = Request::post("/users")
+            .header(header::CONTENT_LENGTH, "16")
+            .body(Body::from("a".repeat(16)))
+            .unwrap();
+        let response = limits.apply(declared).unwrap_err();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Undeclared bodies are cut off at the limit
+        let chunks = vec![Ok::<_, axum::Error>("hello "), Ok("world")];
+        let streamed = Request::post("/users")
+            .body(Body::from_stream(futures::stream::iter(chunks)))
+            .unwrap();
+        let streamed = limits.apply(streamed).unwrap();
+        let error = inspect_body(streamed, 1024).await.unwrap_err();
+        assert!(is_too_large(&error));
+
+        let upload = Request::post("/uploads/a.png")
+            .body(Body::from("a".repeat(16)))
+            .unwrap();
+        assert!(limits.apply(upload).is_ok());
+    }
+}
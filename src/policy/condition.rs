@@ -0,0 +1,276 @@
+use crate::policy::failure::FailureMode;
+use crate::policy::matcher::{compile_all, RouteMatcher};
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyHealth, PolicyResult, ResponsePolicyResult};
+use crate::policy::websocket::WsPolicy;
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{header, HeaderName, HeaderValue, Method, Request, Response},
+};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Requests a policy runs on, from its `apply_if` config
+///
+/// A request must match every list that isn't empty, and one entry in each.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ApplyIf {
+    /// Route patterns, e.g. `/api/**` or `POST /orders`
+    #[serde(default)]
+    pub paths: Vec<String>,
+    #[serde(default)]
+    pub methods: Vec<String>,
+    /// Host names, where `*.example.com` matches its subdomains
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Headers the request must have with the given value, or any value for `*`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+}
+
+/// A compiled [`ApplyIf`]
+#[derive(Debug, Clone)]
+pub struct Condition {
+    paths: Vec<RouteMatcher>,
+    methods: Vec<Method>,
+    hosts: Vec<String>,
+    headers: Vec<(HeaderName, Option<HeaderValue>)>,
+}
+
+impl Condition {
+    pub fn new(config: &ApplyIf) -> Result<Self, String> {
+        let paths = compile_all(&config.paths).map_err(|e| format!("apply_if: {}", e))?;
+        let methods = config
+            .methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|_| format!("apply_if: invalid method '{}'", method))
+            })
+            .collect::<Result<_, _>>()?;
+        let hosts = config
+            .hosts
+            .iter()
+            .map(|host| host.to_ascii_lowercase())
+            .collect();
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| format!("apply_if: invalid header '{}': {}", name, e))?;
+                let value = match value.as_str() {
+                    "*" => None,
+                    value => Some(HeaderValue::from_str(value).map_err(|e| {
+                        format!("apply_if: invalid value for header '{}': {}", name, e)
+                    })?),
+                };
+                Ok((name, value))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            paths,
+            methods,
+            hosts,
+            headers,
+        })
+    }
+
+    pub fn matches(&self, request: &Request<Body>) -> bool {
+        let method = request.method();
+        let path = request.uri().path();
+        (self.paths.is_empty() || self.paths.iter().any(|route| route.matches(method, path)))
+            && (self.methods.is_empty() || self.methods.contains(method))
+            && (self.hosts.is_empty()
+                || host(request).is_some_and(|host| {
+                    self.hosts
+                        .iter()
+                        .any(|pattern| host_matches(pattern, &host))
+                }))
+            && self.headers.iter().all(|(name, value)| {
+                let mut values = request.headers().get_all(name).iter();
+                match value {
+                    Some(value) => values.any(|actual| actual == value),
+                    None => values.next().is_some(),
+                }
+            })
+    }
+}
+
+// The host a request was sent to, without its port
+fn host(request: &Request<Body>) -> Option<String> {
+    let host = match request.headers().get(header::HOST) {
+        Some(host) => host.to_str().ok()?,
+        None => request.uri().host()?,
+    };
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    Some(host.to_ascii_lowercase())
+}
+
+fn host_matches(pattern: &str, host: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|subdomain| subdomain.len() > 1 && subdomain.ends_with('.')),
+        None => pattern == host,
+    }
+}
+
+/// A policy that only runs on requests matching its `apply_if`
+///
+/// The middleware checks [`Policy::applies_to`] and lets other requests
+/// through without calling the policy. `process` checks it as well, for
+/// combinators, which call the policies inside them directly.
+pub struct ConditionalPolicy {
+    inner: Box<dyn Policy>,
+    condition: Condition,
+}
+
+impl ConditionalPolicy {
+    pub fn new(inner: Box<dyn Policy>, condition: Condition) -> Self {
+        Self { inner, condition }
+    }
+}
+
+#[async_trait]
+impl Policy for ConditionalPolicy {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn category(&self) -> &'static str {
+        self.inner.category()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn version(&self) -> &'static str {
+        self.inner.version()
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        self.inner.register_routes()
+    }
+
+    fn applies_to(&self, request: &Request<Body>) -> bool {
+        self.condition.matches(request) && self.inner.applies_to(request)
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        if self.applies_to(&request) {
+            self.inner.process(request).await
+        } else {
+            PolicyResult::Continue(request)
+        }
+    }
+
+    fn processes_requests(&self) -> bool {
+        self.inner.processes_requests()
+    }
+
+    fn failure_mode(&self) -> FailureMode {
+        self.inner.failure_mode()
+    }
+
+    fn group(&self) -> Option<Arc<str>> {
+        self.inner.group()
+    }
+
+    async fn process_response(&self, response: Response<Body>) -> ResponsePolicyResult {
+        self.inner.process_response(response).await
+    }
+
+    fn processes_responses(&self) -> bool {
+        self.inner.processes_responses()
+    }
+
+    fn read_only(&self) -> bool {
+        self.inner.read_only()
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        self.inner.inspects_body()
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        self.inner.requires()
+    }
+
+    // Unlike scheduled policies, requests the policy skips still reach the
+    // policies relying on it, which reject them for lacking what it provides
+    fn provides(&self) -> Vec<Capability> {
+        self.inner.provides()
+    }
+
+    fn websocket(&self) -> Option<Arc<dyn WsPolicy>> {
+        self.inner.websocket()
+    }
+
+    async fn health(&self) -> PolicyHealth {
+        self.inner.health().await
+    }
+
+    async fn warm_up(&self) -> Result<(), String> {
+        self.inner.warm_up().await
+    }
+
+    async fn on_start(&self) -> Result<(), String> {
+        self.inner.on_start().await
+    }
+
+    async fn on_shutdown(&self) {
+        self.inner.on_shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition() {
+        let condition = Condition::new(&ApplyIf {
+            paths: vec!["/api/**".to_string()],
+            methods: vec!["get".to_string(), "POST".to_string()],
+            hosts: vec!["*.example.com".to_string()],
+            headers: HashMap::from([("x-canary".to_string(), "*".to_string())]),
+        })
+        .unwrap();
+        let request = |method: &str, path: &str, host: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header(header::HOST, host)
+                .header("x-canary", "1")
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        assert!(condition.matches(&request("GET", "/api/orders", "api.example.com:8443")));
+        assert!(!condition.matches(&request("GET", "/public/index.html", "api.example.com")));
+        assert!(!condition.matches(&request("DELETE", "/api/orders", "api.example.com")));
+        assert!(!condition.matches(&request("GET", "/api/orders", "example.com")));
+        assert!(!condition.matches(&request("GET", "/api/orders", "evilexample.com")));
+        let without_header = Request::get("/api/orders")
+            .header(header::HOST, "API.example.com")
+            .body(Body::empty())
+            .unwrap();
+        assert!(!condition.matches(&without_header));
+
+        assert!(Condition::new(&ApplyIf::default())
+            .unwrap()
+            .matches(&without_header));
+        assert!(Condition::new(&ApplyIf {
+            paths: vec!["/api/[".to_string()],
+            ..ApplyIf::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,137 @@
+use crate::policy::providers::bouncer::authentication::identity::Identity;
+use axum::{body::Body, http::Request};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+/// Typed state that policies pass along the chain
+///
+/// Kept in the request's extensions, so it never reaches the upstream and
+/// clients can't forge it. Read it with [`context`] and change it with
+/// [`context_mut`]. Authentication policies still set the `x-bouncer-*`
+/// headers as well, for the upstream and for policies that read them.
+#[derive(Debug, Clone, Default)]
+pub struct PolicyContext {
+    identity: Option<Identity>,
+    client_ip: Option<IpAddr>,
+    data: HashMap<String, Value>,
+}
+
+impl PolicyContext {
+    /// The identity an authentication policy resolved the request to
+    pub fn identity(&self) -> Option<&Identity> {
+        self.identity.as_ref()
+    }
+
+    pub fn set_identity(&mut self, identity: Identity) {
+        self.identity = Some(identity);
+    }
+
+    /// The roles of the identity, which can hold several separated by commas
+    pub fn roles(&self) -> impl Iterator<Item = &str> {
+        self.identity
+            .iter()
+            .flat_map(|identity| split_roles(&identity.role))
+    }
+
+    /// The client IP, resolved through the trusted proxies
+    pub fn client_ip(&self) -> Option<IpAddr> {
+        self.client_ip
+    }
+
+    pub fn set_client_ip(&mut self, ip: IpAddr) {
+        self.client_ip = Some(ip);
+    }
+
+    /// A value an earlier policy stored under `key`
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        self.data.get(key)
+    }
+
+    /// Store a value for later policies, returning the previous one
+    ///
+    /// Prefix keys with the policy's name to avoid clashes, e.g.
+    /// `geo.country`.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<Value>) -> Option<Value> {
+        self.data.insert(key.into(), value.into())
+    }
+
+    // Apply the changes a policy made to its copy of `original`, for policies
+    // that run concurrently
+    pub(crate) fn merge_changes(&mut self, original: Option<&PolicyContext>, changed: Self) {
+        if changed.identity.as_ref() != original.and_then(|original| original.identity.as_ref()) {
+            self.identity = changed.identity;
+        }
+        if changed.client_ip != original.and_then(|original| original.client_ip) {
+            self.client_ip = changed.client_ip;
+        }
+        for (key, value) in changed.data {
+            if original.and_then(|original| original.data.get(&key)) != Some(&value) {
+                self.data.insert(key, value);
+            }
+        }
+    }
+}
+
+/// The context of `request`, if any policy or the middleware added one
+pub fn context(request: &Request<Body>) -> Option<&PolicyContext> {
+    request.extensions().get::<PolicyContext>()
+}
+
+/// The context of `request`, added if it has none yet
+pub fn context_mut(request: &mut Request<Body>) -> &mut PolicyContext {
+    request
+        .extensions_mut()
+        .get_or_insert_default::<PolicyContext>()
+}
+
+fn split_roles(roles: &str) -> impl Iterator<Item = &str> {
+    roles
+        .split(',')
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+}
+
+/// The roles of the request's identity
+///
+/// Falls back to `x-bouncer-role` for requests authenticated by policies that
+/// only set the headers, such as plugins built before the context existed.
+/// Empty when the request has no identity.
+pub fn roles(request: &Request<Body>) -> Vec<String> {
+    let roles = match context(request).and_then(PolicyContext::identity) {
+        Some(identity) => identity.role.as_str(),
+        None => request
+            .headers()
+            .get("x-bouncer-role")
+            .and_then(|role| role.to_str().ok())
+            .unwrap_or_default(),
+    };
+    split_roles(roles).map(str::to_string).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_context() {
+        let mut request = Request::get("/")
+            .header("x-bouncer-role", "guest")
+            .body(Body::empty())
+            .unwrap();
+        assert!(context(&request).is_none());
+        assert_eq!(roles(&request), ["guest"]);
+
+        let mut identity = Identity::new("admin, billing");
+        identity.owner = Some("alice".to_string());
+        identity.apply(&mut request);
+        assert_eq!(roles(&request), ["admin", "billing"]);
+        assert_eq!(request.headers()["x-bouncer-owner"], "alice");
+
+        let context = context_mut(&mut request);
+        assert_eq!(context.identity().unwrap().owner.as_deref(), Some("alice"));
+        assert!(context.insert("geo.country", "NZ").is_none());
+        assert_eq!(context.get("geo.country"), Some(&Value::from("NZ")));
+        assert!(context.get("geo.city").is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use crate::config::ServerConfig;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Header clients and upstreams use to pass the time left for a request
+pub const REQUEST_TIMEOUT_HEADER: &str = "x-request-timeout";
+
+/// gRPC's equivalent of [`REQUEST_TIMEOUT_HEADER`]
+pub const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// When a request must be answered by
+///
+/// The policy middleware adds this to the request's extensions, so policies
+/// can bound their own work, e.g. database lookups, by the time that is left.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    /// Time left until the deadline, or zero once it has passed
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+
+    /// Tell the upstream how long it has, in the headers the request came with
+    ///
+    /// `X-Request-Timeout` is always set, in milliseconds. `grpc-timeout` is
+    /// rewritten if the client sent it, so gRPC upstreams see the remaining budget.
+    pub fn propagate(&self, headers: &mut HeaderMap) {
+        let remaining_ms = self.remaining().as_millis().max(1);
+        headers.insert(
+            HeaderName::from_static(REQUEST_TIMEOUT_HEADER),
+            HeaderValue::from(remaining_ms as u64),
+        );
+        if headers.contains_key(GRPC_TIMEOUT_HEADER) {
+            // gRPC allows at most 8 digits
+            let value = format!("{}m", remaining_ms.min(99_999_999));
+            headers.insert(
+                HeaderName::from_static(GRPC_TIMEOUT_HEADER),
+                HeaderValue::from_str(&value).unwrap(),
+            );
+        }
+    }
+}
+
+/// How request deadlines are computed at ingress
+#[derive(Debug, Clone, Default)]
+pub struct Deadlines {
+    /// Deadline for every request, if any
+    timeout: Option<Duration>,
+    /// Whether clients can set a deadline with `X-Request-Timeout` or `grpc-timeout`
+    client_timeouts: bool,
+}
+
+impl Deadlines {
+    pub fn new(config: &ServerConfig) -> Self {
+        Self {
+            timeout: config.request_timeout_ms.map(Duration::from_millis),
+            client_timeouts: config.client_timeouts,
+        }
+    }
+
+    /// The deadline for a request, starting now
+    ///
+    /// A timeout sent by the client can shorten the configured one, but never
+    /// extend it.
+    pub fn deadline(&self, headers: &HeaderMap) -> Option<Deadline> {
+        let client = if self.client_timeouts {
+            client_timeout(headers)
+        } else {
+            None
+        };
+        let timeout = match (self.timeout, client) {
+            (Some(timeout), Some(client)) => timeout.min(client),
+            (timeout, client) => timeout.or(client)?,
+        };
+        Instant::now().checked_add(timeout).map(Deadline)
+    }
+}
+
+// The timeout a client asked for, preferring `X-Request-Timeout`
+fn client_timeout(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    header(REQUEST_TIMEOUT_HEADER)
+        .and_then(parse_request_timeout)
+        .or_else(|| header(GRPC_TIMEOUT_HEADER).and_then(parse_grpc_timeout))
+}
+
+/// Parse an `X-Request-Timeout` value: milliseconds, or a number with an `ms` or `s` suffix
+pub fn parse_request_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Some(ms) = value.strip_suffix("ms") {
+        ms.trim().parse().ok().map(Duration::from_millis)
+    } else if let Some(secs) = value.strip_suffix('s') {
+        let secs = secs.trim().parse::<f64>().ok()?;
+        Duration::try_from_secs_f64(secs).ok()
+    } else {
+        value.parse().ok().map(Duration::from_millis)
+    }
+}
+
+/// Parse a `grpc-timeout` value: up to 8 digits followed by a unit (H, M, S, m, u or n)
+pub fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let unit = value.chars().last()?;
+    let amount = &value[..value.len() - unit.len_utf8()];
+    if amount.is_empty() || amount.len() > 8 || !amount.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = amount.parse().ok()?;
+    match unit {
+        'H' => Some(Duration::from_secs(amount * 3600)),
+        'M' => Some(Duration::from_secs(amount * 60)),
+        'S' => Some(Duration::from_secs(amount)),
+        'm' => Some(Duration::from_millis(amount)),
+        'u' => Some(Duration::from_micros(amount)),
+        'n' => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_timeouts() {
+        assert_eq!(
+            parse_request_timeout("250"),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(parse_request_timeout("2s"), Some(Duration::from_secs(2)));
+        assert_eq!(
+            parse_request_timeout("1.5s"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(parse_request_timeout("-1s"), None);
+        assert_eq!(parse_grpc_timeout("100m"), Some(Duration::from_millis(100)));
+        assert_eq!(parse_grpc_timeout("2S"), Some(Duration::from_secs(2)));
+        assert_eq!(parse_grpc_timeout("123456789m"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+
+        let deadlines = Deadlines {
+            timeout: Some(Duration::from_secs(5)),
+            client_timeouts: true,
+        };
+        let mut headers = HeaderMap::new();
+        headers.insert(REQUEST_TIMEOUT_HEADER, HeaderValue::from_static("60s"));
+        // Clients can't extend the configured timeout
+        let deadline = deadlines.deadline(&headers).unwrap();
+        assert!(deadline.remaining() <= Duration::from_secs(5));
+
+        headers.insert(GRPC_TIMEOUT_HEADER, HeaderValue::from_static("1S"));
+        deadline.propagate(&mut headers);
+        assert!(headers[GRPC_TIMEOUT_HEADER]
+            .to_str()
+            .unwrap()
+            .ends_with('m'));
+        assert_ne!(headers[REQUEST_TIMEOUT_HEADER], "60s");
+    }
+}
@@ -0,0 +1,423 @@
+use crate::config::DenialMessagesConfig;
+use crate::policy::traits::Policy;
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request, Response, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Header requests are correlated by, taken from the client if it sent one
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+// Longest request ID taken from a client
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+/// How much of a denial's reason is sent to the client
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DenialBodies {
+    /// Everything the policy explained about the denial
+    #[default]
+    Detailed,
+    /// Only the error code, `retry_after` and the request ID, for production
+    /// APIs that don't want to reveal how they're protected
+    Terse,
+}
+
+/// Why a policy rejected a request, in a form clients can act on, e.g.
+/// `{"error": "rate_limited", "policy": "@bouncer/traffic/rate-limit/v1",
+/// "retry_after": 30, "request_id": "..."}`
+///
+/// Policies reject with [`Denial::response`], which adds the denial to the
+/// response's extensions. The middleware then fills in the policy and request
+/// ID, logs it and renders the body according to `server.denial_bodies`.
+#[derive(Debug, Clone, Serialize)]
+pub struct Denial {
+    /// Machine-readable reason, e.g. `invalid_token`
+    pub error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    pub message: String,
+    /// Seconds until the request may succeed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Fields specific to the policy, e.g. the plans that include a feature
+    #[serde(flatten)]
+    pub details: serde_json::Map<String, serde_json::Value>,
+}
+
+// What `DenialBodies::Terse` sends of a denial
+#[derive(Serialize)]
+struct TerseDenial<'a> {
+    error: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_id: Option<&'a str>,
+}
+
+impl Denial {
+    pub fn new(error: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            policy: None,
+            message: message.into(),
+            retry_after: None,
+            request_id: None,
+            details: serde_json::Map::new(),
+        }
+    }
+
+    pub fn retry_after(mut self, secs: u64) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    /// Add the fields of `details`, which must serialize to an object
+    pub fn details(mut self, details: impl Serialize) -> Self {
+        if let Ok(serde_json::Value::Object(details)) = serde_json::to_value(details) {
+            self.details.extend(details);
+        }
+        self
+    }
+
+    /// A response rejecting the request with `status`, carrying the denial
+    pub fn response(self, status: StatusCode) -> Response<Body> {
+        let mut response = Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(self.render(DenialBodies::Detailed)))
+            .unwrap();
+        response.extensions_mut().insert(self);
+        response
+    }
+
+    fn render(&self, bodies: DenialBodies) -> Vec<u8> {
+        match bodies {
+            DenialBodies::Detailed => serde_json::to_vec(self),
+            DenialBodies::Terse => serde_json::to_vec(&TerseDenial {
+                error: &self.error,
+                retry_after: self.retry_after,
+                request_id: self.request_id.as_deref(),
+            }),
+        }
+        .unwrap_or_default()
+    }
+}
+
+/// The ID a request is correlated by in denials and logs
+pub fn request_id(request: &Request<Body>) -> Option<String> {
+    request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+        .map(str::to_string)
+}
+
+/// A new random request ID
+pub fn new_request_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// Denial messages by locale, from `server.denial_messages`
+#[derive(Debug, Clone, Default)]
+pub struct DenialMessages {
+    // Templates by lowercase locale and error code
+    locales: HashMap<String, HashMap<String, String>>,
+    fallback_locale: Option<String>,
+}
+
+impl DenialMessages {
+    pub fn new(config: &DenialMessagesConfig) -> Result<Self, String> {
+        let locales: HashMap<_, _> = config
+            .locales
+            .iter()
+            .map(|(locale, templates)| (locale.to_ascii_lowercase(), templates.clone()))
+            .collect();
+        let fallback_locale = config
+            .fallback_locale
+            .as_ref()
+            .map(|locale| locale.to_ascii_lowercase());
+        if let Some(fallback) = &fallback_locale {
+            if !locales.contains_key(fallback) {
+                return Err(format!(
+                    "denial_messages: fallback locale '{}' has no messages",
+                    fallback
+                ));
+            }
+        }
+        Ok(Self {
+            locales,
+            fallback_locale,
+        })
+    }
+
+    // The locale to answer a client in, from its `Accept-Language`
+    //
+    // Languages are tried in order of preference, each exactly and then by
+    // its primary subtag, so `de-CH` falls back to `de`.
+    fn locale(&self, accept_language: Option<&str>) -> Option<&str> {
+        let mut accepted: Vec<(&str, f32)> = accept_language
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|range| {
+                let mut params = range.split(';');
+                let tag = params.next()?.trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Stable, so equally preferred languages keep the client's order
+        accepted.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        for (tag, _) in accepted {
+            let tag = tag.to_ascii_lowercase();
+            let primary = tag.split('-').next().unwrap_or_default();
+            for candidate in [tag.as_str(), primary] {
+                if let Some((locale, _)) = self.locales.get_key_value(candidate) {
+                    return Some(locale);
+                }
+            }
+        }
+        self.fallback_locale.as_deref()
+    }
+
+    // Replace the message of `denial` with its template in the client's
+    // locale, returning the locale if there was one
+    fn localize(&self, denial: &mut Denial, accept_language: Option<&str>) -> Option<String> {
+        if self.locales.is_empty() {
+            return None;
+        }
+        let locale = self.locale(accept_language)?;
+        let (locale, template) = [Some(locale), self.fallback_locale.as_deref()]
+            .into_iter()
+            .flatten()
+            .find_map(|locale| Some((locale, self.locales[locale].get(&denial.error)?)))?;
+        denial.message = render(template, denial);
+        Some(locale.to_string())
+    }
+}
+
+// Replace `{{ field }}` in a template with the denial's field. Unknown fields
+// render as empty strings
+fn render(template: &str, denial: &Denial) -> String {
+    let fields = serde_json::to_value(denial).unwrap_or_default();
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        output.push_str(&rest[..start]);
+        match &fields[rest[start + 2..start + end].trim()] {
+            serde_json::Value::Null => {}
+            serde_json::Value::String(value) => output.push_str(value),
+            value => output.push_str(&value.to_string()),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    output.push_str(rest);
+    output
+}
+
+/// Finish the response `policy` rejected a request with, logging its denial
+///
+/// A structured denial gets the policy and request ID, its message in the
+/// client's language, and its body rendered again as configured. Every
+/// rejection gets the request ID header.
+pub fn complete(
+    policy: &dyn Policy,
+    mut response: Response<Body>,
+    request_id: &str,
+    accept_language: Option<&str>,
+    bodies: DenialBodies,
+    messages: &DenialMessages,
+) -> Response<Body> {
+    let id = format!(
+        "@{}/{}/{}/{}",
+        policy.provider(),
+        policy.category(),
+        policy.name(),
+        policy.version()
+    );
+    if let Ok(value) = HeaderValue::from_str(request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    let status = response.status().as_u16();
+    let Some(mut denial) = response.extensions_mut().remove::<Denial>() else {
+        if response.status().is_redirection() {
+            tracing::info!(policy = %id, status, request_id, "Request redirected");
+        } else {
+            tracing::info!(policy = %id, status, request_id, "Request denied");
+        }
+        return response;
+    };
+
+    // Denials resolved by combinators already name the policy that rejected
+    denial.policy.get_or_insert(id);
+    denial.request_id = Some(request_id.to_string());
+    tracing::info!(
+        policy = denial.policy.as_deref(),
+        status,
+        error = %denial.error,
+        reason = %denial.message,
+        request_id,
+        "Request denied"
+    );
+
+    let (mut parts, _) = response.into_parts();
+    parts.headers.remove(header::CONTENT_LENGTH);
+    if bodies == DenialBodies::Detailed {
+        let locale = messages.localize(&mut denial, accept_language);
+        if let Some(locale) = locale.and_then(|locale| HeaderValue::from_str(&locale).ok()) {
+            parts.headers.insert(header::CONTENT_LANGUAGE, locale);
+        }
+    }
+    let body = Body::from(denial.render(bodies));
+    parts.extensions.insert(denial);
+    Response::from_parts(parts, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::traits::PolicyResult;
+    use async_trait::async_trait;
+
+    struct LimitPolicy;
+
+    #[async_trait]
+    impl Policy for LimitPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "traffic"
+        }
+
+        fn name(&self) -> &'static str {
+            "limit"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, _request: Request<Body>) -> PolicyResult {
+            PolicyResult::Terminate(
+                Denial::new("rate_limited", "Too Many Requests")
+                    .retry_after(30)
+                    .details(serde_json::json!({ "limit": 10 }))
+                    .response(StatusCode::TOO_MANY_REQUESTS),
+            )
+        }
+    }
+
+    async fn body(policy: &dyn Policy, bodies: DenialBodies) -> serde_json::Value {
+        let PolicyResult::Terminate(response) = policy.process(Request::new(Body::empty())).await
+        else {
+            panic!("expected a denial");
+        };
+        let response = complete(
+            policy,
+            response,
+            "abc",
+            None,
+            bodies,
+            &DenialMessages::default(),
+        );
+        assert_eq!(response.headers()[REQUEST_ID_HEADER], "abc");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_complete() {
+        let detailed = body(&LimitPolicy, DenialBodies::Detailed).await;
+        assert_eq!(
+            detailed,
+            serde_json::json!({
+                "error": "rate_limited",
+                "policy": "@test/traffic/limit/v1",
+                "message": "Too Many Requests",
+                "retry_after": 30,
+                "request_id": "abc",
+                "limit": 10,
+            })
+        );
+
+        let terse = body(&LimitPolicy, DenialBodies::Terse).await;
+        assert_eq!(
+            terse,
+            serde_json::json!({ "error": "rate_limited", "retry_after": 30, "request_id": "abc" })
+        );
+
+        let request = Request::get("/")
+            .header(REQUEST_ID_HEADER, "x".repeat(MAX_REQUEST_ID_LEN + 1))
+            .body(Body::empty())
+            .unwrap();
+        assert!(request_id(&request).is_none());
+        assert_eq!(new_request_id().len(), 32);
+    }
+
+    #[test]
+    fn test_localize() {
+        let config = DenialMessagesConfig {
+            fallback_locale: Some("en".to_string()),
+            locales: HashMap::from([
+                (
+                    "en".to_string(),
+                    HashMap::from([
+                        ("rate_limited".to_string(), "Slow down".to_string()),
+                        ("banned".to_string(), "You're banned".to_string()),
+                    ]),
+                ),
+                (
+                    "de".to_string(),
+                    HashMap::from([(
+                        "rate_limited".to_string(),
+                        "Bitte in {{ retry_after }} Sekunden erneut versuchen".to_string(),
+                    )]),
+                ),
+            ]),
+        };
+        let messages = DenialMessages::new(&config).unwrap();
+        let localize = |error: &str, accept_language: Option<&str>| {
+            let mut denial = Denial::new(error, "Original").retry_after(30);
+            let locale = messages.localize(&mut denial, accept_language);
+            (locale, denial.message)
+        };
+
+        assert_eq!(
+            localize("rate_limited", Some("fr;q=0.9, de-CH, en;q=0.5")),
+            (
+                Some("de".to_string()),
+                "Bitte in 30 Sekunden erneut versuchen".to_string()
+            )
+        );
+        // Codes a locale lacks fall back to the fallback locale, then to the
+        // policy's message
+        assert_eq!(
+            localize("banned", Some("de")),
+            (Some("en".to_string()), "You're banned".to_string())
+        );
+        assert_eq!(localize("rate_limited", Some("de;q=0")).1, "Slow down");
+        assert_eq!(localize("no_role", None), (None, "Original".to_string()));
+
+        assert!(DenialMessages::new(&DenialMessagesConfig {
+            fallback_locale: Some("fr".to_string()),
+            ..config
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,413 @@
+use crate::admin::{is_admin, json_error};
+use crate::policy::context::context_mut;
+use crate::policy::denial::Denial;
+use crate::policy::failure::FailureMode;
+use crate::policy::forwarded::ClientIp;
+use crate::policy::headers::ProtectedHeaders;
+use crate::policy::middleware::PolicyChainHandle;
+use crate::policy::preauthorize::DECIDING_CATEGORIES;
+use crate::policy::traits::{redirect_status, PolicyResult};
+use axum::{
+    body::Body,
+    http::{HeaderMap, HeaderName, HeaderValue, Method, Request, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+// Bodies of rejections without a denial are shown as their reason, up to
+// this size
+const MAX_REASON_BYTES: usize = 1024;
+
+/// Marks requests run by the explain endpoint, so policies only describe
+/// their decisions when asked
+#[derive(Debug, Clone, Copy)]
+pub struct Explain;
+
+/// Returns true if `request` is being explained
+pub fn is_explaining(request: &Request<Body>) -> bool {
+    request.extensions().get::<Explain>().is_some()
+}
+
+/// Why a policy let a request through or rejected it
+///
+/// Policies add it to the extensions of the request they continue with, or
+/// of the response they reject it with.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Explanation {
+    /// The rule that decided, e.g. an RBAC route pattern
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// The roles the request was checked with
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub roles: Vec<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StepDecision {
+    Allow,
+    Deny,
+    /// The policy's `apply_if` didn't match
+    Skipped,
+    /// The policy couldn't decide, and its failure mode did
+    Failed,
+}
+
+/// What one policy did with the request
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainStep {
+    pub policy: String,
+    pub decision: StepDecision,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub explanation: Option<Explanation>,
+}
+
+/// The decision of the chain on a request, and how each policy got there
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainReport {
+    pub allowed: bool,
+    /// 200 when allowed, otherwise the status of the rejecting policy's response
+    pub status: u16,
+    pub steps: Vec<ExplainStep>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExplainRequest {
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    client_ip: Option<IpAddr>,
+}
+
+/// Runs requests through the live chain and reports why each policy let them
+/// through or rejected them, to debug RBAC rules and the like
+///
+/// Like pre-authorization, only authentication, authorization and logic
+/// policies run, so explaining a request doesn't use up rate limits.
+pub struct Explainer {
+    chain: PolicyChainHandle,
+    protected_headers: Arc<ProtectedHeaders>,
+}
+
+impl Explainer {
+    pub fn new(chain: PolicyChainHandle, protected_headers: Arc<ProtectedHeaders>) -> Self {
+        Self {
+            chain,
+            protected_headers,
+        }
+    }
+
+    /// Explain the chain's decision on `request`
+    ///
+    /// Protected headers are stripped, as the middleware would.
+    pub async fn explain(&self, mut request: Request<Body>) -> ExplainReport {
+        self.protected_headers.strip(request.headers_mut());
+        request.extensions_mut().insert(Explain);
+
+        let mut steps = Vec::new();
+        let policies = self.chain.load();
+        for policy in policies
+            .iter()
+            .filter(|policy| DECIDING_CATEGORIES.contains(&policy.category()))
+        {
+            let id = format!(
+                "@{}/{}/{}/{}",
+                policy.provider(),
+                policy.category(),
+                policy.name(),
+                policy.version()
+            );
+            let step = |decision, status: Option<StatusCode>, explanation| ExplainStep {
+                policy: id.clone(),
+                decision,
+                status: status.map(|status| status.as_u16()),
+                explanation,
+            };
+            if !policy.applies_to(&request) {
+                steps.push(step(StepDecision::Skipped, None, None));
+                continue;
+            }
+
+            match policy.process(request).await {
+                PolicyResult::Continue(mut next) => {
+                    let explanation = next.extensions_mut().remove::<Explanation>();
+                    steps.push(step(StepDecision::Allow, None, explanation));
+                    request = next;
+                }
+                PolicyResult::Terminate(response) => {
+                    let status = response.status();
+                    let (mut parts, body) = response.into_parts();
+                    let explanation = match (
+                        parts.extensions.remove::<Explanation>(),
+                        parts.extensions.remove::<Denial>(),
+                    ) {
+                        (Some(explanation), _) => explanation,
+                        (None, Some(denial)) => Explanation {
+                            reason: denial.message,
+                            ..Explanation::default()
+                        },
+                        (None, None) => Explanation {
+                            reason: axum::body::to_bytes(body, MAX_REASON_BYTES)
+                                .await
+                                .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                                .unwrap_or_default(),
+                            ..Explanation::default()
+                        },
+                    };
+                    steps.push(step(StepDecision::Deny, Some(status), Some(explanation)));
+                    return ExplainReport {
+                        allowed: false,
+                        status: status.as_u16(),
+                        steps,
+                    };
+                }
+                PolicyResult::Redirect(location, status) => {
+                    let status = redirect_status(status);
+                    let explanation = Explanation {
+                        reason: format!("Redirected to {}", location),
+                        ..Explanation::default()
+                    };
+                    steps.push(step(StepDecision::Deny, Some(status), Some(explanation)));
+                    return ExplainReport {
+                        allowed: false,
+                        status: status.as_u16(),
+                        steps,
+                    };
+                }
+                PolicyResult::Error(next, error) => {
+                    let mode = policy.failure_mode();
+                    let outcome = match mode {
+                        FailureMode::Open => "failed open",
+                        FailureMode::Closed => "failed closed",
+                    };
+                    let explanation = Explanation {
+                        reason: format!("{} ({})", error, outcome),
+                        ..Explanation::default()
+                    };
+                    if mode == FailureMode::Open {
+                        steps.push(step(StepDecision::Failed, None, Some(explanation)));
+                        request = next;
+                        continue;
+                    }
+                    let status = StatusCode::SERVICE_UNAVAILABLE;
+                    steps.push(step(StepDecision::Failed, Some(status), Some(explanation)));
+                    return ExplainReport {
+                        allowed: false,
+                        status: status.as_u16(),
+                        steps,
+                    };
+                }
+            }
+        }
+        ExplainReport {
+            allowed: true,
+            status: StatusCode::OK.as_u16(),
+            steps,
+        }
+    }
+}
+
+// The request to explain, as described in the body of an explain call
+fn build_request(explain: &ExplainRequest) -> Result<Request<Body>, String> {
+    let method = Method::from_bytes(explain.method.to_uppercase().as_bytes())
+        .map_err(|_| format!("Invalid method '{}'", explain.method))?;
+    if !explain.path.starts_with('/') {
+        return Err(format!("Path must start with '/', got '{}'", explain.path));
+    }
+    let mut headers = HeaderMap::new();
+    for (name, value) in &explain.headers {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("Invalid header name '{}'", name))?;
+        let value = HeaderValue::from_str(value)
+            .map_err(|_| format!("Invalid value for header {}", name))?;
+        headers.insert(name, value);
+    }
+    let mut request = Request::builder()
+        .method(method)
+        .uri(&explain.path)
+        .body(Body::empty())
+        .map_err(|e| format!("Invalid request: {}", e))?;
+    *request.headers_mut() = headers;
+    if let Some(ip) = explain.client_ip {
+        request.extensions_mut().insert(ClientIp(ip));
+        context_mut(&mut request).set_client_ip(ip);
+    }
+    Ok(request)
+}
+
+/// Route `/_admin/explain` to explain the chain's decision on a request, if an
+/// admin token is set
+///
+/// Expects `POST` with `{"method": "DELETE", "path": "/orders/1", "headers":
+/// {"authorization": "Bearer ..."}}` and an optional `client_ip`.
+pub fn admin_router(explainer: Arc<Explainer>, admin_token: Option<&str>) -> Router {
+    let Some(admin_token) = admin_token else {
+        return Router::new();
+    };
+    let admin_token = Arc::new(admin_token.to_string());
+
+    let explain = move |headers: HeaderMap, Json(explain): Json<ExplainRequest>| async move {
+        if !is_admin(&headers, &admin_token) {
+            return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+        }
+        match build_request(&explain) {
+            Ok(request) => Json(explainer.explain(request).await).into_response(),
+            Err(e) => json_error(StatusCode::BAD_REQUEST, e),
+        }
+    };
+    Router::new().route("/_admin/explain", post(explain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::providers::bouncer::authentication::identity::Identity;
+    use crate::policy::providers::bouncer::authorization::rbac::v1::{
+        RbacConfig, RbacPolicyFactory,
+    };
+    use crate::policy::traits::{Policy, PolicyFactory};
+    use async_trait::async_trait;
+    use axum::http::header;
+    use tower::ServiceExt;
+
+    // Authenticates requests as the role in `x-test-role`, if any
+    struct TestRolePolicy;
+
+    #[async_trait]
+    impl Policy for TestRolePolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            "test-role"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+            let role = request
+                .headers()
+                .get("x-test-role")
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            if let Some(role) = role {
+                Identity::new(role).apply(&mut request);
+            }
+            PolicyResult::Continue(request)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain() {
+        let rbac = RbacPolicyFactory::new(RbacConfig {
+            route_roles: HashMap::from([
+                ("GET /orders/*".to_string(), vec!["user".to_string()]),
+                ("DELETE /orders/*".to_string(), vec!["admin".to_string()]),
+            ]),
+        })
+        .await
+        .unwrap();
+        let chain = PolicyChainHandle::new(vec![Box::new(TestRolePolicy), Box::new(rbac)]);
+        let explainer = Arc::new(Explainer::new(chain, Arc::new(ProtectedHeaders::default())));
+        let request = |method: &str, role: &str| {
+            Request::builder()
+                .method(method)
+                .uri("/orders/1")
+                .header("x-test-role", role)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let report = explainer.explain(request("GET", "user")).await;
+        assert!(report.allowed);
+        let rbac = report.steps[1].explanation.as_ref().unwrap();
+        assert_eq!(rbac.rule.as_deref(), Some("GET /orders/*"));
+        assert_eq!(rbac.roles, ["user"]);
+
+        let report = explainer.explain(request("DELETE", "user")).await;
+        assert!(!report.allowed);
+        assert_eq!(report.status, 403);
+        assert_eq!(report.steps[1].decision, StepDecision::Deny);
+        let rbac = report.steps[1].explanation.as_ref().unwrap();
+        assert!(rbac.rule.is_none());
+        assert_eq!(rbac.reason, "'DELETE /orders/*' only allows admin");
+
+        // Rejections without an explanation are explained by their denial
+        let response = admin_router(explainer, Some("secret"))
+            .oneshot(
+                Request::post("/_admin/explain")
+                    .header(header::AUTHORIZATION, "Bearer secret")
+                    .header(header::CONTENT_TYPE, "application/json")
+                    .body(Body::from(r#"{"method": "GET", "path": "/orders/1"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let report: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(report["steps"][0]["decision"], "allow");
+        assert_eq!(report["steps"][1]["status"], 401);
+        assert_eq!(report["steps"][1]["reason"], "No role found");
+    }
+
+    struct LoginRedirectPolicy;
+
+    #[async_trait]
+    impl Policy for LoginRedirectPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            "login-redirect"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, _request: Request<Body>) -> PolicyResult {
+            PolicyResult::Redirect(
+                "https://login.example.com/".parse().unwrap(),
+                StatusCode::OK,
+            )
+        }
+    }
+
+    #[tokio::test]
+    async fn test_explain_redirect() {
+        let chain = PolicyChainHandle::new(vec![Box::new(LoginRedirectPolicy)]);
+        let explainer = Explainer::new(chain, Arc::new(ProtectedHeaders::default()));
+
+        // Reports the status the middleware answers with
+        let report = explainer
+            .explain(Request::get("/").body(Body::empty()).unwrap())
+            .await;
+        assert!(!report.allowed);
+        assert_eq!(report.status, 302);
+        assert_eq!(report.steps[0].status, Some(302));
+    }
+}
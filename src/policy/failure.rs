@@ -0,0 +1,186 @@
+use crate::policy::denial::Denial;
+use crate::policy::traits::{redirect, Policy, PolicyResult};
+use axum::{
+    body::Body,
+    http::{Request, Response, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// What happens to a request when a policy can't decide on it, such as when
+/// the database it checks tokens against is down
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailureMode {
+    /// Let the request through to the next policy
+    Open,
+    /// Answer the request with a 503
+    #[default]
+    Closed,
+}
+
+/// How often a policy failed, as served at `/_admin/failures`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FailureStats {
+    pub policy: String,
+    pub failed_open: u64,
+    pub failed_closed: u64,
+}
+
+/// Failures of every policy of an instance that has failed, by policy
+#[derive(Default)]
+pub struct Failures(Mutex<HashMap<String, FailureStats>>);
+
+impl Failures {
+    /// Count a failure of the policy `id`
+    pub fn record(&self, id: &str, mode: FailureMode) {
+        let mut failures = self.0.lock().unwrap();
+        let stats = failures
+            .entry(id.to_string())
+            .or_insert_with(|| FailureStats {
+                policy: id.to_string(),
+                ..FailureStats::default()
+            });
+        match mode {
+            FailureMode::Open => stats.failed_open += 1,
+            FailureMode::Closed => stats.failed_closed += 1,
+        }
+    }
+
+    pub fn stats(&self) -> Vec<FailureStats> {
+        let mut stats: Vec<FailureStats> = self.0.lock().unwrap().values().cloned().collect();
+        stats.sort_by(|a, b| a.policy.cmp(&b.policy));
+        stats
+    }
+}
+
+/// Failure counts of every policy of the current instance that has failed
+/// since startup
+pub fn all_failure_stats() -> Vec<FailureStats> {
+    crate::instance::current().failures.stats()
+}
+
+// How failures and denials name a policy
+fn policy_id(policy: &dyn Policy) -> String {
+    format!(
+        "@{}/{}/{}/{}",
+        policy.provider(),
+        policy.category(),
+        policy.name(),
+        policy.version()
+    )
+}
+
+/// Whether the request continues after `policy` processed it, or else the
+/// response to send
+///
+/// [`PolicyResult::Error`] is decided by the policy's failure mode, and every
+/// failure is logged and counted.
+#[allow(clippy::result_large_err)]
+pub fn resolve(policy: &dyn Policy, result: PolicyResult) -> Result<Request<Body>, Response<Body>> {
+    let (request, error) = match result {
+        PolicyResult::Continue(request) => return Ok(request),
+        PolicyResult::Terminate(mut response) => {
+            // Combinators pass on the denial of the policy inside them that
+            // rejected, which the denial should name
+            if let Some(denial) = response.extensions_mut().get_mut::<Denial>() {
+                denial.policy.get_or_insert_with(|| policy_id(policy));
+            }
+            return Err(response);
+        }
+        PolicyResult::Redirect(location, status) => return Err(redirect(&location, status)),
+        PolicyResult::Error(request, error) => (request, error),
+    };
+    let id = policy_id(policy);
+    let mode = policy.failure_mode();
+    crate::instance::current().failures.record(&id, mode);
+
+    match mode {
+        FailureMode::Open => {
+            tracing::warn!("Policy {} failed open: {}", id, error);
+            Ok(request)
+        }
+        FailureMode::Closed => {
+            tracing::error!("Policy {} failed closed: {}", id, error);
+            Err(Denial::new("policy_failed", "Service Unavailable")
+                .response(StatusCode::SERVICE_UNAVAILABLE))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    struct FailingPolicy(FailureMode);
+
+    #[async_trait]
+    impl Policy for FailingPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            match self.0 {
+                FailureMode::Open => "failing-open",
+                FailureMode::Closed => "failing-closed",
+            }
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, request: Request<Body>) -> PolicyResult {
+            PolicyResult::Error(request, "database is down".to_string())
+        }
+
+        fn failure_mode(&self) -> FailureMode {
+            self.0
+        }
+    }
+
+    async fn run(policy: &dyn Policy) -> Result<Request<Body>, Response<Body>> {
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        resolve(policy, policy.process(request).await)
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let open = FailingPolicy(FailureMode::Open);
+        assert!(run(&open).await.is_ok());
+        let closed = FailingPolicy(FailureMode::Closed);
+        let response = run(&closed).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert!(run(&closed).await.is_err());
+
+        let stats = all_failure_stats();
+        let stats = |id: &str| stats.iter().find(|stats| stats.policy == id).unwrap();
+        assert_eq!(stats("@test/authentication/failing-open/v1").failed_open, 1);
+        assert_eq!(
+            stats("@test/authentication/failing-closed/v1").failed_closed,
+            2
+        );
+        assert_eq!(
+            serde_json::from_str::<FailureMode>(r#""open""#).unwrap(),
+            FailureMode::Open
+        );
+
+        let redirect = PolicyResult::Redirect(
+            "https://login.example.com/?next=%2F".parse().unwrap(),
+            StatusCode::FOUND,
+        );
+        let response = resolve(&open, redirect).unwrap_err();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers()[axum::http::header::LOCATION],
+            "https://login.example.com/?next=%2F"
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use crate::policy::kill_switch::{in_network, validate_network};
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request},
+};
+use std::net::{IpAddr, SocketAddr};
+
+const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+const X_FORWARDED_HOST: HeaderName = HeaderName::from_static("x-forwarded-host");
+
+/// The IP address of the client, resolved through the trusted proxies
+///
+/// The policy middleware adds this to the request's extensions. Use
+/// [`client_ip`] to read it, which falls back to the connection's address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+/// The IP address of the client that sent a request
+///
+/// Behind trusted proxies this is the address they forwarded the request for,
+/// otherwise the address of the connection.
+pub fn client_ip(request: &Request<Body>) -> Option<IpAddr> {
+    request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ClientIp(ip)| *ip)
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip())
+        })
+}
+
+// An address in X-Forwarded-For, which some proxies write with a port
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let hop = hop.trim();
+    hop.parse::<IpAddr>()
+        .ok()
+        .or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+/// Proxies in front of Bouncer whose `X-Forwarded-*` headers are believed
+///
+/// Headers from anyone else are ignored, since clients can send them to
+/// pose as another address.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    networks: Vec<String>,
+}
+
+impl TrustedProxies {
+    /// Trust proxies at these IP addresses or in these CIDR ranges
+    pub fn new(networks: &[String]) -> Result<Self, String> {
+        for network in networks {
+            validate_network(network).map_err(|e| format!("server.trusted_proxies: {}", e))?;
+        }
+        Ok(Self {
+            networks: networks.to_vec(),
+        })
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| in_network(ip, network))
+    }
+
+    /// The client of a request that arrived from `peer`
+    ///
+    /// `X-Forwarded-For` is read from the right, skipping trusted proxies, so
+    /// the first untrusted address is the client. Addresses further left could
+    /// have been sent by the client itself.
+    pub fn resolve(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let mut client = peer;
+        if !self.is_trusted(peer) {
+            return client;
+        }
+
+        let hops: Vec<&str> = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect();
+        for hop in hops.into_iter().rev() {
+            let Some(ip) = parse_hop(hop) else {
+                break;
+            };
+            client = ip;
+            if !self.is_trusted(ip) {
+                break;
+            }
+        }
+        client
+    }
+
+    /// Set `X-Forwarded-For`, `X-Forwarded-Proto` and `X-Forwarded-Host` on the
+    /// headers forwarded to the upstream
+    ///
+    /// The connection's address is appended to the `X-Forwarded-For` of a
+    /// trusted proxy, and the proxy's protocol and host are kept. From anyone
+    /// else, the headers are replaced with what Bouncer saw.
+    pub fn set_forwarded_headers(
+        &self,
+        request: &Request<Body>,
+        headers: &mut HeaderMap,
+        scheme: &str,
+    ) {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let trusted = peer.is_some_and(|peer| self.is_trusted(peer));
+        let incoming = |name: &HeaderName| {
+            request
+                .headers()
+                .get_all(name)
+                .iter()
+                .filter_map(|value| value.to_str().ok())
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+
+        let mut forwarded_for: Vec<String> = Vec::new();
+        if trusted {
+            let hops = incoming(&X_FORWARDED_FOR);
+            if !hops.is_empty() {
+                forwarded_for.push(hops);
+            }
+        }
+        forwarded_for.extend(peer.map(|peer| peer.to_string()));
+        headers.remove(X_FORWARDED_FOR);
+        if let Ok(value) = HeaderValue::from_str(&forwarded_for.join(", ")) {
+            if !value.is_empty() {
+                headers.insert(X_FORWARDED_FOR, value);
+            }
+        }
+
+        let proto = Some(incoming(&X_FORWARDED_PROTO))
+            .filter(|proto| trusted && !proto.is_empty())
+            .unwrap_or_else(|| scheme.to_string());
+        if let Ok(value) = HeaderValue::from_str(&proto) {
+            headers.insert(X_FORWARDED_PROTO, value);
+        }
+
+        let host = Some(incoming(&X_FORWARDED_HOST))
+            .filter(|host| trusted && !host.is_empty())
+            .or_else(|| {
+                let host = request.headers().get(header::HOST)?.to_str().ok()?;
+                Some(host.to_string())
+            })
+            .or_else(|| {
+                request
+                    .uri()
+                    .authority()
+                    .map(|authority| authority.to_string())
+            });
+        headers.remove(X_FORWARDED_HOST);
+        if let Some(value) = host.and_then(|host| HeaderValue::from_str(&host).ok()) {
+            headers.insert(X_FORWARDED_HOST, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_forwarded_headers() {
+        let proxies = TrustedProxies::new(&["10.0.0.0/8".to_string()]).unwrap();
+        let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_FORWARDED_FOR,
+            "198.51.100.1, 203.0.113.7:4711, 10.0.0.2".parse().unwrap(),
+        );
+        assert_eq!(proxies.resolve(ip("10.0.0.1"), &headers), ip("203.0.113.7"));
+        // Untrusted peers can't choose their address
+        assert_eq!(proxies.resolve(ip("192.0.2.1"), &headers), ip("192.0.2.1"));
+
+        let request = |peer: &str| {
+            let mut request = Request::get("/orders")
+                .header(header::HOST, "api.example.com")
+                .header(X_FORWARDED_FOR, "203.0.113.7")
+                .header(X_FORWARDED_PROTO, "https")
+                .body(Body::empty())
+                .unwrap();
+            request.extensions_mut().insert(ConnectInfo(
+                format!("{}:443", peer).parse::<SocketAddr>().unwrap(),
+            ));
+            request
+        };
+
+        let mut headers = HeaderMap::new();
+        proxies.set_forwarded_headers(&request("10.0.0.1"), &mut headers, "http");
+        assert_eq!(headers[X_FORWARDED_FOR], "203.0.113.7, 10.0.0.1");
+        assert_eq!(headers[X_FORWARDED_PROTO], "https");
+        assert_eq!(headers[X_FORWARDED_HOST], "api.example.com");
+
+        let mut headers = HeaderMap::new();
+        proxies.set_forwarded_headers(&request("192.0.2.1"), &mut headers, "http");
+        assert_eq!(headers[X_FORWARDED_FOR], "192.0.2.1");
+        assert_eq!(headers[X_FORWARDED_PROTO], "http");
+    }
+}
@@ -0,0 +1,96 @@
+use crate::config::ProtectedHeadersConfig;
+use axum::http::HeaderMap;
+
+// Prefix of the headers set by built-in policies, which is always protected
+const BOUNCER_PREFIX: &str = "x-bouncer-";
+
+/// Headers that only policies may set
+///
+/// Protected headers are stripped from incoming requests before the policy
+/// chain runs, so clients can't spoof them. Once the chain has run, only those
+/// on the forward allowlist are sent on to the destination.
+#[derive(Debug, Clone)]
+pub struct ProtectedHeaders {
+    prefixes: Vec<String>,
+    forward: Vec<String>,
+}
+
+impl Default for ProtectedHeaders {
+    fn default() -> Self {
+        Self::new(&ProtectedHeadersConfig::default())
+    }
+}
+
+impl ProtectedHeaders {
+    pub fn new(config: &ProtectedHeadersConfig) -> Self {
+        let mut prefixes = vec![BOUNCER_PREFIX.to_string()];
+        prefixes.extend(config.prefixes.iter().map(|prefix| prefix.to_lowercase()));
+        prefixes.dedup();
+
+        Self {
+            prefixes,
+            forward: config
+                .forward
+                .iter()
+                .map(|name| name.to_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Whether a header name matches a protected prefix
+    pub fn is_protected(&self, name: &str) -> bool {
+        // Header names are already lowercase in a HeaderMap
+        self.prefixes.iter().any(|prefix| name.starts_with(prefix))
+    }
+
+    /// Remove every protected header, e.g. those sent by a client
+    pub fn strip(&self, headers: &mut HeaderMap) {
+        self.remove_where(headers, |_| true);
+    }
+
+    /// Remove protected headers that aren't allowed to reach the destination
+    pub fn strip_unforwarded(&self, headers: &mut HeaderMap) {
+        self.remove_where(headers, |name| !self.forward.iter().any(|f| f == name));
+    }
+
+    fn remove_where(&self, headers: &mut HeaderMap, remove: impl Fn(&str) -> bool) {
+        let names: Vec<_> = headers
+            .keys()
+            .filter(|name| self.is_protected(name.as_str()) && remove(name.as_str()))
+            .cloned()
+            .collect();
+
+        for name in names {
+            headers.remove(name);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_protected_headers() {
+        let protected = ProtectedHeaders::new(&ProtectedHeadersConfig {
+            prefixes: vec!["X-Auth-".to_string()],
+            forward: vec!["X-Bouncer-Role".to_string()],
+        });
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-bouncer-role", "admin".parse().unwrap());
+        headers.insert("x-bouncer-owner", "alice".parse().unwrap());
+        headers.insert("x-auth-roles", "admin".parse().unwrap());
+        headers.insert("accept", "*/*".parse().unwrap());
+
+        let mut forwarded = headers.clone();
+        protected.strip_unforwarded(&mut forwarded);
+        assert!(forwarded.contains_key("x-bouncer-role"));
+        assert!(!forwarded.contains_key("x-bouncer-owner"));
+        assert!(!forwarded.contains_key("x-auth-roles"));
+        assert!(forwarded.contains_key("accept"));
+
+        protected.strip(&mut headers);
+        assert_eq!(headers.len(), 1);
+    }
+}
@@ -0,0 +1,428 @@
+use crate::admin::{is_admin, json_error, now_secs};
+use crate::config::DatabasesConfig;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use axum::{
+    extract::State,
+    http::{HeaderMap, Method, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+/// Redis key holding the traffic mode shared by every replica
+pub const KEY: &str = "bouncer:traffic_mode";
+
+/// The Redis key of a named instance's traffic mode, so instances of one
+/// config file can be switched separately
+pub fn key(instance: Option<&str>) -> String {
+    match instance {
+        Some(instance) => format!("{}:{}", KEY, instance),
+        None => KEY.to_string(),
+    }
+}
+
+/// How often each replica reloads the shared traffic mode
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which requests Bouncer lets through, switched at runtime in an emergency
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum TrafficMode {
+    /// Every request goes through the policy chain as usual
+    #[default]
+    Normal,
+    /// Every request is rejected
+    Blocked,
+    /// Only GET and HEAD requests are let through
+    ReadOnly,
+    /// Only requests from these IP addresses or CIDR ranges are let through
+    Allowlist { allow: Vec<String> },
+}
+
+/// The current traffic mode, and who set it why
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TrafficState {
+    #[serde(flatten)]
+    pub mode: TrafficMode,
+    #[serde(default)]
+    pub reason: String,
+    /// Unix timestamp in seconds
+    #[serde(default)]
+    pub updated_at: i64,
+}
+
+// Whether an address is in a network, written as an address or `address/prefix`
+pub(crate) fn in_network(ip: IpAddr, network: &str) -> bool {
+    let (address, prefix) = match network.split_once('/') {
+        Some((address, prefix)) => match prefix.parse::<u32>() {
+            Ok(prefix) => (address, Some(prefix)),
+            Err(_) => return false,
+        },
+        None => (network, None),
+    };
+    let Ok(address) = address.parse::<IpAddr>() else {
+        return false;
+    };
+
+    match (ip, address) {
+        (IpAddr::V4(ip), IpAddr::V4(address)) => {
+            let prefix = prefix.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(address) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(address)) => {
+            let prefix = prefix.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(address) & mask
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn validate_network(network: &str) -> Result<(), String> {
+    let (address, prefix) = network.split_once('/').unwrap_or((network, ""));
+    let address = address
+        .parse::<IpAddr>()
+        .map_err(|_| format!("Invalid address '{}'", network))?;
+    if !prefix.is_empty() {
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        match prefix.parse::<u32>() {
+            Ok(prefix) if prefix <= max => {}
+            _ => return Err(format!("Invalid prefix length in '{}'", network)),
+        }
+    }
+    Ok(())
+}
+
+impl TrafficMode {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            Self::Allowlist { allow } if allow.is_empty() => {
+                Err("The allowlist must not be empty".to_string())
+            }
+            Self::Allowlist { allow } => allow
+                .iter()
+                .try_for_each(|network| validate_network(network)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Why a request is rejected in this mode, if it is
+    pub fn rejects(&self, method: &Method, client_ip: Option<IpAddr>) -> Option<&'static str> {
+        match self {
+            Self::Normal => None,
+            Self::Blocked => Some("Service temporarily unavailable"),
+            Self::ReadOnly if method == Method::GET || method == Method::HEAD => None,
+            Self::ReadOnly => Some("Service is in read-only mode"),
+            Self::Allowlist { allow } => {
+                let allowed =
+                    client_ip.is_some_and(|ip| allow.iter().any(|network| in_network(ip, network)));
+                (!allowed).then_some("Service temporarily unavailable")
+            }
+        }
+    }
+}
+
+/// Storage for the traffic mode shared by every replica
+#[async_trait]
+pub trait TrafficModeStore: Send + Sync + 'static {
+    async fn load(&self) -> Result<Option<TrafficState>, DatabaseError>;
+
+    async fn save(&self, state: &TrafficState) -> Result<(), DatabaseError>;
+}
+
+/// The traffic mode stored as JSON under one Redis key
+#[cfg(feature = "redis")]
+pub struct RedisTrafficModeStore {
+    connection: redis::aio::MultiplexedConnection,
+    key: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisTrafficModeStore {
+    pub async fn new(client: &redis::Client, key: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self { connection, key })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl TrafficModeStore for RedisTrafficModeStore {
+    async fn load(&self) -> Result<Option<TrafficState>, DatabaseError> {
+        let value: Option<String> = redis::cmd("GET")
+            .arg(&self.key)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        value
+            .map(|value| {
+                serde_json::from_str(&value)
+                    .map_err(|e| DatabaseError::ConversionError(e.to_string()))
+            })
+            .transpose()
+    }
+
+    async fn save(&self, state: &TrafficState) -> Result<(), DatabaseError> {
+        let value = serde_json::to_string(state)
+            .map_err(|e| DatabaseError::ConversionError(e.to_string()))?;
+
+        redis::cmd("SET")
+            .arg(&self.key)
+            .arg(value)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+}
+
+/// The traffic mode kept in memory, for a single replica without Redis
+#[derive(Default)]
+pub struct MemoryTrafficModeStore {
+    state: Mutex<Option<TrafficState>>,
+}
+
+#[async_trait]
+impl TrafficModeStore for MemoryTrafficModeStore {
+    async fn load(&self) -> Result<Option<TrafficState>, DatabaseError> {
+        Ok(self.state.lock().unwrap().clone())
+    }
+
+    async fn save(&self, state: &TrafficState) -> Result<(), DatabaseError> {
+        *self.state.lock().unwrap() = Some(state.clone());
+        Ok(())
+    }
+}
+
+/// Emergency switch for all traffic, shared by every replica
+///
+/// Requests are checked against an in-memory copy of the mode. Each replica
+/// reloads it every second, and changes made through this replica apply to it
+/// immediately.
+pub struct KillSwitch {
+    store: Arc<dyn TrafficModeStore>,
+    state: RwLock<TrafficState>,
+}
+
+impl KillSwitch {
+    /// Load the current mode. If the store can't be read, traffic flows normally
+    /// until it can
+    pub async fn new(store: Arc<dyn TrafficModeStore>) -> Arc<Self> {
+        let kill_switch = Arc::new(Self {
+            store,
+            state: RwLock::new(TrafficState::default()),
+        });
+
+        if let Err(e) = kill_switch.refresh().await {
+            tracing::warn!("Failed to load the traffic mode: {}", e);
+        }
+        kill_switch
+    }
+
+    pub fn state(&self) -> TrafficState {
+        self.state.read().unwrap().clone()
+    }
+
+    /// Why a request is rejected in the current mode, if it is
+    pub fn rejects(&self, method: &Method, client_ip: Option<IpAddr>) -> Option<&'static str> {
+        self.state.read().unwrap().mode.rejects(method, client_ip)
+    }
+
+    /// Switch every replica to a mode
+    pub async fn set(
+        &self,
+        mode: TrafficMode,
+        reason: impl Into<String>,
+    ) -> Result<TrafficState, String> {
+        mode.validate()?;
+        let state = TrafficState {
+            mode,
+            reason: reason.into(),
+            updated_at: now_secs(),
+        };
+
+        self.store.save(&state).await.map_err(|e| e.to_string())?;
+        tracing::warn!("Traffic mode set to {:?}: {}", state.mode, state.reason);
+        *self.state.write().unwrap() = state.clone();
+        Ok(state)
+    }
+
+    async fn refresh(&self) -> Result<(), DatabaseError> {
+        let state = self.store.load().await?.unwrap_or_default();
+        let mut current = self.state.write().unwrap();
+        if current.mode != state.mode {
+            tracing::warn!("Traffic mode changed to {:?}: {}", state.mode, state.reason);
+        }
+        *current = state;
+        Ok(())
+    }
+
+    /// Reload the mode every `interval`
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the switch is dropped
+        let kill_switch = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, and the mode was just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(kill_switch) = kill_switch.upgrade() else {
+                    break;
+                };
+
+                if let Err(e) = kill_switch.refresh().await {
+                    tracing::warn!("Failed to refresh the traffic mode: {}", e);
+                }
+            }
+        });
+    }
+}
+
+/// Create the kill switch of an instance, shared through Redis if it's
+/// configured
+pub async fn create_kill_switch(
+    databases: &DatabasesConfig,
+    instance: Option<&str>,
+) -> Result<Arc<KillSwitch>, String> {
+    let store: Arc<dyn TrafficModeStore> = match &databases.redis {
+        Some(redis) => create_redis_store(redis, key(instance))
+            .await
+            .map_err(|e| e.to_string())?,
+        None => Arc::new(MemoryTrafficModeStore::default()),
+    };
+
+    let kill_switch = KillSwitch::new(store).await;
+    if databases.redis.is_some() {
+        kill_switch.spawn_refresh(REFRESH_INTERVAL);
+    }
+    Ok(kill_switch)
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    redis: &crate::config::RedisConfig,
+    key: String,
+) -> Result<Arc<dyn TrafficModeStore>, DatabaseError> {
+    let client = crate::database::get_redis_client(redis).await?;
+    Ok(Arc::new(RedisTrafficModeStore::new(&client, key).await?))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _redis: &crate::config::RedisConfig,
+    _key: String,
+) -> Result<Arc<dyn TrafficModeStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[derive(Clone)]
+struct AdminState {
+    kill_switch: Arc<KillSwitch>,
+    admin_token: Arc<String>,
+}
+
+async fn show(State(state): State<AdminState>, headers: HeaderMap) -> axum::response::Response {
+    if !is_admin(&headers, &state.admin_token) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+    Json(state.kill_switch.state()).into_response()
+}
+
+async fn update(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Json(request): Json<TrafficState>,
+) -> axum::response::Response {
+    if !is_admin(&headers, &state.admin_token) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+    if let Err(e) = request.mode.validate() {
+        return json_error(StatusCode::BAD_REQUEST, e);
+    }
+    match state.kill_switch.set(request.mode, request.reason).await {
+        Ok(state) => Json(state).into_response(),
+        Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e),
+    }
+}
+
+/// Routes under `/_admin/traffic` to read and switch the traffic mode, if an admin token is set
+pub fn admin_router(kill_switch: Arc<KillSwitch>, admin_token: Option<&str>) -> Router {
+    let Some(admin_token) = admin_token else {
+        return Router::new();
+    };
+
+    Router::new()
+        .route("/_admin/traffic", get(show).put(update))
+        .with_state(AdminState {
+            kill_switch,
+            admin_token: Arc::new(admin_token.to_string()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_traffic_modes() {
+        let store = Arc::new(MemoryTrafficModeStore::default());
+        let kill_switch = KillSwitch::new(store.clone()).await;
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+        assert!(kill_switch.rejects(&Method::POST, None).is_none());
+
+        kill_switch
+            .set(TrafficMode::ReadOnly, "database migration")
+            .await
+            .unwrap();
+        assert!(kill_switch.rejects(&Method::HEAD, None).is_none());
+        assert!(kill_switch.rejects(&Method::POST, None).is_some());
+
+        let allowlist = TrafficMode::Allowlist {
+            allow: vec!["10.0.0.0/8".to_string(), "2001:db8::1".to_string()],
+        };
+        kill_switch.set(allowlist, "incident").await.unwrap();
+        assert!(kill_switch.rejects(&Method::POST, ip("10.1.2.3")).is_none());
+        assert!(kill_switch
+            .rejects(&Method::GET, ip("2001:db8::1"))
+            .is_none());
+        assert!(kill_switch.rejects(&Method::GET, ip("192.0.2.1")).is_some());
+        assert!(kill_switch.rejects(&Method::GET, None).is_some());
+
+        // Modes set by other replicas apply after a refresh
+        store
+            .save(&TrafficState {
+                mode: TrafficMode::Blocked,
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        kill_switch.refresh().await.unwrap();
+        assert!(kill_switch.rejects(&Method::GET, ip("10.1.2.3")).is_some());
+
+        assert!(TrafficMode::Allowlist { allow: vec![] }.validate().is_err());
+        assert!(TrafficMode::Allowlist {
+            allow: vec!["10.0.0.0/33".to_string()]
+        }
+        .validate()
+        .is_err());
+
+        assert_eq!(key(None), "bouncer:traffic_mode");
+        assert_eq!(
+            key(Some("0.0.0.0:8001")),
+            "bouncer:traffic_mode:0.0.0.0:8001"
+        );
+    }
+}
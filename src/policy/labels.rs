@@ -0,0 +1,212 @@
+use crate::config::RouteLabelConfig;
+use crate::policy::matcher::RouteMatcher;
+use axum::http::Method;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+/// Route name used for requests that match no `labels` entry
+pub const UNLABELED: &str = "unlabeled";
+
+/// Stable labels for a request, from the first matching `labels` entry
+///
+/// The policy middleware adds these to the request's extensions before the
+/// chain runs, so policies can make decisions based on the route.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RouteLabels {
+    pub name: String,
+    pub service: Option<String>,
+    pub team: Option<String>,
+}
+
+/// Point-in-time metrics for a labeled route
+#[derive(Serialize, Debug, Clone)]
+pub struct RouteStats {
+    pub name: String,
+    pub service: Option<String>,
+    pub team: Option<String>,
+    pub requests: u64,
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub avg_ms: f64,
+    pub max_ms: f64,
+}
+
+// Every live route, so metrics can be reported from one place
+static ROUTES: Lazy<Mutex<Vec<Weak<RouteMetrics>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+
+/// Metrics for every labeled route, plus unlabeled requests
+pub fn all_route_stats() -> Vec<RouteStats> {
+    let mut routes = ROUTES.lock().unwrap();
+    routes.retain(|route| route.strong_count() > 0);
+    routes
+        .iter()
+        .filter_map(|route| route.upgrade())
+        .map(|route| route.stats())
+        .collect()
+}
+
+/// Request counters for one route
+pub struct RouteMetrics {
+    labels: RouteLabels,
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+    total_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl RouteMetrics {
+    fn new(labels: RouteLabels) -> Arc<Self> {
+        let metrics = Arc::new(Self {
+            labels,
+            requests: AtomicU64::new(0),
+            client_errors: AtomicU64::new(0),
+            server_errors: AtomicU64::new(0),
+            total_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        });
+
+        ROUTES.lock().unwrap().push(Arc::downgrade(&metrics));
+        metrics
+    }
+
+    pub fn record(&self, status: u16, elapsed: Duration) {
+        let micros = elapsed.as_micros() as u64;
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        self.total_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+        match status {
+            400..=499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500..=599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+    }
+
+    pub fn stats(&self) -> RouteStats {
+        let requests = self.requests.load(Ordering::Relaxed);
+        let total_micros = self.total_micros.load(Ordering::Relaxed);
+        RouteStats {
+            name: self.labels.name.clone(),
+            service: self.labels.service.clone(),
+            team: self.labels.team.clone(),
+            requests,
+            client_errors: self.client_errors.load(Ordering::Relaxed),
+            server_errors: self.server_errors.load(Ordering::Relaxed),
+            avg_ms: if requests == 0 {
+                0.0
+            } else {
+                total_micros as f64 / requests as f64 / 1000.0
+            },
+            max_ms: self.max_micros.load(Ordering::Relaxed) as f64 / 1000.0,
+        }
+    }
+}
+
+struct LabeledRoute {
+    matcher: RouteMatcher,
+    metrics: Arc<RouteMetrics>,
+}
+
+/// The labels and metrics for a request
+pub struct RouteMatch {
+    pub labels: Option<RouteLabels>,
+    pub metrics: Arc<RouteMetrics>,
+}
+
+/// Assigns labels to requests from the `labels` section of the config
+pub struct RouteLabeler {
+    routes: Vec<LabeledRoute>,
+    unlabeled: Arc<RouteMetrics>,
+}
+
+impl Default for RouteLabeler {
+    fn default() -> Self {
+        Self::new(&[]).expect("no labels to validate")
+    }
+}
+
+impl RouteLabeler {
+    pub fn new(config: &[RouteLabelConfig]) -> Result<Self, String> {
+        let routes = config
+            .iter()
+            .map(|route| {
+                Ok(LabeledRoute {
+                    matcher: RouteMatcher::new(&route.path, &route.methods)?,
+                    metrics: RouteMetrics::new(RouteLabels {
+                        name: route.name.clone(),
+                        service: route.service.clone(),
+                        team: route.team.clone(),
+                    }),
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            routes,
+            unlabeled: RouteMetrics::new(RouteLabels {
+                name: UNLABELED.to_string(),
+                service: None,
+                team: None,
+            }),
+        })
+    }
+
+    /// Find the first route matching a request
+    pub fn label(&self, method: &Method, path: &str) -> RouteMatch {
+        let route = self
+            .routes
+            .iter()
+            .find(|route| route.matcher.matches(method, path));
+
+        match route {
+            Some(route) => RouteMatch {
+                labels: Some(route.metrics.labels.clone()),
+                metrics: route.metrics.clone(),
+            },
+            None => RouteMatch {
+                labels: None,
+                metrics: self.unlabeled.clone(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_matching() {
+        let labeler = RouteLabeler::new(&[
+            RouteLabelConfig {
+                path: "/users/{id}".to_string(),
+                methods: vec!["get".to_string()],
+                name: "get-user".to_string(),
+                service: Some("users".to_string()),
+                team: None,
+            },
+            RouteLabelConfig {
+                path: "/files/{*rest}".to_string(),
+                methods: vec![],
+                name: "files".to_string(),
+                service: None,
+                team: Some("storage".to_string()),
+            },
+        ])
+        .unwrap();
+
+        let name = |method: Method, path: &str| labeler.label(&method, path).labels.map(|l| l.name);
+        assert_eq!(name(Method::GET, "/users/42"), Some("get-user".to_string()));
+        assert_eq!(
+            name(Method::GET, "/users/42/"),
+            Some("get-user".to_string())
+        );
+        assert_eq!(name(Method::DELETE, "/users/42"), None);
+        assert_eq!(name(Method::GET, "/users/42/posts"), None);
+        assert_eq!(name(Method::PUT, "/files/a/b/c"), Some("files".to_string()));
+        assert_eq!(name(Method::GET, "/files"), Some("files".to_string()));
+    }
+}
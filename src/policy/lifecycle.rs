@@ -0,0 +1,128 @@
+use crate::policy::traits::Policy;
+use std::time::Duration;
+
+// Time each policy has to shut down before it's given up on
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+fn id(policy: &dyn Policy) -> String {
+    format!(
+        "@{}/{}/{}/{}",
+        policy.provider(),
+        policy.category(),
+        policy.name(),
+        policy.version()
+    )
+}
+
+/// Start every policy in a chain concurrently, failing if any fails
+pub async fn start(policies: &[Box<dyn Policy>]) -> Result<(), String> {
+    let errors: Vec<String> = futures::future::join_all(policies.iter().map(|policy| async move {
+        policy
+            .on_start()
+            .await
+            .map_err(|e| format!("Policy {} failed to start: {}", id(policy.as_ref()), e))
+    }))
+    .await
+    .into_iter()
+    .filter_map(Result::err)
+    .collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join("; "))
+    }
+}
+
+/// Shut down every policy in a chain concurrently
+pub async fn shut_down(policies: &[Box<dyn Policy>]) {
+    futures::future::join_all(policies.iter().map(|policy| async move {
+        if tokio::time::timeout(SHUTDOWN_TIMEOUT, policy.on_shutdown())
+            .await
+            .is_err()
+        {
+            tracing::warn!(
+                "Policy {} didn't shut down within {:?}",
+                id(policy.as_ref()),
+                SHUTDOWN_TIMEOUT
+            );
+        }
+    }))
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::traits::PolicyResult;
+    use async_trait::async_trait;
+    use axum::{body::Body, http::Request};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    struct BufferingPolicy {
+        started: Arc<AtomicBool>,
+        flushed: Arc<AtomicBool>,
+        fail: bool,
+    }
+
+    #[async_trait]
+    impl Policy for BufferingPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "traffic"
+        }
+
+        fn name(&self) -> &'static str {
+            "buffering"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, request: Request<Body>) -> PolicyResult {
+            PolicyResult::Continue(request)
+        }
+
+        async fn on_start(&self) -> Result<(), String> {
+            if self.fail {
+                return Err("no buffer".to_string());
+            }
+            self.started.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+
+        async fn on_shutdown(&self) {
+            self.flushed.store(true, Ordering::Relaxed);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let (started, flushed) = (
+            Arc::new(AtomicBool::new(false)),
+            Arc::new(AtomicBool::new(false)),
+        );
+        let policy = |fail| {
+            Box::new(BufferingPolicy {
+                started: started.clone(),
+                flushed: flushed.clone(),
+                fail,
+            }) as Box<dyn Policy>
+        };
+
+        let chain = vec![policy(false)];
+        start(&chain).await.unwrap();
+        assert!(started.load(Ordering::Relaxed));
+        shut_down(&chain).await;
+        assert!(flushed.load(Ordering::Relaxed));
+
+        assert_eq!(
+            start(&[policy(false), policy(true)]).await.unwrap_err(),
+            "Policy @test/traffic/buffering/v1 failed to start: no buffer"
+        );
+    }
+}
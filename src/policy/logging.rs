@@ -0,0 +1,326 @@
+use crate::policy::failure::FailureMode;
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyHealth, PolicyResult, ResponsePolicyResult};
+use crate::policy::websocket::WsPolicy;
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{Request, Response};
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id};
+use tracing::{Instrument, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Filter};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Most verbose level a policy logs at
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub fn filter(self) -> LevelFilter {
+        match self {
+            Self::Off => LevelFilter::OFF,
+            Self::Error => LevelFilter::ERROR,
+            Self::Warn => LevelFilter::WARN,
+            Self::Info => LevelFilter::INFO,
+            Self::Debug => LevelFilter::DEBUG,
+            Self::Trace => LevelFilter::TRACE,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+    }
+}
+
+// The `policy_log_level` of the config, for policies without their own level
+static DEFAULT_LEVEL: OnceCell<LogLevel> = OnceCell::new();
+
+/// Set the level for policies without their own `log_level`
+///
+/// Like the global config, the first call wins.
+pub fn set_default_level(level: LogLevel) {
+    let _ = DEFAULT_LEVEL.set(level);
+}
+
+/// Name of the span every policy's request processing runs in
+const POLICY_SPAN: &str = "policy";
+
+/// A policy whose request processing runs in a span naming it, so the events it
+/// logs can be filtered by [`PolicyLogFilter`]
+///
+/// It also carries the `failure_mode` and `group` of the policy's config.
+pub struct LoggedPolicy {
+    inner: Box<dyn Policy>,
+    id: String,
+    level: Option<LogLevel>,
+    failure_mode: FailureMode,
+    group: Option<Arc<str>>,
+}
+
+impl LoggedPolicy {
+    pub fn new(inner: Box<dyn Policy>, id: String, level: Option<LogLevel>) -> Self {
+        Self {
+            inner,
+            id,
+            level,
+            failure_mode: FailureMode::default(),
+            group: None,
+        }
+    }
+
+    pub fn with_failure_mode(mut self, failure_mode: FailureMode) -> Self {
+        self.failure_mode = failure_mode;
+        self
+    }
+
+    pub fn with_group(mut self, group: Option<&str>) -> Self {
+        self.group = group.map(Arc::from);
+        self
+    }
+}
+
+#[async_trait]
+impl Policy for LoggedPolicy {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn category(&self) -> &'static str {
+        self.inner.category()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn version(&self) -> &'static str {
+        self.inner.version()
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        self.inner.register_routes()
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let span = tracing::error_span!(
+            POLICY_SPAN,
+            id = %self.id,
+            log_level = self.level.map(LogLevel::as_str),
+        );
+        self.inner.process(request).instrument(span).await
+    }
+
+    fn processes_requests(&self) -> bool {
+        self.inner.processes_requests()
+    }
+
+    fn failure_mode(&self) -> FailureMode {
+        self.failure_mode
+    }
+
+    fn group(&self) -> Option<Arc<str>> {
+        self.group.clone()
+    }
+
+    fn applies_to(&self, request: &Request<Body>) -> bool {
+        self.inner.applies_to(request)
+    }
+
+    async fn process_response(&self, response: Response<Body>) -> ResponsePolicyResult {
+        let span = tracing::error_span!(
+            POLICY_SPAN,
+            id = %self.id,
+            log_level = self.level.map(LogLevel::as_str),
+        );
+        self.inner.process_response(response).instrument(span).await
+    }
+
+    fn processes_responses(&self) -> bool {
+        self.inner.processes_responses()
+    }
+
+    fn read_only(&self) -> bool {
+        self.inner.read_only()
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        self.inner.inspects_body()
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        self.inner.requires()
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        self.inner.provides()
+    }
+
+    fn websocket(&self) -> Option<Arc<dyn WsPolicy>> {
+        self.inner.websocket()
+    }
+
+    async fn health(&self) -> PolicyHealth {
+        self.inner.health().await
+    }
+
+    async fn warm_up(&self) -> Result<(), String> {
+        self.inner.warm_up().await
+    }
+
+    async fn on_start(&self) -> Result<(), String> {
+        self.inner.on_start().await
+    }
+
+    async fn on_shutdown(&self) {
+        self.inner.on_shutdown().await
+    }
+}
+
+// The level events in a policy span are logged up to, stored on the span
+struct PolicyLevel(LevelFilter);
+
+// Reads the `log_level` field of a policy span
+#[derive(Default)]
+struct LevelVisitor(Option<LogLevel>);
+
+impl Visit for LevelVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "log_level" {
+            self.0 = serde_json::from_value(serde_json::Value::from(value)).ok();
+        }
+    }
+
+    fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+}
+
+/// Filters what a layer logs by the level of the policy it was logged in
+///
+/// Events inside a policy are logged up to the policy's `log_level`, or else
+/// the config's `policy_log_level`. Everything else is logged up to
+/// `max_level`, which also caps the policies' levels.
+pub struct PolicyLogFilter {
+    max_level: LevelFilter,
+}
+
+impl PolicyLogFilter {
+    pub fn new(max_level: LevelFilter) -> Self {
+        Self { max_level }
+    }
+}
+
+impl<S> Filter<S> for PolicyLogFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, meta: &Metadata<'_>, cx: &Context<'_, S>) -> bool {
+        if self.max_level < *meta.level() {
+            return false;
+        }
+        if !meta.is_event() {
+            return true;
+        }
+
+        // The innermost policy the event was logged in decides
+        let Some(span) = cx.lookup_current() else {
+            return true;
+        };
+        for span in span.scope() {
+            if let Some(PolicyLevel(level)) = span.extensions().get::<PolicyLevel>() {
+                return *level >= *meta.level();
+            }
+        }
+        true
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(self.max_level)
+    }
+
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, cx: Context<'_, S>) {
+        if attrs.metadata().name() != POLICY_SPAN
+            || !attrs.metadata().target().starts_with(module_path!())
+        {
+            return;
+        }
+
+        let mut visitor = LevelVisitor::default();
+        attrs.record(&mut visitor);
+        let Some(level) = visitor.0.or_else(|| DEFAULT_LEVEL.get().copied()) else {
+            return;
+        };
+        if let Some(span) = cx.span(id) {
+            span.extensions_mut().insert(PolicyLevel(level.filter()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tracing_subscriber::prelude::*;
+
+    // Collects the messages of the events it's given
+    #[derive(Clone, Default)]
+    struct Messages(Arc<Mutex<Vec<String>>>);
+
+    impl<S: Subscriber> tracing_subscriber::Layer<S> for Messages {
+        fn on_event(
+            &self,
+            event: &tracing::Event<'_>,
+            _cx: tracing_subscriber::layer::Context<'_, S>,
+        ) {
+            struct Message<'a>(&'a mut Vec<String>);
+            impl Visit for Message<'_> {
+                fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                    if field.name() == "message" {
+                        self.0.push(format!("{:?}", value));
+                    }
+                }
+            }
+            event.record(&mut Message(&mut self.0.lock().unwrap()));
+        }
+    }
+
+    #[test]
+    fn test_policy_log_levels() {
+        let messages = Messages::default();
+        let subscriber = tracing_subscriber::registry().with(
+            messages
+                .clone()
+                .with_filter(PolicyLogFilter::new(LevelFilter::DEBUG)),
+        );
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::error_span!(POLICY_SPAN, id = "rbac", log_level = "warn");
+            span.in_scope(|| {
+                tracing::info!("granted");
+                tracing::warn!("denied");
+            });
+            let span = tracing::error_span!(POLICY_SPAN, id = "jwt", log_level = None::<&str>);
+            span.in_scope(|| tracing::debug!("verified"));
+            tracing::trace!("too verbose");
+        });
+
+        assert_eq!(*messages.0.lock().unwrap(), ["denied", "verified"]);
+    }
+}
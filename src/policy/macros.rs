@@ -1,7 +1,8 @@
 /// Macro to simplify policy registration for third-party crates
 ///
-/// This macro is designed to be used to register custom policies
-/// with the Bouncer server.
+/// This macro registers custom policies with a Bouncer server built into the
+/// same binary. Dynamically loaded plugins use `export_policies!` from the
+/// `bouncer-policy-sdk` crate instead.
 ///
 /// # Example
 ///
@@ -16,6 +17,11 @@
 ///
 /// #[async_trait]
 /// impl Policy for MyCustomPolicy {
+///     fn provider(&self) -> &'static str { "mycustom" }
+///     fn category(&self) -> &'static str { "custom" }
+///     fn name(&self) -> &'static str { "policy" }
+///     fn version(&self) -> &'static str { "v1" }
+///
 ///     async fn process(&self, request: Request<Body>) -> PolicyResult {
 ///         // Implementation details...
 ///         PolicyResult::Continue(request)
@@ -48,16 +54,6 @@
 #[macro_export]
 macro_rules! register_policy {
     ($policy_type:ty) => {
-        // For backward compatibility with plugin system
-        #[doc(hidden)]
-        #[no_mangle]
-        pub extern "C" fn __bouncer_register_policy(
-            registry: &mut $crate::policy::registry::PolicyRegistry,
-        ) {
-            registry.register_policy::<$policy_type>();
-        }
-
-        // For the new integrated approach
         $crate::register_custom_policy(|registry| {
             registry.register_policy::<$policy_type>();
         });
@@ -0,0 +1,264 @@
+use axum::http::Method;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    // `{name}` matches exactly one segment
+    Param(String),
+    // `{*name}` matches the rest of the path, including nothing
+    Rest(String),
+}
+
+/// A compiled path pattern
+///
+/// Patterns are written as strings, optionally with a kind prefix:
+///
+/// - `exact:/health` matches only that path
+/// - `prefix:/api` matches `/api` and everything below it
+/// - `glob:/api/*` is a glob, where `*` also matches `/`
+/// - `regex:^/v\d+/` is a regular expression that must match the whole path
+///
+/// Without a prefix, patterns with `{name}` or `{*name}` segments are route
+/// templates, patterns with `*`, `?` or `[` are globs, and anything else is
+/// exact. Trailing slashes are ignored by exact, prefix and template patterns.
+#[derive(Debug, Clone)]
+enum PathPattern {
+    Exact(String),
+    Prefix(String),
+    Glob(glob::Pattern),
+    Regex(Regex),
+    Template(Vec<Segment>),
+}
+
+// Compare paths without their trailing slash, keeping the root
+fn trim_slash(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        trimmed => trimmed,
+    }
+}
+
+impl PathPattern {
+    fn parse(pattern: &str) -> Result<Self, String> {
+        let invalid = |e: &dyn fmt::Display| format!("Invalid route pattern '{}': {}", pattern, e);
+
+        if let Some(path) = pattern.strip_prefix("exact:") {
+            Ok(Self::Exact(trim_slash(path).to_string()))
+        } else if let Some(path) = pattern.strip_prefix("prefix:") {
+            Ok(Self::Prefix(trim_slash(path).to_string()))
+        } else if let Some(glob) = pattern.strip_prefix("glob:") {
+            glob::Pattern::new(glob)
+                .map(Self::Glob)
+                .map_err(|e| invalid(&e))
+        } else if let Some(regex) = pattern.strip_prefix("regex:") {
+            Regex::new(&format!("^(?:{})$", regex))
+                .map(Self::Regex)
+                .map_err(|e| invalid(&e))
+        } else if pattern.contains('{') && pattern.contains('}') {
+            Ok(Self::Template(parse_template(pattern)))
+        } else if pattern.contains(['*', '?', '[']) {
+            glob::Pattern::new(pattern)
+                .map(Self::Glob)
+                .map_err(|e| invalid(&e))
+        } else {
+            Ok(Self::Exact(trim_slash(pattern).to_string()))
+        }
+    }
+
+    fn matches(&self, path: &str) -> bool {
+        match self {
+            Self::Exact(expected) => trim_slash(path) == expected,
+            Self::Prefix(prefix) => {
+                let path = trim_slash(path);
+                prefix == "/"
+                    || path
+                        .strip_prefix(prefix.as_str())
+                        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            }
+            Self::Glob(pattern) => pattern.matches(path),
+            Self::Regex(regex) => regex.is_match(path),
+            Self::Template(segments) => match_template(segments, path).is_some(),
+        }
+    }
+
+    /// Match a path, returning the values of named template segments or regex groups
+    fn captures(&self, path: &str) -> Option<HashMap<String, String>> {
+        match self {
+            Self::Template(segments) => match_template(segments, path),
+            Self::Regex(regex) => {
+                let captures = regex.captures(path)?;
+                Some(
+                    regex
+                        .capture_names()
+                        .flatten()
+                        .filter_map(|name| {
+                            let value = captures.name(name)?;
+                            Some((name.to_string(), value.as_str().to_string()))
+                        })
+                        .collect(),
+                )
+            }
+            _ => self.matches(path).then(HashMap::new),
+        }
+    }
+}
+
+fn parse_template(pattern: &str) -> Vec<Segment> {
+    pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(
+            |segment| match segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+                Some(name) => match name.strip_prefix('*') {
+                    Some(name) => Segment::Rest(name.to_string()),
+                    None => Segment::Param(name.to_string()),
+                },
+                None => Segment::Literal(segment.to_string()),
+            },
+        )
+        .collect()
+}
+
+fn match_template(pattern: &[Segment], path: &str) -> Option<HashMap<String, String>> {
+    let mut params = HashMap::new();
+    let mut segments = path.split('/').filter(|segment| !segment.is_empty());
+    for expected in pattern {
+        match expected {
+            Segment::Rest(name) => {
+                params.insert(name.clone(), segments.collect::<Vec<_>>().join("/"));
+                return Some(params);
+            }
+            Segment::Param(name) => {
+                params.insert(name.clone(), segments.next()?.to_string());
+            }
+            Segment::Literal(literal) => {
+                if segments.next() != Some(literal.as_str()) {
+                    return None;
+                }
+            }
+        }
+    }
+    segments.next().is_none().then_some(params)
+}
+
+/// A path pattern, optionally restricted to some methods
+///
+/// Policies and config sections that match requests against routes share this,
+/// so a pattern means the same thing everywhere. Patterns are compiled once,
+/// when the matcher is created.
+#[derive(Debug, Clone)]
+pub struct RouteMatcher {
+    source: String,
+    methods: Vec<Method>,
+    path: PathPattern,
+}
+
+impl RouteMatcher {
+    /// Compile a path pattern for the given methods, or all methods if empty
+    pub fn new(path: &str, methods: &[String]) -> Result<Self, String> {
+        let methods = methods
+            .iter()
+            .map(|method| {
+                Method::from_bytes(method.to_uppercase().as_bytes())
+                    .map_err(|_| format!("Invalid method '{}' for route '{}'", method, path))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            source: path.to_string(),
+            methods,
+            path: PathPattern::parse(path)?,
+        })
+    }
+
+    /// Compile a pattern that may start with comma-separated methods, e.g. `GET,HEAD /api/*`
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let (methods, path) = match pattern.split_once(' ') {
+            Some((methods, path)) if !methods.starts_with('/') && !methods.contains(':') => (
+                methods.split(',').map(str::to_string).collect(),
+                path.trim(),
+            ),
+            _ => (Vec::new(), pattern),
+        };
+
+        let mut matcher = Self::new(path, &methods)?;
+        matcher.source = pattern.to_string();
+        Ok(matcher)
+    }
+
+    /// The pattern the matcher was compiled from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn matches(&self, method: &Method, path: &str) -> bool {
+        self.allows_method(method) && self.path.matches(path)
+    }
+
+    /// Match a request, returning the values of the pattern's parameters
+    pub fn captures(&self, method: &Method, path: &str) -> Option<HashMap<String, String>> {
+        if !self.allows_method(method) {
+            return None;
+        }
+        self.path.captures(path)
+    }
+
+    fn allows_method(&self, method: &Method) -> bool {
+        self.methods.is_empty() || self.methods.contains(method)
+    }
+}
+
+/// Compile a list of patterns, as accepted by [`RouteMatcher::parse`]
+pub fn compile_all(patterns: &[String]) -> Result<Vec<RouteMatcher>, String> {
+    patterns
+        .iter()
+        .map(|pattern| RouteMatcher::parse(pattern))
+        .collect()
+}
+
+impl fmt::Display for RouteMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pattern_kinds() {
+        let matches = |pattern: &str, path: &str| {
+            RouteMatcher::parse(pattern)
+                .unwrap()
+                .matches(&Method::GET, path)
+        };
+
+        assert!(matches("/health", "/health/"));
+        assert!(!matches("/health", "/health/live"));
+        assert!(matches("prefix:/api", "/api/orders"));
+        assert!(!matches("prefix:/api", "/apis"));
+        assert!(matches("/api/*", "/api/orders/1"));
+        assert!(matches(r"regex:/v\d+/.*", "/v2/orders"));
+        assert!(!matches(r"regex:/v\d+", "/v2/orders"));
+        assert!(matches("/users/{id}", "/users/42"));
+        assert!(matches("GET,HEAD /users/{id}", "/users/42"));
+        assert!(!matches("POST /users/{id}", "/users/42"));
+
+        // Extension methods are matched like any other, case-insensitively
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        let matcher = RouteMatcher::parse("propfind,REPORT /dav/*").unwrap();
+        assert!(matcher.matches(&propfind, "/dav/notes.txt"));
+        assert!(!matcher.matches(&Method::GET, "/dav/notes.txt"));
+        assert!(RouteMatcher::parse("PROP(FIND /dav/*").is_err());
+        assert!(RouteMatcher::parse("/api/[").is_err());
+        assert!(RouteMatcher::parse("regex:(").is_err());
+
+        let matcher = RouteMatcher::parse(r"regex:/orders/(?P<id>\d+)").unwrap();
+        let params = matcher.captures(&Method::GET, "/orders/7").unwrap();
+        assert_eq!(params.get("id").map(String::as_str), Some("7"));
+    }
+}
@@ -1,25 +1,221 @@
-use crate::policy::traits::{Policy, PolicyResult};
+use crate::events::{recent_denials, DecisionEvent, DecisionEventKind, EventEmitter};
+use crate::metering::UsageMeter;
+use crate::policy::body::{inspect_body, is_too_large, too_large_response, BodyLimits};
+use crate::policy::context::{context, context_mut, PolicyContext};
+use crate::policy::deadline::Deadlines;
+use crate::policy::denial::{self, DenialBodies, DenialMessages};
+use crate::policy::failure::resolve;
+use crate::policy::forwarded::{client_ip, ClientIp, TrustedProxies};
+use crate::policy::headers::ProtectedHeaders;
+use crate::policy::kill_switch::KillSwitch;
+use crate::policy::labels::{RouteLabeler, UNLABELED};
+use crate::policy::matcher::RouteMatcher;
+use crate::policy::pipeline::{Pipeline, Stage};
+use crate::policy::sessions::sessions;
+use crate::policy::staging::{Staging, Variant};
+use crate::policy::traits::{Policy, ResponseHeaders, ResponsePolicyResult};
+use crate::policy::transform::{apply_transforms, ResponseTransforms};
+use crate::policy::websocket::{is_upgrade_request, WsChain};
 use axum::{
     body::Body,
-    http::{Request, Response},
+    extract::ConnectInfo,
+    http::{header, HeaderMap, Request, Response, StatusCode},
 };
 use futures::future::BoxFuture;
-use std::sync::Arc;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
 use std::task::{Context, Poll};
+use std::time::Instant;
 use tower::{Layer, Service};
+use tracing::Instrument;
+
+/// Handle to the active policy chain, which can be swapped at runtime
+///
+/// Each request takes a snapshot of the chain when it starts, so requests that are
+/// in flight during a swap finish on the previous chain, which is dropped once the
+/// last of them completes.
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct PolicyChainHandle {
+    current: Arc<RwLock<Arc<Vec<Box<dyn Policy>>>>>,
+}
+
+impl PolicyChainHandle {
+    pub fn new(policies: Vec<Box<dyn Policy>>) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(Arc::new(policies))),
+        }
+    }
+
+    /// Get the currently active chain
+    pub fn load(&self) -> Arc<Vec<Box<dyn Policy>>> {
+        Arc::clone(&self.current.read().unwrap())
+    }
+
+    /// Replace the active chain, returning the previous one
+    pub fn swap(&self, policies: Vec<Box<dyn Policy>>) -> Arc<Vec<Box<dyn Policy>>> {
+        self.store(Arc::new(policies))
+    }
+
+    /// Replace the active chain with one that is already shared, returning the previous one
+    pub fn store(&self, policies: Arc<Vec<Box<dyn Policy>>>) -> Arc<Vec<Box<dyn Policy>>> {
+        std::mem::replace(&mut *self.current.write().unwrap(), policies)
+    }
+}
+
+// Body bytes read for inspecting policies unless configured otherwise
+const DEFAULT_BODY_INSPECTION_LIMIT: usize = 1024 * 1024;
 
 // Our middleware layer
 #[derive(Clone)]
 pub struct PolicyLayer {
-    policies: Arc<Vec<Box<dyn Policy>>>,
+    chain: PolicyChainHandle,
+    protected_headers: Arc<ProtectedHeaders>,
+    route_labels: Arc<RouteLabeler>,
+    events: Arc<EventEmitter>,
+    staging: Option<Arc<Staging>>,
+    parallel: bool,
+    denial_bodies: DenialBodies,
+    denial_messages: Arc<DenialMessages>,
+    bypass: Arc<Vec<RouteMatcher>>,
+    deadlines: Deadlines,
+    body_inspection_limit: usize,
+    track_sessions: bool,
+    kill_switch: Option<Arc<KillSwitch>>,
+    meter: Option<Arc<UsageMeter>>,
+    trusted_proxies: Arc<TrustedProxies>,
+    pipeline: Arc<Pipeline>,
+    body_limits: Arc<BodyLimits>,
 }
 
 impl PolicyLayer {
     pub fn new(policies: Vec<Box<dyn Policy>>) -> Self {
+        Self::from_handle(PolicyChainHandle::new(policies))
+    }
+
+    /// Create a layer whose chain can be swapped through the given handle
+    pub fn from_handle(chain: PolicyChainHandle) -> Self {
         Self {
-            policies: Arc::new(policies),
+            chain,
+            protected_headers: Arc::new(ProtectedHeaders::default()),
+            route_labels: Arc::new(RouteLabeler::default()),
+            events: Arc::new(EventEmitter::default()),
+            staging: None,
+            parallel: false,
+            denial_bodies: DenialBodies::default(),
+            denial_messages: Arc::new(DenialMessages::default()),
+            bypass: Arc::new(Vec::new()),
+            deadlines: Deadlines::default(),
+            body_inspection_limit: DEFAULT_BODY_INSPECTION_LIMIT,
+            track_sessions: false,
+            kill_switch: None,
+            meter: None,
+            trusted_proxies: Arc::new(TrustedProxies::default()),
+            pipeline: Arc::new(Pipeline::default()),
+            body_limits: Arc::new(BodyLimits::default()),
         }
     }
+
+    /// Strip these protected headers from requests instead of only `x-bouncer-*`
+    pub fn with_protected_headers(mut self, protected_headers: Arc<ProtectedHeaders>) -> Self {
+        self.protected_headers = protected_headers;
+        self
+    }
+
+    /// Label requests with these routes in metrics, access logs and traces
+    pub fn with_route_labels(mut self, route_labels: Arc<RouteLabeler>) -> Self {
+        self.route_labels = route_labels;
+        self
+    }
+
+    /// Send rejections to these webhooks
+    pub fn with_events(mut self, events: Arc<EventEmitter>) -> Self {
+        self.events = events;
+        self
+    }
+
+    /// Send a share of the requests through a staged chain instead
+    pub fn with_staging(mut self, staging: Arc<Staging>) -> Self {
+        self.staging = Some(staging);
+        self
+    }
+
+    /// Run consecutive read-only policies concurrently instead of one by one
+    pub fn with_parallel_policies(mut self, parallel: bool) -> Self {
+        self.parallel = parallel;
+        self
+    }
+
+    /// How much of a denial's reason is sent to the client
+    pub fn with_denial_bodies(mut self, denial_bodies: DenialBodies) -> Self {
+        self.denial_bodies = denial_bodies;
+        self
+    }
+
+    /// Denial messages in the languages clients ask for
+    pub fn with_denial_messages(mut self, denial_messages: Arc<DenialMessages>) -> Self {
+        self.denial_messages = denial_messages;
+        self
+    }
+
+    /// Skip the policy chain for requests matching these routes
+    pub fn with_bypass(mut self, bypass: Arc<Vec<RouteMatcher>>) -> Self {
+        self.bypass = bypass;
+        self
+    }
+
+    /// Give requests a deadline, enforced across the policy chain and the inner service
+    pub fn with_deadlines(mut self, deadlines: Deadlines) -> Self {
+        self.deadlines = deadlines;
+        self
+    }
+
+    /// Read at most this much of a request body for policies that inspect it
+    pub fn with_body_inspection_limit(mut self, limit: usize) -> Self {
+        self.body_inspection_limit = limit;
+        self
+    }
+
+    /// Register requests that pass the chain in the session registry, so they
+    /// can be terminated while in flight
+    pub fn with_sessions(mut self, track: bool) -> Self {
+        self.track_sessions = track;
+        self
+    }
+
+    /// Reject requests the kill switch's current traffic mode doesn't allow
+    pub fn with_kill_switch(mut self, kill_switch: Arc<KillSwitch>) -> Self {
+        self.kill_switch = Some(kill_switch);
+        self
+    }
+
+    /// Count the requests and bytes of identified requests that pass the chain
+    pub fn with_meter(mut self, meter: Arc<UsageMeter>) -> Self {
+        self.meter = Some(meter);
+        self
+    }
+
+    /// Resolve the client IP through `X-Forwarded-For` from these proxies
+    pub fn with_trusted_proxies(mut self, trusted_proxies: Arc<TrustedProxies>) -> Self {
+        self.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    /// Run the built-in steps of this ingress pipeline around bypass and the chain
+    pub fn with_pipeline(mut self, pipeline: Arc<Pipeline>) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Reject request bodies over these limits before anything else is done with them
+    pub fn with_body_limits(mut self, body_limits: Arc<BodyLimits>) -> Self {
+        self.body_limits = body_limits;
+        self
+    }
+
+    pub fn handle(&self) -> PolicyChainHandle {
+        self.chain.clone()
+    }
 }
 
 impl<S> Layer<S> for PolicyLayer {
@@ -27,7 +223,23 @@ impl<S> Layer<S> for PolicyLayer {
 
     fn layer(&self, inner: S) -> Self::Service {
         PolicyService {
-            policies: Arc::clone(&self.policies),
+            chain: self.chain.clone(),
+            protected_headers: self.protected_headers.clone(),
+            route_labels: self.route_labels.clone(),
+            events: self.events.clone(),
+            staging: self.staging.clone(),
+            parallel: self.parallel,
+            denial_bodies: self.denial_bodies,
+            denial_messages: self.denial_messages.clone(),
+            bypass: self.bypass.clone(),
+            deadlines: self.deadlines.clone(),
+            body_inspection_limit: self.body_inspection_limit,
+            track_sessions: self.track_sessions,
+            kill_switch: self.kill_switch.clone(),
+            meter: self.meter.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+            pipeline: self.pipeline.clone(),
+            body_limits: self.body_limits.clone(),
             inner,
         }
     }
@@ -36,7 +248,23 @@ impl<S> Layer<S> for PolicyLayer {
 // The actual service that will process requests
 #[derive(Clone)]
 pub struct PolicyService<S> {
-    policies: Arc<Vec<Box<dyn Policy>>>,
+    chain: PolicyChainHandle,
+    protected_headers: Arc<ProtectedHeaders>,
+    route_labels: Arc<RouteLabeler>,
+    events: Arc<EventEmitter>,
+    staging: Option<Arc<Staging>>,
+    parallel: bool,
+    denial_bodies: DenialBodies,
+    denial_messages: Arc<DenialMessages>,
+    bypass: Arc<Vec<RouteMatcher>>,
+    deadlines: Deadlines,
+    body_inspection_limit: usize,
+    track_sessions: bool,
+    kill_switch: Option<Arc<KillSwitch>>,
+    meter: Option<Arc<UsageMeter>>,
+    trusted_proxies: Arc<TrustedProxies>,
+    pipeline: Arc<Pipeline>,
+    body_limits: Arc<BodyLimits>,
     inner: S,
 }
 
@@ -53,47 +281,455 @@ where
         self.inner.poll_ready(cx)
     }
 
-    fn call(&mut self, request: Request<Body>) -> Self::Future {
-        let policies = Arc::clone(&self.policies);
+    fn call(&mut self, mut request: Request<Body>) -> Self::Future {
+        // Everything after this sees the client behind any trusted proxies
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        if let Some(peer) = peer {
+            let ip = self.trusted_proxies.resolve(peer, request.headers());
+            request.extensions_mut().insert(ClientIp(ip));
+        }
+
+        // Everything after this sees the normalized path, if it's normalized first
+        self.pipeline.normalize_first(&mut request);
+
+        // The traffic mode applies before anything else, except to the admin
+        // routes that switch it back
+        if let Some(kill_switch) = &self.kill_switch {
+            let client_ip = client_ip(&request);
+            if !request.uri().path().starts_with("/_admin") {
+                if let Some(message) = kill_switch.rejects(request.method(), client_ip) {
+                    return Box::pin(async move {
+                        Ok(Response::builder()
+                            .status(StatusCode::SERVICE_UNAVAILABLE)
+                            .body(Body::from(message))
+                            .unwrap())
+                    });
+                }
+            }
+        }
+
+        // Bodies over their limit are turned away before any policy or
+        // upstream work, and longer ones are cut off as they're read
+        request = match self.body_limits.apply(request) {
+            Ok(request) => request,
+            Err(response) => return Box::pin(async move { Ok(response) }),
+        };
+
+        let (policies, variant) = match &self.staging {
+            Some(staging) => staging.select(),
+            None => (self.chain.load(), Variant::Stable),
+        };
+        let staging = self.staging.clone();
+        let protected_headers = self.protected_headers.clone();
+        let events = self.events.clone();
+        let parallel = self.parallel;
+        let denial_bodies = self.denial_bodies;
+        let denial_messages = self.denial_messages.clone();
+        let body_inspection_limit = self.body_inspection_limit;
+        let track_sessions = self.track_sessions;
+        let meter = self.meter.clone();
+        let pipeline = self.pipeline.clone();
         let mut inner = self.inner.clone();
 
-        Box::pin(async move {
+        let started = Instant::now();
+        let method = request.method().clone();
+        let path = request.uri().path().to_string();
+        let route = self.route_labels.label(&method, &path);
+        let bypassed = self
+            .bypass
+            .iter()
+            .any(|matcher| matcher.matches(&method, &path));
+
+        let (name, service, team) = match &route.labels {
+            Some(labels) => (
+                labels.name.clone(),
+                labels.service.clone(),
+                labels.team.clone(),
+            ),
+            None => (UNLABELED.to_string(), None, None),
+        };
+        let span = tracing::info_span!(
+            "request",
+            route = %name,
+            service = service.as_deref(),
+            team = team.as_deref(),
+            chain = variant.as_str(),
+        );
+
+        // Denials are correlated by the client's request ID, if it sent one
+        let request_id = denial::request_id(&request);
+        let accept_language = request
+            .headers()
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let event_method = method.to_string();
+        let event_path = path.clone();
+        let event_route = route.labels.as_ref().map(|labels| labels.name.clone());
+        let session_route = name.clone();
+
+        // Policies can read the labels to make decisions based on the route
+        if let Some(labels) = route.labels {
+            request.extensions_mut().insert(labels);
+        }
+
+        // Policies and the upstream call share the request's deadline
+        let deadline = self.deadlines.deadline(request.headers());
+        if let Some(deadline) = deadline {
+            request.extensions_mut().insert(deadline);
+        }
+
+        let process = async move {
             let mut current_request = request;
+            let ip = client_ip(&current_request);
+            if let Some(ip) = ip {
+                context_mut(&mut current_request).set_client_ip(ip);
+            }
+            let client_ip = ip.map(|ip| ip.to_string());
 
-            // Prevent injection of protected bouncer headers
-            clear_bouncer_headers(current_request.headers_mut());
+            // Prevent injection of headers only policies may set
+            protected_headers.strip(current_request.headers_mut());
+
+            // Built-in steps configured before bypass apply to every request,
+            // those after it only to requests that go through the chain
+            current_request = match pipeline.run(Stage::Ingress, current_request).await {
+                Ok(request) => request,
+                Err(response) => return Ok(response),
+            };
+            if !bypassed {
+                current_request = match pipeline.run(Stage::Routed, current_request).await {
+                    Ok(request) => request,
+                    Err(response) => return Ok(response),
+                };
+            }
 
-            // Process each policy in the chain
-            for policy in policies.iter() {
-                match policy.process(current_request).await {
-                    PolicyResult::Continue(req) => {
+            // Process each policy in the chain, or each group of policies that can
+            // run concurrently. Bypassed routes skip the chain entirely
+            let mut remaining = if bypassed { &[][..] } else { &policies[..] };
+
+            // Read the start of the body once, for every policy that inspects it
+            let inspect = remaining
+                .iter()
+                .filter_map(|policy| policy.inspects_body())
+                .max()
+                .map_or(0, |bytes| bytes.min(body_inspection_limit));
+            if inspect > 0 {
+                current_request = match inspect_body(current_request, inspect).await {
+                    Ok(request) => request,
+                    Err(e) if is_too_large(&e) => return Ok(too_large_response()),
+                    Err(e) => {
+                        tracing::warn!("Failed to read request body: {}", e);
+                        return Ok(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from("Failed to read request body"))
+                            .unwrap());
+                    }
+                };
+            }
+            // Policies that processed the request, to process its response
+            let mut applied: Vec<&dyn Policy> = Vec::new();
+            while !remaining.is_empty() {
+                let size = match configured_group_len(remaining) {
+                    Some(size) => size,
+                    None if parallel => parallel_group_len(remaining),
+                    None => 1,
+                };
+                let (group, rest) = remaining.split_at(size);
+                remaining = rest;
+
+                // Policies whose `apply_if` doesn't match let the request through
+                let group: Vec<&dyn Policy> = group
+                    .iter()
+                    .map(|policy| policy.as_ref())
+                    .filter(|policy| policy.applies_to(&current_request))
+                    .collect();
+                if group.is_empty() {
+                    continue;
+                }
+
+                // Identity set by an earlier authentication policy, to log denials for the
+                // user. Taken from the context, since clients can send the headers themselves
+                let owner = context(&current_request)
+                    .and_then(PolicyContext::identity)
+                    .and_then(|identity| identity.owner.clone());
+
+                match process_group(&group, current_request).await {
+                    Ok(req) => {
                         // Continue to the next policy with the possibly modified request
                         current_request = req;
+                        applied.extend(group);
                     }
-                    PolicyResult::Terminate(response) => {
+                    Err((policy, response)) => {
+                        let request_id = request_id.unwrap_or_else(denial::new_request_id);
+                        let response = denial::complete(
+                            policy,
+                            response,
+                            &request_id,
+                            accept_language.as_deref(),
+                            denial_bodies,
+                            &denial_messages,
+                        );
+                        if events.is_enabled() || owner.is_some() {
+                            let status = response.status().as_u16();
+                            let mut event = DecisionEvent::new(
+                                DecisionEventKind::classify(policy.category(), status),
+                                format!(
+                                    "@{}/{}/{}/{}",
+                                    policy.provider(),
+                                    policy.category(),
+                                    policy.name(),
+                                    policy.version()
+                                ),
+                                status,
+                                event_method,
+                                event_path,
+                            );
+                            event.route = event_route;
+                            event.client_ip = client_ip;
+                            if let Some(owner) = &owner {
+                                recent_denials().record(owner, event.clone());
+                            }
+                            events.emit(event);
+                        }
+
                         // Return early with the response from the policy
                         return Ok(response);
                     }
                 }
             }
 
+            if !bypassed {
+                current_request = match pipeline.run(Stage::Forward, current_request).await {
+                    Ok(request) => request,
+                    Err(response) => return Ok(response),
+                };
+            }
+
+            // Websocket connections run the chain's message policies once opened
+            if !bypassed && is_upgrade_request(current_request.headers()) {
+                current_request
+                    .extensions_mut()
+                    .insert(WsChain(policies.clone()));
+            }
+
+            // Headers and body transforms added by policies for the response
+            let response_headers = current_request.extensions_mut().remove::<ResponseHeaders>();
+            let response_transforms = current_request
+                .extensions_mut()
+                .remove::<ResponseTransforms>();
+
+            // Track the request until its response has streamed, so it can be terminated
+            let mut session = track_sessions
+                .then(|| sessions().open(&current_request, &session_route, client_ip));
+
+            // Count the request and its bytes towards its identity's usage
+            let (current_request, metered) = match &meter {
+                Some(meter) => meter.start(current_request),
+                None => (current_request, None),
+            };
+
             // If all policies pass, forward the request to the inner service
-            inner.call(current_request).await
-        })
+            let mut response = match &mut session {
+                Some(session) => tokio::select! {
+                    response = inner.call(current_request) => response?,
+                    _ = session.terminated() => {
+                        return Ok(Response::builder()
+                            .status(StatusCode::FORBIDDEN)
+                            .body(Body::from("Session terminated"))
+                            .unwrap());
+                    }
+                },
+                None => inner.call(current_request).await?,
+            };
+            if let Some(ResponseHeaders(headers)) = response_headers {
+                response.headers_mut().extend(headers);
+            }
+            if let Some(transforms) = response_transforms {
+                response = apply_transforms(response, transforms);
+            }
+
+            // Policies that processed the request see its response in reverse order
+            for policy in applied.iter().rev() {
+                if !policy.processes_responses() {
+                    continue;
+                }
+                match policy.process_response(response).await {
+                    ResponsePolicyResult::Continue(next) => response = next,
+                    ResponsePolicyResult::Terminate(last) => {
+                        response = last;
+                        break;
+                    }
+                }
+            }
+            if let Some(session) = session {
+                response = response.map(|body| session.track_body(body));
+            }
+            if let Some(metered) = metered {
+                response = response.map(|body| metered.track_body(body));
+            }
+            Ok(response)
+        };
+
+        Box::pin(
+            async move {
+                let result = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline.0, process)
+                        .await
+                        .unwrap_or_else(|_| {
+                            tracing::warn!("Request deadline exceeded");
+                            Ok(Response::builder()
+                                .status(StatusCode::GATEWAY_TIMEOUT)
+                                .body(Body::from("Request deadline exceeded"))
+                                .unwrap())
+                        }),
+                    None => process.await,
+                };
+                if let Ok(response) = &result {
+                    let elapsed = started.elapsed();
+                    let status = response.status().as_u16();
+                    route.metrics.record(status, elapsed);
+                    // Bypassed requests never reach either chain
+                    if let Some(staging) = staging.as_ref().filter(|_| !bypassed) {
+                        staging.record(variant, status, elapsed);
+                    }
+                    tracing::info!(
+                        method = %method,
+                        path = %path,
+                        status,
+                        latency_ms = elapsed.as_secs_f64() * 1000.0,
+                        "access"
+                    );
+                }
+                result
+            }
+            .instrument(span),
+        )
     }
 }
 
-// Clear all headers that start with x-bouncer-
-fn clear_bouncer_headers(headers: &mut axum::http::HeaderMap) {
-    let bouncer_headers: Vec<_> = headers
+// Number of policies at the start of `policies` in the same configured group,
+// if the first is in one
+fn configured_group_len(policies: &[Box<dyn Policy>]) -> Option<usize> {
+    let group = policies.first()?.group()?;
+    Some(
+        policies
+            .iter()
+            .take_while(|policy| policy.group().as_ref() == Some(&group))
+            .count(),
+    )
+}
+
+// Number of policies at the start of `policies` that can run together: a run
+// of read-only policies outside configured groups that provide nothing later
+// policies rely on, or one
+fn parallel_group_len(policies: &[Box<dyn Policy>]) -> usize {
+    policies
         .iter()
-        .filter(|(name, _)| name.as_str().to_lowercase().starts_with("x-bouncer-"))
-        .map(|(name, _)| name.clone())
-        .collect();
+        .take_while(|policy| {
+            policy.read_only() && policy.provides().is_empty() && policy.group().is_none()
+        })
+        .count()
+        .max(1)
+}
 
-    for name in bouncer_headers {
-        headers.remove(name);
+// A copy of a request without its body, for read-only policies
+fn copy_request(request: &Request<Body>) -> Request<Body> {
+    let mut copy = Request::new(Body::empty());
+    *copy.method_mut() = request.method().clone();
+    *copy.uri_mut() = request.uri().clone();
+    *copy.version_mut() = request.version();
+    *copy.headers_mut() = request.headers().clone();
+    *copy.extensions_mut() = request.extensions().clone();
+    copy
+}
+
+// Set and remove the headers in `headers` that `changed` sets or removes
+// compared to `original`
+fn merge_header_changes(headers: &mut HeaderMap, original: &HeaderMap, changed: &HeaderMap) {
+    for name in original.keys() {
+        if !changed.contains_key(name) {
+            headers.remove(name);
+        }
+    }
+    for name in changed.keys() {
+        let values = changed.get_all(name);
+        if !values.iter().eq(original.get_all(name).iter()) {
+            headers.remove(name);
+            for value in values {
+                headers.append(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+// Apply the changes a policy in a group made to its copy of `original`
+//
+// Headers, the method and the URI change as they did on the copy, and the
+// context and response headers are merged. Other extensions are replaced by
+// the copy's.
+fn merge_changes(request: &mut Request<Body>, original: &Request<Body>, changed: Request<Body>) {
+    let (mut parts, _) = changed.into_parts();
+    if parts.method != original.method() {
+        *request.method_mut() = parts.method;
+    }
+    if parts.uri != *original.uri() {
+        *request.uri_mut() = parts.uri;
+    }
+    merge_header_changes(request.headers_mut(), original.headers(), &parts.headers);
+
+    if let Some(changed) = parts.extensions.remove::<PolicyContext>() {
+        context_mut(request).merge_changes(context(original), changed);
+    }
+    if let Some(ResponseHeaders(changed)) = parts.extensions.remove::<ResponseHeaders>() {
+        let empty = HeaderMap::new();
+        let original = original
+            .extensions()
+            .get::<ResponseHeaders>()
+            .map_or(&empty, |ResponseHeaders(headers)| headers);
+        let headers = &mut request
+            .extensions_mut()
+            .get_or_insert_default::<ResponseHeaders>()
+            .0;
+        merge_header_changes(headers, original, &changed);
+    }
+    request.extensions_mut().extend(parts.extensions);
+}
+
+// Run a group of policies on a request, returning it if they all let it through,
+// or else the first policy in chain order that rejected it with its response
+//
+// Every policy in a group of several runs, even if another rejects the request.
+// Their changes to the request are merged in chain order, so where two change
+// the same thing the later one wins.
+async fn process_group<'a>(
+    group: &[&'a dyn Policy],
+    request: Request<Body>,
+) -> Result<Request<Body>, (&'a dyn Policy, Response<Body>)> {
+    if let [policy] = group {
+        return resolve(*policy, policy.process(request).await)
+            .map_err(|response| (*policy, response));
+    }
+
+    let results = futures::future::join_all(
+        group
+            .iter()
+            .map(|policy| policy.process(copy_request(&request))),
+    )
+    .await;
+    let mut changed = Vec::with_capacity(results.len());
+    for (policy, result) in group.iter().zip(results) {
+        changed.push(resolve(*policy, result).map_err(|response| (*policy, response))?);
     }
+
+    let original = copy_request(&request);
+    let mut request = request;
+    for changed in changed {
+        merge_changes(&mut request, &original, changed);
+    }
+    Ok(request)
 }
 
 // Extension trait to make it easy to use the policy chain with Axum
@@ -106,3 +742,320 @@ impl PolicyChainExt for Vec<Box<dyn Policy>> {
         PolicyLayer::new(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::providers::bouncer::authentication::identity::Identity;
+    use crate::policy::providers::bouncer::development::mock::v1::{
+        MockConfig, MockPolicyFactory, MockRouteConfig,
+    };
+    use crate::policy::traits::{PolicyFactory, PolicyResult};
+
+    async fn mock(path: &str, status: u16) -> Box<dyn Policy> {
+        let policy = MockPolicyFactory::new(MockConfig {
+            routes: vec![MockRouteConfig {
+                path: path.to_string(),
+                methods: vec![],
+                status,
+                headers: Default::default(),
+                body: serde_json::Value::Null,
+                latency_ms: 0,
+            }],
+        })
+        .await
+        .unwrap();
+        Box::new(policy)
+    }
+
+    #[tokio::test]
+    async fn test_parallel_group() {
+        let group = vec![mock("/a", 403).await, mock("/b", 429).await];
+        assert_eq!(parallel_group_len(&group), 2);
+        let group: Vec<&dyn Policy> = group.iter().map(|policy| policy.as_ref()).collect();
+
+        let request = Request::get("/b").body(Body::empty()).unwrap();
+        let Err((_, response)) = process_group(&group, request).await else {
+            panic!("expected the request to be rejected");
+        };
+        assert_eq!(response.status().as_u16(), 429);
+
+        let request = Request::get("/c").body(Body::empty()).unwrap();
+        let request = process_group(&group, request).await.ok().unwrap();
+        assert_eq!(request.uri().path(), "/c");
+    }
+
+    // Sets `x-<name>` and a context value, and removes `x-remove`
+    struct EnrichPolicy {
+        name: &'static str,
+    }
+
+    #[async_trait::async_trait]
+    impl Policy for EnrichPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "traffic"
+        }
+
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+            let header = axum::http::HeaderName::try_from(format!("x-{}", self.name)).unwrap();
+            request.headers_mut().insert(header, "1".parse().unwrap());
+            request
+                .headers_mut()
+                .insert("x-last", self.name.parse().unwrap());
+            request.headers_mut().remove("x-remove");
+            context_mut(&mut request).insert(format!("{}.ran", self.name), true);
+            PolicyResult::Continue(request)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_configured_group() {
+        let grouped = |name| {
+            Box::new(
+                crate::policy::logging::LoggedPolicy::new(
+                    Box::new(EnrichPolicy { name }),
+                    name.to_string(),
+                    None,
+                )
+                .with_group(Some("enrich")),
+            ) as Box<dyn Policy>
+        };
+        let chain = vec![grouped("geo"), grouped("audit"), mock("/a", 403).await];
+        assert_eq!(configured_group_len(&chain), Some(2));
+        assert_eq!(configured_group_len(&chain[2..]), None);
+        // Read-only policies don't join a configured group
+        assert_eq!(
+            parallel_group_len(&[mock("/a", 403).await, grouped("geo")]),
+            1
+        );
+
+        let group: Vec<&dyn Policy> = chain[..2].iter().map(|policy| policy.as_ref()).collect();
+        let request = Request::get("/")
+            .header("x-remove", "1")
+            .header("x-keep", "1")
+            .body(Body::empty())
+            .unwrap();
+        let request = process_group(&group, request).await.ok().unwrap();
+        assert_eq!(request.headers()["x-geo"], "1");
+        assert_eq!(request.headers()["x-audit"], "1");
+        assert_eq!(request.headers()["x-last"], "audit");
+        assert_eq!(request.headers()["x-keep"], "1");
+        assert!(request.headers().get("x-remove").is_none());
+        let context = context(&request).unwrap();
+        assert!(context.get("geo.ran").is_some() && context.get("audit.ran").is_some());
+    }
+
+    // Appends its name to `x-order` on responses, or replaces them when `terminate` is set
+    struct ResponsePolicy {
+        name: &'static str,
+        terminate: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl Policy for ResponsePolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "response"
+        }
+
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process_response(&self, mut response: Response<Body>) -> ResponsePolicyResult {
+            if self.terminate {
+                return ResponsePolicyResult::Terminate(
+                    Response::builder()
+                        .status(StatusCode::BAD_GATEWAY)
+                        .body(Body::empty())
+                        .unwrap(),
+                );
+            }
+            let order = match response.headers().get("x-order") {
+                Some(order) => format!("{},{}", order.to_str().unwrap(), self.name),
+                None => self.name.to_string(),
+            };
+            response
+                .headers_mut()
+                .insert("x-order", order.parse().unwrap());
+            ResponsePolicyResult::Continue(response)
+        }
+
+        fn processes_responses(&self) -> bool {
+            true
+        }
+    }
+
+    fn response_policy(name: &'static str, terminate: bool) -> Box<dyn Policy> {
+        Box::new(ResponsePolicy { name, terminate })
+    }
+
+    #[tokio::test]
+    async fn test_process_response() {
+        use tower::ServiceExt;
+
+        let upstream = tower::service_fn(|_: Request<Body>| async {
+            Ok::<_, std::convert::Infallible>(Response::new(Body::from("upstream")))
+        });
+
+        let service = PolicyLayer::new(vec![
+            response_policy("first", false),
+            response_policy("second", false),
+        ])
+        .layer(upstream);
+        let response = service
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.headers()["x-order"], "second,first");
+
+        // Policies before the one that terminates don't see its response
+        let service = PolicyLayer::new(vec![
+            response_policy("first", false),
+            response_policy("second", true),
+        ])
+        .layer(upstream);
+        let response = service
+            .oneshot(Request::get("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        assert!(response.headers().get("x-order").is_none());
+    }
+
+    // Redirects every request to the login page with `status`
+    struct RedirectPolicy {
+        status: StatusCode,
+    }
+
+    #[async_trait::async_trait]
+    impl Policy for RedirectPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            "redirect"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, _request: Request<Body>) -> PolicyResult {
+            PolicyResult::Redirect("https://login.example.com/".parse().unwrap(), self.status)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_redirect() {
+        use tower::ServiceExt;
+
+        let upstream = tower::service_fn(|_: Request<Body>| async {
+            Ok::<_, std::convert::Infallible>(Response::new(Body::from("upstream")))
+        });
+        let redirect = |status| async move {
+            PolicyLayer::new(vec![Box::new(RedirectPolicy { status }) as Box<dyn Policy>])
+                .layer(upstream)
+                .oneshot(Request::post("/").body(Body::empty()).unwrap())
+                .await
+                .unwrap()
+        };
+
+        let response = redirect(StatusCode::TEMPORARY_REDIRECT).await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://login.example.com/"
+        );
+
+        // Statuses that aren't redirects are replaced
+        let response = redirect(StatusCode::OK).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://login.example.com/"
+        );
+    }
+
+    // Authenticates requests with an `x-login` header as its owner
+    struct LoginPolicy;
+
+    #[async_trait::async_trait]
+    impl Policy for LoginPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            "login"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+            let login = request.headers().get("x-login").cloned();
+            if let Some(login) = login {
+                let mut identity = Identity::new("user");
+                identity.owner = Some(login.to_str().unwrap().to_string());
+                identity.apply(&mut request);
+            }
+            PolicyResult::Continue(request)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_denials_recorded_for_identity() {
+        use tower::ServiceExt;
+
+        let upstream = tower::service_fn(|_: Request<Body>| async {
+            Ok::<_, std::convert::Infallible>(Response::new(Body::empty()))
+        });
+        let service = PolicyLayer::new(vec![Box::new(LoginPolicy), mock("/denied", 403).await])
+            .layer(upstream);
+        let deny = |request: axum::http::request::Builder| {
+            let service = service.clone();
+            async move {
+                let request = request.uri("/denied").body(Body::empty()).unwrap();
+                let response = service.oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::FORBIDDEN);
+            }
+        };
+
+        // A client can't put denials on someone else's record with the header
+        deny(Request::builder().header("x-bouncer-owner", "denials-victim")).await;
+        assert!(recent_denials().list("denials-victim").is_empty());
+
+        deny(Request::builder().header("x-login", "denials-user")).await;
+        assert_eq!(recent_denials().list("denials-user").len(), 1);
+    }
+}
@@ -1,9 +1,33 @@
+pub mod body;
+pub mod condition;
+pub mod context;
+pub mod deadline;
+pub mod denial;
+pub mod explain;
+pub mod failure;
+pub mod forwarded;
+pub mod headers;
+pub mod kill_switch;
+pub mod labels;
+pub mod lifecycle;
+pub mod logging;
 pub mod macros;
+pub mod matcher;
 pub mod middleware;
+pub mod pipeline;
+pub mod plugins;
+pub mod preauthorize;
 pub mod providers;
 pub mod registry;
+pub mod reload;
 pub mod routes;
+pub mod schedule;
+pub mod sdk;
+pub mod sessions;
+pub mod staging;
 pub mod traits;
+pub mod transform;
+pub mod websocket;
 
 pub use middleware::PolicyChainExt;
 pub use traits::Policy;
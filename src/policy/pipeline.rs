@@ -0,0 +1,312 @@
+use crate::config::PipelineStep;
+use crate::policy::body::{is_too_large, too_large_response};
+use crate::policy::traits::BufferedBody;
+use axum::body::{Body, Bytes};
+use axum::http::{header, Request, Response, StatusCode, Uri};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use futures::StreamExt;
+use std::io::Read;
+
+/// Where built-in steps run, relative to the fixed `bypass`, `chain` and `proxy` steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Before `bypass`, for every request
+    Ingress,
+    /// Between `bypass` and `chain`, for requests that aren't bypassed
+    Routed,
+    /// Between `chain` and `proxy`, for requests that passed the chain
+    Forward,
+}
+
+/// The ingress pipeline from `server.pipeline`
+///
+/// Without built-in steps requests go straight through bypass, chain and proxy.
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    // Normalizing before `bypass` has to happen before the route is matched,
+    // so it's applied on its own rather than with the other ingress steps
+    normalize_first: bool,
+    stages: [Vec<PipelineStep>; 3],
+    max_buffered_body_bytes: usize,
+}
+
+fn step_name(step: PipelineStep) -> String {
+    format!("{:?}", step).to_lowercase()
+}
+
+impl Pipeline {
+    pub fn new(steps: &[PipelineStep], max_buffered_body_bytes: usize) -> Result<Self, String> {
+        let mut pipeline = Self {
+            max_buffered_body_bytes,
+            ..Self::default()
+        };
+        if steps.is_empty() {
+            return Ok(pipeline);
+        }
+
+        // The fixed steps that have been seen, in order
+        let mut fixed = 0;
+        let mut seen = Vec::new();
+        for &step in steps {
+            let expected = match step {
+                PipelineStep::Bypass => Some(0),
+                PipelineStep::Chain => Some(1),
+                PipelineStep::Proxy => Some(2),
+                _ => None,
+            };
+            match expected {
+                Some(index) if index == fixed => fixed += 1,
+                Some(_) => return Err(
+                    "server.pipeline: bypass, chain and proxy must each appear once, in that order"
+                        .to_string(),
+                ),
+                None if fixed == 3 => {
+                    return Err(format!(
+                        "server.pipeline: '{}' must come before proxy",
+                        step_name(step)
+                    ))
+                }
+                None if seen.contains(&step) => {
+                    return Err(format!(
+                        "server.pipeline: '{}' can only appear once",
+                        step_name(step)
+                    ))
+                }
+                None if fixed == 0 && step == PipelineStep::Normalize => {
+                    seen.push(step);
+                    pipeline.normalize_first = true;
+                }
+                None => {
+                    seen.push(step);
+                    pipeline.stages[fixed].push(step);
+                }
+            }
+        }
+        if fixed != 3 {
+            return Err(
+                "server.pipeline: bypass, chain and proxy must each appear once, in that order"
+                    .to_string(),
+            );
+        }
+        Ok(pipeline)
+    }
+
+    /// Normalize the path if that's configured before `bypass`, so routes are
+    /// matched against the normalized path
+    pub fn normalize_first(&self, request: &mut Request<Body>) {
+        if self.normalize_first {
+            normalize(request);
+        }
+    }
+
+    /// Run the built-in steps of `stage` in order
+    ///
+    /// A step that can't process the request returns the response to send instead.
+    pub async fn run(
+        &self,
+        stage: Stage,
+        mut request: Request<Body>,
+    ) -> Result<Request<Body>, Response<Body>> {
+        for step in &self.stages[stage as usize] {
+            request = match step {
+                PipelineStep::Normalize => {
+                    normalize(&mut request);
+                    request
+                }
+                PipelineStep::Decompress => {
+                    decompress(request, self.max_buffered_body_bytes).await?
+                }
+                PipelineStep::Buffer => buffer(request, self.max_buffered_body_bytes).await?,
+                PipelineStep::Bypass | PipelineStep::Chain | PipelineStep::Proxy => request,
+            };
+        }
+        Ok(request)
+    }
+}
+
+// Percent-encoded dots are resolved too, so `%2e%2e` can't climb past a prefix
+fn is_dot(segment: &str) -> bool {
+    segment == "." || segment.eq_ignore_ascii_case("%2e")
+}
+
+fn is_dot_dot(segment: &str) -> bool {
+    segment
+        .strip_suffix('.')
+        .or_else(|| segment.strip_suffix("%2e"))
+        .or_else(|| segment.strip_suffix("%2E"))
+        .is_some_and(is_dot)
+}
+
+/// Collapse repeated slashes and resolve `.` and `..` segments
+///
+/// `..` never climbs above the root. A trailing slash is kept.
+pub fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing = false;
+    for segment in path.split('/') {
+        trailing = true;
+        if is_dot_dot(segment) {
+            segments.pop();
+        } else if !segment.is_empty() && !is_dot(segment) {
+            segments.push(segment);
+            trailing = false;
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if trailing && !segments.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+fn normalize(request: &mut Request<Body>) {
+    let path = normalize_path(request.uri().path());
+    if path == request.uri().path() {
+        return;
+    }
+    let path_and_query = match request.uri().query() {
+        Some(query) => format!("{}?{}", path, query),
+        None => path,
+    };
+    let mut parts = request.uri().clone().into_parts();
+    let Ok(path_and_query) = path_and_query.parse() else {
+        return;
+    };
+    parts.path_and_query = Some(path_and_query);
+    if let Ok(uri) = Uri::from_parts(parts) {
+        *request.uri_mut() = uri;
+    }
+}
+
+fn error_response(status: StatusCode, message: &'static str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::from(message))
+        .unwrap()
+}
+
+// Read the whole body, unless it's longer than `limit`
+async fn read_body(body: Body, limit: usize) -> Result<Bytes, Response<Body>> {
+    let mut stream = body.into_data_stream();
+    let mut chunks = Vec::new();
+    let mut read = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| match is_too_large(&e) {
+            true => too_large_response(),
+            false => {
+                tracing::warn!("Failed to read request body: {}", e);
+                error_response(StatusCode::BAD_REQUEST, "Failed to read request body")
+            }
+        })?;
+        read += chunk.len();
+        if read > limit {
+            return Err(too_large_response());
+        }
+        chunks.push(chunk);
+    }
+    Ok(match chunks.len() {
+        1 => chunks.pop().unwrap(),
+        _ => Bytes::from(chunks.concat()),
+    })
+}
+
+async fn buffer(request: Request<Body>, limit: usize) -> Result<Request<Body>, Response<Body>> {
+    let (mut parts, body) = request.into_parts();
+    let bytes = read_body(body, limit).await?;
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, bytes.len().into());
+    // Policies that inspect the body read it from here instead
+    parts
+        .extensions
+        .insert(BufferedBody::new(bytes.clone(), true));
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+async fn decompress(request: Request<Body>, limit: usize) -> Result<Request<Body>, Response<Body>> {
+    // Other encodings, and several stacked ones, are forwarded as they are
+    let gzip = match request
+        .headers()
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim().to_ascii_lowercase())
+        .as_deref()
+    {
+        Some("gzip" | "x-gzip") => true,
+        Some("deflate") => false,
+        _ => return Ok(request),
+    };
+
+    let (mut parts, body) = request.into_parts();
+    let compressed = read_body(body, limit).await?;
+
+    // Stop one byte past the limit, so bodies that expand too far are caught
+    // without decoding all of them
+    let mut decoded = Vec::new();
+    let result = if gzip {
+        GzDecoder::new(compressed.as_ref())
+            .take(limit as u64 + 1)
+            .read_to_end(&mut decoded)
+    } else {
+        ZlibDecoder::new(compressed.as_ref())
+            .take(limit as u64 + 1)
+            .read_to_end(&mut decoded)
+    };
+    if let Err(e) = result {
+        tracing::warn!("Failed to decompress request body: {}", e);
+        return Err(error_response(
+            StatusCode::BAD_REQUEST,
+            "Invalid compressed request body",
+        ));
+    }
+    if decoded.len() > limit {
+        return Err(too_large_response());
+    }
+
+    parts.headers.remove(header::CONTENT_ENCODING);
+    parts
+        .headers
+        .insert(header::CONTENT_LENGTH, decoded.len().into());
+    // A body buffered before this step is still compressed
+    parts.extensions.remove::<BufferedBody>();
+    Ok(Request::from_parts(parts, Body::from(decoded)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[tokio::test]
+    async fn test_pipeline() {
+        use PipelineStep::*;
+
+        assert!(Pipeline::new(&[Bypass, Proxy, Chain], 1024).is_err());
+        assert!(Pipeline::new(&[Bypass, Chain, Proxy, Buffer], 1024).is_err());
+        assert!(Pipeline::new(&[Normalize, Bypass, Normalize, Chain, Proxy], 1024).is_err());
+
+        assert_eq!(normalize_path("//api/./v1/../users/"), "/api/users/");
+        assert_eq!(normalize_path("/api/%2e%2E/../admin"), "/admin");
+        assert_eq!(normalize_path("/.."), "/");
+
+        let pipeline = Pipeline::new(&[Normalize, Bypass, Decompress, Chain, Proxy], 1024).unwrap();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"{\"role\":\"admin\"}").unwrap();
+        let mut request = Request::post("/users//42/?full=1")
+            .header(header::CONTENT_ENCODING, "gzip")
+            .body(Body::from(encoder.finish().unwrap()))
+            .unwrap();
+
+        pipeline.normalize_first(&mut request);
+        assert_eq!(request.uri(), "/users/42/?full=1");
+        let request = pipeline.run(Stage::Routed, request).await.unwrap();
+        assert!(request.headers().get(header::CONTENT_ENCODING).is_none());
+        let body = axum::body::to_bytes(request.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"{\"role\":\"admin\"}");
+    }
+}
@@ -0,0 +1,258 @@
+use crate::config::PluginsConfig;
+use crate::policy::sdk::SDK_ABI_VERSION;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Name of the manifest file every plugin directory must contain
+pub const MANIFEST_FILE: &str = "manifest.yaml";
+
+/// Manifest describing the plugins in a plugin directory
+///
+/// ```yaml
+/// plugins:
+///   - name: my-policy
+///     version: 1.0.0
+///     sdk_version: 1
+///     file: libmy_policy.so
+///     sha256: 9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08
+///     signature: <base64 Ed25519 signature of the library file>
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginManifest {
+    #[serde(default)]
+    pub plugins: Vec<PluginManifestEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginManifestEntry {
+    pub name: String,
+    pub version: String,
+    pub sdk_version: u32,
+    /// Library file name, relative to the plugin directory
+    pub file: String,
+    /// Hex-encoded SHA-256 checksum of the library file
+    pub sha256: String,
+    /// Base64-encoded Ed25519 signature of the library file
+    pub signature: Option<String>,
+}
+
+/// A plugin that passed manifest verification and is ready to be loaded
+pub struct VerifiedPlugin {
+    pub name: String,
+    pub version: String,
+    /// Private copy of the verified library bytes, so the file on disk can't be
+    /// swapped between verification and loading
+    pub library_path: PathBuf,
+    // Directory only Bouncer can access, holding the copy until the plugin is
+    // dropped
+    _private_dir: TempDir,
+}
+
+impl PluginManifest {
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, String> {
+        let path = dir.as_ref().join(MANIFEST_FILE);
+        let content = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read plugin manifest {}: {}", path.display(), e))?;
+
+        serde_yaml::from_str(&content)
+            .map_err(|e| format!("Failed to parse plugin manifest {}: {}", path.display(), e))
+    }
+}
+
+/// Verify a manifest entry against the plugin config and the library on disk
+///
+/// This checks the allow/deny lists, SDK version, checksum and (if required or
+/// present) signature before the library is ever passed to the dynamic loader.
+pub fn verify_plugin(
+    dir: &Path,
+    entry: &PluginManifestEntry,
+    config: &PluginsConfig,
+) -> Result<VerifiedPlugin, String> {
+    if config.deny.contains(&entry.name) {
+        return Err("plugin is denied by configuration".to_string());
+    }
+
+    if !config.allow.is_empty() && !config.allow.contains(&entry.name) {
+        return Err("plugin is not in the configured allow list".to_string());
+    }
+
+    if entry.sdk_version != SDK_ABI_VERSION {
+        return Err(format!(
+            "plugin targets SDK version {}, but this Bouncer uses version {}",
+            entry.sdk_version, SDK_ABI_VERSION
+        ));
+    }
+
+    // Don't allow manifests to point outside of the plugin directory
+    if Path::new(&entry.file).components().count() != 1 {
+        return Err(format!("invalid plugin file name '{}'", entry.file));
+    }
+
+    let library_file = dir.join(&entry.file);
+    let bytes = fs::read(&library_file)
+        .map_err(|e| format!("failed to read {}: {}", library_file.display(), e))?;
+
+    let checksum = hex_encode(&Sha256::digest(&bytes));
+    if !checksum.eq_ignore_ascii_case(entry.sha256.trim()) {
+        return Err(format!(
+            "checksum mismatch: manifest has {}, file has {}",
+            entry.sha256, checksum
+        ));
+    }
+
+    match &entry.signature {
+        Some(signature) => verify_signature(&bytes, signature, &config.trusted_keys)?,
+        None if config.require_signature => {
+            return Err("plugin is not signed, but signatures are required".to_string())
+        }
+        None => {}
+    }
+
+    // Load from a private copy in a fresh directory that only this process
+    // can write to, so nothing can be swapped in after the checks above
+    let extension = library_file
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("so");
+    let mut private_dir = tempfile::Builder::new();
+    private_dir.prefix("bouncer-plugin-");
+    #[cfg(unix)]
+    private_dir.permissions(std::os::unix::fs::PermissionsExt::from_mode(0o700));
+    let private_dir = private_dir
+        .tempdir()
+        .map_err(|e| format!("failed to create a private plugin directory: {}", e))?;
+    let library_path = private_dir
+        .path()
+        .join(format!("{}-{}.{}", entry.name, checksum, extension));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&library_path)
+        .and_then(|mut file| file.write_all(&bytes))
+        .map_err(|e| format!("failed to write {}: {}", library_path.display(), e))?;
+
+    Ok(VerifiedPlugin {
+        name: entry.name.clone(),
+        version: entry.version.clone(),
+        library_path,
+        _private_dir: private_dir,
+    })
+}
+
+// Check the signature against every trusted key
+fn verify_signature(bytes: &[u8], signature: &str, trusted_keys: &[String]) -> Result<(), String> {
+    if trusted_keys.is_empty() {
+        return Err("plugin is signed, but no trusted keys are configured".to_string());
+    }
+
+    let signature: [u8; 64] = STANDARD
+        .decode(signature.trim())
+        .map_err(|e| format!("invalid signature encoding: {}", e))?
+        .try_into()
+        .map_err(|_| "signature must be 64 bytes".to_string())?;
+    let signature = Signature::from_bytes(&signature);
+
+    for key in trusted_keys {
+        let key: [u8; 32] = match STANDARD.decode(key.trim()).map(|k| k.try_into()) {
+            Ok(Ok(key)) => key,
+            _ => {
+                tracing::warn!("Ignoring malformed trusted plugin key: {}", key);
+                continue;
+            }
+        };
+
+        if let Ok(key) = VerifyingKey::from_bytes(&key) {
+            if key.verify(bytes, &signature).is_ok() {
+                return Ok(());
+            }
+        }
+    }
+
+    Err("signature does not match any trusted key".to_string())
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::{Signer, SigningKey};
+
+    const LIBRARY: &[u8] = b"not really a shared library";
+
+    fn plugin_dir() -> TempDir {
+        let dir = TempDir::new().unwrap();
+        fs::write(dir.path().join("libtest.so"), LIBRARY).unwrap();
+        dir
+    }
+
+    fn entry(sha256: &str, signature: Option<String>) -> PluginManifestEntry {
+        PluginManifestEntry {
+            name: "test".to_string(),
+            version: "1.0.0".to_string(),
+            sdk_version: SDK_ABI_VERSION,
+            file: "libtest.so".to_string(),
+            sha256: sha256.to_string(),
+            signature,
+        }
+    }
+
+    #[test]
+    fn test_verify_checksum() {
+        let dir = plugin_dir();
+        let config = PluginsConfig::default();
+        let checksum = hex_encode(&Sha256::digest(LIBRARY));
+
+        let plugin = verify_plugin(dir.path(), &entry(&checksum, None), &config).unwrap();
+        assert!(!plugin.library_path.starts_with(dir.path()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let private_dir = plugin.library_path.parent().unwrap();
+            let mode = fs::metadata(private_dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
+        }
+
+        // Tampering with the library after verification doesn't reach the
+        // copy that's loaded, and a tampered library fails verification
+        fs::write(dir.path().join("libtest.so"), b"tampered").unwrap();
+        assert_eq!(fs::read(&plugin.library_path).unwrap(), LIBRARY);
+        let error = verify_plugin(dir.path(), &entry(&checksum, None), &config).err();
+        assert!(error.unwrap().starts_with("checksum mismatch"));
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let dir = plugin_dir();
+        let checksum = hex_encode(&Sha256::digest(LIBRARY));
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let config = PluginsConfig {
+            trusted_keys: vec![STANDARD.encode(key.verifying_key().to_bytes())],
+            require_signature: true,
+            ..PluginsConfig::default()
+        };
+        let sign = |key: &SigningKey| Some(STANDARD.encode(key.sign(LIBRARY).to_bytes()));
+
+        assert!(verify_plugin(dir.path(), &entry(&checksum, sign(&key)), &config).is_ok());
+
+        let untrusted = SigningKey::from_bytes(&[8; 32]);
+        let error = verify_plugin(dir.path(), &entry(&checksum, sign(&untrusted)), &config).err();
+        assert_eq!(
+            error.as_deref(),
+            Some("signature does not match any trusted key")
+        );
+        let error = verify_plugin(dir.path(), &entry(&checksum, None), &config).err();
+        assert_eq!(
+            error.as_deref(),
+            Some("plugin is not signed, but signatures are required")
+        );
+    }
+}
@@ -0,0 +1,493 @@
+use crate::admin::json_error;
+use crate::config::PreauthorizeConfig;
+use crate::policy::body::BodyLimits;
+use crate::policy::context::context_mut;
+use crate::policy::failure::resolve;
+use crate::policy::forwarded::{ClientIp, TrustedProxies};
+use crate::policy::headers::ProtectedHeaders;
+use crate::policy::kill_switch::KillSwitch;
+use crate::policy::middleware::PolicyChainHandle;
+use crate::policy::traits::Policy;
+use axum::{
+    body::Body,
+    extract::ConnectInfo,
+    http::{request::Parts, Method, Request, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+// Categories of the policies that decide whether a caller may do something.
+// Traffic policies such as rate limits are left out, so checking actions
+// doesn't use up the caller's quota
+pub(crate) const DECIDING_CATEGORIES: [&str; 3] = ["authentication", "authorization", "logic"];
+
+// Upper bound for `max_actions`, since each action can cost the policies a
+// database lookup
+const MAX_ACTIONS_LIMIT: usize = 200;
+
+// Actions decided at once. The rest wait, so one batch can't take every
+// database connection
+const CONCURRENT_ACTIONS: usize = 4;
+
+/// A request the caller might make
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Action {
+    pub method: String,
+    pub path: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PreauthorizeRequest {
+    actions: Vec<Action>,
+}
+
+/// Whether the policy chain would let an action through
+#[derive(Debug, Clone, Serialize)]
+pub struct ActionDecision {
+    pub method: String,
+    pub path: String,
+    pub allowed: bool,
+    /// 200 when allowed, otherwise the status of the rejecting policy's response
+    pub status: u16,
+}
+
+/// Checks which of a list of actions the chain would allow a caller, so UIs
+/// can hide what they can't do without duplicating the rules
+///
+/// Each action runs through the authentication, authorization and logic
+/// policies of the live chain, as a request with the caller's headers,
+/// client certificate and IP and the action's method and path.
+pub struct Preauthorizer {
+    chain: PolicyChainHandle,
+    protected_headers: Arc<ProtectedHeaders>,
+    trusted_proxies: Arc<TrustedProxies>,
+    kill_switch: Option<Arc<KillSwitch>>,
+    body_limits: Arc<BodyLimits>,
+    max_actions: usize,
+}
+
+impl Preauthorizer {
+    pub fn new(
+        chain: PolicyChainHandle,
+        protected_headers: Arc<ProtectedHeaders>,
+        trusted_proxies: Arc<TrustedProxies>,
+        config: &PreauthorizeConfig,
+    ) -> Self {
+        Self {
+            chain,
+            protected_headers,
+            trusted_proxies,
+            kill_switch: None,
+            body_limits: Arc::new(BodyLimits::default()),
+            max_actions: config.max_actions,
+        }
+    }
+
+    /// Reject checks in the traffic modes that reject other requests
+    pub fn with_kill_switch(mut self, kill_switch: Arc<KillSwitch>) -> Self {
+        self.kill_switch = Some(kill_switch);
+        self
+    }
+
+    /// Limit the size of check requests like that of other requests
+    pub fn with_body_limits(mut self, body_limits: Arc<BodyLimits>) -> Self {
+        self.body_limits = body_limits;
+        self
+    }
+
+    /// Decide every action for the caller that sent `parts`
+    pub async fn authorize(&self, parts: &Parts, actions: &[Action]) -> Vec<ActionDecision> {
+        let policies = self.chain.load();
+        let policies: Vec<&dyn Policy> = policies
+            .iter()
+            .map(|policy| policy.as_ref())
+            .filter(|policy| DECIDING_CATEGORIES.contains(&policy.category()))
+            .collect();
+        let mut decisions = Vec::with_capacity(actions.len());
+        for batch in actions.chunks(CONCURRENT_ACTIONS) {
+            let batch = batch
+                .iter()
+                .map(|action| self.decide(&policies, parts, action));
+            decisions.extend(futures::future::join_all(batch).await);
+        }
+        decisions
+    }
+
+    // Why the traffic mode rejects a check, if it does
+    fn rejects(&self, request: &Request<Body>) -> Option<&'static str> {
+        let kill_switch = self.kill_switch.as_ref()?;
+        let client_ip = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| self.trusted_proxies.resolve(addr.ip(), request.headers()));
+        kill_switch.rejects(request.method(), client_ip)
+    }
+
+    async fn decide(
+        &self,
+        policies: &[&dyn Policy],
+        parts: &Parts,
+        action: &Action,
+    ) -> ActionDecision {
+        let decision = |allowed, status: StatusCode| ActionDecision {
+            method: action.method.clone(),
+            path: action.path.clone(),
+            allowed,
+            status: status.as_u16(),
+        };
+        let Some(mut request) = self.request_for(parts, action) else {
+            return decision(false, StatusCode::BAD_REQUEST);
+        };
+
+        for policy in policies {
+            if !policy.applies_to(&request) {
+                continue;
+            }
+            match resolve(*policy, policy.process(request).await) {
+                Ok(next) => request = next,
+                Err(response) => return decision(false, response.status()),
+            }
+        }
+        decision(true, StatusCode::OK)
+    }
+
+    // The request the caller would send for `action`, as the middleware
+    // would hand it to the chain
+    fn request_for(&self, parts: &Parts, action: &Action) -> Option<Request<Body>> {
+        let method = Method::from_bytes(action.method.to_uppercase().as_bytes()).ok()?;
+        if !action.path.starts_with('/') {
+            return None;
+        }
+        let mut request = Request::builder()
+            .method(method)
+            .uri(&action.path)
+            .body(Body::empty())
+            .ok()?;
+        *request.headers_mut() = parts.headers.clone();
+        *request.extensions_mut() = parts.extensions.clone();
+
+        // Callers can't vouch for their own identity
+        self.protected_headers.strip(request.headers_mut());
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        if let Some(peer) = peer {
+            let ip = self.trusted_proxies.resolve(peer, request.headers());
+            request.extensions_mut().insert(ClientIp(ip));
+            context_mut(&mut request).set_client_ip(ip);
+        }
+        Some(request)
+    }
+}
+
+/// Check `server.preauthorize`
+pub fn validate(config: &PreauthorizeConfig) -> Result<(), String> {
+    if !(1..=MAX_ACTIONS_LIMIT).contains(&config.max_actions) {
+        return Err(format!(
+            "server.preauthorize.max_actions must be between 1 and {}",
+            MAX_ACTIONS_LIMIT
+        ));
+    }
+    Ok(())
+}
+
+/// Serve the pre-authorization endpoint at the configured path
+///
+/// Expects `POST` with `{"actions": [{"method": "DELETE", "path": "/orders/1"}]}`
+/// and answers with a decision for each action, in order.
+pub fn router(preauthorizer: Arc<Preauthorizer>, config: &PreauthorizeConfig) -> Router {
+    let handler = move |request: Request<Body>| async move {
+        if let Some(message) = preauthorizer.rejects(&request) {
+            return json_error(StatusCode::SERVICE_UNAVAILABLE, message);
+        }
+        let request = match preauthorizer.body_limits.apply(request) {
+            Ok(request) => request,
+            Err(response) => return response,
+        };
+
+        let (parts, body) = request.into_parts();
+        let body = match axum::body::to_bytes(body, 1024 * 1024).await {
+            Ok(body) => body,
+            Err(_) => return json_error(StatusCode::PAYLOAD_TOO_LARGE, "Request too large"),
+        };
+        let batch: PreauthorizeRequest = match serde_json::from_slice(&body) {
+            Ok(batch) => batch,
+            Err(e) => {
+                return json_error(StatusCode::BAD_REQUEST, format!("Invalid request: {}", e))
+            }
+        };
+        if batch.actions.len() > preauthorizer.max_actions {
+            return json_error(
+                StatusCode::BAD_REQUEST,
+                format!("At most {} actions per request", preauthorizer.max_actions),
+            );
+        }
+        let decisions = preauthorizer.authorize(&parts, &batch.actions).await;
+        Json(serde_json::json!({ "actions": decisions })).into_response()
+    };
+    Router::new().route(&config.path, post(handler))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ServerConfig;
+    use crate::policy::kill_switch::{MemoryTrafficModeStore, TrafficMode};
+    use crate::policy::providers::bouncer::authentication::identity::Identity;
+    use crate::policy::providers::bouncer::authorization::rbac::v1::{
+        RbacConfig, RbacPolicyFactory,
+    };
+    use crate::policy::providers::bouncer::development::mock::v1::{
+        MockConfig, MockPolicyFactory, MockRouteConfig,
+    };
+    use crate::policy::traits::{PolicyFactory, PolicyResult};
+    use async_trait::async_trait;
+    use axum::http::header;
+    use axum::response::Response;
+    use std::collections::HashMap;
+    use std::sync::atomic::Ordering;
+    use tower::ServiceExt;
+
+    // Authenticates `Authorization: Bearer <role>` as that role
+    struct RoleTokenPolicy;
+
+    #[async_trait]
+    impl Policy for RoleTokenPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            "role-token"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+            let role = request
+                .headers()
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::to_string);
+            match role {
+                Some(role) => {
+                    Identity::new(role).apply(&mut request);
+                    PolicyResult::Continue(request)
+                }
+                None => PolicyResult::Terminate(
+                    Response::builder()
+                        .status(StatusCode::UNAUTHORIZED)
+                        .body(Body::empty())
+                        .unwrap(),
+                ),
+            }
+        }
+    }
+
+    // Lets every request through, tracking how many it handles at once
+    #[derive(Default)]
+    struct ConcurrencyPolicy {
+        active: std::sync::atomic::AtomicUsize,
+        most: std::sync::atomic::AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Policy for Arc<ConcurrencyPolicy> {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authorization"
+        }
+
+        fn name(&self) -> &'static str {
+            "concurrency"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, request: Request<Body>) -> PolicyResult {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(active, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            PolicyResult::Continue(request)
+        }
+    }
+
+    fn action(method: &str, path: &str) -> Action {
+        Action {
+            method: method.to_string(),
+            path: path.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_preauthorize() {
+        let rbac = RbacPolicyFactory::new(RbacConfig {
+            route_roles: HashMap::from([
+                ("GET /orders/*".to_string(), vec!["user".to_string()]),
+                ("DELETE /orders/*".to_string(), vec!["admin".to_string()]),
+            ]),
+        })
+        .await
+        .unwrap();
+        // Traffic and development policies don't take part
+        let mock = MockPolicyFactory::new(MockConfig {
+            routes: vec![MockRouteConfig {
+                path: "/orders/*".to_string(),
+                methods: vec![],
+                status: 429,
+                headers: Default::default(),
+                body: serde_json::Value::Null,
+                latency_ms: 0,
+            }],
+        })
+        .await
+        .unwrap();
+        let chain = PolicyChainHandle::new(vec![
+            Box::new(mock),
+            Box::new(RoleTokenPolicy),
+            Box::new(rbac),
+        ]);
+        let config = PreauthorizeConfig::default();
+        let preauthorizer = Arc::new(Preauthorizer::new(
+            chain,
+            Arc::new(ProtectedHeaders::default()),
+            Arc::new(TrustedProxies::default()),
+            &config,
+        ));
+        let actions = [
+            action("GET", "/orders/1"),
+            action("delete", "/orders/1"),
+            action("GET", "orders"),
+        ];
+
+        let (parts, _) = Request::post("/_bouncer/authorize")
+            .header(header::AUTHORIZATION, "Bearer user")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let decisions = preauthorizer.authorize(&parts, &actions).await;
+        assert!(decisions[0].allowed);
+        assert!(!decisions[1].allowed);
+        assert_eq!(decisions[1].status, 403);
+        assert_eq!(decisions[2].status, 400);
+
+        // A role sent by the caller is stripped like on any other request
+        let response = router(preauthorizer, &config)
+            .oneshot(
+                Request::post("/_bouncer/authorize")
+                    .header("x-bouncer-role", "admin")
+                    .body(Body::from(
+                        r#"{"actions": [{"method": "GET", "path": "/orders/1"}]}"#,
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["actions"][0]["allowed"], false);
+        assert_eq!(body["actions"][0]["status"], 401);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency() {
+        let policy = Arc::new(ConcurrencyPolicy::default());
+        let chain = PolicyChainHandle::new(vec![Box::new(policy.clone())]);
+        let preauthorizer = Preauthorizer::new(
+            chain,
+            Arc::new(ProtectedHeaders::default()),
+            Arc::new(TrustedProxies::default()),
+            &PreauthorizeConfig::default(),
+        );
+
+        let actions: Vec<_> = (0..10)
+            .map(|i| action("GET", &format!("/orders/{}", i)))
+            .collect();
+        let (parts, _) = Request::post("/").body(()).unwrap().into_parts();
+        let decisions = preauthorizer.authorize(&parts, &actions).await;
+
+        // Decisions keep the order of the actions
+        assert_eq!(decisions.len(), 10);
+        assert_eq!(decisions[9].path, "/orders/9");
+        assert!(decisions.iter().all(|decision| decision.allowed));
+        assert_eq!(policy.most.load(Ordering::SeqCst), CONCURRENT_ACTIONS);
+    }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let kill_switch = KillSwitch::new(Arc::new(MemoryTrafficModeStore::default())).await;
+        let server = ServerConfig {
+            max_body_size: Some(100),
+            ..ServerConfig::default()
+        };
+        let config = PreauthorizeConfig {
+            max_actions: 2,
+            ..PreauthorizeConfig::default()
+        };
+        let preauthorizer = Preauthorizer::new(
+            PolicyChainHandle::new(vec![]),
+            Arc::new(ProtectedHeaders::default()),
+            Arc::new(TrustedProxies::default()),
+            &config,
+        )
+        .with_kill_switch(kill_switch.clone())
+        .with_body_limits(Arc::new(BodyLimits::new(&server).unwrap()));
+        let router = router(Arc::new(preauthorizer), &config);
+        let check = |body: String| {
+            let router = router.clone();
+            async move {
+                let request = Request::post("/_bouncer/authorize")
+                    .body(Body::from(body))
+                    .unwrap();
+                router.oneshot(request).await.unwrap().status()
+            }
+        };
+        let actions = |count: usize| {
+            let actions = vec![r#"{"method":"GET","path":"/"}"#; count].join(",");
+            format!(r#"{{"actions":[{}]}}"#, actions)
+        };
+
+        assert_eq!(check(actions(2)).await, StatusCode::OK);
+        assert_eq!(check(actions(3)).await, StatusCode::BAD_REQUEST);
+        assert_eq!(check(actions(4)).await, StatusCode::PAYLOAD_TOO_LARGE);
+
+        kill_switch
+            .set(TrafficMode::Blocked, "incident")
+            .await
+            .unwrap();
+        assert_eq!(check(actions(1)).await, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&PreauthorizeConfig::default()).is_ok());
+        for max_actions in [0, MAX_ACTIONS_LIMIT + 1] {
+            let config = PreauthorizeConfig {
+                max_actions,
+                ..PreauthorizeConfig::default()
+            };
+            assert!(validate(&config).is_err());
+        }
+    }
+}
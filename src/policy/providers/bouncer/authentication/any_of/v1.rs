@@ -0,0 +1,427 @@
+use crate::config::PolicyConfig;
+use crate::policy::body::{inspect_body, is_too_large, too_large_response};
+use crate::policy::denial::Denial;
+use crate::policy::failure::resolve;
+use crate::policy::providers::bouncer::logic::check_processes_requests;
+use crate::policy::traits::{buffered_body, Capability, Policy, PolicyHealth, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request, Response, StatusCode},
+};
+use serde::Deserialize;
+
+/// Authentication policies to try in order
+///
+/// Unlike other policies, the registry builds this one itself, since it
+/// creates the policies inside it.
+#[derive(Clone, Deserialize)]
+pub struct AnyOfConfig {
+    pub policies: Vec<PolicyConfig>,
+}
+
+impl AnyOfConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.policies.is_empty() {
+            return Err("any-of needs at least one policy".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Lets a request through as soon as one of its policies does, for routes
+/// that accept several kinds of credentials
+///
+/// Policies are tried in order, each on the request as it came in, and the
+/// first to let it through decides what later policies see. Policies that
+/// inspect bodies each get a copy of the buffered body, or an empty body when
+/// it didn't fit in the budget, in which case only its start is there through
+/// [`buffered_body`]. When every policy
+/// rejects the request, the first rejection other than a 401 is returned, such
+/// as a 503 from a failing database. Otherwise the first 401 is returned with
+/// the `WWW-Authenticate` challenges of all of them, so clients learn every
+/// scheme they can use.
+pub struct AnyOfPolicy {
+    policies: Vec<Box<dyn Policy>>,
+    category: &'static str,
+}
+
+impl AnyOfPolicy {
+    pub fn new(policies: Vec<Box<dyn Policy>>) -> Result<Self, String> {
+        check_processes_requests("any-of", &policies)?;
+        Ok(Self {
+            policies,
+            category: "authentication",
+        })
+    }
+
+    /// Report the policy under this category, e.g. `logic` for
+    /// `@bouncer/logic/any-of/v1`
+    pub fn with_category(mut self, category: &'static str) -> Self {
+        self.category = category;
+        self
+    }
+}
+
+// The first rejection other than a 401, or else the first 401 with every
+// policy's challenges
+fn combine_rejections(rejections: Vec<Response<Body>>) -> Response<Body> {
+    let mut challenges: Vec<HeaderValue> = Vec::new();
+    for response in &rejections {
+        for challenge in response.headers().get_all(header::WWW_AUTHENTICATE) {
+            if !challenges.contains(challenge) {
+                challenges.push(challenge.clone());
+            }
+        }
+    }
+
+    let mut unauthorized = None;
+    for response in rejections {
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return response;
+        }
+        unauthorized.get_or_insert(response);
+    }
+
+    let mut response = unauthorized.unwrap_or_else(|| {
+        Denial::new("unauthorized", "Unauthorized").response(StatusCode::UNAUTHORIZED)
+    });
+    response.headers_mut().remove(header::WWW_AUTHENTICATE);
+    for challenge in challenges {
+        response
+            .headers_mut()
+            .append(header::WWW_AUTHENTICATE, challenge);
+    }
+    response
+}
+
+#[async_trait]
+impl Policy for AnyOfPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        self.category
+    }
+
+    fn name(&self) -> &'static str {
+        "any-of"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        // The middleware buffers bodies for the policies inside, but not when
+        // this policy is used on its own
+        let request = match self.inspects_body() {
+            Some(limit) => match inspect_body(request, limit).await {
+                Ok(request) => request,
+                Err(e) if is_too_large(&e) => return PolicyResult::Terminate(too_large_response()),
+                Err(e) => {
+                    tracing::warn!("Failed to read request body: {}", e);
+                    return PolicyResult::Terminate(
+                        Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .body(Body::from("Failed to read request body"))
+                            .unwrap(),
+                    );
+                }
+            },
+            None => request,
+        };
+
+        // Each policy gets its own copy of the request, and the body is put
+        // back on the copy that's let through
+        let buffered = buffered_body(&request).and_then(|body| body.complete_bytes().cloned());
+        let (parts, body) = request.into_parts();
+        let mut rejections = Vec::new();
+        for policy in &self.policies {
+            let copy = match &buffered {
+                Some(bytes) => Body::from(bytes.clone()),
+                None => Body::empty(),
+            };
+            let result = policy
+                .process(Request::from_parts(parts.clone(), copy))
+                .await;
+            match resolve(policy.as_ref(), result) {
+                Ok(request) => {
+                    let (parts, _) = request.into_parts();
+                    return PolicyResult::Continue(Request::from_parts(parts, body));
+                }
+                Err(response) => rejections.push(response),
+            }
+        }
+        PolicyResult::Terminate(combine_rejections(rejections))
+    }
+
+    fn read_only(&self) -> bool {
+        self.policies.iter().all(|policy| policy.read_only())
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        self.policies
+            .iter()
+            .filter_map(|policy| policy.inspects_body())
+            .max()
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        let mut required = Vec::new();
+        for capability in self.policies.iter().flat_map(|policy| policy.requires()) {
+            if !required.contains(&capability) {
+                required.push(capability);
+            }
+        }
+        required
+    }
+
+    // Only what every policy provides is there whichever one lets a request through
+    fn provides(&self) -> Vec<Capability> {
+        let Some((first, rest)) = self.policies.split_first() else {
+            return vec![];
+        };
+        first
+            .provides()
+            .into_iter()
+            .filter(|capability| {
+                rest.iter()
+                    .all(|policy| policy.provides().contains(capability))
+            })
+            .collect()
+    }
+
+    // Requests can still be authenticated while any policy is healthy
+    async fn health(&self) -> PolicyHealth {
+        let mut problems = Vec::new();
+        let mut healthy = false;
+        for policy in &self.policies {
+            match policy.health().await {
+                PolicyHealth::Healthy => healthy = true,
+                PolicyHealth::Degraded(reason) => {
+                    healthy = true;
+                    problems.push(reason);
+                }
+                PolicyHealth::Unhealthy(reason) => problems.push(reason),
+            }
+        }
+        match (problems.is_empty(), healthy) {
+            (true, _) => PolicyHealth::Healthy,
+            (false, true) => PolicyHealth::Degraded(problems.join("; ")),
+            (false, false) => PolicyHealth::Unhealthy(problems.join("; ")),
+        }
+    }
+
+    async fn warm_up(&self) -> Result<(), String> {
+        let errors: Vec<String> =
+            futures::future::join_all(self.policies.iter().map(|policy| policy.warm_up()))
+                .await
+                .into_iter()
+                .filter_map(Result::err)
+                .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    async fn on_start(&self) -> Result<(), String> {
+        let errors: Vec<String> =
+            futures::future::join_all(self.policies.iter().map(|policy| policy.on_start()))
+                .await
+                .into_iter()
+                .filter_map(Result::err)
+                .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        futures::future::join_all(self.policies.iter().map(|policy| policy.on_shutdown())).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Lets through requests with its header, and rejects others with its status
+    struct HeaderPolicy {
+        header: &'static str,
+        status: StatusCode,
+        challenge: Option<&'static str>,
+    }
+
+    #[async_trait]
+    impl Policy for HeaderPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            self.header
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+            if request.headers().contains_key(self.header) {
+                request
+                    .headers_mut()
+                    .insert("x-bouncer-role", HeaderValue::from_static(self.header));
+                return PolicyResult::Continue(request);
+            }
+            let mut response = Response::builder().status(self.status);
+            if let Some(challenge) = self.challenge {
+                response = response.header(header::WWW_AUTHENTICATE, challenge);
+            }
+            PolicyResult::Terminate(response.body(Body::from(self.header)).unwrap())
+        }
+
+        fn provides(&self) -> Vec<Capability> {
+            vec![Capability::Identity]
+        }
+    }
+
+    fn policy(
+        header: &'static str,
+        status: StatusCode,
+        challenge: Option<&'static str>,
+    ) -> Box<dyn Policy> {
+        Box::new(HeaderPolicy {
+            header,
+            status,
+            challenge,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_any_of() {
+        let any_of = AnyOfPolicy::new(vec![
+            policy(
+                "authorization",
+                StatusCode::UNAUTHORIZED,
+                Some("Bearer realm=\"api\""),
+            ),
+            policy("x-api-key", StatusCode::UNAUTHORIZED, Some("ApiKey")),
+        ])
+        .unwrap();
+        assert_eq!(any_of.provides(), vec![Capability::Identity]);
+
+        let request = Request::post("/")
+            .header("x-api-key", "key")
+            .body(Body::from("payload"))
+            .unwrap();
+        match any_of.process(request).await {
+            PolicyResult::Continue(request) => {
+                assert_eq!(request.headers()["x-bouncer-role"], "x-api-key");
+                let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                assert_eq!(body.as_ref(), b"payload");
+            }
+            _ => panic!("API key was rejected"),
+        }
+
+        let PolicyResult::Terminate(response) = any_of
+            .process(Request::get("/").body(Body::empty()).unwrap())
+            .await
+        else {
+            panic!("request without credentials was let through");
+        };
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let challenges: Vec<_> = response
+            .headers()
+            .get_all(header::WWW_AUTHENTICATE)
+            .iter()
+            .collect();
+        assert_eq!(challenges, ["Bearer realm=\"api\"", "ApiKey"]);
+
+        // Rejections other than a 401 are more telling
+        let any_of = AnyOfPolicy::new(vec![
+            policy("authorization", StatusCode::UNAUTHORIZED, Some("Bearer")),
+            policy("cookie", StatusCode::SERVICE_UNAVAILABLE, None),
+        ])
+        .unwrap();
+        let PolicyResult::Terminate(response) = any_of
+            .process(Request::get("/").body(Body::empty()).unwrap())
+            .await
+        else {
+            panic!("request without credentials was let through");
+        };
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    // Lets through requests whose body is its token
+    struct BodyPolicy {
+        token: &'static str,
+    }
+
+    #[async_trait]
+    impl Policy for BodyPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "authentication"
+        }
+
+        fn name(&self) -> &'static str {
+            self.token
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn process(&self, request: Request<Body>) -> PolicyResult {
+            let (parts, body) = request.into_parts();
+            let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+            if body == self.token {
+                return PolicyResult::Continue(Request::from_parts(parts, Body::empty()));
+            }
+            PolicyResult::Terminate(
+                Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        }
+
+        fn inspects_body(&self) -> Option<usize> {
+            Some(64)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_inspecting_policies() {
+        let any_of = AnyOfPolicy::new(vec![
+            Box::new(BodyPolicy { token: "first" }),
+            Box::new(BodyPolicy { token: "second" }),
+        ])
+        .unwrap();
+
+        // Each policy reads the whole body, and the next still gets it
+        let request = Request::post("/").body(Body::from("second")).unwrap();
+        let PolicyResult::Continue(request) = any_of.process(request).await else {
+            panic!("the second policy didn't see the body");
+        };
+        let body = axum::body::to_bytes(request.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"second");
+    }
+}
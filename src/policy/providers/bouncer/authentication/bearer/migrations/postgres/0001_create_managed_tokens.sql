@@ -0,0 +1,14 @@
+-- Tokens issued by the @bouncer/authentication/bearer/v1-managed policy.
+-- Only salted SHA-256 hashes of tokens are stored.
+CREATE TABLE IF NOT EXISTS bouncer_managed_tokens (
+    id TEXT PRIMARY KEY,
+    token_hash TEXT NOT NULL UNIQUE,
+    role TEXT NOT NULL,
+    owner TEXT,
+    -- Unix timestamps in seconds
+    created_at BIGINT NOT NULL,
+    expires_at BIGINT
+);
+
+CREATE INDEX IF NOT EXISTS bouncer_managed_tokens_owner_idx
+    ON bouncer_managed_tokens (owner);
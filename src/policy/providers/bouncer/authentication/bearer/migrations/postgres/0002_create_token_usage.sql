@@ -0,0 +1,13 @@
+-- Usage of managed tokens, written in batches by the bearer policy.
+CREATE TABLE IF NOT EXISTS bouncer_managed_token_usage (
+    token_id TEXT PRIMARY KEY REFERENCES bouncer_managed_tokens (id) ON DELETE CASCADE,
+    requests BIGINT NOT NULL DEFAULT 0,
+    last_used_at BIGINT
+);
+
+CREATE TABLE IF NOT EXISTS bouncer_managed_token_route_usage (
+    token_id TEXT NOT NULL REFERENCES bouncer_managed_tokens (id) ON DELETE CASCADE,
+    route TEXT NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (token_id, route)
+);
@@ -0,0 +1,2 @@
+-- ID of the token issued to replace a rotated token.
+ALTER TABLE bouncer_managed_tokens ADD COLUMN IF NOT EXISTS replaced_by TEXT;
@@ -1,9 +1,13 @@
+pub mod store;
+pub mod usage;
 pub mod v1;
+pub mod v1_managed;
 
 // Returns policy ID with version
 pub fn policy_id_with_version(version: &str) -> &'static str {
     match version {
         "v1" => "@bouncer/authentication/bearer/v1",
+        "v1-managed" => "@bouncer/authentication/bearer/v1-managed",
         _ => panic!("Unsupported version: {}", version),
     }
 }
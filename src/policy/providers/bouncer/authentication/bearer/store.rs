@@ -0,0 +1,699 @@
+use crate::config::DatabasesConfig;
+use crate::database::migrations::Migration;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Schema for the PostgreSQL token store
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_managed_tokens",
+        sql: include_str!("migrations/postgres/0001_create_managed_tokens.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_token_usage",
+        sql: include_str!("migrations/postgres/0002_create_token_usage.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "add_token_rotation",
+        sql: include_str!("migrations/postgres/0003_add_token_rotation.sql"),
+    },
+];
+
+/// Metadata for a token issued by the managed bearer policy
+///
+/// The token itself is only returned when it is created; stores only keep a
+/// salted hash of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManagedToken {
+    pub id: String,
+    pub role: String,
+    pub owner: Option<String>,
+    /// Unix timestamp in seconds
+    pub created_at: i64,
+    /// Unix timestamp in seconds after which the token is no longer valid
+    pub expires_at: Option<i64>,
+    /// ID of the token that replaces this one, if it has been rotated
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_by: Option<String>,
+}
+
+/// Usage of one token since the last flush
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageDelta {
+    pub requests: u64,
+    /// Unix timestamp in seconds
+    pub last_used_at: i64,
+    /// Request counts keyed by `METHOD /path`
+    pub routes: HashMap<String, u64>,
+}
+
+/// Total usage of a token
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    pub id: String,
+    pub owner: Option<String>,
+    pub requests: u64,
+    /// Unix timestamp in seconds, or None if the token was never used
+    pub last_used_at: Option<i64>,
+    pub routes: BTreeMap<String, u64>,
+}
+
+/// Storage for managed tokens, keyed by token hash
+#[async_trait]
+pub trait ManagedTokenStore: Send + Sync + 'static {
+    async fn insert(&self, token_hash: &str, token: &ManagedToken) -> Result<(), DatabaseError>;
+
+    async fn find(&self, token_hash: &str) -> Result<Option<ManagedToken>, DatabaseError>;
+
+    async fn get(&self, id: &str) -> Result<Option<ManagedToken>, DatabaseError>;
+
+    async fn list(&self) -> Result<Vec<ManagedToken>, DatabaseError>;
+
+    /// Record that a token was replaced and cut its expiry short. Returns false
+    /// if there is no such token or it has already been rotated
+    async fn mark_rotated(
+        &self,
+        id: &str,
+        replaced_by: &str,
+        expires_at: i64,
+    ) -> Result<bool, DatabaseError>;
+
+    /// Revoke a token by ID. Returns false if there is no such token
+    async fn revoke(&self, id: &str) -> Result<bool, DatabaseError>;
+
+    /// Add a batch of usage, keyed by token ID. Usage of unknown tokens is ignored
+    async fn record_usage(&self, usage: &HashMap<String, UsageDelta>) -> Result<(), DatabaseError>;
+
+    /// Usage of every token, including tokens that were never used
+    async fn usage(&self) -> Result<Vec<TokenUsage>, DatabaseError>;
+}
+
+/// Backend used to store managed tokens
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ManagedStoreBackend {
+    #[default]
+    Redis,
+    Postgres,
+}
+
+/// Hash a token for storage and lookup
+pub fn hash_token(salt: &str, token: &str) -> String {
+    let digest = Sha256::new()
+        .chain_update(salt.as_bytes())
+        .chain_update(token.as_bytes())
+        .finalize();
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Generate a new token and its ID
+pub fn generate_token() -> (String, String) {
+    let mut id = [0u8; 8];
+    let mut secret = [0u8; 32];
+    rand::rngs::OsRng.fill_bytes(&mut id);
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+
+    let hex = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    };
+    let id = hex(&id);
+    let token = format!("bnc_{}_{}", id, hex(&secret));
+    (id, token)
+}
+
+/// Tokens stored in Redis as JSON, expiring with the token
+///
+/// Keys are `{prefix}token:{hash}` for the token, `{prefix}id:{id}` mapping IDs
+/// to hashes, `{prefix}ids` holding every ID and `{prefix}usage:{id}` holding a
+/// hash of usage counters.
+#[cfg(feature = "redis")]
+pub struct RedisTokenStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisTokenStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    fn token_key(&self, token_hash: &str) -> String {
+        format!("{}token:{}", self.key_prefix, token_hash)
+    }
+
+    fn id_key(&self, id: &str) -> String {
+        format!("{}id:{}", self.key_prefix, id)
+    }
+
+    fn ids_key(&self) -> String {
+        format!("{}ids", self.key_prefix)
+    }
+
+    fn usage_key(&self, id: &str) -> String {
+        format!("{}usage:{}", self.key_prefix, id)
+    }
+
+    async fn token_hash(&self, id: &str) -> Result<Option<String>, DatabaseError> {
+        redis::cmd("GET")
+            .arg(self.id_key(id))
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl ManagedTokenStore for RedisTokenStore {
+    async fn insert(&self, token_hash: &str, token: &ManagedToken) -> Result<(), DatabaseError> {
+        let value = serde_json::to_string(token)
+            .map_err(|e| DatabaseError::ConversionError(e.to_string()))?;
+
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        match token.expires_at {
+            Some(expires_at) => {
+                pipe.cmd("SET")
+                    .arg(self.token_key(token_hash))
+                    .arg(&value)
+                    .arg("EXAT")
+                    .arg(expires_at)
+                    .ignore();
+                pipe.cmd("SET")
+                    .arg(self.id_key(&token.id))
+                    .arg(token_hash)
+                    .arg("EXAT")
+                    .arg(expires_at)
+                    .ignore();
+            }
+            None => {
+                pipe.cmd("SET")
+                    .arg(self.token_key(token_hash))
+                    .arg(&value)
+                    .ignore();
+                pipe.cmd("SET")
+                    .arg(self.id_key(&token.id))
+                    .arg(token_hash)
+                    .ignore();
+            }
+        }
+        pipe.cmd("SADD").arg(self.ids_key()).arg(&token.id).ignore();
+
+        pipe.query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+        let value: Option<String> = redis::cmd("GET")
+            .arg(self.token_key(token_hash))
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        value
+            .map(|value| serde_json::from_str(&value))
+            .transpose()
+            .map_err(|e| DatabaseError::ConversionError(e.to_string()))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+        match self.token_hash(id).await? {
+            Some(token_hash) => self.find(&token_hash).await,
+            None => Ok(None),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<ManagedToken>, DatabaseError> {
+        let mut connection = self.connection.clone();
+        let ids: Vec<String> = redis::cmd("SMEMBERS")
+            .arg(self.ids_key())
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        let mut tokens = Vec::new();
+        for id in ids {
+            match self.get(&id).await? {
+                Some(token) => tokens.push(token),
+                // Expired tokens are dropped from the index as they are found
+                None => redis::pipe()
+                    .cmd("SREM")
+                    .arg(self.ids_key())
+                    .arg(&id)
+                    .ignore()
+                    .cmd("DEL")
+                    .arg(self.usage_key(&id))
+                    .ignore()
+                    .query_async::<_, ()>(&mut connection)
+                    .await
+                    .map_err(|e| DatabaseError::QueryError(e.to_string()))?,
+            }
+        }
+
+        tokens.sort_by_key(|token| token.created_at);
+        Ok(tokens)
+    }
+
+    async fn mark_rotated(
+        &self,
+        id: &str,
+        replaced_by: &str,
+        expires_at: i64,
+    ) -> Result<bool, DatabaseError> {
+        let Some(token_hash) = self.token_hash(id).await? else {
+            return Ok(false);
+        };
+        let token = match self.find(&token_hash).await? {
+            Some(token) if token.replaced_by.is_none() => token,
+            _ => return Ok(false),
+        };
+
+        // Rewriting the token also moves its keys' expiry to the new one
+        self.insert(
+            &token_hash,
+            &ManagedToken {
+                replaced_by: Some(replaced_by.to_string()),
+                expires_at: Some(expires_at),
+                ..token
+            },
+        )
+        .await?;
+        Ok(true)
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, DatabaseError> {
+        let mut connection = self.connection.clone();
+        let Some(token_hash) = self.token_hash(id).await? else {
+            return Ok(false);
+        };
+
+        redis::pipe()
+            .atomic()
+            .cmd("DEL")
+            .arg(self.token_key(&token_hash))
+            .arg(self.id_key(id))
+            .arg(self.usage_key(id))
+            .ignore()
+            .cmd("SREM")
+            .arg(self.ids_key())
+            .arg(id)
+            .ignore()
+            .query_async::<_, ()>(&mut connection)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(true)
+    }
+
+    async fn record_usage(&self, usage: &HashMap<String, UsageDelta>) -> Result<(), DatabaseError> {
+        let mut connection = self.connection.clone();
+        for (id, delta) in usage {
+            // Skip tokens revoked or expired since they were used
+            let exists: bool = redis::cmd("EXISTS")
+                .arg(self.id_key(id))
+                .query_async(&mut connection)
+                .await
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+            if !exists {
+                continue;
+            }
+
+            let key = self.usage_key(id);
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            pipe.cmd("HINCRBY")
+                .arg(&key)
+                .arg("requests")
+                .arg(delta.requests)
+                .ignore();
+            pipe.cmd("HSET")
+                .arg(&key)
+                .arg("last_used_at")
+                .arg(delta.last_used_at)
+                .ignore();
+            for (route, requests) in &delta.routes {
+                pipe.cmd("HINCRBY")
+                    .arg(&key)
+                    .arg(format!("route:{}", route))
+                    .arg(*requests)
+                    .ignore();
+            }
+
+            pipe.query_async::<_, ()>(&mut connection)
+                .await
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+        }
+
+        Ok(())
+    }
+
+    async fn usage(&self) -> Result<Vec<TokenUsage>, DatabaseError> {
+        let mut connection = self.connection.clone();
+        let mut usage = Vec::new();
+        for token in self.list().await? {
+            let fields: HashMap<String, i64> = redis::cmd("HGETALL")
+                .arg(self.usage_key(&token.id))
+                .query_async(&mut connection)
+                .await
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+            let mut token_usage = TokenUsage {
+                id: token.id,
+                owner: token.owner,
+                ..Default::default()
+            };
+            for (field, value) in fields {
+                match field.as_str() {
+                    "requests" => token_usage.requests = value as u64,
+                    "last_used_at" => token_usage.last_used_at = Some(value),
+                    _ => {
+                        if let Some(route) = field.strip_prefix("route:") {
+                            token_usage.routes.insert(route.to_string(), value as u64);
+                        }
+                    }
+                }
+            }
+            usage.push(token_usage);
+        }
+
+        Ok(usage)
+    }
+}
+
+/// Tokens stored in the `bouncer_managed_tokens` PostgreSQL table
+///
+/// Lookups are served from read replicas when configured.
+#[cfg(feature = "postgres")]
+pub struct PostgresTokenStore {
+    pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresTokenStore {
+    pub fn new(pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>) -> Self {
+        Self { pools }
+    }
+}
+
+#[cfg(feature = "postgres")]
+type TokenRow = (
+    String,
+    String,
+    Option<String>,
+    i64,
+    Option<i64>,
+    Option<String>,
+);
+
+#[cfg(feature = "postgres")]
+fn token_from_row(
+    (id, role, owner, created_at, expires_at, replaced_by): TokenRow,
+) -> ManagedToken {
+    ManagedToken {
+        id,
+        role,
+        owner,
+        created_at,
+        expires_at,
+        replaced_by,
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl ManagedTokenStore for PostgresTokenStore {
+    async fn insert(&self, token_hash: &str, token: &ManagedToken) -> Result<(), DatabaseError> {
+        sqlx::query(
+            "INSERT INTO bouncer_managed_tokens (id, token_hash, role, owner, created_at, expires_at) \
+             VALUES ($1, $2, $3, $4, $5, $6)",
+        )
+        .bind(&token.id)
+        .bind(token_hash)
+        .bind(&token.role)
+        .bind(&token.owner)
+        .bind(token.created_at)
+        .bind(token.expires_at)
+        .execute(&*self.pools.primary())
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn find(&self, token_hash: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+        let row = self
+            .pools
+            .read(|pool| async move {
+                sqlx::query_as::<_, TokenRow>(
+                    "SELECT id, role, owner, created_at, expires_at, replaced_by \
+                     FROM bouncer_managed_tokens WHERE token_hash = $1",
+                )
+                .bind(token_hash)
+                .fetch_optional(&*pool)
+                .await
+            })
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(row.map(token_from_row))
+    }
+
+    async fn get(&self, id: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+        let row = sqlx::query_as::<_, TokenRow>(
+            "SELECT id, role, owner, created_at, expires_at, replaced_by \
+             FROM bouncer_managed_tokens WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&*self.pools.primary())
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(row.map(token_from_row))
+    }
+
+    async fn list(&self) -> Result<Vec<ManagedToken>, DatabaseError> {
+        let rows = sqlx::query_as::<_, TokenRow>(
+            "SELECT id, role, owner, created_at, expires_at, replaced_by \
+             FROM bouncer_managed_tokens ORDER BY created_at",
+        )
+        .fetch_all(&*self.pools.primary())
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(rows.into_iter().map(token_from_row).collect())
+    }
+
+    async fn mark_rotated(
+        &self,
+        id: &str,
+        replaced_by: &str,
+        expires_at: i64,
+    ) -> Result<bool, DatabaseError> {
+        let result = sqlx::query(
+            "UPDATE bouncer_managed_tokens SET replaced_by = $2, expires_at = $3 \
+             WHERE id = $1 AND replaced_by IS NULL",
+        )
+        .bind(id)
+        .bind(replaced_by)
+        .bind(expires_at)
+        .execute(&*self.pools.primary())
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn revoke(&self, id: &str) -> Result<bool, DatabaseError> {
+        let result = sqlx::query("DELETE FROM bouncer_managed_tokens WHERE id = $1")
+            .bind(id)
+            .execute(&*self.pools.primary())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn record_usage(&self, usage: &HashMap<String, UsageDelta>) -> Result<(), DatabaseError> {
+        let query_error = |e: sqlx::Error| DatabaseError::QueryError(e.to_string());
+        let mut tx = self.pools.primary().begin().await.map_err(query_error)?;
+
+        // The EXISTS checks skip tokens revoked since they were used
+        for (id, delta) in usage {
+            sqlx::query(
+                "INSERT INTO bouncer_managed_token_usage (token_id, requests, last_used_at) \
+                 SELECT $1, $2, $3 WHERE EXISTS (SELECT 1 FROM bouncer_managed_tokens WHERE id = $1) \
+                 ON CONFLICT (token_id) DO UPDATE SET \
+                 requests = bouncer_managed_token_usage.requests + EXCLUDED.requests, \
+                 last_used_at = GREATEST(bouncer_managed_token_usage.last_used_at, EXCLUDED.last_used_at)",
+            )
+            .bind(id)
+            .bind(delta.requests as i64)
+            .bind(delta.last_used_at)
+            .execute(&mut *tx)
+            .await
+            .map_err(query_error)?;
+
+            for (route, requests) in &delta.routes {
+                sqlx::query(
+                    "INSERT INTO bouncer_managed_token_route_usage (token_id, route, requests) \
+                     SELECT $1, $2, $3 WHERE EXISTS (SELECT 1 FROM bouncer_managed_tokens WHERE id = $1) \
+                     ON CONFLICT (token_id, route) DO UPDATE SET \
+                     requests = bouncer_managed_token_route_usage.requests + EXCLUDED.requests",
+                )
+                .bind(id)
+                .bind(route)
+                .bind(*requests as i64)
+                .execute(&mut *tx)
+                .await
+                .map_err(query_error)?;
+            }
+        }
+
+        tx.commit().await.map_err(query_error)
+    }
+
+    async fn usage(&self) -> Result<Vec<TokenUsage>, DatabaseError> {
+        let query_error = |e: sqlx::Error| DatabaseError::QueryError(e.to_string());
+        let pool = self.pools.primary();
+
+        let tokens = sqlx::query_as::<_, (String, Option<String>, i64, Option<i64>)>(
+            "SELECT t.id, t.owner, COALESCE(u.requests, 0), u.last_used_at \
+             FROM bouncer_managed_tokens t \
+             LEFT JOIN bouncer_managed_token_usage u ON u.token_id = t.id \
+             ORDER BY t.created_at",
+        )
+        .fetch_all(&*pool)
+        .await
+        .map_err(query_error)?;
+
+        let mut routes: HashMap<String, BTreeMap<String, u64>> = HashMap::new();
+        let route_rows = sqlx::query_as::<_, (String, String, i64)>(
+            "SELECT token_id, route, requests FROM bouncer_managed_token_route_usage",
+        )
+        .fetch_all(&*pool)
+        .await
+        .map_err(query_error)?;
+        for (id, route, requests) in route_rows {
+            routes.entry(id).or_default().insert(route, requests as u64);
+        }
+
+        Ok(tokens
+            .into_iter()
+            .map(|(id, owner, requests, last_used_at)| TokenUsage {
+                routes: routes.remove(&id).unwrap_or_default(),
+                id,
+                owner,
+                requests: requests as u64,
+                last_used_at,
+            })
+            .collect())
+    }
+}
+
+/// Create the token store selected in the policy config
+pub async fn create_token_store(
+    backend: ManagedStoreBackend,
+    key_prefix: &str,
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn ManagedTokenStore>, DatabaseError> {
+    let provider = match backend {
+        ManagedStoreBackend::Redis => "redis",
+        ManagedStoreBackend::Postgres => "postgres",
+    };
+    crate::database::validate_database_config(databases, provider)?;
+
+    match backend {
+        ManagedStoreBackend::Redis => create_redis_store(key_prefix, databases).await,
+        ManagedStoreBackend::Postgres => create_postgres_store(run_migrations, databases).await,
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    key_prefix: &str,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn ManagedTokenStore>, DatabaseError> {
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisTokenStore::new(&client, key_prefix.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _key_prefix: &str,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn ManagedTokenStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_store(
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn ManagedTokenStore>, DatabaseError> {
+    let postgres_config = databases.postgres.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("PostgreSQL configuration is required".to_string())
+    })?;
+    let pools = crate::database::get_postgres_pools(postgres_config).await?;
+
+    if run_migrations {
+        crate::database::migrations::run_postgres_migrations(&pools.primary(), POSTGRES_MIGRATIONS)
+            .await?;
+    }
+
+    Ok(Arc::new(PostgresTokenStore::new(pools)))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_store(
+    _run_migrations: bool,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn ManagedTokenStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_hashing() {
+        let (id, token) = generate_token();
+        assert!(token.starts_with(&format!("bnc_{}_", id)));
+        assert_eq!(token.len(), "bnc_".len() + 16 + 1 + 64);
+
+        // Hashes depend on the salt
+        assert_eq!(hash_token("a", &token), hash_token("a", &token));
+        assert_ne!(hash_token("a", &token), hash_token("b", &token));
+    }
+}
@@ -0,0 +1,127 @@
+use super::store::{ManagedTokenStore, UsageDelta};
+use crate::admin::now_secs;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Collects token usage in memory and writes it to the store in batches
+///
+/// Usage is best-effort: a batch that fails to write is logged and dropped
+/// rather than slowing down or failing requests.
+pub struct UsageRecorder {
+    store: Arc<dyn ManagedTokenStore>,
+    pending: Mutex<HashMap<String, UsageDelta>>,
+}
+
+impl UsageRecorder {
+    pub fn new(store: Arc<dyn ManagedTokenStore>) -> Arc<Self> {
+        Arc::new(Self {
+            store,
+            pending: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Count a request made with a token
+    pub fn record(&self, id: &str, route: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        let delta = pending.entry(id.to_string()).or_default();
+        delta.requests += 1;
+        delta.last_used_at = now_secs();
+        *delta.routes.entry(route.to_string()).or_default() += 1;
+    }
+
+    fn take(&self) -> HashMap<String, UsageDelta> {
+        std::mem::take(&mut *self.pending.lock().unwrap())
+    }
+
+    /// Write pending usage to the store
+    pub async fn flush(&self) {
+        let batch = self.take();
+        if batch.is_empty() {
+            return;
+        }
+
+        if let Err(e) = self.store.record_usage(&batch).await {
+            tracing::warn!("Failed to record usage for {} tokens: {}", batch.len(), e);
+        }
+    }
+
+    /// Flush pending usage every `interval`
+    pub fn spawn_flush(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the policy is dropped
+        let recorder = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(recorder) = recorder.upgrade() else {
+                    break;
+                };
+                recorder.flush().await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DatabaseError;
+    use crate::policy::providers::bouncer::authentication::bearer::store::{
+        ManagedToken, TokenUsage,
+    };
+    use async_trait::async_trait;
+
+    struct NoopStore;
+
+    #[async_trait]
+    impl ManagedTokenStore for NoopStore {
+        async fn insert(&self, _: &str, _: &ManagedToken) -> Result<(), DatabaseError> {
+            Ok(())
+        }
+
+        async fn find(&self, _: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+            Ok(None)
+        }
+
+        async fn get(&self, _: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+            Ok(None)
+        }
+
+        async fn list(&self) -> Result<Vec<ManagedToken>, DatabaseError> {
+            Ok(vec![])
+        }
+
+        async fn mark_rotated(&self, _: &str, _: &str, _: i64) -> Result<bool, DatabaseError> {
+            Ok(false)
+        }
+
+        async fn revoke(&self, _: &str) -> Result<bool, DatabaseError> {
+            Ok(false)
+        }
+
+        async fn record_usage(&self, _: &HashMap<String, UsageDelta>) -> Result<(), DatabaseError> {
+            Ok(())
+        }
+
+        async fn usage(&self) -> Result<Vec<TokenUsage>, DatabaseError> {
+            Ok(vec![])
+        }
+    }
+
+    #[test]
+    fn test_usage_is_batched_per_token() {
+        let recorder = UsageRecorder::new(Arc::new(NoopStore));
+        recorder.record("a", "GET /users");
+        recorder.record("a", "GET /users");
+        recorder.record("a", "POST /users");
+        recorder.record("b", "GET /users");
+
+        let batch = recorder.take();
+        assert_eq!(batch["a"].requests, 3);
+        assert_eq!(batch["a"].routes["GET /users"], 2);
+        assert_eq!(batch["a"].routes["POST /users"], 1);
+        assert_eq!(batch["b"].requests, 1);
+        assert!(recorder.take().is_empty());
+    }
+}
@@ -1,61 +1,608 @@
+use crate::cache::CacheStore;
+use crate::config::SecretString;
+use crate::database::metrics::QueryMetrics;
+#[cfg(feature = "sqlx")]
+use crate::database::replicas::SqlPools;
+use crate::database::sql::{NamedQuery, PlaceholderStyle};
 use crate::database::DatabaseError;
-use crate::policy::traits::{Policy, PolicyFactory, PolicyResult};
+use crate::policy::denial::Denial;
+#[cfg(feature = "sqlx")]
+use crate::policy::providers::bouncer::authentication::identity::parse_scopes;
+use crate::policy::providers::bouncer::authentication::identity::{continue_anonymous, Identity};
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyResult};
 use async_trait::async_trait;
 use axum::{
     body::Body,
-    http::{header, Request, Response, StatusCode},
+    http::{header, HeaderValue, Request, StatusCode},
 };
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use std::time::Duration;
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BearerAuthConfig {
-    pub token: Option<String>,
+    pub token: Option<SecretString>,
     pub realm: Option<String>,
     pub db_provider: Option<String>,
+    /// Query returning the token's identity. Columns named `role`, `owner`,
+    /// `scopes`, `expires_at` and `enabled` are mapped into the identity, and the
+    /// query can bind `:token` or `:token_sha256` as named parameters
     pub token_validation_query: Option<String>,
+    /// Give up on token lookups that take longer than this many milliseconds
+    pub statement_timeout_ms: Option<u64>,
+    /// Remember unknown tokens in the shared cache store for this many seconds,
+    /// so repeated requests with invalid tokens don't hit the database
+    pub negative_cache_ttl_secs: Option<u64>,
+    /// Token lookup settings when `db_provider` is "mongo"
+    pub mongo: Option<MongoTokenConfig>,
+    /// Let requests without credentials through with an anonymous identity
+    /// instead of rejecting them. Invalid credentials are still rejected
+    #[serde(default)]
+    pub optional: bool,
+    /// Role of anonymous requests when `optional` is set. Defaults to "anonymous"
+    pub anonymous_role: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MongoTokenConfig {
+    pub collection: String,
+    /// Field the token is matched against
+    #[serde(default = "default_token_field")]
+    pub token_field: String,
+    /// Field holding the role. Dotted paths like "auth.role" are supported
+    #[serde(default = "default_role_field")]
+    pub role_field: String,
+    /// The role field holds an array of roles, which are joined with ','
+    #[serde(default)]
+    pub roles_array: bool,
+    /// How tokens are stored in the collection
+    #[serde(default)]
+    pub token_hash: TokenHash,
+    /// Extra conditions every token document must match, e.g. `{ active: true }`
+    pub filter: Option<serde_json::Map<String, serde_json::Value>>,
+    /// Projection used for the lookup. Defaults to only the role field
+    pub projection: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+fn default_token_field() -> String {
+    "token".to_string()
+}
+
+fn default_role_field() -> String {
+    "role".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenHash {
+    /// Tokens are stored as-is
+    #[default]
+    None,
+    /// Tokens are stored as hex-encoded SHA-256 hashes
+    Sha256,
+}
+
+impl TokenHash {
+    #[cfg(feature = "mongo")]
+    fn apply(self, token: &str) -> String {
+        match self {
+            Self::None => token.to_string(),
+            Self::Sha256 => sha256_hex(token),
+        }
+    }
+}
+
+fn sha256_hex(value: &str) -> String {
+    let digest = Sha256::digest(value.as_bytes());
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
 }
 
 // Define the database adapter trait specific to the bearer auth policy
 #[async_trait]
 pub trait TokenDatabaseAdapter: Send + Sync + 'static {
-    async fn get_role_from_token(&self, token: &str) -> Result<Option<String>, DatabaseError>;
+    async fn get_identity(&self, token: &str) -> Result<Option<Identity>, DatabaseError>;
+}
+
+// Token lookups served from `bouncer simulate --mock-db` fixtures
+pub(crate) struct MockTokenAdapter;
+
+#[async_trait]
+impl TokenDatabaseAdapter for MockTokenAdapter {
+    async fn get_identity(&self, token: &str) -> Result<Option<Identity>, DatabaseError> {
+        Ok(crate::database::mock::get()
+            .and_then(|mock| mock.tokens.get(token))
+            .map(Identity::from))
+    }
 }
 
 // Policy implementation with optional database support
 pub struct BearerAuthPolicy {
     config: BearerAuthConfig,
     db_adapter: Option<Arc<dyn TokenDatabaseAdapter>>,
+    negative_cache: Option<Arc<dyn CacheStore>>,
+}
+
+impl BearerAuthPolicy {
+    fn unauthorized(&self, error: &'static str, message: &'static str) -> PolicyResult {
+        let mut response = Denial::new(error, message).response(StatusCode::UNAUTHORIZED);
+        let challenge = format!(
+            "Bearer realm=\"{}\"",
+            self.config.realm.as_deref().unwrap_or("api")
+        );
+        if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, challenge);
+        }
+        PolicyResult::Terminate(response)
+    }
+
+    // Cache key for a token that is known to be invalid. Tokens are hashed so they
+    // are never written to the cache backend in plain text.
+    fn negative_cache_key(token: &str) -> String {
+        format!("bearer:v1:invalid:{}", sha256_hex(token))
+    }
+
+    async fn is_known_invalid(&self, token: &str) -> bool {
+        let Some(cache) = &self.negative_cache else {
+            return false;
+        };
+
+        match cache.get(&Self::negative_cache_key(token)).await {
+            Ok(entry) => entry.is_some(),
+            Err(e) => {
+                tracing::warn!("Negative auth cache lookup failed: {}", e);
+                false
+            }
+        }
+    }
+
+    async fn remember_invalid(&self, token: &str) {
+        let (Some(cache), Some(ttl)) = (&self.negative_cache, self.config.negative_cache_ttl_secs)
+        else {
+            return;
+        };
+
+        let key = Self::negative_cache_key(token);
+        if let Err(e) = cache
+            .set(&key, Vec::new(), Some(Duration::from_secs(ttl)))
+            .await
+        {
+            tracing::warn!("Failed to update negative auth cache: {}", e);
+        }
+    }
+}
+
+// Named parameters a token validation query can use
+const TOKEN_QUERY_PARAMS: [&str; 2] = ["token", "token_sha256"];
+
+// Token validation query prepared for a specific SQL database
+pub struct TokenQuery {
+    // Only SQL databases run token queries
+    #[cfg_attr(not(feature = "sqlx"), allow(dead_code))]
+    query: NamedQuery,
+    #[cfg_attr(not(feature = "sqlx"), allow(dead_code))]
+    timeout: Option<Duration>,
+    metrics: Option<Arc<QueryMetrics>>,
+}
+
+impl TokenQuery {
+    pub fn new(
+        query: &str,
+        style: PlaceholderStyle,
+        timeout: Option<Duration>,
+    ) -> Result<Self, DatabaseError> {
+        Self::with_params(
+            query,
+            style,
+            timeout,
+            "token_validation_query",
+            &TOKEN_QUERY_PARAMS,
+        )
+    }
+
+    /// Prepare a lookup query that names its parameters differently, e.g.
+    /// `:fingerprint`. `:token_sha256`, if allowed, binds the SHA-256 of the
+    /// value and every other parameter binds the value itself
+    pub fn with_params(
+        query: &str,
+        style: PlaceholderStyle,
+        timeout: Option<Duration>,
+        option: &str,
+        params: &[&str],
+    ) -> Result<Self, DatabaseError> {
+        let mut query = NamedQuery::parse(query, style);
+        if let Some(param) = query
+            .params
+            .iter()
+            .find(|param| !params.contains(&param.as_str()))
+        {
+            let expected: Vec<String> = params.iter().map(|p| format!(":{}", p)).collect();
+            return Err(DatabaseError::ConfigurationError(format!(
+                "Unknown parameter ':{}' in {}. Expected {}",
+                param,
+                option,
+                expected.join(" or ")
+            )));
+        }
+
+        // Queries with positional placeholders bind the value once
+        if query.params.is_empty() {
+            query.params.push(params[0].to_string());
+        }
+
+        Ok(Self {
+            query,
+            timeout,
+            metrics: None,
+        })
+    }
+
+    /// Record latency and errors for every lookup
+    pub fn with_metrics(mut self, metrics: Arc<QueryMetrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    // Values to bind, in placeholder order
+    #[cfg(feature = "sqlx")]
+    fn values(&self, token: &str) -> Vec<String> {
+        self.query
+            .params
+            .iter()
+            .map(|param| match param.as_str() {
+                "token_sha256" => sha256_hex(token),
+                _ => token.to_string(),
+            })
+            .collect()
+    }
+
+    // Run a lookup, applying the statement timeout and recording metrics
+    #[cfg(feature = "sqlx")]
+    async fn run<T, E, F>(&self, query: F) -> Result<T, DatabaseError>
+    where
+        E: std::fmt::Display,
+        F: std::future::Future<Output = Result<T, E>>,
+    {
+        let query = async {
+            let result = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, query).await.map_err(|_| {
+                    DatabaseError::QueryError(format!(
+                        "Token lookup timed out after {}ms",
+                        timeout.as_millis()
+                    ))
+                })?,
+                None => query.await,
+            };
+
+            result.map_err(|e| DatabaseError::QueryError(e.to_string()))
+        };
+
+        match &self.metrics {
+            Some(metrics) => metrics.observe(query).await,
+            None => query.await,
+        }
+    }
+}
+
+// Map a token query row into an identity. A query returning a single unnamed
+// column is treated as returning just the role.
+#[cfg(feature = "sqlx")]
+fn identity_from_row<R>(row: &R) -> Result<Identity, DatabaseError>
+where
+    R: sqlx::Row,
+    usize: sqlx::ColumnIndex<R>,
+    for<'r> &'r str: sqlx::ColumnIndex<R>,
+    for<'r> String: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> i64: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+    for<'r> bool: sqlx::Decode<'r, R::Database> + sqlx::Type<R::Database>,
+{
+    use sqlx::Column;
+
+    let has_column = |name: &str| row.columns().iter().any(|c| c.name() == name);
+    let convert = |e: sqlx::Error| DatabaseError::ConversionError(e.to_string());
+
+    let role: Option<String> = if has_column("role") {
+        row.try_get("role").map_err(convert)?
+    } else {
+        row.try_get(0).map_err(convert)?
+    };
+    let mut identity = Identity::new(role.ok_or_else(|| {
+        DatabaseError::ConversionError("Token query returned a NULL role".to_string())
+    })?);
+
+    if has_column("owner") {
+        identity.owner = row.try_get("owner").map_err(convert)?;
+    }
+    if has_column("scopes") {
+        let scopes: Option<String> = row.try_get("scopes").map_err(convert)?;
+        identity.scopes = scopes.as_deref().map(parse_scopes).unwrap_or_default();
+    }
+    if has_column("expires_at") {
+        identity.expires_at = row.try_get("expires_at").map_err(convert)?;
+    }
+    if has_column("enabled") {
+        let enabled: Option<bool> = row.try_get("enabled").map_err(convert)?;
+        identity.enabled = enabled.unwrap_or(true);
+    }
+
+    Ok(identity)
 }
 
 // MySQL Implementation of the TokenDatabaseAdapter
+#[cfg(feature = "mysql")]
 pub struct MySqlTokenAdapter {
-    client: Arc<sqlx::Pool<sqlx::MySql>>,
-    token_validation_query: String,
+    pools: Arc<SqlPools<sqlx::MySql>>,
+    query: TokenQuery,
 }
 
+#[cfg(feature = "mysql")]
 impl MySqlTokenAdapter {
-    pub fn new(client: Arc<sqlx::Pool<sqlx::MySql>>, token_validation_query: String) -> Self {
-        Self {
-            client,
-            token_validation_query,
-        }
+    pub fn new(pools: Arc<SqlPools<sqlx::MySql>>, query: TokenQuery) -> Self {
+        Self { pools, query }
     }
 }
 
+#[cfg(feature = "mysql")]
 #[async_trait]
 impl TokenDatabaseAdapter for MySqlTokenAdapter {
-    async fn get_role_from_token(&self, token: &str) -> Result<Option<String>, DatabaseError> {
-        let result = sqlx::query_scalar::<_, String>(&self.token_validation_query)
-            .bind(token)
-            .fetch_optional(&*self.client)
+    async fn get_identity(&self, token: &str) -> Result<Option<Identity>, DatabaseError> {
+        // Token lookups are reads, so they are served from replicas when available
+        let values = self.query.values(token);
+        let lookup = self.pools.read(|pool| {
+            let mut query = sqlx::query(&self.query.query.sql);
+            for value in &values {
+                query = query.bind(value.clone());
+            }
+            async move { query.fetch_optional(&*pool).await }
+        });
+
+        match self.query.run(lookup).await? {
+            Some(row) => identity_from_row(&row).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+// PostgreSQL Implementation of the TokenDatabaseAdapter
+#[cfg(feature = "postgres")]
+pub struct PostgresTokenAdapter {
+    pools: Arc<SqlPools<sqlx::Postgres>>,
+    query: TokenQuery,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresTokenAdapter {
+    pub fn new(pools: Arc<SqlPools<sqlx::Postgres>>, query: TokenQuery) -> Self {
+        Self { pools, query }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl TokenDatabaseAdapter for PostgresTokenAdapter {
+    async fn get_identity(&self, token: &str) -> Result<Option<Identity>, DatabaseError> {
+        // Token lookups are reads, so they are served from replicas when available
+        let values = self.query.values(token);
+        let lookup = self.pools.read(|pool| {
+            let mut query = sqlx::query(&self.query.query.sql);
+            for value in &values {
+                query = query.bind(value.clone());
+            }
+            async move { query.fetch_optional(&*pool).await }
+        });
+
+        match self.query.run(lookup).await? {
+            Some(row) => identity_from_row(&row).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+fn token_query(config: &BearerAuthConfig, style: PlaceholderStyle) -> Result<TokenQuery, String> {
+    let query = config.token_validation_query.as_deref().ok_or_else(|| {
+        "token_validation_query is required when using a SQL database".to_string()
+    })?;
+    TokenQuery::new(
+        query,
+        style,
+        config.statement_timeout_ms.map(Duration::from_millis),
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "mysql")]
+async fn create_mysql_adapter(
+    config: &BearerAuthConfig,
+    db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    let mysql_config = db_config
+        .mysql
+        .as_ref()
+        .ok_or_else(|| "MySQL configuration is required".to_string())?;
+    let metrics = QueryMetrics::new(
+        "bearer/v1/mysql/token_validation_query",
+        mysql_config.slow_query_ms.map(Duration::from_millis),
+    );
+    let query = token_query(config, PlaceholderStyle::QuestionMark)?.with_metrics(metrics);
+
+    let pools = crate::database::get_mysql_pools(mysql_config)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(Arc::new(MySqlTokenAdapter::new(pools, query)))
+}
+
+#[cfg(not(feature = "mysql"))]
+async fn create_mysql_adapter(
+    _config: &BearerAuthConfig,
+    _db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    Err("MySQL support is not enabled. Rebuild with the 'mysql' feature.".to_string())
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_adapter(
+    config: &BearerAuthConfig,
+    db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    let postgres_config = db_config
+        .postgres
+        .as_ref()
+        .ok_or_else(|| "PostgreSQL configuration is required".to_string())?;
+    let metrics = QueryMetrics::new(
+        "bearer/v1/postgres/token_validation_query",
+        postgres_config.slow_query_ms.map(Duration::from_millis),
+    );
+    let query = token_query(config, PlaceholderStyle::Numbered)?.with_metrics(metrics);
+
+    let pools = crate::database::get_postgres_pools(postgres_config)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(Arc::new(PostgresTokenAdapter::new(pools, query)))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_adapter(
+    _config: &BearerAuthConfig,
+    _db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    Err("PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string())
+}
+
+// MongoDB Implementation of the TokenDatabaseAdapter
+#[cfg(feature = "mongo")]
+pub struct MongoTokenAdapter {
+    collection: mongodb::Collection<mongodb::bson::Document>,
+    config: MongoTokenConfig,
+    filter: mongodb::bson::Document,
+    projection: mongodb::bson::Document,
+}
+
+#[cfg(feature = "mongo")]
+impl MongoTokenAdapter {
+    pub fn new(
+        database: mongodb::Database,
+        config: MongoTokenConfig,
+    ) -> Result<Self, DatabaseError> {
+        use mongodb::bson::{doc, to_document};
+
+        let filter = match &config.filter {
+            Some(filter) => to_document(filter)
+                .map_err(|e| DatabaseError::ConfigurationError(format!("Invalid filter: {}", e)))?,
+            None => doc! {},
+        };
+
+        let projection = match &config.projection {
+            Some(projection) => to_document(projection).map_err(|e| {
+                DatabaseError::ConfigurationError(format!("Invalid projection: {}", e))
+            })?,
+            None => doc! { config.role_field.as_str(): 1, "_id": 0 },
+        };
+
+        Ok(Self {
+            collection: database.collection(&config.collection),
+            config,
+            filter,
+            projection,
+        })
+    }
+
+    // Read the role from a document, following dotted paths into subdocuments
+    fn extract_role(
+        &self,
+        document: &mongodb::bson::Document,
+    ) -> Result<Option<String>, DatabaseError> {
+        use mongodb::bson::Bson;
+
+        let mut path = self.config.role_field.split('.');
+        let mut value = path.next().and_then(|field| document.get(field));
+        for field in path {
+            value = match value {
+                Some(Bson::Document(inner)) => inner.get(field),
+                _ => None,
+            };
+        }
+
+        match value {
+            None | Some(Bson::Null) => Ok(None),
+            Some(Bson::String(role)) => Ok(Some(role.clone())),
+            Some(Bson::Array(roles)) if self.config.roles_array => {
+                let roles: Vec<&str> = roles.iter().filter_map(|role| role.as_str()).collect();
+                Ok((!roles.is_empty()).then(|| roles.join(",")))
+            }
+            Some(other) => Err(DatabaseError::ConversionError(format!(
+                "Field '{}' must be a string{}, found {:?}",
+                self.config.role_field,
+                if self.config.roles_array {
+                    " or an array of strings"
+                } else {
+                    ""
+                },
+                other.element_type()
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "mongo")]
+#[async_trait]
+impl TokenDatabaseAdapter for MongoTokenAdapter {
+    async fn get_identity(&self, token: &str) -> Result<Option<Identity>, DatabaseError> {
+        let mut filter = self.filter.clone();
+        filter.insert(
+            self.config.token_field.as_str(),
+            self.config.token_hash.apply(token),
+        );
+
+        let document = self
+            .collection
+            .find_one(filter)
+            .projection(self.projection.clone())
             .await
             .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
 
-        Ok(result)
+        match document {
+            Some(document) => Ok(self.extract_role(&document)?.map(Identity::new)),
+            None => Ok(None),
+        }
     }
 }
 
+#[cfg(feature = "mongo")]
+async fn create_mongo_adapter(
+    config: &BearerAuthConfig,
+    db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    let mongo_token_config = config
+        .mongo
+        .clone()
+        .ok_or_else(|| "mongo settings are required when using MongoDB".to_string())?;
+    let mongo_config = db_config
+        .mongo
+        .as_ref()
+        .ok_or_else(|| "MongoDB configuration is required".to_string())?;
+
+    let client = crate::database::get_mongo_client(mongo_config)
+        .await
+        .map_err(|e| e.to_string())?;
+    let adapter =
+        MongoTokenAdapter::new(client.database(&mongo_config.database), mongo_token_config)
+            .map_err(|e| e.to_string())?;
+
+    Ok(Arc::new(adapter))
+}
+
+#[cfg(not(feature = "mongo"))]
+async fn create_mongo_adapter(
+    _config: &BearerAuthConfig,
+    _db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    Err("MongoDB support is not enabled. Rebuild with the 'mongo' feature.".to_string())
+}
+
 // Policy factory for creating bearer auth policies
 pub struct BearerAuthPolicyFactory;
 
@@ -73,44 +620,46 @@ impl PolicyFactory for BearerAuthPolicyFactory {
     }
 
     async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
         // If using database authentication, initialize the adapter
         let db_adapter = if let Some(db_provider) = &config.db_provider {
-            if db_provider != "mysql" {
-                return Err("Only MySQL database provider is supported".to_string());
-            }
-
-            if config.token_validation_query.is_none() {
-                return Err(
-                    "token_validation_query is required when using MySQL database".to_string(),
-                );
-            }
-
-            // Get the global database configuration
-            let db_config = match crate::GLOBAL_CONFIG.get() {
-                Some(global_config) => &global_config.databases,
+            // Get the database configuration of the instance
+            let instance_config = crate::instance::config();
+            let db_config = match instance_config.as_deref() {
+                Some(instance_config) => &instance_config.databases,
                 None => return Err("Global configuration not initialized".to_string()),
             };
 
-            // Validate MySQL config exists
-            crate::database::validate_database_config(db_config, "mysql")
+            // Validate the database config exists
+            crate::database::validate_database_config(db_config, db_provider)
                 .map_err(|e| e.to_string())?;
 
-            // Get MySQL client
-            let mysql_config = db_config
-                .mysql
-                .as_ref()
-                .ok_or_else(|| "MySQL configuration is required".to_string())?;
+            // Lookups are answered from fixtures when simulating requests
+            if crate::database::mock::get().is_some() {
+                return Ok(BearerAuthPolicy {
+                    config,
+                    db_adapter: Some(Arc::new(MockTokenAdapter)),
+                    negative_cache: None,
+                });
+            }
 
-            // Get MySQL client asynchronously
-            let client = crate::database::get_mysql_client(mysql_config)
-                .await
-                .map_err(|e| e.to_string())?;
+            match db_provider.as_str() {
+                "mongo" => Some(create_mongo_adapter(&config, db_config).await?),
+                "postgres" => Some(create_postgres_adapter(&config, db_config).await?),
+                _ => Some(create_mysql_adapter(&config, db_config).await?),
+            }
+        } else {
+            None
+        };
 
-            // Create the adapter
-            Some(Arc::new(MySqlTokenAdapter::new(
-                client,
-                config.token_validation_query.clone().unwrap(),
-            )) as Arc<dyn TokenDatabaseAdapter>)
+        // Only database lookups benefit from caching invalid tokens
+        let negative_cache = if db_adapter.is_some() && config.negative_cache_ttl_secs.is_some() {
+            Some(
+                crate::cache::shared_cache_store()
+                    .await
+                    .map_err(|e| e.to_string())?,
+            )
         } else {
             None
         };
@@ -118,20 +667,36 @@ impl PolicyFactory for BearerAuthPolicyFactory {
         Ok(BearerAuthPolicy {
             config,
             db_adapter,
+            negative_cache,
         })
     }
 
     fn validate_config(config: &Self::Config) -> Result<(), String> {
         // If using database authentication, validate required fields
-        if let Some(db_provider) = &config.db_provider {
-            if db_provider != "mysql" {
-                return Err("Only MySQL database provider is supported".to_string());
+        match config.db_provider.as_deref() {
+            None => {}
+            Some(provider @ ("mysql" | "postgres")) => {
+                let style = if provider == "mysql" {
+                    PlaceholderStyle::QuestionMark
+                } else {
+                    PlaceholderStyle::Numbered
+                };
+                token_query(config, style)?;
             }
-
-            if config.token_validation_query.is_none() {
+            Some("mongo") => {
+                let mongo = config
+                    .mongo
+                    .as_ref()
+                    .ok_or_else(|| "mongo settings are required when using MongoDB".to_string())?;
+                if mongo.collection.is_empty() {
+                    return Err("mongo.collection is required".to_string());
+                }
+            }
+            Some(_) => {
                 return Err(
-                    "token_validation_query is required when using MySQL database".to_string(),
-                );
+                    "Only MySQL, PostgreSQL and MongoDB database providers are supported"
+                        .to_string(),
+                )
             }
         }
 
@@ -157,24 +722,33 @@ impl Policy for BearerAuthPolicy {
         "v1"
     }
 
+    fn provides(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    // Look up a token that doesn't exist, so a connection is open and the
+    // query prepared before the first client's token arrives
+    async fn warm_up(&self) -> Result<(), String> {
+        match &self.db_adapter {
+            Some(adapter) => adapter
+                .get_identity("bouncer-warm-up")
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
     async fn process(&self, request: Request<Body>) -> PolicyResult {
+        if self.config.optional && !request.headers().contains_key(header::AUTHORIZATION) {
+            return continue_anonymous(request, self.config.anonymous_role.as_deref());
+        }
+
         // Extract the Authorization header
         let auth_header = match request.headers().get(header::AUTHORIZATION) {
             Some(header) => header,
             None => {
-                return PolicyResult::Terminate(
-                    Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .header(
-                            header::WWW_AUTHENTICATE,
-                            format!(
-                                "Bearer realm=\"{}\"",
-                                self.config.realm.as_deref().unwrap_or("api")
-                            ),
-                        )
-                        .body(Body::from("Unauthorized: Bearer token required"))
-                        .unwrap(),
-                );
+                return self.unauthorized("missing_token", "Unauthorized: Bearer token required");
             }
         };
 
@@ -182,12 +756,7 @@ impl Policy for BearerAuthPolicy {
         let auth_str = match auth_header.to_str() {
             Ok(s) => s,
             Err(_) => {
-                return PolicyResult::Terminate(
-                    Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(Body::from("Invalid Authorization header format"))
-                        .unwrap(),
-                );
+                return self.unauthorized("invalid_request", "Invalid Authorization header format");
             }
         };
 
@@ -195,47 +764,45 @@ impl Policy for BearerAuthPolicy {
         let token = match auth_str.strip_prefix("Bearer ") {
             Some(t) => t,
             None => {
-                return PolicyResult::Terminate(
-                    Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .header(
-                            header::WWW_AUTHENTICATE,
-                            format!(
-                                "Bearer realm=\"{}\"",
-                                self.config.realm.as_deref().unwrap_or("api")
-                            ),
-                        )
-                        .body(Body::from("Unauthorized: Invalid Bearer token format"))
-                        .unwrap(),
+                return self.unauthorized(
+                    "invalid_request",
+                    "Unauthorized: Invalid Bearer token format",
                 );
             }
         };
 
         // Authenticate using either static token or database
         let is_authenticated = if let Some(db_adapter) = &self.db_adapter {
+            // Skip the database for tokens that recently failed
+            if self.is_known_invalid(token).await {
+                return self.unauthorized("invalid_token", "Unauthorized: Invalid token");
+            }
+
             // Authenticate using database
-            match db_adapter.get_role_from_token(token).await {
-                Ok(Some(role)) => {
-                    // Add role to request headers
+            match db_adapter.get_identity(token).await {
+                Ok(Some(identity)) if identity.is_active() => {
+                    // Add identity to request headers
                     let mut request = request;
-                    request.headers_mut().insert(
-                        header::HeaderName::from_static("x-bouncer-role"),
-                        header::HeaderValue::from_str(&role).unwrap_or_else(|_| {
-                            tracing::error!("Failed to create header value for role: {}", role);
-                            header::HeaderValue::from_static("unknown")
-                        }),
-                    );
+                    identity.apply(&mut request);
                     return PolicyResult::Continue(request);
                 }
-                Ok(None) => false,
-                Err(e) => {
-                    tracing::error!("Database authentication error: {}", e);
+                // Disabled and expired tokens are rejected, but not cached as
+                // unknown since they may be re-enabled
+                Ok(Some(_)) => false,
+                Ok(None) => {
+                    self.remember_invalid(token).await;
                     false
                 }
+                Err(e) => {
+                    return PolicyResult::Error(
+                        request,
+                        format!("Database authentication error: {}", e),
+                    );
+                }
             }
         } else if let Some(static_token) = &self.config.token {
             // Authenticate using static token
-            token == static_token
+            crate::admin::secrets_match(token, static_token.expose())
         } else {
             // No authentication method configured
             false
@@ -246,19 +813,128 @@ impl Policy for BearerAuthPolicy {
             PolicyResult::Continue(request)
         } else {
             // Authentication failed
-            PolicyResult::Terminate(
-                Response::builder()
-                    .status(StatusCode::UNAUTHORIZED)
-                    .header(
-                        header::WWW_AUTHENTICATE,
-                        format!(
-                            "Bearer realm=\"{}\"",
-                            self.config.realm.as_deref().unwrap_or("api")
-                        ),
-                    )
-                    .body(Body::from("Unauthorized: Invalid token"))
-                    .unwrap(),
-            )
+            self.unauthorized("invalid_token", "Unauthorized: Invalid token")
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(yaml: &str) -> BearerAuthConfig {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_mongo_config() {
+        let parsed = config("db_provider: mongo\nmongo:\n  collection: tokens\n");
+        let mongo = parsed.mongo.as_ref().unwrap();
+        assert_eq!(mongo.collection, "tokens");
+        assert_eq!(mongo.token_field, "token");
+        assert_eq!(mongo.role_field, "role");
+        assert!(!mongo.roles_array);
+        assert_eq!(mongo.token_hash, TokenHash::None);
+        assert!(BearerAuthPolicyFactory::validate_config(&parsed).is_ok());
+
+        let parsed = config(
+            "db_provider: mongo\nmongo:\n  collection: tokens\n  token_hash: sha256\n  filter: { active: true }\n",
+        );
+        let mongo = parsed.mongo.unwrap();
+        assert_eq!(mongo.token_hash, TokenHash::Sha256);
+        assert_eq!(mongo.filter.unwrap()["active"], true);
+
+        let missing = config("db_provider: mongo\n");
+        assert_eq!(
+            BearerAuthPolicyFactory::validate_config(&missing).unwrap_err(),
+            "mongo settings are required when using MongoDB"
+        );
+        let empty = config("db_provider: mongo\nmongo:\n  collection: ''\n");
+        assert_eq!(
+            BearerAuthPolicyFactory::validate_config(&empty).unwrap_err(),
+            "mongo.collection is required"
+        );
+        assert!(serde_yaml::from_str::<BearerAuthConfig>(
+            "db_provider: mongo\nmongo:\n  collection: tokens\n  token_hash: md5\n"
+        )
+        .is_err());
+    }
+
+    #[cfg(feature = "mongo")]
+    async fn mongo_adapter(uri: &str, yaml: &str) -> MongoTokenAdapter {
+        // Clients connect lazily, so no server is needed until a lookup
+        let client = mongodb::Client::with_uri_str(uri).await.unwrap();
+        let config: MongoTokenConfig = serde_yaml::from_str(yaml).unwrap();
+        MongoTokenAdapter::new(client.database("bouncer"), config).unwrap()
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    async fn test_mongo_adapter() {
+        use mongodb::bson::doc;
+
+        let uri = "mongodb://localhost:27017";
+        let adapter = mongo_adapter(uri, "collection: tokens\nrole_field: auth.role\n").await;
+        assert_eq!(adapter.projection, doc! { "auth.role": 1, "_id": 0 });
+        assert_eq!(adapter.filter, doc! {});
+        assert_eq!(TokenHash::None.apply("secret"), "secret");
+        assert_eq!(TokenHash::Sha256.apply("secret"), sha256_hex("secret"));
+
+        // Roles are read through dotted paths
+        let role = |document| adapter.extract_role(&document);
+        assert_eq!(
+            role(doc! { "auth": { "role": "admin" } }).unwrap(),
+            Some("admin".to_string())
+        );
+        assert_eq!(role(doc! { "auth": { "role": null } }).unwrap(), None);
+        assert_eq!(role(doc! { "auth": "admin" }).unwrap(), None);
+        assert_eq!(role(doc! {}).unwrap(), None);
+
+        // Anything but a string is a conversion error, unless arrays are allowed
+        let error = role(doc! { "auth": { "role": ["a", "b"] } }).unwrap_err();
+        assert!(matches!(error, DatabaseError::ConversionError(_)));
+        assert!(error
+            .to_string()
+            .contains("Field 'auth.role' must be a string"));
+
+        let adapter = mongo_adapter(
+            uri,
+            "collection: tokens\nroles_array: true\nfilter: { active: true }\nprojection: { role: 1 }\n",
+        )
+        .await;
+        assert_eq!(adapter.filter, doc! { "active": true });
+        assert_eq!(adapter.projection, doc! { "role": 1_i64 });
+        assert_eq!(
+            adapter
+                .extract_role(&doc! { "role": ["a", 1, "b"] })
+                .unwrap(),
+            Some("a,b".to_string())
+        );
+        assert_eq!(adapter.extract_role(&doc! { "role": [] }).unwrap(), None);
+        let error = adapter.extract_role(&doc! { "role": 7 }).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("must be a string or an array of strings"));
+    }
+
+    #[cfg(feature = "mongo")]
+    #[tokio::test]
+    async fn test_mongo_errors() {
+        // Lookups against an unreachable server fail as query errors
+        let uri = "mongodb://127.0.0.1:1/?serverSelectionTimeoutMS=100";
+        let adapter = mongo_adapter(uri, "collection: tokens\n").await;
+        let error = adapter.get_identity("secret").await.unwrap_err();
+        assert!(matches!(error, DatabaseError::QueryError(_)));
+
+        let mut config: crate::config::MongoConfig = Default::default();
+        assert!(matches!(
+            crate::database::get_mongo_client(&config).await,
+            Err(DatabaseError::ConfigurationError(_))
+        ));
+        config.connection_uri = serde_yaml::from_str(&format!("'{}'", uri)).unwrap();
+        assert!(matches!(
+            crate::database::get_mongo_client(&config).await,
+            Err(DatabaseError::ConnectionError(_))
+        ));
+    }
+}
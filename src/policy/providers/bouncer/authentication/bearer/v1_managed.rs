@@ -0,0 +1,867 @@
+use super::store::{
+    create_token_store, generate_token, hash_token, ManagedStoreBackend, ManagedToken,
+    ManagedTokenStore, TokenUsage,
+};
+use super::usage::UsageRecorder;
+use crate::admin::{is_admin, json_error, now_secs};
+use crate::config::SecretString;
+use crate::database::DatabaseError;
+use crate::events::{recent_denials, DecisionEvent};
+use crate::policy::denial::Denial;
+use crate::policy::providers::bouncer::authentication::identity::{continue_anonymous, Identity};
+use crate::policy::providers::bouncer::traffic::rate_limit::v1::{Quota, Quotas};
+use crate::policy::routes::RouteRegistration;
+use crate::policy::sessions::{sessions, SessionCredential, SessionFilter};
+use crate::policy::traits::{add_response_header, Capability, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{Extension, Path},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get, post},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BearerAuthManagedConfig {
+    /// Where tokens are stored: "redis" (default) or "postgres"
+    #[serde(default)]
+    pub store: ManagedStoreBackend,
+    pub realm: Option<String>,
+    /// Salt mixed into token hashes. Changing it invalidates every token
+    #[serde(default)]
+    pub salt: SecretString,
+    /// Token required by the management routes, which are disabled without it
+    pub admin_token: Option<SecretString>,
+    /// Prefix for Redis keys
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    /// Create or update the PostgreSQL schema on startup
+    #[serde(default = "default_run_migrations")]
+    pub run_migrations: bool,
+    /// Record last use, request counts and per-route counts for each token
+    #[serde(default = "default_track_usage")]
+    pub track_usage: bool,
+    /// How often recorded usage is written to the store
+    #[serde(default = "default_usage_flush_interval_secs")]
+    pub usage_flush_interval_secs: u64,
+    /// How long a rotated token stays valid after its replacement is issued
+    #[serde(default = "default_rotation_grace_period_secs")]
+    pub rotation_grace_period_secs: u64,
+    /// Serve the developer portal route, where users see their own token with
+    /// its usage, remaining quota and recent denials
+    #[serde(default)]
+    pub portal: bool,
+    /// Let requests without credentials through with an anonymous identity
+    /// instead of rejecting them. Invalid credentials are still rejected
+    #[serde(default)]
+    pub optional: bool,
+    /// Role of anonymous requests when `optional` is set. Defaults to "anonymous"
+    pub anonymous_role: Option<String>,
+}
+
+fn default_key_prefix() -> String {
+    "bouncer:tokens:".to_string()
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+fn default_track_usage() -> bool {
+    true
+}
+
+fn default_usage_flush_interval_secs() -> u64 {
+    10
+}
+
+fn default_rotation_grace_period_secs() -> u64 {
+    24 * 60 * 60
+}
+
+/// Set on responses to a rotated token, with the Unix timestamp after which
+/// it stops working
+const ROTATION_DUE_HEADER: HeaderName = HeaderName::from_static("x-token-rotation-due");
+
+#[derive(Debug, Deserialize)]
+struct CreateTokenRequest {
+    role: String,
+    owner: Option<String>,
+    expires_in_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct RotateTokenRequest {
+    /// Overrides the configured grace period
+    grace_period_secs: Option<u64>,
+}
+
+/// A newly issued token, as returned by the create and rotate routes
+#[derive(Debug, Serialize)]
+pub struct CreatedToken {
+    /// The token itself, only ever returned here
+    pub token: String,
+    #[serde(flatten)]
+    pub metadata: ManagedToken,
+    /// The token this one replaces, when rotating
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replaces: Option<ManagedToken>,
+}
+
+/// Why a token couldn't be rotated
+#[derive(Debug)]
+pub enum RotateError {
+    NotFound,
+    AlreadyRotated,
+    /// Another rotation or a revocation got there first
+    Changed,
+    Store(DatabaseError),
+}
+
+impl fmt::Display for RotateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => f.write_str("Token not found"),
+            Self::AlreadyRotated => f.write_str("Token has already been rotated"),
+            Self::Changed => f.write_str("Token changed while rotating"),
+            Self::Store(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+/// Issue a token for `role`, storing only its hash
+pub async fn issue_token(
+    store: &dyn ManagedTokenStore,
+    salt: &str,
+    role: String,
+    owner: Option<String>,
+    expires_in_secs: Option<u64>,
+) -> Result<CreatedToken, DatabaseError> {
+    let (id, token) = generate_token();
+    let created_at = now_secs();
+    let metadata = ManagedToken {
+        id,
+        role,
+        owner,
+        created_at,
+        expires_at: expires_in_secs.map(|secs| created_at + secs as i64),
+        replaced_by: None,
+    };
+    store.insert(&hash_token(salt, &token), &metadata).await?;
+    Ok(CreatedToken {
+        token,
+        metadata,
+        replaces: None,
+    })
+}
+
+/// Replace a token with a new one that keeps its role, owner and expiry
+///
+/// The old token stays valid for `grace_period_secs`, but never past its own expiry.
+pub async fn rotate_token(
+    store: &dyn ManagedTokenStore,
+    salt: &str,
+    id: &str,
+    grace_period_secs: u64,
+) -> Result<CreatedToken, RotateError> {
+    let previous = match store.get(id).await {
+        Ok(Some(previous)) if previous.replaced_by.is_none() => previous,
+        Ok(Some(_)) => return Err(RotateError::AlreadyRotated),
+        Ok(None) => return Err(RotateError::NotFound),
+        Err(e) => return Err(RotateError::Store(e)),
+    };
+
+    let (new_id, token) = generate_token();
+    let metadata = ManagedToken {
+        id: new_id,
+        role: previous.role.clone(),
+        owner: previous.owner.clone(),
+        created_at: now_secs(),
+        expires_at: previous.expires_at,
+        replaced_by: None,
+    };
+    store
+        .insert(&hash_token(salt, &token), &metadata)
+        .await
+        .map_err(RotateError::Store)?;
+
+    // The old token never outlives its original expiry
+    let due = metadata.created_at + grace_period_secs as i64;
+    let due = previous
+        .expires_at
+        .map_or(due, |expires_at| due.min(expires_at));
+
+    match store.mark_rotated(id, &metadata.id, due).await {
+        Ok(true) => Ok(CreatedToken {
+            token,
+            replaces: Some(ManagedToken {
+                replaced_by: Some(metadata.id.clone()),
+                expires_at: Some(due),
+                ..previous
+            }),
+            metadata,
+        }),
+        // Lost a race with another rotation or a revocation
+        Ok(false) => {
+            let _ = store.revoke(&metadata.id).await;
+            Err(RotateError::Changed)
+        }
+        Err(e) => {
+            let _ = store.revoke(&metadata.id).await;
+            Err(RotateError::Store(e))
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct PortalView {
+    token: ManagedToken,
+    usage: Option<TokenUsage>,
+    /// Quotas of the rate limits this request passed
+    quotas: Vec<Quota>,
+    recent_denials: Vec<DecisionEvent>,
+}
+
+// Bearer authentication against tokens issued through the management routes
+pub struct BearerAuthManagedPolicy {
+    config: Arc<BearerAuthManagedConfig>,
+    store: Arc<dyn ManagedTokenStore>,
+    usage: Option<Arc<UsageRecorder>>,
+}
+
+impl BearerAuthManagedPolicy {
+    fn unauthorized(&self, error: &'static str, message: &'static str) -> PolicyResult {
+        let mut response = Denial::new(error, message).response(StatusCode::UNAUTHORIZED);
+        let challenge = format!(
+            "Bearer realm=\"{}\"",
+            self.config.realm.as_deref().unwrap_or("api")
+        );
+        if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, challenge);
+        }
+        PolicyResult::Terminate(response)
+    }
+
+    // The developer portal, where users authenticate with their own token
+    // instead of the admin token
+    fn portal_routes(&self) -> Vec<RouteRegistration> {
+        if !self.config.portal {
+            return vec![];
+        }
+
+        let (store, config) = (self.store.clone(), self.config.clone());
+        let me = move |headers: HeaderMap, quotas: Option<Extension<Quotas>>| async move {
+            let token = headers
+                .get(header::AUTHORIZATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "));
+            let Some(token) = token else {
+                return json_error(StatusCode::UNAUTHORIZED, "Bearer token required");
+            };
+
+            let token = match store.find(&hash_token(config.salt.expose(), token)).await {
+                Ok(Some(token)) if token.expires_at.is_none_or(|at| at > now_secs()) => token,
+                Ok(_) => return json_error(StatusCode::UNAUTHORIZED, "Invalid token"),
+                Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            };
+
+            let usage = match store.usage().await {
+                Ok(usage) => usage.into_iter().find(|usage| usage.id == token.id),
+                Err(e) => return json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            };
+            let recent_denials = token
+                .owner
+                .as_deref()
+                .map(|owner| recent_denials().list(owner))
+                .unwrap_or_default();
+
+            Json(PortalView {
+                token,
+                usage,
+                quotas: quotas
+                    .map(|Extension(Quotas(quotas))| quotas)
+                    .unwrap_or_default(),
+                recent_denials,
+            })
+            .into_response()
+        };
+
+        vec![RouteRegistration {
+            relative_path: "me".to_string(),
+            handler: get(me),
+        }]
+    }
+}
+
+// Policy factory for creating managed bearer auth policies
+pub struct BearerAuthManagedPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for BearerAuthManagedPolicyFactory {
+    type PolicyType = BearerAuthManagedPolicy;
+    type Config = BearerAuthManagedConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authentication::bearer::policy_id_with_version(
+            "v1-managed",
+        )
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1-managed")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let instance_config = crate::instance::config();
+        let db_config = match instance_config.as_deref() {
+            Some(instance_config) => &instance_config.databases,
+            None => return Err("Global configuration not initialized".to_string()),
+        };
+
+        let store = create_token_store(
+            config.store,
+            &config.key_prefix,
+            config.run_migrations,
+            db_config,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        if config.admin_token.is_none() {
+            tracing::warn!(
+                "No admin_token configured for the managed bearer policy; token management routes are disabled"
+            );
+        }
+
+        let usage = config.track_usage.then(|| {
+            let recorder = UsageRecorder::new(store.clone());
+            recorder.spawn_flush(Duration::from_secs(config.usage_flush_interval_secs));
+            recorder
+        });
+
+        Ok(BearerAuthManagedPolicy {
+            config: Arc::new(config),
+            store,
+            usage,
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config
+            .admin_token
+            .as_ref()
+            .is_some_and(SecretString::is_empty)
+        {
+            return Err("admin_token must not be empty".to_string());
+        }
+        if config.track_usage && config.usage_flush_interval_secs == 0 {
+            return Err("usage_flush_interval_secs must be greater than 0".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for BearerAuthManagedPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authentication"
+    }
+
+    fn name(&self) -> &'static str {
+        "bearer"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1-managed"
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        let mut routes = self.portal_routes();
+        let Some(admin_token) = self.config.admin_token.clone() else {
+            return routes;
+        };
+        let admin_token: Arc<str> = Arc::from(admin_token.expose());
+
+        let list = {
+            let (store, admin_token) = (self.store.clone(), admin_token.clone());
+            move |headers: HeaderMap| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match store.list().await {
+                    Ok(tokens) => Json(tokens).into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let create = {
+            let (store, config, admin_token) =
+                (self.store.clone(), self.config.clone(), admin_token.clone());
+            move |headers: HeaderMap, Json(request): Json<CreateTokenRequest>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                if request.role.is_empty() {
+                    return json_error(StatusCode::BAD_REQUEST, "role is required");
+                }
+
+                match issue_token(
+                    store.as_ref(),
+                    config.salt.expose(),
+                    request.role,
+                    request.owner,
+                    request.expires_in_secs,
+                )
+                .await
+                {
+                    Ok(created) => (StatusCode::CREATED, Json(created)).into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let usage = {
+            let (store, admin_token) = (self.store.clone(), admin_token.clone());
+            move |headers: HeaderMap| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match store.usage().await {
+                    Ok(usage) => Json(usage).into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let rotate = {
+            let (store, config, admin_token) =
+                (self.store.clone(), self.config.clone(), admin_token.clone());
+            move |headers: HeaderMap,
+                  Path(id): Path<String>,
+                  request: Option<Json<RotateTokenRequest>>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                let Json(request) = request.unwrap_or_default();
+
+                let grace = request
+                    .grace_period_secs
+                    .unwrap_or(config.rotation_grace_period_secs);
+                match rotate_token(store.as_ref(), config.salt.expose(), &id, grace).await {
+                    Ok(created) => (StatusCode::CREATED, Json(created)).into_response(),
+                    Err(e) => {
+                        let status = match e {
+                            RotateError::NotFound => StatusCode::NOT_FOUND,
+                            RotateError::AlreadyRotated | RotateError::Changed => {
+                                StatusCode::CONFLICT
+                            }
+                            RotateError::Store(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                        };
+                        json_error(status, e.to_string())
+                    }
+                }
+            }
+        };
+
+        let revoke = {
+            let store = self.store.clone();
+            move |headers: HeaderMap, Path(id): Path<String>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match store.revoke(&id).await {
+                    Ok(true) => {
+                        // Cut off requests still using the token
+                        sessions().terminate_matching(&SessionFilter {
+                            credential: Some(id),
+                            ..Default::default()
+                        });
+                        StatusCode::NO_CONTENT.into_response()
+                    }
+                    Ok(false) => json_error(StatusCode::NOT_FOUND, "Token not found"),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        routes.extend([
+            RouteRegistration {
+                relative_path: "tokens".to_string(),
+                handler: get(list).post(create),
+            },
+            RouteRegistration {
+                relative_path: "tokens/{id}".to_string(),
+                handler: delete(revoke),
+            },
+            RouteRegistration {
+                relative_path: "tokens/{id}/rotate".to_string(),
+                handler: post(rotate),
+            },
+            RouteRegistration {
+                relative_path: "usage".to_string(),
+                handler: get(usage),
+            },
+        ]);
+        routes
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        if self.config.optional && !request.headers().contains_key(header::AUTHORIZATION) {
+            return continue_anonymous(request, self.config.anonymous_role.as_deref());
+        }
+
+        let token = match request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(|value| value.to_str())
+        {
+            Some(Ok(value)) => match value.strip_prefix("Bearer ") {
+                Some(token) => token.to_string(),
+                None => {
+                    return self.unauthorized(
+                        "invalid_request",
+                        "Unauthorized: Invalid Bearer token format",
+                    )
+                }
+            },
+            Some(Err(_)) => {
+                return self.unauthorized("invalid_request", "Invalid Authorization header format")
+            }
+            None => {
+                return self.unauthorized("missing_token", "Unauthorized: Bearer token required")
+            }
+        };
+
+        let token = match self
+            .store
+            .find(&hash_token(self.config.salt.expose(), &token))
+            .await
+        {
+            Ok(Some(token)) => token,
+            Ok(None) => return self.unauthorized("invalid_token", "Unauthorized: Invalid token"),
+            Err(e) => {
+                tracing::error!("Managed token store error: {}", e);
+                return self.unauthorized("invalid_token", "Unauthorized: Invalid token");
+            }
+        };
+
+        let identity = Identity {
+            owner: token.owner,
+            expires_at: token.expires_at,
+            ..Identity::new(token.role)
+        };
+        if !identity.is_active() {
+            return self.unauthorized("invalid_token", "Unauthorized: Invalid token");
+        }
+
+        if let Some(usage) = &self.usage {
+            usage.record(
+                &token.id,
+                &format!("{} {}", request.method(), request.uri().path()),
+            );
+        }
+
+        let mut request = request;
+        if token.replaced_by.is_some() {
+            if let Some(expires_at) = token.expires_at {
+                add_response_header(
+                    &mut request,
+                    ROTATION_DUE_HEADER,
+                    HeaderValue::from(expires_at),
+                );
+            }
+        }
+        identity.apply(&mut request);
+        request.extensions_mut().insert(SessionCredential(token.id));
+        PolicyResult::Continue(request)
+    }
+
+    // Usage recorded since the last flush would otherwise be lost
+    async fn on_shutdown(&self) {
+        if let Some(usage) = &self.usage {
+            usage.flush().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::DecisionEventKind;
+    use crate::policy::providers::bouncer::authentication::bearer::store::UsageDelta;
+    use crate::policy::traits::ResponseHeaders;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    // Keeps tokens in memory, rotating and revoking them like the real stores
+    #[derive(Default)]
+    struct MemoryTokenStore {
+        // Token metadata keyed by token hash
+        tokens: Mutex<HashMap<String, ManagedToken>>,
+        // Rotate tokens to another replacement just before `mark_rotated`, as
+        // if a concurrent rotation got there first
+        race: bool,
+    }
+
+    impl MemoryTokenStore {
+        fn with(token: &str, metadata: ManagedToken) -> Self {
+            let store = Self::default();
+            let hash = hash_token("salt", token);
+            store.tokens.lock().unwrap().insert(hash, metadata);
+            store
+        }
+
+        fn by_id(&self, id: &str) -> Option<ManagedToken> {
+            let tokens = self.tokens.lock().unwrap();
+            tokens.values().find(|token| token.id == id).cloned()
+        }
+    }
+
+    #[async_trait]
+    impl ManagedTokenStore for MemoryTokenStore {
+        async fn insert(
+            &self,
+            token_hash: &str,
+            token: &ManagedToken,
+        ) -> Result<(), DatabaseError> {
+            let mut tokens = self.tokens.lock().unwrap();
+            tokens.insert(token_hash.to_string(), token.clone());
+            Ok(())
+        }
+
+        async fn find(&self, token_hash: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+            Ok(self.tokens.lock().unwrap().get(token_hash).cloned())
+        }
+
+        async fn get(&self, id: &str) -> Result<Option<ManagedToken>, DatabaseError> {
+            Ok(self.by_id(id))
+        }
+
+        async fn list(&self) -> Result<Vec<ManagedToken>, DatabaseError> {
+            Ok(self.tokens.lock().unwrap().values().cloned().collect())
+        }
+
+        async fn mark_rotated(
+            &self,
+            id: &str,
+            replaced_by: &str,
+            expires_at: i64,
+        ) -> Result<bool, DatabaseError> {
+            let mut tokens = self.tokens.lock().unwrap();
+            let Some(token) = tokens.values_mut().find(|token| token.id == id) else {
+                return Ok(false);
+            };
+            if self.race {
+                token.replaced_by = Some("bnc_other".to_string());
+            }
+            if token.replaced_by.is_some() {
+                return Ok(false);
+            }
+            token.replaced_by = Some(replaced_by.to_string());
+            token.expires_at = Some(expires_at);
+            Ok(true)
+        }
+
+        async fn revoke(&self, id: &str) -> Result<bool, DatabaseError> {
+            let mut tokens = self.tokens.lock().unwrap();
+            let before = tokens.len();
+            tokens.retain(|_, token| token.id != id);
+            Ok(tokens.len() < before)
+        }
+
+        async fn record_usage(&self, _: &HashMap<String, UsageDelta>) -> Result<(), DatabaseError> {
+            Ok(())
+        }
+
+        async fn usage(&self) -> Result<Vec<TokenUsage>, DatabaseError> {
+            let tokens = self.tokens.lock().unwrap();
+            Ok(tokens
+                .values()
+                .map(|token| TokenUsage {
+                    id: token.id.clone(),
+                    owner: token.owner.clone(),
+                    requests: 7,
+                    ..TokenUsage::default()
+                })
+                .collect())
+        }
+    }
+
+    fn token(id: &str, expires_at: Option<i64>) -> ManagedToken {
+        ManagedToken {
+            id: id.to_string(),
+            role: "user".to_string(),
+            owner: Some("alice".to_string()),
+            created_at: now_secs(),
+            expires_at,
+            replaced_by: None,
+        }
+    }
+
+    fn policy(store: Arc<dyn ManagedTokenStore>) -> BearerAuthManagedPolicy {
+        let config: BearerAuthManagedConfig =
+            serde_json::from_value(serde_json::json!({ "salt": "salt", "portal": true })).unwrap();
+        BearerAuthManagedPolicy {
+            store,
+            config: Arc::new(config),
+            usage: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let hour_from_now = now_secs() + 3600;
+        let store = MemoryTokenStore::with("bnc_old", token("old", Some(hour_from_now)));
+
+        let created = rotate_token(&store, "salt", "old", 24 * 60 * 60)
+            .await
+            .unwrap();
+        let replaced = created.replaces.unwrap();
+        assert_eq!(
+            replaced.replaced_by.as_deref(),
+            Some(created.metadata.id.as_str())
+        );
+        // The grace period is cut short by the old token's own expiry, and the
+        // new token keeps it
+        assert_eq!(replaced.expires_at, Some(hour_from_now));
+        assert_eq!(store.by_id("old").unwrap().expires_at, Some(hour_from_now));
+        assert_eq!(created.metadata.expires_at, Some(hour_from_now));
+        let found = store.find(&hash_token("salt", &created.token)).await;
+        assert_eq!(found.unwrap().unwrap().owner.as_deref(), Some("alice"));
+
+        let result = rotate_token(&store, "salt", "old", 60).await;
+        assert!(matches!(result, Err(RotateError::AlreadyRotated)));
+        let result = rotate_token(&store, "salt", "missing", 60).await;
+        assert!(matches!(result, Err(RotateError::NotFound)));
+
+        // Without an expiry, the grace period applies in full
+        let store = MemoryTokenStore::with("bnc_old", token("old", None));
+        let before = now_secs();
+        let created = rotate_token(&store, "salt", "old", 60).await.unwrap();
+        let due = created.replaces.unwrap().expires_at.unwrap();
+        assert!((before + 60..=now_secs() + 60).contains(&due));
+        assert_eq!(created.metadata.expires_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_rotation_race() {
+        let store = MemoryTokenStore {
+            race: true,
+            ..MemoryTokenStore::with("bnc_old", token("old", None))
+        };
+
+        let result = rotate_token(&store, "salt", "old", 60).await;
+        assert!(matches!(result, Err(RotateError::Changed)));
+        // The replacement issued before losing the race is revoked again
+        let tokens = store.list().await.unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].id, "old");
+    }
+
+    #[tokio::test]
+    async fn test_rotation_due_header() {
+        let store = Arc::new(MemoryTokenStore::with("bnc_old", token("old", None)));
+        let policy = policy(store.clone());
+        let request = |token: &str| {
+            Request::get("/orders")
+                .header(header::AUTHORIZATION, format!("Bearer {}", token))
+                .body(Body::empty())
+                .unwrap()
+        };
+        let rotation_due = |result: PolicyResult| {
+            let PolicyResult::Continue(request) = result else {
+                panic!("expected the token to be accepted");
+            };
+            let headers = request.extensions().get::<ResponseHeaders>().cloned();
+            headers.and_then(|ResponseHeaders(headers)| headers.get(ROTATION_DUE_HEADER).cloned())
+        };
+
+        assert_eq!(rotation_due(policy.process(request("bnc_old")).await), None);
+
+        let created = rotate_token(store.as_ref(), "salt", "old", 60)
+            .await
+            .unwrap();
+        let due = created.replaces.unwrap().expires_at.unwrap();
+        assert_eq!(
+            rotation_due(policy.process(request("bnc_old")).await),
+            Some(HeaderValue::from(due))
+        );
+        assert_eq!(
+            rotation_due(policy.process(request(&created.token)).await),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_portal() {
+        let (id, secret) = generate_token();
+        let policy = policy(Arc::new(MemoryTokenStore::with(
+            &secret,
+            ManagedToken {
+                owner: Some("portal-owner".to_string()),
+                ..token(&id, None)
+            },
+        )));
+        recent_denials().record(
+            "portal-owner",
+            DecisionEvent::new(
+                DecisionEventKind::RateLimited,
+                "@bouncer/traffic/rate-limit/v1".to_string(),
+                429,
+                "GET".to_string(),
+                "/orders".to_string(),
+            ),
+        );
+
+        let mut routes = policy.portal_routes();
+        assert_eq!(routes.len(), 1);
+        let router = axum::Router::new().route("/me", routes.remove(0).handler);
+        let me = |token: Option<String>| {
+            let mut request = Request::get("/me");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            router.clone().oneshot(request.body(Body::empty()).unwrap())
+        };
+
+        let response = me(Some(secret)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let view: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(view["token"]["id"], id);
+        assert_eq!(view["usage"]["requests"], 7);
+        assert_eq!(view["recent_denials"][0]["status"], 429);
+
+        // Only a valid token of the user's own is accepted
+        let response = me(Some("bnc_unknown".to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = me(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,558 @@
+#[cfg(any(feature = "mysql", feature = "postgres"))]
+use crate::database::metrics::QueryMetrics;
+use crate::database::sql::PlaceholderStyle;
+use crate::policy::denial::Denial;
+use crate::policy::providers::bouncer::authentication::bearer::v1::{
+    MockTokenAdapter, TokenDatabaseAdapter, TokenQuery,
+};
+use crate::policy::providers::bouncer::authentication::identity::{continue_anonymous, Identity};
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyResult};
+use crate::tls::PeerCertificates;
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientCertAuthConfig {
+    /// Client certificates that are allowed
+    #[serde(default)]
+    pub certificates: Vec<PinnedCertificate>,
+    /// Also look fingerprints up in this database, "mysql" or "postgres"
+    pub db_provider: Option<String>,
+    /// Query returning the identity of an allowed fingerprint. It binds
+    /// `:fingerprint`, the lowercase hex SHA-256, and its columns are mapped
+    /// like those of the bearer policy's `token_validation_query`
+    pub fingerprint_query: Option<String>,
+    /// Give up on fingerprint lookups that take longer than this many milliseconds
+    pub statement_timeout_ms: Option<u64>,
+    /// Let requests without a client certificate through with an anonymous identity
+    /// instead of rejecting them. Invalid credentials are still rejected
+    #[serde(default)]
+    pub optional: bool,
+    /// Role of anonymous requests when `optional` is set. Defaults to "anonymous"
+    pub anonymous_role: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinnedCertificate {
+    /// SHA-256 of the certificate's subject public key info, in hex with or
+    /// without colons, or as `sha256/<base64>`
+    pub fingerprint: String,
+    /// Role of requests made with the certificate. Without one, no identity
+    /// headers are set
+    pub role: Option<String>,
+    pub owner: Option<String>,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+// Parameters a fingerprint query can bind
+const FINGERPRINT_QUERY_PARAMS: [&str; 1] = ["fingerprint"];
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Normalize a configured fingerprint to lowercase hex
+fn parse_fingerprint(fingerprint: &str) -> Result<String, String> {
+    let digest = match fingerprint.strip_prefix("sha256/") {
+        Some(encoded) => STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| format!("Invalid fingerprint '{}': {}", fingerprint, e))?,
+        None => {
+            let digits: String = fingerprint
+                .chars()
+                .filter(|c| *c != ':' && !c.is_whitespace())
+                .collect();
+            if !digits.len().is_multiple_of(2) || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("Invalid fingerprint '{}'", fingerprint));
+            }
+            (0..digits.len())
+                .step_by(2)
+                .map(|i| u8::from_str_radix(&digits[i..i + 2], 16).unwrap_or_default())
+                .collect()
+        }
+    };
+    if digest.len() != 32 {
+        return Err(format!(
+            "Invalid fingerprint '{}': a SHA-256 has 32 bytes, found {}",
+            fingerprint,
+            digest.len()
+        ));
+    }
+    Ok(hex(&digest))
+}
+
+// A DER element: its tag, all of its bytes, and its contents
+struct Element<'a> {
+    tag: u8,
+    bytes: &'a [u8],
+    contents: &'a [u8],
+}
+
+// Split the first DER element off `input`
+fn read_element(input: &[u8]) -> Option<(Element<'_>, &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&length, rest) = rest.split_first()?;
+    let (length, rest) = if length < 0x80 {
+        (length as usize, rest)
+    } else {
+        let count = (length & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let length = rest[..count]
+            .iter()
+            .fold(0usize, |length, &b| (length << 8) | b as usize);
+        (length, &rest[count..])
+    };
+    if rest.len() < length {
+        return None;
+    }
+
+    let header = input.len() - rest.len();
+    let element = Element {
+        tag,
+        bytes: &input[..header + length],
+        contents: &rest[..length],
+    };
+    Some((element, &rest[length..]))
+}
+
+/// The subject public key info of a DER certificate
+///
+/// Pinning the key rather than the whole certificate lets a client renew its
+/// certificate with the same key without a config change.
+pub fn subject_public_key_info(certificate: &[u8]) -> Option<&[u8]> {
+    const SEQUENCE: u8 = 0x30;
+    const VERSION: u8 = 0xa0;
+
+    let (certificate, _) = read_element(certificate)?;
+    let (tbs, _) = read_element(certificate.contents)?;
+    if certificate.tag != SEQUENCE || tbs.tag != SEQUENCE {
+        return None;
+    }
+
+    let mut fields = tbs.contents;
+    if read_element(fields)?.0.tag == VERSION {
+        fields = read_element(fields)?.1;
+    }
+    // Serial number, signature algorithm, issuer, validity and subject
+    for _ in 0..5 {
+        fields = read_element(fields)?.1;
+    }
+    let (spki, _) = read_element(fields)?;
+    (spki.tag == SEQUENCE).then_some(spki.bytes)
+}
+
+/// The lowercase hex SHA-256 of a DER certificate's subject public key info
+pub fn fingerprint(certificate: &[u8]) -> Option<String> {
+    subject_public_key_info(certificate).map(|spki| hex(&Sha256::digest(spki)))
+}
+
+pub struct ClientCertAuthPolicy {
+    // Identities of the configured certificates, by fingerprint
+    pinned: HashMap<String, Option<Identity>>,
+    db_adapter: Option<Arc<dyn TokenDatabaseAdapter>>,
+    optional: bool,
+    anonymous_role: Option<String>,
+}
+
+impl ClientCertAuthPolicy {
+    fn reject(status: StatusCode, error: &'static str, message: &'static str) -> PolicyResult {
+        PolicyResult::Terminate(Denial::new(error, message).response(status))
+    }
+
+    // The identity of an allowed fingerprint. The outer `None` means it isn't
+    // allowed
+    async fn lookup(&self, fingerprint: &str) -> Option<Option<Identity>> {
+        if let Some(identity) = self.pinned.get(fingerprint) {
+            return Some(identity.clone());
+        }
+
+        match self.db_adapter.as_ref()?.get_identity(fingerprint).await {
+            Ok(Some(identity)) if identity.is_active() => Some(Some(identity)),
+            Ok(_) => None,
+            Err(e) => {
+                tracing::error!("Client certificate lookup failed: {}", e);
+                None
+            }
+        }
+    }
+}
+
+fn fingerprint_query(
+    config: &ClientCertAuthConfig,
+    style: PlaceholderStyle,
+) -> Result<TokenQuery, String> {
+    let query = config
+        .fingerprint_query
+        .as_deref()
+        .ok_or_else(|| "fingerprint_query is required when db_provider is set".to_string())?;
+    TokenQuery::with_params(
+        query,
+        style,
+        config.statement_timeout_ms.map(Duration::from_millis),
+        "fingerprint_query",
+        &FINGERPRINT_QUERY_PARAMS,
+    )
+    .map_err(|e| e.to_string())
+}
+
+#[cfg(feature = "mysql")]
+async fn create_mysql_adapter(
+    config: &ClientCertAuthConfig,
+    db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    use crate::policy::providers::bouncer::authentication::bearer::v1::MySqlTokenAdapter;
+
+    let mysql_config = db_config
+        .mysql
+        .as_ref()
+        .ok_or_else(|| "MySQL configuration is required".to_string())?;
+    let metrics = QueryMetrics::new(
+        "client_cert/v1/mysql/fingerprint_query",
+        mysql_config.slow_query_ms.map(Duration::from_millis),
+    );
+    let query = fingerprint_query(config, PlaceholderStyle::QuestionMark)?.with_metrics(metrics);
+
+    let pools = crate::database::get_mysql_pools(mysql_config)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(Arc::new(MySqlTokenAdapter::new(pools, query)))
+}
+
+#[cfg(not(feature = "mysql"))]
+async fn create_mysql_adapter(
+    _config: &ClientCertAuthConfig,
+    _db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    Err("MySQL support is not enabled. Rebuild with the 'mysql' feature.".to_string())
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_adapter(
+    config: &ClientCertAuthConfig,
+    db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    use crate::policy::providers::bouncer::authentication::bearer::v1::PostgresTokenAdapter;
+
+    let postgres_config = db_config
+        .postgres
+        .as_ref()
+        .ok_or_else(|| "PostgreSQL configuration is required".to_string())?;
+    let metrics = QueryMetrics::new(
+        "client_cert/v1/postgres/fingerprint_query",
+        postgres_config.slow_query_ms.map(Duration::from_millis),
+    );
+    let query = fingerprint_query(config, PlaceholderStyle::Numbered)?.with_metrics(metrics);
+
+    let pools = crate::database::get_postgres_pools(postgres_config)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(Arc::new(PostgresTokenAdapter::new(pools, query)))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_adapter(
+    _config: &ClientCertAuthConfig,
+    _db_config: &crate::config::DatabasesConfig,
+) -> Result<Arc<dyn TokenDatabaseAdapter>, String> {
+    Err("PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string())
+}
+
+// Policy factory for creating client certificate policies
+pub struct ClientCertAuthPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for ClientCertAuthPolicyFactory {
+    type PolicyType = ClientCertAuthPolicy;
+    type Config = ClientCertAuthConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authentication::client_cert::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let mut pinned = HashMap::new();
+        for certificate in &config.certificates {
+            let identity = certificate.role.as_ref().map(|role| Identity {
+                owner: certificate.owner.clone(),
+                scopes: certificate.scopes.clone(),
+                ..Identity::new(role.clone())
+            });
+            pinned.insert(parse_fingerprint(&certificate.fingerprint)?, identity);
+        }
+
+        let db_adapter = match &config.db_provider {
+            Some(db_provider) => {
+                let instance_config = crate::instance::config();
+                let db_config = match instance_config.as_deref() {
+                    Some(instance_config) => &instance_config.databases,
+                    None => return Err("Global configuration not initialized".to_string()),
+                };
+                crate::database::validate_database_config(db_config, db_provider)
+                    .map_err(|e| e.to_string())?;
+
+                // Fingerprints are looked up in the token fixtures when
+                // simulating requests
+                if crate::database::mock::get().is_some() {
+                    Some(Arc::new(MockTokenAdapter) as Arc<dyn TokenDatabaseAdapter>)
+                } else if db_provider == "postgres" {
+                    Some(create_postgres_adapter(&config, db_config).await?)
+                } else {
+                    Some(create_mysql_adapter(&config, db_config).await?)
+                }
+            }
+            None => None,
+        };
+
+        Ok(ClientCertAuthPolicy {
+            pinned,
+            db_adapter,
+            optional: config.optional,
+            anonymous_role: config.anonymous_role,
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        for certificate in &config.certificates {
+            parse_fingerprint(&certificate.fingerprint)?;
+            if certificate.role.is_none()
+                && (certificate.owner.is_some() || !certificate.scopes.is_empty())
+            {
+                return Err(format!(
+                    "Certificate '{}' has an owner or scopes but no role",
+                    certificate.fingerprint
+                ));
+            }
+        }
+
+        match config.db_provider.as_deref() {
+            None if config.certificates.is_empty() => {
+                Err("Set certificates, or db_provider and fingerprint_query".to_string())
+            }
+            None => Ok(()),
+            Some("mysql") => fingerprint_query(config, PlaceholderStyle::QuestionMark).map(|_| ()),
+            Some("postgres") => fingerprint_query(config, PlaceholderStyle::Numbered).map(|_| ()),
+            Some(_) => {
+                Err("Only MySQL and PostgreSQL database providers are supported".to_string())
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Policy for ClientCertAuthPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authentication"
+    }
+
+    fn name(&self) -> &'static str {
+        "client_cert"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    // Look up a fingerprint that doesn't exist, so a connection is open and
+    // the query prepared before the first client connects
+    async fn warm_up(&self) -> Result<(), String> {
+        match &self.db_adapter {
+            Some(adapter) => adapter
+                .get_identity("bouncer-warm-up")
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+            None => Ok(()),
+        }
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        let fingerprint = match request
+            .extensions()
+            .get::<PeerCertificates>()
+            .and_then(|certificates| certificates.leaf())
+        {
+            Some(certificate) => fingerprint(certificate),
+            None if self.optional => {
+                return continue_anonymous(request, self.anonymous_role.as_deref())
+            }
+            None => {
+                return Self::reject(
+                    StatusCode::UNAUTHORIZED,
+                    "missing_certificate",
+                    "Unauthorized: Client certificate required",
+                )
+            }
+        };
+
+        let Some(fingerprint) = fingerprint else {
+            return Self::reject(
+                StatusCode::UNAUTHORIZED,
+                "invalid_certificate",
+                "Unauthorized: Invalid client certificate",
+            );
+        };
+
+        match self.lookup(&fingerprint).await {
+            Some(identity) => {
+                if let Some(identity) = identity {
+                    identity.apply(&mut request);
+                }
+                PolicyResult::Continue(request)
+            }
+            None => {
+                tracing::debug!("Rejected client certificate {}", fingerprint);
+                Self::reject(
+                    StatusCode::FORBIDDEN,
+                    "certificate_not_allowed",
+                    "Forbidden: Client certificate not allowed",
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rustls::pki_types::CertificateDer;
+
+    const TEST_CERTIFICATE: &str = "-----BEGIN CERTIFICATE-----
+MIIBhjCCAS2gAwIBAgIUUMtBNP7UtZrEcPJpf1LXQj7cjNswCgYIKoZIzj0EAwIw
+GTEXMBUGA1UEAwwOYmlsbGluZy13b3JrZXIwHhcNMjYxMDE1MTgxNTE4WhcNMzYx
+MDEyMTgxNTE4WjAZMRcwFQYDVQQDDA5iaWxsaW5nLXdvcmtlcjBZMBMGByqGSM49
+AgEGCCqGSM49AwEHA0IABANs0O1XyA/+rIJJ7WlJOVT/dztXf8p2Hfh9P3KKtUTm
++GgJfwgVY+XPa3/YRjcWq8+GZpoo7dVINGscegZhMG+jUzBRMB0GA1UdDgQWBBSC
+OO/SvW3tZTDFLZbkuwFd9rCtdTAfBgNVHSMEGDAWgBSCOO/SvW3tZTDFLZbkuwFd
+9rCtdTAPBgNVHRMBAf8EBTADAQH/MAoGCCqGSM49BAMCA0cAMEQCH33w/9941IkV
+HCskAboDSKKHy6Diy25De8a2W2MgEEkCIQD+kHKGLvYUhkK332XqURUK8Jkfz3Vb
+8jor+NBloiCjAA==
+-----END CERTIFICATE-----";
+
+    // `openssl x509 -pubkey -noout | openssl pkey -pubin -outform der | sha256sum`
+    const TEST_FINGERPRINT: &str =
+        "7a4cfd7b85d5786a553391aedaeac88d8798ab0ef7f257480792c1a5c594b82c";
+
+    fn request(certificates: Option<Vec<CertificateDer<'static>>>) -> Request<Body> {
+        let mut request = Request::get("/").body(Body::empty()).unwrap();
+        if let Some(certificates) = certificates {
+            request
+                .extensions_mut()
+                .insert(PeerCertificates(certificates.into()));
+        }
+        request
+    }
+
+    fn status(result: PolicyResult) -> StatusCode {
+        match result {
+            PolicyResult::Continue(_) => StatusCode::OK,
+            PolicyResult::Terminate(response) => response.status(),
+            PolicyResult::Error(_, error) => panic!("policy failed: {}", error),
+            PolicyResult::Redirect(location, _) => panic!("unexpected redirect to {}", location),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pinned_certificates() {
+        let certificates = rustls_pemfile::certs(&mut TEST_CERTIFICATE.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            fingerprint(&certificates[0]).as_deref(),
+            Some(TEST_FINGERPRINT)
+        );
+        assert_eq!(
+            parse_fingerprint("sha256/ekz9e4XVeGpVM5Gu2urIjYeYqw738ldIB5LBpcWUuCw=").unwrap(),
+            TEST_FINGERPRINT
+        );
+
+        let config: ClientCertAuthConfig = serde_json::from_value(serde_json::json!({
+            "certificates": [{
+                "fingerprint": TEST_FINGERPRINT.to_uppercase(),
+                "role": "service",
+                "owner": "billing-worker",
+            }],
+        }))
+        .unwrap();
+        let policy = ClientCertAuthPolicyFactory::new(config).await.unwrap();
+
+        match policy.process(request(Some(certificates))).await {
+            PolicyResult::Continue(request) => {
+                assert_eq!(request.headers()["x-bouncer-role"], "service");
+                assert_eq!(request.headers()["x-bouncer-owner"], "billing-worker");
+            }
+            _ => panic!("pinned certificate was rejected"),
+        }
+        assert_eq!(
+            status(policy.process(request(None)).await),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let other = CertificateDer::from(b"not a certificate".to_vec());
+        assert_eq!(
+            status(policy.process(request(Some(vec![other]))).await),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let mut policy = policy;
+        policy.pinned.clear();
+        let certificates = rustls_pemfile::certs(&mut TEST_CERTIFICATE.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            status(policy.process(request(Some(certificates))).await),
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[tokio::test]
+    async fn test_optional() {
+        let config: ClientCertAuthConfig = serde_json::from_value(serde_json::json!({
+            "certificates": [{ "fingerprint": TEST_FINGERPRINT, "role": "service" }],
+            "optional": true,
+        }))
+        .unwrap();
+        let policy = ClientCertAuthPolicyFactory::new(config).await.unwrap();
+
+        match policy.process(request(None)).await {
+            PolicyResult::Continue(request) => {
+                assert_eq!(request.headers()["x-bouncer-role"], "anonymous");
+            }
+            _ => panic!("anonymous request was rejected"),
+        }
+
+        // Certificates that are presented must still be valid
+        let other = CertificateDer::from(b"not a certificate".to_vec());
+        assert_eq!(
+            status(policy.process(request(Some(vec![other]))).await),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+}
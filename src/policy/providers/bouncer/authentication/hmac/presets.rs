@@ -0,0 +1,302 @@
+use crate::signing::SigningAlgorithm;
+use axum::{
+    body::Body,
+    http::{header, Request},
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+
+/// Webhook senders whose signature schemes are built in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    /// `Stripe-Signature: t=<timestamp>,v1=<hex>` over `<timestamp>.<body>`
+    Stripe,
+    /// `X-Hub-Signature-256: sha256=<hex>` over the body
+    Github,
+    /// `X-Slack-Signature: v0=<hex>` over `v0:<timestamp>:<body>`, with the
+    /// timestamp in `X-Slack-Request-Timestamp`
+    Slack,
+    /// `X-Twilio-Signature: <base64>`, an HMAC-SHA1 over the URL and the
+    /// sorted form parameters
+    Twilio,
+}
+
+impl Preset {
+    /// The algorithm the sender signs with
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        match self {
+            Self::Twilio => SigningAlgorithm::HmacSha1,
+            _ => SigningAlgorithm::HmacSha256,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Encoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+/// A signature scheme for senders without a preset
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignatureScheme {
+    /// Header holding the signature
+    pub header: String,
+    /// Text before the signature in the header, e.g. `sha256=`
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub encoding: Encoding,
+    /// Header holding the Unix timestamp the sender signed, if any
+    pub timestamp_header: Option<String>,
+    /// What is signed, with `{timestamp}`, `{method}`, `{path}` and `{body}`
+    /// replaced. Defaults to `{body}`
+    #[serde(default = "default_payload")]
+    pub payload: String,
+}
+
+fn default_payload() -> String {
+    "{body}".to_string()
+}
+
+impl SignatureScheme {
+    pub(super) fn validate(&self) -> Result<(), String> {
+        if self.header.trim().is_empty() {
+            return Err("scheme.header is required".to_string());
+        }
+        if self.payload.contains("{timestamp}") && self.timestamp_header.is_none() {
+            return Err(
+                "scheme.payload uses {timestamp}, but there's no timestamp_header".to_string(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Why a request's signature couldn't be read
+#[derive(Debug, PartialEq, Eq)]
+pub(super) enum SignatureError {
+    Missing,
+    Malformed,
+}
+
+/// What a request claims to be signed with
+#[derive(Debug)]
+pub(super) struct SignedRequest {
+    /// The request is authentic if any of these matches. Senders rotating
+    /// their secret can send several
+    pub signatures: Vec<Vec<u8>>,
+    pub timestamp: Option<i64>,
+    /// The bytes the signatures are over
+    pub message: Vec<u8>,
+}
+
+pub(super) enum Scheme {
+    Preset(Preset),
+    Custom(SignatureScheme),
+}
+
+impl Scheme {
+    /// Read the signatures of a request and build the message they sign
+    ///
+    /// `public_url` is the scheme and host the sender calls, for schemes that
+    /// sign the URL.
+    pub(super) fn signed_request(
+        &self,
+        request: &Request<Body>,
+        body: &[u8],
+        public_url: Option<&str>,
+    ) -> Result<SignedRequest, SignatureError> {
+        match self {
+            Self::Preset(Preset::Stripe) => stripe(request, body),
+            Self::Preset(Preset::Github) => {
+                let signature = header_value(request, "x-hub-signature-256")?
+                    .strip_prefix("sha256=")
+                    .ok_or(SignatureError::Malformed)?;
+                Ok(SignedRequest {
+                    signatures: vec![decode_hex(signature)?],
+                    timestamp: None,
+                    message: body.to_vec(),
+                })
+            }
+            Self::Preset(Preset::Slack) => {
+                let signature = header_value(request, "x-slack-signature")?
+                    .strip_prefix("v0=")
+                    .ok_or(SignatureError::Malformed)?;
+                let timestamp = header_value(request, "x-slack-request-timestamp")?;
+                let timestamp_secs = parse_timestamp(timestamp)?;
+                Ok(SignedRequest {
+                    signatures: vec![decode_hex(signature)?],
+                    timestamp: Some(timestamp_secs),
+                    message: [b"v0:", timestamp.as_bytes(), b":", body].concat(),
+                })
+            }
+            Self::Preset(Preset::Twilio) => twilio(request, body, public_url),
+            Self::Custom(scheme) => custom(scheme, request, body),
+        }
+    }
+}
+
+fn header_value<'a>(request: &'a Request<Body>, name: &str) -> Result<&'a str, SignatureError> {
+    request
+        .headers()
+        .get(name)
+        .ok_or(SignatureError::Missing)?
+        .to_str()
+        .map(str::trim)
+        .map_err(|_| SignatureError::Malformed)
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<i64, SignatureError> {
+    timestamp.parse().map_err(|_| SignatureError::Malformed)
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, SignatureError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(SignatureError::Malformed);
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| SignatureError::Malformed))
+        .collect()
+}
+
+fn decode_base64(encoded: &str) -> Result<Vec<u8>, SignatureError> {
+    STANDARD
+        .decode(encoded)
+        .map_err(|_| SignatureError::Malformed)
+}
+
+// `Stripe-Signature: t=1492774577,v1=5257a8...,v1=...`, where any `v1` may
+// match. Other schemes, such as the `v0` test signatures, are ignored
+fn stripe(request: &Request<Body>, body: &[u8]) -> Result<SignedRequest, SignatureError> {
+    let mut timestamp = None;
+    let mut signatures = Vec::new();
+    for item in header_value(request, "stripe-signature")?.split(',') {
+        match item.trim().split_once('=') {
+            Some(("t", value)) => timestamp = Some(value),
+            Some(("v1", value)) => signatures.push(decode_hex(value)?),
+            _ => {}
+        }
+    }
+
+    let timestamp = timestamp.ok_or(SignatureError::Malformed)?;
+    if signatures.is_empty() {
+        return Err(SignatureError::Malformed);
+    }
+    Ok(SignedRequest {
+        signatures,
+        timestamp: Some(parse_timestamp(timestamp)?),
+        message: [timestamp.as_bytes(), b".", body].concat(),
+    })
+}
+
+// Twilio signs the full URL it called. Form posts append each parameter's
+// name and value, sorted by name; JSON posts instead carry the body's
+// SHA-256 in the `bodySHA256` query parameter
+fn twilio(
+    request: &Request<Body>,
+    body: &[u8],
+    public_url: Option<&str>,
+) -> Result<SignedRequest, SignatureError> {
+    let signature = decode_base64(header_value(request, "x-twilio-signature")?)?;
+
+    let base = match public_url {
+        Some(url) => url.trim_end_matches('/').to_string(),
+        None => format!("https://{}", header_value(request, "host")?),
+    };
+    let path = request
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    let mut message = format!("{}{}", base, path).into_bytes();
+
+    let body_hash = request.uri().query().and_then(|query| {
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == "bodySHA256")
+            .map(|(_, value)| value.into_owned())
+    });
+    let is_form = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+
+    if let Some(body_hash) = body_hash {
+        let actual = hex(&Sha256::digest(body));
+        if !bool::from(actual.as_bytes().ct_eq(body_hash.to_lowercase().as_bytes())) {
+            return Err(SignatureError::Malformed);
+        }
+    } else if is_form {
+        let mut params: Vec<_> = form_urlencoded::parse(body).collect();
+        params.sort();
+        for (name, value) in params {
+            message.extend_from_slice(name.as_bytes());
+            message.extend_from_slice(value.as_bytes());
+        }
+    }
+
+    Ok(SignedRequest {
+        signatures: vec![signature],
+        timestamp: None,
+        message,
+    })
+}
+
+fn custom(
+    scheme: &SignatureScheme,
+    request: &Request<Body>,
+    body: &[u8],
+) -> Result<SignedRequest, SignatureError> {
+    let signature = header_value(request, &scheme.header)?
+        .strip_prefix(scheme.prefix.as_str())
+        .ok_or(SignatureError::Malformed)?;
+    let signature = match scheme.encoding {
+        Encoding::Hex => decode_hex(signature)?,
+        Encoding::Base64 => decode_base64(signature)?,
+    };
+    let timestamp = scheme
+        .timestamp_header
+        .as_deref()
+        .map(|name| header_value(request, name))
+        .transpose()?;
+
+    // Fill in the template piece by piece, as the body needn't be UTF-8
+    let mut message = Vec::new();
+    let mut rest = scheme.payload.as_str();
+    while let Some(start) = rest.find('{') {
+        message.extend_from_slice(&rest.as_bytes()[..start]);
+        rest = &rest[start..];
+        let (value, placeholder): (&[u8], _) = if rest.starts_with("{body}") {
+            (body, "{body}")
+        } else if rest.starts_with("{timestamp}") {
+            (timestamp.unwrap_or_default().as_bytes(), "{timestamp}")
+        } else if rest.starts_with("{method}") {
+            (request.method().as_str().as_bytes(), "{method}")
+        } else if rest.starts_with("{path}") {
+            let path = request.uri().path_and_query().map(|path| path.as_str());
+            (path.unwrap_or("/").as_bytes(), "{path}")
+        } else {
+            (b"{", "{")
+        };
+        message.extend_from_slice(value);
+        rest = &rest[placeholder.len()..];
+    }
+    message.extend_from_slice(rest.as_bytes());
+
+    Ok(SignedRequest {
+        signatures: vec![signature],
+        timestamp: timestamp.map(parse_timestamp).transpose()?,
+        message,
+    })
+}
+
+pub(super) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
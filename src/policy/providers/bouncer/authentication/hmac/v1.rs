@@ -0,0 +1,552 @@
+use super::presets::{hex, Preset, Scheme, SignatureError, SignatureScheme};
+use crate::admin::now_secs;
+use crate::cache::CacheStore;
+use crate::policy::denial::Denial;
+use crate::policy::providers::bouncer::authentication::identity::Identity;
+use crate::policy::traits::{buffered_body, Capability, Policy, PolicyFactory, PolicyResult};
+use crate::signing::{create_signer, KeyConfig, Signer, SigningAlgorithm};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use subtle::ConstantTimeEq;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HmacVerificationConfig {
+    /// Sender whose signature scheme to verify: stripe, github, slack or
+    /// twilio. Without one, `scheme` describes it
+    pub preset: Option<Preset>,
+    /// Signature scheme of senders without a preset
+    pub scheme: Option<SignatureScheme>,
+    /// The secret the sender signs with. Twilio signs with HMAC-SHA1, so its
+    /// key uses the `HS1` algorithm, and every other preset `HS256`
+    pub key: KeyConfig,
+    /// Reject signatures whose timestamp is further than this from now.
+    /// Defaults to 300 seconds
+    #[serde(default = "default_tolerance_secs")]
+    pub tolerance_secs: u64,
+    /// Reject requests whose signature was already seen, so captured requests
+    /// can't be replayed. Defaults to true
+    #[serde(default = "default_true")]
+    pub replay_protection: bool,
+    /// How long signatures without a timestamp are remembered for replay
+    /// protection. Defaults to 86400 seconds
+    #[serde(default = "default_replay_window_secs")]
+    pub replay_window_secs: u64,
+    /// Largest body that is verified. Larger requests are rejected with a 413.
+    /// Defaults to 1 MiB, and is capped by `server.max_body_inspection_bytes`
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: usize,
+    /// Scheme and host the sender calls, e.g. `https://api.example.com`, for
+    /// schemes that sign the URL. Defaults to `https://` and the `Host` header
+    pub public_url: Option<String>,
+    /// Role of verified requests. Without one, no identity is set
+    pub role: Option<String>,
+}
+
+fn default_tolerance_secs() -> u64 {
+    300
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_replay_window_secs() -> u64 {
+    86400
+}
+
+fn default_max_body_bytes() -> usize {
+    1024 * 1024
+}
+
+pub struct HmacVerificationPolicy {
+    scheme: Scheme,
+    signer: Arc<dyn Signer>,
+    replay_store: Option<Arc<dyn CacheStore>>,
+    tolerance_secs: u64,
+    replay_window_secs: u64,
+    max_body_bytes: usize,
+    public_url: Option<String>,
+    role: Option<String>,
+}
+
+impl HmacVerificationPolicy {
+    fn reject(status: StatusCode, error: &'static str, message: &'static str) -> PolicyResult {
+        PolicyResult::Terminate(Denial::new(error, message).response(status))
+    }
+
+    // Remember a signature until a replay of it would be rejected anyway,
+    // returning whether it was new
+    async fn first_use(&self, mac: &[u8], timestamp: Option<i64>) -> Result<bool, String> {
+        let Some(store) = &self.replay_store else {
+            return Ok(true);
+        };
+
+        let ttl_secs = match timestamp {
+            Some(timestamp) => timestamp + self.tolerance_secs as i64 - now_secs(),
+            None => self.replay_window_secs as i64,
+        };
+        store
+            .add(
+                &format!("webhook_replay:{}", hex(mac)),
+                vec![1],
+                Some(Duration::from_secs(ttl_secs.max(1) as u64)),
+            )
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+// Policy factory for creating HMAC verification policies
+pub struct HmacVerificationPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for HmacVerificationPolicyFactory {
+    type PolicyType = HmacVerificationPolicy;
+    type Config = HmacVerificationConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authentication::hmac::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let signer = create_signer(&config.key)
+            .await
+            .map_err(|e| e.to_string())?;
+        let replay_store = if config.replay_protection {
+            Some(
+                crate::cache::shared_cache_store()
+                    .await
+                    .map_err(|e| e.to_string())?,
+            )
+        } else {
+            None
+        };
+        let scheme = match (config.preset, config.scheme) {
+            (Some(preset), _) => Scheme::Preset(preset),
+            (None, Some(scheme)) => Scheme::Custom(scheme),
+            (None, None) => unreachable!("checked in validate_config"),
+        };
+
+        Ok(HmacVerificationPolicy {
+            scheme,
+            signer,
+            replay_store,
+            tolerance_secs: config.tolerance_secs,
+            replay_window_secs: config.replay_window_secs,
+            max_body_bytes: config.max_body_bytes,
+            public_url: config.public_url,
+            role: config.role,
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        let algorithm = config.key.algorithm();
+        match (config.preset, &config.scheme) {
+            (Some(_), Some(_)) => return Err("Set either preset or scheme, not both".to_string()),
+            (None, None) => return Err("Set preset or scheme".to_string()),
+            (Some(preset), None) if preset.algorithm() != algorithm => {
+                let expected = match preset.algorithm() {
+                    SigningAlgorithm::HmacSha1 => "HS1",
+                    _ => "HS256",
+                };
+                return Err(format!("The {:?} preset needs an {} key", preset, expected));
+            }
+            (Some(_), None) => {}
+            (None, Some(scheme)) => {
+                scheme.validate()?;
+                if !algorithm.is_hmac() {
+                    return Err("key must be an HS256 or HS1 key".to_string());
+                }
+            }
+        }
+
+        if config.max_body_bytes == 0 {
+            return Err("max_body_bytes must be greater than 0".to_string());
+        }
+        if let Some(url) = &config.public_url {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(format!("public_url '{}' must start with https://", url));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for HmacVerificationPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authentication"
+    }
+
+    fn name(&self) -> &'static str {
+        "hmac"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        Some(self.max_body_bytes)
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        match self.role {
+            Some(_) => vec![Capability::Identity],
+            None => Vec::new(),
+        }
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        // The whole body is signed, so one that didn't fit can't be verified
+        let Some(body) = buffered_body(&request)
+            .and_then(|body| body.complete_bytes())
+            .cloned()
+        else {
+            return Self::reject(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "payload_too_large",
+                "Payload Too Large: The body is too large to verify",
+            );
+        };
+
+        let signed = match self
+            .scheme
+            .signed_request(&request, &body, self.public_url.as_deref())
+        {
+            Ok(signed) => signed,
+            Err(SignatureError::Missing) => {
+                return Self::reject(
+                    StatusCode::UNAUTHORIZED,
+                    "missing_signature",
+                    "Unauthorized: Request signature required",
+                )
+            }
+            Err(SignatureError::Malformed) => {
+                return Self::reject(
+                    StatusCode::UNAUTHORIZED,
+                    "invalid_signature",
+                    "Unauthorized: Invalid request signature",
+                )
+            }
+        };
+
+        if let Some(timestamp) = signed.timestamp {
+            if now_secs().abs_diff(timestamp) > self.tolerance_secs {
+                return Self::reject(
+                    StatusCode::UNAUTHORIZED,
+                    "stale_signature",
+                    "Unauthorized: Request signature has expired",
+                );
+            }
+        }
+
+        let mac = match self.signer.sign(&signed.message).await {
+            Ok(mac) => mac,
+            Err(e) => return PolicyResult::Error(request, e.to_string()),
+        };
+        let matches = signed.signatures.iter().fold(false, |matches, signature| {
+            matches | bool::from(signature.ct_eq(&mac))
+        });
+        if !matches {
+            return Self::reject(
+                StatusCode::UNAUTHORIZED,
+                "invalid_signature",
+                "Unauthorized: Invalid request signature",
+            );
+        }
+
+        match self.first_use(&mac, signed.timestamp).await {
+            Ok(true) => {}
+            Ok(false) => {
+                return Self::reject(
+                    StatusCode::CONFLICT,
+                    "replayed_request",
+                    "Conflict: This request was already received",
+                )
+            }
+            Err(e) => return PolicyResult::Error(request, e),
+        }
+
+        if let Some(role) = &self.role {
+            Identity::new(role.clone()).apply(&mut request);
+        }
+        PolicyResult::Continue(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{store::MemoryCacheStore, CacheLimits};
+    use crate::policy::traits::BufferedBody;
+    use crate::signing::local::HmacSigner;
+    use hmac::{Hmac, Mac};
+    use sha2::Sha256;
+
+    const SECRET: &str = "whsec_test";
+
+    fn policy(scheme: Scheme) -> HmacVerificationPolicy {
+        let signer: Arc<dyn Signer> = match &scheme {
+            Scheme::Preset(Preset::Twilio) => Arc::new(HmacSigner::sha1("12345")),
+            _ => Arc::new(HmacSigner::new(SECRET)),
+        };
+        HmacVerificationPolicy {
+            scheme,
+            signer,
+            replay_store: Some(Arc::new(MemoryCacheStore::new(CacheLimits::default()))),
+            tolerance_secs: 300,
+            replay_window_secs: 86400,
+            max_body_bytes: 1024,
+            public_url: None,
+            role: Some("webhook".to_string()),
+        }
+    }
+
+    fn sign(message: &[u8]) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+        mac.update(message);
+        hex(&mac.finalize().into_bytes())
+    }
+
+    fn request(uri: &str, headers: &[(&str, String)], body: &'static str) -> Request<Body> {
+        let mut request = Request::post(uri);
+        for (name, value) in headers {
+            request = request.header(*name, value);
+        }
+        let mut request = request.body(Body::from(body)).unwrap();
+        request
+            .extensions_mut()
+            .insert(BufferedBody::new(body.into(), true));
+        request
+    }
+
+    async fn status(policy: &HmacVerificationPolicy, request: Request<Body>) -> StatusCode {
+        match policy.process(request).await {
+            PolicyResult::Continue(request) => {
+                assert_eq!(request.headers()["x-bouncer-role"], "webhook");
+                StatusCode::OK
+            }
+            PolicyResult::Terminate(response) => response.status(),
+            PolicyResult::Error(_, error) => panic!("policy failed: {}", error),
+            PolicyResult::Redirect(location, _) => panic!("unexpected redirect to {}", location),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stripe_signature() {
+        let policy = policy(Scheme::Preset(Preset::Stripe));
+        let body = r#"{"id": "evt_1"}"#;
+        let now = now_secs();
+        let stripe = |timestamp: i64, signatures: &[String]| {
+            let mut header = format!("t={}", timestamp);
+            for signature in signatures {
+                header.push_str(&format!(",v1={}", signature));
+            }
+            request("/stripe", &[("stripe-signature", header)], body)
+        };
+        let valid = sign(format!("{}.{}", now, body).as_bytes());
+
+        assert_eq!(
+            status(&policy, stripe(now, &["00".repeat(32), valid.clone()])).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&policy, stripe(now, &[sign(b"other")])).await,
+            StatusCode::UNAUTHORIZED
+        );
+        // The timestamp is signed, so it can't be moved forward
+        assert_eq!(
+            status(&policy, stripe(now + 1, &[valid])).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&policy, request("/stripe", &[], body)).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timestamp_skew() {
+        let policy = policy(Scheme::Preset(Preset::Slack));
+        let body = "token=abc&command=%2Fdeploy";
+        let slack = |timestamp: i64| {
+            let signature = sign(format!("v0:{}:{}", timestamp, body).as_bytes());
+            request(
+                "/slack",
+                &[
+                    ("x-slack-signature", format!("v0={}", signature)),
+                    ("x-slack-request-timestamp", timestamp.to_string()),
+                ],
+                body,
+            )
+        };
+
+        assert_eq!(
+            status(&policy, slack(now_secs() - 60)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&policy, slack(now_secs() + 60)).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&policy, slack(now_secs() - 301)).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(&policy, slack(now_secs() + 301)).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let policy = policy(Scheme::Preset(Preset::Github));
+        let github = |body: &'static str| {
+            let signature = format!("sha256={}", sign(body.as_bytes()));
+            request("/github", &[("x-hub-signature-256", signature)], body)
+        };
+
+        assert_eq!(status(&policy, github("{}")).await, StatusCode::OK);
+        assert_eq!(status(&policy, github("{}")).await, StatusCode::CONFLICT);
+        assert_eq!(status(&policy, github("[]")).await, StatusCode::OK);
+
+        // Forged requests aren't remembered, so they can't block real ones
+        let forged = request(
+            "/github",
+            &[("x-hub-signature-256", format!("sha256={}", "00".repeat(32)))],
+            "{\"a\": 1}",
+        );
+        assert_eq!(status(&policy, forged).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(&policy, github("{\"a\": 1}")).await, StatusCode::OK);
+
+        let mut policy = policy;
+        policy.replay_store = None;
+        assert_eq!(status(&policy, github("{}")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_twilio_signature() {
+        // The example from Twilio's webhook security documentation
+        let policy = policy(Scheme::Preset(Preset::Twilio));
+        let body = "CallSid=CA1234567890ABCDE&Caller=%2B12349013030&Digits=1234\
+                    &From=%2B12349013030&To=%2B18005551212";
+        let twilio = |signature: &str| {
+            request(
+                "/myapp.php?foo=1&bar=2",
+                &[
+                    ("host", "mycompany.com".to_string()),
+                    (
+                        "content-type",
+                        "application/x-www-form-urlencoded".to_string(),
+                    ),
+                    ("x-twilio-signature", signature.to_string()),
+                ],
+                body,
+            )
+        };
+
+        assert_eq!(
+            status(&policy, twilio("0/KCTR6DLpKmkAf8muzZqo1nDgQ=")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&policy, twilio("RSOYDt4T1cUTdK1PDd93/VVr8B8=")).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_scheme() {
+        let scheme = SignatureScheme {
+            header: "x-signature".to_string(),
+            prefix: "sha256=".to_string(),
+            encoding: Default::default(),
+            timestamp_header: Some("x-timestamp".to_string()),
+            payload: "{method} {path}\n{timestamp}\n{body}".to_string(),
+        };
+        let policy = policy(Scheme::Custom(scheme));
+        let now = now_secs();
+        let message = format!("POST /hooks?x=1\n{}\nhello", now);
+        let custom = |signature: String| {
+            request(
+                "/hooks?x=1",
+                &[("x-signature", signature), ("x-timestamp", now.to_string())],
+                "hello",
+            )
+        };
+
+        assert_eq!(
+            status(
+                &policy,
+                custom(format!("sha256={}", sign(message.as_bytes())))
+            )
+            .await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(&policy, custom(sign(message.as_bytes()))).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_too_large() {
+        let policy = policy(Scheme::Preset(Preset::Github));
+        let mut request = request(
+            "/github",
+            &[("x-hub-signature-256", format!("sha256={}", sign(b"{}")))],
+            "{}",
+        );
+        request
+            .extensions_mut()
+            .insert(BufferedBody::new("{}".into(), false));
+        assert_eq!(
+            status(&policy, request).await,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let config = |value: serde_json::Value| {
+            let config = serde_json::from_value(value).unwrap();
+            HmacVerificationPolicyFactory::validate_config(&config)
+        };
+        let key = |algorithm: &str| serde_json::json!({ "source": "local", "algorithm": algorithm, "key": "secret" });
+
+        assert!(config(serde_json::json!({ "preset": "stripe", "key": key("HS256") })).is_ok());
+        assert_eq!(
+            config(serde_json::json!({ "preset": "twilio", "key": key("HS256") })).unwrap_err(),
+            "The Twilio preset needs an HS1 key"
+        );
+        assert!(config(serde_json::json!({ "key": key("HS256") })).is_err());
+        assert!(config(serde_json::json!({
+            "scheme": { "header": "x-signature", "payload": "{timestamp}.{body}" },
+            "key": key("HS256"),
+        }))
+        .is_err());
+        assert!(config(serde_json::json!({
+            "scheme": { "header": "x-signature" },
+            "key": key("EdDSA"),
+        }))
+        .is_err());
+    }
+}
@@ -0,0 +1,115 @@
+use crate::policy::context::{context, context_mut};
+use crate::policy::traits::PolicyResult;
+use axum::{
+    body::Body,
+    http::{header::HeaderValue, Request},
+};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Who a credential belongs to and what it grants
+///
+/// Authentication policies resolve credentials into an `Identity` and pass it
+/// on to later policies in the [`PolicyContext`](crate::policy::context::PolicyContext)
+/// and as `x-bouncer-*` headers, which reach the backend when listed in
+/// `server.protected_headers.forward`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Identity {
+    pub role: String,
+    pub owner: Option<String>,
+    pub scopes: Vec<String>,
+    /// Unix timestamp in seconds after which the credential is no longer valid
+    pub expires_at: Option<i64>,
+    pub enabled: bool,
+}
+
+impl Identity {
+    pub fn new(role: impl Into<String>) -> Self {
+        Self {
+            role: role.into(),
+            owner: None,
+            scopes: Vec::new(),
+            expires_at: None,
+            enabled: true,
+        }
+    }
+
+    /// Whether the credential is enabled and not expired
+    pub fn is_active(&self) -> bool {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        self.enabled && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    /// Make this the identity of `request`, in its context and headers
+    pub fn apply(&self, request: &mut Request<Body>) {
+        context_mut(request).set_identity(self.clone());
+        self.apply_headers(request);
+    }
+
+    /// Set the `x-bouncer-role`, `x-bouncer-owner` and `x-bouncer-scopes` headers
+    pub fn apply_headers(&self, request: &mut Request<Body>) {
+        let headers = request.headers_mut();
+        headers.insert(
+            "x-bouncer-role",
+            HeaderValue::from_str(&self.role).unwrap_or_else(|_| {
+                tracing::error!("Failed to create header value for role: {}", self.role);
+                HeaderValue::from_static("unknown")
+            }),
+        );
+
+        if let Some(owner) = &self.owner {
+            match HeaderValue::from_str(owner) {
+                Ok(value) => {
+                    headers.insert("x-bouncer-owner", value);
+                }
+                Err(_) => tracing::error!("Failed to create header value for owner: {}", owner),
+            }
+        }
+
+        if !self.scopes.is_empty() {
+            match HeaderValue::from_str(&self.scopes.join(" ")) {
+                Ok(value) => {
+                    headers.insert("x-bouncer-scopes", value);
+                }
+                Err(_) => tracing::error!("Failed to create header value for scopes"),
+            }
+        }
+    }
+}
+
+/// Role given to requests without credentials by authentication policies
+/// with `optional: true`, unless they set an `anonymous_role`
+pub const ANONYMOUS_ROLE: &str = "anonymous";
+
+/// Let a request without credentials through with an anonymous identity, so
+/// authorization policies can grant public routes to its role
+///
+/// Requests an earlier policy in the chain already authenticated keep their
+/// identity.
+pub fn continue_anonymous(mut request: Request<Body>, role: Option<&str>) -> PolicyResult {
+    let authenticated = context(&request).is_some_and(|context| context.identity().is_some())
+        || request.headers().contains_key("x-bouncer-role");
+    if !authenticated {
+        Identity::new(role.unwrap_or(ANONYMOUS_ROLE)).apply(&mut request);
+    }
+    PolicyResult::Continue(request)
+}
+
+/// The verified claims of the token a request was authenticated with, added
+/// to its extensions for policies that check more than the identity
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claims(pub Map<String, Value>);
+
+/// Split a space or comma separated list of scopes
+pub fn parse_scopes(scopes: &str) -> Vec<String> {
+    scopes
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|scope| !scope.is_empty())
+        .map(|scope| scope.to_string())
+        .collect()
+}
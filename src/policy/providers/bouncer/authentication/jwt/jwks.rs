@@ -0,0 +1,152 @@
+use crate::database::DatabaseError;
+use jsonwebtoken::jwk::{JwkSet, PublicKeyUse};
+use jsonwebtoken::DecodingKey;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+// Tokens signed with a key the set doesn't have yet trigger a refetch, at
+// most this often, so rotated keys are picked up before the next refresh
+const MIN_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Signing keys fetched from an issuer's JSON Web Key Set URL
+///
+/// The keys are held in memory and replaced on every refresh. If a refresh
+/// fails, the previous keys stay in use.
+pub struct Jwks {
+    url: String,
+    client: reqwest::Client,
+    // Keys by their `kid`, if they have one
+    keys: RwLock<Vec<(Option<String>, DecodingKey)>>,
+    last_fetch: Mutex<Instant>,
+    // Why the last refresh failed, until one succeeds
+    refresh_error: RwLock<Option<String>>,
+}
+
+impl Jwks {
+    /// Fetch the keys once, failing if they can't be loaded
+    pub async fn new(url: String) -> Result<Arc<Self>, DatabaseError> {
+        let jwks = Arc::new(Self {
+            url,
+            client: reqwest::Client::new(),
+            keys: RwLock::new(Vec::new()),
+            last_fetch: Mutex::new(Instant::now()),
+            refresh_error: RwLock::new(None),
+        });
+
+        *jwks.keys.write().unwrap() = jwks.fetch().await?;
+        Ok(jwks)
+    }
+
+    async fn fetch(&self) -> Result<Vec<(Option<String>, DecodingKey)>, DatabaseError> {
+        let response = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+        let set = response
+            .json::<JwkSet>()
+            .await
+            .map_err(|e| DatabaseError::ConversionError(e.to_string()))?;
+
+        // Encryption keys and key types jsonwebtoken can't verify with are
+        // skipped, since sets often mix them in
+        let keys: Vec<_> = set
+            .keys
+            .iter()
+            .filter(|jwk| jwk.common.public_key_use != Some(PublicKeyUse::Encryption))
+            .filter_map(|jwk| match DecodingKey::from_jwk(jwk) {
+                Ok(key) => Some((jwk.common.key_id.clone(), key)),
+                Err(e) => {
+                    tracing::debug!(
+                        "Skipping key {:?} from {}: {}",
+                        jwk.common.key_id,
+                        self.url,
+                        e
+                    );
+                    None
+                }
+            })
+            .collect();
+        if keys.is_empty() {
+            return Err(DatabaseError::ConversionError(format!(
+                "No usable signing keys in {}",
+                self.url
+            )));
+        }
+        Ok(keys)
+    }
+
+    // Fetch and swap in the keys, remembering whether it worked
+    async fn refresh(&self) {
+        match self.fetch().await {
+            Ok(keys) => {
+                *self.keys.write().unwrap() = keys;
+                *self.refresh_error.write().unwrap() = None;
+            }
+            Err(e) => {
+                tracing::warn!("Failed to refresh JWKS from {}: {}", self.url, e);
+                *self.refresh_error.write().unwrap() = Some(e.to_string());
+            }
+        }
+    }
+
+    fn find(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        let keys = self.keys.read().unwrap();
+        match kid {
+            Some(kid) => keys
+                .iter()
+                .find(|(id, _)| id.as_deref() == Some(kid))
+                .map(|(_, key)| key.clone()),
+            // Tokens without a `kid` are only accepted from single key sets
+            None if keys.len() == 1 => Some(keys[0].1.clone()),
+            None => None,
+        }
+    }
+
+    /// The key a token with this `kid` header was signed with
+    pub async fn key(&self, kid: Option<&str>) -> Option<DecodingKey> {
+        if let Some(key) = self.find(kid) {
+            return Some(key);
+        }
+
+        let refetch = {
+            let mut last_fetch = self.last_fetch.lock().unwrap();
+            let due = last_fetch.elapsed() >= MIN_REFETCH_INTERVAL;
+            if due {
+                *last_fetch = Instant::now();
+            }
+            due
+        };
+        if !refetch {
+            return None;
+        }
+        self.refresh().await;
+        self.find(kid)
+    }
+
+    /// Refetch the keys every `interval`
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the policy is dropped
+        let jwks = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, and the keys were just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(jwks) = jwks.upgrade() else {
+                    break;
+                };
+                *jwks.last_fetch.lock().unwrap() = Instant::now();
+                jwks.refresh().await;
+            }
+        });
+    }
+
+    /// Why the last refresh failed, while the previous keys are still in use
+    pub fn refresh_error(&self) -> Option<String> {
+        self.refresh_error.read().unwrap().clone()
+    }
+}
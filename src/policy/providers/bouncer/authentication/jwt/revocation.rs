@@ -0,0 +1,247 @@
+use crate::config::DatabasesConfig;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Where revoked token IDs are looked up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RevocationBackend {
+    /// A Redis key per revoked `jti`
+    #[default]
+    Redis,
+    /// A JSON array of revoked `jti` values, fetched periodically
+    Url,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RevocationConfig {
+    #[serde(default)]
+    pub backend: RevocationBackend,
+    /// Prefix of the Redis keys marking a `jti` as revoked
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    /// URL serving the revocation list when `backend` is "url"
+    pub url: Option<String>,
+    /// How often the revocation list is fetched from `url`
+    #[serde(default = "default_refresh_interval_secs")]
+    pub refresh_interval_secs: u64,
+    /// Accept tokens when the revocation list can't be checked
+    #[serde(default)]
+    pub fail_open: bool,
+}
+
+fn default_key_prefix() -> String {
+    "bouncer:jwt:revoked:".to_string()
+}
+
+fn default_refresh_interval_secs() -> u64 {
+    60
+}
+
+/// A denylist of token IDs (`jti` claims)
+#[async_trait]
+pub trait RevocationList: Send + Sync + 'static {
+    async fn is_revoked(&self, jti: &str) -> Result<bool, DatabaseError>;
+
+    /// Check that revocations can be looked up
+    async fn health(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+}
+
+/// Revoked tokens stored as `{prefix}{jti}` keys in Redis
+///
+/// Keys only need to live until the token they revoke expires, e.g.
+/// `SET bouncer:jwt:revoked:<jti> 1 EXAT <exp>`.
+#[cfg(feature = "redis")]
+pub struct RedisRevocationList {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisRevocationList {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl RevocationList for RedisRevocationList {
+    async fn is_revoked(&self, jti: &str) -> Result<bool, DatabaseError> {
+        redis::cmd("EXISTS")
+            .arg(format!("{}{}", self.key_prefix, jti))
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn health(&self) -> Result<(), DatabaseError> {
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut self.connection.clone())
+            .await
+            .map(|_| ())
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))
+    }
+}
+
+/// Revoked tokens fetched from a URL serving a JSON array of `jti` values
+///
+/// The list is held in memory and replaced on every refresh. If a refresh
+/// fails, the previous list stays in use.
+pub struct UrlRevocationList {
+    url: String,
+    client: reqwest::Client,
+    revoked: RwLock<HashSet<String>>,
+    // Why the last refresh failed, until one succeeds
+    refresh_error: RwLock<Option<String>>,
+}
+
+impl UrlRevocationList {
+    /// Fetch the list once, failing if it can't be loaded
+    pub async fn new(url: String) -> Result<Arc<Self>, DatabaseError> {
+        let list = Arc::new(Self {
+            url,
+            client: reqwest::Client::new(),
+            revoked: RwLock::new(HashSet::new()),
+            refresh_error: RwLock::new(None),
+        });
+
+        *list.revoked.write().unwrap() = list.fetch().await?;
+        Ok(list)
+    }
+
+    async fn fetch(&self) -> Result<HashSet<String>, DatabaseError> {
+        let response = self
+            .client
+            .get(&self.url)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        response
+            .json::<HashSet<String>>()
+            .await
+            .map_err(|e| DatabaseError::ConversionError(e.to_string()))
+    }
+
+    /// Refetch the list every `interval`
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the policy is dropped
+        let list = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, and the list was just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(list) = list.upgrade() else {
+                    break;
+                };
+
+                match list.fetch().await {
+                    Ok(revoked) => {
+                        *list.revoked.write().unwrap() = revoked;
+                        *list.refresh_error.write().unwrap() = None;
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            "Failed to refresh JWT revocation list from {}: {}",
+                            list.url,
+                            e
+                        );
+                        *list.refresh_error.write().unwrap() = Some(e.to_string());
+                    }
+                }
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl RevocationList for UrlRevocationList {
+    async fn is_revoked(&self, jti: &str) -> Result<bool, DatabaseError> {
+        Ok(self.revoked.read().unwrap().contains(jti))
+    }
+
+    // The last list fetched stays in use, but it may be missing revocations
+    async fn health(&self) -> Result<(), DatabaseError> {
+        match self.refresh_error.read().unwrap().clone() {
+            Some(e) => Err(DatabaseError::ConnectionError(e)),
+            None => Ok(()),
+        }
+    }
+}
+
+// Mock databases hold a fixed set of revoked token IDs
+#[async_trait]
+impl RevocationList for HashSet<String> {
+    async fn is_revoked(&self, jti: &str) -> Result<bool, DatabaseError> {
+        Ok(self.contains(jti))
+    }
+}
+
+/// Create the revocation list selected in the policy config
+pub async fn create_revocation_list(
+    config: &RevocationConfig,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn RevocationList>, DatabaseError> {
+    if let Some(mock) = crate::database::mock::get() {
+        return Ok(Arc::new(mock.revoked_jtis.clone()));
+    }
+
+    match config.backend {
+        RevocationBackend::Redis => {
+            crate::database::validate_database_config(databases, "redis")?;
+            create_redis_list(&config.key_prefix, databases).await
+        }
+        RevocationBackend::Url => {
+            let url = config.url.clone().ok_or_else(|| {
+                DatabaseError::ConfigurationError(
+                    "A url is required for the url revocation backend".to_string(),
+                )
+            })?;
+            let list = UrlRevocationList::new(url).await?;
+            list.spawn_refresh(Duration::from_secs(config.refresh_interval_secs));
+            Ok(list)
+        }
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_list(
+    key_prefix: &str,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn RevocationList>, DatabaseError> {
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisRevocationList::new(&client, key_prefix.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_list(
+    _key_prefix: &str,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn RevocationList>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
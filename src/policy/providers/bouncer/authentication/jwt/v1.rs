@@ -0,0 +1,780 @@
+use super::jwks::Jwks;
+use super::revocation::{
+    create_revocation_list, RevocationBackend, RevocationConfig, RevocationList,
+};
+use crate::config::SecretString;
+use crate::policy::denial::Denial;
+use crate::policy::providers::bouncer::authentication::claims::{
+    apply_rules, validate_rules, ClaimRule,
+};
+use crate::policy::providers::bouncer::authentication::identity::{
+    continue_anonymous, parse_scopes, Claims, Identity,
+};
+use crate::policy::sessions::SessionCredential;
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyHealth, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{header, HeaderValue, Request, StatusCode},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JwtAuthConfig {
+    #[serde(default = "default_algorithm")]
+    pub algorithm: Algorithm,
+    /// Shared secret for the HS256, HS384 and HS512 algorithms
+    pub secret: Option<SecretString>,
+    /// PEM-encoded public key for every other algorithm
+    pub public_key: Option<String>,
+    /// Required `iss` claim
+    pub issuer: Option<String>,
+    /// Required `aud` claim
+    pub audience: Option<String>,
+    /// Claim holding the role, as a string or an array of strings
+    #[serde(default = "default_role_claim")]
+    pub role_claim: String,
+    #[serde(default = "default_owner_claim")]
+    pub owner_claim: String,
+    /// Claim holding scopes, as a space separated string or an array
+    #[serde(default = "default_scopes_claim")]
+    pub scopes_claim: String,
+    /// Allowed clock skew when checking `exp` and `nbf`
+    #[serde(default = "default_leeway_secs")]
+    pub leeway_secs: u64,
+    pub realm: Option<String>,
+    /// Reject tokens whose `jti` claim has been revoked
+    pub revocation: Option<RevocationConfig>,
+    /// Reshape the claims before the role, owner and scopes are read from them
+    #[serde(default)]
+    pub claim_rules: Vec<ClaimRule>,
+    /// Accept tokens from several identity providers, picked by the token's
+    /// `iss` claim. Replaces `secret`, `public_key`, `issuer` and `audience`
+    #[serde(default)]
+    pub issuers: Vec<IssuerConfig>,
+    /// Let requests without credentials through with an anonymous identity
+    /// instead of rejecting them. Invalid credentials are still rejected
+    #[serde(default)]
+    pub optional: bool,
+    /// Role of anonymous requests when `optional` is set. Defaults to "anonymous"
+    pub anonymous_role: Option<String>,
+}
+
+/// An identity provider whose tokens are accepted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IssuerConfig {
+    /// The `iss` claim of the provider's tokens
+    pub issuer: String,
+    #[serde(default = "default_issuer_algorithm")]
+    pub algorithm: Algorithm,
+    pub secret: Option<SecretString>,
+    pub public_key: Option<String>,
+    /// URL of the provider's JSON Web Key Set, used instead of a fixed key
+    pub jwks_url: Option<String>,
+    /// How often the keys are fetched from `jwks_url`
+    #[serde(default = "default_jwks_refresh_interval_secs")]
+    pub jwks_refresh_interval_secs: u64,
+    /// Required `aud` claim
+    pub audience: Option<String>,
+    /// Claim names for this provider, defaulting to the policy's
+    pub role_claim: Option<String>,
+    pub owner_claim: Option<String>,
+    pub scopes_claim: Option<String>,
+    /// Derive roles from claims instead of reading `role_claim`
+    #[serde(default)]
+    pub role_rules: Vec<RoleRule>,
+    /// Role of tokens no rule matches. Without it, they're rejected
+    pub default_role: Option<String>,
+    /// Reshape this provider's claims, after the policy's `claim_rules`
+    #[serde(default)]
+    pub claim_rules: Vec<ClaimRule>,
+}
+
+/// Grants `role` to tokens whose `claim` is `value`, or an array containing it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoleRule {
+    pub claim: String,
+    pub value: String,
+    pub role: String,
+}
+
+fn default_algorithm() -> Algorithm {
+    Algorithm::HS256
+}
+
+fn default_issuer_algorithm() -> Algorithm {
+    Algorithm::RS256
+}
+
+fn default_jwks_refresh_interval_secs() -> u64 {
+    3600
+}
+
+fn default_role_claim() -> String {
+    "role".to_string()
+}
+
+fn default_owner_claim() -> String {
+    "sub".to_string()
+}
+
+fn default_scopes_claim() -> String {
+    "scope".to_string()
+}
+
+fn default_leeway_secs() -> u64 {
+    60
+}
+
+// Key used to verify token signatures for the configured algorithm
+fn decoding_key(
+    algorithm: Algorithm,
+    secret: Option<&SecretString>,
+    public_key: Option<&String>,
+) -> Result<DecodingKey, String> {
+    if let Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 = algorithm {
+        return match secret {
+            Some(secret) if !secret.is_empty() => {
+                Ok(DecodingKey::from_secret(secret.expose().as_bytes()))
+            }
+            _ => Err(format!("A secret is required for {:?}", algorithm)),
+        };
+    }
+
+    let public_key = public_key
+        .ok_or_else(|| format!("A public_key is required for {:?}", algorithm))?
+        .as_bytes();
+
+    match algorithm {
+        Algorithm::ES256 | Algorithm::ES384 => DecodingKey::from_ec_pem(public_key),
+        Algorithm::EdDSA => DecodingKey::from_ed_pem(public_key),
+        _ => DecodingKey::from_rsa_pem(public_key),
+    }
+    .map_err(|e| format!("Invalid public_key: {}", e))
+}
+
+// A claim holding one string or an array of strings
+fn string_list(claim: Option<&Value>) -> Vec<String> {
+    match claim {
+        Some(Value::String(value)) => vec![value.clone()],
+        Some(Value::Array(values)) => values
+            .iter()
+            .filter_map(|value| value.as_str().map(str::to_string))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn validation(
+    algorithm: Algorithm,
+    leeway_secs: u64,
+    issuer: Option<&String>,
+    audience: Option<&String>,
+) -> Validation {
+    let mut validation = Validation::new(algorithm);
+    validation.leeway = leeway_secs;
+    if let Some(issuer) = issuer {
+        validation.set_issuer(&[issuer]);
+    }
+    match audience {
+        Some(audience) => validation.set_audience(&[audience]),
+        None => validation.validate_aud = false,
+    }
+    validation
+}
+
+// The `iss` claim of a token whose signature hasn't been checked yet, to pick
+// the key to check it with
+fn unverified_issuer(token: &str) -> Option<String> {
+    let payload = URL_SAFE_NO_PAD.decode(token.split('.').nth(1)?).ok()?;
+    let claims: Map<String, Value> = serde_json::from_slice(&payload).ok()?;
+    claims.get("iss")?.as_str().map(str::to_string)
+}
+
+enum IssuerKey {
+    Static(DecodingKey),
+    Jwks(Arc<Jwks>),
+}
+
+// How the tokens of one issuer are verified and mapped into identities
+struct Issuer {
+    key: IssuerKey,
+    validation: Validation,
+    role_claim: String,
+    owner_claim: String,
+    scopes_claim: String,
+    claim_rules: Vec<ClaimRule>,
+    role_rules: Vec<RoleRule>,
+    default_role: Option<String>,
+}
+
+impl Issuer {
+    // Map the token's claims into an identity. Tokens without a role are rejected
+    fn identity(&self, claims: &Map<String, Value>) -> Option<Identity> {
+        let roles = if self.role_rules.is_empty() {
+            string_list(claims.get(&self.role_claim))
+        } else {
+            let mut roles: Vec<String> = Vec::new();
+            for rule in &self.role_rules {
+                let matches = string_list(claims.get(&rule.claim)).contains(&rule.value);
+                if matches && !roles.contains(&rule.role) {
+                    roles.push(rule.role.clone());
+                }
+            }
+            roles
+        };
+        let roles = match (roles.is_empty(), &self.default_role) {
+            (false, _) => roles,
+            (true, Some(role)) => vec![role.clone()],
+            (true, None) => return None,
+        };
+
+        let scopes = match claims.get(&self.scopes_claim) {
+            Some(Value::String(scopes)) => parse_scopes(scopes),
+            claim => string_list(claim),
+        };
+
+        Some(Identity {
+            owner: claims
+                .get(&self.owner_claim)
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            scopes,
+            expires_at: claims.get("exp").and_then(Value::as_i64),
+            ..Identity::new(roles.join(","))
+        })
+    }
+}
+
+// Policy implementation verifying JSON Web Tokens
+pub struct JwtAuthPolicy {
+    config: JwtAuthConfig,
+    // Used for every token when no `issuers` are configured
+    default_issuer: Option<Issuer>,
+    issuers: HashMap<String, Issuer>,
+    revocation: Option<Arc<dyn RevocationList>>,
+}
+
+impl JwtAuthPolicy {
+    fn unauthorized(&self, error: &'static str, message: &'static str) -> PolicyResult {
+        let mut response = Denial::new(error, message).response(StatusCode::UNAUTHORIZED);
+        let challenge = format!(
+            "Bearer realm=\"{}\"",
+            self.config.realm.as_deref().unwrap_or("api")
+        );
+        if let Ok(challenge) = HeaderValue::from_str(&challenge) {
+            response
+                .headers_mut()
+                .insert(header::WWW_AUTHENTICATE, challenge);
+        }
+        PolicyResult::Terminate(response)
+    }
+
+    fn issuer(&self, token: &str) -> Option<&Issuer> {
+        match &self.default_issuer {
+            Some(issuer) => Some(issuer),
+            None => self.issuers.get(&unverified_issuer(token)?),
+        }
+    }
+
+    async fn is_revoked(&self, claims: &Map<String, Value>) -> bool {
+        // Tokens without a jti can't be revoked
+        let (Some(revocation), Some(jti)) =
+            (&self.revocation, claims.get("jti").and_then(Value::as_str))
+        else {
+            return false;
+        };
+
+        match revocation.is_revoked(jti).await {
+            Ok(revoked) => revoked,
+            Err(e) => {
+                let fail_open = self
+                    .config
+                    .revocation
+                    .as_ref()
+                    .is_some_and(|revocation| revocation.fail_open);
+                tracing::warn!("JWT revocation check failed: {}", e);
+                !fail_open
+            }
+        }
+    }
+}
+
+// Policy factory for creating JWT auth policies
+pub struct JwtAuthPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for JwtAuthPolicyFactory {
+    type PolicyType = JwtAuthPolicy;
+    type Config = JwtAuthConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authentication::jwt::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let default_issuer = if config.issuers.is_empty() {
+            Some(Issuer {
+                key: IssuerKey::Static(decoding_key(
+                    config.algorithm,
+                    config.secret.as_ref(),
+                    config.public_key.as_ref(),
+                )?),
+                validation: validation(
+                    config.algorithm,
+                    config.leeway_secs,
+                    config.issuer.as_ref(),
+                    config.audience.as_ref(),
+                ),
+                role_claim: config.role_claim.clone(),
+                owner_claim: config.owner_claim.clone(),
+                scopes_claim: config.scopes_claim.clone(),
+                claim_rules: config.claim_rules.clone(),
+                role_rules: Vec::new(),
+                default_role: None,
+            })
+        } else {
+            None
+        };
+
+        let mut issuers = HashMap::new();
+        for issuer in &config.issuers {
+            let key = match &issuer.jwks_url {
+                Some(url) => {
+                    let jwks = Jwks::new(url.clone())
+                        .await
+                        .map_err(|e| format!("Failed to load JWKS of {}: {}", issuer.issuer, e))?;
+                    jwks.spawn_refresh(Duration::from_secs(issuer.jwks_refresh_interval_secs));
+                    IssuerKey::Jwks(jwks)
+                }
+                None => IssuerKey::Static(decoding_key(
+                    issuer.algorithm,
+                    issuer.secret.as_ref(),
+                    issuer.public_key.as_ref(),
+                )?),
+            };
+            let claim = |claim: &Option<String>, default: &String| {
+                claim.clone().unwrap_or_else(|| default.clone())
+            };
+            issuers.insert(
+                issuer.issuer.clone(),
+                Issuer {
+                    key,
+                    validation: validation(
+                        issuer.algorithm,
+                        config.leeway_secs,
+                        Some(&issuer.issuer),
+                        issuer.audience.as_ref(),
+                    ),
+                    role_claim: claim(&issuer.role_claim, &config.role_claim),
+                    owner_claim: claim(&issuer.owner_claim, &config.owner_claim),
+                    scopes_claim: claim(&issuer.scopes_claim, &config.scopes_claim),
+                    claim_rules: [&config.claim_rules[..], &issuer.claim_rules[..]].concat(),
+                    role_rules: issuer.role_rules.clone(),
+                    default_role: issuer.default_role.clone(),
+                },
+            );
+        }
+
+        let revocation = match &config.revocation {
+            Some(revocation) => {
+                let instance_config = crate::instance::config();
+                let db_config = match instance_config.as_deref() {
+                    Some(instance_config) => &instance_config.databases,
+                    None => return Err("Global configuration not initialized".to_string()),
+                };
+                Some(
+                    create_revocation_list(revocation, db_config)
+                        .await
+                        .map_err(|e| e.to_string())?,
+                )
+            }
+            None => None,
+        };
+
+        Ok(JwtAuthPolicy {
+            config,
+            default_issuer,
+            issuers,
+            revocation,
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        validate_rules(&config.claim_rules)?;
+        if config.issuers.is_empty() {
+            decoding_key(
+                config.algorithm,
+                config.secret.as_ref(),
+                config.public_key.as_ref(),
+            )?;
+        } else if config.secret.is_some()
+            || config.public_key.is_some()
+            || config.issuer.is_some()
+            || config.audience.is_some()
+        {
+            return Err(
+                "With issuers, set secret, public_key, issuer and audience on each issuer"
+                    .to_string(),
+            );
+        }
+
+        let mut names = HashSet::new();
+        for issuer in &config.issuers {
+            if !names.insert(issuer.issuer.as_str()) {
+                return Err(format!("Issuer '{}' is listed twice", issuer.issuer));
+            }
+            validate_rules(&issuer.claim_rules)
+                .map_err(|e| format!("Issuer '{}': {}", issuer.issuer, e))?;
+            match &issuer.jwks_url {
+                Some(_) if issuer.secret.is_some() || issuer.public_key.is_some() => {
+                    return Err(format!(
+                        "Issuer '{}': set one of jwks_url, secret and public_key",
+                        issuer.issuer
+                    ))
+                }
+                Some(_) if issuer.jwks_refresh_interval_secs == 0 => {
+                    return Err(format!(
+                        "Issuer '{}': jwks_refresh_interval_secs must be greater than 0",
+                        issuer.issuer
+                    ))
+                }
+                Some(_) => {}
+                None => {
+                    decoding_key(
+                        issuer.algorithm,
+                        issuer.secret.as_ref(),
+                        issuer.public_key.as_ref(),
+                    )
+                    .map_err(|e| format!("Issuer '{}': {}", issuer.issuer, e))?;
+                }
+            }
+        }
+
+        if let Some(revocation) = &config.revocation {
+            if revocation.refresh_interval_secs == 0 {
+                return Err("refresh_interval_secs must be greater than 0".to_string());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for JwtAuthPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authentication"
+    }
+
+    fn name(&self) -> &'static str {
+        "jwt"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    async fn health(&self) -> PolicyHealth {
+        // Tokens are still checked against the last keys fetched
+        for (name, issuer) in &self.issuers {
+            if let IssuerKey::Jwks(jwks) = &issuer.key {
+                if let Some(e) = jwks.refresh_error() {
+                    return PolicyHealth::Degraded(format!("JWKS of {}: {}", name, e));
+                }
+            }
+        }
+
+        let Some(revocation) = &self.revocation else {
+            return PolicyHealth::Healthy;
+        };
+        match revocation.health().await {
+            Ok(()) => PolicyHealth::Healthy,
+            Err(e) => {
+                // Tokens are still checked against the last list fetched from
+                // a URL, and let through if the policy fails open
+                let reason = format!("JWT revocation list: {}", e);
+                let serving = self.config.revocation.as_ref().is_some_and(|revocation| {
+                    revocation.fail_open || revocation.backend == RevocationBackend::Url
+                });
+                if serving {
+                    PolicyHealth::Degraded(reason)
+                } else {
+                    PolicyHealth::Unhealthy(reason)
+                }
+            }
+        }
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        if self.config.optional && !request.headers().contains_key(header::AUTHORIZATION) {
+            return continue_anonymous(request, self.config.anonymous_role.as_deref());
+        }
+
+        let token = match request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .map(|value| value.to_str())
+        {
+            Some(Ok(value)) => match value.strip_prefix("Bearer ") {
+                Some(token) => token,
+                None => {
+                    return self.unauthorized(
+                        "invalid_request",
+                        "Unauthorized: Invalid Bearer token format",
+                    )
+                }
+            },
+            Some(Err(_)) => {
+                return self.unauthorized("invalid_request", "Invalid Authorization header format")
+            }
+            None => {
+                return self.unauthorized("missing_token", "Unauthorized: Bearer token required")
+            }
+        };
+
+        let Some(issuer) = self.issuer(token) else {
+            return self.unauthorized("invalid_token", "Unauthorized: Unknown token issuer");
+        };
+        let jwks_key;
+        let key = match &issuer.key {
+            IssuerKey::Static(key) => key,
+            IssuerKey::Jwks(jwks) => {
+                let kid = jsonwebtoken::decode_header(token)
+                    .ok()
+                    .and_then(|header| header.kid);
+                match jwks.key(kid.as_deref()).await {
+                    Some(key) => {
+                        jwks_key = key;
+                        &jwks_key
+                    }
+                    None => {
+                        return self.unauthorized("invalid_token", "Unauthorized: Invalid token")
+                    }
+                }
+            }
+        };
+
+        let mut claims =
+            match jsonwebtoken::decode::<Map<String, Value>>(token, key, &issuer.validation) {
+                Ok(data) => data.claims,
+                Err(e) => {
+                    tracing::debug!("Rejected JWT: {}", e);
+                    return self.unauthorized("invalid_token", "Unauthorized: Invalid token");
+                }
+            };
+        apply_rules(&issuer.claim_rules, &mut claims);
+
+        if self.is_revoked(&claims).await {
+            return self.unauthorized("token_revoked", "Unauthorized: Token has been revoked");
+        }
+
+        let Some(identity) = issuer.identity(&claims) else {
+            return self.unauthorized("no_role", "Unauthorized: Token has no role");
+        };
+
+        let mut request = request;
+        identity.apply(&mut request);
+        // Sessions can be terminated by the token's jti
+        if let Some(jti) = claims.get("jti").and_then(Value::as_str) {
+            request
+                .extensions_mut()
+                .insert(SessionCredential(jti.to_string()));
+        }
+        request.extensions_mut().insert(Claims(claims));
+        PolicyResult::Continue(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::admin::now_secs;
+    use jsonwebtoken::{encode, EncodingKey, Header};
+    use serde_json::json;
+
+    struct Revoked(&'static str);
+
+    #[async_trait]
+    impl RevocationList for Revoked {
+        async fn is_revoked(&self, jti: &str) -> Result<bool, crate::database::DatabaseError> {
+            Ok(jti == self.0)
+        }
+    }
+
+    fn config() -> JwtAuthConfig {
+        serde_json::from_value(json!({ "secret": "test-secret" })).unwrap()
+    }
+
+    fn request(claims: Value) -> Request<Body> {
+        let token = encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(b"test-secret"),
+        )
+        .unwrap();
+        Request::builder()
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_secret_redacted() {
+        let config: JwtAuthConfig = serde_json::from_value(json!({
+            "secret": "test-secret",
+            "issuers": [{ "issuer": "https://staff.example.com", "secret": "issuer-secret" }]
+        }))
+        .unwrap();
+        let shown = format!("{:?} {}", config, serde_json::to_string(&config).unwrap());
+        assert!(!shown.contains("test-secret") && !shown.contains("issuer-secret"));
+        assert_eq!(config.secret.unwrap().expose(), "test-secret");
+    }
+
+    #[tokio::test]
+    async fn test_revoked_tokens_are_rejected() {
+        let mut policy = JwtAuthPolicyFactory::new(config()).await.unwrap();
+        policy.revocation = Some(Arc::new(Revoked("revoked")));
+        let exp = now_secs() + 3600;
+
+        match policy
+            .process(request(
+                json!({ "role": ["admin", "billing"], "sub": "alice", "exp": exp, "jti": "ok" }),
+            ))
+            .await
+        {
+            PolicyResult::Continue(request) => {
+                assert_eq!(request.headers()["x-bouncer-role"], "admin,billing");
+                assert_eq!(request.headers()["x-bouncer-owner"], "alice");
+            }
+            _ => panic!("valid token was rejected"),
+        }
+
+        assert!(matches!(
+            policy
+                .process(request(
+                    json!({ "role": "admin", "exp": exp, "jti": "revoked" })
+                ))
+                .await,
+            PolicyResult::Terminate(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_issuers() {
+        let config: JwtAuthConfig = serde_json::from_value(json!({
+            "issuers": [
+                {
+                    "issuer": "https://staff.example.com",
+                    "algorithm": "HS256",
+                    "secret": "test-secret",
+                    "role_rules": [
+                        { "claim": "groups", "value": "ops", "role": "admin" },
+                        { "claim": "groups", "value": "eng", "role": "developer" },
+                    ],
+                },
+                {
+                    "issuer": "https://customers.example.com",
+                    "algorithm": "HS256",
+                    "secret": "test-secret",
+                    "owner_claim": "email",
+                    "default_role": "customer",
+                },
+            ],
+        }))
+        .unwrap();
+        let policy = JwtAuthPolicyFactory::new(config).await.unwrap();
+        let exp = now_secs() + 3600;
+
+        let headers = |result: PolicyResult| match result {
+            PolicyResult::Continue(request) => request.headers().clone(),
+            _ => panic!("valid token was rejected"),
+        };
+
+        let staff = headers(
+            policy
+                .process(request(json!({
+                    "iss": "https://staff.example.com",
+                    "groups": ["eng", "ops"],
+                    "exp": exp,
+                })))
+                .await,
+        );
+        assert_eq!(staff["x-bouncer-role"], "admin,developer");
+
+        let customer = headers(
+            policy
+                .process(request(json!({
+                    "iss": "https://customers.example.com",
+                    "email": "alice@example.com",
+                    "exp": exp,
+                })))
+                .await,
+        );
+        assert_eq!(customer["x-bouncer-role"], "customer");
+        assert_eq!(customer["x-bouncer-owner"], "alice@example.com");
+
+        // Staff tokens need a matching rule, and other issuers are unknown
+        for claims in [
+            json!({ "iss": "https://staff.example.com", "groups": ["sales"], "exp": exp }),
+            json!({ "iss": "https://other.example.com", "role": "admin", "exp": exp }),
+        ] {
+            assert!(matches!(
+                policy.process(request(claims)).await,
+                PolicyResult::Terminate(_)
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_optional() {
+        let config: JwtAuthConfig = serde_json::from_value(json!({
+            "secret": "test-secret",
+            "optional": true,
+            "anonymous_role": "public",
+        }))
+        .unwrap();
+        let policy = JwtAuthPolicyFactory::new(config).await.unwrap();
+
+        match policy
+            .process(Request::builder().body(Body::empty()).unwrap())
+            .await
+        {
+            PolicyResult::Continue(request) => {
+                assert_eq!(request.headers()["x-bouncer-role"], "public");
+                assert!(request.headers().get("x-bouncer-owner").is_none());
+            }
+            _ => panic!("anonymous request was rejected"),
+        }
+
+        // Credentials that are presented must still be valid
+        let invalid = Request::builder()
+            .header(header::AUTHORIZATION, "Bearer not-a-jwt")
+            .body(Body::empty())
+            .unwrap();
+        assert!(matches!(
+            policy.process(invalid).await,
+            PolicyResult::Terminate(_)
+        ));
+    }
+}
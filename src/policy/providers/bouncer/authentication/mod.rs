@@ -1 +1,7 @@
+pub mod any_of;
 pub mod bearer;
+pub mod claims;
+pub mod client_cert;
+pub mod hmac;
+pub mod identity;
+pub mod jwt;
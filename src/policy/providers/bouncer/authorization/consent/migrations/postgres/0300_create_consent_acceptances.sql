@@ -0,0 +1,9 @@
+-- Terms accepted by each owner, for the @bouncer/authorization/consent/v1 policy
+CREATE TABLE IF NOT EXISTS bouncer_consent_acceptances (
+    owner TEXT NOT NULL,
+    -- Version of the terms that were accepted
+    version TEXT NOT NULL,
+    -- Unix timestamp in seconds
+    accepted_at BIGINT NOT NULL,
+    PRIMARY KEY (owner, version)
+);
@@ -0,0 +1,210 @@
+use crate::config::DatabasesConfig;
+use crate::database::migrations::Migration;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Schema for the PostgreSQL acceptance store
+///
+/// Versions share the table of the other Bouncer migrations, so they start at
+/// 300 to stay clear of the token, RBAC and metering ones.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[Migration {
+    version: 300,
+    name: "create_consent_acceptances",
+    sql: include_str!("migrations/postgres/0300_create_consent_acceptances.sql"),
+}];
+
+/// Where accepted terms are recorded
+#[async_trait]
+pub trait ConsentStore: Send + Sync + 'static {
+    /// Whether the owner has accepted this version of the terms
+    async fn has_accepted(&self, owner: &str, version: &str) -> Result<bool, DatabaseError>;
+
+    /// Record that the owner accepted this version of the terms
+    async fn accept(&self, owner: &str, version: &str, at: i64) -> Result<(), DatabaseError>;
+}
+
+/// Backend used to store acceptances
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentStoreBackend {
+    #[default]
+    Postgres,
+    Redis,
+}
+
+/// Acceptances stored in a Redis hash per owner at `{prefix}acceptances:{owner}`,
+/// mapping versions to when they were accepted
+#[cfg(feature = "redis")]
+pub struct RedisConsentStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisConsentStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    fn acceptances_key(&self, owner: &str) -> String {
+        format!("{}acceptances:{}", self.key_prefix, owner)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl ConsentStore for RedisConsentStore {
+    async fn has_accepted(&self, owner: &str, version: &str) -> Result<bool, DatabaseError> {
+        redis::cmd("HEXISTS")
+            .arg(self.acceptances_key(owner))
+            .arg(version)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn accept(&self, owner: &str, version: &str, at: i64) -> Result<(), DatabaseError> {
+        redis::cmd("HSETNX")
+            .arg(self.acceptances_key(owner))
+            .arg(version)
+            .arg(at)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+}
+
+/// Acceptances stored in the `bouncer_consent_acceptances` PostgreSQL table
+///
+/// Lookups are served from read replicas when configured.
+#[cfg(feature = "postgres")]
+pub struct PostgresConsentStore {
+    pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresConsentStore {
+    pub fn new(pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>) -> Self {
+        Self { pools }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl ConsentStore for PostgresConsentStore {
+    async fn has_accepted(&self, owner: &str, version: &str) -> Result<bool, DatabaseError> {
+        let row = self
+            .pools
+            .read(|pool| async move {
+                sqlx::query_as::<_, (i64,)>(
+                    "SELECT accepted_at FROM bouncer_consent_acceptances \
+                     WHERE owner = $1 AND version = $2",
+                )
+                .bind(owner)
+                .bind(version)
+                .fetch_optional(&*pool)
+                .await
+            })
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(row.is_some())
+    }
+
+    async fn accept(&self, owner: &str, version: &str, at: i64) -> Result<(), DatabaseError> {
+        // The first acceptance of a version is the one that counts
+        sqlx::query(
+            "INSERT INTO bouncer_consent_acceptances (owner, version, accepted_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (owner, version) DO NOTHING",
+        )
+        .bind(owner)
+        .bind(version)
+        .bind(at)
+        .execute(&*self.pools.primary())
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(())
+    }
+}
+
+/// Create the acceptance store selected in the policy config
+pub async fn create_consent_store(
+    backend: ConsentStoreBackend,
+    key_prefix: &str,
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn ConsentStore>, DatabaseError> {
+    let provider = match backend {
+        ConsentStoreBackend::Redis => "redis",
+        ConsentStoreBackend::Postgres => "postgres",
+    };
+    crate::database::validate_database_config(databases, provider)?;
+
+    match backend {
+        ConsentStoreBackend::Redis => create_redis_store(key_prefix, databases).await,
+        ConsentStoreBackend::Postgres => create_postgres_store(run_migrations, databases).await,
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    key_prefix: &str,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn ConsentStore>, DatabaseError> {
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisConsentStore::new(&client, key_prefix.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _key_prefix: &str,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn ConsentStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_store(
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn ConsentStore>, DatabaseError> {
+    let postgres_config = databases.postgres.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("PostgreSQL configuration is required".to_string())
+    })?;
+    let pools = crate::database::get_postgres_pools(postgres_config).await?;
+
+    if run_migrations {
+        crate::database::migrations::run_postgres_migrations(&pools.primary(), POSTGRES_MIGRATIONS)
+            .await?;
+    }
+
+    Ok(Arc::new(PostgresConsentStore::new(pools)))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_store(
+    _run_migrations: bool,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn ConsentStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string(),
+    ))
+}
@@ -0,0 +1,532 @@
+use super::store::{create_consent_store, ConsentStore, ConsentStoreBackend};
+use crate::admin::{is_admin, json_error, now_secs};
+use crate::cache::{BoundedCache, CacheLimits};
+use crate::config::SecretString;
+use crate::policy::denial::Denial;
+use crate::policy::matcher::{compile_all, RouteMatcher};
+use crate::policy::providers::bouncer::authentication::identity::Claims;
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{header, HeaderMap, HeaderValue, Request, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json,
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConsentConfig {
+    /// Version of the current terms, e.g. `2025-01`. Changing it requires
+    /// every caller to accept the new terms
+    pub terms_version: String,
+    /// Where callers accept the terms, pointed to when they haven't
+    pub accept_url: String,
+    /// Token claim holding the version of the terms the caller accepted
+    pub claim: Option<String>,
+    /// Look acceptances up by owner in a store: "postgres" or "redis"
+    pub store: Option<ConsentStoreBackend>,
+    /// Status for callers who haven't accepted the terms: 451 (default) or 403
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// How long an acceptance found in the store is remembered
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// Route patterns that don't require acceptance, e.g. the acceptance flow itself
+    #[serde(default)]
+    pub exempt: Vec<String>,
+    /// Token required by the acceptance routes, which are disabled without it
+    pub admin_token: Option<SecretString>,
+    /// Prefix for Redis keys
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    /// Create or update the PostgreSQL schema on startup
+    #[serde(default = "default_run_migrations")]
+    pub run_migrations: bool,
+}
+
+fn default_status() -> u16 {
+    451
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    60
+}
+
+fn default_key_prefix() -> String {
+    "bouncer:consent:".to_string()
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+/// Details of the denial returned to callers who haven't accepted the current
+/// terms
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsentRequired {
+    pub terms_version: String,
+    pub accept_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcceptRequest {
+    owner: String,
+    /// The current terms if not set
+    version: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct AcceptanceView {
+    owner: String,
+    version: String,
+    accepted: bool,
+}
+
+// Whether a caller accepted the current terms
+enum Acceptance {
+    Accepted,
+    NotAccepted,
+    // The store couldn't be reached
+    Unknown(String),
+}
+
+/// Rejects callers who haven't accepted the current terms of service or data
+/// processing terms, pointing them to where they can
+pub struct ConsentPolicy {
+    config: Arc<ConsentConfig>,
+    exempt: Vec<RouteMatcher>,
+    store: Option<Arc<dyn ConsentStore>>,
+    // Owners the store says accepted the current terms, and when it was asked.
+    // Refusals aren't cached, so callers get through as soon as they accept
+    accepted: Arc<BoundedCache<String, Instant>>,
+}
+
+impl ConsentPolicy {
+    fn claim_accepts(&self, request: &Request<Body>) -> bool {
+        let Some(claim) = &self.config.claim else {
+            return false;
+        };
+        let accepted = request
+            .extensions()
+            .get::<Claims>()
+            .and_then(|Claims(claims)| claims.get(claim));
+        match accepted {
+            Some(Value::String(version)) => *version == self.config.terms_version,
+            // Numbers are compared by their text, which comparing the
+            // `Value` itself doesn't do
+            #[allow(clippy::cmp_owned)]
+            Some(version) => version.to_string() == self.config.terms_version,
+            None => false,
+        }
+    }
+
+    // Takes what it needs from the request up front, since requests aren't
+    // `Sync` and can't be held across the store lookup
+    async fn acceptance(&self, claim_accepts: bool, owner: Option<&str>) -> Acceptance {
+        if claim_accepts {
+            return Acceptance::Accepted;
+        }
+
+        let (Some(store), Some(owner)) = (&self.store, owner) else {
+            return Acceptance::NotAccepted;
+        };
+
+        let ttl = Duration::from_secs(self.config.cache_ttl_secs);
+        if let Some(checked) = self.accepted.get(&owner.to_string()) {
+            if checked.elapsed() < ttl {
+                return Acceptance::Accepted;
+            }
+        }
+
+        match store.has_accepted(owner, &self.config.terms_version).await {
+            Ok(true) => {
+                self.accepted.insert(owner.to_string(), Instant::now());
+                Acceptance::Accepted
+            }
+            Ok(false) => Acceptance::NotAccepted,
+            Err(e) => Acceptance::Unknown(format!(
+                "Failed to look up terms acceptance for {}: {}",
+                owner, e
+            )),
+        }
+    }
+
+    fn consent_required(&self) -> PolicyResult {
+        let details = ConsentRequired {
+            terms_version: self.config.terms_version.clone(),
+            accept_url: self.config.accept_url.clone(),
+        };
+        let status = StatusCode::from_u16(self.config.status)
+            .unwrap_or(StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+
+        let mut response = Denial::new(
+            "terms_not_accepted",
+            "The current terms must be accepted before using this API",
+        )
+        .details(details)
+        .response(status);
+        let link = format!("<{}>; rel=\"terms-of-service\"", self.config.accept_url);
+        if let Ok(link) = HeaderValue::from_str(&link) {
+            response.headers_mut().insert(header::LINK, link);
+        }
+        PolicyResult::Terminate(response)
+    }
+}
+
+pub struct ConsentPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for ConsentPolicyFactory {
+    type PolicyType = ConsentPolicy;
+    type Config = ConsentConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authorization::consent::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let store = match config.store {
+            Some(backend) => {
+                let instance_config = crate::instance::config();
+                let db_config = match instance_config.as_deref() {
+                    Some(instance_config) => &instance_config.databases,
+                    None => return Err("Global configuration not initialized".to_string()),
+                };
+                let store = create_consent_store(
+                    backend,
+                    &config.key_prefix,
+                    config.run_migrations,
+                    db_config,
+                )
+                .await
+                .map_err(|e| e.to_string())?;
+                Some(store)
+            }
+            None => None,
+        };
+
+        Ok(ConsentPolicy {
+            exempt: compile_all(&config.exempt)?,
+            config: Arc::new(config),
+            store,
+            accepted: BoundedCache::new("consent_acceptances", CacheLimits::default()),
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.terms_version.is_empty() {
+            return Err("terms_version must not be empty".to_string());
+        }
+        Url::parse(&config.accept_url).map_err(|e| format!("Invalid accept_url: {}", e))?;
+        if config.claim.is_none() && config.store.is_none() {
+            return Err("At least one of claim or store must be configured".to_string());
+        }
+        if !matches!(config.status, 403 | 451) {
+            return Err("status must be 403 or 451".to_string());
+        }
+        if config.admin_token.is_some() && config.store.is_none() {
+            return Err("admin_token requires store".to_string());
+        }
+        if config
+            .admin_token
+            .as_ref()
+            .is_some_and(SecretString::is_empty)
+        {
+            return Err("admin_token must not be empty".to_string());
+        }
+        compile_all(&config.exempt)?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for ConsentPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authorization"
+    }
+
+    fn name(&self) -> &'static str {
+        "consent"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        let (Some(store), Some(admin_token)) =
+            (self.store.clone(), self.config.admin_token.clone())
+        else {
+            return vec![];
+        };
+        let admin_token: Arc<str> = Arc::from(admin_token.expose());
+
+        // Called by the acceptance flow once a caller accepts
+        let accept = {
+            let (store, admin_token) = (store.clone(), admin_token.clone());
+            let (config, accepted) = (self.config.clone(), self.accepted.clone());
+            move |headers: HeaderMap, Json(request): Json<AcceptRequest>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                if request.owner.is_empty() {
+                    return json_error(StatusCode::BAD_REQUEST, "owner must not be empty");
+                }
+
+                let version = request
+                    .version
+                    .unwrap_or_else(|| config.terms_version.clone());
+                if let Err(e) = store.accept(&request.owner, &version, now_secs()).await {
+                    return json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string());
+                }
+                if version == config.terms_version {
+                    accepted.insert(request.owner.clone(), Instant::now());
+                }
+
+                Json(AcceptanceView {
+                    owner: request.owner,
+                    version,
+                    accepted: true,
+                })
+                .into_response()
+            }
+        };
+
+        let check = {
+            let config = self.config.clone();
+            move |headers: HeaderMap, Path(owner): Path<String>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match store.has_accepted(&owner, &config.terms_version).await {
+                    Ok(accepted) => Json(AcceptanceView {
+                        owner,
+                        version: config.terms_version.clone(),
+                        accepted,
+                    })
+                    .into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        vec![
+            RouteRegistration {
+                relative_path: "acceptances".to_string(),
+                handler: post(accept),
+            },
+            RouteRegistration {
+                relative_path: "acceptances/{owner}".to_string(),
+                handler: get(check),
+            },
+        ]
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let path = request.uri().path();
+        if self
+            .exempt
+            .iter()
+            .any(|matcher| matcher.matches(request.method(), path))
+        {
+            return PolicyResult::Continue(request);
+        }
+
+        let owner = request
+            .headers()
+            .get("x-bouncer-owner")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        match self
+            .acceptance(self.claim_accepts(&request), owner.as_deref())
+            .await
+        {
+            Acceptance::Accepted => PolicyResult::Continue(request),
+            Acceptance::NotAccepted => {
+                tracing::debug!("Rejected request to {}: terms not accepted", path);
+                self.consent_required()
+            }
+            Acceptance::Unknown(error) => PolicyResult::Error(request, error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DatabaseError;
+    use std::collections::HashSet;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryConsentStore {
+        acceptances: Mutex<HashSet<(String, String)>>,
+    }
+
+    #[async_trait]
+    impl ConsentStore for MemoryConsentStore {
+        async fn has_accepted(&self, owner: &str, version: &str) -> Result<bool, DatabaseError> {
+            let key = (owner.to_string(), version.to_string());
+            Ok(self.acceptances.lock().unwrap().contains(&key))
+        }
+
+        async fn accept(&self, owner: &str, version: &str, _at: i64) -> Result<(), DatabaseError> {
+            let key = (owner.to_string(), version.to_string());
+            self.acceptances.lock().unwrap().insert(key);
+            Ok(())
+        }
+    }
+
+    fn config() -> ConsentConfig {
+        ConsentConfig {
+            terms_version: "2025-01".to_string(),
+            accept_url: "https://example.com/terms".to_string(),
+            claim: Some("terms".to_string()),
+            store: None,
+            status: default_status(),
+            cache_ttl_secs: default_cache_ttl_secs(),
+            exempt: vec!["/terms/*".to_string()],
+            admin_token: None,
+            key_prefix: default_key_prefix(),
+            run_migrations: false,
+        }
+    }
+
+    fn status(result: PolicyResult) -> StatusCode {
+        match result {
+            PolicyResult::Continue(_) => StatusCode::OK,
+            PolicyResult::Terminate(response) => response.status(),
+            PolicyResult::Error(_, error) => panic!("policy failed: {}", error),
+            PolicyResult::Redirect(location, _) => panic!("unexpected redirect to {}", location),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_consent() {
+        let store = Arc::new(MemoryConsentStore::default());
+        let mut policy = ConsentPolicyFactory::new(config()).await.unwrap();
+        policy.store = Some(store.clone());
+
+        let request = |owner: &str, accepted: Option<&str>| {
+            let mut request = Request::get("/api/orders")
+                .header("x-bouncer-owner", owner)
+                .body(Body::empty())
+                .unwrap();
+            if let Some(version) = accepted {
+                let claims = serde_json::json!({ "terms": version });
+                let claims = claims.as_object().unwrap().clone();
+                request.extensions_mut().insert(Claims(claims));
+            }
+            request
+        };
+
+        // Accepted in the token
+        let result = policy.process(request("alice", Some("2025-01"))).await;
+        assert_eq!(status(result), StatusCode::OK);
+
+        // An older version of the terms doesn't count
+        let result = policy.process(request("alice", Some("2024-06"))).await;
+        assert_eq!(status(result), StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+
+        // Accepted in the store
+        store.accept("bob", "2025-01", 0).await.unwrap();
+        let result = policy.process(request("bob", None)).await;
+        assert_eq!(status(result), StatusCode::OK);
+
+        let exempt = Request::get("/terms/accept").body(Body::empty()).unwrap();
+        assert_eq!(status(policy.process(exempt).await), StatusCode::OK);
+
+        let PolicyResult::Terminate(response) = policy.process(request("carol", None)).await else {
+            panic!("expected a rejection");
+        };
+        assert_eq!(
+            response.headers()[header::LINK],
+            "<https://example.com/terms>; rel=\"terms-of-service\""
+        );
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "terms_not_accepted");
+        assert_eq!(body["terms_version"], "2025-01");
+        assert_eq!(body["accept_url"], "https://example.com/terms");
+    }
+
+    #[tokio::test]
+    async fn test_numeric_claims() {
+        let mut policy = ConsentPolicyFactory::new(ConsentConfig {
+            terms_version: "3".to_string(),
+            ..config()
+        })
+        .await
+        .unwrap();
+        policy.store = Some(Arc::new(MemoryConsentStore::default()));
+        let policy = Arc::new(policy);
+
+        let request = |accepted: Value| {
+            let mut request = Request::get("/api/orders")
+                .header("x-bouncer-owner", "alice")
+                .body(Body::empty())
+                .unwrap();
+            let claims = serde_json::json!({ "terms": accepted });
+            let claims = claims.as_object().unwrap().clone();
+            request.extensions_mut().insert(Claims(claims));
+            request
+        };
+
+        // Runs on another task, which needs the store lookup to be `Send`
+        let process = |request| {
+            let policy = Arc::clone(&policy);
+            async move {
+                tokio::spawn(async move { status(policy.process(request).await) })
+                    .await
+                    .unwrap()
+            }
+        };
+        assert_eq!(process(request(3.into())).await, StatusCode::OK);
+        assert_eq!(process(request("3".into())).await, StatusCode::OK);
+        assert_eq!(
+            process(request(2.into())).await,
+            StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS
+        );
+    }
+
+    #[test]
+    fn test_admin_token_redacted() {
+        let config = ConsentConfig {
+            admin_token: Some("consent-admin-secret".into()),
+            ..config()
+        };
+        assert!(!format!("{:?}", config).contains("consent-admin-secret"));
+        let serialized = serde_json::to_string(&config).unwrap();
+        assert!(!serialized.contains("consent-admin-secret"));
+    }
+}
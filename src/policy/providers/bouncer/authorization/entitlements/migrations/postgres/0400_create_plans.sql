@@ -0,0 +1,7 @@
+-- Each owner's plan, for the @bouncer/authorization/entitlements/v1 policy
+CREATE TABLE IF NOT EXISTS bouncer_plans (
+    owner TEXT PRIMARY KEY,
+    plan TEXT NOT NULL,
+    -- Unix timestamp in seconds
+    updated_at BIGINT NOT NULL
+);
@@ -0,0 +1,233 @@
+use crate::config::DatabasesConfig;
+use crate::database::migrations::Migration;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Schema for the PostgreSQL plan store
+///
+/// Versions share the table of the other Bouncer migrations, so they start at
+/// 400 to stay clear of the other stores'.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[Migration {
+    version: 400,
+    name: "create_plans",
+    sql: include_str!("migrations/postgres/0400_create_plans.sql"),
+}];
+
+/// Where each owner's plan is stored, typically kept up to date by billing
+#[async_trait]
+pub trait PlanStore: Send + Sync + 'static {
+    /// The owner's plan, if one is recorded
+    async fn plan(&self, owner: &str) -> Result<Option<String>, DatabaseError>;
+
+    /// Set the owner's plan
+    async fn set_plan(&self, owner: &str, plan: &str) -> Result<(), DatabaseError>;
+
+    /// Remove the owner's plan. Returns false if it had none
+    async fn remove_plan(&self, owner: &str) -> Result<bool, DatabaseError>;
+}
+
+/// Backend used to store plans
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanStoreBackend {
+    #[default]
+    Postgres,
+    Redis,
+}
+
+/// Plans stored in a Redis hash at `{prefix}plans`, mapping owners to plans
+#[cfg(feature = "redis")]
+pub struct RedisPlanStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisPlanStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    fn plans_key(&self) -> String {
+        format!("{}plans", self.key_prefix)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl PlanStore for RedisPlanStore {
+    async fn plan(&self, owner: &str) -> Result<Option<String>, DatabaseError> {
+        redis::cmd("HGET")
+            .arg(self.plans_key())
+            .arg(owner)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn set_plan(&self, owner: &str, plan: &str) -> Result<(), DatabaseError> {
+        redis::cmd("HSET")
+            .arg(self.plans_key())
+            .arg(owner)
+            .arg(plan)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn remove_plan(&self, owner: &str) -> Result<bool, DatabaseError> {
+        let removed: i64 = redis::cmd("HDEL")
+            .arg(self.plans_key())
+            .arg(owner)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(removed > 0)
+    }
+}
+
+/// Plans stored in the `bouncer_plans` PostgreSQL table
+///
+/// Lookups are served from read replicas when configured.
+#[cfg(feature = "postgres")]
+pub struct PostgresPlanStore {
+    pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresPlanStore {
+    pub fn new(pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>) -> Self {
+        Self { pools }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl PlanStore for PostgresPlanStore {
+    async fn plan(&self, owner: &str) -> Result<Option<String>, DatabaseError> {
+        let row = self
+            .pools
+            .read(|pool| async move {
+                sqlx::query_as::<_, (String,)>("SELECT plan FROM bouncer_plans WHERE owner = $1")
+                    .bind(owner)
+                    .fetch_optional(&*pool)
+                    .await
+            })
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(row.map(|(plan,)| plan))
+    }
+
+    async fn set_plan(&self, owner: &str, plan: &str) -> Result<(), DatabaseError> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        sqlx::query(
+            "INSERT INTO bouncer_plans (owner, plan, updated_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (owner) DO UPDATE SET plan = EXCLUDED.plan, updated_at = EXCLUDED.updated_at",
+        )
+        .bind(owner)
+        .bind(plan)
+        .bind(now)
+        .execute(&*self.pools.primary())
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn remove_plan(&self, owner: &str) -> Result<bool, DatabaseError> {
+        let result = sqlx::query("DELETE FROM bouncer_plans WHERE owner = $1")
+            .bind(owner)
+            .execute(&*self.pools.primary())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// Create the plan store selected in the policy config
+pub async fn create_plan_store(
+    backend: PlanStoreBackend,
+    key_prefix: &str,
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn PlanStore>, DatabaseError> {
+    let provider = match backend {
+        PlanStoreBackend::Redis => "redis",
+        PlanStoreBackend::Postgres => "postgres",
+    };
+    crate::database::validate_database_config(databases, provider)?;
+
+    match backend {
+        PlanStoreBackend::Redis => create_redis_store(key_prefix, databases).await,
+        PlanStoreBackend::Postgres => create_postgres_store(run_migrations, databases).await,
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    key_prefix: &str,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn PlanStore>, DatabaseError> {
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisPlanStore::new(&client, key_prefix.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _key_prefix: &str,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn PlanStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_store(
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn PlanStore>, DatabaseError> {
+    let postgres_config = databases.postgres.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("PostgreSQL configuration is required".to_string())
+    })?;
+    let pools = crate::database::get_postgres_pools(postgres_config).await?;
+
+    if run_migrations {
+        crate::database::migrations::run_postgres_migrations(&pools.primary(), POSTGRES_MIGRATIONS)
+            .await?;
+    }
+
+    Ok(Arc::new(PostgresPlanStore::new(pools)))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_store(
+    _run_migrations: bool,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn PlanStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string(),
+    ))
+}
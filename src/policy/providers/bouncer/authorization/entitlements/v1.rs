@@ -0,0 +1,488 @@
+use super::store::{create_plan_store, PlanStore, PlanStoreBackend};
+use crate::admin::{is_admin, json_error};
+use crate::cache::{BoundedCache, CacheLimits};
+use crate::config::SecretString;
+use crate::policy::denial::Denial;
+use crate::policy::matcher::{compile_all, RouteMatcher};
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EntitlementsConfig {
+    /// Route patterns of each feature. Routes in no feature are open to every plan
+    pub features: BTreeMap<String, Vec<String>>,
+    /// Features included in each plan
+    pub plans: BTreeMap<String, Vec<String>>,
+    /// Plan of owners with none recorded in the store
+    pub default_plan: Option<String>,
+    /// Where callers can upgrade their plan, returned when they aren't entitled
+    pub upgrade_url: Option<String>,
+    /// Status for callers whose plan doesn't include a feature: 402 (default) or 403
+    #[serde(default = "default_status")]
+    pub status: u16,
+    /// Where plans are stored: "postgres" (default) or "redis"
+    #[serde(default)]
+    pub store: PlanStoreBackend,
+    /// How long an owner's plan is remembered after looking it up
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// Token required by the plan management routes, which are disabled without it
+    pub admin_token: Option<SecretString>,
+    /// Prefix for Redis keys
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    /// Create or update the PostgreSQL schema on startup
+    #[serde(default = "default_run_migrations")]
+    pub run_migrations: bool,
+}
+
+fn default_status() -> u16 {
+    402
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    60
+}
+
+fn default_key_prefix() -> String {
+    "bouncer:entitlements:".to_string()
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+/// Details of the denial returned to callers whose plan doesn't include the
+/// feature they requested
+#[derive(Debug, Clone, Serialize)]
+pub struct UpgradeRequired {
+    pub feature: String,
+    pub plan: Option<String>,
+    /// Plans that include the feature
+    pub upgrade_plans: Vec<String>,
+    pub upgrade_url: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PlanView {
+    owner: String,
+    plan: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SetPlanRequest {
+    plan: String,
+}
+
+// A feature with its compiled route patterns
+struct Feature {
+    name: String,
+    matchers: Vec<RouteMatcher>,
+}
+
+/// Checks that the caller's plan includes the feature a route belongs to
+pub struct EntitlementsPolicy {
+    config: Arc<EntitlementsConfig>,
+    features: Vec<Feature>,
+    store: Arc<dyn PlanStore>,
+    // Owners' plans and when they were looked up, including owners with none
+    plans: Arc<BoundedCache<String, (Option<String>, Instant)>>,
+}
+
+impl EntitlementsPolicy {
+    // The owner's plan, falling back to the default plan
+    async fn plan_for(&self, owner: &str) -> Result<Option<String>, String> {
+        let ttl = Duration::from_secs(self.config.cache_ttl_secs);
+        let plan = match self.plans.get(&owner.to_string()) {
+            Some((plan, checked)) if checked.elapsed() < ttl => plan,
+            _ => {
+                let plan = self.store.plan(owner).await.map_err(|e| e.to_string())?;
+                self.plans
+                    .insert(owner.to_string(), (plan.clone(), Instant::now()));
+                plan
+            }
+        };
+        Ok(plan.or_else(|| self.config.default_plan.clone()))
+    }
+
+    fn includes(&self, plan: Option<&str>, feature: &str) -> bool {
+        plan.and_then(|plan| self.config.plans.get(plan))
+            .is_some_and(|features| features.iter().any(|included| included == feature))
+    }
+
+    fn upgrade_required(&self, feature: &str, plan: Option<String>) -> PolicyResult {
+        let upgrade_plans = self
+            .config
+            .plans
+            .iter()
+            .filter(|(_, features)| features.iter().any(|included| included == feature))
+            .map(|(plan, _)| plan.clone())
+            .collect();
+        let details = UpgradeRequired {
+            feature: feature.to_string(),
+            plan,
+            upgrade_plans,
+            upgrade_url: self.config.upgrade_url.clone(),
+        };
+        let status =
+            StatusCode::from_u16(self.config.status).unwrap_or(StatusCode::PAYMENT_REQUIRED);
+
+        PolicyResult::Terminate(
+            Denial::new("not_entitled", "Your plan doesn't include this feature")
+                .details(details)
+                .response(status),
+        )
+    }
+}
+
+pub struct EntitlementsPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for EntitlementsPolicyFactory {
+    type PolicyType = EntitlementsPolicy;
+    type Config = EntitlementsConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authorization::entitlements::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let instance_config = crate::instance::config();
+        let db_config = match instance_config.as_deref() {
+            Some(instance_config) => &instance_config.databases,
+            None => return Err("Global configuration not initialized".to_string()),
+        };
+        let store = create_plan_store(
+            config.store,
+            &config.key_prefix,
+            config.run_migrations,
+            db_config,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        Ok(EntitlementsPolicy {
+            features: compile_features(&config)?,
+            config: Arc::new(config),
+            store,
+            plans: BoundedCache::new("entitlement_plans", CacheLimits::default()),
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.features.is_empty() {
+            return Err("At least one feature must be configured".to_string());
+        }
+        compile_features(config)?;
+
+        for (plan, features) in &config.plans {
+            if let Some(feature) = features.iter().find(|f| !config.features.contains_key(*f)) {
+                return Err(format!("Plan '{}' has unknown feature '{}'", plan, feature));
+            }
+        }
+        if let Some(plan) = &config.default_plan {
+            if !config.plans.contains_key(plan) {
+                return Err(format!("Unknown default_plan '{}'", plan));
+            }
+        }
+        if !matches!(config.status, 402 | 403) {
+            return Err("status must be 402 or 403".to_string());
+        }
+        if config
+            .admin_token
+            .as_ref()
+            .is_some_and(SecretString::is_empty)
+        {
+            return Err("admin_token must not be empty".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+fn compile_features(config: &EntitlementsConfig) -> Result<Vec<Feature>, String> {
+    config
+        .features
+        .iter()
+        .map(|(name, patterns)| {
+            if patterns.is_empty() {
+                return Err(format!("Feature '{}' has no route patterns", name));
+            }
+            Ok(Feature {
+                name: name.clone(),
+                matchers: compile_all(patterns)?,
+            })
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Policy for EntitlementsPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authorization"
+    }
+
+    fn name(&self) -> &'static str {
+        "entitlements"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        let Some(admin_token) = self.config.admin_token.clone() else {
+            return vec![];
+        };
+        let admin_token: Arc<str> = Arc::from(admin_token.expose());
+
+        let show = {
+            let (store, admin_token) = (self.store.clone(), admin_token.clone());
+            move |headers: HeaderMap, Path(owner): Path<String>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match store.plan(&owner).await {
+                    Ok(plan) => Json(PlanView { owner, plan }).into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        // Called by billing when an owner changes plan
+        let set = {
+            let (store, admin_token) = (self.store.clone(), admin_token.clone());
+            let (config, plans) = (self.config.clone(), self.plans.clone());
+            move |headers: HeaderMap,
+                  Path(owner): Path<String>,
+                  Json(request): Json<SetPlanRequest>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                if !config.plans.contains_key(&request.plan) {
+                    return json_error(
+                        StatusCode::BAD_REQUEST,
+                        format!("Unknown plan '{}'", request.plan),
+                    );
+                }
+
+                match store.set_plan(&owner, &request.plan).await {
+                    Ok(()) => {
+                        plans.remove(&owner);
+                        Json(PlanView {
+                            owner,
+                            plan: Some(request.plan),
+                        })
+                        .into_response()
+                    }
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let remove = {
+            let (store, plans) = (self.store.clone(), self.plans.clone());
+            move |headers: HeaderMap, Path(owner): Path<String>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match store.remove_plan(&owner).await {
+                    Ok(true) => {
+                        plans.remove(&owner);
+                        StatusCode::NO_CONTENT.into_response()
+                    }
+                    Ok(false) => json_error(StatusCode::NOT_FOUND, "Owner has no plan"),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        vec![RouteRegistration {
+            relative_path: "plans/{owner}".to_string(),
+            handler: get(show).put(set).delete(remove),
+        }]
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let path = request.uri().path();
+        let required: BTreeSet<&str> = self
+            .features
+            .iter()
+            .filter(|feature| {
+                feature
+                    .matchers
+                    .iter()
+                    .any(|matcher| matcher.matches(request.method(), path))
+            })
+            .map(|feature| feature.name.as_str())
+            .collect();
+        if required.is_empty() {
+            return PolicyResult::Continue(request);
+        }
+
+        let owner = request
+            .headers()
+            .get("x-bouncer-owner")
+            .and_then(|value| value.to_str().ok());
+        let plan = match owner {
+            Some(owner) => match self.plan_for(owner).await {
+                Ok(plan) => plan,
+                Err(e) => {
+                    let error = format!("Failed to look up the plan of {}: {}", owner, e);
+                    return PolicyResult::Error(request, error);
+                }
+            },
+            None => self.config.default_plan.clone(),
+        };
+
+        // Routes in several features need all of them
+        if let Some(feature) = required
+            .into_iter()
+            .find(|feature| !self.includes(plan.as_deref(), feature))
+        {
+            tracing::debug!(
+                "Rejected request to {}: plan {:?} doesn't include {}",
+                path,
+                plan,
+                feature
+            );
+            return self.upgrade_required(feature, plan);
+        }
+
+        PolicyResult::Continue(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::DatabaseError;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryPlanStore {
+        plans: Mutex<HashMap<String, String>>,
+    }
+
+    #[async_trait]
+    impl PlanStore for MemoryPlanStore {
+        async fn plan(&self, owner: &str) -> Result<Option<String>, DatabaseError> {
+            Ok(self.plans.lock().unwrap().get(owner).cloned())
+        }
+
+        async fn set_plan(&self, owner: &str, plan: &str) -> Result<(), DatabaseError> {
+            let mut plans = self.plans.lock().unwrap();
+            plans.insert(owner.to_string(), plan.to_string());
+            Ok(())
+        }
+
+        async fn remove_plan(&self, owner: &str) -> Result<bool, DatabaseError> {
+            Ok(self.plans.lock().unwrap().remove(owner).is_some())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_entitlements() {
+        let strings = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let config = EntitlementsConfig {
+            features: BTreeMap::from([
+                ("analytics".to_string(), strings(&["/analytics/*"])),
+                ("exports".to_string(), strings(&["/exports/*"])),
+            ]),
+            plans: BTreeMap::from([
+                ("free".to_string(), vec![]),
+                ("pro".to_string(), strings(&["analytics"])),
+                ("enterprise".to_string(), strings(&["analytics", "exports"])),
+            ]),
+            default_plan: Some("free".to_string()),
+            upgrade_url: Some("https://example.com/billing".to_string()),
+            status: default_status(),
+            store: PlanStoreBackend::default(),
+            cache_ttl_secs: default_cache_ttl_secs(),
+            admin_token: None,
+            key_prefix: default_key_prefix(),
+            run_migrations: false,
+        };
+        EntitlementsPolicyFactory::validate_config(&config).unwrap();
+
+        let store = Arc::new(MemoryPlanStore::default());
+        store.set_plan("alice", "pro").await.unwrap();
+        let policy = EntitlementsPolicy {
+            features: compile_features(&config).unwrap(),
+            config: Arc::new(config),
+            store,
+            plans: BoundedCache::new("test_entitlement_plans", CacheLimits::default()),
+        };
+
+        let request = |owner: &str, path: &str| {
+            Request::get(path)
+                .header("x-bouncer-owner", owner)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        // Routes in no feature are open to every plan
+        assert!(matches!(
+            policy.process(request("bob", "/orders")).await,
+            PolicyResult::Continue(_)
+        ));
+        assert!(matches!(
+            policy.process(request("alice", "/analytics/daily")).await,
+            PolicyResult::Continue(_)
+        ));
+
+        let PolicyResult::Terminate(response) =
+            policy.process(request("alice", "/exports/csv")).await
+        else {
+            panic!("expected a rejection");
+        };
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["feature"], "exports");
+        assert_eq!(body["plan"], "pro");
+        assert_eq!(body["upgrade_plans"], serde_json::json!(["enterprise"]));
+
+        // Owners without a plan get the default one
+        assert!(matches!(
+            policy.process(request("bob", "/analytics/daily")).await,
+            PolicyResult::Terminate(_)
+        ));
+    }
+}
@@ -0,0 +1,552 @@
+use crate::admin::{is_admin, json_error};
+use crate::cache::{BoundedCache, CacheLimits};
+use crate::config::SecretString;
+use crate::policy::context::{context, PolicyContext};
+use crate::policy::denial::Denial;
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::{Body, Bytes},
+    http::{HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Json,
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExternalAuthzConfig {
+    /// Decision endpoint, e.g. `http://opa:8181/v1/data/http/authz` for OPA
+    pub url: String,
+    /// Bearer token sent to the decision service
+    pub token: Option<SecretString>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Request headers sent to the decision service, e.g. `x-tenant-id`
+    #[serde(default)]
+    pub headers: Vec<String>,
+    /// How long decisions are reused for the same input. 0 disables caching
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// Token required by the invalidation route, which is disabled without it
+    pub admin_token: Option<SecretString>,
+}
+
+fn default_timeout_ms() -> u64 {
+    250
+}
+
+fn default_cache_ttl_secs() -> u64 {
+    30
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Decision {
+    Allow,
+    Deny {
+        status: StatusCode,
+        reason: Option<String>,
+    },
+}
+
+impl Decision {
+    // OPA wraps the decision in `result`, and leaves it out when no rule
+    // matched. Other services may return the decision itself
+    fn parse(response: &Value) -> Result<Self, String> {
+        let decision = response.get("result").unwrap_or(response);
+        let deny = |status: Option<u64>, reason: Option<&str>| Self::Deny {
+            status: status
+                .and_then(|status| u16::try_from(status).ok())
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .filter(StatusCode::is_client_error)
+                .unwrap_or(StatusCode::FORBIDDEN),
+            reason: reason.map(str::to_string),
+        };
+
+        match decision {
+            Value::Bool(true) => Ok(Self::Allow),
+            Value::Bool(false) => Ok(deny(None, None)),
+            Value::Object(fields) => match fields.get("allow") {
+                Some(Value::Bool(true)) => Ok(Self::Allow),
+                Some(Value::Bool(false)) | None => Ok(deny(
+                    fields.get("status").and_then(Value::as_u64),
+                    fields.get("reason").and_then(Value::as_str),
+                )),
+                Some(_) => Err("The decision's allow field isn't a boolean".to_string()),
+            },
+            _ => Err(format!("Unexpected decision: {}", decision)),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct CachedDecision {
+    decision: Decision,
+    owner: Option<String>,
+    expires: Instant,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct InvalidateRequest {
+    /// Only drop the decisions for this owner
+    owner: Option<String>,
+}
+
+/// Asks an external decision service, such as OPA, whether to let requests
+/// through
+///
+/// Decisions are cached by their input: the identity, method, path, query and
+/// configured headers, so the service is only asked again when one of those
+/// changes or the entry expires.
+pub struct ExternalAuthzPolicy {
+    config: Arc<ExternalAuthzConfig>,
+    client: reqwest::Client,
+    decisions: Arc<BoundedCache<String, CachedDecision>>,
+}
+
+impl ExternalAuthzPolicy {
+    // What the decision service decides on, as OPA's `input`
+    fn input(&self, request: &Request<Body>) -> Value {
+        let headers: BTreeMap<&str, &str> = self
+            .config
+            .headers
+            .iter()
+            .filter_map(|name| {
+                let value = request.headers().get(name)?.to_str().ok()?;
+                Some((name.as_str(), value))
+            })
+            .collect();
+        let identity = context(request)
+            .and_then(PolicyContext::identity)
+            .map(|identity| {
+                json!({
+                    "role": identity.role,
+                    "owner": identity.owner,
+                    "scopes": identity.scopes,
+                })
+            });
+
+        json!({
+            "method": request.method().as_str(),
+            "path": request.uri().path(),
+            "query": request.uri().query(),
+            "headers": headers,
+            "identity": identity,
+        })
+    }
+
+    async fn decide(&self, input: &Value) -> Result<Decision, String> {
+        let mut call = self
+            .client
+            .post(&self.config.url)
+            .timeout(Duration::from_millis(self.config.timeout_ms))
+            .json(&json!({ "input": input }));
+        if let Some(token) = &self.config.token {
+            call = call.bearer_auth(token.expose());
+        }
+
+        let response = call
+            .send()
+            .await
+            .map_err(|e| format!("Authorization service request failed: {}", e))?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "Authorization service returned {}",
+                response.status()
+            ));
+        }
+        let response: Value = response
+            .json()
+            .await
+            .map_err(|e| format!("Invalid authorization service response: {}", e))?;
+        Decision::parse(&response)
+    }
+
+    fn cached(&self, key: &String) -> Option<Decision> {
+        let cached = self.decisions.get(key)?;
+        if cached.expires <= Instant::now() {
+            self.decisions.remove(key);
+            return None;
+        }
+        Some(cached.decision)
+    }
+}
+
+pub struct ExternalAuthzPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for ExternalAuthzPolicyFactory {
+    type PolicyType = ExternalAuthzPolicy;
+    type Config = ExternalAuthzConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authorization::external::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(mut config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        for header in &mut config.headers {
+            header.make_ascii_lowercase();
+        }
+
+        Ok(ExternalAuthzPolicy {
+            config: Arc::new(config),
+            client: reqwest::Client::new(),
+            decisions: BoundedCache::new("external_authz_decisions", CacheLimits::default()),
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        let url = Url::parse(&config.url).map_err(|e| format!("Invalid url: {}", e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err("url must be http or https".to_string());
+        }
+        if config.timeout_ms == 0 {
+            return Err("timeout_ms must be greater than 0".to_string());
+        }
+        if config.headers.iter().any(|header| header.trim().is_empty()) {
+            return Err("headers must not be empty".to_string());
+        }
+        if config
+            .admin_token
+            .as_ref()
+            .is_some_and(SecretString::is_empty)
+        {
+            return Err("admin_token must not be empty".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for ExternalAuthzPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authorization"
+    }
+
+    fn name(&self) -> &'static str {
+        "external"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        let Some(admin_token) = self.config.admin_token.clone() else {
+            return vec![];
+        };
+        let admin_token: Arc<str> = Arc::from(admin_token.expose());
+
+        // Called by operators, or as a webhook by whatever deploys new
+        // policies to the decision service
+        let invalidate = {
+            let decisions = self.decisions.clone();
+            move |headers: HeaderMap, body: Bytes| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                let request: InvalidateRequest = if body.is_empty() {
+                    InvalidateRequest::default()
+                } else {
+                    match serde_json::from_slice(&body) {
+                        Ok(request) => request,
+                        Err(e) => return json_error(StatusCode::BAD_REQUEST, e.to_string()),
+                    }
+                };
+
+                let invalidated = match &request.owner {
+                    Some(owner) => {
+                        decisions.retain(|_, cached| cached.owner.as_ref() != Some(owner))
+                    }
+                    None => {
+                        let count = decisions.len();
+                        decisions.clear();
+                        count
+                    }
+                };
+                Json(json!({ "invalidated": invalidated })).into_response()
+            }
+        };
+
+        vec![RouteRegistration {
+            relative_path: "decisions/invalidate".to_string(),
+            handler: post(invalidate),
+        }]
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let input = self.input(&request);
+        let key = input.to_string();
+
+        let decision = match self.cached(&key) {
+            Some(decision) => decision,
+            None => match self.decide(&input).await {
+                Ok(decision) => {
+                    if self.config.cache_ttl_secs > 0 {
+                        let owner = input["identity"]["owner"].as_str().map(str::to_string);
+                        let ttl = Duration::from_secs(self.config.cache_ttl_secs);
+                        self.decisions.insert(
+                            key,
+                            CachedDecision {
+                                decision: decision.clone(),
+                                owner,
+                                expires: Instant::now() + ttl,
+                            },
+                        );
+                    }
+                    decision
+                }
+                Err(error) => return PolicyResult::Error(request, error),
+            },
+        };
+
+        match decision {
+            Decision::Allow => PolicyResult::Continue(request),
+            Decision::Deny { status, reason } => {
+                tracing::debug!(
+                    "Authorization service denied request to {}",
+                    request.uri().path()
+                );
+                let message = reason.unwrap_or_else(|| "Access denied".to_string());
+                PolicyResult::Terminate(Denial::new("access_denied", message).response(status))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::providers::bouncer::authentication::identity::Identity;
+    use axum::{extract::State, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    // An OPA-like service allowing admins, and `/slow` only after a while
+    async fn decision_service() -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let decide = |State(calls): State<Arc<AtomicUsize>>, Json(body): Json<Value>| async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let input = &body["input"];
+            if input["path"] == "/slow" {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+            if input["identity"]["role"] == "admin" {
+                Json(json!({ "result": true }))
+            } else {
+                Json(json!({ "result": { "allow": false, "reason": "Admins only" } }))
+            }
+        };
+        let app = Router::new()
+            .route("/v1/data/authz", post(decide))
+            .with_state(calls.clone());
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}/v1/data/authz", addr), calls)
+    }
+
+    fn config(url: &str) -> ExternalAuthzConfig {
+        ExternalAuthzConfig {
+            url: url.to_string(),
+            token: None,
+            timeout_ms: 200,
+            headers: vec!["X-Tenant".to_string()],
+            cache_ttl_secs: default_cache_ttl_secs(),
+            admin_token: Some("authz-admin-secret".into()),
+        }
+    }
+
+    fn request(path: &str, role: &str, owner: &str) -> Request<Body> {
+        let mut request = Request::get(path)
+            .header("x-tenant", "acme")
+            .body(Body::empty())
+            .unwrap();
+        let mut identity = Identity::new(role);
+        identity.owner = Some(owner.to_string());
+        identity.apply(&mut request);
+        request
+    }
+
+    fn status(result: PolicyResult) -> StatusCode {
+        match result {
+            PolicyResult::Continue(_) => StatusCode::OK,
+            PolicyResult::Terminate(response) => response.status(),
+            PolicyResult::Error(_, _) => StatusCode::SERVICE_UNAVAILABLE,
+            PolicyResult::Redirect(location, _) => panic!("unexpected redirect to {}", location),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allow_and_deny() {
+        let (url, _) = decision_service().await;
+        let policy = ExternalAuthzPolicyFactory::new(config(&url)).await.unwrap();
+
+        let result = policy.process(request("/orders", "admin", "alice")).await;
+        assert_eq!(status(result), StatusCode::OK);
+
+        let PolicyResult::Terminate(response) =
+            policy.process(request("/orders", "user", "bob")).await
+        else {
+            panic!("expected a denial");
+        };
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "access_denied");
+        assert_eq!(body["message"], "Admins only");
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let (url, _) = decision_service().await;
+        let policy = ExternalAuthzPolicyFactory::new(config(&url)).await.unwrap();
+
+        let started = Instant::now();
+        let result = policy.process(request("/slow", "admin", "alice")).await;
+        assert!(matches!(result, PolicyResult::Error(_, _)));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_cache() {
+        let (url, calls) = decision_service().await;
+        let policy = ExternalAuthzPolicyFactory::new(config(&url)).await.unwrap();
+
+        for _ in 0..3 {
+            let result = policy.process(request("/orders", "admin", "alice")).await;
+            assert_eq!(status(result), StatusCode::OK);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Any attribute the service sees is part of the key
+        policy.process(request("/orders", "admin", "carol")).await;
+        policy
+            .process(request("/orders?all=1", "admin", "alice"))
+            .await;
+        let mut other_tenant = request("/orders", "admin", "alice");
+        other_tenant
+            .headers_mut()
+            .insert("x-tenant", "globex".parse().unwrap());
+        policy.process(other_tenant).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        // Expired decisions are asked for again
+        let key = policy
+            .input(&request("/orders", "admin", "alice"))
+            .to_string();
+        let mut cached = policy.decisions.get(&key).unwrap();
+        cached.expires = Instant::now();
+        policy.decisions.insert(key, cached);
+        policy.process(request("/orders", "admin", "alice")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate() {
+        let (url, calls) = decision_service().await;
+        let policy = ExternalAuthzPolicyFactory::new(config(&url)).await.unwrap();
+        let routes = policy.register_routes();
+        let app = Router::new().route("/invalidate", routes[0].handler.clone());
+        let invalidate = |body: &'static str, token: &str| {
+            Request::post("/invalidate")
+                .header("authorization", format!("Bearer {}", token))
+                .body(Body::from(body))
+                .unwrap()
+        };
+
+        policy.process(request("/orders", "admin", "alice")).await;
+        policy.process(request("/orders", "admin", "bob")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let response = app.clone().oneshot(invalidate("", "wrong")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // Only alice's decisions are dropped
+        let response = app
+            .clone()
+            .oneshot(invalidate(r#"{"owner": "alice"}"#, "authz-admin-secret"))
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap()["invalidated"],
+            1
+        );
+        policy.process(request("/orders", "admin", "alice")).await;
+        policy.process(request("/orders", "admin", "bob")).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let response = app
+            .oneshot(invalidate("", "authz-admin-secret"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(policy.decisions.is_empty());
+    }
+
+    #[test]
+    fn test_parse_decision() {
+        assert_eq!(
+            Decision::parse(&json!({ "result": true })),
+            Ok(Decision::Allow)
+        );
+        assert_eq!(
+            Decision::parse(&json!({ "allow": true })),
+            Ok(Decision::Allow)
+        );
+        // OPA leaves out `result` when no rule matched
+        assert_eq!(
+            Decision::parse(&json!({})),
+            Ok(Decision::Deny {
+                status: StatusCode::FORBIDDEN,
+                reason: None
+            })
+        );
+        assert_eq!(
+            Decision::parse(&json!({ "result": { "allow": false, "status": 401 } })),
+            Ok(Decision::Deny {
+                status: StatusCode::UNAUTHORIZED,
+                reason: None
+            })
+        );
+        // Only client errors can be returned
+        assert_eq!(
+            Decision::parse(&json!({ "result": { "allow": false, "status": 200 } })),
+            Ok(Decision::Deny {
+                status: StatusCode::FORBIDDEN,
+                reason: None
+            })
+        );
+        assert!(Decision::parse(&json!({ "result": "yes" })).is_err());
+    }
+}
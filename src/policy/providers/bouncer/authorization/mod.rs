@@ -1 +1,4 @@
+pub mod consent;
+pub mod entitlements;
+pub mod external;
 pub mod rbac;
@@ -0,0 +1,8 @@
+-- Route rules for the @bouncer/authorization/rbac/v2-managed policy
+CREATE TABLE IF NOT EXISTS bouncer_rbac_rules (
+    -- Glob pattern matched against the request path
+    pattern TEXT PRIMARY KEY,
+    roles TEXT[] NOT NULL,
+    -- Unix timestamp in seconds
+    updated_at BIGINT NOT NULL
+);
@@ -1,9 +1,47 @@
+use crate::policy::explain::Explanation;
+use crate::policy::matcher::RouteMatcher;
+
+pub mod store;
 pub mod v1;
+pub mod v2_managed;
 
 // Returns policy ID with version
 pub fn policy_id_with_version(version: &str) -> &'static str {
     match version {
         "v1" => "@bouncer/authorization/rbac/v1",
+        "v2-managed" => "@bouncer/authorization/rbac/v2-managed",
         _ => panic!("Unsupported version: {}", version),
     }
 }
+
+// Why RBAC allowed or denied a request with `roles`, given the patterns of
+// the rules matching it and the roles they allow, for the explain endpoint
+pub(crate) fn explain<'a>(
+    matching: impl Iterator<Item = (&'a RouteMatcher, &'a [String])>,
+    roles: &[String],
+) -> Explanation {
+    let matching: Vec<_> = matching.collect();
+    for (matcher, allowed) in &matching {
+        if let Some(role) = roles.iter().find(|role| allowed.contains(role)) {
+            return Explanation {
+                rule: Some(matcher.source().to_string()),
+                roles: roles.to_vec(),
+                reason: format!("Role '{}' is allowed by '{}'", role, matcher),
+            };
+        }
+    }
+    let reason = if matching.is_empty() {
+        "No rule matches the request".to_string()
+    } else {
+        matching
+            .iter()
+            .map(|(matcher, allowed)| format!("'{}' only allows {}", matcher, allowed.join(", ")))
+            .collect::<Vec<_>>()
+            .join("; ")
+    };
+    Explanation {
+        rule: None,
+        roles: roles.to_vec(),
+        reason,
+    }
+}
@@ -0,0 +1,422 @@
+use crate::config::DatabasesConfig;
+use crate::database::migrations::Migration;
+use crate::database::DatabaseError;
+use crate::policy::matcher::RouteMatcher;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Schema for the PostgreSQL rule store
+///
+/// Versions are recorded in the same table as the other Bouncer migrations, so
+/// they start at 100 to stay clear of the token store's.
+pub const POSTGRES_MIGRATIONS: &[Migration] = &[Migration {
+    version: 100,
+    name: "create_rbac_rules",
+    sql: include_str!("migrations/postgres/0100_create_rbac_rules.sql"),
+}];
+
+/// Roles allowed on the requests matching a route pattern
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RbacRule {
+    pub pattern: String,
+    pub roles: Vec<String>,
+}
+
+/// Where managed RBAC rules are stored
+#[async_trait]
+pub trait RbacRuleStore: Send + Sync + 'static {
+    /// Every rule
+    async fn load(&self) -> Result<Vec<RbacRule>, DatabaseError>;
+
+    /// Add a rule, or replace the roles of an existing one
+    async fn put(&self, rule: &RbacRule) -> Result<(), DatabaseError>;
+
+    /// Remove a rule. Returns false if it didn't exist
+    async fn remove(&self, pattern: &str) -> Result<bool, DatabaseError>;
+}
+
+/// Backend used to store managed RBAC rules
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleStoreBackend {
+    #[default]
+    Postgres,
+    Redis,
+}
+
+/// Rules stored in a Redis hash at `{prefix}rules`, mapping patterns to JSON role lists
+#[cfg(feature = "redis")]
+pub struct RedisRuleStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisRuleStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    fn rules_key(&self) -> String {
+        format!("{}rules", self.key_prefix)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl RbacRuleStore for RedisRuleStore {
+    async fn load(&self) -> Result<Vec<RbacRule>, DatabaseError> {
+        let fields: std::collections::HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(self.rules_key())
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        fields
+            .into_iter()
+            .map(|(pattern, roles)| {
+                let roles = serde_json::from_str(&roles)
+                    .map_err(|e| DatabaseError::ConversionError(e.to_string()))?;
+                Ok(RbacRule { pattern, roles })
+            })
+            .collect()
+    }
+
+    async fn put(&self, rule: &RbacRule) -> Result<(), DatabaseError> {
+        let roles = serde_json::to_string(&rule.roles)
+            .map_err(|e| DatabaseError::ConversionError(e.to_string()))?;
+
+        redis::cmd("HSET")
+            .arg(self.rules_key())
+            .arg(&rule.pattern)
+            .arg(roles)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn remove(&self, pattern: &str) -> Result<bool, DatabaseError> {
+        let removed: i64 = redis::cmd("HDEL")
+            .arg(self.rules_key())
+            .arg(pattern)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(removed > 0)
+    }
+}
+
+/// Rules stored in the `bouncer_rbac_rules` PostgreSQL table
+///
+/// Loads are served from read replicas when configured.
+#[cfg(feature = "postgres")]
+pub struct PostgresRuleStore {
+    pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>,
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresRuleStore {
+    pub fn new(pools: Arc<crate::database::replicas::SqlPools<sqlx::Postgres>>) -> Self {
+        Self { pools }
+    }
+}
+
+#[cfg(feature = "postgres")]
+#[async_trait]
+impl RbacRuleStore for PostgresRuleStore {
+    async fn load(&self) -> Result<Vec<RbacRule>, DatabaseError> {
+        let rows = self
+            .pools
+            .read(|pool| async move {
+                sqlx::query_as::<_, (String, Vec<String>)>(
+                    "SELECT pattern, roles FROM bouncer_rbac_rules",
+                )
+                .fetch_all(&*pool)
+                .await
+            })
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(pattern, roles)| RbacRule { pattern, roles })
+            .collect())
+    }
+
+    async fn put(&self, rule: &RbacRule) -> Result<(), DatabaseError> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+
+        sqlx::query(
+            "INSERT INTO bouncer_rbac_rules (pattern, roles, updated_at) VALUES ($1, $2, $3) \
+             ON CONFLICT (pattern) DO UPDATE SET roles = EXCLUDED.roles, updated_at = EXCLUDED.updated_at",
+        )
+        .bind(&rule.pattern)
+        .bind(&rule.roles)
+        .bind(now)
+        .execute(&*self.pools.primary())
+        .await
+        .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn remove(&self, pattern: &str) -> Result<bool, DatabaseError> {
+        let result = sqlx::query("DELETE FROM bouncer_rbac_rules WHERE pattern = $1")
+            .bind(pattern)
+            .execute(&*self.pools.primary())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+/// A rule with its compiled pattern
+pub struct CompiledRule {
+    pub rule: RbacRule,
+    pub matcher: RouteMatcher,
+}
+
+/// In-memory copy of the rules in a store, reloaded periodically
+///
+/// Requests are checked against the copy, so they never wait on the database.
+/// Rules with invalid patterns are skipped with an error, so they deny rather
+/// than allow.
+pub struct ManagedRules {
+    store: Arc<dyn RbacRuleStore>,
+    rules: RwLock<Arc<Vec<CompiledRule>>>,
+}
+
+impl ManagedRules {
+    /// Load the rules from the store
+    pub async fn new(store: Arc<dyn RbacRuleStore>) -> Result<Arc<Self>, DatabaseError> {
+        let rules = Arc::new(Self {
+            store,
+            rules: RwLock::new(Arc::new(Vec::new())),
+        });
+        rules.refresh().await?;
+        Ok(rules)
+    }
+
+    /// The rules as of the last refresh
+    pub fn current(&self) -> Arc<Vec<CompiledRule>> {
+        Arc::clone(&self.rules.read().unwrap())
+    }
+
+    /// Reload the rules from the store now
+    ///
+    /// This is the invalidation hook: call it after changing rules in the
+    /// database directly to apply them without waiting for the next refresh.
+    pub async fn refresh(&self) -> Result<(), DatabaseError> {
+        let mut rules: Vec<CompiledRule> = self
+            .store
+            .load()
+            .await?
+            .into_iter()
+            .filter_map(|rule| match RouteMatcher::parse(&rule.pattern) {
+                Ok(matcher) => Some(CompiledRule { rule, matcher }),
+                Err(e) => {
+                    tracing::error!("Skipping RBAC rule: {}", e);
+                    None
+                }
+            })
+            .collect();
+        rules.sort_by(|a, b| a.rule.pattern.cmp(&b.rule.pattern));
+
+        *self.rules.write().unwrap() = Arc::new(rules);
+        Ok(())
+    }
+
+    /// Add or replace a rule and apply it right away
+    pub async fn put(&self, rule: RbacRule) -> Result<(), DatabaseError> {
+        RouteMatcher::parse(&rule.pattern).map_err(DatabaseError::ConversionError)?;
+        self.store.put(&rule).await?;
+        self.refresh().await
+    }
+
+    /// Remove a rule and apply it right away. Returns false if it didn't exist
+    pub async fn remove(&self, pattern: &str) -> Result<bool, DatabaseError> {
+        let removed = self.store.remove(pattern).await?;
+        self.refresh().await?;
+        Ok(removed)
+    }
+
+    /// Reload the rules every `interval`
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the policy is dropped
+        let rules = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, and the rules were just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(rules) = rules.upgrade() else {
+                    break;
+                };
+
+                // Keep the previous rules if the store is unavailable
+                if let Err(e) = rules.refresh().await {
+                    tracing::warn!(
+                        "Failed to refresh RBAC rules, keeping the previous ones: {}",
+                        e
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Create the rule store selected in the policy config
+pub async fn create_rule_store(
+    backend: RuleStoreBackend,
+    key_prefix: &str,
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn RbacRuleStore>, DatabaseError> {
+    let provider = match backend {
+        RuleStoreBackend::Redis => "redis",
+        RuleStoreBackend::Postgres => "postgres",
+    };
+    crate::database::validate_database_config(databases, provider)?;
+
+    match backend {
+        RuleStoreBackend::Redis => create_redis_store(key_prefix, databases).await,
+        RuleStoreBackend::Postgres => create_postgres_store(run_migrations, databases).await,
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    key_prefix: &str,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn RbacRuleStore>, DatabaseError> {
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisRuleStore::new(&client, key_prefix.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _key_prefix: &str,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn RbacRuleStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(feature = "postgres")]
+async fn create_postgres_store(
+    run_migrations: bool,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn RbacRuleStore>, DatabaseError> {
+    let postgres_config = databases.postgres.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("PostgreSQL configuration is required".to_string())
+    })?;
+    let pools = crate::database::get_postgres_pools(postgres_config).await?;
+
+    if run_migrations {
+        crate::database::migrations::run_postgres_migrations(&pools.primary(), POSTGRES_MIGRATIONS)
+            .await?;
+    }
+
+    Ok(Arc::new(PostgresRuleStore::new(pools)))
+}
+
+#[cfg(not(feature = "postgres"))]
+async fn create_postgres_store(
+    _run_migrations: bool,
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn RbacRuleStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "PostgreSQL support is not enabled. Rebuild with the 'postgres' feature.".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryRuleStore {
+        rules: Mutex<Vec<RbacRule>>,
+    }
+
+    #[async_trait]
+    impl RbacRuleStore for MemoryRuleStore {
+        async fn load(&self) -> Result<Vec<RbacRule>, DatabaseError> {
+            Ok(self.rules.lock().unwrap().clone())
+        }
+
+        async fn put(&self, rule: &RbacRule) -> Result<(), DatabaseError> {
+            let mut rules = self.rules.lock().unwrap();
+            rules.retain(|existing| existing.pattern != rule.pattern);
+            rules.push(rule.clone());
+            Ok(())
+        }
+
+        async fn remove(&self, pattern: &str) -> Result<bool, DatabaseError> {
+            let mut rules = self.rules.lock().unwrap();
+            let before = rules.len();
+            rules.retain(|rule| rule.pattern != pattern);
+            Ok(rules.len() < before)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_managed_rules() {
+        let store = Arc::new(MemoryRuleStore::default());
+        // Written directly to the store, bypassing validation
+        store
+            .put(&RbacRule {
+                pattern: "/broken/[".to_string(),
+                roles: vec!["admin".to_string()],
+            })
+            .await
+            .unwrap();
+        let rules = ManagedRules::new(store.clone()).await.unwrap();
+        assert!(rules.current().is_empty());
+
+        rules
+            .put(RbacRule {
+                pattern: "/api/*".to_string(),
+                roles: vec!["user".to_string()],
+            })
+            .await
+            .unwrap();
+        assert_eq!(rules.current().len(), 1);
+        assert!(rules.current()[0]
+            .matcher
+            .matches(&axum::http::Method::GET, "/api/orders"));
+
+        assert!(rules
+            .put(RbacRule {
+                pattern: "/bad/[".to_string(),
+                roles: vec!["user".to_string()],
+            })
+            .await
+            .is_err());
+        assert!(rules.remove("/api/*").await.unwrap());
+        assert!(rules.current().is_empty());
+    }
+}
@@ -1,24 +1,34 @@
-use crate::policy::traits::{Policy, PolicyFactory, PolicyResult};
+use super::explain;
+use crate::policy::context::roles;
+use crate::policy::denial::Denial;
+use crate::policy::explain::is_explaining;
+use crate::policy::matcher::RouteMatcher;
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyResult};
 use async_trait::async_trait;
 use axum::{
     body::Body,
-    http::{Request, Response, StatusCode},
+    http::{Request, StatusCode},
 };
-use glob::Pattern;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::pin::Pin;
-use std::sync::Arc;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RbacConfig {
     /// Map of route patterns to allowed roles
-    /// Route patterns can use glob syntax (e.g., "/api/*", "/users/**")
+    /// Route patterns can use glob syntax (e.g., "/api/*", "/users/**"), any other
+    /// route pattern syntax, and can start with methods (e.g., "GET,HEAD /api/*")
     pub route_roles: HashMap<String, Vec<String>>,
 }
 
+// A route pattern compiled when the policy is created
+struct CompiledRoute {
+    matcher: RouteMatcher,
+    roles: Vec<String>,
+}
+
 pub struct RbacPolicy {
-    config: Arc<RbacConfig>,
+    routes: Vec<CompiledRoute>,
 }
 
 #[derive(Default)]
@@ -45,15 +55,21 @@ impl PolicyFactory for RbacPolicyFactory {
                 return Err("At least one route must be configured".to_string());
             }
 
-            // Validate all route patterns
-            for pattern_str in config.route_roles.keys() {
-                Pattern::new(pattern_str)
-                    .map_err(|e| format!("Invalid route pattern '{}': {}", pattern_str, e))?;
-            }
+            // Compile every route pattern once, rejecting the config if any is invalid
+            let mut routes = config
+                .route_roles
+                .into_iter()
+                .map(|(pattern, roles)| {
+                    Ok(CompiledRoute {
+                        matcher: RouteMatcher::parse(&pattern)?,
+                        roles,
+                    })
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            // Check routes in a stable order, so logs don't depend on hash order
+            routes.sort_by(|a, b| a.matcher.source().cmp(b.matcher.source()));
 
-            Ok(RbacPolicy {
-                config: Arc::new(config),
-            })
+            Ok(RbacPolicy { routes })
         })
     }
 
@@ -65,8 +81,7 @@ impl PolicyFactory for RbacPolicyFactory {
 
         // Validate all route patterns
         for pattern_str in config.route_roles.keys() {
-            Pattern::new(pattern_str)
-                .map_err(|e| format!("Invalid route pattern '{}': {}", pattern_str, e))?;
+            RouteMatcher::parse(pattern_str)?;
         }
 
         Ok(())
@@ -91,60 +106,154 @@ impl Policy for RbacPolicy {
         "v1"
     }
 
-    async fn process(&self, request: Request<Body>) -> PolicyResult {
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
         let path = request.uri().path();
-        let role = match request.headers().get("x-bouncer-role") {
-            Some(role) => match role.to_str() {
-                Ok(role) => {
-                    tracing::info!("RBAC Policy: Processing request for path '{}' with role '{}'", path, role);
-                    role
-                },
-                Err(_) => {
-                    tracing::error!("RBAC Policy: Invalid role header format");
-                    return PolicyResult::Terminate(
-                        Response::builder()
-                            .status(StatusCode::UNAUTHORIZED)
-                            .body(Body::from("Invalid role header"))
-                            .unwrap(),
-                    );
-                }
-            },
-            None => {
-                tracing::error!("RBAC Policy: No role header found in request");
-                return PolicyResult::Terminate(
-                    Response::builder()
-                        .status(StatusCode::UNAUTHORIZED)
-                        .body(Body::from("No role header found"))
-                        .unwrap(),
-                );
-            }
-        };
+        let roles = roles(&request);
+        if roles.is_empty() {
+            tracing::error!("RBAC Policy: No role found for request");
+            return PolicyResult::Terminate(
+                Denial::new("no_role", "No role found").response(StatusCode::UNAUTHORIZED),
+            );
+        }
+        let role = roles.join(",");
+        tracing::info!(
+            "RBAC Policy: Processing request for path '{}' with role '{}'",
+            path,
+            role
+        );
 
         // Check if the role has access to the requested path
-        let has_access = self.config.route_roles.iter().any(|(pattern_str, roles)| {
-            let pattern = Pattern::new(pattern_str).unwrap_or_else(|_| {
-                tracing::error!("Invalid glob pattern: {}", pattern_str);
-                Pattern::new("*").unwrap() // Default to matching nothing
-            });
-
-            let matches = pattern.matches(path) && roles.contains(&role.to_string());
+        // Patterns were compiled and validated when the policy was created, so a
+        // request can only be allowed by a route that matches it
+        let has_access = self.routes.iter().any(|route| {
+            // Authentication policies may set several roles
+            let matches = route.matcher.matches(request.method(), path)
+                && roles.iter().any(|role| route.roles.contains(role));
             if matches {
-                tracing::info!("RBAC Policy: Role '{}' has access to path '{}' via pattern '{}'", role, path, pattern_str);
+                tracing::info!(
+                    "RBAC Policy: Role '{}' has access to path '{}' via pattern '{}'",
+                    role,
+                    path,
+                    route.matcher
+                );
             }
             matches
         });
 
+        // Describe the decision for the explain endpoint
+        let explanation = is_explaining(&request).then(|| {
+            explain(
+                self.routes
+                    .iter()
+                    .filter(|route| route.matcher.matches(request.method(), path))
+                    .map(|route| (&route.matcher, route.roles.as_slice())),
+                &roles,
+            )
+        });
+
         if !has_access {
-            tracing::warn!("RBAC Policy: Access denied for role '{}' to path '{}'", role, path);
-            return PolicyResult::Terminate(
-                Response::builder()
-                    .status(StatusCode::FORBIDDEN)
-                    .body(Body::from("Access denied"))
-                    .unwrap(),
+            tracing::warn!(
+                "RBAC Policy: Access denied for role '{}' to path '{}'",
+                role,
+                path
             );
+            let mut response =
+                Denial::new("access_denied", "Access denied").response(StatusCode::FORBIDDEN);
+            if let Some(explanation) = explanation {
+                response.extensions_mut().insert(explanation);
+            }
+            return PolicyResult::Terminate(response);
         }
 
-        tracing::info!("RBAC Policy: Access granted for role '{}' to path '{}'", role, path);
+        tracing::info!(
+            "RBAC Policy: Access granted for role '{}' to path '{}'",
+            role,
+            path
+        );
+        if let Some(explanation) = explanation {
+            request.extensions_mut().insert(explanation);
+        }
         PolicyResult::Continue(request)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::context::context_mut;
+    use crate::policy::providers::bouncer::authentication::identity::Identity;
+
+    fn request(path: &str, role: &str) -> Request<Body> {
+        Request::get(path)
+            .header("x-bouncer-role", role)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_compiled_routes() {
+        let invalid = RbacConfig {
+            route_roles: HashMap::from([("/api/[".to_string(), vec!["user".to_string()])]),
+        };
+        assert!(RbacPolicyFactory::new(invalid).await.is_err());
+
+        let policy = RbacPolicyFactory::new(RbacConfig {
+            route_roles: HashMap::from([
+                ("GET,POST /api/*".to_string(), vec!["user".to_string()]),
+                ("/admin/**".to_string(), vec!["admin".to_string()]),
+            ]),
+        })
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            policy.process(request("/api/orders", "guest, user")).await,
+            PolicyResult::Continue(_)
+        ));
+        let delete = Request::delete("/api/orders")
+            .header("x-bouncer-role", "user")
+            .body(Body::empty())
+            .unwrap();
+        assert!(matches!(
+            policy.process(delete).await,
+            PolicyResult::Terminate(_)
+        ));
+        match policy.process(request("/admin/users", "user")).await {
+            PolicyResult::Terminate(response) => {
+                assert_eq!(response.status(), StatusCode::FORBIDDEN)
+            }
+            _ => panic!("expected access to be denied"),
+        }
+
+        // Roles set in the context take precedence over the header
+        let mut request = request("/admin/users", "user");
+        Identity::new("admin").apply(&mut request);
+        assert!(matches!(
+            policy.process(request).await,
+            PolicyResult::Continue(_)
+        ));
+        let mut request = Request::get("/admin/users").body(Body::empty()).unwrap();
+        context_mut(&mut request).set_identity(Identity::new("user, admin"));
+        assert!(matches!(
+            policy.process(request).await,
+            PolicyResult::Continue(_)
+        ));
+        match policy
+            .process(Request::get("/admin/users").body(Body::empty()).unwrap())
+            .await
+        {
+            PolicyResult::Terminate(response) => {
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+            }
+            _ => panic!("expected a request without a role to be rejected"),
+        }
+    }
+}
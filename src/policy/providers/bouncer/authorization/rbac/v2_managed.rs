@@ -0,0 +1,290 @@
+use super::explain;
+use super::store::{create_rule_store, ManagedRules, RbacRule, RuleStoreBackend};
+use crate::admin::{is_admin, json_error};
+use crate::config::SecretString;
+use crate::policy::context::roles;
+use crate::policy::denial::Denial;
+use crate::policy::explain::is_explaining;
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RbacManagedConfig {
+    /// Where rules are stored: "postgres" (default) or "redis"
+    #[serde(default)]
+    pub store: RuleStoreBackend,
+    /// How often the rules are reloaded from the store
+    #[serde(default = "default_refresh_interval_secs")]
+    pub refresh_interval_secs: u64,
+    /// Token required by the rule management routes, which are disabled without it
+    pub admin_token: Option<SecretString>,
+    /// Prefix for Redis keys
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    /// Create or update the PostgreSQL schema on startup
+    #[serde(default = "default_run_migrations")]
+    pub run_migrations: bool,
+}
+
+fn default_refresh_interval_secs() -> u64 {
+    30
+}
+
+fn default_key_prefix() -> String {
+    "bouncer:rbac:".to_string()
+}
+
+fn default_run_migrations() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct PatternQuery {
+    pattern: String,
+}
+
+// RBAC with route rules loaded from a database instead of the config
+pub struct RbacManagedPolicy {
+    config: Arc<RbacManagedConfig>,
+    rules: Arc<ManagedRules>,
+}
+
+impl RbacManagedPolicy {
+    fn denied(status: StatusCode, error: &'static str, message: &'static str) -> PolicyResult {
+        PolicyResult::Terminate(Denial::new(error, message).response(status))
+    }
+}
+
+pub struct RbacManagedPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for RbacManagedPolicyFactory {
+    type PolicyType = RbacManagedPolicy;
+    type Config = RbacManagedConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::authorization::rbac::policy_id_with_version("v2-managed")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v2-managed")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let instance_config = crate::instance::config();
+        let db_config = match instance_config.as_deref() {
+            Some(instance_config) => &instance_config.databases,
+            None => return Err("Global configuration not initialized".to_string()),
+        };
+
+        let store = create_rule_store(
+            config.store,
+            &config.key_prefix,
+            config.run_migrations,
+            db_config,
+        )
+        .await
+        .map_err(|e| e.to_string())?;
+
+        let rules = ManagedRules::new(store)
+            .await
+            .map_err(|e| format!("Failed to load RBAC rules: {}", e))?;
+        rules.spawn_refresh(Duration::from_secs(config.refresh_interval_secs));
+
+        if config.admin_token.is_none() {
+            tracing::warn!(
+                "No admin_token configured for the managed RBAC policy; rule management routes are disabled"
+            );
+        }
+
+        Ok(RbacManagedPolicy {
+            config: Arc::new(config),
+            rules,
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.refresh_interval_secs == 0 {
+            return Err("refresh_interval_secs must be greater than 0".to_string());
+        }
+        if config
+            .admin_token
+            .as_ref()
+            .is_some_and(SecretString::is_empty)
+        {
+            return Err("admin_token must not be empty".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for RbacManagedPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "authorization"
+    }
+
+    fn name(&self) -> &'static str {
+        "rbac"
+    }
+
+    fn version(&self) -> &'static str {
+        "v2-managed"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        vec![Capability::Identity]
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        let Some(admin_token) = self.config.admin_token.clone() else {
+            return vec![];
+        };
+        let admin_token: Arc<str> = Arc::from(admin_token.expose());
+
+        let list = {
+            let (rules, admin_token) = (self.rules.clone(), admin_token.clone());
+            move |headers: HeaderMap| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                let current: Vec<RbacRule> = rules
+                    .current()
+                    .iter()
+                    .map(|rule| rule.rule.clone())
+                    .collect();
+                Json(current).into_response()
+            }
+        };
+
+        let put = {
+            let (rules, admin_token) = (self.rules.clone(), admin_token.clone());
+            move |headers: HeaderMap, Json(rule): Json<RbacRule>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                if rule.roles.is_empty() {
+                    return json_error(StatusCode::BAD_REQUEST, "roles must not be empty");
+                }
+
+                match rules.put(rule.clone()).await {
+                    Ok(()) => Json(rule).into_response(),
+                    Err(crate::database::DatabaseError::ConversionError(e)) => {
+                        json_error(StatusCode::BAD_REQUEST, e)
+                    }
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let remove = {
+            let (rules, admin_token) = (self.rules.clone(), admin_token.clone());
+            move |headers: HeaderMap, Query(query): Query<PatternQuery>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match rules.remove(&query.pattern).await {
+                    Ok(true) => StatusCode::NO_CONTENT.into_response(),
+                    Ok(false) => json_error(StatusCode::NOT_FOUND, "Rule not found"),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        // Apply changes made directly in the database without waiting for the next refresh
+        let refresh = {
+            let rules = self.rules.clone();
+            move |headers: HeaderMap| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                match rules.refresh().await {
+                    Ok(()) => StatusCode::NO_CONTENT.into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        vec![
+            RouteRegistration {
+                relative_path: "rules".to_string(),
+                handler: get(list).put(put).delete(remove),
+            },
+            RouteRegistration {
+                relative_path: "rules/refresh".to_string(),
+                handler: post(refresh),
+            },
+        ]
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        let path = request.uri().path();
+        let roles = roles(&request);
+        if roles.is_empty() {
+            return Self::denied(StatusCode::UNAUTHORIZED, "no_role", "No role found");
+        }
+
+        // Authentication policies may set several roles
+        let rules = self.rules.current();
+        let has_access = rules.iter().any(|rule| {
+            rule.matcher.matches(request.method(), path)
+                && roles.iter().any(|role| rule.rule.roles.contains(role))
+        });
+
+        // Describe the decision for the explain endpoint
+        let explanation = is_explaining(&request).then(|| {
+            explain(
+                rules
+                    .iter()
+                    .filter(|rule| rule.matcher.matches(request.method(), path))
+                    .map(|rule| (&rule.matcher, rule.rule.roles.as_slice())),
+                &roles,
+            )
+        });
+
+        if !has_access {
+            tracing::warn!(
+                "RBAC Policy: Access denied for role '{}' to path '{}'",
+                roles.join(","),
+                path
+            );
+            let mut response =
+                Denial::new("access_denied", "Access denied").response(StatusCode::FORBIDDEN);
+            if let Some(explanation) = explanation {
+                response.extensions_mut().insert(explanation);
+            }
+            return PolicyResult::Terminate(response);
+        }
+
+        if let Some(explanation) = explanation {
+            request.extensions_mut().insert(explanation);
+        }
+        PolicyResult::Continue(request)
+    }
+}
@@ -0,0 +1,297 @@
+use crate::policy::matcher::RouteMatcher;
+use crate::policy::traits::{Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::Query,
+    http::{header, HeaderName, HeaderValue, Request, Response, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// A canned response for requests matching a path pattern
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockRouteConfig {
+    /// Path pattern. `{name}` matches one segment and `{*name}` the rest of the path,
+    /// and `regex:` patterns capture their named groups
+    pub path: String,
+    /// Methods to answer. All methods if empty
+    #[serde(default)]
+    pub methods: Vec<String>,
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// JSON body. Strings in it can use `{{path.<name>}}`, `{{query.<name>}}`,
+    /// `{{header.<name>}}` and `{{method}}`
+    #[serde(default)]
+    pub body: serde_json::Value,
+    /// Wait this long before answering, to mimic a slow backend
+    #[serde(default)]
+    pub latency_ms: u64,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MockConfig {
+    /// Routes to answer, in order. The first match wins and other requests continue
+    pub routes: Vec<MockRouteConfig>,
+}
+
+// Values available to body templates
+struct TemplateContext<'a> {
+    path: HashMap<String, String>,
+    query: HashMap<String, String>,
+    request: &'a Request<Body>,
+}
+
+impl TemplateContext<'_> {
+    fn lookup(&self, name: &str) -> String {
+        let value = match name.split_once('.') {
+            Some(("path", key)) => self.path.get(key).cloned(),
+            Some(("query", key)) => self.query.get(key).cloned(),
+            Some(("header", key)) => self
+                .request
+                .headers()
+                .get(key)
+                .and_then(|value| value.to_str().ok())
+                .map(|value| value.to_string()),
+            None if name == "method" => Some(self.request.method().to_string()),
+            _ => None,
+        };
+        // Unknown or missing values render as empty strings
+        value.unwrap_or_default()
+    }
+
+    fn render_str(&self, template: &str) -> String {
+        let mut output = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            let Some(end) = rest[start..].find("}}") else {
+                break;
+            };
+            output.push_str(&rest[..start]);
+            output.push_str(&self.lookup(rest[start + 2..start + end].trim()));
+            rest = &rest[start + end + 2..];
+        }
+        output.push_str(rest);
+        output
+    }
+
+    fn render(&self, value: &serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::String(s) => serde_json::Value::String(self.render_str(s)),
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.iter().map(|item| self.render(item)).collect())
+            }
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), self.render(value)))
+                    .collect(),
+            ),
+            other => other.clone(),
+        }
+    }
+}
+
+struct MockRoute {
+    matcher: RouteMatcher,
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: serde_json::Value,
+    latency: Duration,
+}
+
+impl MockRoute {
+    fn new(config: &MockRouteConfig) -> Result<Self, String> {
+        let status = StatusCode::from_u16(config.status)
+            .map_err(|_| format!("Invalid status {} for mock {}", config.status, config.path))?;
+        let headers = config
+            .headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes()).map_err(|e| {
+                    format!("Invalid header '{}' for mock {}: {}", name, config.path, e)
+                })?;
+                let value = HeaderValue::from_str(value).map_err(|e| {
+                    format!(
+                        "Invalid value for header {} for mock {}: {}",
+                        name, config.path, e
+                    )
+                })?;
+                Ok((name, value))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        Ok(Self {
+            matcher: RouteMatcher::new(&config.path, &config.methods)?,
+            status,
+            headers,
+            body: config.body.clone(),
+            latency: Duration::from_millis(config.latency_ms),
+        })
+    }
+
+    fn respond(&self, request: &Request<Body>, path: HashMap<String, String>) -> Response<Body> {
+        let query = Query::<HashMap<String, String>>::try_from_uri(request.uri())
+            .map(|Query(query)| query)
+            .unwrap_or_default();
+        let context = TemplateContext {
+            path,
+            query,
+            request,
+        };
+
+        let mut response = Response::builder().status(self.status);
+        if !self.body.is_null() {
+            response = response.header(header::CONTENT_TYPE, "application/json");
+        }
+        let mut response = response
+            .body(match &self.body {
+                serde_json::Value::Null => Body::empty(),
+                body => Body::from(context.render(body).to_string()),
+            })
+            .unwrap();
+        // Configured headers override the default content type
+        for (name, value) in &self.headers {
+            response.headers_mut().insert(name.clone(), value.clone());
+        }
+        response
+    }
+}
+
+/// Answers matching requests with canned responses, so clients can be built
+/// against routes the upstream doesn't serve yet
+pub struct MockPolicy {
+    routes: Vec<MockRoute>,
+}
+
+pub struct MockPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for MockPolicyFactory {
+    type PolicyType = MockPolicy;
+    type Config = MockConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::development::mock::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        let routes = config
+            .routes
+            .iter()
+            .map(MockRoute::new)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(MockPolicy { routes })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.routes.is_empty() {
+            return Err("At least one mock route must be configured".to_string());
+        }
+
+        for route in &config.routes {
+            MockRoute::new(route)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for MockPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "development"
+    }
+
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let path = request.uri().path();
+        let matched = self.routes.iter().find_map(|route| {
+            route
+                .matcher
+                .captures(request.method(), path)
+                .map(|params| (route, params))
+        });
+
+        let Some((route, params)) = matched else {
+            return PolicyResult::Continue(request);
+        };
+
+        if !route.latency.is_zero() {
+            tokio::time::sleep(route.latency).await;
+        }
+        PolicyResult::Terminate(route.respond(&request, params))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_templated_response() {
+        let policy = MockPolicyFactory::new(MockConfig {
+            routes: vec![MockRouteConfig {
+                path: "/users/{id}".to_string(),
+                methods: vec!["get".to_string()],
+                status: 200,
+                headers: HashMap::new(),
+                body: serde_json::json!({
+                    "id": "{{path.id}}",
+                    "greeting": "Hello, {{ query.name }}!",
+                    "tags": ["{{method}}"],
+                }),
+                latency_ms: 0,
+            }],
+        })
+        .await
+        .unwrap();
+
+        let request = Request::get("/users/42?name=Ada")
+            .body(Body::empty())
+            .unwrap();
+        let PolicyResult::Terminate(response) = policy.process(request).await else {
+            panic!("expected a mock response");
+        };
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "id": "42", "greeting": "Hello, Ada!", "tags": ["GET"] })
+        );
+
+        let request = Request::post("/users/42").body(Body::empty()).unwrap();
+        assert!(matches!(
+            policy.process(request).await,
+            PolicyResult::Continue(_)
+        ));
+    }
+}
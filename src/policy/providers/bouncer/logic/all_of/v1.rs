@@ -0,0 +1,181 @@
+use crate::config::PolicyConfig;
+use crate::policy::failure::resolve;
+use crate::policy::providers::bouncer::logic::check_processes_requests;
+use crate::policy::traits::{Capability, Policy, PolicyHealth, PolicyResult, ResponsePolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{Request, Response},
+};
+use serde::Deserialize;
+
+/// Policies that must all let a request through
+///
+/// Like any-of, the registry builds this policy itself.
+#[derive(Clone, Deserialize)]
+pub struct AllOfConfig {
+    pub policies: Vec<PolicyConfig>,
+}
+
+impl AllOfConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.policies.is_empty() {
+            return Err("all-of needs at least one policy".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Runs its policies in order like a chain of its own, stopping at the first
+/// that rejects the request
+///
+/// On its own this is the same as listing the policies in the chain. It's
+/// meant for groups inside any-of and not, such as "a valid JWT with the
+/// admin role".
+pub struct AllOfPolicy {
+    policies: Vec<Box<dyn Policy>>,
+}
+
+impl AllOfPolicy {
+    pub fn new(policies: Vec<Box<dyn Policy>>) -> Result<Self, String> {
+        check_processes_requests("all-of", &policies)?;
+        Ok(Self { policies })
+    }
+}
+
+#[async_trait]
+impl Policy for AllOfPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "logic"
+    }
+
+    fn name(&self) -> &'static str {
+        "all-of"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        for policy in &self.policies {
+            match resolve(policy.as_ref(), policy.process(request).await) {
+                Ok(next) => request = next,
+                Err(response) => return PolicyResult::Terminate(response),
+            }
+        }
+        PolicyResult::Continue(request)
+    }
+
+    // Every policy processed the request, so each sees the response
+    async fn process_response(&self, mut response: Response<Body>) -> ResponsePolicyResult {
+        for policy in self.policies.iter().rev() {
+            if !policy.processes_responses() {
+                continue;
+            }
+            match policy.process_response(response).await {
+                ResponsePolicyResult::Continue(next) => response = next,
+                terminate => return terminate,
+            }
+        }
+        ResponsePolicyResult::Continue(response)
+    }
+
+    fn processes_responses(&self) -> bool {
+        self.policies
+            .iter()
+            .any(|policy| policy.processes_responses())
+    }
+
+    fn read_only(&self) -> bool {
+        self.policies.iter().all(|policy| policy.read_only())
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        self.policies
+            .iter()
+            .filter_map(|policy| policy.inspects_body())
+            .max()
+    }
+
+    // What the policies need that none of them provides before it
+    fn requires(&self) -> Vec<Capability> {
+        let mut required = Vec::new();
+        let mut provided = Vec::new();
+        for policy in &self.policies {
+            for capability in policy.requires() {
+                if !provided.contains(&capability) && !required.contains(&capability) {
+                    required.push(capability);
+                }
+            }
+            provided.extend(policy.provides());
+        }
+        required
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        let mut provided = Vec::new();
+        for capability in self.policies.iter().flat_map(|policy| policy.provides()) {
+            if !provided.contains(&capability) {
+                provided.push(capability);
+            }
+        }
+        provided
+    }
+
+    // Requests are only let through while every policy can handle them
+    async fn health(&self) -> PolicyHealth {
+        let mut degraded = Vec::new();
+        let mut unhealthy = Vec::new();
+        for policy in &self.policies {
+            match policy.health().await {
+                PolicyHealth::Healthy => {}
+                PolicyHealth::Degraded(reason) => degraded.push(reason),
+                PolicyHealth::Unhealthy(reason) => unhealthy.push(reason),
+            }
+        }
+        if !unhealthy.is_empty() {
+            PolicyHealth::Unhealthy(unhealthy.join("; "))
+        } else if !degraded.is_empty() {
+            PolicyHealth::Degraded(degraded.join("; "))
+        } else {
+            PolicyHealth::Healthy
+        }
+    }
+
+    async fn warm_up(&self) -> Result<(), String> {
+        let errors: Vec<String> =
+            futures::future::join_all(self.policies.iter().map(|policy| policy.warm_up()))
+                .await
+                .into_iter()
+                .filter_map(Result::err)
+                .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    async fn on_start(&self) -> Result<(), String> {
+        let errors: Vec<String> =
+            futures::future::join_all(self.policies.iter().map(|policy| policy.on_start()))
+                .await
+                .into_iter()
+                .filter_map(Result::err)
+                .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        futures::future::join_all(self.policies.iter().map(|policy| policy.on_shutdown())).await;
+    }
+}
@@ -0,0 +1,3 @@
+// The authentication any-of policy works for any policies, and is available
+// under the logic category along with the other combinators
+pub use crate::policy::providers::bouncer::authentication::any_of::v1::{AnyOfConfig, AnyOfPolicy};
@@ -0,0 +1,23 @@
+pub mod all_of;
+pub mod any_of;
+pub mod not;
+
+use crate::policy::traits::Policy;
+
+// Combinators only make sense around policies that process requests
+pub(crate) fn check_processes_requests(
+    combinator: &str,
+    policies: &[Box<dyn Policy>],
+) -> Result<(), String> {
+    match policies.iter().find(|policy| !policy.processes_requests()) {
+        Some(policy) => Err(format!(
+            "{}: @{}/{}/{}/{} doesn't process requests",
+            combinator,
+            policy.provider(),
+            policy.category(),
+            policy.name(),
+            policy.version()
+        )),
+        None => Ok(()),
+    }
+}
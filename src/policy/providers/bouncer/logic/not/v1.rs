@@ -0,0 +1,132 @@
+use crate::config::PolicyConfig;
+use crate::policy::denial::Denial;
+use crate::policy::providers::bouncer::logic::check_processes_requests;
+use crate::policy::traits::{Capability, Policy, PolicyHealth, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{Request, StatusCode},
+};
+use serde::Deserialize;
+
+/// A policy whose decision is inverted
+///
+/// Like any-of, the registry builds this policy itself.
+#[derive(Clone, Deserialize)]
+pub struct NotConfig {
+    pub policy: PolicyConfig,
+    /// Status of the response to requests the policy lets through
+    #[serde(default = "default_status")]
+    pub status: u16,
+    #[serde(default = "default_body")]
+    pub body: String,
+}
+
+fn default_status() -> u16 {
+    403
+}
+
+fn default_body() -> String {
+    "Forbidden".to_string()
+}
+
+impl NotConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        StatusCode::from_u16(self.status)
+            .map_err(|_| format!("not: invalid status {}", self.status))?;
+        Ok(())
+    }
+}
+
+/// Rejects the requests its policy lets through, and lets through those it
+/// rejects
+///
+/// The policy sees a copy of the request without the body, and requests it
+/// rejects continue unchanged, so nothing it adds to a request is kept. This
+/// makes exceptions possible, such as rejecting requests from an IP range
+/// with a policy that only allows it.
+pub struct NotPolicy {
+    policy: Box<dyn Policy>,
+    status: StatusCode,
+    body: String,
+}
+
+impl NotPolicy {
+    pub fn new(policy: Box<dyn Policy>, config: &NotConfig) -> Result<Self, String> {
+        check_processes_requests("not", std::slice::from_ref(&policy))?;
+        config.validate()?;
+        Ok(Self {
+            policy,
+            status: StatusCode::from_u16(config.status).unwrap(),
+            body: config.body.clone(),
+        })
+    }
+}
+
+#[async_trait]
+impl Policy for NotPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "logic"
+    }
+
+    fn name(&self) -> &'static str {
+        "not"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let (parts, body) = request.into_parts();
+        match self
+            .policy
+            .process(Request::from_parts(parts.clone(), Body::empty()))
+            .await
+        {
+            PolicyResult::Continue(_) => {
+                PolicyResult::Terminate(Denial::new("denied", &self.body).response(self.status))
+            }
+            PolicyResult::Terminate(_) | PolicyResult::Redirect(..) => {
+                PolicyResult::Continue(Request::from_parts(parts, body))
+            }
+            // Inverting a failure would let requests through whenever the
+            // policy fails closed, so the failure is not's own
+            PolicyResult::Error(_, error) => {
+                PolicyResult::Error(Request::from_parts(parts, body), error)
+            }
+        }
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        self.policy.inspects_body()
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        self.policy.requires()
+    }
+
+    async fn health(&self) -> PolicyHealth {
+        self.policy.health().await
+    }
+
+    async fn warm_up(&self) -> Result<(), String> {
+        self.policy.warm_up().await
+    }
+
+    async fn on_start(&self) -> Result<(), String> {
+        self.policy.on_start().await
+    }
+
+    async fn on_shutdown(&self) {
+        self.policy.on_shutdown().await
+    }
+}
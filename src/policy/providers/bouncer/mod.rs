@@ -1,2 +1,5 @@
 pub mod authentication;
 pub mod authorization;
+pub mod development;
+pub mod logic;
+pub mod traffic;
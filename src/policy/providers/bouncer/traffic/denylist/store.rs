@@ -0,0 +1,393 @@
+use crate::admin::now_secs;
+use crate::config::DatabasesConfig;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Prefix of the Redis keys holding the shared denylist
+pub const KEY_PREFIX: &str = "bouncer:denylist:";
+
+/// How often each replica reloads the shared denylist
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Who a ban applies to, written as `ip:<address>` or `identity:<owner>`
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum BanSubject {
+    Ip(String),
+    /// The `x-bouncer-owner` set by an authentication policy
+    Identity(String),
+}
+
+impl TryFrom<String> for BanSubject {
+    type Error = String;
+
+    fn try_from(subject: String) -> Result<Self, Self::Error> {
+        match subject.split_once(':') {
+            Some(("ip", ip)) if !ip.is_empty() => Ok(Self::Ip(ip.to_string())),
+            Some(("identity", owner)) if !owner.is_empty() => Ok(Self::Identity(owner.to_string())),
+            _ => Err(format!(
+                "Invalid ban subject '{}'. Expected 'ip:<address>' or 'identity:<owner>'",
+                subject
+            )),
+        }
+    }
+}
+
+impl From<BanSubject> for String {
+    fn from(subject: BanSubject) -> Self {
+        subject.to_string()
+    }
+}
+
+impl fmt::Display for BanSubject {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ip(ip) => write!(f, "ip:{}", ip),
+            Self::Identity(owner) => write!(f, "identity:{}", owner),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BanEntry {
+    pub subject: BanSubject,
+    pub reason: String,
+    /// Unix timestamp in seconds when the ban ends
+    pub expires_at: i64,
+}
+
+/// Storage for ban entries shared by every replica
+#[async_trait]
+pub trait BanStore: Send + Sync + 'static {
+    async fn ban(&self, entry: &BanEntry) -> Result<(), DatabaseError>;
+
+    /// Lift a ban, returning whether there was one
+    async fn unban(&self, subject: &BanSubject) -> Result<bool, DatabaseError>;
+
+    /// Every ban that hasn't expired
+    async fn active(&self) -> Result<Vec<BanEntry>, DatabaseError>;
+}
+
+/// Bans stored in Redis
+///
+/// Entries are JSON values in the `{prefix}entries` hash, keyed by subject, with
+/// their expiry in the `{prefix}expiry` sorted set so expired bans can be swept.
+#[cfg(feature = "redis")]
+pub struct RedisBanStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisBanStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    fn entries_key(&self) -> String {
+        format!("{}entries", self.key_prefix)
+    }
+
+    fn expiry_key(&self) -> String {
+        format!("{}expiry", self.key_prefix)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl BanStore for RedisBanStore {
+    async fn ban(&self, entry: &BanEntry) -> Result<(), DatabaseError> {
+        let subject = entry.subject.to_string();
+        let value = serde_json::to_string(entry)
+            .map_err(|e| DatabaseError::ConversionError(e.to_string()))?;
+
+        redis::pipe()
+            .atomic()
+            .hset(self.entries_key(), &subject, value)
+            .zadd(self.expiry_key(), &subject, entry.expires_at)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn unban(&self, subject: &BanSubject) -> Result<bool, DatabaseError> {
+        let subject = subject.to_string();
+        let (removed, _): (u64, u64) = redis::pipe()
+            .atomic()
+            .hdel(self.entries_key(), &subject)
+            .zrem(self.expiry_key(), &subject)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(removed > 0)
+    }
+
+    async fn active(&self) -> Result<Vec<BanEntry>, DatabaseError> {
+        let mut connection = self.connection.clone();
+        let now = now_secs();
+
+        // Sweep expired bans so the hash doesn't grow forever
+        let expired: Vec<String> = redis::cmd("ZRANGEBYSCORE")
+            .arg(self.expiry_key())
+            .arg("-inf")
+            .arg(now)
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+        if !expired.is_empty() {
+            redis::pipe()
+                .atomic()
+                .hdel(self.entries_key(), &expired)
+                .zrem(self.expiry_key(), &expired)
+                .query_async::<_, ()>(&mut connection)
+                .await
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+        }
+
+        let values: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(self.entries_key())
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(values
+            .values()
+            .filter_map(|value| serde_json::from_str::<BanEntry>(value).ok())
+            .filter(|entry| entry.expires_at > now)
+            .collect())
+    }
+}
+
+/// Bans shared by every replica, checked against an in-memory copy
+///
+/// Checks never wait on Redis: each replica reloads the list every second, and
+/// bans made through this replica apply to it immediately.
+pub struct Denylist {
+    store: Arc<dyn BanStore>,
+    entries: RwLock<HashMap<BanSubject, BanEntry>>,
+}
+
+impl Denylist {
+    /// Load the current bans, failing if the store can't be read
+    pub async fn new(store: Arc<dyn BanStore>) -> Result<Arc<Self>, DatabaseError> {
+        let denylist = Arc::new(Self {
+            store,
+            entries: RwLock::new(HashMap::new()),
+        });
+
+        denylist.refresh().await?;
+        Ok(denylist)
+    }
+
+    /// Ban a subject on every replica for `ttl`
+    pub async fn ban(
+        &self,
+        subject: BanSubject,
+        ttl: Duration,
+        reason: impl Into<String>,
+    ) -> Result<BanEntry, DatabaseError> {
+        let entry = BanEntry {
+            subject,
+            reason: reason.into(),
+            expires_at: now_secs() + ttl.as_secs() as i64,
+        };
+
+        self.store.ban(&entry).await?;
+        self.entries
+            .write()
+            .unwrap()
+            .insert(entry.subject.clone(), entry.clone());
+        Ok(entry)
+    }
+
+    /// Lift a ban on every replica
+    pub async fn unban(&self, subject: &BanSubject) -> Result<bool, DatabaseError> {
+        let removed = self.store.unban(subject).await?;
+        self.entries.write().unwrap().remove(subject);
+        Ok(removed)
+    }
+
+    /// The active ban for a subject, if any
+    pub fn check(&self, subject: &BanSubject) -> Option<BanEntry> {
+        self.entries
+            .read()
+            .unwrap()
+            .get(subject)
+            .filter(|entry| entry.expires_at > now_secs())
+            .cloned()
+    }
+
+    /// Every active ban
+    pub fn entries(&self) -> Vec<BanEntry> {
+        let now = now_secs();
+        self.entries
+            .read()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    async fn refresh(&self) -> Result<(), DatabaseError> {
+        let entries = self
+            .store
+            .active()
+            .await?
+            .into_iter()
+            .map(|entry| (entry.subject.clone(), entry))
+            .collect();
+
+        *self.entries.write().unwrap() = entries;
+        Ok(())
+    }
+
+    /// Reload the bans every `interval`
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the denylist is dropped
+        let denylist = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, and the bans were just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(denylist) = denylist.upgrade() else {
+                    break;
+                };
+
+                if let Err(e) = denylist.refresh().await {
+                    tracing::warn!("Failed to refresh the denylist: {}", e);
+                }
+            }
+        });
+    }
+}
+
+// Denylist shared by every policy, created on first use from the global config
+static SHARED_DENYLIST: tokio::sync::OnceCell<Arc<Denylist>> = tokio::sync::OnceCell::const_new();
+
+/// Get the denylist stored in the global config's Redis database
+///
+/// Policies publish bans through this, and the denylist policy enforces them.
+pub async fn shared_denylist() -> Result<Arc<Denylist>, DatabaseError> {
+    SHARED_DENYLIST
+        .get_or_try_init(|| async {
+            let config = crate::GLOBAL_CONFIG.get().ok_or_else(|| {
+                DatabaseError::ConfigurationError(
+                    "Global configuration not initialized".to_string(),
+                )
+            })?;
+
+            let denylist = Denylist::new(create_redis_store(&config.databases).await?).await?;
+            denylist.spawn_refresh(REFRESH_INTERVAL);
+            Ok(denylist)
+        })
+        .await
+        .cloned()
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn BanStore>, DatabaseError> {
+    crate::database::validate_database_config(databases, "redis")?;
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisBanStore::new(&client, KEY_PREFIX.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn BanStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryBanStore {
+        entries: Mutex<HashMap<BanSubject, BanEntry>>,
+    }
+
+    #[async_trait]
+    impl BanStore for MemoryBanStore {
+        async fn ban(&self, entry: &BanEntry) -> Result<(), DatabaseError> {
+            let mut entries = self.entries.lock().unwrap();
+            entries.insert(entry.subject.clone(), entry.clone());
+            Ok(())
+        }
+
+        async fn unban(&self, subject: &BanSubject) -> Result<bool, DatabaseError> {
+            Ok(self.entries.lock().unwrap().remove(subject).is_some())
+        }
+
+        async fn active(&self) -> Result<Vec<BanEntry>, DatabaseError> {
+            Ok(self.entries.lock().unwrap().values().cloned().collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_denylist() {
+        let store = Arc::new(MemoryBanStore::default());
+        let denylist = Denylist::new(store.clone()).await.unwrap();
+        let ip = BanSubject::try_from("ip:203.0.113.7".to_string()).unwrap();
+
+        assert!(denylist.check(&ip).is_none());
+        denylist
+            .ban(
+                ip.clone(),
+                Duration::from_secs(60),
+                "too many failed logins",
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            denylist.check(&ip).unwrap().reason,
+            "too many failed logins"
+        );
+
+        // Bans made by other replicas show up after a refresh
+        let owner = BanSubject::Identity("alice".to_string());
+        store
+            .ban(&BanEntry {
+                subject: owner.clone(),
+                reason: "abuse".to_string(),
+                expires_at: now_secs() + 60,
+            })
+            .await
+            .unwrap();
+        assert!(denylist.check(&owner).is_none());
+        denylist.refresh().await.unwrap();
+        assert!(denylist.check(&owner).is_some());
+
+        assert!(denylist.unban(&ip).await.unwrap());
+        assert!(denylist.check(&ip).is_none());
+        assert!(BanSubject::try_from("user:alice".to_string()).is_err());
+    }
+}
@@ -0,0 +1,236 @@
+use super::store::{shared_denylist, BanEntry, BanSubject, Denylist};
+use crate::admin::{is_admin, json_error, now_secs};
+use crate::config::SecretString;
+use crate::policy::denial::Denial;
+use crate::policy::forwarded::client_ip;
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::Path,
+    http::{header, HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DenylistConfig {
+    /// Reject requests from banned IP addresses
+    #[serde(default = "default_true")]
+    pub check_ip: bool,
+    /// Reject requests whose `x-bouncer-owner` is banned. Only works after an
+    /// authentication policy in the chain
+    #[serde(default = "default_true")]
+    pub check_identity: bool,
+    /// Read the client IP from the first address in this header, e.g.
+    /// `X-Forwarded-For`, instead of the client IP resolved through
+    /// `server.trusted_proxies`
+    pub ip_header: Option<String>,
+    /// Token required by the ban management routes, which are disabled without it
+    pub admin_token: Option<SecretString>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize)]
+struct BanRequest {
+    subject: BanSubject,
+    ttl_secs: u64,
+    #[serde(default)]
+    reason: String,
+}
+
+pub struct DenylistPolicy {
+    config: DenylistConfig,
+    denylist: Arc<Denylist>,
+}
+
+impl DenylistPolicy {
+    fn client_ip(&self, request: &Request<Body>) -> Option<String> {
+        match &self.config.ip_header {
+            Some(name) => request
+                .headers()
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.split(',').next())
+                .map(|ip| ip.trim().to_string()),
+            None => client_ip(request).map(|ip| ip.to_string()),
+        }
+    }
+
+    fn banned_response(entry: &BanEntry) -> PolicyResult {
+        let retry_after = (entry.expires_at - now_secs()).max(0) as u64;
+        let mut response = Denial::new("banned", "Forbidden: banned")
+            .retry_after(retry_after)
+            .response(StatusCode::FORBIDDEN);
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, retry_after.into());
+        PolicyResult::Terminate(response)
+    }
+}
+
+pub struct DenylistPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for DenylistPolicyFactory {
+    type PolicyType = DenylistPolicy;
+    type Config = DenylistConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::traffic::denylist::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        let denylist = shared_denylist().await.map_err(|e| e.to_string())?;
+        Ok(DenylistPolicy { config, denylist })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if !config.check_ip && !config.check_identity {
+            return Err("At least one of check_ip and check_identity must be enabled".to_string());
+        }
+
+        if let Some(name) = &config.ip_header {
+            header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| format!("Invalid ip_header '{}': {}", name, e))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for DenylistPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "traffic"
+    }
+
+    fn name(&self) -> &'static str {
+        "denylist"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        let Some(admin_token) = self.config.admin_token.clone() else {
+            return vec![];
+        };
+        let admin_token: Arc<str> = Arc::from(admin_token.expose());
+
+        let list = {
+            let (denylist, admin_token) = (self.denylist.clone(), admin_token.clone());
+            move |headers: HeaderMap| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                Json(denylist.entries()).into_response()
+            }
+        };
+
+        let ban = {
+            let (denylist, admin_token) = (self.denylist.clone(), admin_token.clone());
+            move |headers: HeaderMap, Json(request): Json<BanRequest>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                if request.ttl_secs == 0 {
+                    return json_error(StatusCode::BAD_REQUEST, "ttl_secs must be greater than 0");
+                }
+
+                match denylist
+                    .ban(
+                        request.subject,
+                        Duration::from_secs(request.ttl_secs),
+                        request.reason,
+                    )
+                    .await
+                {
+                    Ok(entry) => (StatusCode::CREATED, Json(entry)).into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let unban = {
+            let denylist = self.denylist.clone();
+            move |headers: HeaderMap, Path(subject): Path<String>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                let subject = match BanSubject::try_from(subject) {
+                    Ok(subject) => subject,
+                    Err(e) => return json_error(StatusCode::BAD_REQUEST, e),
+                };
+
+                match denylist.unban(&subject).await {
+                    Ok(true) => StatusCode::NO_CONTENT.into_response(),
+                    Ok(false) => json_error(StatusCode::NOT_FOUND, "Ban not found"),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        vec![
+            RouteRegistration {
+                relative_path: "bans".to_string(),
+                handler: get(list).post(ban),
+            },
+            RouteRegistration {
+                relative_path: "bans/{subject}".to_string(),
+                handler: delete(unban),
+            },
+        ]
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        // Only the in-memory copy is checked, so this never waits on Redis
+        if self.config.check_ip {
+            if let Some(ip) = self.client_ip(&request) {
+                if let Some(entry) = self.denylist.check(&BanSubject::Ip(ip)) {
+                    return Self::banned_response(&entry);
+                }
+            }
+        }
+
+        if self.config.check_identity {
+            let owner = request
+                .headers()
+                .get("x-bouncer-owner")
+                .and_then(|value| value.to_str().ok());
+            if let Some(owner) = owner {
+                if let Some(entry) = self
+                    .denylist
+                    .check(&BanSubject::Identity(owner.to_string()))
+                {
+                    return Self::banned_response(&entry);
+                }
+            }
+        }
+
+        PolicyResult::Continue(request)
+    }
+}
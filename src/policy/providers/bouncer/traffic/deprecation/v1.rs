@@ -0,0 +1,343 @@
+use crate::admin::now_secs;
+use crate::cache::{BoundedCache, CacheLimits};
+use crate::policy::denial::Denial;
+use crate::policy::forwarded::client_ip;
+use crate::policy::matcher::RouteMatcher;
+use crate::policy::schedule::{http_date, Timestamp};
+use crate::policy::traits::{add_response_header, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{header, HeaderName, HeaderValue, Request, StatusCode},
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeprecatedRoute {
+    /// Route pattern of the deprecated endpoints
+    pub pattern: String,
+    /// When the routes were deprecated, as RFC 3339 or Unix seconds
+    pub deprecated_at: Timestamp,
+    /// When the routes stop working
+    pub sunset: Option<Timestamp>,
+    /// Documentation of the deprecation, e.g. a migration guide
+    pub link: Option<String>,
+    /// The route that replaces this one
+    pub replacement: Option<String>,
+    /// Reject requests after the sunset instead of only announcing it
+    #[serde(default)]
+    pub enforce: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeprecationConfig {
+    pub routes: Vec<DeprecatedRoute>,
+    /// How often each caller of a deprecated route is logged
+    #[serde(default = "default_log_interval_secs")]
+    pub log_interval_secs: u64,
+}
+
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+fn default_log_interval_secs() -> u64 {
+    3600
+}
+
+/// Details of the denial returned for deprecated routes after their enforced
+/// sunset
+#[derive(Debug, Clone, Serialize)]
+pub struct SunsetResponse {
+    pub sunset: String,
+    pub link: Option<String>,
+    pub replacement: Option<String>,
+}
+
+// A deprecated route with its compiled pattern and response headers
+struct Deprecation {
+    route: DeprecatedRoute,
+    matcher: RouteMatcher,
+    deprecation: HeaderValue,
+    sunset: Option<HeaderValue>,
+    link: Option<HeaderValue>,
+}
+
+impl Deprecation {
+    fn new(route: DeprecatedRoute) -> Result<Self, String> {
+        let matcher = RouteMatcher::parse(&route.pattern)?;
+        let invalid = |e| format!("Invalid header value for '{}': {}", route.pattern, e);
+
+        // Structured field date, as in RFC 9745
+        let deprecation =
+            HeaderValue::from_str(&format!("@{}", route.deprecated_at.0)).map_err(invalid)?;
+        let sunset = route
+            .sunset
+            .map(|Timestamp(sunset)| HeaderValue::from_str(&http_date(sunset)))
+            .transpose()
+            .map_err(invalid)?;
+
+        let links: Vec<String> = [
+            route
+                .link
+                .as_ref()
+                .map(|link| format!("<{}>; rel=\"deprecation\"", link)),
+            route
+                .replacement
+                .as_ref()
+                .map(|replacement| format!("<{}>; rel=\"successor-version\"", replacement)),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let link = if links.is_empty() {
+            None
+        } else {
+            Some(HeaderValue::from_str(&links.join(", ")).map_err(invalid)?)
+        };
+
+        Ok(Self {
+            route,
+            matcher,
+            deprecation,
+            sunset,
+            link,
+        })
+    }
+
+    fn is_past_sunset(&self, now: i64) -> bool {
+        self.route.enforce
+            && self
+                .route
+                .sunset
+                .is_some_and(|Timestamp(sunset)| now >= sunset)
+    }
+
+    fn headers(&self) -> impl Iterator<Item = (HeaderName, HeaderValue)> + '_ {
+        [
+            (DEPRECATION, Some(&self.deprecation)),
+            (SUNSET, self.sunset.as_ref()),
+            (header::LINK, self.link.as_ref()),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| Some((name, value?.clone())))
+    }
+}
+
+/// Announces the deprecation and sunset of routes to their callers, logs who
+/// still calls them, and optionally turns them off after the sunset
+pub struct DeprecationPolicy {
+    config: DeprecationConfig,
+    routes: Vec<Deprecation>,
+    // When each caller of each route was last logged
+    logged: Arc<BoundedCache<(usize, String), i64>>,
+}
+
+impl DeprecationPolicy {
+    // Log a caller of a deprecated route at most once per interval
+    fn log_caller(&self, index: usize, request: &Request<Body>, now: i64) {
+        let owner = request
+            .headers()
+            .get("x-bouncer-owner")
+            .and_then(|value| value.to_str().ok())
+            .map(|owner| format!("owner {}", owner));
+        let ip = || client_ip(request).map(|ip| format!("ip {}", ip));
+        let caller = owner.or_else(ip).unwrap_or_else(|| "unknown".to_string());
+
+        let key = (index, caller);
+        let interval = self.config.log_interval_secs as i64;
+        if self
+            .logged
+            .get(&key)
+            .is_some_and(|logged| now - logged < interval)
+        {
+            return;
+        }
+
+        let route = &self.routes[index].route;
+        tracing::warn!(
+            "Deprecated route {} called by {}: {} {}",
+            route.pattern,
+            key.1,
+            request.method(),
+            request.uri().path()
+        );
+        self.logged.insert(key, now);
+    }
+
+    fn sunset(&self, deprecation: &Deprecation) -> PolicyResult {
+        let route = &deprecation.route;
+        let sunset = route.sunset.map(|Timestamp(sunset)| http_date(sunset));
+        let message = match &route.replacement {
+            Some(replacement) => {
+                format!("This route has been retired; use {} instead", replacement)
+            }
+            None => "This route has been retired".to_string(),
+        };
+        let details = SunsetResponse {
+            sunset: sunset.unwrap_or_default(),
+            link: route.link.clone(),
+            replacement: route.replacement.clone(),
+        };
+
+        let mut response = Denial::new("sunset", message)
+            .details(details)
+            .response(StatusCode::GONE);
+        response.headers_mut().extend(deprecation.headers());
+        PolicyResult::Terminate(response)
+    }
+}
+
+pub struct DeprecationPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for DeprecationPolicyFactory {
+    type PolicyType = DeprecationPolicy;
+    type Config = DeprecationConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::traffic::deprecation::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+
+        let routes = config
+            .routes
+            .iter()
+            .cloned()
+            .map(Deprecation::new)
+            .collect::<Result<_, _>>()?;
+        Ok(DeprecationPolicy {
+            config,
+            routes,
+            logged: BoundedCache::new("deprecated_route_callers", CacheLimits::default()),
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.routes.is_empty() {
+            return Err("At least one route must be configured".to_string());
+        }
+
+        for route in &config.routes {
+            Deprecation::new(route.clone())?;
+            if route.enforce && route.sunset.is_none() {
+                return Err(format!(
+                    "Route '{}': enforce requires sunset",
+                    route.pattern
+                ));
+            }
+            if route
+                .sunset
+                .is_some_and(|sunset| sunset < route.deprecated_at)
+            {
+                return Err(format!(
+                    "Route '{}': sunset must not be before deprecated_at",
+                    route.pattern
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for DeprecationPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "traffic"
+    }
+
+    fn name(&self) -> &'static str {
+        "deprecation"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        // The first matching route applies
+        let Some(index) = self.routes.iter().position(|route| {
+            route
+                .matcher
+                .matches(request.method(), request.uri().path())
+        }) else {
+            return PolicyResult::Continue(request);
+        };
+
+        let now = now_secs();
+        self.log_caller(index, &request, now);
+
+        let deprecation = &self.routes[index];
+        if deprecation.is_past_sunset(now) {
+            return self.sunset(deprecation);
+        }
+
+        for (name, value) in deprecation.headers() {
+            add_response_header(&mut request, name, value);
+        }
+        PolicyResult::Continue(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deprecated_routes() {
+        let route = |pattern: &str, sunset: i64| DeprecatedRoute {
+            pattern: pattern.to_string(),
+            deprecated_at: Timestamp(1738292400),
+            sunset: Some(Timestamp(sunset)),
+            link: Some("https://example.com/migrate".to_string()),
+            replacement: Some("/v2/orders".to_string()),
+            enforce: true,
+        };
+        let policy = DeprecationPolicyFactory::new(DeprecationConfig {
+            routes: vec![
+                route("/v1/orders", 4102444800),
+                route("/v1/users", 1738292400),
+            ],
+            log_interval_secs: default_log_interval_secs(),
+        })
+        .await
+        .unwrap();
+
+        let request = Request::get("/v1/orders").body(Body::empty()).unwrap();
+        let PolicyResult::Continue(request) = policy.process(request).await else {
+            panic!("expected the request to pass");
+        };
+        let headers = &request
+            .extensions()
+            .get::<crate::policy::traits::ResponseHeaders>()
+            .unwrap()
+            .0;
+        assert_eq!(headers["deprecation"], "@1738292400");
+        assert_eq!(
+            headers[header::LINK],
+            "<https://example.com/migrate>; rel=\"deprecation\", </v2/orders>; rel=\"successor-version\""
+        );
+
+        // Past an enforced sunset
+        let request = Request::get("/v1/users").body(Body::empty()).unwrap();
+        let PolicyResult::Terminate(response) = policy.process(request).await else {
+            panic!("expected a rejection");
+        };
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert_eq!(
+            response.headers()["sunset"],
+            "Fri, 31 Jan 2025 03:00:00 GMT"
+        );
+    }
+}
@@ -0,0 +1,7 @@
+pub mod denylist;
+pub mod deprecation;
+pub mod rate_limit;
+pub mod residency;
+pub mod rewrite;
+pub mod versioning;
+pub mod websocket;
@@ -0,0 +1,480 @@
+use crate::admin::now_secs;
+use crate::config::DatabasesConfig;
+use crate::database::DatabaseError;
+use crate::policy::providers::bouncer::traffic::denylist::store::BanSubject;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Prefix of the Redis keys holding the shared rate limit overrides
+pub const KEY_PREFIX: &str = "bouncer:rate-limit:overrides:";
+
+/// How often each replica reloads the shared overrides
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Most audit records kept
+#[cfg(feature = "redis")]
+const AUDIT_LENGTH: isize = 1000;
+
+/// A temporary exception to rate limits for one client
+///
+/// The subject is written like a ban's, as `ip:<address>` or `identity:<owner>`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimitOverride {
+    pub subject: BanSubject,
+    /// Requests allowed per window instead of the policy's, or none to exempt
+    /// the subject from rate limits entirely
+    pub requests: Option<u64>,
+    pub reason: String,
+    /// Who granted the override, for the audit trail
+    pub granted_by: String,
+    /// Unix timestamps in seconds
+    pub created_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditAction {
+    Granted,
+    Revoked,
+}
+
+/// A change to the overrides, kept for the audit trail
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    pub action: AuditAction,
+    pub subject: BanSubject,
+    pub requests: Option<u64>,
+    pub reason: String,
+    pub actor: String,
+    /// Unix timestamp in seconds
+    pub at: i64,
+}
+
+/// Storage for overrides and their audit trail, shared by every replica
+#[async_trait]
+pub trait OverrideStore: Send + Sync + 'static {
+    async fn grant(&self, entry: &RateLimitOverride) -> Result<(), DatabaseError>;
+
+    /// Remove an override, returning whether there was one
+    async fn revoke(&self, subject: &BanSubject) -> Result<bool, DatabaseError>;
+
+    /// Every override that hasn't expired
+    async fn active(&self) -> Result<Vec<RateLimitOverride>, DatabaseError>;
+
+    async fn record(&self, record: &AuditRecord) -> Result<(), DatabaseError>;
+
+    /// The most recent audit records, newest first
+    async fn audit(&self, limit: usize) -> Result<Vec<AuditRecord>, DatabaseError>;
+}
+
+/// Overrides stored in Redis
+///
+/// Entries are JSON values in the `{prefix}entries` hash, keyed by subject, with
+/// their expiry in the `{prefix}expiry` sorted set so expired overrides can be
+/// swept. Audit records are pushed onto the `{prefix}audit` list.
+#[cfg(feature = "redis")]
+pub struct RedisOverrideStore {
+    connection: redis::aio::MultiplexedConnection,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisOverrideStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            key_prefix,
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.key_prefix, name)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl OverrideStore for RedisOverrideStore {
+    async fn grant(&self, entry: &RateLimitOverride) -> Result<(), DatabaseError> {
+        let subject = entry.subject.to_string();
+        let value =
+            serde_json::to_string(entry).map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        redis::pipe()
+            .atomic()
+            .hset(self.key("entries"), &subject, value)
+            .zadd(self.key("expiry"), &subject, entry.expires_at)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn revoke(&self, subject: &BanSubject) -> Result<bool, DatabaseError> {
+        let subject = subject.to_string();
+        let (removed, _): (u64, u64) = redis::pipe()
+            .atomic()
+            .hdel(self.key("entries"), &subject)
+            .zrem(self.key("expiry"), &subject)
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(removed > 0)
+    }
+
+    async fn active(&self) -> Result<Vec<RateLimitOverride>, DatabaseError> {
+        let mut connection = self.connection.clone();
+        let now = now_secs();
+
+        // Sweep expired overrides so the hash doesn't grow forever
+        let expired: Vec<String> = redis::cmd("ZRANGEBYSCORE")
+            .arg(self.key("expiry"))
+            .arg("-inf")
+            .arg(now)
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+        if !expired.is_empty() {
+            redis::pipe()
+                .atomic()
+                .hdel(self.key("entries"), &expired)
+                .zrem(self.key("expiry"), &expired)
+                .query_async::<_, ()>(&mut connection)
+                .await
+                .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+        }
+
+        let values: HashMap<String, String> = redis::cmd("HGETALL")
+            .arg(self.key("entries"))
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(values
+            .values()
+            .filter_map(|value| serde_json::from_str::<RateLimitOverride>(value).ok())
+            .filter(|entry| entry.expires_at > now)
+            .collect())
+    }
+
+    async fn record(&self, record: &AuditRecord) -> Result<(), DatabaseError> {
+        let value =
+            serde_json::to_string(record).map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        redis::pipe()
+            .atomic()
+            .lpush(self.key("audit"), value)
+            .ltrim(self.key("audit"), 0, AUDIT_LENGTH - 1)
+            .query_async::<_, ()>(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))
+    }
+
+    async fn audit(&self, limit: usize) -> Result<Vec<AuditRecord>, DatabaseError> {
+        let values: Vec<String> = redis::cmd("LRANGE")
+            .arg(self.key("audit"))
+            .arg(0)
+            .arg(limit.saturating_sub(1))
+            .query_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        Ok(values
+            .iter()
+            .filter_map(|value| serde_json::from_str(value).ok())
+            .collect())
+    }
+}
+
+/// Overrides shared by every replica, checked against an in-memory copy
+///
+/// Like the denylist, checks never wait on Redis: each replica reloads the
+/// overrides every second, and changes made through this replica apply to it
+/// immediately.
+pub struct Overrides {
+    store: Arc<dyn OverrideStore>,
+    entries: RwLock<HashMap<BanSubject, RateLimitOverride>>,
+}
+
+impl Overrides {
+    /// Load the current overrides, failing if the store can't be read
+    pub async fn new(store: Arc<dyn OverrideStore>) -> Result<Arc<Self>, DatabaseError> {
+        let overrides = Arc::new(Self {
+            store,
+            entries: RwLock::new(HashMap::new()),
+        });
+
+        overrides.refresh().await?;
+        Ok(overrides)
+    }
+
+    /// Grant an override on every replica for `ttl`
+    pub async fn grant(
+        &self,
+        subject: BanSubject,
+        requests: Option<u64>,
+        ttl: Duration,
+        reason: impl Into<String>,
+        granted_by: impl Into<String>,
+    ) -> Result<RateLimitOverride, DatabaseError> {
+        let now = now_secs();
+        let entry = RateLimitOverride {
+            subject,
+            requests,
+            reason: reason.into(),
+            granted_by: granted_by.into(),
+            created_at: now,
+            expires_at: now + ttl.as_secs() as i64,
+        };
+
+        self.store.grant(&entry).await?;
+        self.entries
+            .write()
+            .unwrap()
+            .insert(entry.subject.clone(), entry.clone());
+        self.audit_change(AuditRecord {
+            action: AuditAction::Granted,
+            subject: entry.subject.clone(),
+            requests,
+            reason: entry.reason.clone(),
+            actor: entry.granted_by.clone(),
+            at: now,
+        })
+        .await;
+        Ok(entry)
+    }
+
+    /// Remove an override on every replica
+    pub async fn revoke(
+        &self,
+        subject: &BanSubject,
+        revoked_by: impl Into<String>,
+    ) -> Result<bool, DatabaseError> {
+        let removed = self.store.revoke(subject).await?;
+        let entry = self.entries.write().unwrap().remove(subject);
+        if removed {
+            self.audit_change(AuditRecord {
+                action: AuditAction::Revoked,
+                subject: subject.clone(),
+                requests: entry.and_then(|entry| entry.requests),
+                reason: String::new(),
+                actor: revoked_by.into(),
+                at: now_secs(),
+            })
+            .await;
+        }
+        Ok(removed)
+    }
+
+    // Log a change and keep it in the shared audit trail
+    async fn audit_change(&self, record: AuditRecord) {
+        tracing::info!(
+            action = ?record.action,
+            subject = %record.subject,
+            requests = ?record.requests,
+            actor = %record.actor,
+            "Rate limit override changed: {}",
+            record.reason
+        );
+        if let Err(e) = self.store.record(&record).await {
+            tracing::error!("Failed to record rate limit override change: {}", e);
+        }
+    }
+
+    /// The active override for a subject, if any
+    pub fn check(&self, subject: &BanSubject) -> Option<RateLimitOverride> {
+        self.entries
+            .read()
+            .unwrap()
+            .get(subject)
+            .filter(|entry| entry.expires_at > now_secs())
+            .cloned()
+    }
+
+    /// Every active override
+    pub fn entries(&self) -> Vec<RateLimitOverride> {
+        let now = now_secs();
+        self.entries
+            .read()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.expires_at > now)
+            .cloned()
+            .collect()
+    }
+
+    /// The most recent changes, newest first
+    pub async fn audit(&self, limit: usize) -> Result<Vec<AuditRecord>, DatabaseError> {
+        self.store.audit(limit).await
+    }
+
+    async fn refresh(&self) -> Result<(), DatabaseError> {
+        let entries = self
+            .store
+            .active()
+            .await?
+            .into_iter()
+            .map(|entry| (entry.subject.clone(), entry))
+            .collect();
+
+        *self.entries.write().unwrap() = entries;
+        Ok(())
+    }
+
+    /// Reload the overrides every `interval`
+    pub fn spawn_refresh(self: &Arc<Self>, interval: Duration) {
+        // Hold a weak reference so the task stops once the overrides are dropped
+        let overrides = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            // The first tick completes immediately, and the overrides were just loaded
+            ticker.tick().await;
+            loop {
+                ticker.tick().await;
+                let Some(overrides) = overrides.upgrade() else {
+                    break;
+                };
+
+                if let Err(e) = overrides.refresh().await {
+                    tracing::warn!("Failed to refresh rate limit overrides: {}", e);
+                }
+            }
+        });
+    }
+}
+
+// Overrides shared by every rate limit policy, created on first use from the global config
+static SHARED_OVERRIDES: tokio::sync::OnceCell<Arc<Overrides>> = tokio::sync::OnceCell::const_new();
+
+/// Get the overrides stored in the global config's Redis database
+pub async fn shared_overrides() -> Result<Arc<Overrides>, DatabaseError> {
+    SHARED_OVERRIDES
+        .get_or_try_init(|| async {
+            let config = crate::GLOBAL_CONFIG.get().ok_or_else(|| {
+                DatabaseError::ConfigurationError(
+                    "Global configuration not initialized".to_string(),
+                )
+            })?;
+
+            let overrides = Overrides::new(create_redis_store(&config.databases).await?).await?;
+            overrides.spawn_refresh(REFRESH_INTERVAL);
+            Ok(overrides)
+        })
+        .await
+        .cloned()
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn OverrideStore>, DatabaseError> {
+    crate::database::validate_database_config(databases, "redis")?;
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+
+    let client = crate::database::get_redis_client(redis_config).await?;
+    Ok(Arc::new(
+        RedisOverrideStore::new(&client, KEY_PREFIX.to_string()).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn OverrideStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct MemoryOverrideStore {
+        entries: Mutex<HashMap<BanSubject, RateLimitOverride>>,
+        audit: Mutex<Vec<AuditRecord>>,
+    }
+
+    #[async_trait]
+    impl OverrideStore for MemoryOverrideStore {
+        async fn grant(&self, entry: &RateLimitOverride) -> Result<(), DatabaseError> {
+            let mut entries = self.entries.lock().unwrap();
+            entries.insert(entry.subject.clone(), entry.clone());
+            Ok(())
+        }
+
+        async fn revoke(&self, subject: &BanSubject) -> Result<bool, DatabaseError> {
+            Ok(self.entries.lock().unwrap().remove(subject).is_some())
+        }
+
+        async fn active(&self) -> Result<Vec<RateLimitOverride>, DatabaseError> {
+            Ok(self.entries.lock().unwrap().values().cloned().collect())
+        }
+
+        async fn record(&self, record: &AuditRecord) -> Result<(), DatabaseError> {
+            self.audit.lock().unwrap().insert(0, record.clone());
+            Ok(())
+        }
+
+        async fn audit(&self, limit: usize) -> Result<Vec<AuditRecord>, DatabaseError> {
+            let audit = self.audit.lock().unwrap();
+            Ok(audit.iter().take(limit).cloned().collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_overrides() {
+        let store = Arc::new(MemoryOverrideStore::default());
+        let overrides = Overrides::new(store.clone()).await.unwrap();
+        let alice = BanSubject::Identity("alice".to_string());
+
+        overrides
+            .grant(
+                alice.clone(),
+                Some(10_000),
+                Duration::from_secs(3600),
+                "load test",
+                "ops@example.com",
+            )
+            .await
+            .unwrap();
+        assert_eq!(overrides.check(&alice).unwrap().requests, Some(10_000));
+
+        // Overrides granted by other replicas show up after a refresh
+        let ip = BanSubject::Ip("203.0.113.7".to_string());
+        store
+            .grant(&RateLimitOverride {
+                subject: ip.clone(),
+                requests: None,
+                reason: "partner".to_string(),
+                granted_by: "support".to_string(),
+                created_at: now_secs(),
+                expires_at: now_secs() + 60,
+            })
+            .await
+            .unwrap();
+        assert!(overrides.check(&ip).is_none());
+        overrides.refresh().await.unwrap();
+        assert!(overrides.check(&ip).is_some());
+
+        assert!(overrides.revoke(&alice, "ops@example.com").await.unwrap());
+        assert!(overrides.check(&alice).is_none());
+        let audit = overrides.audit(10).await.unwrap();
+        assert_eq!(audit.len(), 2);
+        assert_eq!(audit[0].action, AuditAction::Revoked);
+        assert_eq!(audit[0].requests, Some(10_000));
+    }
+}
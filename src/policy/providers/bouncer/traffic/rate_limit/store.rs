@@ -0,0 +1,216 @@
+use crate::cache::{BoundedCache, CacheLimits};
+use crate::config::DatabasesConfig;
+use crate::database::DatabaseError;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Outcome of counting a request against a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    pub allowed: bool,
+    /// Requests left in the current window
+    pub remaining: u64,
+    /// Time until the current window resets
+    pub reset_after: Duration,
+}
+
+impl RateLimitDecision {
+    fn from_count(count: u64, limit: u64, reset_after: Duration) -> Self {
+        Self {
+            allowed: count <= limit,
+            remaining: limit.saturating_sub(count),
+            reset_after,
+        }
+    }
+}
+
+/// Storage for rate limit counters
+///
+/// The rate limit policy only talks to this trait, so new backends (e.g. an
+/// in-cluster gossip limiter) can be added without touching the policy.
+/// Implementations must count atomically, since many requests for the same key
+/// are processed concurrently, possibly on different replicas.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Count one request for `key` in its current fixed window of length `window`
+    async fn hit(
+        &self,
+        key: &str,
+        limit: u64,
+        window: Duration,
+    ) -> Result<RateLimitDecision, DatabaseError>;
+
+    /// Check that counters can be reached
+    async fn health(&self) -> Result<(), DatabaseError> {
+        Ok(())
+    }
+}
+
+/// Counters kept in process memory, limited to each replica
+pub struct MemoryRateLimitStore {
+    // Request count and window start for every key
+    windows: Arc<BoundedCache<String, (u64, Instant)>>,
+}
+
+impl MemoryRateLimitStore {
+    pub fn new(limits: CacheLimits) -> Self {
+        Self {
+            windows: BoundedCache::new("rate_limit_windows", limits),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryRateLimitStore {
+    async fn hit(
+        &self,
+        key: &str,
+        limit: u64,
+        window: Duration,
+    ) -> Result<RateLimitDecision, DatabaseError> {
+        let now = Instant::now();
+        let (count, started) = self
+            .windows
+            .update(key.to_string(), |current| match current {
+                Some(&(count, started)) if now.duration_since(started) < window => {
+                    (count + 1, started)
+                }
+                _ => (1, now),
+            });
+
+        let reset_after = window.saturating_sub(now.duration_since(started));
+        Ok(RateLimitDecision::from_count(count, limit, reset_after))
+    }
+}
+
+// Increment the counter and start its expiry on the first hit, in one atomic step
+#[cfg(feature = "redis")]
+const REDIS_HIT_SCRIPT: &str = r#"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('PEXPIRE', KEYS[1], ARGV[1])
+end
+return {count, redis.call('PTTL', KEYS[1])}
+"#;
+
+/// Counters shared by all replicas through Redis
+#[cfg(feature = "redis")]
+pub struct RedisRateLimitStore {
+    connection: redis::aio::MultiplexedConnection,
+    script: redis::Script,
+    key_prefix: String,
+}
+
+#[cfg(feature = "redis")]
+impl RedisRateLimitStore {
+    pub async fn new(client: &redis::Client, key_prefix: String) -> Result<Self, DatabaseError> {
+        let connection = client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))?;
+
+        Ok(Self {
+            connection,
+            script: redis::Script::new(REDIS_HIT_SCRIPT),
+            key_prefix,
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl RateLimitStore for RedisRateLimitStore {
+    async fn hit(
+        &self,
+        key: &str,
+        limit: u64,
+        window: Duration,
+    ) -> Result<RateLimitDecision, DatabaseError> {
+        let window_ms = window.as_millis().max(1) as u64;
+        let (count, ttl_ms): (u64, i64) = self
+            .script
+            .key(format!("{}{}", self.key_prefix, key))
+            .arg(window_ms)
+            .invoke_async(&mut self.connection.clone())
+            .await
+            .map_err(|e| DatabaseError::QueryError(e.to_string()))?;
+
+        // PTTL is negative if the key has no expiry, which the script prevents
+        let reset_after = Duration::from_millis(ttl_ms.max(0) as u64);
+        Ok(RateLimitDecision::from_count(count, limit, reset_after))
+    }
+
+    async fn health(&self) -> Result<(), DatabaseError> {
+        redis::cmd("PING")
+            .query_async::<_, String>(&mut self.connection.clone())
+            .await
+            .map(|_| ())
+            .map_err(|e| DatabaseError::ConnectionError(e.to_string()))
+    }
+}
+
+/// Backend used to store rate limit counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RateLimitBackend {
+    #[default]
+    Memory,
+    Redis,
+}
+
+/// Create the rate limit store selected in a policy's config
+pub async fn create_store(
+    backend: RateLimitBackend,
+    limits: CacheLimits,
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn RateLimitStore>, DatabaseError> {
+    match backend {
+        RateLimitBackend::Memory => Ok(Arc::new(MemoryRateLimitStore::new(limits))),
+        RateLimitBackend::Redis => create_redis_store(databases).await,
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn create_redis_store(
+    databases: &DatabasesConfig,
+) -> Result<Arc<dyn RateLimitStore>, DatabaseError> {
+    crate::database::validate_database_config(databases, "redis")?;
+    let redis_config = databases.redis.as_ref().ok_or_else(|| {
+        DatabaseError::ConfigurationError("Redis configuration is required".to_string())
+    })?;
+
+    let client = crate::database::get_redis_client(redis_config).await?;
+    let store = RedisRateLimitStore::new(&client, "bouncer:rate-limit:".to_string()).await?;
+    Ok(Arc::new(store))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn create_redis_store(
+    _databases: &DatabasesConfig,
+) -> Result<Arc<dyn RateLimitStore>, DatabaseError> {
+    Err(DatabaseError::ConfigurationError(
+        "Redis support is not enabled. Rebuild with the 'redis' feature.".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryRateLimitStore::new(CacheLimits::default());
+        let window = Duration::from_secs(60);
+
+        assert!(store.hit("a", 2, window).await.unwrap().allowed);
+        let decision = store.hit("a", 2, window).await.unwrap();
+        assert!(decision.allowed);
+        assert_eq!(decision.remaining, 0);
+        assert!(!store.hit("a", 2, window).await.unwrap().allowed);
+
+        // Keys are counted separately
+        assert!(store.hit("b", 2, window).await.unwrap().allowed);
+    }
+}
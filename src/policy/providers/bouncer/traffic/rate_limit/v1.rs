@@ -0,0 +1,437 @@
+use super::overrides::{shared_overrides, Overrides};
+use super::store::{create_store, RateLimitBackend, RateLimitDecision, RateLimitStore};
+use crate::admin::{is_admin, json_error};
+use crate::cache::CacheLimits;
+use crate::config::SecretString;
+use crate::policy::denial::Denial;
+use crate::policy::forwarded::client_ip;
+use crate::policy::providers::bouncer::traffic::denylist::store::{
+    shared_denylist, BanSubject, Denylist,
+};
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyFactory, PolicyHealth, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    http::{header, HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Number of requests allowed per window
+    pub requests: u64,
+    /// Window length in seconds
+    pub window_secs: u64,
+    /// What to count requests by: "ip", "role" or "header:<name>"
+    #[serde(default = "default_key")]
+    pub key: String,
+    /// Where counters are stored: "memory" (per replica) or "redis" (shared)
+    #[serde(default)]
+    pub store: RateLimitBackend,
+    /// Limits for the in-memory store
+    #[serde(default)]
+    pub limits: CacheLimits,
+    /// Ban IPs that hit the limit for this many seconds, on every replica,
+    /// through the shared denylist. Requires `key: ip`
+    pub ban_ttl_secs: Option<u64>,
+    /// Apply the shared rate limit overrides granted through the admin routes
+    #[serde(default)]
+    pub overrides: bool,
+    /// Token required by the override management routes, which are disabled without it
+    pub admin_token: Option<SecretString>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OverrideRequest {
+    subject: BanSubject,
+    /// Requests allowed per window, or none to exempt the subject
+    requests: Option<u64>,
+    ttl_secs: u64,
+    reason: String,
+    #[serde(default)]
+    granted_by: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RevokeQuery {
+    revoked_by: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    #[serde(default = "default_audit_limit")]
+    limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+/// A client's standing against a rate limit, after counting the current request
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Quota {
+    /// What requests are counted by, e.g. `role`
+    pub key: String,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Seconds until the window resets
+    pub reset_secs: u64,
+}
+
+/// Quotas of the rate limits a request passed, added to its extensions so
+/// handlers like the developer portal can report them
+#[derive(Debug, Clone, Default)]
+pub struct Quotas(pub Vec<Quota>);
+
+fn default_key() -> String {
+    "ip".to_string()
+}
+
+// What requests are grouped by when counting
+enum RateLimitKey {
+    Ip,
+    Role,
+    Header(header::HeaderName),
+}
+
+impl RateLimitKey {
+    fn parse(key: &str) -> Result<Self, String> {
+        match key {
+            "ip" => Ok(Self::Ip),
+            "role" => Ok(Self::Role),
+            _ => match key.strip_prefix("header:") {
+                Some(name) => header::HeaderName::from_bytes(name.trim().as_bytes())
+                    .map(Self::Header)
+                    .map_err(|e| format!("Invalid rate limit header '{}': {}", name, e)),
+                None => Err(format!(
+                    "Invalid rate limit key '{}'. Expected 'ip', 'role' or 'header:<name>'",
+                    key
+                )),
+            },
+        }
+    }
+
+    fn extract(&self, request: &Request<Body>) -> Option<String> {
+        match self {
+            Self::Ip => client_ip(request).map(|ip| ip.to_string()),
+            Self::Role => header_value(request, "x-bouncer-role"),
+            Self::Header(name) => header_value(request, name.as_str()),
+        }
+    }
+}
+
+fn header_value(request: &Request<Body>, name: &str) -> Option<String> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string())
+}
+
+pub struct RateLimitPolicy {
+    config: RateLimitConfig,
+    key: RateLimitKey,
+    store: Arc<dyn RateLimitStore>,
+    denylist: Option<Arc<Denylist>>,
+    overrides: Option<Arc<Overrides>>,
+}
+
+impl RateLimitPolicy {
+    // The limit for a client, or none if an override exempts it
+    fn limit_for(&self, request: &Request<Body>) -> Option<u64> {
+        let Some(overrides) = &self.overrides else {
+            return Some(self.config.requests);
+        };
+
+        // Overrides are granted to an identity or an IP, whatever the policy counts by
+        let owner = header_value(request, "x-bouncer-owner").map(BanSubject::Identity);
+        let ip = RateLimitKey::Ip.extract(request).map(BanSubject::Ip);
+        match owner
+            .iter()
+            .chain(ip.iter())
+            .find_map(|subject| overrides.check(subject))
+        {
+            Some(entry) => entry.requests,
+            None => Some(self.config.requests),
+        }
+    }
+
+    fn limited_response(&self, limit: u64, decision: &RateLimitDecision) -> PolicyResult {
+        let reset_secs = decision.reset_after.as_secs_f64().ceil() as u64;
+        let mut response = Denial::new("rate_limited", "Too Many Requests")
+            .retry_after(reset_secs)
+            .response(StatusCode::TOO_MANY_REQUESTS);
+        let headers = response.headers_mut();
+        headers.insert(header::RETRY_AFTER, reset_secs.into());
+        headers.insert("x-ratelimit-limit", limit.into());
+        headers.insert("x-ratelimit-remaining", decision.remaining.into());
+        headers.insert("x-ratelimit-reset", reset_secs.into());
+        PolicyResult::Terminate(response)
+    }
+}
+
+pub struct RateLimitPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for RateLimitPolicyFactory {
+    type PolicyType = RateLimitPolicy;
+    type Config = RateLimitConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::traffic::rate_limit::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        let key = RateLimitKey::parse(&config.key)?;
+
+        let instance_config = crate::instance::config();
+        let db_config = match instance_config.as_deref() {
+            Some(instance_config) => &instance_config.databases,
+            None => return Err("Global configuration not initialized".to_string()),
+        };
+
+        let store = create_store(config.store, config.limits.clone(), db_config)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let denylist = match config.ban_ttl_secs {
+            Some(_) => Some(shared_denylist().await.map_err(|e| e.to_string())?),
+            None => None,
+        };
+
+        let overrides = if config.overrides {
+            Some(shared_overrides().await.map_err(|e| e.to_string())?)
+        } else {
+            None
+        };
+
+        Ok(RateLimitPolicy {
+            config,
+            key,
+            store,
+            denylist,
+            overrides,
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.requests == 0 {
+            return Err("requests must be greater than 0".to_string());
+        }
+
+        if config.window_secs == 0 {
+            return Err("window_secs must be greater than 0".to_string());
+        }
+
+        if config.ban_ttl_secs.is_some() && config.key != "ip" {
+            return Err("ban_ttl_secs requires key: ip".to_string());
+        }
+
+        if config.admin_token.is_some() && !config.overrides {
+            return Err("admin_token requires overrides: true".to_string());
+        }
+
+        RateLimitKey::parse(&config.key).map(|_| ())
+    }
+}
+
+#[async_trait]
+impl Policy for RateLimitPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "traffic"
+    }
+
+    fn name(&self) -> &'static str {
+        "rate-limit"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    // Keyed by role, requests without an identity would never be limited
+    fn requires(&self) -> Vec<Capability> {
+        match self.key {
+            RateLimitKey::Role => vec![Capability::Identity],
+            _ => vec![],
+        }
+    }
+
+    // Requests are let through while the store is down
+    async fn health(&self) -> PolicyHealth {
+        match self.store.health().await {
+            Ok(()) => PolicyHealth::Healthy,
+            Err(e) => PolicyHealth::Degraded(format!("Rate limit store: {}", e)),
+        }
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        let (Some(overrides), Some(admin_token)) =
+            (self.overrides.clone(), self.config.admin_token.clone())
+        else {
+            return vec![];
+        };
+        let admin_token: Arc<str> = Arc::from(admin_token.expose());
+
+        let list = {
+            let (overrides, admin_token) = (overrides.clone(), admin_token.clone());
+            move |headers: HeaderMap| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+
+                Json(overrides.entries()).into_response()
+            }
+        };
+
+        let grant = {
+            let (overrides, admin_token) = (overrides.clone(), admin_token.clone());
+            move |headers: HeaderMap, Json(request): Json<OverrideRequest>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                // Overrides are temporary, so they can't be forgotten
+                if request.ttl_secs == 0 {
+                    return json_error(StatusCode::BAD_REQUEST, "ttl_secs must be greater than 0");
+                }
+                if request.reason.trim().is_empty() {
+                    return json_error(StatusCode::BAD_REQUEST, "reason is required");
+                }
+
+                match overrides
+                    .grant(
+                        request.subject,
+                        request.requests,
+                        Duration::from_secs(request.ttl_secs),
+                        request.reason,
+                        request.granted_by.unwrap_or_else(|| "admin".to_string()),
+                    )
+                    .await
+                {
+                    Ok(entry) => (StatusCode::CREATED, Json(entry)).into_response(),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let revoke = {
+            let (overrides, admin_token) = (overrides.clone(), admin_token.clone());
+            move |headers: HeaderMap,
+                  Path(subject): Path<String>,
+                  Query(query): Query<RevokeQuery>| async move {
+                if !is_admin(&headers, &admin_token) {
+                    return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                }
+                let subject = match BanSubject::try_from(subject) {
+                    Ok(subject) => subject,
+                    Err(e) => return json_error(StatusCode::BAD_REQUEST, e),
+                };
+
+                let revoked_by = query.revoked_by.unwrap_or_else(|| "admin".to_string());
+                match overrides.revoke(&subject, revoked_by).await {
+                    Ok(true) => StatusCode::NO_CONTENT.into_response(),
+                    Ok(false) => json_error(StatusCode::NOT_FOUND, "Override not found"),
+                    Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+                }
+            }
+        };
+
+        let audit = move |headers: HeaderMap, Query(query): Query<AuditQuery>| async move {
+            if !is_admin(&headers, &admin_token) {
+                return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+            }
+
+            match overrides.audit(query.limit).await {
+                Ok(records) => Json(records).into_response(),
+                Err(e) => json_error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+            }
+        };
+
+        vec![
+            RouteRegistration {
+                relative_path: "overrides".to_string(),
+                handler: get(list).post(grant),
+            },
+            RouteRegistration {
+                relative_path: "overrides/audit".to_string(),
+                handler: get(audit),
+            },
+            RouteRegistration {
+                relative_path: "overrides/{subject}".to_string(),
+                handler: delete(revoke),
+            },
+        ]
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        // Requests without a key (e.g. no role yet) are not limited
+        let Some(value) = self.key.extract(&request) else {
+            return PolicyResult::Continue(request);
+        };
+
+        let Some(limit) = self.limit_for(&request) else {
+            return PolicyResult::Continue(request);
+        };
+
+        // Namespace counters by key kind, since stores like Redis are shared
+        let key = format!("{}:{}", self.config.key, value);
+        let window = Duration::from_secs(self.config.window_secs);
+        match self.store.hit(&key, limit, window).await {
+            Ok(decision) if !decision.allowed => {
+                if let (Some(denylist), Some(ttl_secs)) = (&self.denylist, self.config.ban_ttl_secs)
+                {
+                    let ban = denylist
+                        .ban(
+                            BanSubject::Ip(value),
+                            Duration::from_secs(ttl_secs),
+                            "rate limit exceeded",
+                        )
+                        .await;
+                    if let Err(e) = ban {
+                        tracing::error!("Failed to ban rate limited client: {}", e);
+                    }
+                }
+                self.limited_response(limit, &decision)
+            }
+            Ok(decision) => {
+                let mut request = request;
+                request
+                    .extensions_mut()
+                    .get_or_insert_default::<Quotas>()
+                    .0
+                    .push(Quota {
+                        key: self.config.key.clone(),
+                        limit,
+                        remaining: decision.remaining,
+                        reset_secs: decision.reset_after.as_secs_f64().ceil() as u64,
+                    });
+                PolicyResult::Continue(request)
+            }
+            Err(e) => {
+                // Fail open so a store outage doesn't take the API down
+                tracing::error!("Rate limit store error: {}", e);
+                PolicyResult::Continue(request)
+            }
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::policy::denial::Denial;
+use crate::policy::traits::{route_to, Capability, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RegionConfig {
+    /// Base URL of the region's upstream, e.g. `https://eu.api.internal`
+    pub upstream: String,
+    /// Path polled to check the region is up, e.g. `/health`. Regions without
+    /// one are assumed to be up
+    pub health_check_path: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResidencyConfig {
+    pub regions: HashMap<String, RegionConfig>,
+    /// Header identifying the tenant, set by an authentication policy
+    #[serde(default = "default_tenant_header")]
+    pub tenant_header: String,
+    /// Region each tenant's data must stay in
+    #[serde(default)]
+    pub tenants: HashMap<String, String>,
+    /// Header with the client's ISO country code, e.g. `CF-IPCountry` from a CDN
+    pub country_header: Option<String>,
+    /// Region for clients in each country, for tenants not in `tenants`
+    #[serde(default)]
+    pub countries: HashMap<String, String>,
+    /// Region for requests matching no tenant or country. Without one, they're rejected
+    pub default_region: Option<String>,
+    #[serde(default = "default_health_check_interval_secs")]
+    pub health_check_interval_secs: u64,
+}
+
+fn default_tenant_header() -> String {
+    "x-bouncer-owner".to_string()
+}
+
+fn default_health_check_interval_secs() -> u64 {
+    10
+}
+
+// A region's upstream and whether its last health check passed
+struct Region {
+    name: String,
+    upstream: String,
+    healthy: AtomicBool,
+}
+
+impl Region {
+    // Poll the health check until the policy is dropped
+    fn spawn_health_checks(self: &Arc<Self>, url: String, interval: Duration) {
+        let region: Weak<Self> = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let client = reqwest::Client::new();
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(region) = region.upgrade() else {
+                    break;
+                };
+
+                let healthy = client
+                    .get(&url)
+                    .timeout(interval)
+                    .send()
+                    .await
+                    .is_ok_and(|response| response.status().is_success());
+                if region.healthy.swap(healthy, Ordering::Relaxed) == healthy {
+                    continue;
+                }
+                if healthy {
+                    tracing::info!("Region {} is available again", region.name);
+                } else {
+                    tracing::error!(
+                        "Region {} is unavailable; its requests are rejected",
+                        region.name
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Routes requests to the upstream of the region their tenant's data lives in
+///
+/// Requests are never sent to another region: if theirs is down they're
+/// rejected, since serving them elsewhere could breach data residency.
+pub struct ResidencyPolicy {
+    config: ResidencyConfig,
+    regions: HashMap<String, Arc<Region>>,
+}
+
+impl ResidencyPolicy {
+    // The region a request must be served from, if any
+    fn region_for(&self, request: &Request<Body>) -> Option<&Arc<Region>> {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+        };
+
+        // A known tenant's region always wins over where the client is
+        let tenant =
+            header(&self.config.tenant_header).and_then(|tenant| self.config.tenants.get(tenant));
+        let country = || {
+            let country = header(self.config.country_header.as_deref()?)?;
+            self.config
+                .countries
+                .get(country.trim().to_ascii_uppercase().as_str())
+        };
+        let name = tenant
+            .or_else(country)
+            .or(self.config.default_region.as_ref())?;
+        self.regions.get(name)
+    }
+
+    fn unavailable(&self, region: &Region) -> PolicyResult {
+        let retry_after = self.config.health_check_interval_secs;
+        let mut response = Denial::new(
+            "region_unavailable",
+            format!("Region {} is unavailable", region.name),
+        )
+        .retry_after(retry_after)
+        .response(StatusCode::SERVICE_UNAVAILABLE);
+        response
+            .headers_mut()
+            .insert(header::RETRY_AFTER, retry_after.into());
+        PolicyResult::Terminate(response)
+    }
+}
+
+pub struct ResidencyPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for ResidencyPolicyFactory {
+    type PolicyType = ResidencyPolicy;
+    type Config = ResidencyConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::traffic::residency::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        let interval = Duration::from_secs(config.health_check_interval_secs);
+
+        let mut regions = HashMap::new();
+        for (name, region_config) in &config.regions {
+            let region = Arc::new(Region {
+                name: name.clone(),
+                upstream: region_config.upstream.clone(),
+                healthy: AtomicBool::new(true),
+            });
+            if let Some(path) = &region_config.health_check_path {
+                let url = format!(
+                    "{}/{}",
+                    region.upstream.trim_end_matches('/'),
+                    path.trim_start_matches('/')
+                );
+                region.spawn_health_checks(url, interval);
+            }
+            regions.insert(name.clone(), region);
+        }
+
+        let countries = config
+            .countries
+            .iter()
+            .map(|(country, region)| (country.to_ascii_uppercase(), region.clone()))
+            .collect();
+        Ok(ResidencyPolicy {
+            config: ResidencyConfig {
+                countries,
+                ..config
+            },
+            regions,
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.regions.is_empty() {
+            return Err("At least one region must be configured".to_string());
+        }
+        if config.health_check_interval_secs == 0 {
+            return Err("health_check_interval_secs must be greater than 0".to_string());
+        }
+
+        for (name, region) in &config.regions {
+            let url = Url::parse(&region.upstream)
+                .map_err(|e| format!("Invalid upstream for region '{}': {}", name, e))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(format!(
+                    "Upstream for region '{}' must be an http or https URL",
+                    name
+                ));
+            }
+        }
+
+        // Every mapping must point at a configured region
+        let targets = config
+            .tenants
+            .values()
+            .chain(config.countries.values())
+            .chain(config.default_region.iter());
+        for region in targets {
+            if !config.regions.contains_key(region) {
+                return Err(format!("Unknown region '{}'", region));
+            }
+        }
+
+        if !config.countries.is_empty() && config.country_header.is_none() {
+            return Err("countries requires country_header".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for ResidencyPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "traffic"
+    }
+
+    fn name(&self) -> &'static str {
+        "residency"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    // Tenants are usually identified by the authenticated owner
+    fn requires(&self) -> Vec<Capability> {
+        if !self.config.tenants.is_empty() && self.config.tenant_header == default_tenant_header() {
+            vec![Capability::Identity]
+        } else {
+            vec![]
+        }
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        let Some(region) = self.region_for(&request) else {
+            return PolicyResult::Terminate(
+                Denial::new("no_region", "Forbidden: no data region for this request")
+                    .response(StatusCode::FORBIDDEN),
+            );
+        };
+
+        if !region.healthy.load(Ordering::Relaxed) {
+            return self.unavailable(region);
+        }
+
+        tracing::debug!("Routing request to region {}", region.name);
+        route_to(&mut request, region.upstream.clone());
+        PolicyResult::Continue(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::traits::Upstream;
+
+    #[tokio::test]
+    async fn test_route_by_region() {
+        let region = |upstream: &str| RegionConfig {
+            upstream: upstream.to_string(),
+            health_check_path: None,
+        };
+        let policy = ResidencyPolicyFactory::new(ResidencyConfig {
+            regions: HashMap::from([
+                ("eu".to_string(), region("https://eu.example.com")),
+                ("us".to_string(), region("https://us.example.com")),
+            ]),
+            tenant_header: default_tenant_header(),
+            tenants: HashMap::from([("acme".to_string(), "eu".to_string())]),
+            country_header: Some("cf-ipcountry".to_string()),
+            countries: HashMap::from([("us".to_string(), "us".to_string())]),
+            default_region: None,
+            health_check_interval_secs: 10,
+        })
+        .await
+        .unwrap();
+
+        let upstream = |result: PolicyResult| match result {
+            PolicyResult::Continue(request) => request
+                .extensions()
+                .get::<Upstream>()
+                .map(|Upstream(url)| url.clone()),
+            PolicyResult::Terminate(response) => Some(response.status().to_string()),
+            PolicyResult::Error(_, error) => panic!("policy failed: {}", error),
+            PolicyResult::Redirect(location, _) => panic!("unexpected redirect to {}", location),
+        };
+
+        // The tenant's region wins over the client's country
+        let request = Request::get("/")
+            .header("x-bouncer-owner", "acme")
+            .header("cf-ipcountry", "US")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            upstream(policy.process(request).await).as_deref(),
+            Some("https://eu.example.com")
+        );
+
+        let request = Request::get("/")
+            .header("cf-ipcountry", "US")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            upstream(policy.process(request).await).as_deref(),
+            Some("https://us.example.com")
+        );
+
+        let request = Request::get("/").body(Body::empty()).unwrap();
+        assert_eq!(
+            upstream(policy.process(request).await).as_deref(),
+            Some("403 Forbidden")
+        );
+
+        // A region that's down isn't swapped for another
+        policy.regions["eu"].healthy.store(false, Ordering::Relaxed);
+        let request = Request::get("/")
+            .header("x-bouncer-owner", "acme")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            upstream(policy.process(request).await).as_deref(),
+            Some("503 Service Unavailable")
+        );
+    }
+}
@@ -0,0 +1,333 @@
+use crate::policy::traits::{Policy, PolicyFactory, PolicyResult};
+use crate::policy::transform::{add_response_transform, BodyTransform, Replace, ResponseTransform};
+use async_trait::async_trait;
+use axum::{
+    body::{Body, Bytes},
+    http::{header, HeaderMap, HeaderValue, Request},
+};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RewriteConfig {
+    /// Base URL the upstream believes it is served at, e.g. `http://app:8080/base`
+    pub upstream_url: String,
+    /// Base URL clients reach it at through Bouncer, e.g. `https://example.com`
+    pub public_url: String,
+    /// Content types whose bodies are rewritten
+    #[serde(default = "default_content_types")]
+    pub content_types: Vec<String>,
+    /// Rewrite the `Domain` and `Path` of cookies the upstream sets
+    #[serde(default = "default_true")]
+    pub rewrite_cookies: bool,
+}
+
+fn default_content_types() -> Vec<String> {
+    [
+        "text/html",
+        "text/css",
+        "text/javascript",
+        "application/javascript",
+    ]
+    .into_iter()
+    .map(String::from)
+    .collect()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+// A base URL, in the forms responses refer to it by
+#[derive(Debug)]
+struct Base {
+    // `http://app:8080/base`, without a trailing slash
+    url: String,
+    // `//app:8080/base`, for protocol-relative URLs
+    relative: String,
+    host: String,
+    // `/base`, or empty at the root
+    path: String,
+}
+
+impl Base {
+    fn parse(field: &str, value: &str) -> Result<Self, String> {
+        let url = Url::parse(value).map_err(|e| format!("Invalid {} '{}': {}", field, value, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("{} must be an http or https URL", field));
+        }
+        if url.query().is_some() || url.fragment().is_some() {
+            return Err(format!("{} must not have a query or fragment", field));
+        }
+        let Some(host) = url.host_str() else {
+            return Err(format!("{} must have a host", field));
+        };
+
+        let authority = match url.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+        let path = url.path().trim_end_matches('/');
+        Ok(Self {
+            url: format!("{}://{}{}", url.scheme(), authority, path),
+            relative: format!("//{}{}", authority, path),
+            host: host.to_string(),
+            path: path.to_string(),
+        })
+    }
+}
+
+// Replace `from` at the start of `value`, if it's followed by the end or a
+// path, query or fragment
+fn rebase(value: &str, from: &str, to: &str) -> Option<String> {
+    value
+        .strip_prefix(from)
+        .filter(|rest| rest.is_empty() || rest.starts_with(['/', '?', '#']))
+        .map(|rest| format!("{}{}", to, rest))
+}
+
+/// Rewrites the upstream's URLs in responses to the public ones
+struct Rewriter {
+    upstream: Base,
+    public: Base,
+    content_types: Vec<String>,
+    rewrite_cookies: bool,
+}
+
+impl Rewriter {
+    fn rewrite_path(&self, path: &str) -> Option<String> {
+        rebase(path, &self.upstream.path, &self.public.path).map(|path| match path.as_str() {
+            "" => "/".to_string(),
+            _ => path,
+        })
+    }
+
+    // Rewrite an absolute, protocol-relative or root-relative URL
+    fn rewrite_url(&self, url: &str) -> Option<String> {
+        rebase(url, &self.upstream.url, &self.public.url)
+            .or_else(|| rebase(url, &self.upstream.relative, &self.public.relative))
+            .or_else(|| {
+                if url.starts_with('/') && !url.starts_with("//") {
+                    self.rewrite_path(url)
+                } else {
+                    None
+                }
+            })
+    }
+
+    fn rewrite_cookie(&self, cookie: &str) -> String {
+        let mut parts = cookie.split(';');
+        let mut rewritten = vec![parts.next().unwrap_or_default().to_string()];
+        for attribute in parts {
+            let attribute = attribute.trim();
+            let value = match attribute.split_once('=') {
+                Some((name, value)) if name.trim().eq_ignore_ascii_case("domain") => {
+                    let domain = value.trim().trim_start_matches('.');
+                    domain
+                        .eq_ignore_ascii_case(&self.upstream.host)
+                        .then(|| format!("Domain={}", self.public.host))
+                }
+                Some((name, value)) if name.trim().eq_ignore_ascii_case("path") => self
+                    .rewrite_path(value.trim())
+                    .map(|path| format!("Path={}", path)),
+                _ => None,
+            };
+            rewritten.push(value.unwrap_or_else(|| attribute.to_string()));
+        }
+        rewritten.join("; ")
+    }
+}
+
+impl ResponseTransform for Rewriter {
+    fn rewrite_headers(&self, headers: &mut HeaderMap) {
+        for name in [header::LOCATION, header::CONTENT_LOCATION] {
+            let rewritten = headers
+                .get(&name)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| self.rewrite_url(value))
+                .and_then(|value| HeaderValue::from_str(&value).ok());
+            if let Some(value) = rewritten {
+                headers.insert(name, value);
+            }
+        }
+
+        if self.rewrite_cookies && headers.contains_key(header::SET_COOKIE) {
+            let cookies: Vec<HeaderValue> = headers
+                .get_all(header::SET_COOKIE)
+                .iter()
+                .map(|value| {
+                    value
+                        .to_str()
+                        .ok()
+                        .and_then(|cookie| HeaderValue::from_str(&self.rewrite_cookie(cookie)).ok())
+                        .unwrap_or_else(|| value.clone())
+                })
+                .collect();
+            headers.remove(header::SET_COOKIE);
+            for cookie in cookies {
+                headers.append(header::SET_COOKIE, cookie);
+            }
+        }
+    }
+
+    fn applies_to(&self, headers: &HeaderMap) -> bool {
+        let Some(content_type) = headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return false;
+        };
+        let mime = content_type.split(';').next().unwrap_or_default().trim();
+        self.content_types
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(mime))
+    }
+
+    fn start(&self) -> Box<dyn BodyTransform> {
+        let pairs = vec![
+            (
+                Bytes::from(self.upstream.url.clone()),
+                Bytes::from(self.public.url.clone()),
+            ),
+            (
+                Bytes::from(self.upstream.relative.clone()),
+                Bytes::from(self.public.relative.clone()),
+            ),
+        ];
+        Box::new(Replace::many(pairs).unwrap())
+    }
+}
+
+/// Rewrites absolute URLs, redirects and cookies in upstream responses to the
+/// public URL, so web apps that don't know they're behind a proxy work through it
+pub struct RewritePolicy {
+    rewriter: Arc<Rewriter>,
+}
+
+pub struct RewritePolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for RewritePolicyFactory {
+    type PolicyType = RewritePolicy;
+    type Config = RewriteConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::traffic::rewrite::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        Ok(RewritePolicy {
+            rewriter: Arc::new(Rewriter {
+                upstream: Base::parse("upstream_url", &config.upstream_url)?,
+                public: Base::parse("public_url", &config.public_url)?,
+                content_types: config.content_types,
+                rewrite_cookies: config.rewrite_cookies,
+            }),
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        let upstream = Base::parse("upstream_url", &config.upstream_url)?;
+        let public = Base::parse("public_url", &config.public_url)?;
+        if upstream.url == public.url {
+            return Err("upstream_url and public_url must differ".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for RewritePolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "traffic"
+    }
+
+    fn name(&self) -> &'static str {
+        "rewrite"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        // Compressed bodies can't be rewritten, so ask the upstream for plain ones
+        request.headers_mut().remove(header::ACCEPT_ENCODING);
+        add_response_transform(&mut request, self.rewriter.clone());
+        PolicyResult::Continue(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::transform::{apply_transforms, ResponseTransforms};
+    use axum::http::Response;
+
+    #[tokio::test]
+    async fn test_rewrite_response() {
+        let policy = RewritePolicyFactory::new(RewriteConfig {
+            upstream_url: "http://app:8080/base/".to_string(),
+            public_url: "https://example.com".to_string(),
+            content_types: default_content_types(),
+            rewrite_cookies: true,
+        })
+        .await
+        .unwrap();
+
+        let request = Request::get("/")
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        let PolicyResult::Continue(mut request) = policy.process(request).await else {
+            panic!("expected the request to continue");
+        };
+        assert!(!request.headers().contains_key(header::ACCEPT_ENCODING));
+        let transforms = request
+            .extensions_mut()
+            .remove::<ResponseTransforms>()
+            .unwrap();
+
+        let response = Response::builder()
+            .header(header::LOCATION, "/base/login?next=%2F")
+            .header(header::CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(
+                header::SET_COOKIE,
+                "session=abc; Domain=.app; Path=/base/; HttpOnly",
+            )
+            .header(header::SET_COOKIE, "theme=dark; Domain=other.org")
+            .body(Body::from(
+                r#"<a href="http://app:8080/base/docs">Docs</a> <img src="//app:8080/base/logo.png">"#,
+            ))
+            .unwrap();
+        let response = apply_transforms(response, transforms);
+
+        let headers = response.headers();
+        assert_eq!(headers[header::LOCATION], "/login?next=%2F");
+        let cookies: Vec<_> = headers.get_all(header::SET_COOKIE).iter().collect();
+        assert_eq!(
+            cookies[0],
+            "session=abc; Domain=example.com; Path=/; HttpOnly"
+        );
+        assert_eq!(cookies[1], "theme=dark; Domain=other.org");
+
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(
+            body.as_ref(),
+            br#"<a href="https://example.com/docs">Docs</a> <img src="//example.com/logo.png">"#
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use crate::policy::denial::Denial;
+use crate::policy::traits::{add_response_header, route_to, Policy, PolicyFactory, PolicyResult};
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{HeaderName, HeaderValue, Request, StatusCode, Uri},
+};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersionConfig {
+    /// Base URL of the upstream serving this version. Without one, requests go
+    /// to the configured destination
+    pub upstream: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VersioningConfig {
+    /// Supported versions by name, e.g. `v1` or `2024-06-01`
+    pub versions: BTreeMap<String, VersionConfig>,
+    /// Header clients request a version with
+    #[serde(default = "default_header")]
+    pub header: String,
+    /// Also read the version from the first path segment, e.g. `/v2/orders`.
+    /// It takes precedence over the header
+    #[serde(default = "default_path_segment")]
+    pub path_segment: bool,
+    /// Remove the version segment from the path before forwarding
+    #[serde(default)]
+    pub strip_path_segment: bool,
+    /// Version for requests that don't ask for one. Without one, they get a 406
+    pub default_version: Option<String>,
+}
+
+fn default_header() -> String {
+    "accept-version".to_string()
+}
+
+fn default_path_segment() -> bool {
+    true
+}
+
+/// Set on responses to the version that served the request
+const API_VERSION_HEADER: HeaderName = HeaderName::from_static("api-version");
+
+// Path segments that look like a version, so unsupported ones get a 406
+// rather than being forwarded as an ordinary path
+static VERSION_SEGMENT: Lazy<Regex> = Lazy::new(|| Regex::new(r"^v\d+(\.\d+)*$").unwrap());
+
+/// Details of the denial returned for requests asking for a version that
+/// isn't supported
+#[derive(Debug, Clone, Serialize)]
+pub struct UnsupportedVersion {
+    pub version: Option<String>,
+    pub supported: Vec<String>,
+}
+
+/// Routes each request to the upstream of the API version it asks for
+pub struct VersioningPolicy {
+    config: VersioningConfig,
+}
+
+// Where a request asked for a version
+enum Requested<'a> {
+    Path(&'a str),
+    Header(&'a str),
+    None,
+}
+
+impl VersioningPolicy {
+    fn requested<'a>(&self, request: &'a Request<Body>) -> Requested<'a> {
+        if self.config.path_segment {
+            let segment = request
+                .uri()
+                .path()
+                .trim_start_matches('/')
+                .split('/')
+                .next()
+                .unwrap_or_default();
+            if self.version(segment).is_some() || VERSION_SEGMENT.is_match(segment) {
+                return Requested::Path(segment);
+            }
+        }
+
+        match request
+            .headers()
+            .get(&self.config.header)
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+        {
+            Some(version) if !version.is_empty() => Requested::Header(version),
+            _ => Requested::None,
+        }
+    }
+
+    // The configured version with this name, ignoring case
+    fn version(&self, name: &str) -> Option<(&String, &VersionConfig)> {
+        self.config
+            .versions
+            .iter()
+            .find(|(version, _)| version.eq_ignore_ascii_case(name))
+    }
+
+    fn unsupported(&self, version: Option<&str>) -> PolicyResult {
+        let details = UnsupportedVersion {
+            version: version.map(str::to_string),
+            supported: self.config.versions.keys().cloned().collect(),
+        };
+
+        PolicyResult::Terminate(
+            Denial::new(
+                "unsupported_version",
+                "The requested API version isn't supported",
+            )
+            .details(details)
+            .response(StatusCode::NOT_ACCEPTABLE),
+        )
+    }
+}
+
+// Remove the first path segment, keeping the query
+fn strip_first_segment(uri: &Uri) -> Option<Uri> {
+    let path = uri.path().trim_start_matches('/');
+    let rest = path.split_once('/').map_or("", |(_, rest)| rest);
+    let path_and_query = match uri.query() {
+        Some(query) => format!("/{}?{}", rest, query),
+        None => format!("/{}", rest),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+pub struct VersioningPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for VersioningPolicyFactory {
+    type PolicyType = VersioningPolicy;
+    type Config = VersioningConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::traffic::versioning::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        Ok(VersioningPolicy { config })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.versions.is_empty() {
+            return Err("At least one version must be configured".to_string());
+        }
+        HeaderName::try_from(config.header.as_str())
+            .map_err(|e| format!("Invalid header '{}': {}", config.header, e))?;
+
+        for (name, version) in &config.versions {
+            HeaderValue::from_str(name).map_err(|_| format!("Invalid version name '{}'", name))?;
+            if let Some(upstream) = &version.upstream {
+                let url = Url::parse(upstream)
+                    .map_err(|e| format!("Invalid upstream for version '{}': {}", name, e))?;
+                if !matches!(url.scheme(), "http" | "https") {
+                    return Err(format!(
+                        "Upstream for version '{}' must be an http or https URL",
+                        name
+                    ));
+                }
+            }
+        }
+
+        if let Some(default_version) = &config.default_version {
+            if !config.versions.contains_key(default_version) {
+                return Err(format!("Unknown default_version '{}'", default_version));
+            }
+        }
+        if config.strip_path_segment && !config.path_segment {
+            return Err("strip_path_segment requires path_segment".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Policy for VersioningPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "traffic"
+    }
+
+    fn name(&self) -> &'static str {
+        "versioning"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        let requested = self.requested(&request);
+        let from_path = matches!(requested, Requested::Path(_));
+        let found = match requested {
+            Requested::Path(name) | Requested::Header(name) => match self.version(name) {
+                Some(found) => found,
+                None => return self.unsupported(Some(name)),
+            },
+            Requested::None => {
+                let default_version = self.config.default_version.as_ref();
+                match default_version.and_then(|name| self.config.versions.get_key_value(name)) {
+                    Some(found) => found,
+                    None => return self.unsupported(None),
+                }
+            }
+        };
+        let (name, version) = found;
+
+        tracing::debug!("Serving API version {}", name);
+        if from_path && self.config.strip_path_segment {
+            if let Some(uri) = strip_first_segment(request.uri()) {
+                *request.uri_mut() = uri;
+            }
+        }
+        if let Some(upstream) = &version.upstream {
+            route_to(&mut request, upstream.clone());
+        }
+        if let Ok(value) = HeaderValue::from_str(name) {
+            add_response_header(&mut request, API_VERSION_HEADER, value);
+        }
+        PolicyResult::Continue(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::traits::Upstream;
+
+    #[tokio::test]
+    async fn test_version_routing() {
+        let version = |upstream: &str| VersionConfig {
+            upstream: Some(upstream.to_string()),
+        };
+        let policy = VersioningPolicyFactory::new(VersioningConfig {
+            versions: BTreeMap::from([
+                ("v1".to_string(), version("https://v1.example.com")),
+                ("v2".to_string(), version("https://v2.example.com")),
+            ]),
+            header: default_header(),
+            path_segment: true,
+            strip_path_segment: true,
+            default_version: Some("v2".to_string()),
+        })
+        .await
+        .unwrap();
+
+        let route = |result: PolicyResult| match result {
+            PolicyResult::Continue(request) => {
+                let upstream = request.extensions().get::<Upstream>().unwrap().0.clone();
+                Ok((upstream, request.uri().to_string()))
+            }
+            PolicyResult::Terminate(response) => Err(response.status()),
+            PolicyResult::Error(_, error) => panic!("policy failed: {}", error),
+            PolicyResult::Redirect(location, _) => panic!("unexpected redirect to {}", location),
+        };
+
+        let request = Request::get("/v1/orders?page=2")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            route(policy.process(request).await),
+            Ok((
+                "https://v1.example.com".to_string(),
+                "/orders?page=2".to_string()
+            ))
+        );
+
+        let request = Request::get("/orders")
+            .header("accept-version", "V1")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            route(policy.process(request).await),
+            Ok(("https://v1.example.com".to_string(), "/orders".to_string()))
+        );
+
+        let request = Request::get("/orders").body(Body::empty()).unwrap();
+        assert_eq!(
+            route(policy.process(request).await),
+            Ok(("https://v2.example.com".to_string(), "/orders".to_string()))
+        );
+
+        let request = Request::get("/v3/orders").body(Body::empty()).unwrap();
+        assert_eq!(
+            route(policy.process(request).await),
+            Err(StatusCode::NOT_ACCEPTABLE)
+        );
+    }
+}
@@ -0,0 +1,275 @@
+use crate::policy::traits::{Policy, PolicyFactory};
+use crate::policy::websocket::{WsConnection, WsDirection, WsMessage, WsPolicy, WsVerdict};
+use async_trait::async_trait;
+use axum::http::request::Parts;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// What to do with a text message matching `deny_patterns`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchAction {
+    /// Close the connection
+    #[default]
+    Close,
+    /// Discard the message
+    Drop,
+    /// Replace the matching text with `[redacted]` and forward the message
+    Redact,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WebsocketConfig {
+    /// Close connections that send a larger message
+    pub max_message_bytes: Option<usize>,
+    /// Close client connections that send more messages than this in a second
+    pub messages_per_second: Option<u32>,
+    /// Close connections that send text messages that aren't valid JSON
+    #[serde(default)]
+    pub require_json: bool,
+    /// Regular expressions to look for in text messages, e.g. for profanity or PII
+    #[serde(default)]
+    pub deny_patterns: Vec<String>,
+    #[serde(default)]
+    pub on_match: MatchAction,
+    /// Also check messages from the upstream, except for the rate limit
+    #[serde(default)]
+    pub inspect_upstream: bool,
+}
+
+const REDACTED: &str = "[redacted]";
+
+// Shared by every connection
+#[derive(Clone)]
+struct Checks {
+    config: Arc<WebsocketConfig>,
+    deny_patterns: Arc<Vec<Regex>>,
+}
+
+impl Checks {
+    fn check(&self, message: WsMessage) -> WsVerdict {
+        if let Some(max) = self.config.max_message_bytes {
+            if message.len() > max {
+                return WsVerdict::Close {
+                    code: 1009,
+                    reason: "Message too big".to_string(),
+                };
+            }
+        }
+
+        let WsMessage::Text(text) = message else {
+            return WsVerdict::Forward(message);
+        };
+
+        if self.config.require_json && serde_json::from_str::<serde_json::Value>(&text).is_err() {
+            return WsVerdict::violation("Messages must be JSON");
+        }
+
+        if !self
+            .deny_patterns
+            .iter()
+            .any(|pattern| pattern.is_match(&text))
+        {
+            return WsVerdict::Forward(WsMessage::Text(text));
+        }
+        match self.config.on_match {
+            MatchAction::Close => WsVerdict::violation("Message not allowed"),
+            MatchAction::Drop => WsVerdict::Drop,
+            MatchAction::Redact => {
+                let redacted = self.deny_patterns.iter().fold(text, |text, pattern| {
+                    pattern.replace_all(&text, REDACTED).into_owned()
+                });
+                WsVerdict::Forward(WsMessage::Text(redacted))
+            }
+        }
+    }
+}
+
+impl WsPolicy for Checks {
+    fn open(&self, _request: &Parts) -> Box<dyn WsConnection> {
+        Box::new(Connection {
+            checks: self.clone(),
+            window_start: Instant::now(),
+            window_count: 0,
+        })
+    }
+}
+
+// Per-connection state: the client's message count in the current second
+struct Connection {
+    checks: Checks,
+    window_start: Instant,
+    window_count: u32,
+}
+
+impl Connection {
+    fn over_rate_limit(&mut self) -> bool {
+        let Some(limit) = self.checks.config.messages_per_second else {
+            return false;
+        };
+        let now = Instant::now();
+        if now.duration_since(self.window_start) >= Duration::from_secs(1) {
+            self.window_start = now;
+            self.window_count = 0;
+        }
+        self.window_count += 1;
+        self.window_count > limit
+    }
+}
+
+#[async_trait]
+impl WsConnection for Connection {
+    async fn on_message(&mut self, direction: WsDirection, message: WsMessage) -> WsVerdict {
+        match direction {
+            WsDirection::ClientToUpstream => {
+                if self.over_rate_limit() {
+                    return WsVerdict::violation("Rate limit exceeded");
+                }
+                self.checks.check(message)
+            }
+            WsDirection::UpstreamToClient if self.checks.config.inspect_upstream => {
+                self.checks.check(message)
+            }
+            WsDirection::UpstreamToClient => WsVerdict::Forward(message),
+        }
+    }
+}
+
+/// Checks the messages of websocket connections: their size, rate, format and content
+pub struct WebsocketPolicy {
+    checks: Arc<Checks>,
+}
+
+pub struct WebsocketPolicyFactory;
+
+#[async_trait]
+impl PolicyFactory for WebsocketPolicyFactory {
+    type PolicyType = WebsocketPolicy;
+    type Config = WebsocketConfig;
+
+    fn policy_id() -> &'static str {
+        crate::policy::providers::bouncer::traffic::websocket::policy_id_with_version("v1")
+    }
+
+    fn version() -> Option<&'static str> {
+        Some("v1")
+    }
+
+    async fn new(config: Self::Config) -> Result<Self::PolicyType, String> {
+        Self::validate_config(&config)?;
+        let deny_patterns = compile_patterns(&config.deny_patterns)?;
+        Ok(WebsocketPolicy {
+            checks: Arc::new(Checks {
+                config: Arc::new(config),
+                deny_patterns: Arc::new(deny_patterns),
+            }),
+        })
+    }
+
+    fn validate_config(config: &Self::Config) -> Result<(), String> {
+        if config.max_message_bytes.is_none()
+            && config.messages_per_second.is_none()
+            && !config.require_json
+            && config.deny_patterns.is_empty()
+        {
+            return Err("At least one websocket message check must be configured".to_string());
+        }
+        if config.messages_per_second == Some(0) {
+            return Err("messages_per_second must be greater than 0".to_string());
+        }
+
+        compile_patterns(&config.deny_patterns)?;
+        Ok(())
+    }
+}
+
+fn compile_patterns(patterns: &[String]) -> Result<Vec<Regex>, String> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(pattern).map_err(|e| format!("Invalid deny pattern '{}': {}", pattern, e))
+        })
+        .collect()
+}
+
+#[async_trait]
+impl Policy for WebsocketPolicy {
+    fn provider(&self) -> &'static str {
+        "bouncer"
+    }
+
+    fn category(&self) -> &'static str {
+        "traffic"
+    }
+
+    fn name(&self) -> &'static str {
+        "websocket"
+    }
+
+    fn version(&self) -> &'static str {
+        "v1"
+    }
+
+    fn read_only(&self) -> bool {
+        true
+    }
+
+    fn websocket(&self) -> Option<Arc<dyn WsPolicy>> {
+        Some(self.checks.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::websocket::WsSession;
+    use axum::http::Request;
+
+    #[tokio::test]
+    async fn test_message_checks() {
+        let policy = WebsocketPolicyFactory::new(WebsocketConfig {
+            max_message_bytes: Some(64),
+            messages_per_second: Some(3),
+            deny_patterns: vec![r"\b\d{3}-\d{2}-\d{4}\b".to_string()],
+            on_match: MatchAction::Redact,
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let chain: Vec<Box<dyn Policy>> = vec![Box::new(policy)];
+        let (parts, _) = Request::get("/ws").body(()).unwrap().into_parts();
+        let mut session = WsSession::open(&chain, &parts);
+
+        let text = |text: &str| WsMessage::Text(text.to_string());
+        assert_eq!(
+            session
+                .process(WsDirection::ClientToUpstream, text("ssn 123-45-6789"))
+                .await,
+            WsVerdict::Forward(text("ssn [redacted]"))
+        );
+        assert!(matches!(
+            session
+                .process(WsDirection::ClientToUpstream, text(&"x".repeat(65)))
+                .await,
+            WsVerdict::Close { code: 1009, .. }
+        ));
+        // Upstream messages aren't inspected or counted by default
+        assert_eq!(
+            session
+                .process(WsDirection::UpstreamToClient, text(&"x".repeat(65)))
+                .await,
+            WsVerdict::Forward(text(&"x".repeat(65)))
+        );
+        session
+            .process(WsDirection::ClientToUpstream, text("hi"))
+            .await;
+        assert!(matches!(
+            session
+                .process(WsDirection::ClientToUpstream, text("hi"))
+                .await,
+            WsVerdict::Close { code: 1008, .. }
+        ));
+    }
+}
@@ -1,11 +1,33 @@
-use crate::config::PolicyConfig;
+use crate::config::{PluginsConfig, PolicyConfig};
+use crate::policy::condition::{Condition, ConditionalPolicy};
+use crate::policy::logging::LoggedPolicy;
+use crate::policy::plugins::{self, PluginManifest};
+use crate::policy::providers::bouncer::authentication::any_of;
+use crate::policy::providers::bouncer::authentication::any_of::v1::{AnyOfConfig, AnyOfPolicy};
+use crate::policy::providers::bouncer::logic::all_of::{
+    self,
+    v1::{AllOfConfig, AllOfPolicy},
+};
+use crate::policy::providers::bouncer::logic::any_of as logic_any_of;
+use crate::policy::providers::bouncer::logic::not::{
+    self,
+    v1::{NotConfig, NotPolicy},
+};
+use crate::policy::reload::ReloadablePolicy;
 use crate::policy::routes::PolicyRouter;
+use crate::policy::schedule::ScheduledPolicy;
+use crate::policy::sdk::{Plugin, DECLARATION_SYMBOL};
 use crate::policy::traits::{Policy, PolicyFactory};
+use bouncer_policy_sdk::ffi::PluginDeclaration;
+use futures::future::BoxFuture;
 use libloading::{Library, Symbol};
-use std::collections::HashMap;
+use serde::de::DeserializeOwned;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::Arc;
 use tracing;
 
+#[allow(clippy::type_complexity)]
 pub struct PolicyRegistry {
     factories: HashMap<
         String,
@@ -18,9 +40,9 @@ pub struct PolicyRegistry {
                 + Sync,
         >,
     >,
-    // Store loaded libraries to keep them in memory
-    #[allow(dead_code)]
-    loaded_libraries: Vec<Library>,
+    // Parse and check a policy's parameters without creating it
+    validators:
+        HashMap<String, Box<dyn Fn(&serde_json::Value) -> Result<(), String> + Send + Sync>>,
     // Store policy routes
     // policy_router: PolicyRouter,
 }
@@ -35,7 +57,7 @@ impl PolicyRegistry {
     pub fn new() -> Self {
         Self {
             factories: HashMap::new(),
-            loaded_libraries: Vec::new(),
+            validators: HashMap::new(),
             // policy_router: PolicyRouter::new(),
         }
     }
@@ -48,6 +70,14 @@ impl PolicyRegistry {
         let policy_id = F::policy_id().to_string();
         tracing::debug!("Registering policy: {}", policy_id);
 
+        self.validators.insert(
+            policy_id.clone(),
+            Box::new(|config| {
+                let parsed_config = serde_json::from_value::<F::Config>(config.clone())
+                    .map_err(|e| format!("Failed to parse config: {}", e))?;
+                F::validate_config(&parsed_config)
+            }),
+        );
         self.factories.insert(
             policy_id,
             Box::new(move |config| {
@@ -71,64 +101,86 @@ impl PolicyRegistry {
         );
     }
 
-    /// Load a policy from a dynamic library
+    /// Load the policies of a plugin library
+    ///
+    /// The library must export a declaration built with `export_policies!` from
+    /// `bouncer-policy-sdk`. It's verified before any plugin code is called, so
+    /// plugins built for another SDK ABI version, or declaring invalid policies,
+    /// are rejected instead of crashing at runtime.
     ///
-    /// This function loads a dynamic library containing a policy implementation
-    /// and registers it with the policy registry.
+    /// The library itself is not authenticated; use [`PolicyRegistry::load_plugins`]
+    /// to load plugins verified against a manifest.
     pub fn load_policy_from_library<P: AsRef<Path>>(&mut self, path: P) -> Result<(), String> {
-        // Load the dynamic library
         let lib = unsafe {
             Library::new(path.as_ref()).map_err(|e| format!("Failed to load library: {}", e))?
         };
 
-        // Find and call the registration function
-        let register_fn: Symbol<unsafe extern "C" fn(&mut PolicyRegistry)> = unsafe {
-            lib.get(b"__bouncer_register_policy")
-                .map_err(|e| format!("Failed to find registration function: {}", e))?
+        let declaration: Symbol<*const PluginDeclaration> = unsafe {
+            lib.get(DECLARATION_SYMBOL).map_err(|e| {
+                format!(
+                    "Failed to find plugin declaration (was the plugin built with export_policies! from bouncer-policy-sdk?): {}",
+                    e
+                )
+            })?
         };
+        let declaration = *declaration;
+        let plugin = unsafe { Plugin::load(declaration, lib)? };
+        self.register_plugin(plugin);
 
-        // Call the registration function
-        unsafe { register_fn(self) };
+        Ok(())
+    }
 
-        // Store the library to keep it loaded
-        self.loaded_libraries.push(lib);
+    // Register factories for a verified plugin's policies. Policies created by
+    // them keep the plugin's library loaded for as long as they're in use
+    pub(crate) fn register_plugin(&mut self, plugin: Arc<Plugin>) {
+        for (index, policy_id) in plugin.policies() {
+            tracing::debug!("Registering plugin policy: {}", policy_id);
 
-        Ok(())
+            let validator = Arc::clone(&plugin);
+            self.validators.insert(
+                policy_id.to_string(),
+                Box::new(move |config| validator.validate(index, config)),
+            );
+            let factory = Arc::clone(&plugin);
+            self.factories.insert(
+                policy_id.to_string(),
+                Box::new(move |config| {
+                    Box::pin(futures::future::ready(factory.create(index, config)))
+                }),
+            );
+        }
     }
 
-    /// Load all policy plugins from a directory
+    /// Load all policy plugins listed in a plugin directory's manifest
     ///
-    /// This function scans a directory for dynamic libraries and attempts to load
-    /// each one as a policy plugin.
-    pub fn load_policies_from_directory<P: AsRef<Path>>(
-        &mut self,
-        dir_path: P,
-    ) -> Result<(), String> {
-        let dir_path = dir_path.as_ref();
-        let entries = std::fs::read_dir(dir_path)
-            .map_err(|e| format!("Failed to read plugin directory: {}", e))?;
-
-        for entry in entries {
-            let entry = entry.map_err(|e| format!("Failed to read directory entry: {}", e))?;
-            let path = entry.path();
-
-            // Only try to load files with the appropriate extension for the platform
-            let extension = if cfg!(target_os = "windows") {
-                "dll"
-            } else if cfg!(target_os = "macos") {
-                "dylib"
-            } else {
-                "so"
+    /// Every plugin must be listed in the directory's `manifest.yaml` with a matching
+    /// checksum (and signature, if required), and pass the configured allow/deny
+    /// lists. Plugins that fail verification are skipped. Returns the number of
+    /// plugins that were loaded.
+    pub fn load_plugins(&mut self, config: &PluginsConfig) -> Result<usize, String> {
+        let dir_path = Path::new(&config.directory);
+        let manifest = PluginManifest::load(dir_path)?;
+        let mut loaded = 0;
+
+        for entry in &manifest.plugins {
+            let plugin = match plugins::verify_plugin(dir_path, entry, config) {
+                Ok(plugin) => plugin,
+                Err(e) => {
+                    tracing::warn!("Refusing to load plugin '{}': {}", entry.name, e);
+                    continue;
+                }
             };
 
-            if path.is_file() && path.extension().is_some_and(|ext| ext == extension) {
-                if let Err(e) = self.load_policy_from_library(&path) {
-                    tracing::warn!("Failed to load policy from {}: {}", path.display(), e);
+            match self.load_policy_from_library(&plugin.library_path) {
+                Ok(()) => {
+                    tracing::info!("Loaded plugin {} {}", plugin.name, plugin.version);
+                    loaded += 1;
                 }
+                Err(e) => tracing::warn!("Failed to load plugin '{}': {}", plugin.name, e),
             }
         }
 
-        Ok(())
+        Ok(loaded)
     }
 
     // Split a policy provider identifier into parts
@@ -144,23 +196,173 @@ impl PolicyRegistry {
     //     Ok((base_provider, version))
     // }
 
-    /// Build a policy chain from a list of policy configurations
-    pub async fn build_policy_chain(
+    /// IDs of every registered policy, sorted
+    pub fn policy_ids(&self) -> Vec<&str> {
+        let mut ids: Vec<&str> = self.factories.keys().map(String::as_str).collect();
+        ids.extend(Combinator::all().map(|(id, _)| id));
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Check a policy's configuration without creating it
+    ///
+    /// Unlike [`PolicyRegistry::create_policy`], this never connects to
+    /// databases or other services, so it only catches mistakes in the config
+    /// itself.
+    pub fn validate_policy(&self, policy_config: &PolicyConfig) -> Result<(), String> {
+        let schedule = policy_config.schedule();
+        if let (Some(from), Some(until)) = (schedule.from, schedule.until) {
+            if from >= until {
+                return Err("effective_from must be before effective_until".to_string());
+            }
+        }
+        if let Some(apply_if) = &policy_config.apply_if {
+            Condition::new(apply_if)?;
+        }
+
+        if let Some(combinator) = Combinator::from_provider(&policy_config.provider) {
+            return combinator
+                .policies(&policy_config.parameters)?
+                .iter()
+                .try_for_each(|policy| {
+                    self.validate_policy(policy)
+                        .map_err(|e| format!("Policy {}: {}", policy.id, e))
+                });
+        }
+
+        let validate = self
+            .validators
+            .get(&policy_config.provider)
+            .ok_or_else(|| {
+                format!(
+                    "Policy not found for provider ID: {}",
+                    policy_config.provider
+                )
+            })?;
+        validate(&policy_config.parameters)
+    }
+
+    /// Create a single policy from its configuration
+    pub async fn create_policy(
         &self,
-        config: &[PolicyConfig],
-    ) -> Result<(Vec<Box<dyn Policy>>, PolicyRouter), String> {
-        let mut policy_chain = Vec::new();
-        let mut policy_router = PolicyRouter::new();
+        policy_config: &PolicyConfig,
+    ) -> Result<Box<dyn Policy>, String> {
+        let schedule = policy_config.schedule();
+        if let (Some(from), Some(until)) = (schedule.from, schedule.until) {
+            if from >= until {
+                return Err(format!(
+                    "Policy {}: effective_from must be before effective_until",
+                    policy_config.id
+                ));
+            }
+        }
+        let condition = policy_config
+            .apply_if
+            .as_ref()
+            .map(Condition::new)
+            .transpose()
+            .map_err(|e| format!("Policy {}: {}", policy_config.id, e))?;
 
-        for policy_config in config {
+        let policy = if let Some(combinator) = Combinator::from_provider(&policy_config.provider) {
+            self.create_combinator(combinator, &policy_config.parameters)
+                .await?
+        } else {
             let factory = self.factories.get(&policy_config.provider).ok_or_else(|| {
                 format!(
                     "Policy not found for provider ID: {}",
                     policy_config.provider
                 )
             })?;
+            factory(&policy_config.parameters).await?
+        };
+        let policy: Box<dyn Policy> = if schedule.is_scheduled() {
+            Box::new(ScheduledPolicy::new(policy, schedule))
+        } else {
+            policy
+        };
+        let policy: Box<dyn Policy> = match condition {
+            Some(condition) => Box::new(ConditionalPolicy::new(policy, condition)),
+            None => policy,
+        };
+        Ok(Box::new(
+            LoggedPolicy::new(policy, policy_config.id.clone(), policy_config.log_level)
+                .with_failure_mode(policy_config.failure_mode)
+                .with_group(policy_config.group.as_deref()),
+        ))
+    }
 
-            let policy = factory(&policy_config.parameters).await?;
+    // Create a combinator and the policies inside it. Boxed, since they can
+    // be combinators themselves
+    fn create_combinator<'a>(
+        &'a self,
+        combinator: Combinator,
+        parameters: &'a serde_json::Value,
+    ) -> BoxFuture<'a, Result<Box<dyn Policy>, String>> {
+        Box::pin(async move {
+            let configs = combinator.policies(parameters)?;
+            let mut policies = Vec::new();
+            for policy_config in &configs {
+                policies.push(self.create_policy(policy_config).await?);
+            }
+            let policy: Box<dyn Policy> = match combinator {
+                Combinator::AllOf => {
+                    // The policies inside form a chain of their own
+                    validate_chain(
+                        &configs
+                            .iter()
+                            .zip(&policies)
+                            .map(|(policy_config, policy)| {
+                                (policy_config.id.as_str(), policy.as_ref())
+                            })
+                            .collect::<Vec<_>>(),
+                    )?;
+                    Box::new(AllOfPolicy::new(policies)?)
+                }
+                Combinator::AnyOf(category) => {
+                    Box::new(AnyOfPolicy::new(policies)?.with_category(category))
+                }
+                Combinator::Not => {
+                    let config: NotConfig = parse_config(parameters)?;
+                    let policy = policies.pop().expect("not has one policy");
+                    Box::new(NotPolicy::new(policy, &config)?)
+                }
+            };
+            Ok(policy)
+        })
+    }
+
+    /// Build a policy chain from a list of policy configurations
+    pub async fn build_policy_chain(
+        &self,
+        config: &[PolicyConfig],
+    ) -> Result<(Vec<Box<dyn Policy>>, PolicyRouter), String> {
+        self.build_chain(config, |_, policy| policy).await
+    }
+
+    /// Build a policy chain whose policies can be replaced one at a time
+    ///
+    /// Clones of the returned policies share their slot with the chain, so they
+    /// can be kept to reload the policies later.
+    pub async fn build_reloadable_chain(
+        &self,
+        config: &[PolicyConfig],
+    ) -> Result<(Vec<ReloadablePolicy>, PolicyRouter), String> {
+        self.build_chain(config, |policy_config, policy| {
+            ReloadablePolicy::new(policy_config.clone(), policy)
+        })
+        .await
+    }
+
+    async fn build_chain<T>(
+        &self,
+        config: &[PolicyConfig],
+        wrap: impl Fn(&PolicyConfig, Box<dyn Policy>) -> T,
+    ) -> Result<(Vec<T>, PolicyRouter), String> {
+        let mut policies = Vec::new();
+        let mut policy_router = PolicyRouter::new();
+
+        for policy_config in config {
+            let policy = self.create_policy(policy_config).await?;
 
             // Register routes for all policies
             let routes = policy.register_routes();
@@ -177,10 +379,319 @@ impl PolicyRegistry {
 
             // Only add to policy chain if the policy processes requests
             if policy.processes_requests() {
-                policy_chain.push(policy);
+                policies.push((policy_config, policy));
             }
         }
 
+        validate_chain(
+            &policies
+                .iter()
+                .map(|(policy_config, policy)| (policy_config.id.as_str(), policy.as_ref()))
+                .collect::<Vec<_>>(),
+        )?;
+        let policy_chain = policies
+            .into_iter()
+            .map(|(policy_config, policy)| wrap(policy_config, policy))
+            .collect();
+
         Ok((policy_chain, policy_router))
     }
 }
+
+// Policies the registry builds itself, since they contain other policies
+#[derive(Debug, Clone, Copy)]
+enum Combinator {
+    AllOf,
+    /// With the category it's reported under
+    AnyOf(&'static str),
+    Not,
+}
+
+impl Combinator {
+    // Provider IDs of every combinator
+    fn all() -> [(&'static str, Combinator); 4] {
+        [
+            (all_of::policy_id_with_version("v1"), Self::AllOf),
+            (
+                logic_any_of::policy_id_with_version("v1"),
+                Self::AnyOf("logic"),
+            ),
+            (not::policy_id_with_version("v1"), Self::Not),
+            (
+                any_of::policy_id_with_version("v1"),
+                Self::AnyOf("authentication"),
+            ),
+        ]
+    }
+
+    fn from_provider(provider: &str) -> Option<Self> {
+        Self::all()
+            .into_iter()
+            .find(|(id, _)| *id == provider)
+            .map(|(_, combinator)| combinator)
+    }
+
+    // The checked configs of the policies inside
+    fn policies(&self, parameters: &serde_json::Value) -> Result<Vec<PolicyConfig>, String> {
+        match self {
+            Self::AllOf => {
+                let config: AllOfConfig = parse_config(parameters)?;
+                config.validate()?;
+                Ok(config.policies)
+            }
+            Self::AnyOf(_) => {
+                let config: AnyOfConfig = parse_config(parameters)?;
+                config.validate()?;
+                Ok(config.policies)
+            }
+            Self::Not => {
+                let config: NotConfig = parse_config(parameters)?;
+                config.validate()?;
+                Ok(vec![config.policy])
+            }
+        }
+    }
+}
+
+fn parse_config<T: DeserializeOwned>(parameters: &serde_json::Value) -> Result<T, String> {
+    serde_json::from_value(parameters.clone()).map_err(|e| format!("Failed to parse config: {}", e))
+}
+
+/// Check that every policy's requirements are provided by a policy before it
+///
+/// `chain` holds the ID and policy of every policy that processes requests, in
+/// chain order.
+pub fn validate_chain(chain: &[(&str, &dyn Policy)]) -> Result<(), String> {
+    let mut provided = HashSet::new();
+    // What the policies of the current group provide, once the whole group has run
+    let mut group_provides = Vec::new();
+    let mut group = None;
+    for (id, policy) in chain {
+        if policy.group().is_none() || policy.group() != group {
+            provided.extend(group_provides.drain(..));
+        }
+        group = policy.group();
+
+        if let Some(missing) = policy
+            .requires()
+            .into_iter()
+            .find(|capability| !provided.contains(capability))
+        {
+            if let Some(group) = group.as_ref().filter(|_| group_provides.contains(&missing)) {
+                return Err(format!(
+                    "Policy {} requires {}, which a policy in its group '{}' provides while it runs. \
+                     Move it out of the group",
+                    id, missing, group
+                ));
+            }
+            return Err(format!(
+                "Policy {} (@{}/{}/{}/{}) requires {}, but no policy before it in the chain provides one. \
+                 Move it after one of the {}",
+                id,
+                policy.provider(),
+                policy.category(),
+                policy.name(),
+                policy.version(),
+                missing,
+                missing.provided_by()
+            ));
+        }
+        group_provides.extend(policy.provides());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::traits::{Capability, PolicyResult};
+    use async_trait::async_trait;
+
+    struct TestPolicy {
+        requires: Vec<Capability>,
+        provides: Vec<Capability>,
+    }
+
+    #[async_trait]
+    impl Policy for TestPolicy {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "test"
+        }
+
+        fn name(&self) -> &'static str {
+            "test"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        fn requires(&self) -> Vec<Capability> {
+            self.requires.clone()
+        }
+
+        fn provides(&self) -> Vec<Capability> {
+            self.provides.clone()
+        }
+    }
+
+    #[test]
+    fn test_validate_chain() {
+        let auth = TestPolicy {
+            requires: vec![],
+            provides: vec![Capability::Identity],
+        };
+        let rbac = TestPolicy {
+            requires: vec![Capability::Identity],
+            provides: vec![],
+        };
+
+        assert!(validate_chain(&[("auth", &auth), ("rbac", &rbac)]).is_ok());
+        let error = validate_chain(&[("rbac", &rbac), ("auth", &auth)]).unwrap_err();
+        assert!(error.contains("Policy rbac"));
+
+        // Policies in a group can't rely on each other
+        let grouped = |policy: TestPolicy| LoggedPolicy::new(Box::new(policy), String::new(), None);
+        let (auth, rbac) = (
+            grouped(auth).with_group(Some("checks")),
+            grouped(rbac).with_group(Some("checks")),
+        );
+        let error = validate_chain(&[("auth", &auth), ("rbac", &rbac)]).unwrap_err();
+        assert!(error.contains("its group 'checks'"));
+        let rbac = rbac.with_group(None);
+        assert!(validate_chain(&[("auth", &auth), ("rbac", &rbac)]).is_ok());
+    }
+
+    // A mock policy rejecting requests to `path` with `status`
+    fn mock(id: &str, path: &str, status: u16) -> serde_json::Value {
+        serde_json::json!({
+            "id": id,
+            "provider": "@bouncer/development/mock/v1",
+            "parameters": { "routes": [{ "path": path, "status": status }] },
+        })
+    }
+
+    async fn status(policy: &dyn Policy, path: &str) -> Option<u16> {
+        match policy
+            .process(
+                axum::http::Request::get(path)
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+        {
+            PolicyResult::Continue(_) => None,
+            PolicyResult::Terminate(response) => Some(response.status().as_u16()),
+            PolicyResult::Error(_, error) => panic!("policy failed: {}", error),
+            PolicyResult::Redirect(location, _) => panic!("unexpected redirect to {}", location),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_combinators() {
+        use crate::policy::providers::bouncer::development::mock::v1::MockPolicyFactory;
+
+        let mut registry = PolicyRegistry::new();
+        registry.register_policy::<MockPolicyFactory>();
+
+        // Only /a and /b, except /b/private
+        let config: PolicyConfig = serde_json::from_value(serde_json::json!({
+            "id": "combined",
+            "provider": "@bouncer/logic/all-of/v1",
+            "parameters": { "policies": [
+                {
+                    "id": "paths",
+                    "provider": "@bouncer/logic/any-of/v1",
+                    "parameters": { "policies": [
+                        {
+                            "id": "a",
+                            "provider": "@bouncer/logic/not/v1",
+                            "parameters": { "policy": mock("a", "/a", 401), "status": 404 },
+                        },
+                        {
+                            "id": "b",
+                            "provider": "@bouncer/logic/not/v1",
+                            "parameters": { "policy": mock("b", "/b/*", 401), "status": 404 },
+                        },
+                    ]},
+                },
+                mock("private", "/b/private", 403),
+            ]},
+        }))
+        .unwrap();
+        registry.validate_policy(&config).unwrap();
+        let policy = registry.create_policy(&config).await.unwrap();
+        assert_eq!(policy.category(), "logic");
+
+        assert_eq!(status(policy.as_ref(), "/a").await, None);
+        assert_eq!(status(policy.as_ref(), "/b/public").await, None);
+        assert_eq!(status(policy.as_ref(), "/b/private").await, Some(403));
+        assert_eq!(status(policy.as_ref(), "/c").await, Some(404));
+
+        let mut config = config;
+        config.parameters = serde_json::json!({ "policies": [] });
+        assert!(registry.validate_policy(&config).is_err());
+    }
+
+    #[test]
+    fn test_validate_policy() {
+        use crate::policy::providers::bouncer::development::mock::v1::MockPolicyFactory;
+
+        let mut registry = PolicyRegistry::new();
+        registry.register_policy::<MockPolicyFactory>();
+        let validate = |config: serde_json::Value| {
+            registry.validate_policy(&serde_json::from_value(config).unwrap())
+        };
+
+        assert!(validate(mock("mock", "/a", 401)).is_ok());
+        let error = validate(serde_json::json!({
+            "id": "unknown",
+            "provider": "@acme/unknown/policy/v1",
+            "parameters": {},
+        }))
+        .unwrap_err();
+        assert!(error.contains("@acme/unknown/policy/v1"));
+        assert!(validate(serde_json::json!({
+            "id": "mock",
+            "provider": "@bouncer/development/mock/v1",
+            "parameters": { "routes": "/a" },
+        }))
+        .is_err());
+
+        // Policies inside combinators are checked too, and named in the error
+        let error = validate(serde_json::json!({
+            "id": "combined",
+            "provider": "@bouncer/logic/any-of/v1",
+            "parameters": { "policies": [
+                mock("a", "/a", 401),
+                { "id": "inner", "provider": "@acme/unknown/policy/v1", "parameters": {} },
+            ]},
+        }))
+        .unwrap_err();
+        assert!(error.starts_with("Policy inner:"));
+
+        let mut scheduled = mock("mock", "/a", 401);
+        scheduled["effective_from"] = "2026-02-01T00:00:00Z".into();
+        scheduled["effective_until"] = "2026-01-01T00:00:00Z".into();
+        assert!(validate(scheduled).is_err());
+    }
+
+    #[test]
+    fn test_policy_ids() {
+        use crate::policy::providers::bouncer::development::mock::v1::MockPolicyFactory;
+
+        let mut registry = PolicyRegistry::new();
+        registry.register_policy::<MockPolicyFactory>();
+        let ids = registry.policy_ids();
+
+        // Registered policies and the combinators, in order
+        assert!(ids.contains(&"@bouncer/development/mock/v1"));
+        assert!(ids.contains(&"@bouncer/logic/not/v1"));
+        assert_eq!(ids.len(), 5);
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
@@ -0,0 +1,511 @@
+use crate::admin::{is_admin, json_error};
+use crate::config::PolicyConfig;
+use crate::diagnostics::mask_secrets;
+use crate::policy::failure::FailureMode;
+use crate::policy::lifecycle;
+use crate::policy::middleware::PolicyChainHandle;
+use crate::policy::registry::{validate_chain, PolicyRegistry};
+use crate::policy::routes::{PolicyRouter, RouteRegistration};
+use crate::policy::traits::{Capability, Policy, PolicyHealth, PolicyResult, ResponsePolicyResult};
+use crate::policy::websocket::WsPolicy;
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{HeaderMap, Request, Response, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use serde::Serialize;
+use std::sync::{Arc, Mutex, RwLock};
+
+struct Slot {
+    config: RwLock<PolicyConfig>,
+    policy: RwLock<Arc<dyn Policy>>,
+}
+
+/// A policy in the chain that can be replaced while requests are served
+///
+/// Clones share the same slot. Each request uses the policy that was in the slot
+/// when it reached it, so replacing a policy never interrupts a request.
+#[derive(Clone)]
+pub struct ReloadablePolicy {
+    slot: Arc<Slot>,
+}
+
+impl ReloadablePolicy {
+    pub fn new(config: PolicyConfig, policy: Box<dyn Policy>) -> Self {
+        Self {
+            slot: Arc::new(Slot {
+                config: RwLock::new(config),
+                policy: RwLock::new(Arc::from(policy)),
+            }),
+        }
+    }
+
+    fn current(&self) -> Arc<dyn Policy> {
+        Arc::clone(&self.slot.policy.read().unwrap())
+    }
+
+    /// The config the current policy was created from
+    pub fn config(&self) -> PolicyConfig {
+        self.slot.config.read().unwrap().clone()
+    }
+
+    // Returns the policy that was replaced
+    fn replace(&self, config: PolicyConfig, policy: Box<dyn Policy>) -> Arc<dyn Policy> {
+        let old = std::mem::replace(&mut *self.slot.policy.write().unwrap(), Arc::from(policy));
+        *self.slot.config.write().unwrap() = config;
+        old
+    }
+}
+
+#[async_trait]
+impl Policy for ReloadablePolicy {
+    fn provider(&self) -> &'static str {
+        self.current().provider()
+    }
+
+    fn category(&self) -> &'static str {
+        self.current().category()
+    }
+
+    fn name(&self) -> &'static str {
+        self.current().name()
+    }
+
+    fn version(&self) -> &'static str {
+        self.current().version()
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        self.current().register_routes()
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let policy = self.current();
+        policy.process(request).await
+    }
+
+    fn processes_requests(&self) -> bool {
+        self.current().processes_requests()
+    }
+
+    fn failure_mode(&self) -> FailureMode {
+        self.current().failure_mode()
+    }
+
+    fn group(&self) -> Option<Arc<str>> {
+        self.current().group()
+    }
+
+    fn applies_to(&self, request: &Request<Body>) -> bool {
+        self.current().applies_to(request)
+    }
+
+    async fn process_response(&self, response: Response<Body>) -> ResponsePolicyResult {
+        let policy = self.current();
+        policy.process_response(response).await
+    }
+
+    fn processes_responses(&self) -> bool {
+        self.current().processes_responses()
+    }
+
+    fn read_only(&self) -> bool {
+        self.current().read_only()
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        self.current().inspects_body()
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        self.current().requires()
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        self.current().provides()
+    }
+
+    fn websocket(&self) -> Option<Arc<dyn WsPolicy>> {
+        self.current().websocket()
+    }
+
+    async fn health(&self) -> PolicyHealth {
+        self.current().health().await
+    }
+
+    async fn warm_up(&self) -> Result<(), String> {
+        self.current().warm_up().await
+    }
+
+    async fn on_start(&self) -> Result<(), String> {
+        self.current().on_start().await
+    }
+
+    async fn on_shutdown(&self) {
+        self.current().on_shutdown().await
+    }
+}
+
+struct Loaded {
+    registry: Arc<PolicyRegistry>,
+    policies: Vec<ReloadablePolicy>,
+    // The chain the policies belong to, to notice when it was replaced
+    chain: Arc<Vec<Box<dyn Policy>>>,
+}
+
+/// Replaces single policies of the active chain at runtime
+pub struct PolicyReloader {
+    handle: PolicyChainHandle,
+    loaded: Mutex<Loaded>,
+}
+
+/// A policy in the chain, as listed at `/_admin/policies`
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyEntry {
+    pub index: usize,
+    pub id: String,
+    pub provider: String,
+    /// Parameters with secrets masked
+    pub parameters: serde_json::Value,
+}
+
+impl PolicyReloader {
+    /// Build a reloadable chain and a layer handle serving it
+    pub async fn build(
+        registry: PolicyRegistry,
+        config: &[PolicyConfig],
+    ) -> Result<(Self, PolicyRouter), String> {
+        let (policies, router) = registry.build_reloadable_chain(config).await?;
+        lifecycle::start(&to_chain(&policies)).await?;
+        let handle = PolicyChainHandle::new(to_chain(&policies));
+        let chain = handle.load();
+
+        Ok((
+            Self {
+                handle,
+                loaded: Mutex::new(Loaded {
+                    registry: Arc::new(registry),
+                    policies,
+                    chain,
+                }),
+            },
+            router,
+        ))
+    }
+
+    /// Handle to the chain the reloader manages
+    pub fn handle(&self) -> PolicyChainHandle {
+        self.handle.clone()
+    }
+
+    /// Rebuild the whole chain with a new registry, keeping parameters changed at runtime
+    pub async fn rebuild(&self, registry: PolicyRegistry) -> Result<(), String> {
+        let configs: Vec<PolicyConfig> = {
+            let loaded = self.loaded.lock().unwrap();
+            if !Arc::ptr_eq(&loaded.chain, &self.handle.load()) {
+                return Err(
+                    "The policy chain was replaced, e.g. by promoting a staged chain".to_string(),
+                );
+            }
+            loaded
+                .policies
+                .iter()
+                .map(|policy| policy.config())
+                .collect()
+        };
+        let (policies, _routes) = registry.build_reloadable_chain(&configs).await?;
+        lifecycle::start(&to_chain(&policies)).await?;
+
+        let old = {
+            let mut loaded = self.loaded.lock().unwrap();
+            let old = self.handle.swap(to_chain(&policies));
+            *loaded = Loaded {
+                registry: Arc::new(registry),
+                policies,
+                chain: self.handle.load(),
+            };
+            old
+        };
+        lifecycle::shut_down(&old).await;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<PolicyEntry> {
+        let loaded = self.loaded.lock().unwrap();
+        loaded
+            .policies
+            .iter()
+            .enumerate()
+            .map(|(index, policy)| {
+                let config = policy.config();
+                let mut parameters = config.parameters;
+                mask_secrets(&mut parameters);
+                PolicyEntry {
+                    index,
+                    id: config.id,
+                    provider: config.provider,
+                    parameters,
+                }
+            })
+            .collect()
+    }
+
+    /// Replace the parameters of the policy at `index` in the chain
+    ///
+    /// With `merge`, objects in `parameters` are merged into the current
+    /// parameters instead of replacing them. The new parameters are validated and
+    /// the policy is created by its factory before it is swapped in; if either
+    /// fails the current policy stays.
+    pub async fn reload(
+        &self,
+        index: usize,
+        parameters: serde_json::Value,
+        merge: bool,
+    ) -> Result<PolicyEntry, ReloadError> {
+        let (registry, policies) = {
+            let loaded = self.loaded.lock().unwrap();
+            if !Arc::ptr_eq(&loaded.chain, &self.handle.load()) {
+                return Err(ReloadError::Replaced);
+            }
+            (Arc::clone(&loaded.registry), loaded.policies.clone())
+        };
+        let policy = policies.get(index).ok_or(ReloadError::NotFound)?;
+
+        let mut config = policy.config();
+        if merge {
+            merge_json(&mut config.parameters, parameters);
+        } else {
+            config.parameters = parameters;
+        }
+
+        let new_policy = registry
+            .create_policy(&config)
+            .await
+            .map_err(ReloadError::Invalid)?;
+        if !new_policy.processes_requests() {
+            return Err(ReloadError::Invalid(
+                "The new policy doesn't process requests".to_string(),
+            ));
+        }
+
+        // New parameters can change what the policy requires, e.g. a rate limit keyed by role
+        let current: Vec<(String, Arc<dyn Policy>)> = policies
+            .iter()
+            .map(|policy| (policy.config().id, policy.current()))
+            .collect();
+        let chain: Vec<(&str, &dyn Policy)> = current
+            .iter()
+            .enumerate()
+            .map(|(i, (id, policy))| {
+                if i == index {
+                    (config.id.as_str(), new_policy.as_ref())
+                } else {
+                    (id.as_str(), policy.as_ref())
+                }
+            })
+            .collect();
+        validate_chain(&chain).map_err(ReloadError::Invalid)?;
+        new_policy.on_start().await.map_err(ReloadError::Invalid)?;
+
+        tracing::info!("Reloaded policy {} at position {}", config.id, index);
+        policy.replace(config, new_policy).on_shutdown().await;
+        Ok(self.list().swap_remove(index))
+    }
+}
+
+fn to_chain(policies: &[ReloadablePolicy]) -> Vec<Box<dyn Policy>> {
+    policies
+        .iter()
+        .map(|policy| Box::new(policy.clone()) as Box<dyn Policy>)
+        .collect()
+}
+
+// Merge `patch` into `target` as in JSON Merge Patch (RFC 7396)
+fn merge_json(target: &mut serde_json::Value, patch: serde_json::Value) {
+    match (target, patch) {
+        (serde_json::Value::Object(target), serde_json::Value::Object(patch)) => {
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(&key);
+                } else {
+                    merge_json(target.entry(key).or_insert(serde_json::Value::Null), value);
+                }
+            }
+        }
+        (target, patch) => *target = patch,
+    }
+}
+
+/// Why a policy could not be reloaded
+#[derive(Debug)]
+pub enum ReloadError {
+    NotFound,
+    /// The new parameters were rejected by the policy's factory
+    Invalid(String),
+    /// The chain was replaced by another mechanism, e.g. a staged rollout
+    Replaced,
+}
+
+impl ReloadError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            Self::NotFound => json_error(StatusCode::NOT_FOUND, "Policy not found"),
+            Self::Invalid(e) => json_error(StatusCode::UNPROCESSABLE_ENTITY, e),
+            Self::Replaced => json_error(
+                StatusCode::CONFLICT,
+                "The policy chain was replaced and can no longer be reloaded policy by policy",
+            ),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct AdminState {
+    reloader: Arc<PolicyReloader>,
+    admin_token: Arc<String>,
+}
+
+async fn list(State(state): State<AdminState>, headers: HeaderMap) -> axum::response::Response {
+    if !is_admin(&headers, &state.admin_token) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+    Json(state.reloader.list()).into_response()
+}
+
+async fn replace(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Path(index): Path<usize>,
+    Json(parameters): Json<serde_json::Value>,
+) -> axum::response::Response {
+    if !is_admin(&headers, &state.admin_token) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+    match state.reloader.reload(index, parameters, false).await {
+        Ok(entry) => Json(entry).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+async fn update(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Path(index): Path<usize>,
+    Json(parameters): Json<serde_json::Value>,
+) -> axum::response::Response {
+    if !is_admin(&headers, &state.admin_token) {
+        return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+    }
+    match state.reloader.reload(index, parameters, true).await {
+        Ok(entry) => Json(entry).into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// Routes under `/_admin/policies` to list and reload policies, if an admin token is set
+pub fn admin_router(reloader: Arc<PolicyReloader>, admin_token: Option<&str>) -> Router {
+    let Some(admin_token) = admin_token else {
+        return Router::new();
+    };
+
+    Router::new()
+        .route("/_admin/policies", get(list))
+        .route(
+            "/_admin/policies/{index}",
+            axum::routing::put(replace).patch(update),
+        )
+        .with_state(AdminState {
+            reloader,
+            admin_token: Arc::new(admin_token.to_string()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::providers::bouncer::development::mock::v1::MockPolicyFactory;
+
+    fn registry() -> PolicyRegistry {
+        let mut registry = PolicyRegistry::new();
+        registry.register_policy::<MockPolicyFactory>();
+        registry
+    }
+
+    fn config() -> PolicyConfig {
+        PolicyConfig {
+            id: "mock".to_string(),
+            provider: "@bouncer/development/mock/v1".to_string(),
+            parameters: serde_json::json!({ "routes": [{ "path": "/a", "status": 200 }] }),
+            effective_from: None,
+            effective_until: None,
+            log_level: None,
+            apply_if: None,
+            failure_mode: FailureMode::Closed,
+            group: None,
+        }
+    }
+
+    // Status the first policy of the chain answers `/a` with
+    async fn status(chain: Arc<Vec<Box<dyn Policy>>>) -> u16 {
+        let request = Request::get("/a").body(Body::empty()).unwrap();
+        match chain[0].process(request).await {
+            PolicyResult::Terminate(response) => response.status().as_u16(),
+            PolicyResult::Continue(_) => 0,
+            PolicyResult::Error(_, error) => panic!("policy failed: {}", error),
+            PolicyResult::Redirect(location, _) => {
+                panic!("unexpected redirect to {}", location)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reload_policy() {
+        let (reloader, _) = PolicyReloader::build(registry(), &[config()])
+            .await
+            .unwrap();
+        let status = |reloader: &PolicyReloader| status(reloader.handle().load());
+        assert_eq!(status(&reloader).await, 200);
+
+        let patch = serde_json::json!({ "routes": [{ "path": "/a", "status": 503 }] });
+        reloader.reload(0, patch, true).await.unwrap();
+        assert_eq!(status(&reloader).await, 503);
+
+        // Invalid parameters keep the current policy
+        let invalid = serde_json::json!({ "routes": [] });
+        assert!(matches!(
+            reloader.reload(0, invalid, false).await,
+            Err(ReloadError::Invalid(_))
+        ));
+        assert_eq!(status(&reloader).await, 503);
+        assert!(matches!(
+            reloader.reload(1, serde_json::json!({}), true).await,
+            Err(ReloadError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_chain() {
+        let (reloader, _) = PolicyReloader::build(registry(), &[config()])
+            .await
+            .unwrap();
+        let patch = serde_json::json!({ "routes": [{ "path": "/a", "status": 503 }] });
+        reloader.reload(0, patch, true).await.unwrap();
+        let previous = reloader.handle().load();
+
+        // The rebuilt chain keeps parameters changed at runtime, and requests
+        // in flight finish on the previous chain
+        reloader.rebuild(registry()).await.unwrap();
+        let rebuilt = reloader.handle().load();
+        assert!(!Arc::ptr_eq(&previous, &rebuilt));
+        assert_eq!(status(Arc::clone(&rebuilt)).await, 503);
+        assert_eq!(status(previous).await, 503);
+
+        // A chain that fails to build leaves the current one in place
+        assert!(reloader.rebuild(PolicyRegistry::new()).await.is_err());
+        assert!(Arc::ptr_eq(&rebuilt, &reloader.handle().load()));
+    }
+}
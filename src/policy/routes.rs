@@ -71,6 +71,14 @@ impl PolicyRouter {
         }
     }
 
+    /// Full paths of the registered routes, without their trailing slash variants
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        self.routes
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .filter(|path| !path.ends_with('/'))
+    }
+
     pub fn into_router(self) -> Router {
         let mut router = Router::new();
         let route_count = self.routes.len();
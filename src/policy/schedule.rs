@@ -0,0 +1,331 @@
+use crate::admin::now_secs;
+use crate::policy::failure::FailureMode;
+use crate::policy::routes::RouteRegistration;
+use crate::policy::traits::{Capability, Policy, PolicyHealth, PolicyResult, ResponsePolicyResult};
+use crate::policy::websocket::WsPolicy;
+use async_trait::async_trait;
+use axum::{
+    body::Body,
+    http::{Request, Response},
+};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A point in time, given in config as RFC 3339 (`2025-01-31T03:00:00Z`) or
+/// Unix seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Timestamp(pub i64);
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(i64),
+            Text(String),
+        }
+
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(secs) => Ok(Self(secs)),
+            Raw::Text(text) => parse_timestamp(&text)
+                .map(Self)
+                .map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Parse an RFC 3339 timestamp, or Unix seconds, into Unix seconds
+pub fn parse_timestamp(value: &str) -> Result<i64, String> {
+    let invalid = || {
+        format!(
+            "Invalid timestamp '{}'. Expected RFC 3339, e.g. 2025-01-31T03:00:00Z, or Unix seconds",
+            value
+        )
+    };
+
+    if let Ok(secs) = value.parse::<i64>() {
+        return Ok(secs);
+    }
+
+    let (date, rest) = value.split_once(['T', 't', ' ']).ok_or_else(invalid)?;
+
+    // The offset is `Z` or `+HH:MM`/`-HH:MM`
+    let (time, offset_secs) = match rest.strip_suffix(['Z', 'z']) {
+        Some(time) => (time, 0),
+        None => {
+            let index = rest.rfind(['+', '-']).ok_or_else(invalid)?;
+            let (time, offset) = rest.split_at(index);
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':').ok_or_else(invalid)?;
+            let hours: i64 = hours.parse().map_err(|_| invalid())?;
+            let minutes: i64 = minutes.parse().map_err(|_| invalid())?;
+            (time, sign * (hours * 3600 + minutes * 60))
+        }
+    };
+
+    let date: Vec<i64> = date
+        .split('-')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    // Fractional seconds are ignored
+    let time: Vec<i64> = time
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .split(':')
+        .map(|part| part.parse().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+
+    let (&[year, month, day], &[hour, minute, second]) = (date.as_slice(), time.as_slice()) else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || !(0..24).contains(&hour)
+        || !(0..60).contains(&minute)
+        || !(0..=60).contains(&second)
+    {
+        return Err(invalid());
+    }
+
+    Ok(
+        days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second
+            - offset_secs,
+    )
+}
+
+// Days since 1970-01-01 for a proleptic Gregorian date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = if year >= 0 { year } else { year - 399 } / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+// The proleptic Gregorian date of a day since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = if days >= 0 { days } else { days - 146096 } / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Format Unix seconds as an HTTP date, e.g. `Fri, 31 Jan 2025 03:00:00 GMT`
+pub fn http_date(secs: i64) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let (days, time) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+/// When a policy is in effect
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Schedule {
+    pub from: Option<Timestamp>,
+    pub until: Option<Timestamp>,
+}
+
+impl Schedule {
+    /// Whether the schedule restricts when the policy runs
+    pub fn is_scheduled(&self) -> bool {
+        self.from.is_some() || self.until.is_some()
+    }
+
+    /// Whether the policy is in effect at `now`, in Unix seconds. `until` is exclusive
+    pub fn is_active_at(&self, now: i64) -> bool {
+        self.from.is_none_or(|Timestamp(from)| now >= from)
+            && self.until.is_none_or(|Timestamp(until)| now < until)
+    }
+}
+
+/// A policy that only runs between its `effective_from` and `effective_until`
+///
+/// Outside that window requests pass through untouched. Routes the policy
+/// registers are always served.
+pub struct ScheduledPolicy {
+    inner: Box<dyn Policy>,
+    schedule: Schedule,
+    // Whether the policy was active on the last request, to log transitions
+    active: AtomicBool,
+}
+
+impl ScheduledPolicy {
+    pub fn new(inner: Box<dyn Policy>, schedule: Schedule) -> Self {
+        let active = schedule.is_active_at(now_secs());
+        Self {
+            inner,
+            schedule,
+            active: AtomicBool::new(active),
+        }
+    }
+}
+
+#[async_trait]
+impl Policy for ScheduledPolicy {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn category(&self) -> &'static str {
+        self.inner.category()
+    }
+
+    fn name(&self) -> &'static str {
+        self.inner.name()
+    }
+
+    fn version(&self) -> &'static str {
+        self.inner.version()
+    }
+
+    fn register_routes(&self) -> Vec<RouteRegistration> {
+        self.inner.register_routes()
+    }
+
+    async fn process(&self, request: Request<Body>) -> PolicyResult {
+        let active = self.schedule.is_active_at(now_secs());
+        if self.active.swap(active, Ordering::Relaxed) != active {
+            tracing::info!(
+                "Scheduled policy @{}/{}/{}/{} is now {}",
+                self.provider(),
+                self.category(),
+                self.name(),
+                self.version(),
+                if active { "in effect" } else { "inactive" }
+            );
+        }
+
+        if active {
+            self.inner.process(request).await
+        } else {
+            PolicyResult::Continue(request)
+        }
+    }
+
+    fn processes_requests(&self) -> bool {
+        self.inner.processes_requests()
+    }
+
+    fn failure_mode(&self) -> FailureMode {
+        self.inner.failure_mode()
+    }
+
+    fn group(&self) -> Option<Arc<str>> {
+        self.inner.group()
+    }
+
+    fn applies_to(&self, request: &Request<Body>) -> bool {
+        self.inner.applies_to(request)
+    }
+
+    // Responses follow whether the policy was in effect for the last request
+    async fn process_response(&self, response: Response<Body>) -> ResponsePolicyResult {
+        if self.active.load(Ordering::Relaxed) {
+            self.inner.process_response(response).await
+        } else {
+            ResponsePolicyResult::Continue(response)
+        }
+    }
+
+    fn processes_responses(&self) -> bool {
+        self.inner.processes_responses()
+    }
+
+    fn read_only(&self) -> bool {
+        self.inner.read_only()
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        self.inner.inspects_body()
+    }
+
+    fn requires(&self) -> Vec<Capability> {
+        self.inner.requires()
+    }
+
+    // Outside its window the policy lets requests through untouched, so later
+    // policies can't rely on what it provides
+    fn provides(&self) -> Vec<Capability> {
+        vec![]
+    }
+
+    // Whether messages are checked is decided when a connection opens
+    fn websocket(&self) -> Option<Arc<dyn WsPolicy>> {
+        if self.schedule.is_active_at(now_secs()) {
+            self.inner.websocket()
+        } else {
+            None
+        }
+    }
+
+    async fn health(&self) -> PolicyHealth {
+        self.inner.health().await
+    }
+
+    async fn warm_up(&self) -> Result<(), String> {
+        self.inner.warm_up().await
+    }
+
+    async fn on_start(&self) -> Result<(), String> {
+        self.inner.on_start().await
+    }
+
+    async fn on_shutdown(&self) {
+        self.inner.on_shutdown().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("2025-01-31T03:00:00Z"), Ok(1738292400));
+        assert_eq!(parse_timestamp("2024-02-29T12:30:15+02:00"), Ok(1709202615));
+        assert_eq!(parse_timestamp("2025-01-31T03:00:00.250Z"), Ok(1738292400));
+        assert_eq!(parse_timestamp("1738292400"), Ok(1738292400));
+        assert!(parse_timestamp("2025-13-01T00:00:00Z").is_err());
+        assert!(parse_timestamp("tomorrow").is_err());
+        assert_eq!(http_date(1738292400), "Fri, 31 Jan 2025 03:00:00 GMT");
+        assert_eq!(http_date(1709202615), "Thu, 29 Feb 2024 10:30:15 GMT");
+
+        let schedule = Schedule {
+            from: Some(Timestamp(100)),
+            until: Some(Timestamp(200)),
+        };
+        assert!(!schedule.is_active_at(99));
+        assert!(schedule.is_active_at(100));
+        assert!(!schedule.is_active_at(200));
+    }
+}
@@ -0,0 +1,520 @@
+use crate::policy::context::{context, PolicyContext};
+use crate::policy::providers::bouncer::authentication::identity::Identity;
+use crate::policy::traits::{buffered_body, Capability, Policy, PolicyResult};
+use async_trait::async_trait;
+use axum::body::Body;
+use axum::http::{HeaderName, HeaderValue, Request, Response, StatusCode};
+use bouncer_policy_sdk::ffi::{FfiBuffer, FfiStr, PluginDeclaration, PolicyEntry};
+use libloading::Library;
+use once_cell::sync::Lazy;
+use std::collections::HashSet;
+use std::ffi::c_void;
+use std::sync::{Arc, Mutex};
+
+pub use bouncer_policy_sdk::ffi::{ABI_VERSION as SDK_ABI_VERSION, DECLARATION_SYMBOL};
+
+/// A plugin's declaration, with the library it lives in
+///
+/// Plugin policies hold this, so the library is only unloaded once the
+/// registry that loaded it and every policy created from it are dropped, e.g.
+/// when requests finish draining on the previous chain after a hot reload.
+pub(crate) struct Plugin {
+    declaration: &'static PluginDeclaration,
+    // Policies linked into Bouncer itself, as in tests, have no library
+    _library: Option<Library>,
+}
+
+impl Plugin {
+    /// Check the declaration a library exports and keep the library loaded
+    ///
+    /// # Safety
+    ///
+    /// `declaration` must point into `library`, as found under
+    /// [`DECLARATION_SYMBOL`].
+    pub(crate) unsafe fn load(
+        declaration: *const PluginDeclaration,
+        library: Library,
+    ) -> Result<Arc<Self>, String> {
+        if declaration.is_null() {
+            return Err("Plugin declaration is null".to_string());
+        }
+        // The declaration lives as long as the library, which this holds
+        Self::new(&*declaration, Some(library))
+    }
+
+    pub(crate) fn new(
+        declaration: &'static PluginDeclaration,
+        library: Option<Library>,
+    ) -> Result<Arc<Self>, String> {
+        verify(declaration)?;
+        Ok(Arc::new(Self {
+            declaration,
+            _library: library,
+        }))
+    }
+
+    /// The plugin's policies, with their provider IDs
+    pub(crate) fn policies(&self) -> impl Iterator<Item = (usize, &'static str)> {
+        entries(self.declaration)
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| (index, intern(unsafe { id(entry) }.unwrap_or_default())))
+    }
+
+    pub(crate) fn validate(&self, index: usize, config: &serde_json::Value) -> Result<(), String> {
+        let entry = &entries(self.declaration)[index];
+        let config = config.to_string();
+        let error = self.take((entry.validate)(FfiStr::from_bytes(config.as_bytes())));
+        match error.is_empty() {
+            true => Ok(()),
+            false => Err(String::from_utf8_lossy(&error).into_owned()),
+        }
+    }
+
+    pub(crate) fn create(
+        self: &Arc<Self>,
+        index: usize,
+        config: &serde_json::Value,
+    ) -> Result<Box<dyn Policy>, String> {
+        let entry = &entries(self.declaration)[index];
+        let config = config.to_string();
+        let mut error = FfiBuffer::empty();
+        let instance = (entry.create)(FfiStr::from_bytes(config.as_bytes()), &mut error);
+        let error = self.take(error);
+        if instance.is_null() {
+            return Err(String::from_utf8_lossy(&error).into_owned());
+        }
+
+        let id = unsafe { id(entry) }.unwrap_or_default();
+        let mut parts = id.trim_start_matches('@').splitn(4, '/').map(intern);
+        Ok(Box::new(PluginPolicy {
+            instance,
+            index,
+            provider: parts.next().unwrap_or_default(),
+            category: parts.next().unwrap_or_default(),
+            name: parts.next().unwrap_or_default(),
+            version: parts.next().unwrap_or_default(),
+            plugin: Arc::clone(self),
+        }))
+    }
+
+    // Copy a buffer the plugin returned and give it back to be freed
+    fn take(&self, buffer: FfiBuffer) -> Vec<u8> {
+        let bytes = unsafe { buffer.as_bytes() }.to_vec();
+        if !buffer.ptr.is_null() {
+            (self.declaration.free)(buffer);
+        }
+        bytes
+    }
+}
+
+/// Check that a plugin declaration uses this Bouncer's ABI and declares valid,
+/// unique policies, before anything in it is called
+pub fn verify(declaration: &PluginDeclaration) -> Result<(), String> {
+    // Only the version is read until it matches, as the rest of the layout
+    // may differ
+    if declaration.abi_version != SDK_ABI_VERSION {
+        return Err(format!(
+            "Plugin was built for SDK ABI version {}, but this Bouncer uses version {}",
+            declaration.abi_version, SDK_ABI_VERSION
+        ));
+    }
+
+    if declaration.policies.is_null() || declaration.policy_count == 0 {
+        return Err("Plugin declares no policies".to_string());
+    }
+
+    let mut ids = HashSet::new();
+    for entry in entries(declaration) {
+        let id = unsafe { id(entry) }?;
+        let parts: Vec<&str> = id.split('/').collect();
+        let valid = parts.len() == 4
+            && parts[0].len() > 1
+            && parts[0].starts_with('@')
+            && parts.iter().all(|part| !part.is_empty())
+            && parts[3].starts_with('v');
+        if !valid {
+            return Err(format!(
+                "Plugin policy ID '{}' is not of the form @provider/category/name/version",
+                id
+            ));
+        }
+        if parts[0] == "@bouncer" {
+            return Err(format!(
+                "Plugin policy ID '{}' uses the reserved @bouncer provider",
+                id
+            ));
+        }
+        if !ids.insert(id) {
+            return Err(format!("Plugin declares policy '{}' more than once", id));
+        }
+    }
+
+    Ok(())
+}
+
+fn entries(declaration: &PluginDeclaration) -> &[PolicyEntry] {
+    if declaration.policies.is_null() {
+        return &[];
+    }
+    unsafe { std::slice::from_raw_parts(declaration.policies, declaration.policy_count) }
+}
+
+// The ID of a policy entry, which lives as long as the plugin's library
+unsafe fn id(entry: &PolicyEntry) -> Result<&str, String> {
+    std::str::from_utf8(entry.id.as_bytes())
+        .map_err(|_| "Plugin policy ID is not valid UTF-8".to_string())
+}
+
+// Policies name themselves with `&'static str`s, which plugin strings aren't,
+// as libraries can be unloaded. Interning keeps one copy of each name, however
+// often plugins are reloaded
+fn intern(name: &str) -> &'static str {
+    static NAMES: Lazy<Mutex<HashSet<&'static str>>> = Lazy::new(Default::default);
+
+    let mut names = NAMES.lock().unwrap_or_else(|e| e.into_inner());
+    match names.get(name) {
+        Some(name) => name,
+        None => {
+            let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+            names.insert(name);
+            name
+        }
+    }
+}
+
+/// A policy created by a plugin
+struct PluginPolicy {
+    instance: *mut c_void,
+    index: usize,
+    provider: &'static str,
+    category: &'static str,
+    name: &'static str,
+    version: &'static str,
+    // Keeps the plugin's code loaded until `drop` has destroyed the instance
+    plugin: Arc<Plugin>,
+}
+
+// Plugin policies are `Send + Sync` by the SDK's `Policy` trait bounds
+unsafe impl Send for PluginPolicy {}
+unsafe impl Sync for PluginPolicy {}
+
+impl PluginPolicy {
+    fn entry(&self) -> &PolicyEntry {
+        &entries(self.plugin.declaration)[self.index]
+    }
+
+    fn call(&self, request: &Request<Body>) -> Result<bouncer_policy_sdk::Decision, String> {
+        let context = context(request);
+        let plugin_request = bouncer_policy_sdk::Request {
+            method: request.method().to_string(),
+            uri: request
+                .uri()
+                .path_and_query()
+                .map(|path| path.to_string())
+                .unwrap_or_else(|| request.uri().path().to_string()),
+            headers: request
+                .headers()
+                .iter()
+                .filter_map(|(name, value)| {
+                    Some((name.to_string(), value.to_str().ok()?.to_string()))
+                })
+                .collect(),
+            client_ip: context
+                .and_then(PolicyContext::client_ip)
+                .map(|ip| ip.to_string()),
+            identity: context.and_then(PolicyContext::identity).map(|identity| {
+                bouncer_policy_sdk::Identity {
+                    role: identity.role.clone(),
+                    owner: identity.owner.clone(),
+                    scopes: identity.scopes.clone(),
+                }
+            }),
+            body: Vec::new(),
+        };
+        let plugin_request = serde_json::to_vec(&plugin_request)
+            .map_err(|e| format!("Failed to serialize request: {}", e))?;
+        let body = match self.entry().inspect_body_bytes {
+            0 => &[][..],
+            limit => buffered_body(request)
+                .map(|body| &body.bytes()[..body.bytes().len().min(limit)])
+                .unwrap_or_default(),
+        };
+
+        let decision = (self.entry().process)(
+            self.instance,
+            FfiStr::from_bytes(&plugin_request),
+            FfiStr::from_bytes(body),
+        );
+        serde_json::from_slice(&self.plugin.take(decision))
+            .map_err(|e| format!("Plugin returned an invalid decision: {}", e))
+    }
+}
+
+impl Drop for PluginPolicy {
+    fn drop(&mut self) {
+        (self.entry().destroy)(self.instance);
+    }
+}
+
+#[async_trait]
+impl Policy for PluginPolicy {
+    fn provider(&self) -> &'static str {
+        self.provider
+    }
+
+    fn category(&self) -> &'static str {
+        self.category
+    }
+
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn version(&self) -> &'static str {
+        self.version
+    }
+
+    fn inspects_body(&self) -> Option<usize> {
+        Some(self.entry().inspect_body_bytes).filter(|&limit| limit > 0)
+    }
+
+    fn provides(&self) -> Vec<Capability> {
+        match self.category {
+            "authentication" => vec![Capability::Identity],
+            _ => Vec::new(),
+        }
+    }
+
+    async fn process(&self, mut request: Request<Body>) -> PolicyResult {
+        use bouncer_policy_sdk::Decision;
+
+        let decision = match self.call(&request) {
+            Ok(decision) => decision,
+            Err(e) => return PolicyResult::Error(request, e),
+        };
+        match decision {
+            Decision::Continue {
+                set_headers,
+                remove_headers,
+                identity,
+            } => {
+                for name in remove_headers {
+                    request.headers_mut().remove(name.as_str());
+                }
+                for (name, value) in set_headers {
+                    match (
+                        HeaderName::try_from(name.as_str()),
+                        HeaderValue::try_from(value),
+                    ) {
+                        (Ok(name), Ok(value)) => {
+                            request.headers_mut().insert(name, value);
+                        }
+                        _ => {
+                            let message = format!("Plugin set an invalid header '{}'", name);
+                            return PolicyResult::Error(request, message);
+                        }
+                    }
+                }
+                if let Some(identity) = identity {
+                    Identity {
+                        owner: identity.owner,
+                        scopes: identity.scopes,
+                        ..Identity::new(identity.role)
+                    }
+                    .apply(&mut request);
+                }
+                PolicyResult::Continue(request)
+            }
+            Decision::Terminate {
+                status,
+                headers,
+                body,
+            } => {
+                let mut response = Response::builder()
+                    .status(StatusCode::from_u16(status).unwrap_or(StatusCode::FORBIDDEN));
+                for (name, value) in headers {
+                    response = response.header(name, value);
+                }
+                match response.body(Body::from(body)) {
+                    Ok(response) => PolicyResult::Terminate(response),
+                    Err(e) => PolicyResult::Error(
+                        request,
+                        format!("Plugin returned an invalid response: {}", e),
+                    ),
+                }
+            }
+            Decision::Error { message } => PolicyResult::Error(request, message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::traits::BufferedBody;
+    use bouncer_policy_sdk::{Decision, PolicyFactory};
+
+    struct KeyPolicy {
+        key: String,
+    }
+
+    impl bouncer_policy_sdk::Policy for KeyPolicy {
+        fn process(&self, request: &bouncer_policy_sdk::Request) -> Decision {
+            if request.body.starts_with(b"fail") {
+                return Decision::error("backend unavailable");
+            }
+            match request.header("x-key") {
+                Some(key) if key == self.key => {
+                    Decision::authenticate(bouncer_policy_sdk::Identity {
+                        role: "partner".to_string(),
+                        ..Default::default()
+                    })
+                }
+                _ => Decision::deny(401, "Invalid key"),
+            }
+        }
+    }
+
+    struct KeyPolicyFactory;
+
+    impl PolicyFactory for KeyPolicyFactory {
+        type Policy = KeyPolicy;
+        const ID: &'static str = "@acme/authentication/key/v1";
+        const INSPECT_BODY_BYTES: usize = 4;
+
+        fn create(config: serde_json::Value) -> Result<KeyPolicy, String> {
+            let key = config["key"].as_str().ok_or("key is required")?;
+            Ok(KeyPolicy {
+                key: key.to_string(),
+            })
+        }
+    }
+
+    static POLICIES: &[PolicyEntry] = &[PolicyEntry::of::<KeyPolicyFactory>()];
+    static DECLARATION: PluginDeclaration = PluginDeclaration::new(POLICIES);
+
+    #[test]
+    fn test_verify_abi_version() {
+        let declaration = PluginDeclaration {
+            abi_version: SDK_ABI_VERSION + 1,
+            ..PluginDeclaration::new(POLICIES)
+        };
+        assert_eq!(
+            verify(&declaration).err().unwrap(),
+            format!(
+                "Plugin was built for SDK ABI version {}, but this Bouncer uses version {}",
+                SDK_ABI_VERSION + 1,
+                SDK_ABI_VERSION
+            )
+        );
+        assert!(verify(&DECLARATION).is_ok());
+    }
+
+    #[test]
+    fn test_verify_rejects_declarations() {
+        fn with_id(id: &'static str) -> PolicyEntry {
+            PolicyEntry {
+                id: FfiStr::new(id),
+                ..PolicyEntry::of::<KeyPolicyFactory>()
+            }
+        }
+        fn verify_ids(ids: &[&'static str]) -> Result<(), String> {
+            let policies = Box::leak(ids.iter().map(|id| with_id(id)).collect());
+            verify(&PluginDeclaration::new(policies))
+        }
+
+        assert_eq!(
+            verify_ids(&[]).err().as_deref(),
+            Some("Plugin declares no policies")
+        );
+        for id in [
+            "acme/authentication/key/v1",
+            "@acme/key/v1",
+            "@acme//key/v1",
+            "@/a/b/v1",
+        ] {
+            assert!(verify_ids(&[id])
+                .unwrap_err()
+                .ends_with("is not of the form @provider/category/name/version"));
+        }
+        assert!(verify_ids(&["@acme/authentication/key/1"]).is_err());
+        assert!(verify_ids(&["@bouncer/authentication/bearer/v1"])
+            .unwrap_err()
+            .ends_with("uses the reserved @bouncer provider"));
+        assert_eq!(
+            verify_ids(&["@acme/a/b/v1", "@acme/a/b/v1"])
+                .err()
+                .as_deref(),
+            Some("Plugin declares policy '@acme/a/b/v1' more than once")
+        );
+        assert!(verify_ids(&["@acme/a/b/v1", "@acme/a/b/v2"]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_plugin_policy() {
+        let plugin = Plugin::new(&DECLARATION, None).unwrap();
+        assert_eq!(
+            plugin.policies().collect::<Vec<_>>(),
+            vec![(0, "@acme/authentication/key/v1")]
+        );
+        assert_eq!(
+            plugin.validate(0, &serde_json::json!({})).err().as_deref(),
+            Some("key is required")
+        );
+        assert_eq!(
+            plugin.create(0, &serde_json::json!({})).err().as_deref(),
+            Some("key is required")
+        );
+
+        let policy = plugin
+            .create(0, &serde_json::json!({"key": "secret"}))
+            .unwrap();
+        assert_eq!(
+            [
+                policy.provider(),
+                policy.category(),
+                policy.name(),
+                policy.version()
+            ],
+            ["acme", "authentication", "key", "v1"]
+        );
+        assert_eq!(policy.inspects_body(), Some(4));
+        assert_eq!(policy.provides(), vec![Capability::Identity]);
+
+        let request = |key: &str, body: &'static str| {
+            let mut request = Request::builder()
+                .uri("/orders?page=2")
+                .header("x-key", key)
+                .body(Body::empty())
+                .unwrap();
+            request
+                .extensions_mut()
+                .insert(BufferedBody::new(body.into(), true));
+            request
+        };
+
+        match policy.process(request("secret", "")).await {
+            PolicyResult::Continue(request) => {
+                let identity = context(&request).and_then(PolicyContext::identity);
+                assert_eq!(
+                    identity.map(|identity| identity.role.as_str()),
+                    Some("partner")
+                );
+                assert_eq!(request.headers()["x-bouncer-role"], "partner");
+            }
+            _ => panic!("expected the request to continue"),
+        }
+        match policy.process(request("wrong", "")).await {
+            PolicyResult::Terminate(response) => {
+                assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            }
+            _ => panic!("expected the request to be denied"),
+        }
+        // Only the first 4 body bytes reach the plugin
+        match policy.process(request("secret", "failure")).await {
+            PolicyResult::Error(_, message) => assert_eq!(message, "backend unavailable"),
+            _ => panic!("expected an error"),
+        }
+    }
+}
@@ -0,0 +1,319 @@
+use crate::admin::{is_admin, json_error};
+use axum::{
+    body::Body,
+    extract::{Path, Query},
+    http::{HeaderMap, Request, StatusCode},
+    response::IntoResponse,
+    routing::{delete, get},
+    Json, Router,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::watch;
+
+/// The credential a request was authenticated with, e.g. a managed token's ID
+/// or a JWT's `jti`
+///
+/// Authentication policies add this to the request's extensions, so every
+/// session opened with a credential can be terminated when it's revoked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionCredential(pub String);
+
+/// An in-flight request or open connection, as listed by the admin API
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub owner: Option<String>,
+    pub role: Option<String>,
+    pub credential: Option<String>,
+    pub client_ip: Option<String>,
+    pub method: String,
+    pub path: String,
+    pub route: String,
+    /// Unix timestamp in seconds
+    pub started_at: i64,
+    pub duration_ms: u64,
+}
+
+/// Which sessions to list or terminate. Empty fields match every session
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SessionFilter {
+    pub owner: Option<String>,
+    pub credential: Option<String>,
+    pub client_ip: Option<String>,
+}
+
+impl SessionFilter {
+    pub fn is_empty(&self) -> bool {
+        self.owner.is_none() && self.credential.is_none() && self.client_ip.is_none()
+    }
+
+    fn matches(&self, session: &SessionInfo) -> bool {
+        let field = |filter: &Option<String>, value: &Option<String>| {
+            filter
+                .as_ref()
+                .is_none_or(|filter| value.as_ref() == Some(filter))
+        };
+        field(&self.owner, &session.owner)
+            && field(&self.credential, &session.credential)
+            && field(&self.client_ip, &session.client_ip)
+    }
+}
+
+struct Entry {
+    info: SessionInfo,
+    started: Instant,
+    terminate: watch::Sender<bool>,
+}
+
+/// Active sessions: requests that passed the policy chain and whose response
+/// hasn't finished streaming
+///
+/// Sessions are registered by the policy middleware and removed when their
+/// [`Session`] guard is dropped.
+#[derive(Default)]
+pub struct SessionRegistry {
+    next_id: AtomicU64,
+    sessions: Mutex<HashMap<u64, Entry>>,
+}
+
+/// The session registry of the current instance
+pub fn sessions() -> Arc<SessionRegistry> {
+    crate::instance::current().sessions.clone()
+}
+
+impl SessionRegistry {
+    /// Register a request that passed the policy chain
+    pub fn open(
+        self: &Arc<Self>,
+        request: &Request<Body>,
+        route: &str,
+        client_ip: Option<String>,
+    ) -> Session {
+        let header = |name: &str| {
+            request
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string)
+        };
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let info = SessionInfo {
+            id,
+            owner: header("x-bouncer-owner"),
+            role: header("x-bouncer-role"),
+            credential: request
+                .extensions()
+                .get::<SessionCredential>()
+                .map(|SessionCredential(credential)| credential.clone()),
+            client_ip,
+            method: request.method().to_string(),
+            path: request.uri().path().to_string(),
+            route: route.to_string(),
+            started_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs() as i64)
+                .unwrap_or(0),
+            duration_ms: 0,
+        };
+
+        let (terminate, terminated) = watch::channel(false);
+        self.sessions.lock().unwrap().insert(
+            id,
+            Entry {
+                info,
+                started: Instant::now(),
+                terminate,
+            },
+        );
+        Session {
+            id,
+            registry: self.clone(),
+            terminated,
+        }
+    }
+
+    /// Sessions matching the filter, oldest first
+    pub fn list(&self, filter: &SessionFilter) -> Vec<SessionInfo> {
+        let sessions = self.sessions.lock().unwrap();
+        let mut list: Vec<SessionInfo> = sessions
+            .values()
+            .filter(|entry| filter.matches(&entry.info))
+            .map(|entry| SessionInfo {
+                duration_ms: entry.started.elapsed().as_millis() as u64,
+                ..entry.info.clone()
+            })
+            .collect();
+        list.sort_by_key(|session| session.id);
+        list
+    }
+
+    /// Terminate a session, returning whether it was active
+    pub fn terminate(&self, id: u64) -> bool {
+        match self.sessions.lock().unwrap().get(&id) {
+            Some(entry) => {
+                entry.terminate.send_replace(true);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Terminate every session matching the filter, returning how many there were
+    pub fn terminate_matching(&self, filter: &SessionFilter) -> usize {
+        let sessions = self.sessions.lock().unwrap();
+        let mut terminated = 0;
+        for entry in sessions
+            .values()
+            .filter(|entry| filter.matches(&entry.info))
+        {
+            entry.terminate.send_replace(true);
+            terminated += 1;
+        }
+        if terminated > 0 {
+            tracing::info!("Terminated {} sessions", terminated);
+        }
+        terminated
+    }
+}
+
+/// Guard for a registered session, which deregisters it when dropped
+pub struct Session {
+    id: u64,
+    registry: Arc<SessionRegistry>,
+    terminated: watch::Receiver<bool>,
+}
+
+impl Session {
+    /// Resolves once the session is terminated through the registry
+    pub async fn terminated(&mut self) {
+        if self
+            .terminated
+            .wait_for(|terminated| *terminated)
+            .await
+            .is_err()
+        {
+            // The registry always outlives its sessions
+            std::future::pending::<()>().await;
+        }
+    }
+
+    /// Keep the session open while `body` streams, and cut the body off if
+    /// the session is terminated
+    pub fn track_body(self, body: Body) -> Body {
+        let state = (body.into_data_stream(), Some(self));
+        let stream = futures::stream::unfold(state, |(mut stream, session)| async move {
+            let mut session = session?;
+            tokio::select! {
+                chunk = stream.next() => Some((chunk?, (stream, Some(session)))),
+                _ = session.terminated() => {
+                    tracing::info!("Session {} terminated while streaming", session.id);
+                    Some((Err(axum::Error::new("Session terminated")), (stream, None)))
+                }
+            }
+        });
+        Body::from_stream(stream)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.registry.sessions.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Routes under `/_admin/sessions` to list and terminate sessions, if an admin token is set
+pub fn admin_router(admin_token: Option<&str>) -> Router {
+    let Some(admin_token) = admin_token else {
+        return Router::new();
+    };
+    let admin_token = Arc::new(admin_token.to_string());
+
+    let list = {
+        let admin_token = admin_token.clone();
+        move |headers: HeaderMap, Query(filter): Query<SessionFilter>| async move {
+            if !is_admin(&headers, &admin_token) {
+                return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+            }
+            Json(sessions().list(&filter)).into_response()
+        }
+    };
+
+    let terminate_matching = {
+        let admin_token = admin_token.clone();
+        move |headers: HeaderMap, Query(filter): Query<SessionFilter>| async move {
+            if !is_admin(&headers, &admin_token) {
+                return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+            }
+            // Don't terminate every session by accident
+            if filter.is_empty() {
+                return json_error(
+                    StatusCode::BAD_REQUEST,
+                    "Filter by owner, credential or client_ip",
+                );
+            }
+            let terminated = sessions().terminate_matching(&filter);
+            Json(serde_json::json!({ "terminated": terminated })).into_response()
+        }
+    };
+
+    let terminate = move |headers: HeaderMap, Path(id): Path<u64>| async move {
+        if !is_admin(&headers, &admin_token) {
+            return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+        }
+        if sessions().terminate(id) {
+            StatusCode::NO_CONTENT.into_response()
+        } else {
+            json_error(StatusCode::NOT_FOUND, "Session not found")
+        }
+    };
+
+    Router::new()
+        .route("/_admin/sessions", get(list).delete(terminate_matching))
+        .route("/_admin/sessions/{id}", delete(terminate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_terminate_sessions() {
+        let registry = Arc::new(SessionRegistry::default());
+        let mut request = Request::get("/stream")
+            .header("x-bouncer-owner", "alice")
+            .body(Body::empty())
+            .unwrap();
+        request
+            .extensions_mut()
+            .insert(SessionCredential("tok_1".to_string()));
+
+        let session = registry.open(&request, "stream", None);
+        let other = registry.open(
+            &Request::get("/").body(Body::empty()).unwrap(),
+            "root",
+            None,
+        );
+        let filter = SessionFilter {
+            credential: Some("tok_1".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(registry.list(&filter)[0].owner.as_deref(), Some("alice"));
+
+        // A terminated session cuts off its response body
+        let (sender, receiver) = futures::channel::mpsc::unbounded::<Result<&str, axum::Error>>();
+        let body = session.track_body(Body::from_stream(receiver));
+        assert_eq!(registry.terminate_matching(&filter), 1);
+        assert!(axum::body::to_bytes(body, 1024).await.is_err());
+        drop(sender);
+
+        // Finished sessions are deregistered
+        assert!(registry.list(&filter).is_empty());
+        drop(other);
+        assert!(registry.list(&SessionFilter::default()).is_empty());
+    }
+}
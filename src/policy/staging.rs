@@ -0,0 +1,331 @@
+use crate::admin::{is_admin, json_error};
+use crate::config::StagingConfig;
+use crate::policy::middleware::PolicyChainHandle;
+use crate::policy::traits::Policy;
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+type Chain = Arc<Vec<Box<dyn Policy>>>;
+
+/// Which chain handled a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    Stable,
+    Staged,
+}
+
+impl Variant {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Staged => "staged",
+        }
+    }
+}
+
+/// Request counts and latency for one chain
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VariantStats {
+    pub requests: u64,
+    /// Responses with a 4xx status, including policy rejections
+    pub client_errors: u64,
+    pub server_errors: u64,
+    pub total_latency_ms: u64,
+}
+
+#[derive(Default)]
+struct VariantMetrics {
+    requests: AtomicU64,
+    client_errors: AtomicU64,
+    server_errors: AtomicU64,
+    total_latency_ms: AtomicU64,
+}
+
+impl VariantMetrics {
+    fn record(&self, status: u16, elapsed: Duration) {
+        self.requests.fetch_add(1, Ordering::Relaxed);
+        match status {
+            400..=499 => self.client_errors.fetch_add(1, Ordering::Relaxed),
+            500..=599 => self.server_errors.fetch_add(1, Ordering::Relaxed),
+            _ => 0,
+        };
+        self.total_latency_ms
+            .fetch_add(elapsed.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> VariantStats {
+        VariantStats {
+            requests: self.requests.load(Ordering::Relaxed),
+            client_errors: self.client_errors.load(Ordering::Relaxed),
+            server_errors: self.server_errors.load(Ordering::Relaxed),
+            total_latency_ms: self.total_latency_ms.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        self.requests.store(0, Ordering::Relaxed);
+        self.client_errors.store(0, Ordering::Relaxed);
+        self.server_errors.store(0, Ordering::Relaxed);
+        self.total_latency_ms.store(0, Ordering::Relaxed);
+    }
+}
+
+/// State of a staged rollout, as served at `/_admin/staging`
+#[derive(Debug, Clone, Serialize)]
+pub struct StagingStatus {
+    /// Whether a staged chain is loaded
+    pub active: bool,
+    pub percent: u8,
+    pub stable: VariantStats,
+    pub staged: VariantStats,
+}
+
+/// A staged policy chain that receives a percentage of the traffic
+///
+/// The stable chain is the one behind the layer's [`PolicyChainHandle`].
+/// Promoting swaps the staged chain in as the stable one; rolling back drops it.
+/// Either way the rollout ends, and the percentage is reset to 0.
+pub struct Staging {
+    stable: PolicyChainHandle,
+    staged: RwLock<Option<Chain>>,
+    percent: AtomicU8,
+    stable_metrics: VariantMetrics,
+    staged_metrics: VariantMetrics,
+}
+
+impl Staging {
+    pub fn new(stable: PolicyChainHandle) -> Self {
+        Self {
+            stable,
+            staged: RwLock::new(None),
+            percent: AtomicU8::new(0),
+            stable_metrics: VariantMetrics::default(),
+            staged_metrics: VariantMetrics::default(),
+        }
+    }
+
+    /// Load a staged chain, replacing any previous one, and reset the metrics
+    pub fn stage(&self, policies: Vec<Box<dyn Policy>>, percent: u8) {
+        *self.staged.write().unwrap() = Some(Arc::new(policies));
+        self.percent.store(percent.min(100), Ordering::Relaxed);
+        self.stable_metrics.reset();
+        self.staged_metrics.reset();
+    }
+
+    /// Set the percentage of requests sent through the staged chain
+    pub fn set_percent(&self, percent: u8) -> Result<(), String> {
+        if percent > 100 {
+            return Err("percent must be between 0 and 100".to_string());
+        }
+        self.percent.store(percent, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Pick the chain for a request
+    pub fn select(&self) -> (Chain, Variant) {
+        let percent = self.percent.load(Ordering::Relaxed);
+        if percent > 0 && rand::thread_rng().gen_range(0..100) < percent {
+            if let Some(staged) = self.staged.read().unwrap().clone() {
+                return (staged, Variant::Staged);
+            }
+        }
+        (self.stable.load(), Variant::Stable)
+    }
+
+    pub fn record(&self, variant: Variant, status: u16, elapsed: Duration) {
+        match variant {
+            Variant::Stable => self.stable_metrics.record(status, elapsed),
+            Variant::Staged => self.staged_metrics.record(status, elapsed),
+        }
+    }
+
+    /// Make the staged chain the stable one. Returns false if nothing is staged
+    pub fn promote(&self) -> bool {
+        let Some(staged) = self.staged.write().unwrap().take() else {
+            return false;
+        };
+        self.percent.store(0, Ordering::Relaxed);
+        self.stable.store(staged);
+        tracing::info!("Promoted the staged policy chain");
+        true
+    }
+
+    /// Drop the staged chain. Returns false if nothing is staged
+    pub fn rollback(&self) -> bool {
+        let rolled_back = self.staged.write().unwrap().take().is_some();
+        self.percent.store(0, Ordering::Relaxed);
+        if rolled_back {
+            tracing::info!("Rolled back the staged policy chain");
+        }
+        rolled_back
+    }
+
+    pub fn status(&self) -> StagingStatus {
+        StagingStatus {
+            active: self.staged.read().unwrap().is_some(),
+            percent: self.percent.load(Ordering::Relaxed),
+            stable: self.stable_metrics.snapshot(),
+            staged: self.staged_metrics.snapshot(),
+        }
+    }
+}
+
+/// Build the staged chain from the config file named in `config`
+///
+/// Files with an `instances:` list stage the chain of the instance bound to
+/// `bind_address`, the address of the gateway being staged. The staged
+/// policies' own admin routes are not served, since they would clash with
+/// those of the stable chain.
+pub async fn load_staged_chain(
+    config: &StagingConfig,
+    bind_address: &str,
+) -> Result<Vec<Box<dyn Policy>>, String> {
+    let staged = crate::config::load_instance(&config.config, bind_address)
+        .map_err(|e| format!("Staged config {}: {}", config.config, e))?;
+    let registry = crate::server::create_registry(&staged);
+    let (policies, _routes) = registry
+        .build_policy_chain(&staged.policies)
+        .await
+        .map_err(|e| format!("Staged config {}: {}", config.config, e))?;
+    crate::policy::lifecycle::start(&policies)
+        .await
+        .map_err(|e| format!("Staged config {}: {}", config.config, e))?;
+    Ok(policies)
+}
+
+#[derive(Clone)]
+struct AdminState {
+    staging: Arc<Staging>,
+    config: StagingConfig,
+    bind_address: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PercentRequest {
+    percent: u8,
+}
+
+#[allow(clippy::result_large_err)]
+fn authorize(state: &AdminState, headers: &HeaderMap) -> Result<(), axum::response::Response> {
+    match &state.config.admin_token {
+        Some(token) if is_admin(headers, token.expose()) => Ok(()),
+        _ => Err(json_error(StatusCode::UNAUTHORIZED, "Invalid admin token")),
+    }
+}
+
+async fn status(State(state): State<AdminState>, headers: HeaderMap) -> axum::response::Response {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+    Json(state.staging.status()).into_response()
+}
+
+async fn set_percent(
+    State(state): State<AdminState>,
+    headers: HeaderMap,
+    Json(request): Json<PercentRequest>,
+) -> axum::response::Response {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+    if let Err(e) = state.staging.set_percent(request.percent) {
+        return json_error(StatusCode::BAD_REQUEST, e);
+    }
+    tracing::info!(
+        "Staged policy chain now receives {}% of requests",
+        request.percent
+    );
+    Json(state.staging.status()).into_response()
+}
+
+// Re-read the staged config file, e.g. to stage a new version after a promotion
+async fn reload(State(state): State<AdminState>, headers: HeaderMap) -> axum::response::Response {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+    match load_staged_chain(&state.config, &state.bind_address).await {
+        Ok(policies) => {
+            state.staging.stage(policies, state.config.percent);
+            tracing::info!("Staged policy chain from {}", state.config.config);
+            Json(state.staging.status()).into_response()
+        }
+        Err(e) => json_error(StatusCode::UNPROCESSABLE_ENTITY, e),
+    }
+}
+
+async fn promote(State(state): State<AdminState>, headers: HeaderMap) -> axum::response::Response {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+    if !state.staging.promote() {
+        return json_error(StatusCode::CONFLICT, "No staged policy chain");
+    }
+    Json(state.staging.status()).into_response()
+}
+
+async fn rollback(State(state): State<AdminState>, headers: HeaderMap) -> axum::response::Response {
+    if let Err(response) = authorize(&state, &headers) {
+        return response;
+    }
+    if !state.staging.rollback() {
+        return json_error(StatusCode::CONFLICT, "No staged policy chain");
+    }
+    Json(state.staging.status()).into_response()
+}
+
+/// Admin routes for the rollout under `/_admin/staging`, if an admin token is set
+pub fn admin_router(staging: Arc<Staging>, config: &StagingConfig, bind_address: &str) -> Router {
+    if config.admin_token.is_none() {
+        return Router::new();
+    }
+
+    Router::new()
+        .route("/_admin/staging", get(status))
+        .route("/_admin/staging/percent", post(set_percent))
+        .route("/_admin/staging/reload", post(reload))
+        .route("/_admin/staging/promote", post(promote))
+        .route("/_admin/staging/rollback", post(rollback))
+        .with_state(AdminState {
+            staging,
+            config: config.clone(),
+            bind_address: bind_address.to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_promote_and_rollback() {
+        let stable = PolicyChainHandle::new(Vec::new());
+        let staging = Staging::new(stable.clone());
+        assert_eq!(staging.select().1, Variant::Stable);
+        assert!(!staging.promote());
+
+        staging.stage(Vec::new(), 100);
+        assert_eq!(staging.select().1, Variant::Staged);
+        assert!(staging.set_percent(101).is_err());
+
+        staging.set_percent(0).unwrap();
+        assert_eq!(staging.select().1, Variant::Stable);
+
+        staging.set_percent(100).unwrap();
+        let (staged, _) = staging.select();
+        assert!(staging.promote());
+        assert!(Arc::ptr_eq(&stable.load(), &staged));
+        assert_eq!(staging.select().1, Variant::Stable);
+        assert!(!staging.rollback());
+    }
+}
@@ -1,11 +1,168 @@
+use crate::policy::failure::FailureMode;
+use crate::policy::websocket::WsPolicy;
 use async_trait::async_trait;
-use axum::body::Body;
-use axum::http::{Request, Response};
-use serde::Deserialize;
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, Request, Response, StatusCode, Uri};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
 
 pub enum PolicyResult {
     Continue(Request<axum::body::Body>),
     Terminate(Response<axum::body::Body>),
+    /// The policy couldn't decide, e.g. because its database is down
+    ///
+    /// The middleware continues with the request or answers 503, depending on
+    /// the policy's [`Policy::failure_mode`].
+    Error(Request<axum::body::Body>, String),
+    /// Send the client to another URL, e.g. a login page with `302 Found`, or
+    /// the HTTPS URL with `307 Temporary Redirect` to keep the method and body
+    ///
+    /// The middleware answers with [`redirect`].
+    Redirect(Uri, StatusCode),
+}
+
+/// The status a redirect is sent with
+///
+/// Statuses that aren't redirects are replaced with `302 Found`.
+pub fn redirect_status(status: StatusCode) -> StatusCode {
+    if status.is_redirection() {
+        return status;
+    }
+    tracing::warn!(
+        "Redirecting with {} instead of {}",
+        StatusCode::FOUND,
+        status
+    );
+    StatusCode::FOUND
+}
+
+/// The response redirecting to `location` with [`redirect_status`]
+pub fn redirect(location: &Uri, status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = redirect_status(status);
+    if let Ok(location) = HeaderValue::from_str(&location.to_string()) {
+        response.headers_mut().insert(header::LOCATION, location);
+    }
+    response
+}
+
+/// What a policy does with the response to a request on its way back
+pub enum ResponsePolicyResult {
+    /// Hand the possibly modified response to the policy before this one
+    Continue(Response<Body>),
+    /// Send this response as is, skipping the policies before this one
+    Terminate(Response<Body>),
+}
+
+/// Status of the backends a policy depends on, such as a database or a
+/// remote key set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "snake_case")]
+pub enum PolicyHealth {
+    Healthy,
+    /// A backend is failing, but the policy can still handle requests, e.g.
+    /// by failing open
+    Degraded(String),
+    /// The policy can't handle requests correctly until a backend recovers
+    Unhealthy(String),
+}
+
+/// Headers to set on the response to a request that passes the policy chain
+///
+/// Policies add these with [`add_response_header`]. They are carried in the
+/// request's extensions and applied by the policy middleware once the inner
+/// service responds.
+#[derive(Clone, Debug, Default)]
+pub struct ResponseHeaders(pub HeaderMap);
+
+/// Set a header on the eventual response to `request`
+pub fn add_response_header(request: &mut Request<Body>, name: HeaderName, value: HeaderValue) {
+    request
+        .extensions_mut()
+        .get_or_insert_default::<ResponseHeaders>()
+        .0
+        .insert(name, value);
+}
+
+/// Something a policy adds to requests that later policies in the chain rely on
+///
+/// Policies declare what they provide and require, and the chain is checked
+/// when it's built, so a misordered chain fails at startup instead of
+/// rejecting every request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Capability {
+    /// An authenticated identity, set by authentication policies in the
+    /// `x-bouncer-role`, `x-bouncer-owner` and `x-bouncer-scopes` headers
+    Identity,
+}
+
+impl Capability {
+    /// The kind of policy that provides this capability, for error messages
+    pub fn provided_by(&self) -> &'static str {
+        match self {
+            Self::Identity => "authentication policies",
+        }
+    }
+}
+
+impl std::fmt::Display for Capability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Identity => f.write_str("an identity"),
+        }
+    }
+}
+
+/// Request body bytes shared by every policy in the chain and the forwarder
+///
+/// The policy middleware reads the body once, up to the largest amount any
+/// policy asks for with [`Policy::inspects_body`], and adds this to the
+/// request's extensions. Clones share the same bytes. When the whole body fit,
+/// the forwarder sends these bytes instead of reading the body again; otherwise
+/// they are replayed ahead of the rest of the body. Requests nobody inspects
+/// have no `BufferedBody` and are streamed untouched.
+#[derive(Debug, Clone)]
+pub struct BufferedBody {
+    bytes: Bytes,
+    complete: bool,
+}
+
+impl BufferedBody {
+    pub(crate) fn new(bytes: Bytes, complete: bool) -> Self {
+        Self { bytes, complete }
+    }
+
+    /// The bytes that were read, at most the inspection budget
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Whether `bytes` is the whole body, rather than only its start
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// The whole body, if it fit in the inspection budget
+    pub fn complete_bytes(&self) -> Option<&Bytes> {
+        self.complete.then_some(&self.bytes)
+    }
+}
+
+/// The buffered start of `request`'s body, if a policy in the chain inspects bodies
+pub fn buffered_body(request: &Request<Body>) -> Option<&BufferedBody> {
+    request.extensions().get::<BufferedBody>()
+}
+
+/// Upstream base URL to forward a request to instead of `server.destination_address`
+///
+/// Routing policies set this with [`route_to`]. The request's path and query
+/// are appended to it, as with the configured destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream(pub String);
+
+/// Forward `request` to `upstream` instead of the configured destination
+pub fn route_to(request: &mut Request<Body>, upstream: impl Into<String>) {
+    request.extensions_mut().insert(Upstream(upstream.into()));
 }
 
 #[async_trait]
@@ -53,6 +210,15 @@ pub trait Policy: Send + Sync {
         vec![]
     }
 
+    /// Returns true if the policy should process `request`
+    ///
+    /// The middleware lets requests the policy doesn't apply to through
+    /// without calling `process` or `process_response`. Policies with an
+    /// `apply_if` in their config check it here.
+    fn applies_to(&self, _request: &Request<Body>) -> bool {
+        true
+    }
+
     /// Process the request. This method is optional - policies can choose to only register routes.
     /// If not implemented, the policy will not be added to the policy chain.
     async fn process(&self, request: Request<Body>) -> PolicyResult {
@@ -63,4 +229,106 @@ pub trait Policy: Send + Sync {
     fn processes_requests(&self) -> bool {
         true
     }
+
+    /// What happens to requests the policy returns [`PolicyResult::Error`] for
+    ///
+    /// Set by the `failure_mode` of the policy's config.
+    fn failure_mode(&self) -> FailureMode {
+        FailureMode::Closed
+    }
+
+    /// The `group` of the policy's config
+    ///
+    /// Consecutive policies in the same group run concurrently, each on a copy
+    /// of the request, and their changes are merged in chain order.
+    fn group(&self) -> Option<Arc<str>> {
+        None
+    }
+
+    /// Process the response to a request the policy let through
+    ///
+    /// Once the upstream responds, the policies that processed the request see
+    /// its response in reverse chain order, e.g. to add headers, scrub the body
+    /// or log it. Only called when [`Policy::processes_responses`] returns true.
+    async fn process_response(&self, response: Response<Body>) -> ResponsePolicyResult {
+        ResponsePolicyResult::Continue(response)
+    }
+
+    /// Returns true if the policy processes responses (i.e., implements
+    /// process_response)
+    fn processes_responses(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the policy only reads the request's method, URI, headers
+    /// and extensions, never its body, and continues with it unchanged
+    ///
+    /// With `server.parallel_policies`, consecutive policies like this that
+    /// provide no capabilities run concurrently, on copies of the request
+    /// without a body.
+    fn read_only(&self) -> bool {
+        false
+    }
+
+    /// Returns how many bytes at the start of the request body the policy needs
+    /// to inspect, if any
+    ///
+    /// The middleware reads the body once for all policies, up to the largest
+    /// amount requested and `server.max_body_inspection_bytes`, and adds it to
+    /// the request as a [`BufferedBody`]. Read it with [`buffered_body`] instead
+    /// of consuming the body.
+    fn inspects_body(&self) -> Option<usize> {
+        None
+    }
+
+    /// Capabilities that a policy earlier in the chain must provide
+    fn requires(&self) -> Vec<Capability> {
+        vec![]
+    }
+
+    /// Capabilities this policy adds to the requests it lets through
+    fn provides(&self) -> Vec<Capability> {
+        vec![]
+    }
+
+    /// Returns the policy's hooks for the messages of websocket connections, if any
+    ///
+    /// `process` still sees the upgrade request like any other request.
+    fn websocket(&self) -> Option<Arc<dyn WsPolicy>> {
+        None
+    }
+
+    /// Check the backends the policy depends on
+    ///
+    /// Called for `/readyz` and `/_admin/status`, so it should be quick.
+    /// Unhealthy policies make the gateway report itself as not ready.
+    async fn health(&self) -> PolicyHealth {
+        PolicyHealth::Healthy
+    }
+
+    /// Load what the first requests would otherwise wait for, such as
+    /// connections, prepared statements or caches
+    ///
+    /// Called once before traffic is accepted when `server.warm_up` is set.
+    /// Errors are logged and don't stop the gateway from starting.
+    async fn warm_up(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Start what the policy runs alongside requests, such as a task flushing
+    /// buffered usage on an interval
+    ///
+    /// Called once before the policy serves requests: at startup, and when a
+    /// reload creates it. An error stops the gateway from starting, or keeps
+    /// the policy it was to replace.
+    async fn on_start(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Flush what the policy holds in memory and stop what `on_start` started
+    ///
+    /// Called once when the gateway shuts down, after requests in flight have
+    /// finished, and when a reload replaces the policy. Requests that were
+    /// already running on a replaced policy may still reach it afterwards.
+    async fn on_shutdown(&self) {}
 }
@@ -0,0 +1,274 @@
+use axum::body::{Body, Bytes};
+use axum::http::{header, HeaderMap, Request, Response, StatusCode};
+use futures::StreamExt;
+use std::sync::Arc;
+
+/// Rewrites a response body one chunk at a time
+///
+/// A transform sees each chunk as it arrives from the upstream, so responses
+/// are never buffered whole. It can hold bytes back, e.g. the start of a match
+/// that may continue in the next chunk, and must return them from `finish`.
+pub trait BodyTransform: Send {
+    fn transform(&mut self, chunk: Bytes) -> Bytes;
+
+    /// Called once the body ends, for any bytes held back
+    fn finish(&mut self) -> Bytes {
+        Bytes::new()
+    }
+}
+
+/// Creates a [`BodyTransform`] for each response it applies to
+pub trait ResponseTransform: Send + Sync {
+    /// Rewrite the response's headers, e.g. `Location`
+    ///
+    /// Called for every response, before `applies_to`, including compressed
+    /// responses whose bodies are passed through.
+    fn rewrite_headers(&self, _headers: &mut HeaderMap) {}
+
+    /// Whether to transform a response with these headers, e.g. only HTML
+    fn applies_to(&self, _headers: &HeaderMap) -> bool {
+        true
+    }
+
+    fn start(&self) -> Box<dyn BodyTransform>;
+}
+
+/// Transforms for the response to a request that passes the policy chain
+///
+/// Policies add these with [`add_response_transform`]. They are carried in the
+/// request's extensions and applied in order by the policy middleware to the
+/// response from the inner service.
+#[derive(Clone, Default)]
+pub struct ResponseTransforms(pub Vec<Arc<dyn ResponseTransform>>);
+
+/// Transform the body of the eventual response to `request`
+pub fn add_response_transform(request: &mut Request<Body>, transform: Arc<dyn ResponseTransform>) {
+    request
+        .extensions_mut()
+        .get_or_insert_default::<ResponseTransforms>()
+        .0
+        .push(transform);
+}
+
+/// Apply the transforms that apply to `response` to its body as it streams
+///
+/// Compressed responses are passed through untouched, since their chunks
+/// can't be rewritten without decompressing them, and so are partial (206)
+/// responses, since a match may cross the edge of the range.
+pub fn apply_transforms(
+    response: Response<Body>,
+    ResponseTransforms(transforms): ResponseTransforms,
+) -> Response<Body> {
+    let (mut parts, body) = response.into_parts();
+    for transform in &transforms {
+        transform.rewrite_headers(&mut parts.headers);
+    }
+    let stages: Vec<Box<dyn BodyTransform>> = transforms
+        .iter()
+        .filter(|transform| transform.applies_to(&parts.headers))
+        .map(|transform| transform.start())
+        .collect();
+    if stages.is_empty() {
+        return Response::from_parts(parts, body);
+    }
+
+    let encoding = parts
+        .headers
+        .get(header::CONTENT_ENCODING)
+        .and_then(|value| value.to_str().ok());
+    if encoding.is_some_and(|encoding| !encoding.eq_ignore_ascii_case("identity")) {
+        tracing::debug!("Not transforming a compressed response");
+        return Response::from_parts(parts, body);
+    }
+    if parts.status == StatusCode::PARTIAL_CONTENT {
+        tracing::debug!("Not transforming a partial response");
+        return Response::from_parts(parts, body);
+    }
+
+    // The length changes with the body, and byte ranges of the upstream's body
+    // no longer line up with the rewritten one
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::ACCEPT_RANGES);
+    Response::from_parts(parts, transform_body(body, Chain(stages)))
+}
+
+fn transform_body(body: Body, chain: Chain) -> Body {
+    let state = (body.into_data_stream(), chain, false);
+    let stream = futures::stream::unfold(state, |(mut stream, mut chain, done)| async move {
+        if done {
+            return None;
+        }
+        match stream.next().await {
+            Some(Ok(chunk)) => {
+                let chunk = chain.transform(chunk);
+                Some((Ok(chunk), (stream, chain, false)))
+            }
+            Some(Err(e)) => Some((Err(e), (stream, chain, true))),
+            None => {
+                let tail = chain.finish();
+                Some((Ok(tail), (stream, chain, true)))
+            }
+        }
+    })
+    .filter(|chunk| futures::future::ready(chunk.as_ref().map_or(true, |c| !c.is_empty())));
+    Body::from_stream(stream)
+}
+
+/// Runs transforms one after another, each on the output of the one before
+pub struct Chain(pub Vec<Box<dyn BodyTransform>>);
+
+impl BodyTransform for Chain {
+    fn transform(&mut self, chunk: Bytes) -> Bytes {
+        run_stages(&mut self.0, chunk)
+    }
+
+    // Flush each stage in order, passing what it held back through the later stages
+    fn finish(&mut self) -> Bytes {
+        let mut tail = Vec::new();
+        for index in 0..self.0.len() {
+            let (stage, later) = self.0[index..].split_first_mut().unwrap();
+            let held = stage.finish();
+            tail.extend_from_slice(&run_stages(later, held));
+        }
+        Bytes::from(tail)
+    }
+}
+
+fn run_stages(stages: &mut [Box<dyn BodyTransform>], chunk: Bytes) -> Bytes {
+    stages
+        .iter_mut()
+        .fold(chunk, |chunk, stage| stage.transform(chunk))
+}
+
+/// Replaces every occurrence of byte strings, including across chunk boundaries
+pub struct Replace {
+    pairs: Vec<(Bytes, Bytes)>,
+    longest: usize,
+    pending: Vec<u8>,
+}
+
+impl Replace {
+    pub fn new(from: impl Into<Bytes>, to: impl Into<Bytes>) -> Result<Self, String> {
+        Self::many(vec![(from.into(), to.into())])
+    }
+
+    /// Replace several byte strings in one pass, so replacements are never
+    /// replaced again. Where several match, the first in `pairs` wins.
+    pub fn many(pairs: Vec<(Bytes, Bytes)>) -> Result<Self, String> {
+        if pairs.iter().any(|(from, _)| from.is_empty()) {
+            return Err("The text to replace must not be empty".to_string());
+        }
+        let Some(longest) = pairs.iter().map(|(from, _)| from.len()).max() else {
+            return Err("At least one replacement must be given".to_string());
+        };
+        Ok(Self {
+            pairs,
+            longest,
+            pending: Vec::new(),
+        })
+    }
+
+    fn replace_pending(&mut self, at_end: bool) -> Bytes {
+        // Until the body ends, keep back fewer bytes than the longest match,
+        // which the next chunk may complete
+        let limit = if at_end {
+            self.pending.len()
+        } else {
+            (self.pending.len() + 1).saturating_sub(self.longest)
+        };
+        let mut output = Vec::with_capacity(self.pending.len());
+        let mut index = 0;
+        while index < limit {
+            let rest = &self.pending[index..];
+            match self.pairs.iter().find(|(from, _)| rest.starts_with(from)) {
+                Some((from, to)) => {
+                    output.extend_from_slice(to);
+                    index += from.len();
+                }
+                None => {
+                    output.push(rest[0]);
+                    index += 1;
+                }
+            }
+        }
+        self.pending.drain(..index);
+        Bytes::from(output)
+    }
+}
+
+impl BodyTransform for Replace {
+    fn transform(&mut self, chunk: Bytes) -> Bytes {
+        self.pending.extend_from_slice(&chunk);
+        self.replace_pending(false)
+    }
+
+    fn finish(&mut self) -> Bytes {
+        self.replace_pending(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct ReplaceAll(&'static str, &'static str);
+
+    impl ResponseTransform for ReplaceAll {
+        fn start(&self) -> Box<dyn BodyTransform> {
+            Box::new(Replace::new(self.0, self.1).unwrap())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_streaming_replace() {
+        let chunks = vec![
+            Ok::<_, axum::Error>("see http://inter"),
+            Ok("nal:8080/a and http"),
+            Ok("://internal:8080/b"),
+        ];
+        let response = Response::builder()
+            .header(header::CONTENT_LENGTH, "52")
+            .body(Body::from_stream(futures::stream::iter(chunks)))
+            .unwrap();
+
+        let transforms = ResponseTransforms(vec![
+            Arc::new(ReplaceAll(
+                "http://internal:8080",
+                "https://api.example.com",
+            )),
+            Arc::new(ReplaceAll(" and ", ", ")),
+        ]);
+        let response = apply_transforms(response, transforms);
+        assert!(!response.headers().contains_key(header::CONTENT_LENGTH));
+
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(
+            body.as_ref(),
+            b"see https://api.example.com/a, https://api.example.com/b"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_partial_response_untouched() {
+        let response = Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, "bytes 4-23/52")
+            .header(header::CONTENT_LENGTH, "20")
+            .body(Body::from("http://internal:8080"))
+            .unwrap();
+
+        let transforms = ResponseTransforms(vec![Arc::new(ReplaceAll(
+            "http://internal:8080",
+            "https://api.example.com",
+        ))]);
+        let response = apply_transforms(response, transforms);
+        assert_eq!(response.headers()[header::CONTENT_LENGTH], "20");
+
+        let body = axum::body::to_bytes(response.into_body(), 1024)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"http://internal:8080");
+    }
+}
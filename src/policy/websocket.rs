@@ -0,0 +1,137 @@
+use crate::policy::traits::Policy;
+use async_trait::async_trait;
+use axum::body::Bytes;
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap};
+use std::sync::Arc;
+
+/// A complete websocket data message, after reassembling fragmented frames
+///
+/// Control frames (ping, pong and close) are handled by the proxy and never
+/// reach message policies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsMessage {
+    Text(String),
+    Binary(Bytes),
+}
+
+impl WsMessage {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Text(text) => text.len(),
+            Self::Binary(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Which way a message is travelling
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WsDirection {
+    ClientToUpstream,
+    UpstreamToClient,
+}
+
+/// What to do with a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WsVerdict {
+    /// Pass the message on, possibly rewritten
+    Forward(WsMessage),
+    /// Silently discard the message and keep the connection open
+    Drop,
+    /// Close both sides of the connection with a close code and reason
+    Close { code: u16, reason: String },
+}
+
+impl WsVerdict {
+    /// Close with 1008 (policy violation)
+    pub fn violation(reason: impl Into<String>) -> Self {
+        Self::Close {
+            code: 1008,
+            reason: reason.into(),
+        }
+    }
+}
+
+/// A policy's per-connection state, which sees every message on the connection
+#[async_trait]
+pub trait WsConnection: Send {
+    async fn on_message(&mut self, direction: WsDirection, message: WsMessage) -> WsVerdict;
+}
+
+/// Hooks into the messages of websocket connections, once the upgrade request
+/// has passed the policy chain
+///
+/// Policies opt in by returning one from [`Policy::websocket`]. For each
+/// connection, `open` creates the state that the policy keeps for it, e.g.
+/// message counts for rate limiting.
+pub trait WsPolicy: Send + Sync {
+    fn open(&self, request: &Parts) -> Box<dyn WsConnection>;
+}
+
+/// Whether a request asks to upgrade to a websocket connection
+pub fn is_upgrade_request(headers: &HeaderMap) -> bool {
+    let has_token = |name: header::HeaderName, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    };
+    has_token(header::CONNECTION, "upgrade") && has_token(header::UPGRADE, "websocket")
+}
+
+/// The policy chain an upgrade request passed, added to its extensions so the
+/// proxy can open the connection's [`WsSession`]
+#[derive(Clone)]
+pub struct WsChain(pub Arc<Vec<Box<dyn Policy>>>);
+
+/// The message policies of one websocket connection, in chain order
+pub struct WsSession {
+    connections: Vec<(String, Box<dyn WsConnection>)>,
+}
+
+impl WsSession {
+    /// Open the connection state of every policy in the chain that hooks websocket messages
+    pub fn open(chain: &[Box<dyn Policy>], request: &Parts) -> Self {
+        let connections = chain
+            .iter()
+            .filter_map(|policy| {
+                let ws_policy = policy.websocket()?;
+                let id = format!(
+                    "@{}/{}/{}/{}",
+                    policy.provider(),
+                    policy.category(),
+                    policy.name(),
+                    policy.version()
+                );
+                Some((id, ws_policy.open(request)))
+            })
+            .collect();
+        Self { connections }
+    }
+
+    /// Whether any policy inspects messages, so the proxy can relay frames untouched if not
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Run a message through every policy in order, each seeing the message the one before forwarded
+    pub async fn process(&mut self, direction: WsDirection, message: WsMessage) -> WsVerdict {
+        let mut message = message;
+        for (id, connection) in &mut self.connections {
+            match connection.on_message(direction, message).await {
+                WsVerdict::Forward(forwarded) => message = forwarded,
+                verdict => {
+                    tracing::debug!("Websocket message stopped by {}: {:?}", id, verdict);
+                    return verdict;
+                }
+            }
+        }
+        WsVerdict::Forward(message)
+    }
+}
@@ -0,0 +1,160 @@
+use crate::config::FallbackConfig;
+use crate::policy::matcher::RouteMatcher;
+use axum::body::Body;
+use axum::http::{HeaderName, HeaderValue, Method, Response, StatusCode};
+use std::collections::HashMap;
+
+// A response built from the config, cloned into every answer
+#[derive(Debug, Clone)]
+struct StaticResponse {
+    status: StatusCode,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: String,
+}
+
+impl StaticResponse {
+    fn new(
+        context: &str,
+        status: u16,
+        headers: &HashMap<String, String>,
+        body: &str,
+    ) -> Result<Self, String> {
+        let status = StatusCode::from_u16(status)
+            .map_err(|_| format!("{}: invalid status {}", context, status))?;
+        let headers = headers
+            .iter()
+            .map(|(name, value)| {
+                let name = HeaderName::from_bytes(name.as_bytes())
+                    .map_err(|e| format!("{}: invalid header '{}': {}", context, name, e))?;
+                let value = HeaderValue::from_str(value).map_err(|e| {
+                    format!("{}: invalid value for header '{}': {}", context, name, e)
+                })?;
+                Ok((name, value))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            status,
+            headers,
+            body: body.to_string(),
+        })
+    }
+
+    fn response(&self, method: &Method) -> Response<Body> {
+        let mut builder = Response::builder().status(self.status);
+        for (name, value) in &self.headers {
+            builder = builder.header(name, value);
+        }
+        let body = if method == Method::HEAD {
+            Body::empty()
+        } else {
+            Body::from(self.body.clone())
+        };
+        builder.body(body).unwrap()
+    }
+}
+
+/// Responses Bouncer answers itself, from `server.fallback`
+#[derive(Debug, Clone)]
+pub struct Fallback {
+    default: StaticResponse,
+    routes: Vec<(RouteMatcher, StaticResponse)>,
+}
+
+impl Fallback {
+    pub fn new(config: &FallbackConfig) -> Result<Self, String> {
+        let default = StaticResponse::new(
+            "server.fallback",
+            config.status,
+            &config.headers,
+            &config.body,
+        )?;
+        let routes = config
+            .routes
+            .iter()
+            .map(|route| {
+                let context = format!("server.fallback.routes '{}'", route.route);
+                let matcher =
+                    RouteMatcher::parse(&route.route).map_err(|e| format!("{}: {}", context, e))?;
+                let response =
+                    StaticResponse::new(&context, route.status, &route.headers, &route.body)?;
+                Ok((matcher, response))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { default, routes })
+    }
+
+    /// The static response of the first route matching a request, if any
+    pub fn route(&self, method: &Method, path: &str) -> Option<Response<Body>> {
+        self.routes
+            .iter()
+            .find(|(matcher, _)| matcher.matches(method, path))
+            .map(|(_, response)| response.response(method))
+    }
+
+    /// The response to requests when no destination is configured
+    pub fn response(&self, method: &Method) -> Response<Body> {
+        self.default.response(method)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let config: FallbackConfig = serde_json::from_value(serde_json::json!({
+            "status": 503,
+            "body": "Down for maintenance",
+            "headers": { "Retry-After": "600" },
+            "routes": [
+                {
+                    "route": "GET,HEAD /robots.txt",
+                    "body": "User-agent: *\nDisallow: /\n",
+                    "headers": { "Content-Type": "text/plain" },
+                },
+                { "route": "/wp-admin/*", "status": 404 },
+            ],
+        }))
+        .unwrap();
+        let fallback = Fallback::new(&config).unwrap();
+
+        let response = fallback.route(&Method::GET, "/robots.txt").unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"User-agent: *\nDisallow: /\n");
+
+        let response = fallback.route(&Method::HEAD, "/robots.txt").unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(body.is_empty());
+
+        assert!(fallback.route(&Method::POST, "/robots.txt").is_none());
+        assert_eq!(
+            fallback
+                .route(&Method::POST, "/wp-admin/login.php")
+                .unwrap()
+                .status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let response = fallback.response(&Method::GET);
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()["retry-after"], "600");
+
+        let config: FallbackConfig = serde_json::from_value(serde_json::json!({})).unwrap();
+        let response = Fallback::new(&config).unwrap().response(&Method::GET);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body.as_ref(), b"Hello from Bouncer!");
+
+        let config: FallbackConfig =
+            serde_json::from_value(serde_json::json!({ "status": 1000 })).unwrap();
+        assert!(Fallback::new(&config).is_err());
+    }
+}
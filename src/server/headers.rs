@@ -0,0 +1,123 @@
+use crate::config::ResponseHeadersConfig;
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue};
+
+/// Headers that describe a single connection rather than the message, so a
+/// proxy must not pass them on (RFC 9110, section 7.6.1)
+const HOP_BY_HOP: [HeaderName; 8] = [
+    header::CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    HeaderName::from_static("proxy-connection"),
+    header::PROXY_AUTHENTICATE,
+    header::PROXY_AUTHORIZATION,
+    header::TE,
+    header::TRAILER,
+    header::TRANSFER_ENCODING,
+];
+
+/// Remove hop-by-hop headers, including any the `Connection` header names
+///
+/// `Upgrade` is removed too; websocket upgrades are relayed separately.
+pub fn strip_hop_by_hop(headers: &mut HeaderMap) {
+    let named: Vec<HeaderName> = headers
+        .get_all(header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|name| HeaderName::try_from(name.trim()).ok())
+        .collect();
+
+    for name in HOP_BY_HOP.iter().chain(&named) {
+        headers.remove(name);
+    }
+    headers.remove(header::UPGRADE);
+}
+
+/// Changes to the headers of upstream responses, from `server.response_headers`
+#[derive(Debug, Clone, Default)]
+pub struct ResponseHeaderRules {
+    remove: Vec<HeaderName>,
+    set: Vec<(HeaderName, HeaderValue)>,
+    add: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl ResponseHeaderRules {
+    pub fn new(config: &ResponseHeadersConfig) -> Result<Self, String> {
+        let name = |name: &str| {
+            HeaderName::try_from(name)
+                .map_err(|_| format!("server.response_headers: invalid header name '{}'", name))
+        };
+        let header = |(key, value): (&String, &String)| {
+            let value = HeaderValue::try_from(value.as_str()).map_err(|_| {
+                format!(
+                    "server.response_headers: invalid value for header '{}'",
+                    key
+                )
+            })?;
+            Ok((name(key)?, value))
+        };
+
+        Ok(Self {
+            remove: config
+                .remove
+                .iter()
+                .map(|key| name(key))
+                .collect::<Result<_, String>>()?,
+            set: config
+                .set
+                .iter()
+                .map(header)
+                .collect::<Result<_, String>>()?,
+            add: config
+                .add
+                .iter()
+                .map(header)
+                .collect::<Result<_, String>>()?,
+        })
+    }
+
+    /// Remove, then override, then append headers
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.remove {
+            headers.remove(name);
+        }
+        for (name, value) in &self.set {
+            headers.insert(name.clone(), value.clone());
+        }
+        for (name, value) in &self.add {
+            headers.append(name.clone(), value.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_response_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(header::CONNECTION, "keep-alive, x-trace".parse().unwrap());
+        headers.insert("keep-alive", "timeout=5".parse().unwrap());
+        headers.insert("x-trace", "abc".parse().unwrap());
+        headers.insert(header::TRANSFER_ENCODING, "chunked".parse().unwrap());
+        headers.insert(header::SERVER, "nginx".parse().unwrap());
+        headers.insert(header::CACHE_CONTROL, "no-cache".parse().unwrap());
+        headers.insert(header::VARY, "Accept".parse().unwrap());
+        strip_hop_by_hop(&mut headers);
+
+        let config: ResponseHeadersConfig = serde_json::from_value(serde_json::json!({
+            "remove": ["Server"],
+            "set": { "Cache-Control": "no-store" },
+            "add": { "Vary": "Origin" },
+        }))
+        .unwrap();
+        ResponseHeaderRules::new(&config)
+            .unwrap()
+            .apply(&mut headers);
+
+        assert_eq!(headers.len(), 3);
+        assert_eq!(headers[header::CACHE_CONTROL], "no-store");
+        let vary: Vec<_> = headers.get_all(header::VARY).iter().collect();
+        assert_eq!(vary, ["Accept", "Origin"]);
+    }
+}
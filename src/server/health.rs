@@ -0,0 +1,353 @@
+use crate::config::HealthCheckConfig;
+use crate::policy::traits::{Policy, PolicyHealth};
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+// An upstream and the outcome of its recent checks
+struct Upstream {
+    url: String,
+    healthy: AtomicBool,
+    // Consecutive failed checks
+    failures: AtomicU32,
+}
+
+/// Health of one upstream, as reported on `/_admin/upstreams`
+#[derive(Debug, Clone, Serialize)]
+pub struct UpstreamStatus {
+    pub url: String,
+    pub healthy: bool,
+    pub consecutive_failures: u32,
+}
+
+/// The destination and failover upstreams, in order of preference
+///
+/// Upstreams start out healthy, so requests are forwarded before the first
+/// checks complete. Without health checks they stay healthy.
+pub struct UpstreamHealth {
+    upstreams: Vec<Upstream>,
+}
+
+impl UpstreamHealth {
+    pub fn new(urls: Vec<String>) -> Arc<Self> {
+        Arc::new(Self {
+            upstreams: urls
+                .into_iter()
+                .map(|url| Upstream {
+                    url,
+                    healthy: AtomicBool::new(true),
+                    failures: AtomicU32::new(0),
+                })
+                .collect(),
+        })
+    }
+
+    /// The first healthy upstream, if any
+    pub fn select(&self) -> Option<&str> {
+        self.upstreams
+            .iter()
+            .find(|upstream| upstream.healthy.load(Ordering::Relaxed))
+            .map(|upstream| upstream.url.as_str())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.upstreams.is_empty()
+    }
+
+    pub fn statuses(&self) -> Vec<UpstreamStatus> {
+        self.upstreams
+            .iter()
+            .map(|upstream| UpstreamStatus {
+                url: upstream.url.clone(),
+                healthy: upstream.healthy.load(Ordering::Relaxed),
+                consecutive_failures: upstream.failures.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+
+    /// Probe every upstream on the configured interval until the router is dropped
+    pub fn spawn_checks(
+        self: &Arc<Self>,
+        config: &HealthCheckConfig,
+        client: reqwest::Client,
+    ) -> Result<(), String> {
+        if config.interval_secs == 0 {
+            return Err("server.health_check.interval_secs must be greater than 0".to_string());
+        }
+        if config.unhealthy_threshold == 0 {
+            return Err(
+                "server.health_check.unhealthy_threshold must be greater than 0".to_string(),
+            );
+        }
+        if !config.path.starts_with('/') {
+            return Err("server.health_check.path must start with '/'".to_string());
+        }
+
+        let health = Arc::downgrade(self);
+        let config = config.clone();
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs));
+            loop {
+                ticker.tick().await;
+                let Some(health) = Weak::upgrade(&health) else {
+                    return;
+                };
+                health.check_all(&config, &client).await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Probe every upstream once
+    pub async fn check_all(&self, config: &HealthCheckConfig, client: &reqwest::Client) {
+        futures::future::join_all(
+            self.upstreams
+                .iter()
+                .map(|upstream| check(upstream, config, client)),
+        )
+        .await;
+    }
+}
+
+// Time each policy has to report its health before it counts as unhealthy
+const POLICY_HEALTH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Health of one policy in the chain
+#[derive(Debug, Clone, Serialize)]
+pub struct PolicyStatus {
+    pub index: usize,
+    pub policy: String,
+    #[serde(flatten)]
+    pub health: PolicyHealth,
+}
+
+/// Whether the gateway can serve requests, as reported on `/readyz` and
+/// `/_admin/status`
+#[derive(Debug, Clone, Serialize)]
+pub struct Readiness {
+    /// The warm-up is done, no policy is unhealthy and an upstream, if any are
+    /// configured, is healthy
+    pub ready: bool,
+    pub warming_up: bool,
+    pub policies: Vec<PolicyStatus>,
+    pub upstreams: Vec<UpstreamStatus>,
+}
+
+/// Overall health of the policy chain, as reported on `/_admin/health`
+#[derive(Debug, Clone, Serialize)]
+pub struct Health {
+    /// The worst health of any policy
+    pub status: HealthStatus,
+    pub policies: Vec<PolicyStatus>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthStatus {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+/// Ask every policy in the chain for its health, concurrently
+pub async fn policy_statuses(policies: &[Box<dyn Policy>]) -> Vec<PolicyStatus> {
+    futures::future::join_all(
+        policies
+            .iter()
+            .enumerate()
+            .map(|(index, policy)| async move {
+                let health = tokio::time::timeout(POLICY_HEALTH_TIMEOUT, policy.health())
+                    .await
+                    .unwrap_or_else(|_| {
+                        PolicyHealth::Unhealthy("Health check timed out".to_string())
+                    });
+                PolicyStatus {
+                    index,
+                    policy: format!(
+                        "@{}/{}/{}/{}",
+                        policy.provider(),
+                        policy.category(),
+                        policy.name(),
+                        policy.version()
+                    ),
+                    health,
+                }
+            }),
+    )
+    .await
+}
+
+/// Aggregate the health of every policy in the chain
+pub async fn health(policies: &[Box<dyn Policy>]) -> Health {
+    let policies = policy_statuses(policies).await;
+    let status = policies
+        .iter()
+        .map(|status| match status.health {
+            PolicyHealth::Healthy => HealthStatus::Healthy,
+            PolicyHealth::Degraded(_) => HealthStatus::Degraded,
+            PolicyHealth::Unhealthy(_) => HealthStatus::Unhealthy,
+        })
+        .max()
+        .unwrap_or(HealthStatus::Healthy);
+    Health { status, policies }
+}
+
+/// Whether the gateway can serve requests, from the health of its policies and upstreams
+pub async fn readiness(
+    policies: &[Box<dyn Policy>],
+    upstreams: &UpstreamHealth,
+    warming_up: bool,
+) -> Readiness {
+    let policies = policy_statuses(policies).await;
+    let unhealthy = policies
+        .iter()
+        .any(|status| matches!(status.health, PolicyHealth::Unhealthy(_)));
+    let upstream_available = upstreams.is_empty() || upstreams.select().is_some();
+    let ready = !warming_up && !unhealthy && upstream_available;
+    Readiness {
+        ready,
+        warming_up,
+        policies,
+        upstreams: upstreams.statuses(),
+    }
+}
+
+/// Warm up every policy in the chain and, with health checks, the connections
+/// to the upstreams, giving up after `timeout`
+pub async fn warm_up(
+    policies: &[Box<dyn Policy>],
+    upstreams: &UpstreamHealth,
+    health_check: Option<&HealthCheckConfig>,
+    client: &reqwest::Client,
+    timeout: Duration,
+) {
+    let started = std::time::Instant::now();
+    let policies = futures::future::join_all(policies.iter().map(|policy| async move {
+        if let Err(e) = policy.warm_up().await {
+            tracing::warn!(
+                "Warm-up of policy @{}/{}/{}/{} failed: {}",
+                policy.provider(),
+                policy.category(),
+                policy.name(),
+                policy.version(),
+                e
+            );
+        }
+    }));
+    let upstreams = async {
+        if let Some(health_check) = health_check {
+            upstreams.check_all(health_check, client).await;
+        }
+    };
+
+    match tokio::time::timeout(timeout, futures::future::join(policies, upstreams)).await {
+        Ok(_) => tracing::info!("Warm-up finished in {:?}", started.elapsed()),
+        Err(_) => tracing::warn!("Warm-up timed out after {:?}", timeout),
+    }
+}
+
+// Probe one upstream and record the outcome
+async fn check(upstream: &Upstream, config: &HealthCheckConfig, client: &reqwest::Client) {
+    let url = format!("{}{}", upstream.url.trim_end_matches('/'), config.path);
+    let result = client
+        .get(&url)
+        .timeout(Duration::from_millis(config.timeout_ms))
+        .send()
+        .await;
+    let passed = match &result {
+        Ok(response) => response.status().as_u16() == config.expected_status,
+        Err(_) => false,
+    };
+
+    if passed {
+        upstream.failures.store(0, Ordering::Relaxed);
+        if !upstream.healthy.swap(true, Ordering::Relaxed) {
+            tracing::info!("Upstream {} is healthy again", upstream.url);
+        }
+        return;
+    }
+
+    let failures = upstream.failures.fetch_add(1, Ordering::Relaxed) + 1;
+    if failures >= config.unhealthy_threshold && upstream.healthy.swap(false, Ordering::Relaxed) {
+        match result {
+            Ok(response) => tracing::warn!(
+                "Upstream {} is unhealthy: {} answered {}",
+                upstream.url,
+                url,
+                response.status()
+            ),
+            Err(e) => tracing::warn!("Upstream {} is unhealthy: {}", upstream.url, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select_skips_unhealthy() {
+        let health = UpstreamHealth::new(vec![
+            "http://primary".to_string(),
+            "http://secondary".to_string(),
+        ]);
+        assert_eq!(health.select(), Some("http://primary"));
+
+        health.upstreams[0].healthy.store(false, Ordering::Relaxed);
+        assert_eq!(health.select(), Some("http://secondary"));
+
+        health.upstreams[1].healthy.store(false, Ordering::Relaxed);
+        assert_eq!(health.select(), None);
+    }
+
+    struct Failing;
+
+    #[async_trait::async_trait]
+    impl Policy for Failing {
+        fn provider(&self) -> &'static str {
+            "test"
+        }
+
+        fn category(&self) -> &'static str {
+            "test"
+        }
+
+        fn name(&self) -> &'static str {
+            "failing"
+        }
+
+        fn version(&self) -> &'static str {
+            "v1"
+        }
+
+        async fn health(&self) -> PolicyHealth {
+            PolicyHealth::Unhealthy("Redis is down".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unhealthy_policy_is_not_ready() {
+        let upstreams = UpstreamHealth::new(vec!["http://primary".to_string()]);
+        assert!(readiness(&[], &upstreams, false).await.ready);
+        assert!(!readiness(&[], &upstreams, true).await.ready);
+
+        let readiness = readiness(&[Box::new(Failing)], &upstreams, false).await;
+        assert!(!readiness.ready);
+        assert_eq!(
+            serde_json::to_value(&readiness.policies).unwrap(),
+            serde_json::json!([{
+                "index": 0,
+                "policy": "@test/test/failing/v1",
+                "status": "unhealthy",
+                "reason": "Redis is down",
+            }])
+        );
+
+        assert_eq!(health(&[]).await.status, HealthStatus::Healthy);
+        assert_eq!(
+            health(&[Box::new(Failing)]).await.status,
+            HealthStatus::Unhealthy
+        );
+    }
+}
@@ -0,0 +1,1479 @@
+pub mod fallback;
+pub mod headers;
+pub mod health;
+pub mod response_cache;
+pub mod rewrite;
+pub mod stats;
+
+use crate::config::WarmUpMode;
+use crate::diagnostics::DiagnosticsReport;
+use crate::events::EventEmitter;
+use crate::instance::{self, Instance};
+use crate::metering;
+use crate::policy::body::{is_too_large, too_large_response, BodyLimits};
+use crate::policy::deadline::{Deadline, Deadlines};
+use crate::policy::denial::DenialMessages;
+use crate::policy::explain::{self, Explainer};
+use crate::policy::forwarded::TrustedProxies;
+use crate::policy::headers::ProtectedHeaders;
+use crate::policy::kill_switch;
+use crate::policy::labels::RouteLabeler;
+use crate::policy::matcher;
+use crate::policy::middleware::{PolicyChainHandle, PolicyLayer};
+use crate::policy::pipeline::Pipeline;
+use crate::policy::plugins::MANIFEST_FILE;
+use crate::policy::preauthorize::{self, Preauthorizer};
+use crate::policy::registry::PolicyRegistry;
+use crate::policy::reload::{self, PolicyReloader};
+use crate::policy::sessions;
+use crate::policy::staging::{self, load_staged_chain, Staging};
+use crate::policy::traits::{buffered_body, Upstream};
+use crate::policy::websocket::is_upgrade_request;
+use crate::GLOBAL_CONFIG;
+use axum::body::{Body, HttpBody};
+use axum::http::{HeaderValue, Method, Request, Response, StatusCode};
+use axum::Router;
+use axum_server::Server;
+use fallback::Fallback;
+use headers::{strip_hop_by_hop, ResponseHeaderRules};
+use hyper_util::rt::TokioExecutor;
+use hyper_util::server::conn::auto::Builder as AutoBuilder;
+use reqwest;
+use response_cache::{Lookup, ResponseCache, CACHE_STATUS_HEADER};
+use rewrite::PathRewriter;
+use std::convert::TryFrom;
+use std::env;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub async fn start_server(config: crate::config::Config) {
+    start_servers(vec![config]).await;
+}
+
+/// Start one gateway per config and run them concurrently
+///
+/// This is used for config files with an `instances:` list. Each instance has
+/// its own sessions, failure counts, recent denials and traffic mode, named
+/// after its bind address. The first config is also stored globally for code
+/// running outside of any instance.
+pub async fn start_servers(configs: Vec<crate::config::Config>) {
+    // Store config in global cell for access from policies
+    if let Some(config) = configs.first() {
+        if GLOBAL_CONFIG.set(config.clone()).is_err() {
+            tracing::warn!("Global config already set, using existing config");
+        }
+    }
+
+    let named = configs.len() > 1;
+    futures::future::join_all(configs.into_iter().map(|config| {
+        let name = named.then(|| config.full_bind_address());
+        run_instance(config, name)
+    }))
+    .await;
+}
+
+// Build the router for a single gateway and serve it until shutdown
+async fn run_instance(config: crate::config::Config, name: Option<String>) {
+    let addr: SocketAddr = config
+        .full_bind_address()
+        .parse()
+        .expect("Invalid bind address");
+
+    let tls = config.server.tls.clone();
+    let http2 = config.server.http2;
+    let acme = match &config.server.acme {
+        Some(acme) => Some((
+            crate::acme::start(&config.server, &config.databases)
+                .await
+                .expect("Failed to set up ACME"),
+            acme.http_port,
+        )),
+        None => None,
+    };
+    let (app, chain) = build_gateway(config, name)
+        .await
+        .expect("Failed to build policy chain");
+    let service = app.into_make_service_with_connect_info::<SocketAddr>();
+
+    // Stop accepting connections on SIGINT or SIGTERM, and let requests in
+    // flight finish before the policies shut down
+    let handle = axum_server::Handle::new();
+    tokio::spawn({
+        let handle = handle.clone();
+        async move {
+            shutdown_signal().await;
+            tracing::info!("Shutting down");
+            handle.graceful_shutdown(Some(SHUTDOWN_GRACE_PERIOD));
+        }
+    });
+
+    match (tls, acme) {
+        (Some(tls), _) => {
+            let rustls_config =
+                crate::tls::load(&tls, http2).expect("Failed to load TLS certificate");
+            tracing::info!("Starting server on {} with TLS", addr);
+            let mut server = axum_server::bind(addr)
+                .acceptor(crate::tls::PeerCertificateAcceptor::new(rustls_config))
+                .handle(handle.clone());
+            if !http2 {
+                http1_only(server.http_builder());
+            }
+            match &tls.redirect_http {
+                Some(redirect) => {
+                    let redirect_addr = SocketAddr::new(addr.ip(), redirect.port);
+                    tracing::info!("Redirecting plain HTTP on {} to HTTPS", redirect_addr);
+                    let redirect = Server::bind(redirect_addr)
+                        .handle(handle.clone())
+                        .serve(crate::tls::redirect_router(addr.port()).into_make_service());
+                    tokio::try_join!(server.serve(service), redirect).expect("Server failed");
+                }
+                None => server.serve(service).await.expect("Server failed"),
+            }
+        }
+        (None, Some((acme, http_port))) => {
+            tracing::info!("Starting server on {} with TLS from ACME", addr);
+            let mut server = axum_server::bind_rustls(addr, acme.tls).handle(handle.clone());
+            if !http2 {
+                http1_only(server.http_builder());
+            }
+            match acme.http {
+                Some(http) => {
+                    let http_addr = SocketAddr::new(addr.ip(), http_port);
+                    tracing::info!("Answering ACME challenges on {}", http_addr);
+                    let http = Server::bind(http_addr)
+                        .handle(handle.clone())
+                        .serve(http.into_make_service());
+                    tokio::try_join!(server.serve(service), http).expect("Server failed");
+                }
+                None => server.serve(service).await.expect("Server failed"),
+            }
+        }
+        (None, None) => {
+            tracing::info!("Starting server on {}", addr);
+            let mut server = Server::bind(addr).handle(handle);
+            if !http2 {
+                http1_only(server.http_builder());
+            }
+            server.serve(service).await.expect("Server failed");
+        }
+    }
+
+    crate::policy::lifecycle::shut_down(&chain.load()).await;
+}
+
+// Time requests in flight have to finish once a shutdown signal arrives
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(30);
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
+
+// Refuse HTTP/2, including cleartext prior knowledge connections, which the
+// listener accepts by default
+fn http1_only(builder: &mut AutoBuilder<TokioExecutor>) {
+    *builder = builder.clone().http1_only();
+}
+
+/// Build the Bouncer router for a config without starting a server
+///
+/// This lets embedders mount Bouncer under a path in their own application,
+/// combine it with other routers, and control the listener themselves. The
+/// config is stored globally for access from policies if it isn't set yet.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # async fn example() -> Result<(), String> {
+/// let config = bouncer::config::load_config("config.yaml")?;
+/// let bouncer = bouncer::build_router(config).await?;
+///
+/// let app = axum::Router::new().nest("/gateway", bouncer);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn build_router(config: crate::config::Config) -> Result<Router, String> {
+    Ok(build_gateway(config, None).await?.0)
+}
+
+// Build the router, and the handle to the chain whose policies are shut down
+// with the server. Policies are created, and requests served, as part of the
+// gateway's own instance
+async fn build_gateway(
+    config: crate::config::Config,
+    name: Option<String>,
+) -> Result<(Router, PolicyChainHandle), String> {
+    let instance = Arc::new(Instance::new(config.clone(), name));
+    let (app, chain) = instance::scope(Arc::clone(&instance), build_app(config, &instance)).await?;
+    let app = app.layer(axum::middleware::from_fn(
+        move |request: Request<Body>, next: axum::middleware::Next| {
+            instance::scope(Arc::clone(&instance), next.run(request))
+        },
+    ));
+    Ok((app, chain))
+}
+
+async fn build_app(
+    config: crate::config::Config,
+    instance: &Arc<Instance>,
+) -> Result<(Router, PolicyChainHandle), String> {
+    // Store config in global cell for code outside of any instance, unless a
+    // server already did so
+    let _ = GLOBAL_CONFIG.set(config.clone());
+    if let Some(level) = config.policy_log_level {
+        crate::policy::logging::set_default_level(level);
+    }
+
+    // Check for BOUNCER_TOKEN environment variable
+    // (a missing token is reported in the startup diagnostics)
+    let bouncer_token = env::var("BOUNCER_TOKEN").unwrap_or_else(|_| "secret".to_string());
+
+    // Create policy registry and register all available policies
+    let registry = create_registry(&config);
+
+    // Build policy chain based on config file. Its policies can be replaced one
+    // at a time through the reloader
+    let (reloader, policy_router) = PolicyReloader::build(registry, &config.policies).await?;
+    let reloader = Arc::new(reloader);
+    let shutdown_chain = reloader.handle();
+
+    // Report the resolved setup so misconfigurations are obvious at boot
+    let report = Arc::new(DiagnosticsReport::collect(&config, &reloader.handle().load()).await);
+    report.log();
+
+    let protected_headers = Arc::new(ProtectedHeaders::new(&config.server.protected_headers));
+    let trusted_proxies = Arc::new(TrustedProxies::new(&config.server.trusted_proxies)?);
+    let response_headers = Arc::new(ResponseHeaderRules::new(&config.server.response_headers)?);
+    let rewriter = Arc::new(PathRewriter::new(&config.server.rewrite)?);
+    let fallback = Arc::new(Fallback::new(&config.server.fallback)?);
+    let response_cache = match &config.server.response_cache {
+        Some(cache) => {
+            let store = crate::cache::shared_cache_store()
+                .await
+                .map_err(|e| format!("Failed to open the response cache store: {}", e))?;
+            Some(Arc::new(ResponseCache::new(cache, store)?))
+        }
+        None => None,
+    };
+    let kill_switch =
+        kill_switch::create_kill_switch(&config.databases, instance.name.as_deref()).await?;
+    let body_limits = Arc::new(BodyLimits::new(&config.server)?);
+    let policy_layer = PolicyLayer::from_handle(reloader.handle())
+        .with_protected_headers(protected_headers.clone())
+        .with_route_labels(Arc::new(RouteLabeler::new(&config.labels)?))
+        .with_events(Arc::new(EventEmitter::new(&config.webhooks).await?))
+        .with_parallel_policies(config.server.parallel_policies)
+        .with_denial_bodies(config.server.denial_bodies)
+        .with_denial_messages(Arc::new(DenialMessages::new(
+            &config.server.denial_messages,
+        )?))
+        .with_bypass(Arc::new(matcher::compile_all(&config.bypass)?))
+        .with_deadlines(Deadlines::new(&config.server))
+        .with_body_inspection_limit(config.server.max_body_inspection_bytes)
+        .with_sessions(config.server.track_sessions)
+        .with_kill_switch(kill_switch.clone())
+        .with_trusted_proxies(trusted_proxies.clone())
+        .with_pipeline(Arc::new(Pipeline::new(
+            &config.server.pipeline,
+            config.server.max_buffered_body_bytes,
+        )?))
+        .with_body_limits(body_limits.clone());
+    let policy_layer = match &config.metering {
+        Some(metering) => {
+            policy_layer.with_meter(metering::create_meter(metering, &config.databases).await?)
+        }
+        None => policy_layer,
+    };
+
+    // Send a share of the traffic through the staged config's chain
+    let mut staging_router = Router::new();
+    let policy_layer = match &config.staging {
+        Some(staging_config) => {
+            if staging_config.percent > 100 {
+                return Err("staging.percent must be between 0 and 100".to_string());
+            }
+            let staging = Arc::new(Staging::new(policy_layer.handle()));
+            staging.stage(
+                load_staged_chain(staging_config, &config.full_bind_address()).await?,
+                staging_config.percent,
+            );
+            tracing::info!(
+                "Staged policy chain from {} receives {}% of requests",
+                staging_config.config,
+                staging_config.percent
+            );
+            staging_router = staging::admin_router(
+                Arc::clone(&staging),
+                staging_config,
+                &config.full_bind_address(),
+            );
+            policy_layer.with_staging(staging)
+        }
+        None => policy_layer,
+    };
+
+    // Create a shared HTTP client for forwarding requests
+    let client = reqwest::Client::builder();
+    let client = match config.server.upstream.connect_timeout_ms {
+        Some(ms) => client.connect_timeout(Duration::from_millis(ms)),
+        None => client,
+    };
+
+    // Connection reuse, tuned for the upstream's load
+    let upstream = &config.server.upstream;
+    let client = match upstream.pool_max_idle_per_host {
+        Some(max) => client.pool_max_idle_per_host(max),
+        None => client,
+    };
+    let client = client
+        .pool_idle_timeout(
+            Some(upstream.pool_idle_timeout_ms)
+                .filter(|ms| *ms > 0)
+                .map(Duration::from_millis),
+        )
+        .tcp_keepalive(upstream.tcp_keepalive_secs.map(Duration::from_secs))
+        .tcp_nodelay(upstream.tcp_nodelay);
+    let client = if config.server.upstream_http2_prior_knowledge {
+        client.http2_prior_knowledge()
+    } else {
+        client
+    };
+    let client = match &config.server.upstream_tls {
+        Some(upstream_tls) => crate::tls::upstream_client(client, upstream_tls)?,
+        None => client,
+    };
+    let client = client
+        .build()
+        .map_err(|e| format!("Failed to create HTTP client: {}", e))?;
+
+    // The destination first, then the failover addresses in order
+    if !config.server.failover_addresses.is_empty() && config.server.destination_address.is_none() {
+        return Err("server.failover_addresses requires server.destination_address".to_string());
+    }
+    let upstreams = health::UpstreamHealth::new(
+        config
+            .server
+            .destination_address
+            .iter()
+            .chain(&config.server.failover_addresses)
+            .cloned()
+            .collect(),
+    );
+    if let Some(health_check) = &config.server.health_check {
+        upstreams.spawn_checks(health_check, client.clone())?;
+    }
+    let upstreams_for_admin = Arc::clone(&upstreams);
+    let chain = reloader.handle();
+
+    // Warm up before the listener is bound, or in the background while
+    // /readyz fails
+    let warming_up = Arc::new(AtomicBool::new(false));
+    if let Some(warm_up) = &config.server.warm_up {
+        let (chain, upstreams, client) = (chain.clone(), Arc::clone(&upstreams), client.clone());
+        let health_check = config.server.health_check.clone();
+        let timeout = Duration::from_secs(warm_up.timeout_secs);
+        let task = async move {
+            let policies = chain.load();
+            health::warm_up(
+                &policies,
+                &upstreams,
+                health_check.as_ref(),
+                &client,
+                timeout,
+            )
+            .await;
+        };
+        match warm_up.mode {
+            WarmUpMode::BeforeListening => task.await,
+            WarmUpMode::BeforeReady => {
+                warming_up.store(true, Ordering::Relaxed);
+                let warming_up = Arc::clone(&warming_up);
+                tokio::spawn(async move {
+                    task.await;
+                    warming_up.store(false, Ordering::Relaxed);
+                });
+            }
+        }
+    }
+
+    // Share config with handler
+    let config = Arc::new(config);
+    let config_for_handler = Arc::clone(&config);
+
+    if config.plugins.hot_reload {
+        spawn_plugin_watcher(
+            Arc::clone(&config),
+            Arc::clone(&reloader),
+            Arc::clone(instance),
+        );
+    }
+    let admin_token = config
+        .server
+        .admin_token
+        .as_ref()
+        .map(|token| token.expose());
+    let reload_router = reload::admin_router(reloader, admin_token);
+    let sessions_router = sessions::admin_router(admin_token);
+    let traffic_router = kill_switch::admin_router(kill_switch.clone(), admin_token);
+    let explain_router = explain::admin_router(
+        Arc::new(Explainer::new(chain.clone(), protected_headers.clone())),
+        admin_token,
+    );
+    // Batch checks of what the caller may do, outside the chain so checking
+    // doesn't count as a request to any of the actions. The traffic mode and
+    // body limits still apply
+    let preauthorize_router = match &config.server.preauthorize {
+        Some(preauthorize) => {
+            preauthorize::validate(preauthorize)?;
+            let preauthorizer = Preauthorizer::new(
+                chain.clone(),
+                protected_headers.clone(),
+                trusted_proxies.clone(),
+                preauthorize,
+            )
+            .with_kill_switch(kill_switch.clone())
+            .with_body_limits(body_limits);
+            preauthorize::router(Arc::new(preauthorizer), preauthorize)
+        }
+        None => Router::new(),
+    };
+    let stats_router = stats::admin_router(
+        report,
+        chain.clone(),
+        Arc::clone(&upstreams_for_admin),
+        Arc::clone(&warming_up),
+        admin_token,
+    )
+    .merge(stats::health_router(chain.clone(), admin_token));
+    let fanout_router = match &config.fanout {
+        Some(fanout) => crate::fanout::router(Arc::new(crate::fanout::Fanout::new(fanout).await?)),
+        None => Router::new(),
+    };
+
+    // Create Axum router with middleware for policies
+    let app = Router::new()
+        // Add policy routes first
+        .merge(policy_router.into_router())
+        // Staged rollout status, promotion and rollback
+        .merge(staging_router)
+        // Listing and reloading single policies
+        .merge(reload_router)
+        // Listing and terminating active sessions
+        .merge(sessions_router)
+        // Emergency traffic modes: blocked, read-only or allowlisted
+        .merge(traffic_router)
+        // Why the chain would allow or deny a request
+        .merge(explain_router)
+        // Publishing events to consumer webhooks, and their dead letters
+        .merge(fanout_router)
+        // Diagnostics, stats and the health of policies and upstreams
+        .merge(stats_router)
+        // Add catch-all route for forwarding (excluding /_admin paths)
+        .route(
+            "/{*path}",
+            axum::routing::any(move |req: Request<Body>| async move {
+                let path = req.uri().path();
+                tracing::debug!("Received request for path: {}", path);
+
+                // Don't forward /_admin paths
+                if path.starts_with("/_admin") {
+                    tracing::debug!("Path starts with /_admin, returning 404");
+                    return Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .body(Body::from("Not Found"))
+                        .unwrap();
+                }
+
+                // Clone the token for use in the handler
+                let token = bouncer_token.clone();
+                handler(
+                    req,
+                    client.clone(),
+                    config_for_handler.clone(),
+                    token,
+                    protected_headers.clone(),
+                    trusted_proxies.clone(),
+                    response_headers.clone(),
+                    rewriter.clone(),
+                    upstreams.clone(),
+                    response_cache.clone(),
+                    fallback.clone(),
+                )
+                .await
+            }),
+        )
+        .layer(policy_layer)
+        .merge(preauthorize_router)
+        // Readiness probes skip the policy chain and the traffic mode
+        .route(
+            "/readyz",
+            axum::routing::get(move || async move {
+                let warming_up = warming_up.load(Ordering::Relaxed);
+                let readiness =
+                    health::readiness(&chain.load(), &upstreams_for_admin, warming_up).await;
+                let status = if readiness.ready {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                (status, axum::Json(readiness))
+            }),
+        );
+
+    // Tell browsers to stick to HTTPS once they've reached it, on every
+    // response including rejections
+    let hsts = config
+        .server
+        .tls
+        .as_ref()
+        .and_then(|tls| tls.redirect_http.as_ref())
+        .and_then(crate::tls::hsts_header);
+    let app = match hsts {
+        Some(hsts) => app.layer(axum::middleware::map_response(
+            move |mut response: Response<Body>| {
+                let hsts = hsts.clone();
+                async move {
+                    response
+                        .headers_mut()
+                        .insert(axum::http::header::STRICT_TRANSPORT_SECURITY, hsts);
+                    response
+                }
+            },
+        )),
+        None => app,
+    };
+
+    Ok((app, shutdown_chain))
+}
+
+// Handler for processing requests after middleware executes
+#[allow(clippy::too_many_arguments)]
+async fn handler(
+    req: Request<Body>,
+    client: reqwest::Client,
+    config: Arc<crate::config::Config>,
+    bouncer_token: String,
+    protected_headers: Arc<ProtectedHeaders>,
+    trusted_proxies: Arc<TrustedProxies>,
+    response_headers: Arc<ResponseHeaderRules>,
+    rewriter: Arc<PathRewriter>,
+    upstreams: Arc<health::UpstreamHealth>,
+    response_cache: Option<Arc<ResponseCache>>,
+    fallback: Arc<Fallback>,
+) -> Response<Body> {
+    // Static routes are answered by Bouncer itself, once the policy chain let
+    // the request through
+    if let Some(response) = fallback.route(req.method(), req.uri().path()) {
+        return response;
+    }
+
+    // Use the upstream a routing policy picked, or else the first healthy one
+    // of the configured destination and failover addresses
+    let routed = req
+        .extensions()
+        .get::<Upstream>()
+        .map(|Upstream(url)| url.clone());
+    let destination = match routed {
+        Some(url) => Some(url),
+        None if upstreams.is_empty() => None,
+        None => match upstreams.select() {
+            Some(url) => Some(url.to_string()),
+            None => {
+                tracing::warn!("No healthy upstream available");
+                return Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from("No healthy upstream available"))
+                    .unwrap();
+            }
+        },
+    };
+    if let Some(destination) = &destination {
+        // Extract URI components we need to preserve
+        let method = req.method().clone();
+        let uri = req.uri();
+        let query = uri.query().unwrap_or("");
+
+        tracing::info!("Original request path: {}", uri.path());
+        let path = rewriter.rewrite(uri.path());
+
+        // Construct the destination URL
+        let url = {
+            let destination_trimmed = destination.trim_end_matches('/');
+            let path_trimmed = path.trim_start_matches('/');
+
+            if path_trimmed.is_empty() {
+                // Just the destination for root path
+                destination_trimmed.to_string()
+            } else if query.is_empty() {
+                // No query parameters
+                format!("{}/{}", destination_trimmed, path_trimmed)
+            } else {
+                // With query parameters
+                format!("{}/{}?{}", destination_trimmed, path_trimmed, query)
+            }
+        };
+
+        tracing::info!("Forwarding to URL: {}", url);
+
+        // Serve fresh copies from the response cache, and keep stale ones to
+        // revalidate with the upstream
+        let cache_key = response_cache
+            .as_ref()
+            .filter(|_| !is_upgrade_request(req.headers()))
+            .and_then(|cache| cache.key(&method, uri.path(), &url, req.headers()));
+        let credentialed = response_cache::has_credentials(&req);
+        let mut stale = None;
+        if let (Some(cache), Some(key)) = (&response_cache, &cache_key) {
+            match cache.lookup(key, req.headers(), credentialed).await {
+                Lookup::Fresh(entry) => {
+                    let mut response = entry.response(&method, req.headers(), "hit");
+                    response_headers.apply(response.headers_mut());
+                    return response;
+                }
+                Lookup::Stale(entry) => stale = Some(entry),
+                Lookup::Miss => {}
+            }
+        }
+
+        // Extract headers and body from the request
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in req.headers() {
+            // Skip host header as we'll set it correctly
+            if name.as_str().to_lowercase() == "host" {
+                continue;
+            }
+
+            if let Ok(header_name) = reqwest::header::HeaderName::try_from(name.as_str()) {
+                if let Ok(header_value) = reqwest::header::HeaderValue::try_from(value.as_bytes()) {
+                    headers.insert(header_name, header_value);
+                }
+            }
+        }
+
+        // Only forward protected headers on the allowlist
+        protected_headers.strip_unforwarded(&mut headers);
+
+        // Set the correct host header based on the destination URL
+        if let Ok(host_value) = reqwest::header::HeaderValue::from_str(
+            url.split("://")
+                .nth(1)
+                .unwrap_or("")
+                .split('/')
+                .next()
+                .unwrap_or(""),
+        ) {
+            headers.insert(reqwest::header::HOST, host_value);
+        }
+
+        // Tell the upstream who the client is and how it reached us
+        let scheme = if config.server.tls.is_some() || config.server.acme.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        trusted_proxies.set_forwarded_headers(&req, &mut headers, scheme);
+
+        // Add bouncer-token header with our token
+        if let Ok(token_value) = reqwest::header::HeaderValue::try_from(bouncer_token.as_bytes()) {
+            headers.insert("bouncer-token", token_value);
+        }
+
+        // Relay websocket connections rather than forwarding a single request
+        if is_upgrade_request(req.headers()) {
+            return crate::websocket_proxy::proxy(req, &url, headers).await;
+        }
+
+        // Connection-specific headers are for us, not the upstream
+        strip_hop_by_hop(&mut headers);
+
+        // Pass the remaining time on, so the upstream can give up when we do
+        let deadline = req.extensions().get::<Deadline>().copied();
+        if let Some(deadline) = deadline {
+            deadline.propagate(&mut headers);
+        }
+
+        // Send the body buffered for policies if it holds all of it, otherwise
+        // stream it to the upstream as it arrives, so uploads aren't held in memory
+        let buffered = buffered_body(&req).and_then(|body| body.complete_bytes().cloned());
+        let revalidating = stale
+            .as_ref()
+            .is_some_and(|entry| entry.add_validators(&mut headers));
+        let request_headers = cache_key.as_ref().map(|_| req.headers().clone());
+        let (_parts, body) = req.into_parts();
+
+        // Any method is forwarded, including WebDAV verbs like PROPFIND, with
+        // its body whenever the client sent one, e.g. on DELETE or OPTIONS
+        let sends_body = match &buffered {
+            Some(bytes) => !bytes.is_empty(),
+            None => !body.is_end_stream(),
+        };
+        let mut streamed = match buffered {
+            None if sends_body => Some(reqwest::Body::wrap_stream(body.into_data_stream())),
+            _ => None,
+        };
+
+        // Only idempotent requests whose body can be sent again are retried
+        let upstream = &config.server.upstream;
+        let replayable = matches!(
+            method.as_str(),
+            "GET" | "HEAD" | "OPTIONS" | "TRACE" | "PUT" | "DELETE"
+        ) && (!sends_body || buffered.is_some());
+        let attempts = if replayable { upstream.retries + 1 } else { 1 };
+        let mut backoff = Duration::from_millis(upstream.retry_backoff_ms);
+        let mut attempt = 1;
+
+        // Forward the request to the destination. Sending it and waiting for the
+        // response headers is bounded by the upstream timeout and the deadline;
+        // the body is bounded separately below
+        let sent = loop {
+            let mut proxy_request = client
+                .request(method.clone(), &url)
+                .headers(headers.clone());
+            if sends_body {
+                if let Some(bytes) = &buffered {
+                    proxy_request = proxy_request.body(bytes.clone());
+                } else if let Some(body) = streamed.take() {
+                    proxy_request = proxy_request.body(body);
+                }
+            }
+
+            let limit = [
+                upstream
+                    .timeout_ms
+                    .map(|ms| tokio::time::Instant::now() + Duration::from_millis(ms)),
+                deadline.map(|deadline| deadline.0),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            let sent = match limit {
+                Some(limit) => tokio::time::timeout_at(limit, proxy_request.send())
+                    .await
+                    .ok(),
+                None => Some(proxy_request.send().await),
+            };
+
+            let retry = attempt < attempts
+                && match &sent {
+                    Some(Ok(res)) => upstream.retry_on.contains(&res.status().as_u16()),
+                    Some(Err(e)) => e.is_connect() || e.is_timeout(),
+                    None => !deadline.is_some_and(|deadline| deadline.is_expired()),
+                };
+            if !retry {
+                break sent;
+            }
+
+            tracing::warn!(
+                "Upstream attempt {} of {} for {} failed, retrying in {:?}",
+                attempt,
+                attempts,
+                url,
+                backoff
+            );
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        };
+
+        let response = match sent {
+            Some(Ok(res)) => res,
+            None if deadline.is_some_and(|deadline| deadline.is_expired()) => {
+                tracing::warn!("Request deadline exceeded waiting for the upstream");
+                return Response::builder()
+                    .status(StatusCode::GATEWAY_TIMEOUT)
+                    .body(Body::from("Request deadline exceeded"))
+                    .unwrap();
+            }
+            None => {
+                tracing::warn!("Timed out waiting for the upstream");
+                return Response::builder()
+                    .status(StatusCode::GATEWAY_TIMEOUT)
+                    .body(Body::from("Upstream timed out"))
+                    .unwrap();
+            }
+            // The body was cut off by `max_body_size` while it was streamed
+            Some(Err(e)) if is_too_large(&e) => return too_large_response(),
+            Some(Err(e)) => {
+                tracing::error!("Failed to forward request: {}", e);
+                return Response::builder()
+                    .status(StatusCode::BAD_GATEWAY)
+                    .body(Body::from(format!("Failed to forward request: {}", e)))
+                    .unwrap();
+            }
+        };
+
+        // Convert the response back to an Axum response
+        let status_code = StatusCode::from_u16(response.status().as_u16())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+
+        if let (Some(cache), Some(key), Some(request_headers)) =
+            (&response_cache, &cache_key, &request_headers)
+        {
+            // The upstream confirmed the stale copy is still current
+            if let Some(entry) =
+                stale.filter(|_| revalidating && status_code == StatusCode::NOT_MODIFIED)
+            {
+                let entry = cache
+                    .refresh(key, entry, credentialed, response.headers())
+                    .await;
+                let mut response = entry.response(&method, request_headers, "revalidated");
+                response_headers.apply(response.headers_mut());
+                return response;
+            }
+
+            // Buffer cacheable responses to store them, within the deadline
+            let ttl = cache.freshness(credentialed, status_code, response.headers());
+            if let Some(ttl) =
+                ttl.filter(|_| method == Method::GET && cache.fits(response.content_length()))
+            {
+                let mut headers = response.headers().clone();
+                strip_hop_by_hop(&mut headers);
+                let bytes = match deadline {
+                    Some(deadline) => tokio::time::timeout_at(deadline.0, response.bytes())
+                        .await
+                        .ok(),
+                    None => Some(response.bytes().await),
+                };
+                let bytes = match bytes {
+                    Some(Ok(bytes)) => bytes,
+                    None => {
+                        tracing::warn!("Request deadline exceeded reading the response");
+                        return Response::builder()
+                            .status(StatusCode::GATEWAY_TIMEOUT)
+                            .body(Body::from("Request deadline exceeded"))
+                            .unwrap();
+                    }
+                    Some(Err(e)) => {
+                        tracing::error!("Failed to read the upstream response: {}", e);
+                        return Response::builder()
+                            .status(StatusCode::BAD_GATEWAY)
+                            .body(Body::from("Failed to read the upstream response"))
+                            .unwrap();
+                    }
+                };
+                let entry = cache.store(key, status_code, &headers, &bytes, ttl).await;
+                let mut response = entry.response(&method, request_headers, "miss");
+                response_headers.apply(response.headers_mut());
+                return response;
+            }
+        }
+        let mut response_builder = Response::builder().status(status_code);
+
+        // Copy headers from the forwarded response, except those about the
+        // upstream connection, and apply the configured changes
+        let mut headers = response.headers().clone();
+        strip_hop_by_hop(&mut headers);
+        response_headers.apply(&mut headers);
+        if cache_key.is_some() {
+            headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static("miss"));
+        }
+        for (name, value) in &headers {
+            response_builder = response_builder.header(name, value);
+        }
+
+        // Keep reverse proxies in front of Bouncer from buffering event streams
+        let streaming = is_stream(response.headers());
+        if is_event_stream(response.headers()) && !response.headers().contains_key(ACCEL_BUFFERING)
+        {
+            response_builder = response_builder.header(ACCEL_BUFFERING, "no");
+        }
+
+        // Stream the response body as it arrives, each chunk written to the
+        // client as soon as the upstream sends it. If the upstream fails midway
+        // the client sees the body end with an error, as it would talking to
+        // the upstream directly. Streams meant to stay open, like server-sent
+        // events, aren't cut off at the deadline
+        let body = match deadline {
+            Some(deadline) if !streaming => {
+                Body::from_stream(until_deadline(response.bytes_stream(), deadline))
+            }
+            _ => Body::from_stream(response.bytes_stream()),
+        };
+
+        return response_builder.body(body).unwrap_or_else(|_| {
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to construct response"))
+                .unwrap()
+        });
+    }
+
+    // If no destination is configured, return the fallback response
+    fallback.response(req.method())
+}
+
+/// Header asking nginx and compatible proxies not to buffer a response
+const ACCEL_BUFFERING: &str = "x-accel-buffering";
+
+// Whether a response is a stream of server-sent events
+fn is_event_stream(headers: &reqwest::header::HeaderMap) -> bool {
+    headers
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case("text/event-stream"))
+}
+
+// Whether a response body is an open-ended stream: server-sent events, or a
+// chunked body of unknown length
+fn is_stream(headers: &reqwest::header::HeaderMap) -> bool {
+    let chunked = headers
+        .get(reqwest::header::TRANSFER_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    is_event_stream(headers) || chunked
+}
+
+// End a response body with an error once the request's deadline passes
+fn until_deadline(
+    stream: impl futures::Stream<Item = reqwest::Result<axum::body::Bytes>> + Send + 'static,
+    deadline: Deadline,
+) -> impl futures::Stream<Item = Result<axum::body::Bytes, axum::BoxError>> + Send + 'static {
+    use futures::StreamExt;
+
+    futures::stream::unfold(Some(Box::pin(stream)), move |stream| async move {
+        let mut stream = stream?;
+        match tokio::time::timeout_at(deadline.0, stream.next()).await {
+            Ok(Some(chunk)) => Some((chunk.map_err(axum::BoxError::from), Some(stream))),
+            Ok(None) => None,
+            Err(_) => {
+                tracing::warn!("Request deadline exceeded streaming the response");
+                Some((Err("Request deadline exceeded".into()), None))
+            }
+        }
+    })
+}
+
+/// Create a registry with built-in, custom and plugin policies
+pub fn create_registry(config: &crate::config::Config) -> PolicyRegistry {
+    let mut registry = PolicyRegistry::new();
+
+    // Register built-in policies
+    register_builtin_policies(&mut registry);
+
+    // Register user-provided custom policies
+    register_custom_policies(&mut registry);
+
+    // Load verified external policies from the plugins directory if it exists
+    let plugins_dir = Path::new(&config.plugins.directory);
+    if plugins_dir.exists() && plugins_dir.is_dir() {
+        match registry.load_plugins(&config.plugins) {
+            Ok(count) => tracing::info!(
+                "Loaded {} external policies from {}",
+                count,
+                plugins_dir.display()
+            ),
+            Err(e) => tracing::warn!("Failed to load external policies: {}", e),
+        }
+    }
+
+    registry
+}
+
+/// Check a config for mistakes without starting a server or connecting to anything
+///
+/// Every problem found is returned, rather than only the first.
+pub fn validate_config(config: &crate::config::Config, registry: &PolicyRegistry) -> Vec<String> {
+    let server = &config.server;
+    let mut errors: Vec<String> = [
+        crate::config::validate_version(&config.bouncer_version, crate::VERSION).err(),
+        TrustedProxies::new(&server.trusted_proxies).err(),
+        ResponseHeaderRules::new(&server.response_headers).err(),
+        PathRewriter::new(&server.rewrite).err(),
+        server
+            .response_cache
+            .as_ref()
+            .and_then(|cache| ResponseCache::validate(cache).err()),
+        Pipeline::new(&server.pipeline, server.max_buffered_body_bytes).err(),
+        BodyLimits::new(server).err(),
+        server
+            .preauthorize
+            .as_ref()
+            .and_then(|preauthorize| preauthorize::validate(preauthorize).err()),
+        crate::acme::validate(server).err(),
+        RouteLabeler::new(&config.labels).err(),
+        matcher::compile_all(&config.bypass).err(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+
+    if config
+        .staging
+        .as_ref()
+        .is_some_and(|staging| staging.percent > 100)
+    {
+        errors.push("staging.percent must be between 0 and 100".to_string());
+    }
+    for policy in &config.policies {
+        if let Err(e) = registry.validate_policy(policy) {
+            errors.push(format!("Policy {}: {}", policy.id, e));
+        }
+    }
+    errors
+}
+
+// Watch the plugin manifest and swap in a rebuilt policy chain when it changes.
+// If the new chain fails to build, the previous one stays active.
+fn spawn_plugin_watcher(
+    config: Arc<crate::config::Config>,
+    reloader: Arc<PolicyReloader>,
+    instance: Arc<Instance>,
+) {
+    let manifest_path = Path::new(&config.plugins.directory).join(MANIFEST_FILE);
+    let interval = Duration::from_secs(config.plugins.reload_interval_secs.max(1));
+
+    tokio::spawn(instance::scope(instance, async move {
+        let mut last_manifest = tokio::fs::read(&manifest_path).await.ok();
+        let mut ticker = tokio::time::interval(interval);
+
+        loop {
+            ticker.tick().await;
+
+            let manifest = tokio::fs::read(&manifest_path).await.ok();
+            if manifest == last_manifest {
+                continue;
+            }
+            last_manifest = manifest;
+
+            tracing::info!("Plugin manifest changed, rebuilding policy chain");
+            match reloader.rebuild(create_registry(&config)).await {
+                Ok(()) => {
+                    tracing::info!("Swapped in new policy chain; in-flight requests finish on the previous chain");
+                }
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to rebuild policy chain, keeping the previous one: {}",
+                        e
+                    )
+                }
+            }
+        }
+    }));
+}
+
+// Register built-in policies
+fn register_builtin_policies(registry: &mut PolicyRegistry) {
+    // Only register the versioned implementations
+    registry.register_policy::<crate::policy::providers::bouncer::authentication::bearer::v1::BearerAuthPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authentication::bearer::v1_managed::BearerAuthManagedPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authentication::client_cert::v1::ClientCertAuthPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authentication::hmac::v1::HmacVerificationPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authentication::jwt::v1::JwtAuthPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authorization::consent::v1::ConsentPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authorization::entitlements::v1::EntitlementsPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authorization::external::v1::ExternalAuthzPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authorization::rbac::v1::RbacPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::authorization::rbac::v2_managed::RbacManagedPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::traffic::rate_limit::v1::RateLimitPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::traffic::denylist::v1::DenylistPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::traffic::deprecation::v1::DeprecationPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::traffic::residency::v1::ResidencyPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::traffic::rewrite::v1::RewritePolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::traffic::versioning::v1::VersioningPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::traffic::websocket::v1::WebsocketPolicyFactory>();
+    registry.register_policy::<crate::policy::providers::bouncer::development::mock::v1::MockPolicyFactory>();
+
+    // Add other built-in policies here
+}
+
+// Register custom policies from global registry
+fn register_custom_policies(registry: &mut PolicyRegistry) {
+    for register_fn in crate::get_custom_policies() {
+        register_fn(registry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, UpstreamConfig};
+    use axum::http::header;
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    // An upstream that fails its first `failures` requests with 503, and is
+    // slow to answer `/slow`
+    async fn flaky_upstream(failures: usize) -> (String, Arc<AtomicUsize>) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/flaky",
+                axum::routing::any({
+                    let calls = Arc::clone(&calls);
+                    move || async move {
+                        if calls.fetch_add(1, Ordering::SeqCst) < failures {
+                            StatusCode::SERVICE_UNAVAILABLE
+                        } else {
+                            StatusCode::OK
+                        }
+                    }
+                }),
+            )
+            .route(
+                "/slow",
+                axum::routing::get(|| async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    StatusCode::OK
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}", addr), calls)
+    }
+
+    async fn gateway(destination: &str, upstream: UpstreamConfig) -> Router {
+        let config = Config::builder()
+            .destination_address(destination)
+            .upstream(upstream)
+            .build()
+            .unwrap();
+        build_router(config).await.unwrap()
+    }
+
+    async fn send(router: &Router, method: Method, path: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(path)
+            .body(Body::empty())
+            .unwrap();
+        router.clone().oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn test_retries() {
+        let upstream = UpstreamConfig {
+            retries: 2,
+            retry_backoff_ms: 1,
+            ..UpstreamConfig::default()
+        };
+
+        // Idempotent requests are retried until they succeed
+        let (destination, calls) = flaky_upstream(2).await;
+        let router = gateway(&destination, upstream.clone()).await;
+        assert_eq!(send(&router, Method::GET, "/flaky").await, StatusCode::OK);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // or the retries run out
+        let (destination, calls) = flaky_upstream(5).await;
+        let router = gateway(&destination, upstream.clone()).await;
+        assert_eq!(
+            send(&router, Method::PUT, "/flaky").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // Others are sent once
+        let (destination, calls) = flaky_upstream(1).await;
+        let router = gateway(&destination, upstream).await;
+        assert_eq!(
+            send(&router, Method::POST, "/flaky").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_timeout() {
+        let (destination, _) = flaky_upstream(0).await;
+        let router = gateway(
+            &destination,
+            UpstreamConfig {
+                timeout_ms: Some(50),
+                retries: 1,
+                retry_backoff_ms: 1,
+                ..UpstreamConfig::default()
+            },
+        )
+        .await;
+
+        let started = tokio::time::Instant::now();
+        assert_eq!(
+            send(&router, Method::GET, "/slow").await,
+            StatusCode::GATEWAY_TIMEOUT
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_validate_config() {
+        let mut config = Config::builder().build().unwrap();
+        let registry = create_registry(&config);
+        assert!(validate_config(&config, &registry).is_empty());
+
+        config.policies.push(
+            serde_json::from_value(serde_json::json!({
+                "id": "unknown",
+                "provider": "@acme/unknown/policy/v1",
+                "parameters": {},
+            }))
+            .unwrap(),
+        );
+        config.staging = Some(crate::config::StagingConfig {
+            config: "staging.yaml".to_string(),
+            percent: 120,
+            admin_token: None,
+        });
+        let errors = validate_config(&config, &registry);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].contains("staging.percent"));
+        assert!(errors[1].starts_with("Policy unknown:"));
+    }
+
+    #[tokio::test]
+    async fn test_warm_up_before_ready() {
+        // Health checks hang until released, keeping the warm-up going
+        let (release, released) = tokio::sync::watch::channel(false);
+        let app = Router::new().route(
+            "/health",
+            axum::routing::get(move || {
+                let mut released = released.clone();
+                async move {
+                    released.wait_for(|released| *released).await.unwrap();
+                    StatusCode::OK
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut config = Config::builder()
+            .destination_address(format!("http://{}", addr))
+            .warm_up(crate::config::WarmUpConfig {
+                mode: WarmUpMode::BeforeReady,
+                timeout_secs: 30,
+            })
+            .build()
+            .unwrap();
+        config.server.health_check =
+            Some(serde_yaml::from_str("path: /health\ntimeout_ms: 30000").unwrap());
+        let router = build_router(config).await.unwrap();
+        let readyz = || async {
+            let request = Request::get("/readyz").body(Body::empty()).unwrap();
+            let response = router.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let readiness: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            (status, readiness["warming_up"].as_bool().unwrap())
+        };
+
+        // The router is built before the warm-up is done
+        for _ in 0..3 {
+            assert_eq!(readyz().await, (StatusCode::SERVICE_UNAVAILABLE, true));
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+
+        release.send(true).unwrap();
+        let mut readiness = readyz().await;
+        for _ in 0..100 {
+            if readiness.0 == StatusCode::OK {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            readiness = readyz().await;
+        }
+        assert_eq!(readiness, (StatusCode::OK, false));
+    }
+
+    #[tokio::test]
+    async fn test_extension_methods() {
+        // Answers every method with 207, echoing the method and body
+        let app = Router::new().route(
+            "/dav/{*path}",
+            axum::routing::any(|method: Method, body: String| async move {
+                (StatusCode::MULTI_STATUS, format!("{} {}", method, body))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        // The body limit only matches PROPFIND requests
+        let config = Config::builder()
+            .destination_address(format!("http://{}", addr))
+            .max_body_size_route("PROPFIND /dav/*", 32)
+            .build()
+            .unwrap();
+        let router = build_router(config).await.unwrap();
+        let send = |method: &str, body: String| {
+            let request = Request::builder()
+                .method(Method::from_bytes(method.as_bytes()).unwrap())
+                .uri("/dav/notes.txt")
+                .body(Body::from(body))
+                .unwrap();
+            let router = router.clone();
+            async move {
+                let response = router.oneshot(request).await.unwrap();
+                let status = response.status();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, String::from_utf8(body.to_vec()).unwrap())
+            }
+        };
+
+        let propfind = "<propfind><allprop/></propfind>".to_string();
+        assert_eq!(
+            send("PROPFIND", propfind.clone()).await,
+            (StatusCode::MULTI_STATUS, format!("PROPFIND {}", propfind))
+        );
+        let large = "x".repeat(64);
+        assert_eq!(
+            send("PROPFIND", large.clone()).await.0,
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            send("PROPPATCH", large.clone()).await,
+            (StatusCode::MULTI_STATUS, format!("PROPPATCH {}", large))
+        );
+    }
+
+    // Streams a first chunk at once and the second only when released, at
+    // `/events` as server-sent events and at `/chunked` as a chunked body.
+    // `/sized` declares ten uncacheable bytes but only ever sends five
+    async fn streaming_upstream() -> (String, Arc<tokio::sync::Notify>, Arc<AtomicUsize>) {
+        let release = Arc::new(tokio::sync::Notify::new());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stream = |content_type: &'static str| {
+            let (release, calls) = (Arc::clone(&release), Arc::clone(&calls));
+            axum::routing::get(move || async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                let chunks = futures::stream::unfold(0, move |sent| {
+                    let release = Arc::clone(&release);
+                    async move {
+                        if sent == 1 {
+                            release.notified().await;
+                        }
+                        let chunk = format!("data: {}\n\n", sent + 1);
+                        (sent < 2).then_some((Ok::<_, axum::Error>(chunk), sent + 1))
+                    }
+                });
+                Response::builder()
+                    .header(header::CONTENT_TYPE, content_type)
+                    .header(header::CACHE_CONTROL, "max-age=60")
+                    .body(Body::from_stream(chunks))
+                    .unwrap()
+            })
+        };
+        let sized = axum::routing::get(|| async {
+            let chunks = futures::stream::once(async { Ok::<_, axum::Error>("12345") }).chain(
+                futures::stream::once(async {
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    Ok("67890")
+                }),
+            );
+            Response::builder()
+                .header(header::CONTENT_LENGTH, 10)
+                .header(header::CACHE_CONTROL, "no-store")
+                .body(Body::from_stream(chunks))
+                .unwrap()
+        });
+        let app = Router::new()
+            .route("/events", stream("text/event-stream"))
+            .route("/chunked", stream("application/octet-stream"))
+            .route("/sized", sized);
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{}", addr), release, calls)
+    }
+
+    #[tokio::test]
+    async fn test_streaming() {
+        let (destination, release, calls) = streaming_upstream().await;
+        let config = Config::builder()
+            .destination_address(&destination)
+            .request_timeout_ms(300)
+            .response_cache(
+                serde_json::from_value(serde_json::json!({ "default_ttl_secs": 60 })).unwrap(),
+            )
+            .build()
+            .unwrap();
+        let router = build_router(config).await.unwrap();
+
+        for (path, accel_buffering) in [("/events", Some("no")), ("/chunked", None)] {
+            for call in 1..=2 {
+                let request = Request::get(path).body(Body::empty()).unwrap();
+                let response = router.clone().oneshot(request).await.unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let header = response.headers().get(ACCEL_BUFFERING);
+                assert_eq!(
+                    header.and_then(|value| value.to_str().ok()),
+                    accel_buffering
+                );
+
+                // The first chunk reaches the client while the upstream is
+                // still holding back the second
+                let mut body = response.into_body().into_data_stream();
+                let first = tokio::time::timeout(Duration::from_secs(2), body.next()).await;
+                assert_eq!(first.unwrap().unwrap().unwrap(), "data: 1\n\n");
+
+                // and streams aren't cut off at the request deadline
+                tokio::time::sleep(Duration::from_millis(400)).await;
+                release.notify_one();
+                let second = tokio::time::timeout(Duration::from_secs(2), body.next()).await;
+                assert_eq!(second.unwrap().unwrap().unwrap(), "data: 2\n\n");
+                assert!(body.next().await.is_none());
+
+                // nor buffered into the cache, despite being cacheable
+                assert_eq!(calls.load(Ordering::SeqCst), call);
+            }
+            calls.store(0, Ordering::SeqCst);
+        }
+
+        // Bodies of a known length end in an error at the deadline
+        let started = tokio::time::Instant::now();
+        let request = Request::get("/sized").body(Body::empty()).unwrap();
+        let response = router.clone().oneshot(request).await.unwrap();
+        assert!(axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_is_stream() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = reqwest::header::HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, value.parse().unwrap());
+            }
+            headers
+        };
+
+        assert!(is_stream(&headers(&[(
+            "content-type",
+            "text/event-stream; charset=utf-8"
+        )])));
+        assert!(is_stream(&headers(&[("transfer-encoding", "chunked")])));
+        assert!(!is_stream(&headers(&[
+            ("content-type", "application/json"),
+            ("content-length", "2")
+        ])));
+    }
+}
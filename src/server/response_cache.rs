@@ -0,0 +1,898 @@
+use crate::admin::now_secs;
+use crate::cache::CacheStore;
+use crate::config::ResponseCacheConfig;
+use crate::policy::context::context;
+use crate::policy::matcher::{compile_all, RouteMatcher};
+use crate::tls::PeerCertificates;
+use axum::body::{Body, Bytes};
+use axum::http::{
+    header, HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Header telling clients whether a response came from the cache: `hit`,
+/// `revalidated` or `miss`
+pub const CACHE_STATUS_HEADER: &str = "x-bouncer-cache";
+
+// Statuses that can be cached, from RFC 9110
+const CACHEABLE_STATUSES: [u16; 10] = [200, 203, 204, 300, 301, 404, 405, 410, 414, 501];
+
+// Request headers that carry credentials
+const CREDENTIAL_HEADERS: [&str; 4] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "x-api-key",
+];
+
+/// Whether a request carries credentials, a client certificate or an
+/// identity set by the policy chain
+///
+/// Responses to such requests may be meant for the caller alone, so they're
+/// only cached when the upstream marks them `public`.
+pub fn has_credentials(request: &Request<Body>) -> bool {
+    CREDENTIAL_HEADERS
+        .iter()
+        .any(|name| request.headers().contains_key(*name))
+        || request
+            .extensions()
+            .get::<PeerCertificates>()
+            .is_some_and(|certificates| certificates.leaf().is_some())
+        || context(request).is_some_and(|context| context.identity().is_some())
+        || request.headers().contains_key("x-bouncer-role")
+}
+
+// `Cache-Control` directives, lowercased, with their values
+struct CacheControl(Vec<(String, Option<String>)>);
+
+impl CacheControl {
+    fn new(headers: &HeaderMap) -> Self {
+        let directives = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|directive| !directive.is_empty())
+            .map(|directive| match directive.split_once('=') {
+                Some((name, value)) => (
+                    name.trim().to_ascii_lowercase(),
+                    Some(value.trim().trim_matches('"').to_string()),
+                ),
+                None => (directive.to_ascii_lowercase(), None),
+            })
+            .collect();
+        Self(directives)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.0.iter().any(|(directive, _)| directive == name)
+    }
+
+    fn secs(&self, name: &str) -> Option<u64> {
+        self.0
+            .iter()
+            .find(|(directive, _)| directive == name)
+            .and_then(|(_, value)| value.as_deref()?.parse().ok())
+    }
+}
+
+// The part of a body of `length` bytes a `Range` request asks for
+#[derive(Debug, PartialEq, Eq)]
+enum ByteRange {
+    /// No range, or one that can't be served from a single slice, such as
+    /// several ranges or an invalid one
+    Whole,
+    /// The first and last byte, inclusive
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    fn new(request: &HeaderMap, length: u64) -> Self {
+        let Some(spec) = request
+            .get(header::RANGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().strip_prefix("bytes="))
+            .filter(|spec| !spec.contains(','))
+        else {
+            return Self::Whole;
+        };
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return Self::Whole;
+        };
+        let (first, last) = match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => (first, last.min(length.saturating_sub(1))),
+            (Ok(first), Err(_)) if last.is_empty() => (first, length.saturating_sub(1)),
+            // The last `suffix` bytes
+            (Err(_), Ok(suffix)) if first.is_empty() => match suffix {
+                0 => return Self::Unsatisfiable,
+                suffix => (length.saturating_sub(suffix), length.saturating_sub(1)),
+            },
+            _ => return Self::Whole,
+        };
+        if first >= length {
+            return Self::Unsatisfiable;
+        }
+        Self::Partial(first, last)
+    }
+}
+
+/// An upstream response held in the cache
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedResponse {
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+    /// Base64 encoded
+    body: String,
+    stored_at: i64,
+    fresh_until: i64,
+}
+
+impl CachedResponse {
+    fn header_map(&self) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::try_from(name.as_str()),
+                HeaderValue::from_bytes(value),
+            ) {
+                headers.append(name, value);
+            }
+        }
+        headers
+    }
+
+    fn header(&self, name: &HeaderName) -> Option<HeaderValue> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name.as_str())
+            .and_then(|(_, value)| HeaderValue::from_bytes(value).ok())
+    }
+
+    fn is_public(&self) -> bool {
+        CacheControl::new(&self.header_map()).has("public")
+    }
+
+    fn has_validator(&self) -> bool {
+        self.header(&header::ETAG).is_some() || self.header(&header::LAST_MODIFIED).is_some()
+    }
+
+    /// Ask the upstream whether this copy is still current, unless the
+    /// client sent its own conditional headers. Returns whether any were added
+    pub fn add_validators(&self, headers: &mut HeaderMap) -> bool {
+        if headers.contains_key(header::IF_NONE_MATCH)
+            || headers.contains_key(header::IF_MODIFIED_SINCE)
+        {
+            return false;
+        }
+        let mut added = false;
+        if let Some(etag) = self.header(&header::ETAG) {
+            headers.insert(header::IF_NONE_MATCH, etag);
+            added = true;
+        }
+        if let Some(last_modified) = self.header(&header::LAST_MODIFIED) {
+            headers.insert(header::IF_MODIFIED_SINCE, last_modified);
+            added = true;
+        }
+        added
+    }
+
+    // Whether the client's `If-None-Match` names this copy
+    fn matches_client(&self, request: &HeaderMap) -> bool {
+        let (Some(etag), Some(wanted)) = (
+            self.header(&header::ETAG),
+            request
+                .get(header::IF_NONE_MATCH)
+                .and_then(|value| value.to_str().ok()),
+        ) else {
+            return false;
+        };
+        let etag = etag.to_str().unwrap_or_default();
+        let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        wanted
+            .split(',')
+            .any(|tag| tag.trim() == "*" || weak(tag) == weak(etag))
+    }
+
+    // Whether the client's `If-Range`, if any, names this copy. Only strong
+    // ETags and exact dates match
+    fn matches_if_range(&self, request: &HeaderMap) -> bool {
+        let Some(wanted) = request.get(header::IF_RANGE) else {
+            return true;
+        };
+        let validator = match wanted.as_bytes().starts_with(b"\"") {
+            true => self.header(&header::ETAG),
+            false => self.header(&header::LAST_MODIFIED),
+        };
+        validator.is_some_and(|validator| validator == wanted)
+    }
+
+    /// The response sent to the client
+    ///
+    /// Clients revalidating a copy that's still current get a 304, `Range`
+    /// requests for a single range of a 200 get a 206, or a 416 if it's past
+    /// the end, and `HEAD` requests get the headers only.
+    pub fn response(
+        &self,
+        method: &Method,
+        request: &HeaderMap,
+        status: &'static str,
+    ) -> Response<Body> {
+        let mut headers = self.header_map();
+        let age = (now_secs() - self.stored_at).max(0);
+        headers.insert(header::AGE, HeaderValue::from(age));
+        headers.insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+
+        let not_modified = self.status == 200 && self.matches_client(request);
+        let mut body = match not_modified {
+            true => Vec::new(),
+            false => STANDARD.decode(&self.body).unwrap_or_default(),
+        };
+        let length = body.len() as u64;
+        let range = match self.status == 200 && !not_modified && self.matches_if_range(request) {
+            true => ByteRange::new(request, length),
+            false => ByteRange::Whole,
+        };
+        let status = match range {
+            _ if not_modified => StatusCode::NOT_MODIFIED,
+            ByteRange::Whole => StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK),
+            ByteRange::Partial(first, last) => {
+                body = body[first as usize..=last as usize].to_vec();
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes {}-{}/{}", first, last, length)).unwrap(),
+                );
+                headers.insert(header::CONTENT_LENGTH, HeaderValue::from(body.len()));
+                StatusCode::PARTIAL_CONTENT
+            }
+            ByteRange::Unsatisfiable => {
+                body.clear();
+                headers.insert(
+                    header::CONTENT_RANGE,
+                    HeaderValue::from_str(&format!("bytes */{}", length)).unwrap(),
+                );
+                headers.insert(header::CONTENT_LENGTH, HeaderValue::from(0));
+                StatusCode::RANGE_NOT_SATISFIABLE
+            }
+        };
+        let body = match *method == Method::HEAD {
+            true => Body::empty(),
+            false => Body::from(body),
+        };
+
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        *response.headers_mut() = headers;
+        response
+    }
+}
+
+/// What the cache holds for a request
+pub enum Lookup {
+    Fresh(CachedResponse),
+    /// An expired copy that can be revalidated with the upstream
+    Stale(CachedResponse),
+    Miss,
+}
+
+/// Upstream responses cached in the shared `cache` store, from
+/// `server.response_cache`
+///
+/// Keys are made of the upstream URL and the values of `vary_headers`, so
+/// `GET` and `HEAD` requests share entries, and only `GET` responses are
+/// stored. Requests reach the cache after the policy chain, so cached
+/// responses are only served to requests the chain lets through.
+pub struct ResponseCache {
+    store: Arc<dyn CacheStore>,
+    routes: Vec<RouteMatcher>,
+    vary: Vec<HeaderName>,
+    default_ttl_secs: Option<u64>,
+    revalidate_secs: u64,
+    max_entry_bytes: usize,
+}
+
+fn compile(config: &ResponseCacheConfig) -> Result<(Vec<RouteMatcher>, Vec<HeaderName>), String> {
+    let routes =
+        compile_all(&config.routes).map_err(|e| format!("server.response_cache.routes: {}", e))?;
+    let vary = config
+        .vary_headers
+        .iter()
+        .map(|name| {
+            HeaderName::try_from(name.as_str()).map_err(|_| {
+                format!(
+                    "server.response_cache.vary_headers: invalid header name '{}'",
+                    name
+                )
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    if config.max_entry_bytes == 0 {
+        return Err("server.response_cache.max_entry_bytes must be greater than 0".to_string());
+    }
+    Ok((routes, vary))
+}
+
+impl ResponseCache {
+    pub fn new(config: &ResponseCacheConfig, store: Arc<dyn CacheStore>) -> Result<Self, String> {
+        let (routes, vary) = compile(config)?;
+        Ok(Self {
+            store,
+            routes,
+            vary,
+            default_ttl_secs: config.default_ttl_secs,
+            revalidate_secs: config.revalidate_secs,
+            max_entry_bytes: config.max_entry_bytes,
+        })
+    }
+
+    /// Check `server.response_cache` without a store
+    pub fn validate(config: &ResponseCacheConfig) -> Result<(), String> {
+        compile(config).map(|_| ())
+    }
+
+    /// The cache key of a request for `url`, if its response can be cached
+    pub fn key(
+        &self,
+        method: &Method,
+        path: &str,
+        url: &str,
+        request: &HeaderMap,
+    ) -> Option<String> {
+        if *method != Method::GET && *method != Method::HEAD {
+            return None;
+        }
+        // `HEAD` requests share the entries of `GET`, so they're matched as one
+        if !self.routes.is_empty()
+            && !self
+                .routes
+                .iter()
+                .any(|route| route.matches(&Method::GET, path))
+        {
+            return None;
+        }
+        if CacheControl::new(request).has("no-store") {
+            return None;
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(url.as_bytes());
+        for name in &self.vary {
+            hasher.update(b"\n");
+            hasher.update(name.as_str().as_bytes());
+            for value in request.get_all(name) {
+                hasher.update(b"\0");
+                hasher.update(value.as_bytes());
+            }
+        }
+        let digest = hasher.finalize();
+        let digest: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        Some(format!("response:{}", digest))
+    }
+
+    /// Find the cached copy of a response
+    ///
+    /// Copies are stale when their lifetime is over, or when the client asks
+    /// for a newer one with `no-cache` or `max-age`. Requests with credentials
+    /// only get copies marked `public`.
+    pub async fn lookup(&self, key: &str, request: &HeaderMap, credentialed: bool) -> Lookup {
+        let entry = match self.store.get(key).await {
+            Ok(Some(bytes)) => match serde_json::from_slice::<CachedResponse>(&bytes) {
+                Ok(entry) => entry,
+                Err(_) => return Lookup::Miss,
+            },
+            Ok(None) => return Lookup::Miss,
+            Err(e) => {
+                tracing::warn!("Response cache lookup failed: {}", e);
+                return Lookup::Miss;
+            }
+        };
+        if credentialed && !entry.is_public() {
+            return Lookup::Miss;
+        }
+
+        let control = CacheControl::new(request);
+        let now = now_secs();
+        let young_enough = match control.secs("max-age") {
+            Some(max_age) => now - entry.stored_at <= max_age as i64,
+            None => true,
+        };
+        if now < entry.fresh_until && young_enough && !control.has("no-cache") {
+            Lookup::Fresh(entry)
+        } else if entry.has_validator() {
+            Lookup::Stale(entry)
+        } else {
+            Lookup::Miss
+        }
+    }
+
+    // Whether every header the response varies on is part of the key
+    fn varies_within_key(&self, response: &HeaderMap) -> bool {
+        response
+            .get_all(header::VARY)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .all(|name| {
+                self.vary
+                    .iter()
+                    .any(|vary| vary.as_str().eq_ignore_ascii_case(name))
+            })
+    }
+
+    /// How many seconds a response stays fresh, if it may be stored
+    ///
+    /// Responses marked `no-store` or `private`, setting cookies, or to
+    /// requests with credentials that the upstream didn't mark `public`
+    /// aren't stored. `no-cache` responses are stored to be revalidated.
+    pub fn freshness(
+        &self,
+        credentialed: bool,
+        status: StatusCode,
+        response: &HeaderMap,
+    ) -> Option<u64> {
+        if !CACHEABLE_STATUSES.contains(&status.as_u16()) {
+            return None;
+        }
+        let control = CacheControl::new(response);
+        if control.has("no-store")
+            || control.has("private")
+            || response.contains_key(header::SET_COOKIE)
+            || !self.varies_within_key(response)
+        {
+            return None;
+        }
+        if credentialed && !control.has("public") {
+            return None;
+        }
+
+        if control.has("no-cache") {
+            return Some(0);
+        }
+        let age = response
+            .get(header::AGE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok())
+            .unwrap_or(0);
+        match control.secs("s-maxage").or_else(|| control.secs("max-age")) {
+            Some(max_age) => Some(max_age.saturating_sub(age)),
+            None => self.default_ttl_secs,
+        }
+    }
+
+    /// Whether a response body of `length` bytes can be stored
+    pub fn fits(&self, length: Option<u64>) -> bool {
+        length.is_some_and(|length| length <= self.max_entry_bytes as u64)
+    }
+
+    /// Store a response, fresh for `ttl` seconds
+    pub async fn store(
+        &self,
+        key: &str,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &Bytes,
+        ttl: u64,
+    ) -> CachedResponse {
+        let now = now_secs();
+        let entry = CachedResponse {
+            status: status.as_u16(),
+            headers: headers
+                .iter()
+                .filter(|(name, _)| **name != header::AGE)
+                .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            body: STANDARD.encode(body),
+            stored_at: now,
+            fresh_until: now + ttl as i64,
+        };
+        self.save(key, &entry, ttl).await;
+        entry
+    }
+
+    /// Make a stale copy fresh again after the upstream answered a
+    /// revalidation with 304, taking the validators and lifetime it sent
+    pub async fn refresh(
+        &self,
+        key: &str,
+        mut entry: CachedResponse,
+        credentialed: bool,
+        response: &HeaderMap,
+    ) -> CachedResponse {
+        for name in [
+            header::CACHE_CONTROL,
+            header::ETAG,
+            header::LAST_MODIFIED,
+            header::EXPIRES,
+            header::DATE,
+        ] {
+            if let Some(value) = response.get(&name) {
+                entry.headers.retain(|(header, _)| header != name.as_str());
+                entry
+                    .headers
+                    .push((name.as_str().to_string(), value.as_bytes().to_vec()));
+            }
+        }
+
+        let status = StatusCode::from_u16(entry.status).unwrap_or(StatusCode::OK);
+        let ttl = self
+            .freshness(credentialed, status, &entry.header_map())
+            .unwrap_or(0);
+        let now = now_secs();
+        entry.stored_at = now;
+        entry.fresh_until = now + ttl as i64;
+        self.save(key, &entry, ttl).await;
+        entry
+    }
+
+    async fn save(&self, key: &str, entry: &CachedResponse, ttl: u64) {
+        // Copies that can be revalidated are kept a while after they expire
+        let keep = match entry.has_validator() {
+            true => ttl + self.revalidate_secs,
+            false => ttl,
+        };
+        if keep == 0 {
+            return;
+        }
+
+        let result = match serde_json::to_vec(entry) {
+            Ok(bytes) => self
+                .store
+                .set(key, bytes, Some(Duration::from_secs(keep)))
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        if let Err(e) = result {
+            tracing::warn!("Failed to store response in the cache: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::store::MemoryCacheStore;
+    use crate::cache::CacheLimits;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    HeaderName::from_static(name),
+                    HeaderValue::from_static(value),
+                )
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_response_cache() {
+        let config: ResponseCacheConfig =
+            serde_yaml::from_str("routes: [GET /products/*]").unwrap();
+        let store = Arc::new(MemoryCacheStore::new(CacheLimits {
+            max_entries: 100,
+            max_bytes: None,
+        }));
+        let cache = ResponseCache::new(&config, store).unwrap();
+
+        let request = headers(&[("accept", "application/json")]);
+        let url = "http://upstream/products/1";
+        let key = cache
+            .key(&Method::GET, "/products/1", url, &request)
+            .unwrap();
+        assert_eq!(
+            cache.key(&Method::HEAD, "/products/1", url, &request),
+            Some(key.clone())
+        );
+        assert_ne!(
+            cache.key(
+                &Method::GET,
+                "/products/1",
+                url,
+                &headers(&[("accept", "text/html")])
+            ),
+            Some(key.clone())
+        );
+        assert!(cache
+            .key(&Method::POST, "/products/1", url, &request)
+            .is_none());
+        assert!(cache
+            .key(&Method::GET, "/orders/1", url, &request)
+            .is_none());
+
+        // Private and cookie-setting responses aren't stored
+        let ok = StatusCode::OK;
+        let private = headers(&[("cache-control", "private, max-age=60")]);
+        assert_eq!(cache.freshness(false, ok, &private), None);
+        let cookie = headers(&[("cache-control", "max-age=60"), ("set-cookie", "a=b")]);
+        assert_eq!(cache.freshness(false, ok, &cookie), None);
+        let varies = headers(&[("cache-control", "max-age=60"), ("vary", "Cookie")]);
+        assert_eq!(cache.freshness(false, ok, &varies), None);
+
+        let response = headers(&[
+            ("cache-control", "max-age=60"),
+            ("age", "10"),
+            ("etag", "\"v1\""),
+            ("content-type", "application/json"),
+        ]);
+        let ttl = cache.freshness(false, ok, &response).unwrap();
+        assert_eq!(ttl, 50);
+        cache
+            .store(&key, ok, &response, &Bytes::from_static(b"{}"), ttl)
+            .await;
+
+        let Lookup::Fresh(entry) = cache.lookup(&key, &request, false).await else {
+            panic!("stored response wasn't fresh");
+        };
+        let served = entry.response(&Method::GET, &request, "hit");
+        assert_eq!(served.status(), StatusCode::OK);
+        assert_eq!(served.headers()[CACHE_STATUS_HEADER], "hit");
+
+        let conditional = headers(&[("if-none-match", "W/\"v1\"")]);
+        let served = entry.response(&Method::GET, &conditional, "hit");
+        assert_eq!(served.status(), StatusCode::NOT_MODIFIED);
+
+        // Clients asking for a newer copy get it revalidated
+        let no_cache = headers(&[("cache-control", "no-cache")]);
+        let Lookup::Stale(entry) = cache.lookup(&key, &no_cache, false).await else {
+            panic!("no-cache request was served from the cache");
+        };
+        let mut upstream = HeaderMap::new();
+        assert!(entry.add_validators(&mut upstream));
+        assert_eq!(upstream[header::IF_NONE_MATCH], "\"v1\"");
+    }
+
+    #[tokio::test]
+    async fn test_vary_headers() {
+        let config: ResponseCacheConfig =
+            serde_yaml::from_str("vary_headers: [accept-language]").unwrap();
+        let store = Arc::new(MemoryCacheStore::new(CacheLimits {
+            max_entries: 100,
+            max_bytes: None,
+        }));
+        let cache = ResponseCache::new(&config, store).unwrap();
+        let url = "http://upstream/greeting";
+        let key = |request: &HeaderMap| cache.key(&Method::GET, "/greeting", url, request).unwrap();
+
+        // Only the configured headers are part of the key
+        let english = headers(&[("accept-language", "en"), ("accept", "text/html")]);
+        let french = headers(&[("accept-language", "fr"), ("accept", "text/html")]);
+        assert_ne!(key(&english), key(&french));
+        assert_eq!(
+            key(&english),
+            key(&headers(&[
+                ("accept-language", "en"),
+                ("accept", "text/plain")
+            ]))
+        );
+        assert_ne!(key(&english), key(&HeaderMap::new()));
+
+        // Responses varying on headers outside the key aren't stored
+        let ok = StatusCode::OK;
+        let by_language = headers(&[("cache-control", "max-age=60"), ("vary", "Accept-Language")]);
+        let by_accept = headers(&[("cache-control", "max-age=60"), ("vary", "Accept")]);
+        assert_eq!(cache.freshness(false, ok, &by_accept), None);
+        let ttl = cache.freshness(false, ok, &by_language).unwrap();
+
+        cache
+            .store(
+                &key(&english),
+                ok,
+                &by_language,
+                &Bytes::from_static(b"hello"),
+                ttl,
+            )
+            .await;
+        assert!(matches!(
+            cache.lookup(&key(&english), &english, false).await,
+            Lookup::Fresh(_)
+        ));
+        assert!(matches!(
+            cache.lookup(&key(&french), &french, false).await,
+            Lookup::Miss
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_eviction() {
+        let config: ResponseCacheConfig = serde_yaml::from_str("revalidate_secs: 0").unwrap();
+        let store = Arc::new(MemoryCacheStore::new(CacheLimits {
+            max_entries: 1,
+            max_bytes: None,
+        }));
+        let cache = ResponseCache::new(&config, store).unwrap();
+        let request = HeaderMap::new();
+        let key = |path: &str| {
+            let url = format!("http://upstream{}", path);
+            cache.key(&Method::GET, path, &url, &request).unwrap()
+        };
+        let ok = StatusCode::OK;
+        let response = headers(&[("cache-control", "max-age=1")]);
+        let body = Bytes::from_static(b"{}");
+
+        // Copies leave the store once their lifetime is over
+        cache.store(&key("/a"), ok, &response, &body, 1).await;
+        assert!(matches!(
+            cache.lookup(&key("/a"), &request, false).await,
+            Lookup::Fresh(_)
+        ));
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert!(matches!(
+            cache.lookup(&key("/a"), &request, false).await,
+            Lookup::Miss
+        ));
+
+        // and are evicted when the store is full
+        cache.store(&key("/b"), ok, &response, &body, 60).await;
+        cache.store(&key("/c"), ok, &response, &body, 60).await;
+        assert!(matches!(
+            cache.lookup(&key("/b"), &request, false).await,
+            Lookup::Miss
+        ));
+        assert!(matches!(
+            cache.lookup(&key("/c"), &request, false).await,
+            Lookup::Fresh(_)
+        ));
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let range = |value: &'static str| ByteRange::new(&headers(&[("range", value)]), 10);
+        assert_eq!(range("bytes=2-4"), ByteRange::Partial(2, 4));
+        assert_eq!(range("bytes=5-"), ByteRange::Partial(5, 9));
+        assert_eq!(range("bytes=-3"), ByteRange::Partial(7, 9));
+        assert_eq!(range("bytes=8-20"), ByteRange::Partial(8, 9));
+        assert_eq!(range("bytes=-20"), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=10-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-1,4-5"), ByteRange::Whole);
+        assert_eq!(range("bytes=4-2"), ByteRange::Whole);
+        assert_eq!(range("items=0-1"), ByteRange::Whole);
+        assert_eq!(ByteRange::new(&HeaderMap::new(), 10), ByteRange::Whole);
+    }
+
+    #[tokio::test]
+    async fn test_range_requests() {
+        let entry = CachedResponse {
+            status: 200,
+            headers: vec![
+                ("etag".to_string(), b"\"v1\"".to_vec()),
+                ("content-length".to_string(), b"10".to_vec()),
+            ],
+            body: STANDARD.encode(b"0123456789"),
+            stored_at: now_secs(),
+            fresh_until: now_secs() + 60,
+        };
+        let serve = |request: &[(&'static str, &'static str)]| {
+            let response = entry.response(&Method::GET, &headers(request), "hit");
+            async move {
+                let status = response.status();
+                let content_range = response
+                    .headers()
+                    .get(header::CONTENT_RANGE)
+                    .map(|value| value.to_str().unwrap().to_string());
+                let length = response.headers()[header::CONTENT_LENGTH].clone();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                assert_eq!(length, body.len().to_string());
+                (status, content_range, body)
+            }
+        };
+
+        let (status, content_range, body) = serve(&[("range", "bytes=2-4")]).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(content_range.as_deref(), Some("bytes 2-4/10"));
+        assert_eq!(body, "234");
+
+        let (status, content_range, body) = serve(&[("range", "bytes=20-")]).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(content_range.as_deref(), Some("bytes */10"));
+        assert!(body.is_empty());
+
+        // The range only applies while the client's copy is still current
+        let current = [("range", "bytes=-2"), ("if-range", "\"v1\"")];
+        let (status, _, body) = serve(&current).await;
+        assert_eq!(
+            (status, body.as_ref()),
+            (StatusCode::PARTIAL_CONTENT, &b"89"[..])
+        );
+        let changed = [("range", "bytes=-2"), ("if-range", "\"v0\"")];
+        let (status, content_range, body) = serve(&changed).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(content_range.is_none());
+        assert_eq!(body, "0123456789");
+    }
+
+    #[tokio::test]
+    async fn test_credentialed_requests() {
+        use crate::policy::providers::bouncer::authentication::identity::Identity;
+
+        let config: ResponseCacheConfig = serde_yaml::from_str("routes: []").unwrap();
+        let store = Arc::new(MemoryCacheStore::new(CacheLimits {
+            max_entries: 100,
+            max_bytes: None,
+        }));
+        let cache = ResponseCache::new(&config, store).unwrap();
+        let request = |header: Option<(&str, &str)>, role: Option<&str>| {
+            let mut request = Request::get("/account").body(Body::empty()).unwrap();
+            if let Some((name, value)) = header {
+                request.headers_mut().insert(
+                    HeaderName::try_from(name).unwrap(),
+                    HeaderValue::try_from(value).unwrap(),
+                );
+            }
+            if let Some(role) = role {
+                Identity::new(role).apply(&mut request);
+            }
+            request
+        };
+
+        assert!(!has_credentials(&request(None, None)));
+        for header in [
+            ("authorization", "Bearer x"),
+            ("cookie", "session=1"),
+            ("x-api-key", "key"),
+        ] {
+            assert!(has_credentials(&request(Some(header), None)));
+        }
+        assert!(has_credentials(&request(None, Some("user"))));
+        let mut with_cert = request(None, None);
+        with_cert
+            .extensions_mut()
+            .insert(PeerCertificates(vec![vec![1u8].into()].into()));
+        assert!(has_credentials(&with_cert));
+
+        // One identity's response isn't served to another
+        let (alice, bob) = (request(None, Some("alice")), request(None, Some("bob")));
+        let url = "http://upstream/account";
+        let key = cache
+            .key(&Method::GET, "/account", url, alice.headers())
+            .unwrap();
+        assert_eq!(
+            cache.key(&Method::GET, "/account", url, bob.headers()),
+            Some(key.clone())
+        );
+        let ok = StatusCode::OK;
+        let personal = headers(&[("cache-control", "max-age=60")]);
+        assert_eq!(
+            cache.freshness(has_credentials(&alice), ok, &personal),
+            None
+        );
+
+        // Neither are copies stored for anonymous requests
+        let ttl = cache.freshness(false, ok, &personal).unwrap();
+        cache
+            .store(&key, ok, &personal, &Bytes::from_static(b"anonymous"), ttl)
+            .await;
+        assert!(matches!(
+            cache
+                .lookup(&key, bob.headers(), has_credentials(&bob))
+                .await,
+            Lookup::Miss
+        ));
+
+        // Unless the upstream marks them public
+        let public = headers(&[("cache-control", "public, max-age=60")]);
+        let ttl = cache
+            .freshness(has_credentials(&alice), ok, &public)
+            .unwrap();
+        cache
+            .store(&key, ok, &public, &Bytes::from_static(b"shared"), ttl)
+            .await;
+        assert!(matches!(
+            cache
+                .lookup(&key, bob.headers(), has_credentials(&bob))
+                .await,
+            Lookup::Fresh(_)
+        ));
+    }
+}
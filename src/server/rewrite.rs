@@ -0,0 +1,103 @@
+use crate::config::RewriteRuleConfig;
+use regex::Regex;
+
+// A compiled `server.rewrite` rule
+#[derive(Debug, Clone)]
+enum Rule {
+    StripPrefix(String),
+    AddPrefix(String),
+    Regex { regex: Regex, replacement: String },
+}
+
+/// Rewrites the path of requests before they're forwarded, from `server.rewrite`
+///
+/// Every rule is applied in order, each to the result of the one before.
+#[derive(Debug, Clone, Default)]
+pub struct PathRewriter {
+    rules: Vec<Rule>,
+}
+
+// A prefix without its trailing slash, so `/api/` and `/api` are the same
+fn normalize_prefix(prefix: &str) -> Result<String, String> {
+    if !prefix.starts_with('/') {
+        return Err(format!(
+            "server.rewrite: prefix '{}' must start with '/'",
+            prefix
+        ));
+    }
+    Ok(prefix.trim_end_matches('/').to_string())
+}
+
+impl PathRewriter {
+    pub fn new(rules: &[RewriteRuleConfig]) -> Result<Self, String> {
+        let rules = rules
+            .iter()
+            .map(|rule| match rule {
+                RewriteRuleConfig::StripPrefix(prefix) => {
+                    normalize_prefix(prefix).map(Rule::StripPrefix)
+                }
+                RewriteRuleConfig::AddPrefix(prefix) => {
+                    normalize_prefix(prefix).map(Rule::AddPrefix)
+                }
+                RewriteRuleConfig::Regex {
+                    pattern,
+                    replacement,
+                } => Regex::new(pattern)
+                    .map(|regex| Rule::Regex {
+                        regex,
+                        replacement: replacement.clone(),
+                    })
+                    .map_err(|e| format!("server.rewrite: invalid pattern '{}': {}", pattern, e)),
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The path to forward a request for `path` to
+    pub fn rewrite(&self, path: &str) -> String {
+        let mut path = path.to_string();
+        for rule in &self.rules {
+            path = match rule {
+                // Only whole segments are stripped: `/api` leaves `/apis` alone
+                Rule::StripPrefix(prefix) => match path.strip_prefix(prefix.as_str()) {
+                    Some("") => "/".to_string(),
+                    Some(rest) if rest.starts_with('/') => rest.to_string(),
+                    _ => path,
+                },
+                Rule::AddPrefix(prefix) if path == "/" => format!("{}/", prefix),
+                Rule::AddPrefix(prefix) => format!("{}{}", prefix, path),
+                Rule::Regex { regex, replacement } => {
+                    regex.replace(&path, replacement.as_str()).into_owned()
+                }
+            };
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_path() {
+        let rules: Vec<RewriteRuleConfig> = serde_json::from_value(serde_json::json!([
+            { "strip_prefix": "/api/v1/" },
+            { "regex": { "pattern": "^/users/(\\d+)$", "replacement": "/accounts/$1" } },
+            { "add_prefix": "/internal" },
+        ]))
+        .unwrap();
+        let rewriter = PathRewriter::new(&rules).unwrap();
+
+        assert_eq!(rewriter.rewrite("/api/v1/users"), "/internal/users");
+        assert_eq!(
+            rewriter.rewrite("/api/v1/users/42"),
+            "/internal/accounts/42"
+        );
+        assert_eq!(rewriter.rewrite("/api/v1"), "/internal/");
+        assert_eq!(
+            rewriter.rewrite("/api/v10/users"),
+            "/internal/api/v10/users"
+        );
+    }
+}
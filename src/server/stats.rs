@@ -0,0 +1,189 @@
+use super::health::{self, UpstreamHealth};
+use crate::admin::{is_admin, json_error};
+use crate::diagnostics::DiagnosticsReport;
+use crate::policy::middleware::PolicyChainHandle;
+use axum::{
+    body::Body,
+    http::{HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::IntoResponse,
+    routing::get,
+    Json, Router,
+};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Diagnostics and stats routes under `/_admin`, if an admin token is set
+pub fn admin_router(
+    report: Arc<DiagnosticsReport>,
+    chain: PolicyChainHandle,
+    upstreams: Arc<UpstreamHealth>,
+    warming_up: Arc<AtomicBool>,
+    admin_token: Option<&str>,
+) -> Router {
+    let Some(admin_token) = admin_token else {
+        return Router::new();
+    };
+    let admin_token: Arc<str> = Arc::from(admin_token);
+
+    Router::new()
+        // Startup diagnostics with secrets masked
+        .route(
+            "/_admin/diagnostics",
+            get(move || async move { Json(report.as_ref().clone()) }),
+        )
+        // Hit/miss/eviction metrics for in-memory caches
+        .route(
+            "/_admin/caches",
+            get(|| async { Json(crate::cache::all_stats()) }),
+        )
+        // Request counts and latency per labeled route
+        .route(
+            "/_admin/routes",
+            get(|| async { Json(crate::policy::labels::all_route_stats()) }),
+        )
+        // Latency, error and slow query counts for database queries
+        .route(
+            "/_admin/queries",
+            get(|| async { Json(crate::database::metrics::all_query_stats()) }),
+        )
+        // Requests let through or rejected because a policy failed
+        .route(
+            "/_admin/failures",
+            get(|| async { Json(crate::policy::failure::all_failure_stats()) }),
+        )
+        // Health of the destination and failover upstreams
+        .route(
+            "/_admin/upstreams",
+            get({
+                let upstreams = Arc::clone(&upstreams);
+                move || async move { Json(upstreams.statuses()) }
+            }),
+        )
+        // Health of each policy's backends and of the upstreams
+        .route(
+            "/_admin/status",
+            get(move || async move {
+                let warming_up = warming_up.load(Ordering::Relaxed);
+                Json(health::readiness(&chain.load(), &upstreams, warming_up).await)
+            }),
+        )
+        .route_layer(middleware::from_fn(
+            move |request: Request<Body>, next: Next| {
+                let admin_token = Arc::clone(&admin_token);
+                async move {
+                    if !is_admin(request.headers(), &admin_token) {
+                        return json_error(StatusCode::UNAUTHORIZED, "Invalid admin token");
+                    }
+                    next.run(request).await
+                }
+            },
+        ))
+}
+
+/// `/_admin/health`, failing if any policy is unhealthy
+///
+/// Only callers with the admin token see the policies, others get the overall
+/// status alone.
+pub fn health_router(chain: PolicyChainHandle, admin_token: Option<&str>) -> Router {
+    let admin_token: Option<Arc<str>> = admin_token.map(Arc::from);
+    Router::new().route(
+        "/_admin/health",
+        get(move |headers: HeaderMap| async move {
+            let health = health::health(&chain.load()).await;
+            let status = if health.status == health::HealthStatus::Unhealthy {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+            match &admin_token {
+                Some(admin_token) if is_admin(&headers, admin_token) => {
+                    (status, Json(health)).into_response()
+                }
+                _ => (status, Json(serde_json::json!({ "status": health.status }))).into_response(),
+            }
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use axum::http::header;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_admin_token_required() {
+        let config = Config::builder().build().unwrap();
+        let report = Arc::new(DiagnosticsReport::collect(&config, &[]).await);
+        let router = |admin_token| {
+            admin_router(
+                Arc::clone(&report),
+                PolicyChainHandle::new(Vec::new()),
+                UpstreamHealth::new(Vec::new()),
+                Arc::new(AtomicBool::new(false)),
+                admin_token,
+            )
+        };
+        let status = |router: Router, token: Option<&str>| {
+            let mut request = Request::get("/_admin/failures");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            async move {
+                let response = router
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                response.status()
+            }
+        };
+
+        assert_eq!(
+            status(router(Some("secret")), Some("secret")).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            status(router(Some("secret")), Some("wrong")).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            status(router(Some("secret")), None).await,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(status(router(None), None).await, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_health_details_need_admin_token() {
+        let router = health_router(PolicyChainHandle::new(Vec::new()), Some("secret"));
+        let health = |token: Option<&str>| {
+            let mut request = Request::get("/_admin/health");
+            if let Some(token) = token {
+                request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+            }
+            let router = router.clone();
+            async move {
+                let response = router
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                assert_eq!(response.status(), StatusCode::OK);
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                serde_json::from_slice::<serde_json::Value>(&body).unwrap()
+            }
+        };
+
+        assert_eq!(
+            health(Some("secret")).await,
+            serde_json::json!({ "status": "healthy", "policies": [] })
+        );
+        assert_eq!(
+            health(None).await,
+            serde_json::json!({ "status": "healthy" })
+        );
+    }
+}
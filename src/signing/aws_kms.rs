@@ -0,0 +1,102 @@
+use super::{Signer, SigningAlgorithm, SigningError};
+use async_trait::async_trait;
+use aws_sdk_kms::primitives::Blob;
+use aws_sdk_kms::types::{MacAlgorithmSpec, MessageType, SigningAlgorithmSpec};
+use sha2::{Digest, Sha256};
+
+// Largest message KMS signs as-is; longer messages are signed by digest
+const MAX_RAW_MESSAGE: usize = 4096;
+
+/// Signs with a key in AWS KMS
+///
+/// Credentials come from the default AWS chain: environment, profile, or the
+/// instance or task role.
+pub struct AwsKmsSigner {
+    client: aws_sdk_kms::Client,
+    key_id: String,
+    algorithm: SigningAlgorithm,
+}
+
+impl AwsKmsSigner {
+    pub async fn new(
+        algorithm: SigningAlgorithm,
+        key_id: String,
+        region: Option<&str>,
+    ) -> Result<Self, SigningError> {
+        if matches!(
+            algorithm,
+            SigningAlgorithm::Ed25519 | SigningAlgorithm::HmacSha1
+        ) {
+            return Err(SigningError::ConfigurationError(
+                "AWS KMS keys support HS256, ES256 and RS256".to_string(),
+            ));
+        }
+
+        let mut loader = aws_config::defaults(aws_config::BehaviorVersion::latest());
+        if let Some(region) = region {
+            loader = loader.region(aws_config::Region::new(region.to_string()));
+        }
+
+        Ok(Self {
+            client: aws_sdk_kms::Client::new(&loader.load().await),
+            key_id,
+            algorithm,
+        })
+    }
+}
+
+#[async_trait]
+impl Signer for AwsKmsSigner {
+    fn algorithm(&self) -> SigningAlgorithm {
+        self.algorithm
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, SigningError> {
+        let backend_error = |e: String| SigningError::BackendError(e);
+
+        let spec = match self.algorithm {
+            SigningAlgorithm::HmacSha256 => {
+                let output = self
+                    .client
+                    .generate_mac()
+                    .key_id(&self.key_id)
+                    .mac_algorithm(MacAlgorithmSpec::HmacSha256)
+                    .message(Blob::new(message))
+                    .send()
+                    .await
+                    .map_err(|e| backend_error(e.to_string()))?;
+                return output
+                    .mac
+                    .map(Blob::into_inner)
+                    .ok_or_else(|| backend_error("KMS returned no MAC".to_string()));
+            }
+            SigningAlgorithm::EcdsaP256Sha256 => SigningAlgorithmSpec::EcdsaSha256,
+            SigningAlgorithm::RsaPkcs1Sha256 => SigningAlgorithmSpec::RsassaPkcs1V15Sha256,
+            SigningAlgorithm::Ed25519 | SigningAlgorithm::HmacSha1 => {
+                unreachable!("rejected in AwsKmsSigner::new")
+            }
+        };
+
+        let (message, message_type) = if message.len() > MAX_RAW_MESSAGE {
+            (Sha256::digest(message).to_vec(), MessageType::Digest)
+        } else {
+            (message.to_vec(), MessageType::Raw)
+        };
+
+        let output = self
+            .client
+            .sign()
+            .key_id(&self.key_id)
+            .message(Blob::new(message))
+            .message_type(message_type)
+            .signing_algorithm(spec)
+            .send()
+            .await
+            .map_err(|e| backend_error(e.to_string()))?;
+
+        output
+            .signature
+            .map(Blob::into_inner)
+            .ok_or_else(|| backend_error("KMS returned no signature".to_string()))
+    }
+}
@@ -0,0 +1,149 @@
+use super::{Signer, SigningAlgorithm, SigningError};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::Sha256;
+use std::sync::Arc;
+
+/// HMAC with a key from config
+pub struct HmacSigner {
+    key: Vec<u8>,
+    algorithm: SigningAlgorithm,
+}
+
+impl HmacSigner {
+    /// HMAC-SHA256
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            algorithm: SigningAlgorithm::HmacSha256,
+        }
+    }
+
+    /// HMAC-SHA1, for verifying senders that haven't moved off it
+    pub fn sha1(key: impl Into<Vec<u8>>) -> Self {
+        Self {
+            key: key.into(),
+            algorithm: SigningAlgorithm::HmacSha1,
+        }
+    }
+}
+
+#[async_trait]
+impl Signer for HmacSigner {
+    fn algorithm(&self) -> SigningAlgorithm {
+        self.algorithm
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, SigningError> {
+        let invalid_key =
+            |e: hmac::digest::InvalidLength| SigningError::ConfigurationError(e.to_string());
+        Ok(match self.algorithm {
+            SigningAlgorithm::HmacSha1 => {
+                let mut mac = Hmac::<Sha1>::new_from_slice(&self.key).map_err(invalid_key)?;
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+            _ => {
+                let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).map_err(invalid_key)?;
+                mac.update(message);
+                mac.finalize().into_bytes().to_vec()
+            }
+        })
+    }
+}
+
+/// Ed25519 with a seed from config
+pub struct Ed25519Signer {
+    key: ed25519_dalek::SigningKey,
+}
+
+impl Ed25519Signer {
+    /// Create a signer from a base64-encoded 32-byte seed
+    pub fn from_base64(seed: &str) -> Result<Self, SigningError> {
+        let seed: [u8; 32] = STANDARD
+            .decode(seed.trim())
+            .ok()
+            .and_then(|seed| seed.try_into().ok())
+            .ok_or_else(|| {
+                SigningError::ConfigurationError(
+                    "Ed25519 keys must be a base64-encoded 32-byte seed".to_string(),
+                )
+            })?;
+
+        Ok(Self {
+            key: ed25519_dalek::SigningKey::from_bytes(&seed),
+        })
+    }
+}
+
+#[async_trait]
+impl Signer for Ed25519Signer {
+    fn algorithm(&self) -> SigningAlgorithm {
+        SigningAlgorithm::Ed25519
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, SigningError> {
+        use ed25519_dalek::Signer as _;
+        Ok(self.key.sign(message).to_bytes().to_vec())
+    }
+}
+
+pub(super) fn create_local_signer(
+    algorithm: SigningAlgorithm,
+    key: &str,
+) -> Result<Arc<dyn Signer>, SigningError> {
+    if key.is_empty() {
+        return Err(SigningError::ConfigurationError(
+            "A key is required for local signing".to_string(),
+        ));
+    }
+
+    match algorithm {
+        SigningAlgorithm::HmacSha256 => Ok(Arc::new(HmacSigner::new(key.as_bytes()))),
+        SigningAlgorithm::HmacSha1 => Ok(Arc::new(HmacSigner::sha1(key.as_bytes()))),
+        SigningAlgorithm::Ed25519 => Ok(Arc::new(Ed25519Signer::from_base64(key)?)),
+        _ => Err(SigningError::ConfigurationError(
+            "Local keys only support HS256, HS1 and EdDSA. Use a KMS or PKCS#11 key instead"
+                .to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::Verifier;
+
+    #[tokio::test]
+    async fn test_local_signers() {
+        // RFC 4231 test case 2
+        let mac = HmacSigner::new("Jefe")
+            .sign(b"what do ya want for nothing?")
+            .await
+            .unwrap();
+        let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(
+            hex,
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+
+        // RFC 2202 test case 2
+        let mac = HmacSigner::sha1("Jefe")
+            .sign(b"what do ya want for nothing?")
+            .await
+            .unwrap();
+        let hex: String = mac.iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "effcdf6ae5eb2fa2d27416d5f184df9c259a7c79");
+
+        let signer = Ed25519Signer::from_base64(&STANDARD.encode([7u8; 32])).unwrap();
+        let signature = signer.sign(b"payload").await.unwrap();
+        let signature = ed25519_dalek::Signature::from_slice(&signature).unwrap();
+        assert!(signer
+            .key
+            .verifying_key()
+            .verify(b"payload", &signature)
+            .is_ok());
+    }
+}
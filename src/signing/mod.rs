@@ -0,0 +1,193 @@
+pub mod gcp_kms;
+pub mod local;
+
+#[cfg(feature = "aws-kms")]
+pub mod aws_kms;
+#[cfg(feature = "pkcs11")]
+pub mod pkcs11;
+
+use crate::config::SecretString;
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+/// Error type for signing operations
+#[derive(Debug)]
+pub enum SigningError {
+    /// Error related to key configuration
+    ConfigurationError(String),
+    /// Error reported by the key backend
+    BackendError(String),
+}
+
+impl fmt::Display for SigningError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ConfigurationError(msg) => write!(f, "Signing configuration error: {}", msg),
+            Self::BackendError(msg) => write!(f, "Signing backend error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SigningError {}
+
+/// Signature algorithms, named as in JWT `alg` headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SigningAlgorithm {
+    #[serde(rename = "HS256")]
+    HmacSha256,
+    /// Only for verifying senders that still sign with SHA-1, such as Twilio
+    #[serde(rename = "HS1")]
+    HmacSha1,
+    #[serde(rename = "EdDSA")]
+    Ed25519,
+    #[serde(rename = "ES256")]
+    EcdsaP256Sha256,
+    #[serde(rename = "RS256")]
+    RsaPkcs1Sha256,
+}
+
+impl SigningAlgorithm {
+    pub fn is_hmac(&self) -> bool {
+        matches!(self, Self::HmacSha256 | Self::HmacSha1)
+    }
+}
+
+/// Where a signing key is held
+///
+/// Keys in a KMS or HSM never leave it: Bouncer only sends it the data to sign.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "source", rename_all = "snake_case")]
+pub enum KeyConfig {
+    /// Key material in the config, usually from an `ENV.` value. HMAC keys are
+    /// used as-is and Ed25519 keys are a base64 32-byte seed
+    Local {
+        algorithm: SigningAlgorithm,
+        key: SecretString,
+    },
+    /// A key in AWS KMS, using the default AWS credential chain
+    AwsKms {
+        algorithm: SigningAlgorithm,
+        key_id: String,
+        region: Option<String>,
+    },
+    /// A key version in Google Cloud KMS, e.g.
+    /// `projects/p/locations/l/keyRings/r/cryptoKeys/k/cryptoKeyVersions/1`
+    GcpKms {
+        algorithm: SigningAlgorithm,
+        key_name: String,
+    },
+    /// A key on a PKCS#11 token, found by its label
+    Pkcs11 {
+        algorithm: SigningAlgorithm,
+        /// Path to the PKCS#11 module, e.g. `/usr/lib/softhsm/libsofthsm2.so`
+        module: String,
+        slot: u64,
+        pin: SecretString,
+        label: String,
+    },
+}
+
+impl KeyConfig {
+    pub fn algorithm(&self) -> SigningAlgorithm {
+        match self {
+            Self::Local { algorithm, .. }
+            | Self::AwsKms { algorithm, .. }
+            | Self::GcpKms { algorithm, .. }
+            | Self::Pkcs11 { algorithm, .. } => *algorithm,
+        }
+    }
+}
+
+/// Signs data with a key that may be held outside of Bouncer
+///
+/// Policies that issue tokens or sign payloads take a [`KeyConfig`] and sign
+/// through this trait, so raw private keys don't have to be in config files.
+#[async_trait]
+pub trait Signer: Send + Sync + 'static {
+    fn algorithm(&self) -> SigningAlgorithm;
+
+    /// Sign `message`. HMAC signatures are the MAC itself and ECDSA signatures
+    /// are DER-encoded
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, SigningError>;
+}
+
+/// Create the signer for a key
+pub async fn create_signer(config: &KeyConfig) -> Result<Arc<dyn Signer>, SigningError> {
+    match config {
+        KeyConfig::Local { algorithm, key } => local::create_local_signer(*algorithm, key.expose()),
+        KeyConfig::AwsKms {
+            algorithm,
+            key_id,
+            region,
+        } => create_aws_kms_signer(*algorithm, key_id, region.as_deref()).await,
+        KeyConfig::GcpKms {
+            algorithm,
+            key_name,
+        } => Ok(Arc::new(gcp_kms::GcpKmsSigner::new(
+            *algorithm,
+            key_name.clone(),
+        ))),
+        KeyConfig::Pkcs11 {
+            algorithm,
+            module,
+            slot,
+            pin,
+            label,
+        } => create_pkcs11_signer(*algorithm, module, *slot, pin.expose(), label).await,
+    }
+}
+
+#[cfg(feature = "aws-kms")]
+async fn create_aws_kms_signer(
+    algorithm: SigningAlgorithm,
+    key_id: &str,
+    region: Option<&str>,
+) -> Result<Arc<dyn Signer>, SigningError> {
+    Ok(Arc::new(
+        aws_kms::AwsKmsSigner::new(algorithm, key_id.to_string(), region).await?,
+    ))
+}
+
+#[cfg(not(feature = "aws-kms"))]
+async fn create_aws_kms_signer(
+    _algorithm: SigningAlgorithm,
+    _key_id: &str,
+    _region: Option<&str>,
+) -> Result<Arc<dyn Signer>, SigningError> {
+    Err(SigningError::ConfigurationError(
+        "AWS KMS support is not enabled. Rebuild with the 'aws-kms' feature.".to_string(),
+    ))
+}
+
+#[cfg(feature = "pkcs11")]
+async fn create_pkcs11_signer(
+    algorithm: SigningAlgorithm,
+    module: &str,
+    slot: u64,
+    pin: &str,
+    label: &str,
+) -> Result<Arc<dyn Signer>, SigningError> {
+    let (module, pin, label) = (module.to_string(), pin.to_string(), label.to_string());
+    // Loading the module and logging in are blocking calls
+    let signer = tokio::task::spawn_blocking(move || {
+        pkcs11::Pkcs11Signer::new(algorithm, &module, slot, &pin, &label)
+    })
+    .await
+    .map_err(|e| SigningError::BackendError(e.to_string()))??;
+    Ok(Arc::new(signer))
+}
+
+#[cfg(not(feature = "pkcs11"))]
+async fn create_pkcs11_signer(
+    _algorithm: SigningAlgorithm,
+    _module: &str,
+    _slot: u64,
+    _pin: &str,
+    _label: &str,
+) -> Result<Arc<dyn Signer>, SigningError> {
+    Err(SigningError::ConfigurationError(
+        "PKCS#11 support is not enabled. Rebuild with the 'pkcs11' feature.".to_string(),
+    ))
+}
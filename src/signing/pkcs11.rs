@@ -0,0 +1,161 @@
+use super::{Signer, SigningAlgorithm, SigningError};
+use async_trait::async_trait;
+use cryptoki::context::{CInitializeArgs, Pkcs11};
+use cryptoki::mechanism::Mechanism;
+use cryptoki::object::{Attribute, ObjectClass, ObjectHandle};
+use cryptoki::session::{Session, UserType};
+use cryptoki::types::AuthPin;
+use std::sync::{Arc, Mutex};
+
+/// Signs with a key on a PKCS#11 token, such as an HSM or SoftHSM
+///
+/// One logged-in session is shared, so signing operations are serialized.
+/// HMAC keys aren't supported, since cryptoki has no HMAC mechanism.
+pub struct Pkcs11Signer {
+    session: Arc<Mutex<Session>>,
+    key: ObjectHandle,
+    algorithm: SigningAlgorithm,
+}
+
+impl Pkcs11Signer {
+    /// Load the module, log in to the slot and find the key. This blocks
+    pub fn new(
+        algorithm: SigningAlgorithm,
+        module: &str,
+        slot: u64,
+        pin: &str,
+        label: &str,
+    ) -> Result<Self, SigningError> {
+        if algorithm.is_hmac() {
+            return Err(SigningError::ConfigurationError(
+                "HMAC isn't supported with PKCS#11 keys".to_string(),
+            ));
+        }
+        let backend_error = |e: cryptoki::error::Error| SigningError::BackendError(e.to_string());
+
+        let pkcs11 = Pkcs11::new(module).map_err(backend_error)?;
+        pkcs11
+            .initialize(CInitializeArgs::OsThreads)
+            .map_err(backend_error)?;
+
+        let slot = pkcs11
+            .get_slots_with_token()
+            .map_err(backend_error)?
+            .into_iter()
+            .find(|candidate| candidate.id() == slot)
+            .ok_or_else(|| {
+                SigningError::ConfigurationError(format!("No PKCS#11 token in slot {}", slot))
+            })?;
+
+        let session = pkcs11.open_ro_session(slot).map_err(backend_error)?;
+        session
+            .login(UserType::User, Some(&AuthPin::new(pin.to_string())))
+            .map_err(backend_error)?;
+
+        let key = session
+            .find_objects(&[
+                Attribute::Class(ObjectClass::PRIVATE_KEY),
+                Attribute::Label(label.as_bytes().to_vec()),
+            ])
+            .map_err(backend_error)?
+            .into_iter()
+            .next()
+            .ok_or_else(|| {
+                SigningError::ConfigurationError(format!("No PKCS#11 key labelled '{}'", label))
+            })?;
+
+        Ok(Self {
+            session: Arc::new(Mutex::new(session)),
+            key,
+            algorithm,
+        })
+    }
+}
+
+/// Convert a raw `r || s` ECDSA signature, as returned by PKCS#11, to DER
+fn ecdsa_raw_to_der(raw: &[u8]) -> Vec<u8> {
+    let integer = |bytes: &[u8]| {
+        let bytes = &bytes[bytes
+            .iter()
+            .take_while(|b| **b == 0)
+            .count()
+            .min(bytes.len() - 1)..];
+        let mut encoded = vec![0x02];
+        if bytes[0] & 0x80 != 0 {
+            encoded.push(bytes.len() as u8 + 1);
+            encoded.push(0);
+        } else {
+            encoded.push(bytes.len() as u8);
+        }
+        encoded.extend_from_slice(bytes);
+        encoded
+    };
+
+    let (r, s) = raw.split_at(raw.len() / 2);
+    let body = [integer(r), integer(s)].concat();
+    let mut der = vec![0x30, body.len() as u8];
+    der.extend(body);
+    der
+}
+
+#[async_trait]
+impl Signer for Pkcs11Signer {
+    fn algorithm(&self) -> SigningAlgorithm {
+        self.algorithm
+    }
+
+    async fn sign(&self, message: &[u8]) -> Result<Vec<u8>, SigningError> {
+        let (session, key, algorithm) = (self.session.clone(), self.key, self.algorithm);
+        let message = message.to_vec();
+
+        let signature = tokio::task::spawn_blocking(move || {
+            let mechanism = match algorithm {
+                SigningAlgorithm::Ed25519 => Mechanism::Eddsa,
+                SigningAlgorithm::EcdsaP256Sha256 => Mechanism::EcdsaSha256,
+                SigningAlgorithm::RsaPkcs1Sha256 => Mechanism::Sha256RsaPkcs,
+                SigningAlgorithm::HmacSha256 | SigningAlgorithm::HmacSha1 => {
+                    unreachable!("rejected in new")
+                }
+            };
+            session.lock().unwrap().sign(&mechanism, key, &message)
+        })
+        .await
+        .map_err(|e| SigningError::BackendError(e.to_string()))?
+        .map_err(|e| SigningError::BackendError(e.to_string()))?;
+
+        Ok(match algorithm {
+            SigningAlgorithm::EcdsaP256Sha256 => ecdsa_raw_to_der(&signature),
+            _ => signature,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ecdsa_raw_to_der() {
+        let mut raw = vec![0u8; 64];
+        raw[0] = 0x80;
+        raw[63] = 0x01;
+        let der = ecdsa_raw_to_der(&raw);
+
+        // r gains a leading zero, s is trimmed to one byte
+        assert_eq!(&der[..4], &[0x30, 2 + 33 + 2 + 1, 0x02, 33]);
+        assert_eq!(der[4], 0);
+        assert_eq!(&der[der.len() - 3..], &[0x02, 1, 0x01]);
+    }
+
+    #[test]
+    fn test_hmac_rejected() {
+        let result = Pkcs11Signer::new(
+            SigningAlgorithm::HmacSha256,
+            "/nonexistent/libsofthsm2.so",
+            0,
+            "1234",
+            "bouncer-signing",
+        );
+        assert!(matches!(result, Err(SigningError::ConfigurationError(_))));
+    }
+}
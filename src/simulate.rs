@@ -0,0 +1,383 @@
+use crate::config::Config;
+use crate::diagnostics::redact_url;
+use crate::policy::body::inspect_body;
+use crate::policy::failure::resolve;
+use crate::policy::headers::ProtectedHeaders;
+use crate::policy::labels::{RouteLabeler, RouteLabels};
+use crate::policy::matcher;
+use crate::policy::traits::ResponseHeaders;
+use crate::GLOBAL_CONFIG;
+use axum::body::Body;
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method, Request};
+use std::fmt;
+
+// Terminating responses are printed, so don't buffer more than this of them
+const MAX_BODY_BYTES: usize = 64 * 1024;
+
+/// A request to run through the policy chain
+#[derive(Debug, Clone)]
+pub struct SimulatedRequest {
+    pub method: Method,
+    /// Path with an optional query string
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
+
+impl SimulatedRequest {
+    pub fn new(method: Method, path: impl Into<String>) -> Self {
+        Self {
+            method,
+            path: path.into(),
+            headers: HeaderMap::new(),
+            body: Vec::new(),
+        }
+    }
+
+    /// Add a header given as `Name: value`, as with curl's `-H`
+    pub fn header(mut self, header: &str) -> Result<Self, String> {
+        let (name, value) = header
+            .split_once(':')
+            .ok_or_else(|| format!("Headers must look like 'Name: value', got '{}'", header))?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes())
+            .map_err(|e| format!("Invalid header name '{}': {}", name, e))?;
+        let value = HeaderValue::from_str(value.trim())
+            .map_err(|e| format!("Invalid value for header {}: {}", name, e))?;
+        self.headers.append(name, value);
+        Ok(self)
+    }
+}
+
+/// What one policy did with the request
+#[derive(Debug, Clone)]
+pub enum Decision {
+    /// The request continues, with the headers the policy set or removed
+    Continue {
+        set: Vec<(String, String)>,
+        removed: Vec<String>,
+    },
+    /// The policy's `apply_if` didn't match, so it let the request through
+    /// without running
+    Skipped,
+    /// The policy answered the request itself
+    Terminate {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct PolicyStep {
+    pub policy: String,
+    pub decision: Decision,
+}
+
+/// The result of running a request through the chain
+#[derive(Debug, Clone)]
+pub struct Simulation {
+    pub method: Method,
+    pub path: String,
+    pub route: Option<RouteLabels>,
+    /// Protected headers removed from the incoming request
+    pub stripped: Vec<String>,
+    /// Whether the request matched a `bypass` route and skipped the chain
+    pub bypassed: bool,
+    pub steps: Vec<PolicyStep>,
+    /// Where the request would be sent and with which headers, if every
+    /// policy let it through
+    pub forwarded: Option<Forwarded>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Forwarded {
+    pub upstream: Option<String>,
+    pub headers: Vec<(String, String)>,
+    /// Headers policies added to the response
+    pub response_headers: Vec<(String, String)>,
+}
+
+fn header_pairs(headers: &HeaderMap) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect()
+}
+
+// Headers that were added or changed, and those that were removed
+fn diff_headers(before: &HeaderMap, after: &HeaderMap) -> Decision {
+    let set = after
+        .keys()
+        .filter(|name| before.get_all(*name).iter().ne(after.get_all(*name).iter()))
+        .flat_map(|name| {
+            after.get_all(name).iter().map(move |value| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).into_owned(),
+                )
+            })
+        })
+        .collect();
+    let removed = before
+        .keys()
+        .filter(|name| !after.contains_key(*name))
+        .map(|name| name.to_string())
+        .collect();
+
+    Decision::Continue { set, removed }
+}
+
+/// Run a request through the policy chain of a config, without starting a server
+///
+/// Policies are created as they would be at startup. Requests that pass every
+/// policy are not forwarded; the result shows what the upstream would receive.
+pub async fn simulate(config: &Config, request: SimulatedRequest) -> Result<Simulation, String> {
+    let _ = GLOBAL_CONFIG.set(config.clone());
+
+    let registry = crate::server::create_registry(config);
+    let (policy_chain, _) = registry.build_policy_chain(&config.policies).await?;
+    let protected_headers = ProtectedHeaders::new(&config.server.protected_headers);
+    let labeler = RouteLabeler::new(&config.labels)?;
+    let bypass = matcher::compile_all(&config.bypass)?;
+
+    let mut current = Request::builder()
+        .method(request.method.clone())
+        .uri(request.path.as_str())
+        .body(Body::from(request.body))
+        .map_err(|e| format!("Invalid request: {}", e))?;
+    *current.headers_mut() = request.headers;
+
+    let path = current.uri().path().to_string();
+    let route = labeler.label(&request.method, &path).labels;
+    let bypassed = bypass
+        .iter()
+        .any(|matcher| matcher.matches(&request.method, &path));
+    if let Some(labels) = &route {
+        current.extensions_mut().insert(labels.clone());
+    }
+
+    let before = current.headers().clone();
+    protected_headers.strip(current.headers_mut());
+    let stripped = before
+        .keys()
+        .filter(|name| !current.headers().contains_key(*name))
+        .map(|name| name.to_string())
+        .collect();
+
+    let mut simulation = Simulation {
+        method: request.method,
+        path: request.path,
+        route,
+        stripped,
+        bypassed,
+        steps: Vec::new(),
+        forwarded: None,
+    };
+
+    let policy_chain = if bypassed { &[][..] } else { &policy_chain[..] };
+    let inspect = policy_chain
+        .iter()
+        .filter_map(|policy| policy.inspects_body())
+        .max()
+        .map_or(0, |bytes| {
+            bytes.min(config.server.max_body_inspection_bytes)
+        });
+    let mut current = inspect_body(current, inspect)
+        .await
+        .map_err(|e| format!("Failed to read request body: {}", e))?;
+    for policy in policy_chain {
+        let id = format!(
+            "@{}/{}/{}/{}",
+            policy.provider(),
+            policy.category(),
+            policy.name(),
+            policy.version()
+        );
+        if !policy.applies_to(&current) {
+            simulation.steps.push(PolicyStep {
+                policy: id,
+                decision: Decision::Skipped,
+            });
+            continue;
+        }
+        let before = current.headers().clone();
+
+        match resolve(policy.as_ref(), policy.process(current).await) {
+            Ok(request) => {
+                simulation.steps.push(PolicyStep {
+                    policy: id,
+                    decision: diff_headers(&before, request.headers()),
+                });
+                current = request;
+            }
+            Err(response) => {
+                let (parts, body) = response.into_parts();
+                let body = axum::body::to_bytes(body, MAX_BODY_BYTES)
+                    .await
+                    .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+                    .unwrap_or_else(|_| "<body too large to show>".to_string());
+                simulation.steps.push(PolicyStep {
+                    policy: id,
+                    decision: Decision::Terminate {
+                        status: parts.status.as_u16(),
+                        headers: header_pairs(&parts.headers),
+                        body,
+                    },
+                });
+                return Ok(simulation);
+            }
+        }
+    }
+
+    let response_headers = current
+        .extensions_mut()
+        .remove::<ResponseHeaders>()
+        .map(|ResponseHeaders(headers)| header_pairs(&headers))
+        .unwrap_or_default();
+    protected_headers.strip_unforwarded(current.headers_mut());
+
+    simulation.forwarded = Some(Forwarded {
+        upstream: config.server.destination_address.as_deref().map(redact_url),
+        headers: header_pairs(current.headers()),
+        response_headers,
+    });
+    Ok(simulation)
+}
+
+impl fmt::Display for Simulation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} {}", self.method, self.path)?;
+        if let Some(route) = &self.route {
+            write!(f, "  route: {}", route.name)?;
+            if let Some(service) = &route.service {
+                write!(f, " (service: {})", service)?;
+            }
+            writeln!(f)?;
+        }
+        if !self.stripped.is_empty() {
+            writeln!(
+                f,
+                "  stripped protected headers: {}",
+                self.stripped.join(", ")
+            )?;
+        }
+        writeln!(f)?;
+
+        for (position, step) in self.steps.iter().enumerate() {
+            match &step.decision {
+                Decision::Continue { set, removed } => {
+                    writeln!(f, "{}. {}: continue", position + 1, step.policy)?;
+                    for (name, value) in set {
+                        writeln!(f, "     + {}: {}", name, value)?;
+                    }
+                    for name in removed {
+                        writeln!(f, "     - {}", name)?;
+                    }
+                }
+                Decision::Skipped => {
+                    writeln!(f, "{}. {}: skipped (apply_if)", position + 1, step.policy)?;
+                }
+                Decision::Terminate {
+                    status,
+                    headers,
+                    body,
+                } => {
+                    writeln!(f, "{}. {}: terminate {}", position + 1, step.policy, status)?;
+                    for (name, value) in headers {
+                        writeln!(f, "     {}: {}", name, value)?;
+                    }
+                    if !body.is_empty() {
+                        writeln!(f, "     {}", body)?;
+                    }
+                }
+            }
+        }
+        if self.bypassed {
+            writeln!(f, "(bypassed, policy chain skipped)")?;
+        } else if self.steps.is_empty() {
+            writeln!(f, "(no policies)")?;
+        }
+        writeln!(f)?;
+
+        match (&self.forwarded, self.steps.last()) {
+            (Some(forwarded), _) => {
+                match &forwarded.upstream {
+                    Some(upstream) => writeln!(f, "Result: forwarded to {}", upstream)?,
+                    None => writeln!(f, "Result: allowed (no destination configured)")?,
+                }
+                for (name, value) in &forwarded.headers {
+                    writeln!(f, "  {}: {}", name, value)?;
+                }
+                for (name, value) in &forwarded.response_headers {
+                    writeln!(f, "  response {}: {}", name, value)?;
+                }
+            }
+            (None, Some(step)) => writeln!(f, "Result: rejected by {}", step.policy)?,
+            (None, None) => {}
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::providers::bouncer::authentication::bearer::v1::{
+        BearerAuthConfig, BearerAuthPolicyFactory,
+    };
+
+    #[tokio::test]
+    async fn test_simulate_rejects_wrong_token() {
+        let config = Config::builder()
+            .policy::<BearerAuthPolicyFactory>(BearerAuthConfig {
+                token: Some("secret".into()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        let request = SimulatedRequest::new(Method::GET, "/api/x")
+            .header("Authorization: Bearer wrong")
+            .unwrap()
+            .header("X-Bouncer-Role: admin")
+            .unwrap();
+        let simulation = simulate(&config, request).await.unwrap();
+
+        assert_eq!(simulation.stripped, vec!["x-bouncer-role".to_string()]);
+        assert!(simulation.forwarded.is_none());
+        assert!(matches!(
+            simulation.steps[0].decision,
+            Decision::Terminate { status: 401, .. }
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_simulate_bypass() {
+        let config = Config::builder()
+            .bypass("GET /health")
+            .policy::<BearerAuthPolicyFactory>(BearerAuthConfig {
+                token: Some("secret".into()),
+                ..Default::default()
+            })
+            .build()
+            .unwrap();
+
+        let simulation = simulate(&config, SimulatedRequest::new(Method::GET, "/health"))
+            .await
+            .unwrap();
+        assert!(simulation.bypassed);
+        assert!(simulation.steps.is_empty());
+        assert!(simulation.forwarded.is_some());
+
+        let simulation = simulate(&config, SimulatedRequest::new(Method::POST, "/health"))
+            .await
+            .unwrap();
+        assert!(!simulation.bypassed);
+        assert!(simulation.forwarded.is_none());
+    }
+}
@@ -0,0 +1,344 @@
+use crate::policy::websocket::{WsChain, WsDirection, WsMessage, WsSession, WsVerdict};
+use axum::body::Body;
+use axum::extract::ws::{self, WebSocket, WebSocketUpgrade};
+use axum::extract::FromRequestParts;
+use axum::http::{header, HeaderMap, HeaderName, Request, Response, StatusCode};
+use axum::response::IntoResponse;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::{self, protocol::CloseFrame};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+type UpstreamSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Headers of the client's handshake, which the upstream handshake sets itself
+const HANDSHAKE_HEADERS: [HeaderName; 6] = [
+    header::HOST,
+    header::CONNECTION,
+    header::UPGRADE,
+    header::SEC_WEBSOCKET_KEY,
+    header::SEC_WEBSOCKET_VERSION,
+    header::SEC_WEBSOCKET_EXTENSIONS,
+];
+
+// A frame from either side of the connection
+enum Frame {
+    Data(WsMessage),
+    Close(Option<(u16, String)>),
+    // Pings and pongs, which each side answers on its own
+    Control,
+}
+
+impl From<ws::Message> for Frame {
+    fn from(message: ws::Message) -> Self {
+        match message {
+            ws::Message::Text(text) => Frame::Data(WsMessage::Text(text.as_str().to_string())),
+            ws::Message::Binary(bytes) => Frame::Data(WsMessage::Binary(bytes)),
+            ws::Message::Close(frame) => {
+                Frame::Close(frame.map(|frame| (frame.code, frame.reason.as_str().to_string())))
+            }
+            ws::Message::Ping(_) | ws::Message::Pong(_) => Frame::Control,
+        }
+    }
+}
+
+impl From<tungstenite::Message> for Frame {
+    fn from(message: tungstenite::Message) -> Self {
+        match message {
+            tungstenite::Message::Text(text) => {
+                Frame::Data(WsMessage::Text(text.as_str().to_string()))
+            }
+            tungstenite::Message::Binary(bytes) => Frame::Data(WsMessage::Binary(bytes)),
+            tungstenite::Message::Close(frame) => Frame::Close(
+                frame.map(|frame| (u16::from(frame.code), frame.reason.as_str().to_string())),
+            ),
+            tungstenite::Message::Ping(_)
+            | tungstenite::Message::Pong(_)
+            | tungstenite::Message::Frame(_) => Frame::Control,
+        }
+    }
+}
+
+fn client_message(message: WsMessage) -> ws::Message {
+    match message {
+        WsMessage::Text(text) => ws::Message::Text(text.into()),
+        WsMessage::Binary(bytes) => ws::Message::Binary(bytes),
+    }
+}
+
+fn client_close(close: Option<(u16, String)>) -> ws::Message {
+    ws::Message::Close(close.map(|(code, reason)| ws::CloseFrame {
+        code,
+        reason: reason.into(),
+    }))
+}
+
+fn upstream_message(message: WsMessage) -> tungstenite::Message {
+    match message {
+        WsMessage::Text(text) => tungstenite::Message::Text(text.into()),
+        WsMessage::Binary(bytes) => tungstenite::Message::Binary(bytes),
+    }
+}
+
+fn upstream_close(close: Option<(u16, String)>) -> tungstenite::Message {
+    tungstenite::Message::Close(close.map(|(code, reason)| CloseFrame {
+        code: CloseCode::from(code),
+        reason: reason.into(),
+    }))
+}
+
+fn bad_gateway(message: String) -> Response<Body> {
+    tracing::error!("{}", message);
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .body(Body::from(message))
+        .unwrap()
+}
+
+/// Open a websocket connection to the upstream at `url` for an upgrade request
+/// that passed the policy chain, and relay messages between it and the client
+///
+/// `headers` are the headers forwarded to the upstream. The client's upgrade
+/// only completes once the upstream has accepted the connection, so a client
+/// never sees a connection the upstream refused.
+pub async fn proxy(request: Request<Body>, url: &str, headers: HeaderMap) -> Response<Body> {
+    let (mut parts, _body) = request.into_parts();
+    let upgrade = match WebSocketUpgrade::from_request_parts(&mut parts, &()).await {
+        Ok(upgrade) => upgrade,
+        Err(rejection) => return rejection.into_response(),
+    };
+
+    let url = match url.split_once("://") {
+        Some(("https", rest)) => format!("wss://{}", rest),
+        Some(("http", rest)) => format!("ws://{}", rest),
+        _ => url.to_string(),
+    };
+    let mut upstream_request = match url.as_str().into_client_request() {
+        Ok(upstream_request) => upstream_request,
+        Err(e) => return bad_gateway(format!("Invalid websocket upstream {}: {}", url, e)),
+    };
+    for (name, value) in &headers {
+        if !HANDSHAKE_HEADERS.contains(name) {
+            upstream_request
+                .headers_mut()
+                .append(name.clone(), value.clone());
+        }
+    }
+
+    tracing::info!("Opening websocket connection to {}", url);
+    let (upstream, response) = match tokio_tungstenite::connect_async(upstream_request).await {
+        Ok(connected) => connected,
+        Err(e) => return bad_gateway(format!("Failed to connect websocket upstream: {}", e)),
+    };
+
+    // Agree to the subprotocol the upstream picked
+    let protocol = response
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let upgrade = match protocol {
+        Some(protocol) => upgrade.protocols([protocol]),
+        None => upgrade,
+    };
+
+    // Upgrade requests on bypassed routes carry no chain, and skip message policies
+    let session = match parts.extensions.get::<WsChain>() {
+        Some(WsChain(chain)) => WsSession::open(chain, &parts),
+        None => WsSession::open(&[], &parts),
+    };
+
+    upgrade
+        .on_upgrade(move |client| relay(client, upstream, session))
+        .into_response()
+}
+
+// Relay messages both ways through the message policies until either side closes
+async fn relay(client: WebSocket, upstream: UpstreamSocket, mut session: WsSession) {
+    let (mut client_tx, mut client_rx) = client.split();
+    let (mut upstream_tx, mut upstream_rx) = upstream.split();
+
+    loop {
+        // A side that errors or goes away is treated as having closed
+        let (direction, frame) = tokio::select! {
+            message = client_rx.next() => (
+                WsDirection::ClientToUpstream,
+                message.and_then(Result::ok).map_or(Frame::Close(None), Frame::from),
+            ),
+            message = upstream_rx.next() => (
+                WsDirection::UpstreamToClient,
+                message.and_then(Result::ok).map_or(Frame::Close(None), Frame::from),
+            ),
+        };
+
+        let verdict = match frame {
+            Frame::Data(message) if session.is_empty() => WsVerdict::Forward(message),
+            Frame::Data(message) => session.process(direction, message).await,
+            Frame::Control => continue,
+            Frame::Close(close) => {
+                // Pass the close on to the other side
+                match direction {
+                    WsDirection::ClientToUpstream => {
+                        let _ = upstream_tx.send(upstream_close(close)).await;
+                    }
+                    WsDirection::UpstreamToClient => {
+                        let _ = client_tx.send(client_close(close)).await;
+                    }
+                }
+                break;
+            }
+        };
+
+        let sent = match verdict {
+            WsVerdict::Forward(message) => match direction {
+                WsDirection::ClientToUpstream => {
+                    upstream_tx.send(upstream_message(message)).await.is_ok()
+                }
+                WsDirection::UpstreamToClient => {
+                    client_tx.send(client_message(message)).await.is_ok()
+                }
+            },
+            WsVerdict::Drop => true,
+            WsVerdict::Close { code, reason } => {
+                let close = Some((code, reason));
+                let _ = client_tx.send(client_close(close.clone())).await;
+                let _ = upstream_tx.send(upstream_close(close)).await;
+                break;
+            }
+        };
+        if !sent {
+            break;
+        }
+    }
+
+    tracing::debug!("Websocket connection closed");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy::middleware::PolicyLayer;
+    use crate::policy::providers::bouncer::authorization::rbac::v1::{
+        RbacConfig, RbacPolicyFactory,
+    };
+    use crate::policy::providers::bouncer::traffic::websocket::v1::{
+        MatchAction, WebsocketConfig, WebsocketPolicyFactory,
+    };
+    use crate::policy::traits::{Policy, PolicyFactory};
+    use axum::routing::{any, get};
+    use axum::Router;
+    use std::collections::HashMap;
+
+    async fn serve(app: Router) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        addr.to_string()
+    }
+
+    // Echoes every message back at `/ws`, and refuses upgrades at `/refuse`
+    async fn echo_upstream() -> String {
+        let echo = |upgrade: WebSocketUpgrade| async move {
+            upgrade.on_upgrade(|mut socket| async move {
+                while let Some(Ok(message)) = socket.recv().await {
+                    if matches!(message, ws::Message::Close(_))
+                        || socket.send(message).await.is_err()
+                    {
+                        break;
+                    }
+                }
+            })
+        };
+        let app = Router::new()
+            .route("/ws", get(echo))
+            .route("/refuse", get(|| async { StatusCode::FORBIDDEN }));
+        serve(app).await
+    }
+
+    // Relays every path to the upstream, behind a policy chain
+    async fn gateway(upstream: String, policies: Vec<Box<dyn Policy>>) -> String {
+        let relay = move |request: Request<Body>| {
+            let url = format!("http://{}{}", upstream, request.uri().path());
+            let headers = request.headers().clone();
+            async move { proxy(request, &url, headers).await }
+        };
+        let app = Router::new()
+            .route("/{*path}", any(relay))
+            .layer(PolicyLayer::new(policies));
+        serve(app).await
+    }
+
+    async fn next_text(socket: &mut UpstreamSocket) -> String {
+        match socket.next().await {
+            Some(Ok(tungstenite::Message::Text(text))) => text.as_str().to_string(),
+            other => panic!("expected a text message, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_relay() {
+        let websocket = WebsocketPolicyFactory::new(WebsocketConfig {
+            deny_patterns: vec!["secret".to_string()],
+            on_match: MatchAction::Redact,
+            ..WebsocketConfig::default()
+        })
+        .await
+        .unwrap();
+        let gateway = gateway(echo_upstream().await, vec![Box::new(websocket)]).await;
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}/ws", gateway))
+            .await
+            .unwrap();
+        socket
+            .send(tungstenite::Message::Text("hello".into()))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut socket).await, "hello");
+
+        // Messages pass through the message policies on the way
+        socket
+            .send(tungstenite::Message::Text("my secret".into()))
+            .await
+            .unwrap();
+        assert_eq!(next_text(&mut socket).await, "my [redacted]");
+
+        let binary = tungstenite::Message::Binary(vec![1, 2, 3].into());
+        socket.send(binary.clone()).await.unwrap();
+        assert_eq!(socket.next().await.unwrap().unwrap(), binary);
+
+        // A close from the client reaches the upstream, which hangs up
+        socket.close(None).await.unwrap();
+        while let Some(Ok(message)) = socket.next().await {
+            assert!(matches!(message, tungstenite::Message::Close(_)));
+        }
+    }
+
+    // The status a failed upgrade was answered with
+    async fn rejected(url: String) -> StatusCode {
+        match tokio_tungstenite::connect_async(url).await {
+            Err(tungstenite::Error::Http(response)) => response.status(),
+            other => panic!("expected the upgrade to fail, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rejected_upgrade() {
+        let rbac = RbacPolicyFactory::new(RbacConfig {
+            route_roles: HashMap::from([("GET /ws".to_string(), vec!["admin".to_string()])]),
+        })
+        .await
+        .unwrap();
+
+        // Denied by the chain
+        let denied = gateway(echo_upstream().await, vec![Box::new(rbac)]).await;
+        let status = rejected(format!("ws://{}/ws", denied)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        // Refused by the upstream
+        let open = gateway(echo_upstream().await, vec![]).await;
+        let status = rejected(format!("ws://{}/refuse", open)).await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+}